@@ -0,0 +1,7 @@
+// build.rs
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use the bundled protoc, so building doesn't require one on the system
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure().build_client(false).compile_protos(&["proto/mediathek.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,321 @@
+// src/algorithms/association_rules.rs
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use ahash::RandomState;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use tracing::{error, info};
+
+use crate::algorithms::recent_lists::RecentLists;
+use crate::config::AssociationRuleSettings;
+use crate::{determinism, locks};
+
+/// A mined rule of the form `{antecedent} -> consequent`.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct AssociationRule {
+    pub antecedent: Vec<String>,
+    pub consequent: String,
+    /// Share of all lists that contain antecedent and consequent.
+    pub support: f64,
+    /// Share of lists containing the antecedent that also contain the consequent.
+    pub confidence: f64,
+    /// Confidence divided by the consequent's own support (> 1 means positive correlation).
+    pub lift: f64,
+}
+
+/// The result of the most recent mining pass.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RuleSet {
+    pub rules: Vec<AssociationRule>,
+    /// When the rules were mined, `None` before the first pass completed.
+    pub mined_at: Option<DateTime<Utc>>,
+    /// Number of lists the rules were mined from.
+    pub list_count: usize,
+}
+
+impl RuleSet {
+    /// Returns all rules whose antecedent contains `identifier`, or all rules if `None`.
+    pub fn rules_involving(&self, identifier: Option<&str>, limit: usize) -> Vec<AssociationRule> {
+        self.rules
+            .iter()
+            .filter(|rule| identifier.is_none_or(|id| rule.antecedent.iter().any(|a| a == id)))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Suggests items for a basket: every rule whose antecedent is fully contained in
+    /// the basket contributes its consequent, scored by the best confidence seen.
+    /// Items already in the basket are never suggested.
+    pub fn recommend_for_basket(&self, basket: &[String], limit: usize) -> Vec<(String, f64)> {
+        let basket: HashSet<&str> = basket.iter().map(String::as_str).collect();
+        let mut scores: HashMap<&str, f64> = HashMap::new();
+
+        for rule in &self.rules {
+            if basket.contains(rule.consequent.as_str()) {
+                continue;
+            }
+            if !rule.antecedent.iter().all(|a| basket.contains(a.as_str())) {
+                continue;
+            }
+            let score = scores.entry(rule.consequent.as_str()).or_insert(0.0);
+            *score = score.max(rule.confidence);
+        }
+
+        let mut ranked: Vec<(String, f64)> = scores.into_iter().map(|(id, score)| (id.to_string(), score)).collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked.truncate(limit);
+        ranked
+    }
+}
+
+/// Mines association rules from `lists` using the Apriori algorithm.
+///
+/// Each list is treated as a set (duplicates and order are ignored). Frequent itemsets
+/// are grown level by level up to `max_itemset_size`; every frequent itemset of size >= 2
+/// then yields one candidate rule per contained item as consequent. Rules are returned
+/// sorted by confidence, then lift.
+pub fn mine_rules(lists: &[Vec<String>], settings: &AssociationRuleSettings) -> Vec<AssociationRule> {
+    if lists.is_empty() {
+        return Vec::new();
+    }
+
+    // Intern identifiers and turn every list into a sorted, de-duplicated transaction
+    let mut identifier_to_id: HashMap<&str, u32, RandomState> = HashMap::with_hasher(determinism::random_state());
+    let mut id_to_identifier: Vec<&str> = Vec::new();
+    let transactions: Vec<Vec<u32>> = lists
+        .iter()
+        .map(|list| {
+            let mut ids: Vec<u32> = list
+                .iter()
+                .map(|s| {
+                    *identifier_to_id.entry(s.as_str()).or_insert_with(|| {
+                        id_to_identifier.push(s.as_str());
+                        (id_to_identifier.len() - 1) as u32
+                    })
+                })
+                .collect();
+            ids.sort_unstable();
+            ids.dedup();
+            ids
+        })
+        .collect();
+
+    let total = transactions.len() as f64;
+    let min_count = (settings.min_support * total).ceil().max(1.0) as u32;
+
+    // Level 1: frequent single items
+    let mut item_counts: HashMap<u32, u32, RandomState> = HashMap::with_hasher(determinism::random_state());
+    for transaction in &transactions {
+        for &id in transaction {
+            *item_counts.entry(id).or_insert(0) += 1;
+        }
+    }
+    let mut frequent: HashMap<Vec<u32>, u32, RandomState> = HashMap::with_hasher(determinism::random_state());
+    let mut current_level: Vec<Vec<u32>> = Vec::new();
+    for (&id, &count) in &item_counts {
+        if count >= min_count {
+            frequent.insert(vec![id], count);
+            current_level.push(vec![id]);
+        }
+    }
+
+    // Level k: join itemsets sharing a (k-1)-prefix, prune, then count
+    let mut size = 2;
+    while size <= settings.max_itemset_size && !current_level.is_empty() {
+        current_level.sort_unstable();
+        let mut candidates: Vec<Vec<u32>> = Vec::new();
+        for i in 0..current_level.len() {
+            for j in (i + 1)..current_level.len() {
+                let (a, b) = (&current_level[i], &current_level[j]);
+                if a[..size - 2] != b[..size - 2] {
+                    break;
+                }
+                let mut candidate = a.clone();
+                candidate.push(b[size - 2]);
+                // Apriori property: every subset of a frequent itemset must be frequent
+                let all_subsets_frequent = (0..candidate.len()).all(|skip| {
+                    let subset: Vec<u32> = candidate
+                        .iter()
+                        .enumerate()
+                        .filter(|&(idx, _)| idx != skip)
+                        .map(|(_, &id)| id)
+                        .collect();
+                    frequent.contains_key(&subset)
+                });
+                if all_subsets_frequent {
+                    candidates.push(candidate);
+                }
+            }
+        }
+
+        let mut candidate_counts: Vec<u32> = vec![0; candidates.len()];
+        for transaction in &transactions {
+            if transaction.len() < size {
+                continue;
+            }
+            for (idx, candidate) in candidates.iter().enumerate() {
+                if candidate.iter().all(|id| transaction.binary_search(id).is_ok()) {
+                    candidate_counts[idx] += 1;
+                }
+            }
+        }
+
+        current_level = Vec::new();
+        for (candidate, count) in candidates.into_iter().zip(candidate_counts) {
+            if count >= min_count {
+                frequent.insert(candidate.clone(), count);
+                current_level.push(candidate);
+            }
+        }
+        size += 1;
+    }
+
+    // Derive rules with a single consequent from all frequent itemsets of size >= 2
+    let mut rules = Vec::new();
+    for (itemset, &count) in frequent.iter().filter(|(itemset, _)| itemset.len() >= 2) {
+        for (idx, &consequent) in itemset.iter().enumerate() {
+            let antecedent: Vec<u32> = itemset
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != idx)
+                .map(|(_, &id)| id)
+                .collect();
+            let antecedent_count = frequent[&antecedent];
+            let confidence = count as f64 / antecedent_count as f64;
+            if confidence < settings.min_confidence {
+                continue;
+            }
+            let consequent_support = item_counts[&consequent] as f64 / total;
+            let mut antecedent: Vec<String> = antecedent.iter().map(|&id| id_to_identifier[id as usize].to_string()).collect();
+            antecedent.sort();
+            rules.push(AssociationRule {
+                antecedent,
+                consequent: id_to_identifier[consequent as usize].to_string(),
+                support: count as f64 / total,
+                confidence,
+                lift: confidence / consequent_support,
+            });
+        }
+    }
+
+    rules.sort_by(|a, b| {
+        b.confidence
+            .total_cmp(&a.confidence)
+            .then_with(|| b.lift.total_cmp(&a.lift))
+            .then_with(|| a.antecedent.cmp(&b.antecedent))
+            .then_with(|| a.consequent.cmp(&b.consequent))
+    });
+    rules
+}
+
+// Function to periodically mine association rules from the recent lists buffer
+pub async fn run_rule_mining(
+    recent_lists: Arc<Mutex<RecentLists>>,
+    rule_set: Arc<Mutex<RuleSet>>,
+    settings: AssociationRuleSettings,
+) {
+    info!("Association rule mining thread started.");
+
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(settings.mining_interval_secs)).await;
+
+        let recent_lists = recent_lists.clone();
+        let rule_set = rule_set.clone();
+        let settings = settings.clone();
+
+        // Mining is CPU-bound, so it runs on the blocking thread pool. The recent lists
+        // are copied first so ingestion is not blocked during the pass.
+        let result = web::block(move || {
+            let lists = locks::lock(&recent_lists, "recent_lists").snapshot();
+            let rules = mine_rules(&lists, &settings);
+            let rule_count = rules.len();
+            *locks::lock(&rule_set, "rule_set") = RuleSet {
+                rules,
+                mined_at: Some(determinism::now()),
+                list_count: lists.len(),
+            };
+            (lists.len(), rule_count)
+        })
+        .await;
+
+        match result {
+            Ok((list_count, rule_count)) => {
+                info!("Mined {} association rules from {} lists.", rule_count, list_count);
+            }
+            Err(e) => {
+                error!("Error in association rule mining block: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(min_support: f64, min_confidence: f64) -> AssociationRuleSettings {
+        AssociationRuleSettings {
+            min_support,
+            min_confidence,
+            max_itemset_size: 3,
+            mining_interval_secs: 600,
+        }
+    }
+
+    fn list(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_pair_rules_and_metrics() {
+        let lists = vec![list(&["a", "b"]), list(&["a", "b"]), list(&["a", "c"]), list(&["b", "c"])];
+        let rules = mine_rules(&lists, &settings(0.5, 0.6));
+
+        // Only {a,b} is frequent among the pairs (2 of 4 lists)
+        assert_eq!(rules.len(), 2);
+        let a_to_b = rules.iter().find(|r| r.antecedent == vec!["a"] && r.consequent == "b").unwrap();
+        assert!((a_to_b.support - 0.5).abs() < 1e-9);
+        assert!((a_to_b.confidence - 2.0 / 3.0).abs() < 1e-9);
+        assert!((a_to_b.lift - (2.0 / 3.0) / 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_three_item_rules() {
+        let lists = vec![list(&["a", "b", "c"]), list(&["a", "b", "c"]), list(&["a", "b"]), list(&["c", "d"])];
+        let rules = mine_rules(&lists, &settings(0.5, 0.9));
+
+        let ab_to_c = rules
+            .iter()
+            .find(|r| r.antecedent == vec!["a", "b"] && r.consequent == "c");
+        assert!(ab_to_c.is_none(), "{{a,b}} -> c only has confidence 2/3");
+
+        let ac_to_b = rules
+            .iter()
+            .find(|r| r.antecedent == vec!["a", "c"] && r.consequent == "b")
+            .unwrap();
+        assert!((ac_to_b.confidence - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_basket_recommendation() {
+        let lists = vec![list(&["a", "b", "c"]), list(&["a", "b", "c"]), list(&["a", "d"])];
+        let rule_set = RuleSet {
+            rules: mine_rules(&lists, &settings(0.3, 0.5)),
+            mined_at: None,
+            list_count: lists.len(),
+        };
+
+        let suggestions = rule_set.recommend_for_basket(&list(&["a", "b"]), 10);
+        assert_eq!(suggestions[0].0, "c");
+        assert!((suggestions[0].1 - 1.0).abs() < 1e-9);
+        assert!(suggestions.iter().all(|(id, _)| id != "a" && id != "b"));
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert!(mine_rules(&[], &settings(0.1, 0.1)).is_empty());
+    }
+}
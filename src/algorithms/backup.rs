@@ -0,0 +1,159 @@
+// src/algorithms/backup.rs
+use std::sync::RwLock;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::algorithms::co_occurrence::Snapshot;
+use crate::algorithms::consistent_snapshot::{self, SnapshotPair};
+use crate::algorithms::snapshot;
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::config::{SnapshotFormat, SnapshotSettings};
+use crate::determinism;
+use crate::locks;
+
+/// Version of the backup format, increased whenever backups written by a new version
+/// can't be restored by older ones.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// How backups are encoded: like binary snapshots, which are recognized on restore.
+const ENCODING: SnapshotSettings = SnapshotSettings {
+    format: SnapshotFormat::MessagePack,
+    compression_level: 3,
+    keep_last: 0,
+    keep_daily: 0,
+    keep_weekly: 0,
+};
+
+/// Describes a backup.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct BackupMetadata {
+    pub format_version: u32,
+    pub created_at: DateTime<Utc>,
+    /// Version of the server that wrote the backup
+    pub server_version: String,
+    /// Identifiers in the co-occurrences
+    pub identifiers: usize,
+    /// Pairs in the co-occurrences
+    pub pairs: usize,
+    /// Identifiers with counts
+    pub counter_identifiers: usize,
+    /// ID of the snapshot of both the co-occurrences and the counters (see
+    /// `consistent_snapshot`); 0 in backups written before it existed
+    #[serde(default)]
+    pub snapshot_id: u64,
+}
+
+#[derive(Serialize)]
+struct BackupRef<'a> {
+    metadata: &'a BackupMetadata,
+    co_occurrences: Snapshot,
+    counters: &'a Counters,
+}
+
+#[derive(Deserialize)]
+struct Backup {
+    metadata: BackupMetadata,
+    co_occurrences: Snapshot,
+    counters: Counters,
+}
+
+/// Only the metadata, read first to check the format version.
+#[derive(Deserialize)]
+struct BackupHeader {
+    metadata: BackupMetadata,
+}
+
+/// Writes the co-occurrences and the counters into a single archive. Both are copied
+/// at the same moment (see `consistent_snapshot::capture`), so the archive holds a
+/// consistent state, and encoded after the writes went on.
+pub fn create(co_occurrence: &RwLock<CoOccurrenceCounter>, counters: &RwLock<Counters>) -> Result<(BackupMetadata, Vec<u8>), String> {
+    let SnapshotPair { id, co_occurrences, counters } = consistent_snapshot::capture(co_occurrence, counters)?;
+    write_archive(id, co_occurrences, &counters)
+}
+
+/// Writes an archive like `create`, for callers holding the locks already.
+pub fn encode(co_occurrence: &CoOccurrenceCounter, counters: &Counters) -> Result<(BackupMetadata, Vec<u8>), String> {
+    let id = consistent_snapshot::next_id();
+    let mut co_occurrences = co_occurrence.to_snapshot()?;
+    co_occurrences.set_snapshot_id(id);
+    write_archive(id, co_occurrences, counters)
+}
+
+fn write_archive(snapshot_id: u64, co_occurrences: Snapshot, counters: &Counters) -> Result<(BackupMetadata, Vec<u8>), String> {
+    let metadata = BackupMetadata {
+        format_version: FORMAT_VERSION,
+        created_at: determinism::now(),
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        identifiers: co_occurrences.identifier_count(),
+        pairs: co_occurrences.pair_count(),
+        counter_identifiers: counters.first_seen.len(),
+        snapshot_id,
+    };
+    let backup = BackupRef { metadata: &metadata, co_occurrences, counters };
+    let (data, _) = snapshot::encode_compressed(&backup, ENCODING).map_err(|e| e.to_string())?;
+    Ok((metadata, data))
+}
+
+/// Replaces the co-occurrences and the counters with those of an archive written by
+/// `create`. The counters are advanced to the current time in `timezone` first, so their
+/// buckets line up with the current period. The co-occurrences are persisted right away;
+/// persisting the counters is left to the caller.
+pub fn restore(
+    data: &[u8],
+    co_occurrence: &RwLock<CoOccurrenceCounter>,
+    counters: &RwLock<Counters>,
+    timezone: Tz,
+) -> Result<BackupMetadata, String> {
+    let header: BackupHeader = snapshot::decode(data).map_err(|e| format!("Invalid backup: {}", e))?;
+    if header.metadata.format_version != FORMAT_VERSION {
+        return Err(format!(
+            "Unsupported backup format version {}, expected {}",
+            header.metadata.format_version, FORMAT_VERSION
+        ));
+    }
+    let Backup { metadata, co_occurrences, counters: mut restored } =
+        snapshot::decode(data).map_err(|e| format!("Invalid backup: {}", e))?;
+
+    let mut co_occurrence = locks::write(co_occurrence, "co_occurrence");
+    let mut counters = locks::write(counters, "rotating_counters");
+    co_occurrence.replace(co_occurrences)?;
+    let now = determinism::now().with_timezone(&timezone);
+    restored.advance_to(&now);
+    counters.advance_to(&now);
+    counters.reset();
+    counters.merge(restored);
+    counters.snapshot_id = metadata.snapshot_id;
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::rotating_counters::count_of;
+
+    #[test]
+    fn test_backup_restores_into_another_instance() {
+        let co_occurrence = RwLock::new(CoOccurrenceCounter::new());
+        let counters = RwLock::new(Counters::with_depths(3, 3, 1, 1));
+        locks::write(&co_occurrence, "co_occurrence").process_list(&["a".to_string(), "b".to_string()]);
+        locks::read(&counters, "rotating_counters").increment("a", 3);
+        let (metadata, data) = create(&co_occurrence, &counters).unwrap();
+        assert_eq!((metadata.identifiers, metadata.pairs, metadata.counter_identifiers), (2, 1, 1));
+
+        let other_co_occurrence = RwLock::new(CoOccurrenceCounter::new());
+        let other_counters = RwLock::new(Counters::with_depths(3, 3, 1, 1));
+        locks::write(&other_co_occurrence, "co_occurrence").process_list(&["c".to_string(), "d".to_string()]);
+        locks::read(&other_counters, "rotating_counters").increment("c", 1);
+        assert_eq!(restore(&data, &other_co_occurrence, &other_counters, Tz::UTC), Ok(metadata));
+
+        let restored = locks::read(&other_co_occurrence, "co_occurrence");
+        assert_eq!(restored.get_metrics_for_identifier("a").get("b"), Some(&1));
+        assert!(restored.get_metrics_for_identifier("c").is_empty());
+        let restored = locks::read(&other_counters, "rotating_counters");
+        assert_eq!(count_of(&restored.window("today").unwrap(), "a"), 3);
+        assert_eq!(count_of(&restored.window("today").unwrap(), "c"), 0);
+        assert!(restore(b"{}", &other_co_occurrence, &other_counters, Tz::UTC).is_err());
+    }
+}
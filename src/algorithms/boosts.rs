@@ -0,0 +1,83 @@
+// src/algorithms/boosts.rs
+use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::algorithms::snapshot;
+
+/// Path of the boosts file, relative to the data directory.
+pub const SNAPSHOT_PATH: &str = "boosts.json";
+
+/// An editorial boost: a score multiplier, a fixed slot in the recommendations, or both,
+/// until it expires.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Boost {
+    pub identifier: String,
+    /// Multiplies the item's score wherever it is a candidate
+    pub factor: Option<f64>,
+    /// 1-based slot the item is put in, even if no model proposed it
+    pub pin_position: Option<usize>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The boosts set by editors, at most one per identifier. Kept in a file in the data
+/// directory, so they survive restarts.
+#[derive(Debug, Default)]
+pub struct Boosts {
+    boosts: Vec<Boost>,
+    path: Option<PathBuf>,
+}
+
+impl Boosts {
+    /// Loads the boosts from `path`, which receives every change.
+    pub fn load(path: PathBuf) -> Self {
+        let boosts = snapshot::read(&path).unwrap_or_default();
+        Boosts { boosts, path: Some(path) }
+    }
+
+    /// Adds `boost`, replacing the one of its identifier, and drops the expired ones.
+    pub fn set(&mut self, boost: Boost, now: DateTime<Utc>) {
+        self.boosts.retain(|existing| existing.identifier != boost.identifier && existing.expires_at > now);
+        self.boosts.push(boost);
+        self.persist();
+    }
+
+    /// Returns the boosts that haven't expired at `now`.
+    pub fn active(&self, now: DateTime<Utc>) -> Vec<Boost> {
+        self.boosts.iter().filter(|boost| boost.expires_at > now).cloned().collect()
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_vec(&self.boosts).map_err(|e| e.to_string()).and_then(|data| snapshot::write(path, &data).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("Failed to save the boosts to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boosts_replace_per_identifier_and_expire() {
+        let now = DateTime::UNIX_EPOCH;
+        let boost = |identifier: &str, factor: f64, hours: i64| Boost {
+            identifier: identifier.to_string(),
+            factor: Some(factor),
+            pin_position: None,
+            expires_at: now + chrono::Duration::hours(hours),
+        };
+        let mut boosts = Boosts::default();
+        boosts.set(boost("a", 2.0, 1), now);
+        boosts.set(boost("b", 2.0, 2), now);
+        boosts.set(boost("a", 3.0, 3), now);
+        assert_eq!(boosts.active(now), [boost("b", 2.0, 2), boost("a", 3.0, 3)]);
+        assert_eq!(boosts.active(now + chrono::Duration::hours(2)), [boost("a", 3.0, 3)]);
+    }
+}
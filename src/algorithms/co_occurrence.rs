@@ -1,95 +1,1516 @@
 // src/algorithms/co_occurrence.rs
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::ffi::OsString;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use std::{fs, io};
 use ahash::RandomState;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::algorithms::event_log::{read_entries, EventLog, ListEvent};
+use crate::algorithms::identifier_filter::IdentifierFilter;
+use crate::algorithms::interner::Interner;
+use crate::algorithms::replication::{Change, ChangeFeed};
+use crate::algorithms::snapshot;
+use crate::config::{
+    CardinalityPolicy, CardinalitySettings, IdentifierFilterSettings, MetricsCacheSettings, PairStrategy, Shrinkage, SnapshotSettings, StorageSettings,
+    TwoHopSettings,
+};
+use crate::{determinism, locks, memory, stats};
+
+pub const SNAPSHOT_PATH: &str = "co_occurrences.json";
+const WAL_PATH: &str = "co_occurrences.log";
+
+/// Recently requested results of `get_metrics_for_identifier`, keyed by ID. Entries are
+/// dropped when a list containing the identifier is processed, or after the TTL. Behind
+/// its own mutex, so lookups can fill it while sharing the counter with other readers.
+#[derive(Debug)]
+struct MetricsCache {
+    entries: LruCache<u32, (Instant, HashMap<String, u64>)>,
+    ttl: Duration,
+}
+
+/// The persisted co-occurrence state: identifiers with their IDs, and the pair counts.
+pub type PairState = (Vec<(String, u32)>, Vec<((u32, u32), u64)>);
+
+/// The co-occurrences as written to the snapshot.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Sequence number of the last logged list the snapshot contains
+    seq: u64,
+    /// Incremented by every full snapshot, so deltas of an older one are recognized
+    #[serde(default)]
+    generation: u64,
+    /// ID of the snapshot of both the co-occurrences and the counters this one belongs to
+    /// (see `consistent_snapshot`); 0 if none
+    #[serde(default)]
+    snapshot_id: u64,
+    identifiers: Interner,
+    /// Smaller ID, larger ID and count
+    pairs: Vec<(u32, u32, u64)>,
+    /// Per ID, when the identifier was last seen in a list, in seconds since the Unix
+    /// epoch. Missing in snapshots written before it was tracked.
+    #[serde(default)]
+    last_seen: Vec<i64>,
+    /// Per ID, when the identifier was first seen in a list, in seconds since the Unix
+    /// epoch; 0 if unknown. Missing in snapshots written before it was tracked.
+    #[serde(default)]
+    first_seen: Vec<i64>,
+    /// Per ID, the number of lists the identifier was in (weighted like the pairs).
+    /// Missing in snapshots written before it was tracked.
+    #[serde(default)]
+    occurrences: Vec<u64>,
+}
+
+impl Snapshot {
+    pub fn snapshot_id(&self) -> u64 {
+        self.snapshot_id
+    }
+
+    pub fn set_snapshot_id(&mut self, snapshot_id: u64) {
+        self.snapshot_id = snapshot_id;
+    }
+
+    pub fn identifier_count(&self) -> usize {
+        self.identifiers.len()
+    }
+
+    pub fn pair_count(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Returns the `limit` pairs with the highest counts, highest first.
+    pub fn top_pairs(&self, limit: usize) -> Vec<(String, String, u64)> {
+        let mut pairs: Vec<&(u32, u32, u64)> = self.pairs.iter().collect();
+        pairs.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| (a.0, a.1).cmp(&(b.0, b.1))));
+        pairs
+            .into_iter()
+            .take(limit)
+            .filter_map(|&(a, b, count)| Some((self.identifiers.resolve(a)?.to_string(), self.identifiers.resolve(b)?.to_string(), count)))
+            .collect()
+    }
+}
+
+/// The changes since the previous snapshot or delta, written instead of a full snapshot
+/// most of the time. Pairs carry their new count rather than the increment, so applying a
+/// delta twice does no harm.
+#[derive(Serialize, Deserialize)]
+struct Delta {
+    /// Generation of the full snapshot the delta applies to
+    generation: u64,
+    /// Sequence number of the last logged list the delta contains
+    seq: u64,
+    /// Like `Snapshot::snapshot_id`
+    #[serde(default)]
+    snapshot_id: u64,
+    /// Identifiers seen for the first time, with their IDs
+    identifiers: Vec<(String, u32)>,
+    /// Smaller ID, larger ID and new count
+    pairs: Vec<(u32, u32, u64)>,
+    /// IDs seen since the previous snapshot or delta, with when they were last seen
+    #[serde(default)]
+    last_seen: Vec<(u32, i64)>,
+    /// IDs first seen since the previous snapshot or delta, with when
+    #[serde(default)]
+    first_seen: Vec<(u32, i64)>,
+    /// The same IDs as `last_seen` with their new number of lists
+    #[serde(default)]
+    occurrences: Vec<(u32, u64)>,
+}
+
+/// Path of the `index`th delta on top of the snapshot at `snapshot_path`, e.g.
+/// "co_occurrences.json.delta.1".
+fn delta_path(snapshot_path: &Path, index: usize) -> PathBuf {
+    let mut name = OsString::from(snapshot_path.as_os_str());
+    name.push(format!(".delta.{}", index));
+    PathBuf::from(name)
+}
+
+/// Deletes the first `count` deltas on top of the snapshot at `snapshot_path`, once a
+/// full snapshot replaced them.
+fn remove_deltas(snapshot_path: &Path, count: usize) {
+    for index in 1..=count {
+        match fs::remove_file(delta_path(snapshot_path, index)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => error!("Failed to delete delta {}: {}", index, e),
+            _ => {}
+        }
+    }
+}
+
+/// A snapshot or delta copied by `CoOccurrenceCounter::prepare_snapshot`, to be written
+/// without holding the lock.
+pub struct PendingSnapshot {
+    /// Where it is written to
+    path: PathBuf,
+    file: PendingFile,
+    snapshots: SnapshotSettings,
+    /// Sequence number of the last logged list it contains
+    seq: u64,
+    /// The generation and number of deltas before it, restored if it can't be written
+    previous: (u64, usize),
+    /// Value of `snapshot_writes` once it was copied
+    ticket: u64,
+}
+
+enum PendingFile {
+    /// A full snapshot, replacing the given number of deltas of the previous one
+    Full(Snapshot, usize),
+    Delta(Delta),
+}
+
+impl PendingSnapshot {
+    /// Writes the snapshot or delta, and deletes the deltas a full snapshot replaces.
+    pub fn write(&self) -> io::Result<()> {
+        match &self.file {
+            PendingFile::Full(snapshot, replaced) => {
+                snapshot::save(&self.path, snapshot, self.snapshots)?;
+                remove_deltas(&self.path, *replaced);
+                info!("Co-occurrences persisted.");
+            }
+            PendingFile::Delta(delta) => {
+                let bytes = snapshot::save_unversioned(&self.path, delta, self.snapshots)?;
+                info!(pairs = delta.pairs.len(), bytes, "Co-occurrence delta persisted.");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Durable storage the co-occurrence state is written to, one processed list at a time,
+/// and loaded from on startup.
+pub trait PairStore: Send + Sync + fmt::Debug {
+    /// Records a processed list: the identifiers seen for the first time with their new
+    /// IDs, and the pairs of the list (see `counted_pairs`), smaller ID first, whose counts
+    /// are incremented by `weight`. A pair may occur repeatedly.
+    fn add_list(&self, new_identifiers: &[(String, u32)], pairs: &[(u32, u32)], weight: u64) -> Result<(), String>;
+
+    /// Loads all identifiers with their IDs, and all pair counts.
+    fn load_pairs(&self) -> Result<PairState, String>;
+
+    /// Whether the store answers lookups itself (see `pairs_with`), so the pair counts
+    /// aren't kept in memory and `load_pairs` only returns the identifiers.
+    fn serves_lookups(&self) -> bool {
+        false
+    }
+
+    /// Reads the counts of all pairs containing `id`, keyed by the other ID. Only
+    /// supported by stores serving lookups.
+    fn pairs_with(&self, _id: u32) -> Result<HashMap<u32, u64>, String> {
+        Err("The store doesn't serve lookups".to_string())
+    }
+
+    /// Number of distinct pairs with a count, for stores serving lookups.
+    fn pair_count(&self) -> usize {
+        0
+    }
+}
+
+/// Returns the pairs of a list of IDs that `strategy` counts, smaller ID first.
+pub fn counted_pairs(ids: &[u32], strategy: PairStrategy) -> Vec<(u32, u32)> {
+    counted_positions(ids.len(), strategy).into_iter().map(|(i, j)| ordered(ids[i], ids[j])).collect()
+}
+
+/// Like `counted_pairs`, but only the pairs of items seen at most `window` seconds apart,
+/// given when each was seen in `times` (seconds since the Unix epoch, one per ID).
+pub fn counted_pairs_within(ids: &[u32], times: &[i64], window: u64, strategy: PairStrategy) -> Vec<(u32, u32)> {
+    counted_positions(ids.len(), strategy)
+        .into_iter()
+        .filter(|&(i, j)| times[i].abs_diff(times[j]) <= window)
+        .map(|(i, j)| ordered(ids[i], ids[j]))
+        .collect()
+}
+
+fn ordered(id1: u32, id2: u32) -> (u32, u32) {
+    if id1 < id2 { (id1, id2) } else { (id2, id1) }
+}
+
+/// Returns the positions of the pairs `strategy` counts in a list of `len` items, earlier
+/// position first.
+fn counted_positions(len: usize, strategy: PairStrategy) -> Vec<(usize, usize)> {
+    let all = |start: usize, end: usize| (start..end).flat_map(move |i| (i + 1..end).map(move |j| (i, j)));
+    match strategy {
+        PairStrategy::All => all(0, len).collect(),
+        PairStrategy::Sample(max_pairs) => {
+            let total = len * len.saturating_sub(1) / 2;
+            if total <= max_pairs {
+                return all(0, len).collect();
+            }
+            // Every pair whose index crosses the next multiple of total / max_pairs, which
+            // is deterministic, so replaying the list counts the same pairs
+            all(0, len).enumerate().filter(|&(index, _)| index * max_pairs / total != (index + 1) * max_pairs / total).map(|(_, pair)| pair).collect()
+        }
+        PairStrategy::Window(k) => (0..len).flat_map(|i| (i + 1..len).take(k).map(move |j| (i, j))).collect(),
+        PairStrategy::Chunk(size) => (0..len).step_by(size).flat_map(|start| all(start, (start + size).min(len))).collect(),
+    }
+}
+
+/// A pair a list would count, see `CoOccurrenceCounter::dry_run`.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct PlannedPair {
+    /// The identifiers of the pair, in lexicographic order
+    pub a: String,
+    pub b: String,
+    /// Count before the list; 0 for pairs it would create
+    pub count: u64,
+    pub new_count: u64,
+}
+
+/// What processing a list would change, see `CoOccurrenceCounter::dry_run`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, ToSchema)]
+pub struct ListDryRun {
+    /// Identifiers the model doesn't know yet, in the order of the list
+    pub new_identifiers: Vec<String>,
+    pub known_identifiers: Vec<String>,
+    /// The pairs whose counts would be incremented, sorted by identifiers
+    pub pairs: Vec<PlannedPair>,
+}
+
+/// How often an item was in the same lists as a target, relative to the lists of the target.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct ConditionalNeighbor {
+    pub identifier: String,
+    /// Number of lists with both
+    pub count: u64,
+    /// Share of the target's lists the item was in as well, P(item | target), shrunk
+    /// towards the configured prior if the target was in few lists
+    pub probability: f64,
+}
+
+/// What the quality metrics are computed from, see `CoOccurrenceCounter::neighborhoods`.
+#[derive(Debug, Default)]
+pub struct Neighborhoods {
+    /// Per identifier, the number of identifiers it was in a list with
+    pub neighbor_counts: Vec<usize>,
+    /// Per identifier, the number of lists it was in
+    pub occurrences: Vec<u64>,
+    /// The sampled identifiers with their strongest neighbors, strongest first, each with
+    /// the number of lists it was in
+    pub sampled: Vec<(String, Vec<(String, u64)>)>,
+}
+
+/// Returns every pair of positions in a list of IDs, smaller ID first.
+pub fn pairs_of(ids: &[u32]) -> impl Iterator<Item = (u32, u32)> + '_ {
+    ids.iter().enumerate().flat_map(move |(i, &id1)| {
+        ids[i + 1..].iter().map(move |&id2| if id1 < id2 { (id1, id2) } else { (id2, id1) })
+    })
+}
 
 /// A struct to manage identifier-to-ID mapping and co-occurrence counts.
 #[derive(Debug)] // Added derive for Debug for easier printing in tests
 pub struct CoOccurrenceCounter {
-    /// Maps identifier strings to their unique integer IDs.
-    identifier_to_id: HashMap<String, u32, RandomState>,
+    /// Maps identifier strings to their unique integer IDs and back.
+    identifiers: Interner,
     /// Stores the counts for each unique pair of integer IDs.
     /// The tuple (u32, u32) always stores the smaller ID first to ensure uniqueness.
-    co_occurrence_counts: HashMap<(u32, u32), u32, RandomState>,
+    /// Counts are 64 bit, as very hot pairs would eventually overflow 32 bits.
+    co_occurrence_counts: HashMap<(u32, u32), u64, RandomState>,
     /// The next available ID to assign to a new identifier.
     next_id: u32,
+    /// Number of processed lists that changed any counts.
+    changes: u64,
+    /// Per ID, the value of `changes` when the identifier's counts last changed.
+    changed_at: Vec<u64>,
+    /// Per ID, when the identifier was last seen in a list, in seconds since the Unix
+    /// epoch. Identifiers loaded without one count as seen on loading.
+    last_seen: Vec<i64>,
+    /// Per ID, when the identifier was first seen in a list, in seconds since the Unix
+    /// epoch; 0 for identifiers loaded without one, e.g. from a store.
+    first_seen: Vec<i64>,
+    /// Per ID, the number of lists the identifier was in, weighted like the pairs. 0 for
+    /// identifiers loaded from a store or from snapshots written before it was tracked.
+    occurrences: Vec<u64>,
+    /// When the last snapshot or delta was written, in seconds since the Unix epoch.
+    persisted_at: i64,
+    /// Cache of hot lookups, if enabled.
+    metrics_cache: Option<Mutex<MetricsCache>>,
+    /// Every identifier ever interned, shared with the handlers so they can turn away
+    /// unknown ones without the lock.
+    identifier_filter: Arc<IdentifierFilter>,
+    /// Where every processed list is written to, if anywhere.
+    store: Option<Arc<dyn PairStore>>,
+    /// Where snapshots are written to, once recovered from one (see `recover`).
+    snapshot_path: Option<PathBuf>,
+    /// Write-ahead log of the lists processed since the last snapshot, if enabled.
+    wal: Option<EventLog>,
+    /// Sequence number of the last logged list that was applied.
+    log_sequence: u64,
+    /// Whether any list was processed since the last snapshot or delta.
+    dirty: bool,
+    /// Pairs changed since the last snapshot or delta, written with the next delta.
+    dirty_pairs: HashSet<(u32, u32), RandomState>,
+    /// IDs from this one on were assigned since the last snapshot or delta.
+    persisted_ids: u32,
+    /// IDs left unused by removals, handed out again before new ones (see `allocate_id`).
+    free_ids: Vec<u32>,
+    /// Released IDs handed out again since the last snapshot or delta, which the next
+    /// delta has to contain besides those from `persisted_ids` on.
+    recycled_ids: Vec<u32>,
+    /// Number of deltas written on top of the current full snapshot.
+    deltas: usize,
+    /// Generation of the current full snapshot (see `Snapshot`).
+    generation: u64,
+    /// ID of the last snapshot of both the co-occurrences and the counters (see
+    /// `consistent_snapshot`), written with every snapshot and delta.
+    snapshot_id: u64,
+    /// Number of deltas after which the next snapshot is a full one; 0 for full ones only.
+    compaction_deltas: usize,
+    /// How snapshots are written
+    snapshots: SnapshotSettings,
+    /// Whether the IDs were renumbered since the last full snapshot (see `shrink`), so
+    /// the next one can't be a delta.
+    renumbered: bool,
+    /// Number of snapshots and deltas started, so one written without the lock can tell
+    /// whether another was written meanwhile (see `prepare_snapshot`).
+    snapshot_writes: u64,
+    /// Where processed lists are streamed to replicas, if anywhere.
+    change_feed: Option<Arc<ChangeFeed>>,
+    /// Which pairs of a list are counted.
+    pair_strategy: PairStrategy,
+    /// Seconds items of a list with timestamps may be seen apart to be counted as a
+    /// pair; 0 ignores the timestamps.
+    co_visitation_window: u64,
+    /// Limit of distinct identifiers and how it is kept.
+    cardinality: CardinalitySettings,
+}
+
+impl Default for CoOccurrenceCounter {
+    fn default() -> Self {
+        CoOccurrenceCounter::new()
+    }
 }
 
 impl CoOccurrenceCounter {
     /// Creates a new, empty CoOccurrenceCounter.
     pub fn new() -> Self {
         CoOccurrenceCounter {
-            identifier_to_id: HashMap::with_hasher(RandomState::new()),
-            co_occurrence_counts: HashMap::with_hasher(RandomState::new()),
+            identifiers: Interner::new(),
+            co_occurrence_counts: HashMap::with_hasher(determinism::random_state()),
             next_id: 0,
+            changes: 0,
+            changed_at: Vec::new(),
+            last_seen: Vec::new(),
+            first_seen: Vec::new(),
+            occurrences: Vec::new(),
+            persisted_at: 0,
+            metrics_cache: None,
+            identifier_filter: Arc::new(IdentifierFilter::disabled()),
+            store: None,
+            snapshot_path: None,
+            wal: None,
+            log_sequence: 0,
+            dirty: false,
+            dirty_pairs: HashSet::with_hasher(determinism::random_state()),
+            persisted_ids: 0,
+            free_ids: Vec::new(),
+            recycled_ids: Vec::new(),
+            deltas: 0,
+            generation: 0,
+            snapshot_id: 0,
+            compaction_deltas: 0,
+            snapshots: SnapshotSettings::default(),
+            renumbered: false,
+            snapshot_writes: 0,
+            change_feed: None,
+            pair_strategy: PairStrategy::All,
+            co_visitation_window: 0,
+            cardinality: CardinalitySettings::default(),
+        }
+    }
+
+    /// Creates a new, empty CoOccurrenceCounter that caches lookups as configured.
+    pub fn with_metrics_cache(settings: &MetricsCacheSettings) -> Self {
+        let mut counter = CoOccurrenceCounter::new();
+        counter.metrics_cache = NonZeroUsize::new(settings.capacity).map(|capacity| {
+            Mutex::new(MetricsCache { entries: LruCache::new(capacity), ttl: Duration::from_secs(settings.ttl_secs) })
+        });
+        counter
+    }
+
+    /// Loads the state written to `store` so far and writes every list processed from now
+    /// on to it. If the store can't be read, the counter stays in memory only, so it
+    /// doesn't hand out IDs the store already uses.
+    pub fn attach_store(&mut self, store: Arc<dyn PairStore>) {
+        let (identifiers, pairs) = match store.load_pairs() {
+            Ok(state) => state,
+            Err(e) => {
+                error!("Failed to load co-occurrences, keeping them in memory only: {}", e);
+                return;
+            }
+        };
+        for (identifier, id) in identifiers {
+            self.next_id = self.next_id.max(id + 1);
+            self.identifier_filter.insert(&identifier);
+            self.identifiers.insert(&identifier, id);
         }
+        self.changed_at.resize(self.next_id as usize, 0);
+        self.last_seen.resize(self.next_id as usize, determinism::now().timestamp());
+        self.first_seen.resize(self.next_id as usize, 0);
+        self.occurrences.resize(self.next_id as usize, 0);
+        self.co_occurrence_counts.extend(pairs);
+        self.store = Some(store);
+        info!("Loaded {} identifiers and {} co-occurring pairs.", self.identifier_count(), self.pair_count());
+    }
+
+    /// Loads the last snapshot and its deltas from the data directory, replays the
+    /// write-ahead log on top of them and starts a new log. Only for counters without a
+    /// store, which records every list itself.
+    pub fn recover(&mut self, settings: &StorageSettings) {
+        self.recover_from(&settings.data_path(SNAPSHOT_PATH), &settings.data_path(WAL_PATH), settings);
+    }
+
+    fn recover_from(&mut self, snapshot_path: &Path, wal_path: &Path, settings: &StorageSettings) {
+        self.snapshots = settings.snapshots;
+        self.compaction_deltas = settings.lists_compaction_deltas;
+        // Before replaying, so the replayed lists are tracked for the next delta
+        self.snapshot_path = Some(snapshot_path.to_path_buf());
+        if let Some(snapshot) = snapshot::read::<Snapshot>(snapshot_path) {
+            self.log_sequence = snapshot.seq;
+            self.generation = snapshot.generation;
+            self.load_snapshot(snapshot);
+            info!("Loaded {} identifiers and {} co-occurring pairs from {}", self.identifier_count(), self.pair_count(), snapshot_path.display());
+        }
+        let complete = self.load_deltas(snapshot_path);
+
+        let mut replayed = 0;
+        for entry in read_entries::<ListEvent>(wal_path) {
+            if entry.seq <= self.log_sequence {
+                continue;
+            }
+            match entry.event {
+                ListEvent::List { identifiers, times, weight } => self.apply_list(&identifiers, times.as_deref(), weight),
+            }
+            self.log_sequence = entry.seq;
+            replayed += 1;
+        }
+        if replayed > 0 {
+            info!("Replayed {} lists from {}", replayed, wal_path.display());
+        }
+
+        if settings.lists_wal {
+            match EventLog::open(wal_path, settings.lists_wal_sync_batch, self.log_sequence) {
+                Ok(wal) => self.wal = Some(wal),
+                Err(e) => error!("Failed to open list write-ahead log {}: {}", wal_path.display(), e),
+            }
+        }
+        if !complete {
+            // Replaces the unusable deltas
+            self.compact();
+        } else if replayed > 0 {
+            // Fold the replayed lists into a fresh snapshot or delta, which also empties the log
+            self.persist();
+        }
+    }
+
+    /// Applies the deltas written on top of the loaded snapshot, in order. Returns `false`
+    /// if any had to be skipped: unreadable ones, and ones left over from an older
+    /// snapshot, e.g. by a crash during compaction.
+    fn load_deltas(&mut self, snapshot_path: &Path) -> bool {
+        let (mut applied, mut complete) = (0, true);
+        while delta_path(snapshot_path, self.deltas + 1).exists() {
+            self.deltas += 1;
+            let path = delta_path(snapshot_path, self.deltas);
+            match snapshot::read::<Delta>(&path) {
+                Some(delta) if delta.generation == self.generation => {
+                    for (identifier, id) in delta.identifiers {
+                        self.next_id = self.next_id.max(id + 1);
+                        self.identifier_filter.insert(&identifier);
+                        self.identifiers.insert(&identifier, id);
+                    }
+                    self.co_occurrence_counts.extend(delta.pairs.into_iter().map(|(id1, id2, count)| ((id1, id2), count)));
+                    self.last_seen.resize(self.next_id as usize, determinism::now().timestamp());
+                    for (id, seen) in delta.last_seen {
+                        if let Some(last_seen) = self.last_seen.get_mut(id as usize) {
+                            *last_seen = seen;
+                        }
+                    }
+                    self.first_seen.resize(self.next_id as usize, 0);
+                    for (id, seen) in delta.first_seen {
+                        if let Some(first_seen) = self.first_seen.get_mut(id as usize) {
+                            *first_seen = seen;
+                        }
+                    }
+                    self.occurrences.resize(self.next_id as usize, 0);
+                    for (id, lists) in delta.occurrences {
+                        if let Some(occurrences) = self.occurrences.get_mut(id as usize) {
+                            *occurrences = lists;
+                        }
+                    }
+                    self.log_sequence = self.log_sequence.max(delta.seq);
+                    self.snapshot_id = self.snapshot_id.max(delta.snapshot_id);
+                    applied += 1;
+                }
+                Some(_) => complete = false,
+                None => {
+                    error!("Skipped the unreadable delta {}", path.display());
+                    complete = false;
+                }
+            }
+        }
+        self.changed_at.resize(self.next_id as usize, self.changes);
+        self.last_seen.resize(self.next_id as usize, determinism::now().timestamp());
+        self.first_seen.resize(self.next_id as usize, 0);
+        self.occurrences.resize(self.next_id as usize, 0);
+        self.persisted_ids = self.next_id;
+        self.persisted_at = determinism::now().timestamp();
+        self.collect_free_ids();
+        if applied > 0 {
+            info!("Applied {} deltas, now at {} identifiers and {} co-occurring pairs", applied, self.identifier_count(), self.pair_count());
+        }
+        complete
+    }
+
+    /// Replaces the whole state with the one of a snapshot. The log sequence is left to
+    /// the caller.
+    fn load_snapshot(&mut self, snapshot: Snapshot) {
+        self.next_id = snapshot.identifiers.ids().map(|id| id + 1).max().unwrap_or(0);
+        self.snapshot_id = snapshot.snapshot_id;
+        self.identifiers = snapshot.identifiers;
+        self.refill_identifier_filter();
+        // Every identifier's counts changed
+        self.changed_at = vec![self.changes; self.next_id as usize];
+        self.last_seen = snapshot.last_seen;
+        self.last_seen.resize(self.next_id as usize, determinism::now().timestamp());
+        self.first_seen = snapshot.first_seen;
+        self.first_seen.resize(self.next_id as usize, 0);
+        self.occurrences = snapshot.occurrences;
+        self.occurrences.resize(self.next_id as usize, 0);
+        self.co_occurrence_counts = snapshot.pairs.into_iter().map(|(id1, id2, count)| ((id1, id2), count)).collect();
+        self.dirty_pairs.clear();
+        self.persisted_ids = self.next_id;
+        self.collect_free_ids();
+        if let Some(cache) = self.metrics_cache_mut() {
+            cache.entries.clear();
+        }
+    }
+
+    /// Collects the IDs below `next_id` no identifier uses, e.g. after loading a snapshot
+    /// written after removals.
+    fn collect_free_ids(&mut self) {
+        self.recycled_ids.clear();
+        self.free_ids = (0..self.next_id).rev().filter(|&id| self.identifiers.resolve(id).is_none()).collect();
+    }
+
+    /// Replaces the co-occurrences with a retained version of the snapshot (see
+    /// `snapshot::versions`), which becomes the current snapshot right away. Only for
+    /// counters recovered from snapshots.
+    pub fn restore(&mut self, version: &str) -> Result<(), String> {
+        let Some(path) = self.snapshot_path.clone() else {
+            return Err("The co-occurrences are kept in a store, not in snapshots".to_string());
+        };
+        let snapshot = snapshot::read_version::<Snapshot>(&path, version)?;
+        self.replace(snapshot)?;
+        info!("Restored {} identifiers and {} co-occurring pairs from version {}", self.identifier_count(), self.pair_count(), version);
+        Ok(())
+    }
+
+    /// Returns a copy of the whole state, e.g. for a backup. Fails if the counts are kept
+    /// in a store serving lookups instead of in memory.
+    pub fn to_snapshot(&self) -> Result<Snapshot, String> {
+        if self.lookup_store().is_some() {
+            return Err("The co-occurrences are kept in a database, not in memory".to_string());
+        }
+        Ok(self.copy_snapshot())
+    }
+
+    fn copy_snapshot(&self) -> Snapshot {
+        Snapshot {
+            seq: self.log_sequence,
+            generation: self.generation,
+            snapshot_id: self.snapshot_id,
+            identifiers: self.identifiers.clone(),
+            pairs: self.co_occurrence_counts.iter().map(|(&(id1, id2), &count)| (id1, id2, count)).collect(),
+            last_seen: self.last_seen.clone(),
+            first_seen: self.first_seen.clone(),
+            occurrences: self.occurrences.clone(),
+        }
+    }
+
+    /// Replaces the whole state with the one of a snapshot, e.g. of a backup, and writes it
+    /// as the current snapshot right away. Not supported with a co-occurrence database.
+    pub fn replace(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        if self.store.is_some() {
+            return Err("The co-occurrences are kept in a database, which can't be replaced".to_string());
+        }
+        self.changes += 1;
+        self.load_snapshot(snapshot);
+        self.resync_replicas();
+        // The lists logged so far and the deltas are replaced as well
+        self.dirty = true;
+        self.compact();
+        Ok(())
+    }
+
+    /// Whether any list was processed since the last snapshot or delta.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn snapshot_id(&self) -> u64 {
+        self.snapshot_id
+    }
+
+    /// Sets the ID the next snapshot or delta is written with.
+    pub fn set_snapshot_id(&mut self, snapshot_id: u64) {
+        self.snapshot_id = snapshot_id;
+    }
+
+    /// Writes the lists processed since the last snapshot or delta, if any. Usually as a
+    /// delta holding only the changed pairs, but as a full snapshot every
+    /// `compaction_deltas` times, after renumbering, or if most pairs changed anyway.
+    /// Afterwards the
+    /// write-ahead log is emptied. Does nothing unless the counter was recovered
+    /// (see `recover`).
+    #[tracing::instrument(skip_all)]
+    pub fn persist(&mut self) {
+        if self.snapshot_path.is_none() || !self.dirty {
+            return;
+        }
+        if self.needs_full_snapshot() {
+            self.compact();
+        } else {
+            self.write_delta();
+        }
+    }
+
+    fn needs_full_snapshot(&self) -> bool {
+        self.renumbered || self.deltas >= self.compaction_deltas || self.dirty_pairs.len() * 2 >= self.co_occurrence_counts.len()
+    }
+
+    /// Copies what `persist` would write for snapshot ID `id`, and starts tracking changes
+    /// anew, so the copy can be written without holding the lock. Returns `None` if
+    /// nothing changed or a database records every list. Once written, the outcome is
+    /// handed to `finish_snapshot`.
+    pub fn prepare_snapshot(&mut self, id: u64) -> Option<PendingSnapshot> {
+        let snapshot_path = self.snapshot_path.clone().filter(|_| self.dirty)?;
+        self.snapshot_id = id;
+        self.snapshot_writes += 1;
+        let previous = (self.generation, self.deltas);
+        let (path, file) = if self.needs_full_snapshot() {
+            let mut snapshot = self.copy_snapshot();
+            snapshot.generation += 1;
+            self.generation += 1;
+            self.deltas = 0;
+            self.renumbered = false;
+            (snapshot_path, PendingFile::Full(snapshot, previous.1))
+        } else {
+            self.deltas += 1;
+            (delta_path(&snapshot_path, self.deltas), PendingFile::Delta(self.delta()))
+        };
+        self.reset_changes();
+        Some(PendingSnapshot { path, file, snapshots: self.snapshots, seq: self.log_sequence, previous, ticket: self.snapshot_writes })
+    }
+
+    /// Records the outcome of writing `pending`. Once it is written, the write-ahead log
+    /// is emptied unless lists were logged since the copy, which are replayed on top of it
+    /// anyway. If it couldn't be written, the next snapshot is a full one; if another one
+    /// was written meanwhile, which it may have overwritten, a full one is written right
+    /// away.
+    pub fn finish_snapshot(&mut self, pending: PendingSnapshot, result: io::Result<()>) {
+        match result {
+            Ok(()) if self.snapshot_writes == pending.ticket => {
+                if self.log_sequence == pending.seq {
+                    self.truncate_wal();
+                }
+            }
+            Ok(()) => {
+                self.renumbered = true;
+                self.dirty = true;
+                self.compact();
+            }
+            Err(e) => {
+                error!("Failed to write {}: {}", pending.path.display(), e);
+                (self.generation, self.deltas) = pending.previous;
+                self.renumbered = true;
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Writes a full snapshot, which replaces all deltas, unless nothing changed since the
+    /// last one. Also done on shutdown, so a restart only has to load a single file.
+    #[tracing::instrument(skip_all)]
+    pub fn compact(&mut self) {
+        let Some(path) = self.snapshot_path.clone() else {
+            return;
+        };
+        if !self.dirty && self.deltas == 0 {
+            return;
+        }
+        if let Err(e) = self.write_snapshot(&path) {
+            error!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    /// Writes a full snapshot even if nothing changed since the last one, e.g. before a
+    /// planned maintenance. Returns the path written to, `None` if a database records
+    /// every list instead.
+    pub fn flush(&mut self) -> io::Result<Option<PathBuf>> {
+        let Some(path) = self.snapshot_path.clone() else {
+            return Ok(None);
+        };
+        self.write_snapshot(&path)?;
+        Ok(Some(path))
+    }
+
+    fn write_snapshot(&mut self, path: &Path) -> io::Result<()> {
+        self.snapshot_writes += 1;
+        let snapshot = Snapshot {
+            seq: self.log_sequence,
+            generation: self.generation + 1,
+            snapshot_id: self.snapshot_id,
+            identifiers: std::mem::take(&mut self.identifiers),
+            pairs: self.co_occurrence_counts.iter().map(|(&(id1, id2), &count)| (id1, id2, count)).collect(),
+            last_seen: std::mem::take(&mut self.last_seen),
+            first_seen: std::mem::take(&mut self.first_seen),
+            occurrences: std::mem::take(&mut self.occurrences),
+        };
+        let result = snapshot::save(path, &snapshot, self.snapshots);
+        self.identifiers = snapshot.identifiers;
+        self.last_seen = snapshot.last_seen;
+        self.first_seen = snapshot.first_seen;
+        self.occurrences = snapshot.occurrences;
+        result?;
+        self.generation += 1;
+        self.renumbered = false;
+        remove_deltas(path, self.deltas);
+        self.deltas = 0;
+        info!("Co-occurrences persisted.");
+        self.mark_persisted();
+        Ok(())
+    }
+
+    /// Writes the identifiers and pairs changed since the last snapshot or delta.
+    fn write_delta(&mut self) {
+        let Some(path) = self.snapshot_path.as_ref() else {
+            return;
+        };
+        let (delta, path) = (self.delta(), delta_path(path, self.deltas + 1));
+        self.snapshot_writes += 1;
+        match snapshot::save_unversioned(&path, &delta, self.snapshots) {
+            Ok(bytes) => info!(pairs = delta.pairs.len(), bytes, "Co-occurrence delta persisted."),
+            Err(e) => {
+                error!("Failed to write {}: {}", path.display(), e);
+                return;
+            }
+        }
+        self.deltas += 1;
+        self.mark_persisted();
+    }
+
+    /// Copies the identifiers and pairs changed since the last snapshot or delta.
+    fn delta(&self) -> Delta {
+        let seen_since = |id: u32| self.last_seen[id as usize] >= self.persisted_at;
+        Delta {
+            generation: self.generation,
+            seq: self.log_sequence,
+            snapshot_id: self.snapshot_id,
+            identifiers: self
+                .identifiers
+                .iter()
+                .filter(|&(_, id)| id >= self.persisted_ids)
+                .chain(self.recycled_ids.iter().filter_map(|&id| Some((self.identifiers.resolve(id)?, id))))
+                .map(|(identifier, id)| (identifier.to_string(), id))
+                .collect(),
+            pairs: self.dirty_pairs.iter().map(|&(id1, id2)| (id1, id2, self.co_occurrence_counts[&(id1, id2)])).collect(),
+            last_seen: (0..).zip(&self.last_seen).filter(|&(id, _)| seen_since(id)).map(|(id, &seen)| (id, seen)).collect(),
+            first_seen: (0..).zip(&self.first_seen).filter(|&(_, &seen)| seen >= self.persisted_at).map(|(id, &seen)| (id, seen)).collect(),
+            occurrences: (0..).zip(&self.occurrences).filter(|&(id, _)| seen_since(id)).map(|(id, &lists)| (id, lists)).collect(),
+        }
+    }
+
+    /// Starts tracking changes anew after a snapshot or delta, and empties the write-ahead
+    /// log, whose lists are all contained.
+    fn mark_persisted(&mut self) {
+        self.reset_changes();
+        self.truncate_wal();
+    }
+
+    fn reset_changes(&mut self) {
+        self.dirty = false;
+        self.dirty_pairs.clear();
+        self.persisted_ids = self.next_id;
+        self.recycled_ids.clear();
+        self.persisted_at = determinism::now().timestamp();
+    }
+
+    fn truncate_wal(&mut self) {
+        if let Some(wal) = &mut self.wal {
+            if let Err(e) = wal.truncate() {
+                error!("Failed to truncate list write-ahead log: {}", e);
+            }
+        }
+    }
+
+    /// Processes a list of identifiers, updating the co-occurrence counts. The list is
+    /// logged first, so a crash before the next snapshot doesn't lose it.
+    pub fn process_list<S: AsRef<str>>(&mut self, identifiers: &[S]) {
+        self.process_weighted_list(identifiers, 1);
+    }
+
+    /// Processes a list like `process_list`, counting each of its pairs `weight` times,
+    /// for lists from sources that are stronger signals than others.
+    pub fn process_weighted_list<S: AsRef<str>>(&mut self, identifiers: &[S], weight: u64) {
+        self.process_timed_list(identifiers, None, weight);
+    }
+
+    /// Processes a list like `process_weighted_list`. With `times`, when each item was
+    /// seen (seconds since the Unix epoch, one per identifier), only the pairs seen within
+    /// the co-visitation window of each other are counted, e.g. "watched next within 30
+    /// minutes" rather than anywhere in the same session.
+    #[tracing::instrument(skip_all, fields(identifiers = identifiers.len()))]
+    pub fn process_timed_list<S: AsRef<str>>(&mut self, identifiers: &[S], times: Option<&[i64]>, weight: u64) {
+        let owned = || -> Vec<String> { identifiers.iter().map(|identifier| identifier.as_ref().to_string()).collect() };
+        let times = times.map(<[i64]>::to_vec);
+        if let Some(wal) = &mut self.wal {
+            match wal.append(determinism::now(), ListEvent::List { identifiers: owned(), times: times.clone(), weight }) {
+                Ok(sequence) => self.log_sequence = sequence,
+                Err(e) => error!("Failed to append to list write-ahead log: {}", e),
+            }
+        }
+        if let Some(feed) = &self.change_feed {
+            feed.publish(|| Change::List { identifiers: owned(), times: times.clone(), weight });
+        }
+        self.apply_list(identifiers, times.as_deref(), weight);
+        self.enforce_cardinality_limit();
+        stats::record_ingest(determinism::now());
+    }
+
+    /// Counts only the pairs `strategy` selects of every list processed from now on,
+    /// replayed ones included.
+    pub fn set_pair_strategy(&mut self, strategy: PairStrategy) {
+        self.pair_strategy = strategy;
+    }
+
+    /// Counts only the pairs of lists with timestamps that were seen at most `secs` apart
+    /// from now on, replayed ones included; 0 ignores the timestamps.
+    pub fn set_co_visitation_window(&mut self, secs: u64) {
+        self.co_visitation_window = secs;
+    }
+
+    /// Limits the distinct identifiers as configured from now on. Lists replayed on
+    /// recovery are counted regardless.
+    pub fn set_cardinality_limit(&mut self, settings: &CardinalitySettings) {
+        self.cardinality = settings.clone();
+    }
+
+    /// Checks a list against the limit of distinct identifiers with the "reject" policy.
+    /// Returns why it is rejected if counting it would exceed the limit; lists of known
+    /// identifiers only are always admitted.
+    pub fn admits_list<S: AsRef<str>>(&self, identifiers: &[S]) -> Result<(), String> {
+        let Some(reason) = self.cardinality_violation(identifiers) else {
+            return Ok(());
+        };
+        let rejected = stats::record_cardinality_rejection();
+        // Logged for the first and every 1000th, the metric has them all
+        if rejected == 1 || rejected.is_multiple_of(1000) {
+            warn!(rejected, limit = self.cardinality.max_identifiers, "Rejected a list with new identifiers, the limit of distinct identifiers is reached.");
+        }
+        Err(reason)
+    }
+
+    /// Like `admits_list`, but only returns the reason, without recording the rejection.
+    pub fn cardinality_violation<S: AsRef<str>>(&self, identifiers: &[S]) -> Option<String> {
+        if !self.cardinality.rejects_new_identifiers() {
+            return None;
+        }
+        let max = self.cardinality.max_identifiers;
+        let new: HashSet<&str> = identifiers.iter().map(AsRef::as_ref).filter(|identifier| self.identifiers.get(identifier).is_none()).collect();
+        if new.is_empty() || self.identifiers.len() + new.len() <= max {
+            return None;
+        }
+        Some(format!(
+            "The list has {} new identifiers, but the limit of {} distinct identifiers leaves room for {}",
+            new.len(),
+            max,
+            max.saturating_sub(self.identifiers.len())
+        ))
+    }
+
+    /// What processing a list like `process_timed_list` would change, without changing
+    /// anything: the identifiers new to the model, and the pairs it would count with their
+    /// counts before and after.
+    pub fn dry_run<S: AsRef<str>>(&self, identifiers: &[S], times: Option<&[i64]>, weight: u64) -> Result<ListDryRun, String> {
+        let identifiers: Vec<&str> = identifiers.iter().map(AsRef::as_ref).collect();
+        let mut dry_run = ListDryRun::default();
+        let mut seen = HashSet::new();
+        for &identifier in identifiers.iter().filter(|&&identifier| seen.insert(identifier)) {
+            match self.identifiers.get(identifier) {
+                Some(_) => dry_run.known_identifiers.push(identifier.to_string()),
+                None => dry_run.new_identifiers.push(identifier.to_string()),
+            }
+        }
+
+        let times = times.filter(|times| self.co_visitation_window > 0 && times.len() == identifiers.len());
+        let positions = counted_positions(identifiers.len(), self.pair_strategy)
+            .into_iter()
+            .filter(|&(i, j)| times.is_none_or(|times| times[i].abs_diff(times[j]) <= self.co_visitation_window));
+        let mut increments: BTreeMap<(&str, &str), u64> = BTreeMap::new();
+        for (i, j) in positions {
+            let pair = if identifiers[i] <= identifiers[j] { (identifiers[i], identifiers[j]) } else { (identifiers[j], identifiers[i]) };
+            *increments.entry(pair).or_insert(0) += weight;
+        }
+        // Read once per identifier from a store serving lookups
+        let mut stored: HashMap<u32, HashMap<u32, u64>> = HashMap::new();
+        for ((a, b), increment) in increments {
+            let count = match (self.identifiers.get(a), self.identifiers.get(b)) {
+                (Some(id1), Some(id2)) => {
+                    let (id1, id2) = ordered(id1, id2);
+                    match self.lookup_store() {
+                        Some(store) => {
+                            let pairs = match stored.entry(id1) {
+                                Entry::Occupied(entry) => entry.into_mut(),
+                                Entry::Vacant(entry) => entry.insert(store.pairs_with(id1)?),
+                            };
+                            pairs.get(&id2).copied().unwrap_or(0)
+                        }
+                        None => self.co_occurrence_counts.get(&(id1, id2)).copied().unwrap_or(0),
+                    }
+                }
+                _ => 0,
+            };
+            dry_run.pairs.push(PlannedPair { a: a.to_string(), b: b.to_string(), count, new_count: count.saturating_add(increment) });
+        }
+        Ok(dry_run)
+    }
+
+    /// With the "evict" policy, removes the identifiers seen least recently and their
+    /// pairs once the limit of distinct identifiers is exceeded, down to 95% of it, so
+    /// the full snapshot written after a removal isn't due with every new identifier.
+    fn enforce_cardinality_limit(&mut self) {
+        let max = self.cardinality.max_identifiers;
+        if max == 0 || self.cardinality.policy != CardinalityPolicy::Evict || self.identifiers.len() <= max || self.store.is_some() {
+            return;
+        }
+        let mut seen: Vec<(i64, u32)> = self.identifiers.ids().map(|id| (self.last_seen[id as usize], id)).collect();
+        let excess = seen.len() - (max - max / 20);
+        seen.select_nth_unstable(excess - 1);
+        let removed_ids = seen[..excess].iter().map(|&(_, id)| id).collect();
+        let (identifiers, pairs) = self.remove_ids(removed_ids);
+        stats::record_cardinality_eviction(identifiers);
+        warn!(identifiers, pairs, limit = max, "Exceeded the limit of distinct identifiers, evicted the least recently seen.");
+    }
+
+    /// Replaces the filter of known identifiers by one sized as configured, holding the
+    /// identifiers known so far. Call before handing out `identifier_filter`, which
+    /// stays the same from then on.
+    pub fn set_identifier_filter(&mut self, settings: &IdentifierFilterSettings) {
+        let filter = IdentifierFilter::new(settings);
+        self.identifiers.iter().for_each(|(identifier, _)| filter.insert(identifier));
+        self.identifier_filter = Arc::new(filter);
     }
 
-    /// Processes a list of identifiers, updating the co-occurrence counts.
-    pub fn process_list(&mut self, identifiers: &[String]) {
+    /// The filter of known identifiers, which every identifier is added to before the
+    /// counter knows it.
+    pub fn identifier_filter(&self) -> Arc<IdentifierFilter> {
+        Arc::clone(&self.identifier_filter)
+    }
+
+    /// Adds every identifier to the filter, after they were replaced wholesale.
+    fn refill_identifier_filter(&self) {
+        self.identifiers.iter().for_each(|(identifier, _)| self.identifier_filter.insert(identifier));
+    }
+
+    /// Streams every processed list from now on to `feed`. Removals and replacements,
+    /// which replicas can't follow list by list, make them load the whole state again.
+    pub fn attach_change_feed(&mut self, feed: Arc<ChangeFeed>) {
+        self.change_feed = Some(feed);
+    }
+
+    fn resync_replicas(&self) {
+        if let Some(feed) = &self.change_feed {
+            feed.publish(|| Change::Resync);
+        }
+    }
+
+    fn apply_list<S: AsRef<str>>(&mut self, identifiers: &[S], times: Option<&[i64]>, weight: u64) {
+        self.dirty = true;
         let mut current_list_ids: Vec<u32> = Vec::with_capacity(identifiers.len());
-        for id_str in identifiers {
-            let id = *self.identifier_to_id.entry(id_str.clone()).or_insert_with(|| {
-                let new_id = self.next_id;
-                self.next_id += 1;
-                new_id
-            });
+        // Without a window, or with times that don't match the identifiers, as if untimed
+        let times = times.filter(|times| self.co_visitation_window > 0 && times.len() == identifiers.len());
+        let mut current_times: Vec<i64> = Vec::new();
+        let mut new_identifiers = Vec::new();
+        let now = determinism::now().timestamp();
+        let mut skipped = 0;
+        for (position, id_str) in identifiers.iter().enumerate() {
+            let id_str = id_str.as_ref();
+            // Looked up by reference, so only identifiers new to the table are copied
+            let id = match self.identifiers.get(id_str) {
+                Some(id) => id,
+                None => {
+                    let Some(new_id) = self.allocate_id() else {
+                        skipped += 1;
+                        continue;
+                    };
+                    self.identifier_filter.insert(id_str);
+                    self.identifiers.insert(id_str, new_id);
+                    new_identifiers.push((id_str.to_string(), new_id));
+                    new_id
+                }
+            };
+            self.last_seen[id as usize] = now;
+            self.occurrences[id as usize] = self.occurrences[id as usize].saturating_add(weight);
             current_list_ids.push(id);
+            if let Some(times) = times {
+                current_times.push(times[position]);
+            }
         }
+        if skipped > 0 {
+            error!("All {} identifier IDs are in use, skipped {} new identifiers of a list", u32::MAX, skipped);
+        }
+        let pairs = match times {
+            Some(_) => counted_pairs_within(&current_list_ids, &current_times, self.co_visitation_window, self.pair_strategy),
+            None => counted_pairs(&current_list_ids, self.pair_strategy),
+        };
+        if let Some(store) = &self.store {
+            if let Err(e) = store.add_list(&new_identifiers, &pairs, weight) {
+                error!("Failed to write list to the co-occurrence store: {}", e);
+            }
+        }
+        let counts_in_memory = self.lookup_store().is_none();
 
         if identifiers.len() < 2 {
             return;
         }
+        self.changes += 1;
+        for &id in &current_list_ids {
+            self.changed_at[id as usize] = self.changes;
+            if let Some(cache) = self.metrics_cache_mut() {
+                cache.entries.pop(&id);
+            }
+        }
+
+        if counts_in_memory {
+            let track_changes = self.snapshot_path.is_some();
+            for pair in pairs {
+                let count = self.co_occurrence_counts.entry(pair).or_insert(0);
+                *count = count.saturating_add(weight);
+                if track_changes {
+                    self.dirty_pairs.insert(pair);
+                }
+            }
+        }
+    }
+
+    /// Returns an ID for a new identifier: one released by a removal if there is any, so
+    /// the ID space doesn't leak, otherwise the next unused one. `None` once all IDs but
+    /// `u32::MAX`, which is never assigned, are in use; as released IDs are handed out
+    /// first, compacting wouldn't free any either.
+    fn allocate_id(&mut self) -> Option<u32> {
+        if let Some(id) = self.free_ids.pop() {
+            self.recycled_ids.push(id);
+            // The lists of the removed identifier aren't the new one's
+            self.occurrences[id as usize] = 0;
+            self.first_seen[id as usize] = determinism::now().timestamp();
+            return Some(id);
+        }
+        if self.next_id == u32::MAX {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.changed_at.push(0);
+        self.last_seen.push(0);
+        self.first_seen.push(determinism::now().timestamp());
+        self.occurrences.push(0);
+        Some(id)
+    }
+
+    /// Removes every identifier starting with `prefix` and all its pairs, e.g. all items
+    /// of a broadcaster. Returns the number of identifiers and pairs removed. A full
+    /// snapshot is written right away, as deltas and the write-ahead log only ever add
+    /// counts and would bring the removed pairs back on a restart. Not supported with a
+    /// co-occurrence database.
+    pub fn remove_prefix(&mut self, prefix: &str) -> Result<(usize, usize), String> {
+        if self.store.is_some() {
+            return Err("Purging isn't supported with a co-occurrence database".to_string());
+        }
+        let removed_ids =
+            self.identifiers.iter().filter(|(identifier, _)| identifier.starts_with(prefix)).map(|(_, id)| id).collect();
+        Ok(self.remove_ids(removed_ids))
+    }
 
-        for i in 0..current_list_ids.len() {
-            for j in (i + 1)..current_list_ids.len() {
-                let id1 = current_list_ids[i];
-                let id2 = current_list_ids[j];
+    /// Removes every identifier not seen in a list since `cutoff`, in seconds since the
+    /// Unix epoch, and all its pairs, like `remove_prefix`.
+    pub fn evict_unseen(&mut self, cutoff: i64) -> Result<(usize, usize), String> {
+        if self.store.is_some() {
+            return Err("Eviction isn't supported with a co-occurrence database".to_string());
+        }
+        let removed_ids = self.identifiers.ids().filter(|&id| self.last_seen[id as usize] < cutoff).collect();
+        Ok(self.remove_ids(removed_ids))
+    }
 
-                let pair = if id1 < id2 { (id1, id2) } else { (id2, id1) };
+    /// Removes the identifiers with the given IDs and their pairs, and writes a full
+    /// snapshot if any were removed (see `remove_prefix`).
+    fn remove_ids(&mut self, removed_ids: HashSet<u32>) -> (usize, usize) {
+        if removed_ids.is_empty() {
+            return (0, 0);
+        }
+        self.identifiers.retain(|_, id| !removed_ids.contains(&id));
+        self.free_ids.extend(&removed_ids);
+        // Smallest last, so they are handed out first
+        self.free_ids.sort_unstable_by(|a, b| b.cmp(a));
 
-                *self.co_occurrence_counts.entry(pair).or_insert(0) += 1;
+        // The identifiers that lose a pair have changed as well
+        self.changes += 1;
+        let pairs_before = self.co_occurrence_counts.len();
+        let changes = self.changes;
+        let changed_at = &mut self.changed_at;
+        self.co_occurrence_counts.retain(|&(id1, id2), _| {
+            let removed = removed_ids.contains(&id1) || removed_ids.contains(&id2);
+            if removed {
+                changed_at[id1 as usize] = changes;
+                changed_at[id2 as usize] = changes;
             }
+            !removed
+        });
+        let removed_pairs = pairs_before - self.co_occurrence_counts.len();
+        self.dirty_pairs.retain(|(id1, id2)| !removed_ids.contains(id1) && !removed_ids.contains(id2));
+        if let Some(cache) = self.metrics_cache_mut() {
+            cache.entries.clear();
         }
+        self.dirty = true;
+        self.compact();
+        self.resync_replicas();
+        (removed_ids.len(), removed_pairs)
+    }
+
+    /// Releases the IDs left unused by removals (see `remove_prefix`) by renumbering the
+    /// identifiers densely, and shrinks the maps to their contents. Returns the number of
+    /// IDs released. As the snapshot and its deltas use the old IDs, a full snapshot is
+    /// written right away. With a co-occurrence database, which keeps the IDs, the maps
+    /// are only shrunk.
+    pub fn shrink(&mut self) -> usize {
+        let unused_ids = self.next_id as usize - self.identifiers.len();
+        let released = if unused_ids > 0 && self.store.is_none() {
+            self.renumber();
+            unused_ids
+        } else {
+            0
+        };
+        self.identifiers.shrink_to_fit();
+        self.co_occurrence_counts.shrink_to_fit();
+        self.dirty_pairs.shrink_to_fit();
+        self.changed_at.shrink_to_fit();
+        self.last_seen.shrink_to_fit();
+        self.first_seen.shrink_to_fit();
+        self.occurrences.shrink_to_fit();
+        released
+    }
+
+    /// Assigns the identifiers the IDs 0 to n - 1, keeping their order.
+    fn renumber(&mut self) {
+        let mut old_ids: Vec<u32> = self.identifiers.ids().collect();
+        old_ids.sort_unstable();
+        let mut new_ids = vec![u32::MAX; self.next_id as usize];
+        for (new_id, &old_id) in (0..).zip(&old_ids) {
+            new_ids[old_id as usize] = new_id;
+        }
+        self.identifiers.renumber(&new_ids);
+        // The order is kept, so the smaller ID of a pair stays first
+        let renumber_pair = |(id1, id2): (u32, u32)| (new_ids[id1 as usize], new_ids[id2 as usize]);
+        self.co_occurrence_counts = self.co_occurrence_counts.drain().map(|(pair, count)| (renumber_pair(pair), count)).collect();
+        self.dirty_pairs = self.dirty_pairs.drain().map(renumber_pair).collect();
+        self.changed_at = old_ids.iter().map(|&id| self.changed_at[id as usize]).collect();
+        self.last_seen = old_ids.iter().map(|&id| self.last_seen[id as usize]).collect();
+        self.first_seen = old_ids.iter().map(|&id| self.first_seen[id as usize]).collect();
+        self.occurrences = old_ids.iter().map(|&id| self.occurrences[id as usize]).collect();
+        self.next_id = old_ids.len() as u32;
+        self.persisted_ids = self.persisted_ids.min(self.next_id);
+        self.free_ids.clear();
+        if let Some(cache) = self.metrics_cache_mut() {
+            cache.entries.clear();
+        }
+        self.renumbered = true;
+        self.dirty = true;
+        self.compact();
+    }
+
+    /// Returns the store, if it serves lookups instead of the counts in memory.
+    fn lookup_store(&self) -> Option<&Arc<dyn PairStore>> {
+        self.store.as_ref().filter(|store| store.serves_lookups())
     }
 
     /// Returns the current co-occurrence counts.
     #[cfg(test)]
-    pub fn get_co_occurrence_counts(&self) -> &HashMap<(u32, u32), u32, RandomState> {
+    pub fn get_co_occurrence_counts(&self) -> &HashMap<(u32, u32), u64, RandomState> {
         &self.co_occurrence_counts
     }
 
     // /// Returns the mapping from identifier strings to their IDs.
     #[cfg(test)]
-    pub fn get_identifier_to_id_map(&self) -> &HashMap<String, u32, RandomState> {
-        &self.identifier_to_id
+    pub fn get_identifier_to_id_map(&self) -> &HashMap<Arc<str>, u32, RandomState> {
+        self.identifiers.map()
+    }
+
+    /// Returns the number of distinct identifiers seen so far.
+    pub fn identifier_count(&self) -> usize {
+        self.identifiers.len()
+    }
+
+    /// Returns the number of distinct pairs with a count.
+    pub fn pair_count(&self) -> usize {
+        match self.lookup_store() {
+            Some(store) => store.pair_count(),
+            None => self.co_occurrence_counts.len(),
+        }
+    }
+
+    /// Estimated bytes used by the identifier-to-ID mapping, including the identifiers,
+    /// their change markers, first- and last-seen times and occurrence counts.
+    pub fn identifier_map_bytes(&self) -> usize {
+        let changed_at = self.changed_at.capacity() * std::mem::size_of::<u64>();
+        let seen = (self.last_seen.capacity() + self.first_seen.capacity()) * std::mem::size_of::<i64>();
+        let occurrences = self.occurrences.capacity() * std::mem::size_of::<u64>();
+        self.identifiers.bytes() + changed_at + seen + occurrences
+    }
+
+    /// Estimated bytes used by the pair counts, including the pairs changed since the last
+    /// snapshot or delta.
+    pub fn pair_counts_bytes(&self) -> usize {
+        memory::table_bytes::<(u32, u32), u64>(self.co_occurrence_counts.capacity())
+            + memory::table_bytes::<(u32, u32), ()>(self.dirty_pairs.capacity())
+    }
+
+    /// The cache of hot lookups, if enabled, without locking it, as `&mut self` rules
+    /// out lookups holding it.
+    fn metrics_cache_mut(&mut self) -> Option<&mut MetricsCache> {
+        self.metrics_cache.as_mut().map(|cache| cache.get_mut().unwrap_or_else(PoisonError::into_inner))
     }
 
-    /// A helper to get the identifier string for a given ID.
-    pub fn get_id_to_identifier_map(&self) -> HashMap<u32, String> {
-        self.identifier_to_id.iter().map(|(s, &id)| (id, s.clone())).collect()
+    /// Estimated bytes used by the cached lookups.
+    pub fn metrics_cache_bytes(&self) -> usize {
+        let Some(cache) = &self.metrics_cache else {
+            return 0;
+        };
+        let cache = locks::lock(cache, "metrics_cache");
+        let entries = memory::table_bytes::<u32, (Instant, HashMap<String, u64>)>(cache.entries.cap().get());
+        let metrics: usize = cache
+            .entries
+            .iter()
+            .map(|(_, (_, metrics))| {
+                let identifiers: usize = metrics.keys().map(String::capacity).sum();
+                memory::table_bytes::<String, u64>(metrics.capacity()) + identifiers
+            })
+            .sum();
+        entries + metrics
+    }
+
+    /// Returns a version of the co-occurrence counts of `identifier` that changes whenever
+    /// they do, or `None` for unknown identifiers. Versions are only comparable within
+    /// one process.
+    pub fn version_of(&self, identifier: &str) -> Option<u64> {
+        let id = self.identifiers.get(identifier)?;
+        Some(self.changed_at[id as usize])
+    }
+
+    /// Returns the number of lists `identifier` was in, weighted like the pairs; 0 for
+    /// unknown identifiers and those counted before the lists were.
+    pub fn occurrences_of(&self, identifier: &str) -> u64 {
+        self.identifiers.get(identifier).map_or(0, |id| self.occurrences[id as usize])
+    }
+
+    /// Returns when `identifier` was first and last seen in a list, in seconds since the
+    /// Unix epoch, or `None` if it's unknown. The first time is `None` for identifiers
+    /// counted before it was tracked.
+    pub fn lifecycle_of(&self, identifier: &str) -> Option<(Option<i64>, i64)> {
+        let id = self.identifiers.get(identifier)? as usize;
+        let first_seen = Some(self.first_seen[id]).filter(|&seen| seen > 0);
+        Some((first_seen, self.last_seen[id]))
+    }
+
+    /// Returns the identifier with the ID `id`, if any.
+    pub fn identifier_of(&self, id: u32) -> Option<&str> {
+        self.identifiers.resolve(id)
+    }
+
+    /// Collects the `limit` strongest neighbors of each of `ids`, strongest first, in one
+    /// pass over the pairs, calling `visit` with both IDs of every pair on the way. Not
+    /// supported with a co-occurrence database, which doesn't keep the pairs in memory.
+    fn strongest_neighbors_of(&self, ids: &[u32], limit: usize, mut visit: impl FnMut(u32)) -> Result<Vec<Vec<u32>>, String> {
+        if self.store.is_some() {
+            return Err("Reading all pairs isn't supported with a co-occurrence database".to_string());
+        }
+        let positions: HashMap<u32, usize, RandomState> = ids.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+        // Min-heaps, so the weakest of the strongest neighbors so far is dropped
+        let mut strongest: Vec<BinaryHeap<Reverse<(u64, u32)>>> = vec![BinaryHeap::new(); ids.len()];
+        for (&(id_a, id_b), &count) in self.co_occurrence_counts.iter() {
+            for (id, other_id) in [(id_a, id_b), (id_b, id_a)] {
+                visit(id);
+                if let Some(&i) = positions.get(&id) {
+                    strongest[i].push(Reverse((count, other_id)));
+                    if strongest[i].len() > limit {
+                        strongest[i].pop();
+                    }
+                }
+            }
+        }
+        Ok(strongest.into_iter().map(|heap| heap.into_sorted_vec().into_iter().map(|Reverse((_, id))| id).collect()).collect())
+    }
+
+    /// Returns the `limit` strongest neighbors of each known one of `identifiers`,
+    /// strongest first, as GET /lists/{identifier} ranks them. Takes one pass over the
+    /// pairs for all of them, see `strongest_neighbors_of`.
+    pub fn strongest_neighbors(&self, identifiers: &[&str], limit: usize) -> Result<HashMap<String, Vec<String>>, String> {
+        let ids: Vec<u32> = identifiers.iter().filter_map(|identifier| self.identifiers.get(identifier)).collect();
+        let neighbors = self.strongest_neighbors_of(&ids, limit, |_| {})?;
+        Ok(ids
+            .iter()
+            .zip(neighbors)
+            .filter_map(|(&id, neighbors)| {
+                let neighbors = neighbors.into_iter().filter_map(|other_id| Some(self.identifier_of(other_id)?.to_string())).collect();
+                Some((self.identifier_of(id)?.to_string(), neighbors))
+            })
+            .collect())
+    }
+
+    /// Collects what the quality metrics are computed from in one pass over the pairs: the
+    /// number of neighbors and lists of every identifier, and the `limit` strongest
+    /// neighbors of the `sample` most frequent ones, whose neighbors are shown most.
+    pub fn neighborhoods(&self, sample: usize, limit: usize) -> Result<Neighborhoods, String> {
+        let mut sampled_ids: Vec<u32> = self.identifiers.ids().collect();
+        sampled_ids.sort_unstable_by_key(|&id| (Reverse(self.occurrences[id as usize]), id));
+        sampled_ids.truncate(sample);
+
+        let mut neighbor_counts = vec![0; self.next_id as usize];
+        let strongest = self.strongest_neighbors_of(&sampled_ids, limit, |id| neighbor_counts[id as usize] += 1)?;
+        let sampled = sampled_ids
+            .iter()
+            .zip(strongest)
+            .filter_map(|(&id, neighbors)| {
+                let neighbors = neighbors
+                    .into_iter()
+                    .filter_map(|other_id| Some((self.identifier_of(other_id)?.to_string(), self.occurrences[other_id as usize])))
+                    .collect();
+                Some((self.identifier_of(id)?.to_string(), neighbors))
+            })
+            .collect();
+        Ok(Neighborhoods {
+            neighbor_counts: self.identifiers.ids().map(|id| neighbor_counts[id as usize]).collect(),
+            occurrences: self.identifiers.ids().map(|id| self.occurrences[id as usize]).collect(),
+            sampled,
+        })
     }
 
     /// Gets co-occurrence metrics for a specific identifier.
-    pub fn get_metrics_for_identifier(&self, target_id_str: &str) -> HashMap<String, u32> {
+    pub fn get_metrics_for_identifier(&self, target_id_str: &str) -> HashMap<String, u64> {
         let mut metrics = HashMap::new();
 
-        let Some(&target_id) = self.identifier_to_id.get(target_id_str) else {
+        let Some(target_id) = self.identifiers.get(target_id_str) else {
             return metrics;
         };
 
-        let id_to_str_map = self.get_id_to_identifier_map();
+        if let Some(store) = self.lookup_store() {
+            match store.pairs_with(target_id) {
+                Ok(pairs) => metrics.extend(pairs.into_iter().filter_map(|(id, count)| Some((self.identifier_of(id)?.to_string(), count)))),
+                Err(e) => error!("Failed to read co-occurrences of {}: {}", target_id_str, e),
+            }
+            return metrics;
+        }
 
         for (&(id_a, id_b), &count) in self.co_occurrence_counts.iter() {
-            if id_a == target_id {
-                let co_occurring_id_str = id_to_str_map.get(&id_b).unwrap();
-                metrics.insert(co_occurring_id_str.clone(), count);
+            let other_id = if id_a == target_id {
+                id_b
             } else if id_b == target_id {
-                let co_occurring_id_str = id_to_str_map.get(&id_a).unwrap();
-                metrics.insert(co_occurring_id_str.clone(), count);
+                id_a
+            } else {
+                continue;
+            };
+            if let Some(co_occurring_id_str) = self.identifier_of(other_id) {
+                metrics.insert(co_occurring_id_str.to_string(), count);
+            }
+        }
+        metrics
+    }
+
+    /// Like `get_metrics_for_identifier`, but served from the cache if possible. Only
+    /// needs shared access, so lookups don't wait for each other; the cache is locked
+    /// just for checking and filling it, not while the metrics are collected.
+    pub fn cached_metrics_for_identifier(&self, target_id_str: &str) -> HashMap<String, u64> {
+        let Some(target_id) = self.identifiers.get(target_id_str) else {
+            return HashMap::new();
+        };
+        let now = Instant::now();
+        if let Some(cache) = &self.metrics_cache {
+            let mut cache = locks::lock(cache, "metrics_cache");
+            let ttl = cache.ttl;
+            if let Some((_, metrics)) = cache.entries.get(&target_id).filter(|(cached_at, _)| now - *cached_at < ttl) {
+                return metrics.clone();
             }
         }
+
+        let metrics = self.get_metrics_for_identifier(target_id_str);
+        if let Some(cache) = &self.metrics_cache {
+            locks::lock(cache, "metrics_cache").entries.put(target_id, (now, metrics.clone()));
+        }
         metrics
     }
+
+    /// Expands `direct`, the pair counts of the neighbors of `seeds` summed over the seeds,
+    /// by a second hop: the neighbors of the strongest direct ones that are neither a seed
+    /// nor a direct neighbor. Each is scored by the direct neighbor's count times the share
+    /// of that neighbor's lists it was in as well, decayed, and summed over the direct
+    /// neighbors leading to it. Returns the strongest, highest score first, or `None` if
+    /// `deadline` passed before all the direct neighbors were expanded.
+    pub fn second_hop(
+        &self,
+        seeds: &[String],
+        direct: &HashMap<String, u64>,
+        settings: &TwoHopSettings,
+        deadline: Option<Instant>,
+    ) -> Option<Vec<(String, f64)>> {
+        let mut strongest: Vec<(&String, u64)> = direct.iter().map(|(identifier, &count)| (identifier, count)).collect();
+        strongest.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        strongest.truncate(settings.fan_out);
+
+        let mut scores: HashMap<String, f64> = HashMap::new();
+        for (neighbor, count) in strongest {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return None;
+            }
+            let neighbors = self.cached_metrics_for_identifier(neighbor);
+            // At least as many as the most it shared with another item, see `conditional_for_identifier`
+            let lists = self.occurrences_of(neighbor).max(neighbors.values().copied().max().unwrap_or(0));
+            if lists == 0 {
+                continue;
+            }
+            for (identifier, shared) in neighbors {
+                if direct.contains_key(&identifier) || seeds.contains(&identifier) {
+                    continue;
+                }
+                *scores.entry(identifier).or_insert(0.0) += settings.decay * count as f64 * shared as f64 / lists as f64;
+            }
+        }
+        let mut scores: Vec<(String, f64)> = scores.into_iter().collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scores.truncate(settings.max_candidates);
+        Some(scores)
+    }
+
+    /// Returns the number of lists `identifier` was in and its neighbors ranked by the share
+    /// of those lists they were in as well (descending, ties by identifier), or `None` for
+    /// unknown identifiers. Unlike the pair counts, the shares are directional: a niche
+    /// item always watched with a hit has a high share, the hit a low one. Identifiers
+    /// counted before the lists were (see `occurrences`) use their highest pair count as
+    /// the number of lists, a lower bound. The shares are damped by `shrinkage`.
+    pub fn conditional_for_identifier(&self, identifier: &str, shrinkage: &Shrinkage) -> Option<(u64, Vec<ConditionalNeighbor>)> {
+        let id = self.identifiers.get(identifier)?;
+        let metrics = self.cached_metrics_for_identifier(identifier);
+        let lists = metrics.values().copied().max().unwrap_or(0).max(self.occurrences[id as usize]);
+        let mut neighbors: Vec<ConditionalNeighbor> = metrics
+            .into_iter()
+            .map(|(neighbor, count)| ConditionalNeighbor { identifier: neighbor, count, probability: shrinkage.share(count, lists) })
+            .collect();
+        neighbors.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.identifier.cmp(&b.identifier)));
+        Some((lists, neighbors))
+    }
 }
 
 #[cfg(test)]
@@ -117,6 +1538,91 @@ mod tests {
         assert_eq!(counts.len(), 3);
     }
 
+    #[test]
+    fn test_borrowed_lists_count_like_owned_ones() {
+        let mut counter = CoOccurrenceCounter::new();
+        counter.process_list(&[ID1_STR, ID2_STR]);
+        counter.process_list(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        let id_map = counter.get_identifier_to_id_map();
+        let (id1, id2) = (id_map[ID1_STR], id_map[ID2_STR]);
+        assert_eq!(id_map.len(), 2);
+        assert_eq!(counter.get_co_occurrence_counts()[&(id1.min(id2), id1.max(id2))], 2);
+    }
+
+    #[test]
+    fn test_weighted_lists_count_their_pairs_repeatedly() {
+        let weights: crate::config::SourceWeights = "playlist=3; search=1".parse().unwrap();
+        assert_eq!((weights.of(Some("playlist")), weights.of(Some("unknown")), weights.of(None)), (3, 1, 1));
+        assert!("search=0".parse::<crate::config::SourceWeights>().is_err());
+
+        let mut counter = CoOccurrenceCounter::new();
+        counter.process_weighted_list(&[ID1_STR, ID2_STR], weights.of(Some("playlist")));
+        counter.process_list(&[ID1_STR, ID2_STR]);
+        assert_eq!(counter.get_metrics_for_identifier(ID1_STR).get(ID2_STR), Some(&4));
+    }
+
+    #[test]
+    fn test_pair_strategies_bound_the_pairs_of_long_lists() {
+        let ids: Vec<u32> = (0..6).collect();
+        assert_eq!(counted_pairs(&ids, PairStrategy::All).len(), 15);
+        assert_eq!(counted_pairs(&ids, PairStrategy::Sample(15)).len(), 15);
+        let sampled = counted_pairs(&ids, PairStrategy::Sample(4));
+        assert_eq!(sampled.len(), 4);
+        assert_eq!(sampled, counted_pairs(&ids, PairStrategy::Sample(4)));
+        assert_eq!(counted_pairs(&[5, 3, 4], PairStrategy::Window(1)), [(3, 5), (3, 4)]);
+        assert_eq!(counted_pairs(&ids, PairStrategy::Chunk(2)), [(0, 1), (2, 3), (4, 5)]);
+
+        assert_eq!("window:10".parse::<PairStrategy>(), Ok(PairStrategy::Window(10)));
+        assert!("chunk:0".parse::<PairStrategy>().is_err() && "all:3".parse::<PairStrategy>().is_err());
+
+        let mut counter = CoOccurrenceCounter::new();
+        counter.set_pair_strategy(PairStrategy::Window(1));
+        counter.process_list(&[ID1_STR, ID2_STR, ID3_STR]);
+        assert_eq!(counter.pair_count(), 2);
+        assert!(!counter.get_metrics_for_identifier(ID1_STR).contains_key(ID3_STR));
+    }
+
+    #[test]
+    fn test_only_pairs_seen_within_the_window_are_counted() {
+        // Exactly the window apart still counts, one second more doesn't
+        assert_eq!(counted_pairs_within(&[0, 1, 2], &[0, 1800, 1801], 1800, PairStrategy::All), [(0, 1), (1, 2)]);
+
+        let mut counter = CoOccurrenceCounter::new();
+        counter.set_co_visitation_window(1800);
+        counter.process_timed_list(&[ID1_STR, ID2_STR, ID3_STR], Some(&[0, 1800, 1801]), 1);
+        assert_eq!(counter.pair_count(), 2);
+        assert!(!counter.get_metrics_for_identifier(ID1_STR).contains_key(ID3_STR));
+        // Without the window, or with times not matching the identifiers, every pair counts
+        counter.process_timed_list(&[ID1_STR, ID3_STR], Some(&[0]), 1);
+        assert_eq!(counter.get_metrics_for_identifier(ID1_STR).get(ID3_STR), Some(&1));
+        counter.set_co_visitation_window(0);
+        counter.process_timed_list(&[ID1_STR, ID3_STR], Some(&[0, 3000]), 1);
+        assert_eq!(counter.get_metrics_for_identifier(ID1_STR).get(ID3_STR), Some(&2));
+    }
+
+    #[test]
+    fn test_conditional_shares_are_directional() {
+        let mut counter = CoOccurrenceCounter::new();
+        counter.process_list(&[ID1_STR, ID2_STR]);
+        counter.process_list(&[ID1_STR, ID3_STR]);
+        counter.process_list(&[ID1_STR]);
+        counter.process_list(&[ID1_STR, ID2_STR]);
+
+        let unshrunk = Shrinkage::default();
+        let (lists, neighbors) = counter.conditional_for_identifier(ID1_STR, &unshrunk).unwrap();
+        assert_eq!(lists, 4);
+        assert_eq!(neighbors[0], ConditionalNeighbor { identifier: ID2_STR.to_string(), count: 2, probability: 0.5 });
+        assert_eq!(neighbors[1].probability, 0.25);
+        // Every list of ID2 had ID1 as well
+        assert_eq!(counter.conditional_for_identifier(ID2_STR, &unshrunk).unwrap().1[0].probability, 1.0);
+        assert!(counter.conditional_for_identifier(ID4_STR, &unshrunk).is_none());
+
+        // Two of two lists are weak evidence, 200 of 200 strong
+        let shrinkage = Shrinkage { prior: 0.1, pseudo_counts: 2.0 };
+        assert_eq!(counter.conditional_for_identifier(ID2_STR, &shrinkage).unwrap().1[0].probability, 0.55);
+        assert!(shrinkage.share(200, 200) > 0.99);
+    }
+
     #[test]
     fn test_multiple_lists_and_cumulative_counts() {
         let mut counter = CoOccurrenceCounter::new();
@@ -148,7 +1654,7 @@ mod tests {
     #[test]
     fn test_empty_and_single_element_lists() {
         let mut counter = CoOccurrenceCounter::new();
-        counter.process_list(&[]);
+        counter.process_list::<String>(&[]);
         assert!(counter.get_co_occurrence_counts().is_empty());
         assert!(counter.get_identifier_to_id_map().is_empty());
 
@@ -189,4 +1695,332 @@ mod tests {
         let metrics = counter.get_metrics_for_identifier("non_existent_id");
         assert!(metrics.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_memory_estimates_include_identifiers() {
+        let mut counter = CoOccurrenceCounter::new();
+        assert_eq!(counter.identifier_map_bytes(), 0);
+        assert_eq!(counter.pair_counts_bytes(), 0);
+
+        counter.process_list(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        assert!(counter.identifier_map_bytes() > ID1_STR.len() + ID2_STR.len());
+        assert!(counter.pair_counts_bytes() >= std::mem::size_of::<((u32, u32), u64)>());
+    }
+
+    #[test]
+    fn test_version_changes_with_counts() {
+        let mut counter = CoOccurrenceCounter::new();
+        assert_eq!(counter.version_of(ID1_STR), None);
+        counter.process_list(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        let (v1, v2) = (counter.version_of(ID1_STR).unwrap(), counter.version_of(ID2_STR).unwrap());
+
+        counter.process_list(&[ID2_STR.to_string(), ID3_STR.to_string()]);
+        assert_eq!(counter.version_of(ID1_STR), Some(v1));
+        assert_ne!(counter.version_of(ID2_STR), Some(v2));
+
+        // A single identifier doesn't change any counts
+        counter.process_list(&[ID1_STR.to_string()]);
+        assert_eq!(counter.version_of(ID1_STR), Some(v1));
+    }
+
+    #[test]
+    fn test_metrics_cache_is_invalidated_by_lists() {
+        let mut counter = CoOccurrenceCounter::with_metrics_cache(&MetricsCacheSettings { capacity: 2, ttl_secs: 60 });
+        counter.process_list(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        assert_eq!(counter.cached_metrics_for_identifier(ID1_STR), counter.get_metrics_for_identifier(ID1_STR));
+        assert!(counter.metrics_cache_bytes() > 0);
+
+        counter.process_list(&[ID1_STR.to_string(), ID3_STR.to_string()]);
+        assert_eq!(counter.cached_metrics_for_identifier(ID1_STR).len(), 2);
+        assert!(counter.cached_metrics_for_identifier("non_existent_id").is_empty());
+
+        let mut expired = CoOccurrenceCounter::with_metrics_cache(&MetricsCacheSettings { capacity: 2, ttl_secs: 0 });
+        expired.process_list(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        expired.cached_metrics_for_identifier(ID1_STR);
+        // Changed behind the cache's back, which only the TTL catches
+        expired.co_occurrence_counts.clear();
+        assert!(expired.cached_metrics_for_identifier(ID1_STR).is_empty());
+    }
+
+    #[test]
+    fn test_identifier_filter_follows_the_identifiers() {
+        let mut counter = CoOccurrenceCounter::new();
+        counter.process_list(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        assert!(!counter.identifier_filter().is_enabled());
+
+        counter.set_identifier_filter(&IdentifierFilterSettings { capacity: 100, false_positive_rate: 0.01 });
+        let filter = counter.identifier_filter();
+        counter.process_list(&[ID1_STR.to_string(), ID3_STR.to_string()]);
+        assert!([ID1_STR, ID2_STR, ID3_STR].iter().all(|identifier| filter.may_contain(identifier)));
+        assert!(!filter.may_contain(ID4_STR));
+    }
+
+    #[test]
+    fn test_lists_are_recovered_from_snapshot_and_wal() {
+        let directory = std::env::temp_dir();
+        let snapshot_path = directory.join(format!("co_occurrences_{}.json", std::process::id()));
+        let wal_path = directory.join(format!("co_occurrences_{}.log", std::process::id()));
+        let _ = (std::fs::remove_file(&snapshot_path), std::fs::remove_file(&wal_path));
+        let settings = crate::config::Settings::from_env().storage;
+        let recover = || {
+            let mut counter = CoOccurrenceCounter::new();
+            counter.recover_from(&snapshot_path, &wal_path, &settings);
+            counter
+        };
+
+        let mut counter = recover();
+        counter.process_list(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        counter.persist();
+        counter.process_list(&[ID1_STR.to_string(), ID3_STR.to_string()]);
+        counter.wal.as_mut().unwrap().sync().unwrap();
+        // Crashes before the next snapshot
+        drop(counter);
+
+        let recovered = recover();
+        let metrics = recovered.get_metrics_for_identifier(ID1_STR);
+        assert_eq!((metrics.get(ID2_STR), metrics.get(ID3_STR)), (Some(&1), Some(&1)));
+        assert_eq!(recovered.next_id, 3);
+        // Replayed lists are folded into the snapshot
+        assert!(read_entries::<ListEvent>(&wal_path).is_empty());
+        assert_eq!(recover().pair_count(), 2);
+        let _ = (std::fs::remove_file(&snapshot_path), std::fs::remove_file(&wal_path));
+    }
+
+    #[test]
+    fn test_deltas_are_applied_on_top_of_the_snapshot() {
+        let directory = std::env::temp_dir();
+        let snapshot_path = directory.join(format!("co_occurrence_deltas_{}.json", std::process::id()));
+        let wal_path = directory.join(format!("co_occurrence_deltas_{}.log", std::process::id()));
+        let mut settings = crate::config::Settings::from_env().storage;
+        settings.lists_compaction_deltas = 2;
+        let recover = || {
+            let mut counter = CoOccurrenceCounter::new();
+            counter.recover_from(&snapshot_path, &wal_path, &settings);
+            counter
+        };
+        let list = |identifiers: &[&str]| identifiers.iter().map(|identifier| identifier.to_string()).collect::<Vec<_>>();
+
+        let mut counter = recover();
+        counter.process_list(&list(&["a", "b", "c", "d", "e"]));
+        counter.persist();
+        counter.process_list(&list(&["a", "b"]));
+        counter.persist();
+        counter.process_list(&list(&["a", "f"]));
+        counter.persist();
+        assert_eq!((counter.generation, counter.deltas), (1, 2));
+        assert!(delta_path(&snapshot_path, 2).exists());
+
+        let mut recovered = recover();
+        let metrics = recovered.get_metrics_for_identifier("a");
+        assert_eq!((metrics.get("b"), metrics.get("c"), metrics.get("f")), (Some(&2), Some(&1), Some(&1)));
+        assert_eq!((recovered.next_id, recovered.pair_count()), (6, 11));
+
+        // The third change is compacted into a full snapshot, replacing the deltas
+        recovered.process_list(&list(&["b", "f"]));
+        recovered.persist();
+        assert_eq!((recovered.generation, recovered.deltas), (2, 0));
+        assert!(!delta_path(&snapshot_path, 1).exists());
+        assert_eq!(recover().get_metrics_for_identifier("f").get("b"), Some(&1));
+        let backup_path = directory.join(format!("co_occurrence_deltas_{}.json.bak", std::process::id()));
+        let _ = (std::fs::remove_file(&snapshot_path), std::fs::remove_file(&backup_path), std::fs::remove_file(&wal_path));
+    }
+
+    #[test]
+    fn test_purged_identifiers_stay_removed_after_a_restart() {
+        let directory = std::env::temp_dir();
+        let snapshot_path = directory.join(format!("co_occurrence_purge_{}.json", std::process::id()));
+        let wal_path = directory.join(format!("co_occurrence_purge_{}.log", std::process::id()));
+        let settings = crate::config::Settings::from_env().storage;
+        let recover = || {
+            let mut counter = CoOccurrenceCounter::new();
+            counter.recover_from(&snapshot_path, &wal_path, &settings);
+            counter
+        };
+
+        let mut counter = recover();
+        counter.process_list(&[ID1_STR.to_string(), ID2_STR.to_string(), ID3_STR.to_string()]);
+        counter.process_list(&[ID2_STR.to_string(), ID4_STR.to_string()]);
+        assert_eq!(counter.remove_prefix("zdf:"), Ok((1, 3)));
+        assert_eq!(counter.remove_prefix("zdf:"), Ok((0, 0)));
+        assert!(counter.get_metrics_for_identifier(ID2_STR).is_empty());
+
+        // The logged lists containing the identifier aren't replayed
+        let recovered = recover();
+        assert_eq!(recovered.pair_count(), 1);
+        assert!(!recovered.get_identifier_to_id_map().contains_key(ID2_STR));
+        let backup_path = directory.join(format!("co_occurrence_purge_{}.json.bak", std::process::id()));
+        let _ = (std::fs::remove_file(&snapshot_path), std::fs::remove_file(&backup_path), std::fs::remove_file(&wal_path));
+    }
+
+    #[test]
+    fn test_released_ids_are_recycled_and_persisted_with_the_next_delta() {
+        let directory = std::env::temp_dir();
+        let snapshot_path = directory.join(format!("co_occurrence_recycle_{}.json", std::process::id()));
+        let wal_path = directory.join(format!("co_occurrence_recycle_{}.log", std::process::id()));
+        let settings = crate::config::Settings::from_env().storage;
+        let recover = || {
+            let mut counter = CoOccurrenceCounter::new();
+            counter.recover_from(&snapshot_path, &wal_path, &settings);
+            counter
+        };
+
+        let mut counter = recover();
+        counter.process_list(&[ID1_STR, ID2_STR, ID3_STR, "a", "b"]);
+        assert_eq!(counter.remove_prefix("zdf:"), Ok((1, 4)));
+        counter.process_list(&[ID4_STR, ID1_STR]);
+        let id4 = counter.get_identifier_to_id_map()[ID4_STR];
+        assert_eq!((id4, counter.next_id), (1, 5));
+        counter.persist();
+        assert_eq!(counter.deltas, 1);
+
+        let recovered = recover();
+        assert_eq!(recovered.identifier_of(1), Some(ID4_STR));
+        assert_eq!(recovered.get_metrics_for_identifier(ID1_STR).get(ID4_STR), Some(&1));
+        assert!(recovered.free_ids.is_empty());
+        let backup_path = directory.join(format!("co_occurrence_recycle_{}.json.bak", std::process::id()));
+        let _ = (std::fs::remove_file(&snapshot_path), std::fs::remove_file(&backup_path), std::fs::remove_file(&wal_path));
+    }
+
+    #[test]
+    fn test_new_identifiers_are_skipped_once_all_ids_are_used() {
+        let mut counter = CoOccurrenceCounter::new();
+        counter.process_list(&[ID1_STR, ID2_STR]);
+        assert_eq!(counter.remove_prefix("zdf:"), Ok((1, 1)));
+        counter.next_id = u32::MAX;
+
+        // The released ID is still handed out, then nothing
+        counter.process_list(&[ID3_STR, ID4_STR, ID1_STR]);
+        assert_eq!(counter.identifier_of(1), Some(ID3_STR));
+        assert!(!counter.get_identifier_to_id_map().contains_key(ID4_STR));
+        assert_eq!(counter.get_metrics_for_identifier(ID1_STR).get(ID3_STR), Some(&1));
+    }
+
+    #[test]
+    fn test_last_seen_survives_a_restart_and_drives_eviction() {
+        let directory = std::env::temp_dir();
+        let snapshot_path = directory.join(format!("co_occurrence_evict_{}.json", std::process::id()));
+        let wal_path = directory.join(format!("co_occurrence_evict_{}.log", std::process::id()));
+        let settings = crate::config::Settings::from_env().storage;
+        let recover = || {
+            let mut counter = CoOccurrenceCounter::new();
+            counter.recover_from(&snapshot_path, &wal_path, &settings);
+            counter
+        };
+
+        let mut counter = recover();
+        counter.process_list(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        counter.process_list(&[ID3_STR.to_string(), ID4_STR.to_string()]);
+        counter.process_list(&[ID1_STR.to_string(), ID3_STR.to_string()]);
+        counter.compact();
+        counter.process_list(&[ID2_STR.to_string(), ID3_STR.to_string()]);
+        let id1 = counter.get_identifier_to_id_map()[ID1_STR];
+        counter.last_seen[id1 as usize] = 1000;
+        counter.persisted_at = 0;
+        // Written as a delta, whose last-seen times apply on top of the snapshot
+        counter.persist();
+        assert_eq!(counter.deltas, 1);
+
+        let mut recovered = recover();
+        assert_eq!(recovered.last_seen[id1 as usize], 1000);
+        assert_eq!(recovered.evict_unseen(2000), Ok((1, 2)));
+        assert_eq!(recovered.evict_unseen(2000), Ok((0, 0)));
+        assert_eq!(recover().identifier_count(), 3);
+        let backup_path = directory.join(format!("co_occurrence_evict_{}.json.bak", std::process::id()));
+        let _ = (std::fs::remove_file(&snapshot_path), std::fs::remove_file(&backup_path), std::fs::remove_file(&wal_path));
+    }
+
+    #[test]
+    fn test_cardinality_limit_rejects_or_evicts_new_identifiers() {
+        let mut counter = CoOccurrenceCounter::new();
+        counter.set_cardinality_limit(&CardinalitySettings { max_identifiers: 3, policy: CardinalityPolicy::Reject });
+        counter.process_list(&[ID1_STR, ID2_STR]);
+        assert!(counter.admits_list(&[ID1_STR, ID3_STR, ID3_STR]).is_ok());
+        assert!(counter.admits_list(&[ID3_STR, ID4_STR]).is_err());
+        // Known identifiers only are always admitted
+        assert!(counter.admits_list(&[ID2_STR, ID1_STR]).is_ok());
+
+        let mut counter = CoOccurrenceCounter::new();
+        counter.set_cardinality_limit(&CardinalitySettings { max_identifiers: 20, policy: CardinalityPolicy::Evict });
+        assert!(counter.admits_list(&[ID1_STR, ID2_STR]).is_ok());
+        let identifiers: Vec<String> = (0..21).map(|i| format!("ard:{}", i)).collect();
+        for (i, list) in identifiers.chunks(3).enumerate() {
+            counter.process_list(list);
+            for identifier in list {
+                let id = counter.get_identifier_to_id_map()[identifier.as_str()];
+                counter.last_seen[id as usize] = i as i64;
+            }
+        }
+        // Down to 19, the two seen first evicted
+        assert_eq!(counter.identifier_count(), 19);
+        assert!(!counter.get_identifier_to_id_map().contains_key("ard:0"));
+        assert!(counter.get_identifier_to_id_map().contains_key("ard:2"));
+    }
+
+    #[test]
+    fn test_dry_run_reports_the_pairs_without_counting_them() {
+        let mut counter = CoOccurrenceCounter::new();
+        counter.process_list(&[ID1_STR, ID2_STR]);
+        let dry_run = counter.dry_run(&[ID2_STR, ID1_STR, ID3_STR, ID1_STR], None, 2).unwrap();
+        assert_eq!((dry_run.known_identifiers.len(), dry_run.new_identifiers.as_slice()), (2, [ID3_STR.to_string()].as_slice()));
+        let counts: Vec<(&str, &str, u64, u64)> =
+            dry_run.pairs.iter().map(|pair| (pair.a.as_str(), pair.b.as_str(), pair.count, pair.new_count)).collect();
+        // The repeated identifier counts its pairs twice, and with itself
+        assert_eq!(counts.len(), 4);
+        assert!(counts.contains(&(ID1_STR, ID2_STR, 1, 5)));
+        assert!(counts.iter().any(|&(a, b, count, new_count)| a == b && (count, new_count) == (0, 2)));
+        assert_eq!(counter.identifier_count(), 2);
+        assert_eq!(counter.get_co_occurrence_counts().len(), 1);
+    }
+
+    #[test]
+    fn test_second_hop_reaches_the_neighbors_of_neighbors() {
+        let mut counter = CoOccurrenceCounter::new();
+        let list = |identifiers: &[&str]| identifiers.iter().map(|identifier| identifier.to_string()).collect::<Vec<String>>();
+        // The seed "a" was only ever watched with "b", which has neighbors of its own
+        counter.process_list(&list(&["a", "b"]));
+        for _ in 0..3 {
+            counter.process_list(&list(&["b", "c", "d"]));
+        }
+        counter.process_list(&list(&["b", "e"]));
+        let seeds = list(&["a"]);
+        let direct = counter.cached_metrics_for_identifier("a");
+        let settings = TwoHopSettings { decay: 0.5, fan_out: 10, max_candidates: 2 };
+
+        let second_hop = counter.second_hop(&seeds, &direct, &settings, None).unwrap();
+        // b was in 5 lists, 3 of them with c and d
+        assert_eq!(second_hop, [("c".to_string(), 0.3), ("d".to_string(), 0.3)]);
+        assert!(counter.second_hop(&seeds, &direct, &TwoHopSettings { fan_out: 0, ..settings }, None).unwrap().is_empty());
+        assert_eq!(counter.second_hop(&seeds, &direct, &settings, Some(Instant::now())), None);
+    }
+
+    #[test]
+    fn test_first_seen_survives_a_restart() {
+        let directory = std::env::temp_dir();
+        let snapshot_path = directory.join(format!("co_occurrence_lifecycle_{}.json", std::process::id()));
+        let wal_path = directory.join(format!("co_occurrence_lifecycle_{}.log", std::process::id()));
+        let settings = crate::config::Settings::from_env().storage;
+        let recover = || {
+            let mut counter = CoOccurrenceCounter::new();
+            counter.recover_from(&snapshot_path, &wal_path, &settings);
+            counter
+        };
+
+        let mut counter = recover();
+        assert_eq!(counter.lifecycle_of(ID1_STR), None);
+        counter.process_list(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        let id1 = counter.get_identifier_to_id_map()[ID1_STR];
+        counter.first_seen[id1 as usize] = 500;
+        counter.last_seen[id1 as usize] = 1000;
+        counter.compact();
+        counter.process_list(&[ID2_STR.to_string(), ID3_STR.to_string()]);
+        counter.persist();
+        let (first_seen, last_seen) = counter.lifecycle_of(ID3_STR).unwrap();
+        assert!(first_seen.is_some_and(|first_seen| first_seen <= last_seen));
+
+        let recovered = recover();
+        assert_eq!(recovered.lifecycle_of(ID1_STR), Some((Some(500), 1000)));
+        assert_eq!(recovered.lifecycle_of(ID3_STR), counter.lifecycle_of(ID3_STR));
+        let backup_path = directory.join(format!("co_occurrence_lifecycle_{}.json.bak", std::process::id()));
+        let _ = (std::fs::remove_file(&snapshot_path), std::fs::remove_file(&backup_path), std::fs::remove_file(&wal_path));
+    }
+}
@@ -0,0 +1,146 @@
+// src/algorithms/consistent_snapshot.rs
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use tracing::info;
+
+use crate::algorithms::co_occurrence::Snapshot;
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::{determinism, locks};
+
+// Snapshots of the co-occurrences and the counters taken at the same moment, so a backup
+// or a pair of snapshot files never holds lists without their plays or the other way
+// round. Both are locked in the order every caller taking both uses, the co-occurrences
+// first: a read lock holds off the lists, a write lock on the counters the increments,
+// which only take a read lock. Both are copied under the locks and encoded after
+// releasing them. The pair shares an ID, which orders the snapshots.
+
+/// The last snapshot ID handed out.
+static LAST_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Held by `persist` from copying until the files are written.
+static PERSISTING: Mutex<()> = Mutex::new(());
+
+/// Returns a new snapshot ID: the milliseconds since the Unix epoch, or one more than the
+/// previous ID if that isn't smaller.
+pub fn next_id() -> u64 {
+    let now = determinism::now().timestamp_millis().max(0) as u64;
+    let next = |last: u64| now.max(last + 1);
+    match LAST_ID.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| Some(next(last))) {
+        Ok(last) | Err(last) => next(last),
+    }
+}
+
+/// Copies of the co-occurrences and the counters at the same moment.
+pub struct SnapshotPair {
+    pub id: u64,
+    pub co_occurrences: Snapshot,
+    pub counters: Counters,
+}
+
+/// Copies both under a new snapshot ID. Writes are only held off while copying, not while
+/// the copies are encoded. Fails if the co-occurrences are kept in a database.
+pub fn capture(co_occurrence: &RwLock<CoOccurrenceCounter>, counters: &RwLock<Counters>) -> Result<SnapshotPair, String> {
+    let co_occurrence_lock = locks::read(co_occurrence, "co_occurrence");
+    let counters_lock = locks::write(counters, "rotating_counters");
+    let id = next_id();
+    let mut pair = SnapshotPair { id, co_occurrences: co_occurrence_lock.to_snapshot()?, counters: counters_lock.detached_copy() };
+    drop(counters_lock);
+    drop(co_occurrence_lock);
+    pair.co_occurrences.set_snapshot_id(id);
+    pair.counters.snapshot_id = id;
+    Ok(pair)
+}
+
+/// Persists both under a new snapshot ID. Like `capture`, writes are only held off while
+/// copying what changed, not while it is written. The co-occurrences are only included
+/// with `with_co_occurrences`, i.e. unless a database records every list. Either is only
+/// written if it changed; the other keeps its previous ID, its state being the same at
+/// both. Persists run one at a time, so their files are written in the order of their
+/// IDs. Returns the ID, `None` if nothing changed.
+pub fn persist(co_occurrence: &RwLock<CoOccurrenceCounter>, counters: &RwLock<Counters>, with_co_occurrences: bool) -> Option<u64> {
+    let _persisting = locks::lock(&PERSISTING, "persisting");
+    let mut co_occurrence_lock = locks::write(co_occurrence, "co_occurrence");
+    let mut counters_lock = locks::write(counters, "rotating_counters");
+    let co_occurrence_changed = with_co_occurrences && co_occurrence_lock.is_dirty();
+    if !co_occurrence_changed && !counters_lock.is_dirty() {
+        return None;
+    }
+    let id = next_id();
+    let pending_co_occurrences = co_occurrence_changed.then(|| co_occurrence_lock.prepare_snapshot(id)).flatten();
+    let pending_counters = counters_lock.prepare_snapshot(id);
+    drop(counters_lock);
+    drop(co_occurrence_lock);
+
+    if let Some(pending) = pending_co_occurrences {
+        let result = pending.write();
+        locks::write(co_occurrence, "co_occurrence").finish_snapshot(pending, result);
+    }
+    if let Some(pending) = pending_counters {
+        let result = pending.write();
+        locks::write(counters, "rotating_counters").finish_snapshot(pending, result);
+    }
+    info!(snapshot_id = id, "Snapshot persisted.");
+    Some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::rotating_counters::count_of;
+
+    #[test]
+    fn test_captured_pair_shares_an_increasing_id() {
+        let co_occurrence = RwLock::new(CoOccurrenceCounter::new());
+        let counters = RwLock::new(Counters::with_depths(3, 3, 1, 1));
+        locks::write(&co_occurrence, "co_occurrence").process_list(&["a".to_string(), "b".to_string()]);
+        locks::read(&counters, "rotating_counters").increment("a", 3);
+
+        let first = capture(&co_occurrence, &counters).unwrap();
+        assert_eq!((first.co_occurrences.snapshot_id(), first.counters.snapshot_id), (first.id, first.id));
+        assert_eq!(first.co_occurrences.pair_count(), 1);
+        assert_eq!(count_of(&first.counters.window("today").unwrap(), "a"), 3);
+        assert_eq!(first.counters.namespace_plays("today").unwrap().0, 3);
+
+        // Later changes don't reach the copies
+        locks::read(&counters, "rotating_counters").increment("a", 1);
+        assert_eq!(count_of(&first.counters.window("today").unwrap(), "a"), 3);
+        let second = capture(&co_occurrence, &counters).unwrap();
+        assert!(second.id > first.id);
+        assert_eq!(count_of(&second.counters.window("today").unwrap(), "a"), 4);
+        assert!(next_id() > second.id);
+    }
+
+    #[test]
+    fn test_persist_writes_the_copies_and_catches_up_on_writes_meanwhile() {
+        let mut settings = crate::config::Settings::from_env().storage;
+        settings.data_dir = std::env::temp_dir().join(format!("mediathek_consistent_snapshot_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&settings.data_dir);
+        std::fs::create_dir_all(&settings.data_dir).unwrap();
+        let recover = || {
+            let mut counter = CoOccurrenceCounter::new();
+            counter.recover(&settings);
+            RwLock::new(counter)
+        };
+        let list = |identifiers: [&str; 2]| identifiers.map(str::to_string);
+        let co_occurrence = recover();
+        let counters = RwLock::new(Counters::with_depths(3, 3, 1, 1));
+
+        locks::write(&co_occurrence, "co_occurrence").process_list(&list(["a", "b"]));
+        let id = persist(&co_occurrence, &counters, true).unwrap();
+        assert_eq!((locks::read(&co_occurrence, "co_occurrence").snapshot_id(), locks::read(&counters, "rotating_counters").snapshot_id), (id, 0));
+        assert!(!locks::read(&co_occurrence, "co_occurrence").is_dirty());
+        assert_eq!(persist(&co_occurrence, &counters, true), None);
+
+        // A snapshot written between the copy and its write isn't lost
+        let mut counter = locks::write(&co_occurrence, "co_occurrence");
+        counter.process_list(&list(["a", "c"]));
+        let pending = counter.prepare_snapshot(next_id()).unwrap();
+        counter.process_list(&list(["b", "c"]));
+        counter.compact();
+        let result = pending.write();
+        counter.finish_snapshot(pending, result);
+        drop(counter);
+        assert_eq!(locks::read(&recover(), "co_occurrence").pair_count(), 3);
+        let _ = std::fs::remove_dir_all(&settings.data_dir);
+    }
+}
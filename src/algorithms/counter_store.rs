@@ -0,0 +1,227 @@
+// src/algorithms/counter_store.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use redis::{Commands, Connection, Script};
+use tracing::{error, info};
+
+use crate::algorithms::rotating_counters::{Bucket, Granularity};
+use crate::config::{CounterBackend, CounterSettings};
+use crate::locks;
+
+/// Timeout for connecting to Redis and for every command, so a stalled server can't
+/// block increments indefinitely.
+const REDIS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Shifts the bucket hashes of one granularity, unless another instance already did so
+/// for the same period. KEYS are the buckets (current first) followed by the period
+/// marker; ARGV are the steps and the new period.
+const ROTATE_SCRIPT: &str = r#"
+local marker = KEYS[#KEYS]
+if redis.call('GET', marker) == ARGV[2] then
+    return 0
+end
+local depth = #KEYS - 1
+local steps = tonumber(ARGV[1])
+for i = depth, 1, -1 do
+    if i + steps > depth then
+        redis.call('DEL', KEYS[i])
+    elseif redis.call('EXISTS', KEYS[i]) == 1 then
+        redis.call('RENAME', KEYS[i], KEYS[i + steps])
+    else
+        redis.call('DEL', KEYS[i + steps])
+    end
+end
+redis.call('SET', marker, ARGV[2])
+return 1
+"#;
+
+/// Storage the rotating counters mirror their changes to: a shared one, so that several
+/// instances count together, or a durable one replacing the snapshots. Without one, the
+/// counts only live in memory.
+///
+/// The store is the source of truth for the buckets: `Counters` applies changes locally
+/// right away and loads the buckets from the store on startup (and for shared stores
+/// periodically, which also picks up the changes of other instances).
+pub trait CounterStore: Send + Sync + fmt::Debug {
+    /// Adds `amount` to `id` in the current bucket of every granularity.
+    fn increment(&self, id: &str, amount: u64) -> Result<(), String>;
+
+    /// Adds all counts of `bucket` to the bucket at `index` of a granularity.
+    fn add(&self, granularity: Granularity, index: usize, bucket: &Bucket) -> Result<(), String>;
+
+    /// Shifts the buckets of one granularity by `steps` positions, so the current bucket
+    /// belongs to `period`. Returns `false` if they already were, e.g. by another instance.
+    fn rotate(&self, granularity: Granularity, steps: usize, period: &str) -> Result<bool, String>;
+
+    /// Removes `id` from every bucket.
+    fn remove(&self, id: &str) -> Result<(), String>;
+
+    /// Clears all buckets.
+    fn reset(&self) -> Result<(), String>;
+
+    /// Reads the buckets of every granularity (in the order of `Granularity::ALL`),
+    /// current bucket first.
+    fn load(&self) -> Result<Vec<Vec<Bucket>>, String>;
+
+    /// Whether the store keeps every change durably, so neither the event log nor the
+    /// buckets in the snapshots are needed.
+    fn is_durable(&self) -> bool {
+        false
+    }
+}
+
+/// Returns the store configured for the counters: Redis if they are shared, otherwise
+/// the database, if there is one.
+pub fn open_counter_store(settings: &CounterSettings, database: Option<Arc<dyn CounterStore>>) -> Option<Arc<dyn CounterStore>> {
+    match settings.backend {
+        CounterBackend::Redis => match RedisStore::new(settings) {
+            Ok(store) => {
+                info!("Sharing rotating counters through Redis at {}.", settings.redis_url);
+                Some(Arc::new(store))
+            }
+            Err(e) => {
+                error!("Invalid Redis URL {}, keeping the counters in memory: {}", settings.redis_url, e);
+                None
+            }
+        },
+        CounterBackend::Memory | CounterBackend::Gossip => database,
+    }
+}
+
+/// Keeps the counters in Redis: one hash per bucket, named
+/// `<prefix>:<series>:<index>` (e.g. "mediathek:counters:daily:0" for today), and one
+/// `<prefix>:<series>:period` key per granularity recording the period of its current
+/// bucket. Increments are atomic `HINCRBY`s; rotations rename the hashes in a script.
+pub struct RedisStore {
+    client: redis::Client,
+    prefix: String,
+    /// Number of buckets per granularity, in the order of `Granularity::ALL`
+    depths: [usize; 4],
+    rotate_script: Script,
+    /// Opened on first use, and again after an error
+    connection: Mutex<Option<Connection>>,
+}
+
+impl fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStore").field("prefix", &self.prefix).field("depths", &self.depths).finish()
+    }
+}
+
+impl RedisStore {
+    /// Creates a store for the configured server and bucket depths. Doesn't connect yet.
+    pub fn new(settings: &CounterSettings) -> Result<Self, String> {
+        let client = redis::Client::open(settings.redis_url.as_str()).map_err(|e| e.to_string())?;
+        Ok(RedisStore {
+            client,
+            prefix: settings.redis_key_prefix.clone(),
+            depths: [settings.hourly_buckets, settings.daily_buckets, settings.weekly_buckets, settings.monthly_buckets]
+                .map(|depth| depth.max(1)),
+            rotate_script: Script::new(ROTATE_SCRIPT),
+            connection: Mutex::new(None),
+        })
+    }
+
+    fn depth(&self, granularity: Granularity) -> usize {
+        self.depths[Granularity::ALL.iter().position(|&g| g == granularity).unwrap_or_default()]
+    }
+
+    fn key(&self, granularity: Granularity, index: usize) -> String {
+        format!("{}:{}:{}", self.prefix, granularity.series_name(), index)
+    }
+
+    /// Keys of all buckets of one granularity, current first.
+    fn keys(&self, granularity: Granularity) -> Vec<String> {
+        (0..self.depth(granularity)).map(|index| self.key(granularity, index)).collect()
+    }
+
+    fn all_keys(&self) -> Vec<String> {
+        Granularity::ALL.into_iter().flat_map(|granularity| self.keys(granularity)).collect()
+    }
+
+    /// Runs `f` on the connection, connecting first if needed. The connection is dropped
+    /// after an error, so the next call starts over with a fresh one.
+    fn with_connection<T>(&self, f: impl FnOnce(&mut Connection) -> redis::RedisResult<T>) -> Result<T, String> {
+        let mut connection = locks::lock(&self.connection, "redis_connection");
+        if connection.is_none() {
+            let new_connection = self.client.get_connection_with_timeout(REDIS_TIMEOUT).map_err(|e| e.to_string())?;
+            new_connection.set_read_timeout(Some(REDIS_TIMEOUT)).map_err(|e| e.to_string())?;
+            new_connection.set_write_timeout(Some(REDIS_TIMEOUT)).map_err(|e| e.to_string())?;
+            *connection = Some(new_connection);
+        }
+        let result = connection.as_mut().map(f).expect("connection was just opened");
+        if result.is_err() {
+            *connection = None;
+        }
+        result.map_err(|e| e.to_string())
+    }
+}
+
+/// Redis, SQLite and PostgreSQL integers are signed, larger counts are capped.
+pub fn signed_count(amount: u64) -> i64 {
+    amount.min(i64::MAX as u64) as i64
+}
+
+impl CounterStore for RedisStore {
+    fn increment(&self, id: &str, amount: u64) -> Result<(), String> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for granularity in Granularity::ALL {
+            pipe.hincr(self.key(granularity, 0), id, signed_count(amount)).ignore();
+        }
+        self.with_connection(|connection| pipe.query(connection))
+    }
+
+    fn add(&self, granularity: Granularity, index: usize, bucket: &Bucket) -> Result<(), String> {
+        if index >= self.depth(granularity) || bucket.is_empty() {
+            return Ok(());
+        }
+        let key = self.key(granularity, index);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for entry in bucket.iter() {
+            pipe.hincr(&key, entry.key(), signed_count(*entry.value())).ignore();
+        }
+        self.with_connection(|connection| pipe.query(connection))
+    }
+
+    fn rotate(&self, granularity: Granularity, steps: usize, period: &str) -> Result<bool, String> {
+        let mut invocation = self.rotate_script.prepare_invoke();
+        for key in self.keys(granularity) {
+            invocation.key(key);
+        }
+        invocation.key(format!("{}:{}:period", self.prefix, granularity.series_name()));
+        invocation.arg(steps).arg(period);
+        self.with_connection(|connection| invocation.invoke::<i64>(connection)).map(|rotated| rotated == 1)
+    }
+
+    fn remove(&self, id: &str) -> Result<(), String> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for key in self.all_keys() {
+            pipe.hdel(key, id).ignore();
+        }
+        self.with_connection(|connection| pipe.query(connection))
+    }
+
+    fn reset(&self) -> Result<(), String> {
+        let keys = self.all_keys();
+        self.with_connection(|connection| connection.del(keys))
+    }
+
+    fn load(&self) -> Result<Vec<Vec<Bucket>>, String> {
+        let mut pipe = redis::pipe();
+        for key in self.all_keys() {
+            pipe.hgetall(key);
+        }
+        let mut hashes = self.with_connection(|connection| pipe.query::<Vec<HashMap<String, u64>>>(connection))?.into_iter();
+        Ok(Granularity::ALL
+            .into_iter()
+            .map(|granularity| {
+                hashes.by_ref().take(self.depth(granularity)).map(|hash| hash.into_iter().collect()).collect()
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,179 @@
+// src/algorithms/digest.rs
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::algorithms::rotating_counters::{top_entries, CountEntry, Counters};
+use crate::algorithms::trending::{rising_stars, trending, RisingStar, TrendingBasis, TrendingItem};
+use crate::config::DigestSettings;
+use crate::determinism;
+use crate::locks;
+
+/// How often the counters are checked for a daily rotation.
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Maximum age of the rising stars in a digest.
+const RISING_STARS_MAX_AGE_HOURS: u32 = 24;
+/// Minimum current count of trending items and rising stars in a digest, filters out noise.
+const MIN_COUNT: u64 = 3;
+
+/// Why a digest was sent.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestTrigger {
+    Schedule,
+    Rotation,
+}
+
+/// The top items of one counter window.
+#[derive(Debug, Serialize)]
+pub struct WindowDigest {
+    pub window: String,
+    pub items: Vec<CountEntry>,
+}
+
+/// Body of a digest webhook.
+#[derive(Debug, Serialize)]
+pub struct Digest {
+    pub generated_at: DateTime<Utc>,
+    pub trigger: DigestTrigger,
+    /// Top items of every configured window; unknown windows are left out
+    pub windows: Vec<WindowDigest>,
+    /// Items growing fastest today compared to the trailing week
+    pub trending: Vec<TrendingItem>,
+    /// Items first seen within the last day, by velocity
+    pub rising_stars: Vec<RisingStar>,
+}
+
+/// Summarizes the counters as configured in `settings`.
+pub fn build_digest(counters: &Counters, now: DateTime<Utc>, trigger: DigestTrigger, settings: &DigestSettings) -> Digest {
+    let windows = settings
+        .windows
+        .iter()
+        .filter_map(|window| {
+            let bucket = counters.window(window)?;
+            Some(WindowDigest { window: window.clone(), items: top_entries(&bucket, 0, settings.limit) })
+        })
+        .collect();
+    Digest {
+        generated_at: now,
+        trigger,
+        windows,
+        trending: trending(counters, TrendingBasis::Day, MIN_COUNT, settings.limit),
+        rising_stars: rising_stars(counters, now, RISING_STARS_MAX_AGE_HOURS, MIN_COUNT, settings.limit),
+    }
+}
+
+/// Returns how long to wait before retry number `retry` (0-based): the base backoff,
+/// doubled for every retry before.
+fn retry_delay(backoff_secs: u64, retry: u32) -> Duration {
+    Duration::from_secs(backoff_secs.saturating_mul(1 << retry.min(16)))
+}
+
+/// POSTs `body` to `url`, retrying with exponential backoff on network errors, 5xx and
+/// 429 responses. Other responses are not retried, as sending the same body again
+/// won't help.
+async fn deliver(client: awc::Client, url: String, body: serde_json::Value, settings: DigestSettings) {
+    for attempt in 0..=settings.max_retries {
+        if attempt > 0 {
+            tokio::time::sleep(retry_delay(settings.retry_backoff_secs, attempt - 1)).await;
+        }
+        match client.post(&url).send_json(&body).await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) if response.status().is_server_error() || response.status().as_u16() == 429 => {
+                warn!(url, attempt, "Digest webhook responded with status {}.", response.status());
+            }
+            Ok(response) => {
+                error!(url, "Digest webhook responded with status {}, giving up.", response.status());
+                return;
+            }
+            Err(e) => warn!(url, attempt, "Failed to send digest webhook: {}", e),
+        }
+    }
+    error!(url, "Giving up on digest webhook after {} retries.", settings.max_retries);
+}
+
+// Function to send the trending digests to the configured webhooks.
+// Must be spawned on the actix runtime, since the webhook client is not `Send`.
+pub async fn run_digest_webhooks(counters: Arc<RwLock<Counters>>, settings: DigestSettings, timezone: Tz) {
+    info!("Digest webhooks started for {} URLs.", settings.webhook_urls.len());
+    let client = awc::Client::default();
+    let interval = (settings.interval_secs > 0).then(|| Duration::from_secs(settings.interval_secs));
+    let mut last_scheduled_at = Instant::now();
+    let mut last_rotation_at = locks::read(&counters, "rotating_counters").last_rotation_at;
+
+    loop {
+        tokio::time::sleep(ROTATION_CHECK_INTERVAL).await;
+
+        let now = determinism::now();
+        let digest = {
+            let counters_lock = locks::read(&counters, "rotating_counters");
+            // Only a new day counts, not every hourly rotation
+            let rotation_at = counters_lock.last_rotation_at;
+            let day = |at: DateTime<Utc>| at.with_timezone(&timezone).date_naive();
+            let rotated = matches!((last_rotation_at, rotation_at), (Some(last), Some(current)) if day(last) != day(current));
+            last_rotation_at = rotation_at;
+
+            let trigger = if settings.after_rotation && rotated {
+                Some(DigestTrigger::Rotation)
+            } else if interval.is_some_and(|interval| last_scheduled_at.elapsed() >= interval) {
+                Some(DigestTrigger::Schedule)
+            } else {
+                None
+            };
+            trigger.map(|trigger| build_digest(&counters_lock, now, trigger, &settings))
+        };
+        let Some(digest) = digest else {
+            continue;
+        };
+        if digest.trigger == DigestTrigger::Schedule {
+            last_scheduled_at = Instant::now();
+        }
+
+        let body = match serde_json::to_value(&digest) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to serialize digest: {}", e);
+                continue;
+            }
+        };
+        info!(trigger = ?digest.trigger, "Sending trending digest.");
+        // Every URL on its own, so a slow or failing one doesn't hold up the others
+        for url in &settings.webhook_urls {
+            actix_web::rt::spawn(deliver(client.clone(), url.clone(), body.clone(), settings.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_lists_top_items_per_window() {
+        let settings = DigestSettings {
+            webhook_urls: Vec::new(),
+            interval_secs: 0,
+            after_rotation: true,
+            windows: vec!["today".to_string(), "no_such_window".to_string()],
+            limit: 2,
+            max_retries: 5,
+            retry_backoff_secs: 2,
+        };
+        let counters = Counters::with_depths(3, 13, 4, 3);
+        counters.increment("a", 5);
+        counters.increment("b", 9);
+        counters.increment("c", 1);
+
+        let digest = build_digest(&counters, Utc::now(), DigestTrigger::Schedule, &settings);
+        assert_eq!(digest.windows.len(), 1);
+        let top: Vec<_> = digest.windows[0].items.iter().map(|entry| (entry.id.as_str(), entry.count)).collect();
+        assert_eq!(top, [("b", 9), ("a", 5)]);
+        assert_eq!(digest.trending.len(), 2);
+
+        assert_eq!(retry_delay(2, 0), Duration::from_secs(2));
+        assert_eq!(retry_delay(2, 3), Duration::from_secs(16));
+    }
+}
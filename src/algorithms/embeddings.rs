@@ -0,0 +1,266 @@
+// src/algorithms/embeddings.rs
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use ahash::RandomState;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use utoipa::ToSchema;
+use tracing::{error, info};
+
+use crate::algorithms::recent_lists::RecentLists;
+use crate::config::EmbeddingSettings;
+use crate::{determinism, locks};
+
+/// Size of the table used to draw negative samples from the unigram distribution.
+const NEGATIVE_TABLE_SIZE: usize = 1_000_000;
+
+/// A neighbor in embedding space, as returned by `ItemEmbeddings::most_similar`.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct SimilarItem {
+    pub identifier: String,
+    /// Cosine similarity in [-1, 1]
+    pub similarity: f32,
+}
+
+/// Trained item vectors (item2vec), L2-normalized so that a dot product equals the
+/// cosine similarity.
+#[derive(Debug, Default)]
+pub struct ItemEmbeddings {
+    vectors: HashMap<String, Vec<f32>, RandomState>,
+    /// When the vectors were trained, `None` before the first training run completed.
+    pub trained_at: Option<DateTime<Utc>>,
+}
+
+impl ItemEmbeddings {
+    /// Returns the `limit` nearest neighbors of `identifier` by cosine similarity,
+    /// or `None` if no vector has been trained for it.
+    pub fn most_similar(&self, identifier: &str, limit: usize) -> Option<Vec<SimilarItem>> {
+        let target = self.vectors.get(identifier)?;
+
+        let mut similar: Vec<SimilarItem> = self
+            .vectors
+            .iter()
+            .filter(|(other, _)| other.as_str() != identifier)
+            .map(|(other, vector)| SimilarItem {
+                identifier: other.clone(),
+                similarity: dot(target, vector),
+            })
+            .collect();
+
+        similar.sort_by(|a, b| b.similarity.total_cmp(&a.similarity).then_with(|| a.identifier.cmp(&b.identifier)));
+        similar.truncate(limit);
+        Some(similar)
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Trains item vectors from `lists` with skip-gram and negative sampling.
+///
+/// Every identifier within `window` positions of another one in the same list is
+/// treated as its context. Identifiers occurring fewer than `min_count` times in all
+/// lists together are skipped, however many lists they were in.
+pub fn train_embeddings(lists: &[Vec<String>], settings: &EmbeddingSettings, seed: u64) -> ItemEmbeddings {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // Build the vocabulary
+    let mut frequencies: HashMap<&str, u32, RandomState> = HashMap::with_hasher(determinism::random_state());
+    for list in lists {
+        for identifier in list {
+            *frequencies.entry(identifier.as_str()).or_insert(0) += 1;
+        }
+    }
+    let mut vocabulary: Vec<(&str, u32)> = frequencies
+        .into_iter()
+        .filter(|&(_, count)| count >= settings.min_count)
+        .collect();
+    // Sorting keeps training deterministic for a given seed
+    vocabulary.sort_unstable();
+    if vocabulary.len() < 2 {
+        return ItemEmbeddings::default();
+    }
+    let word_to_index: HashMap<&str, usize, RandomState> = vocabulary
+        .iter()
+        .enumerate()
+        .map(|(index, &(word, _))| (word, index))
+        .collect();
+
+    // Negative samples are drawn proportionally to frequency^0.75
+    let weights: Vec<f64> = vocabulary.iter().map(|&(_, count)| (count as f64).powf(0.75)).collect();
+    let total_weight: f64 = weights.iter().sum();
+    let mut negative_table = Vec::with_capacity(NEGATIVE_TABLE_SIZE);
+    for (index, weight) in weights.iter().enumerate() {
+        let slots = ((weight / total_weight) * NEGATIVE_TABLE_SIZE as f64).ceil() as usize;
+        negative_table.extend(std::iter::repeat_n(index, slots));
+    }
+
+    let dimensions = settings.dimensions;
+    let mut input: Vec<f32> = (0..vocabulary.len() * dimensions)
+        .map(|_| (rng.random::<f32>() - 0.5) / dimensions as f32)
+        .collect();
+    let mut output: Vec<f32> = vec![0.0; vocabulary.len() * dimensions];
+
+    let sentences: Vec<Vec<usize>> = lists
+        .iter()
+        .map(|list| list.iter().filter_map(|s| word_to_index.get(s.as_str()).copied()).collect())
+        .collect();
+
+    let mut gradient = vec![0.0f32; dimensions];
+    for epoch in 0..settings.epochs {
+        // Linearly decay the learning rate over the epochs
+        let learning_rate = settings.learning_rate * (1.0 - epoch as f32 / settings.epochs as f32).max(0.0001);
+
+        for sentence in &sentences {
+            for (position, &center) in sentence.iter().enumerate() {
+                let start = position.saturating_sub(settings.window);
+                let end = (position + settings.window + 1).min(sentence.len());
+                for (context_position, &context) in sentence.iter().enumerate().take(end).skip(start) {
+                    if context_position == position || context == center {
+                        continue;
+                    }
+
+                    gradient.iter_mut().for_each(|g| *g = 0.0);
+                    let center_vector = center * dimensions..(center + 1) * dimensions;
+
+                    // One positive target plus `negative_samples` random ones
+                    for sample in 0..=settings.negative_samples {
+                        let (target, label) = if sample == 0 {
+                            (context, 1.0)
+                        } else {
+                            let target = negative_table[rng.random_range(0..negative_table.len())];
+                            if target == context {
+                                continue;
+                            }
+                            (target, 0.0)
+                        };
+                        let target_vector = target * dimensions..(target + 1) * dimensions;
+
+                        let score = sigmoid(dot(&input[center_vector.clone()], &output[target_vector.clone()]));
+                        let step = (label - score) * learning_rate;
+                        for d in 0..dimensions {
+                            gradient[d] += step * output[target_vector.start + d];
+                            output[target_vector.start + d] += step * input[center_vector.start + d];
+                        }
+                    }
+
+                    for d in 0..dimensions {
+                        input[center_vector.start + d] += gradient[d];
+                    }
+                }
+            }
+        }
+    }
+
+    let vectors = vocabulary
+        .iter()
+        .enumerate()
+        .map(|(index, &(word, _))| {
+            let mut vector = input[index * dimensions..(index + 1) * dimensions].to_vec();
+            let norm = dot(&vector, &vector).sqrt();
+            if norm > 0.0 {
+                vector.iter_mut().for_each(|v| *v /= norm);
+            }
+            (word.to_string(), vector)
+        })
+        .collect();
+
+    ItemEmbeddings {
+        vectors,
+        trained_at: Some(determinism::now()),
+    }
+}
+
+// Function to periodically retrain the item embeddings from the recent lists buffer
+pub async fn run_embedding_training(
+    recent_lists: Arc<Mutex<RecentLists>>,
+    embeddings: Arc<Mutex<ItemEmbeddings>>,
+    settings: EmbeddingSettings,
+) {
+    info!("Embedding training thread started.");
+
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(settings.training_interval_secs)).await;
+
+        let recent_lists = recent_lists.clone();
+        let embeddings = embeddings.clone();
+        let settings = settings.clone();
+
+        // Training is CPU-bound and can take a while, so it runs on the blocking thread pool
+        // and only holds the embeddings lock to swap in the finished result.
+        let result = web::block(move || {
+            let lists = locks::lock(&recent_lists, "recent_lists").snapshot();
+            let trained = train_embeddings(&lists, &settings, determinism::now().timestamp() as u64);
+            let vector_count = trained.vectors.len();
+            *locks::lock(&embeddings, "embeddings") = trained;
+            (lists.len(), vector_count)
+        })
+        .await;
+
+        match result {
+            Ok((list_count, vector_count)) => {
+                info!("Trained {} item embeddings from {} lists.", vector_count, list_count);
+            }
+            Err(e) => {
+                error!("Error in embedding training block: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> EmbeddingSettings {
+        EmbeddingSettings {
+            dimensions: 16,
+            window: 5,
+            negative_samples: 3,
+            epochs: 50,
+            learning_rate: 0.05,
+            min_count: 1,
+            training_interval_secs: 3600,
+        }
+    }
+
+    fn list(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_items_from_the_same_cluster_are_closer() {
+        let mut lists = Vec::new();
+        for _ in 0..50 {
+            lists.push(list(&["news:a", "news:b", "news:c"]));
+            lists.push(list(&["kids:x", "kids:y", "kids:z"]));
+        }
+        let embeddings = train_embeddings(&lists, &settings(), 42);
+
+        let similar = embeddings.most_similar("news:a", 5).unwrap();
+        assert_eq!(similar.len(), 5);
+        assert!(similar[0].identifier.starts_with("news:"));
+        assert!(similar[1].identifier.starts_with("news:"));
+        assert!(similar[0].similarity > similar[4].similarity);
+    }
+
+    #[test]
+    fn test_unknown_and_rare_identifiers() {
+        let mut settings = settings();
+        settings.min_count = 2;
+        let lists = vec![list(&["a", "b"]), list(&["a", "b"]), list(&["a", "rare"])];
+        let embeddings = train_embeddings(&lists, &settings, 42);
+
+        assert!(embeddings.most_similar("rare", 5).is_none());
+        assert!(embeddings.most_similar("unknown", 5).is_none());
+        assert_eq!(embeddings.most_similar("a", 5).unwrap().len(), 1);
+    }
+}
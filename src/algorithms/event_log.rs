@@ -0,0 +1,149 @@
+// src/algorithms/event_log.rs
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// A change to the rotating counters, as recorded in the event log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum CounterEvent {
+    Increment { id: String, count: u64 },
+    Remove { id: String },
+    Reset,
+}
+
+/// A processed list, as recorded in the write-ahead log of the co-occurrences.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ListEvent {
+    List {
+        identifiers: Vec<String>,
+        /// When each identifier was seen, in seconds since the Unix epoch, if sent along
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        times: Option<Vec<i64>>,
+        /// Times the pairs are counted, see `SourceWeights`; left out if 1
+        #[serde(default = "unit_weight", skip_serializing_if = "is_unit_weight")]
+        weight: u64,
+    },
+}
+
+/// The weight of lists logged without one.
+pub(crate) fn unit_weight() -> u64 {
+    1
+}
+
+pub(crate) fn is_unit_weight(weight: &u64) -> bool {
+    *weight == 1
+}
+
+/// One line of an event log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LogEntry<E = CounterEvent> {
+    /// Strictly increasing, so entries already contained in a snapshot can be skipped
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: E,
+}
+
+/// Append-only log of changes since the last snapshot, written before they are applied.
+///
+/// Every entry is written to the file right away, so a crashed process loses nothing;
+/// `fsync` happens every `sync_batch` entries, bounding what a power loss can take.
+#[derive(Debug)]
+pub struct EventLog {
+    file: File,
+    sync_batch: usize,
+    unsynced: usize,
+    sequence: u64,
+}
+
+impl EventLog {
+    /// Opens (or creates) the log at `path`. New entries are numbered after `sequence`.
+    pub fn open(path: impl AsRef<Path>, sync_batch: usize, sequence: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(EventLog {
+            file,
+            sync_batch: sync_batch.max(1),
+            unsynced: 0,
+            sequence,
+        })
+    }
+
+    /// Appends an event and returns its sequence number.
+    pub fn append<E: Serialize>(&mut self, at: DateTime<Utc>, event: E) -> io::Result<u64> {
+        let entry = LogEntry { seq: self.sequence + 1, at, event };
+        let mut line = serde_json::to_vec(&entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.sequence = entry.seq;
+
+        self.unsynced += 1;
+        if self.unsynced >= self.sync_batch {
+            self.sync()?;
+        }
+        Ok(entry.seq)
+    }
+
+    /// Forces all appended entries to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Drops all entries, once they are contained in a snapshot.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.sync()
+    }
+}
+
+/// Reads all entries of the log at `path`, oldest first. A missing file yields no
+/// entries; lines that can't be parsed (e.g. a line cut off by a crash) are skipped.
+pub fn read_entries<E: DeserializeOwned>(path: impl AsRef<Path>) -> Vec<LogEntry<E>> {
+    let Ok(data) = fs::read_to_string(path.as_ref()) else {
+        return Vec::new();
+    };
+    data.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Skipping unreadable line of {}: {}", path.as_ref().display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_read_and_truncate() {
+        let path = std::env::temp_dir().join(format!("mediathek_event_log_{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let now = Utc::now();
+
+        let mut log = EventLog::open(&path, 2, 10).unwrap();
+        assert_eq!(log.append(now, CounterEvent::Increment { id: "a".to_string(), count: 3 }).unwrap(), 11);
+        assert_eq!(log.append(now, CounterEvent::Remove { id: "a".to_string() }).unwrap(), 12);
+        // A torn last line is ignored
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"seq\":13,").unwrap();
+
+        let entries: Vec<LogEntry> = read_entries(&path);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].seq, 11);
+        assert_eq!(entries[1].event, CounterEvent::Remove { id: "a".to_string() });
+
+        log.truncate().unwrap();
+        assert!(read_entries::<CounterEvent>(&path).is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
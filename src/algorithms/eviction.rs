@@ -0,0 +1,39 @@
+// src/algorithms/eviction.rs
+use std::sync::RwLock;
+use chrono::{DateTime, Utc};
+
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::locks;
+
+/// Removes the identifiers not seen since `cutoff` from the co-occurrences and the
+/// counters. Returns the number of co-occurrence identifiers, pairs and counter
+/// identifiers removed.
+pub fn evict_unseen(
+    co_occurrence: &RwLock<CoOccurrenceCounter>,
+    counters: &RwLock<Counters>,
+    cutoff: DateTime<Utc>,
+) -> Result<(usize, usize, usize), String> {
+    let (identifiers, pairs) = locks::write(co_occurrence, "co_occurrence").evict_unseen(cutoff.timestamp())?;
+    let counter_identifiers = locks::write(counters, "rotating_counters").evict_unseen(cutoff);
+    Ok((identifiers, pairs, counter_identifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_identifiers_not_seen_recently_are_evicted() {
+        let co_occurrence = RwLock::new(CoOccurrenceCounter::new());
+        let counters = RwLock::new(Counters::with_depths(3, 3, 1, 1));
+        locks::write(&co_occurrence, "co_occurrence").process_list(&["a".to_string(), "b".to_string()]);
+        locks::read(&counters, "rotating_counters").increment("a", 1);
+        locks::read(&counters, "rotating_counters").increment("b", 1);
+        let now = Utc::now();
+        locks::write(&counters, "rotating_counters").last_seen.insert("a".to_string(), now - chrono::Duration::days(10));
+
+        assert_eq!(evict_unseen(&co_occurrence, &counters, now - chrono::Duration::days(7)), Ok((0, 0, 1)));
+        assert!(!locks::read(&counters, "rotating_counters").first_seen.contains_key("a"));
+        assert_eq!(evict_unseen(&co_occurrence, &counters, now + chrono::Duration::minutes(1)), Ok((2, 1, 1)));
+    }
+}
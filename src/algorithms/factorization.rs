@@ -0,0 +1,343 @@
+// src/algorithms/factorization.rs
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use ahash::RandomState;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+use crate::algorithms::recent_lists::RecentLists;
+use crate::config::FactorizationSettings;
+use crate::{determinism, locks};
+
+/// A trained implicit-feedback matrix factorization model.
+///
+/// Every ingested list is treated as one "user" that interacted with the contained
+/// items. Only the item factors are kept; user vectors for a basket are computed on
+/// demand ("fold-in").
+#[derive(Debug)]
+pub struct FactorizationModel {
+    /// Increases by one with every successful training run
+    pub version: u64,
+    pub trained_at: DateTime<Utc>,
+    item_index: HashMap<String, usize, RandomState>,
+    items: Vec<String>,
+    /// Row-major `items.len() x factors` matrix
+    item_factors: Vec<f64>,
+    /// Precomputed Y^T Y, needed for the fold-in of new users
+    gram: Vec<f64>,
+    factors: usize,
+    regularization: f64,
+    alpha: f64,
+}
+
+impl FactorizationModel {
+    fn item_vector(&self, index: usize) -> &[f64] {
+        &self.item_factors[index * self.factors..(index + 1) * self.factors]
+    }
+
+    /// Scores all items for a basket by folding the basket in as a new user.
+    /// Basket items and unknown identifiers are skipped.
+    pub fn recommend_for_basket(&self, basket: &[String], limit: usize) -> Vec<(String, f64)> {
+        let indices: Vec<usize> = basket.iter().filter_map(|id| self.item_index.get(id).copied()).collect();
+        if indices.is_empty() {
+            return Vec::new();
+        }
+        let user = solve_row(&self.gram, &self.item_factors, &indices, self.factors, self.regularization, self.alpha);
+        let excluded: HashSet<usize> = indices.into_iter().collect();
+
+        let mut scored: Vec<(String, f64)> = (0..self.items.len())
+            .filter(|index| !excluded.contains(index))
+            .map(|index| (self.items[index].clone(), dot(&user, self.item_vector(index))))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(limit);
+        scored
+    }
+
+    /// Returns the items whose factors are most similar (cosine) to `identifier`'s.
+    pub fn similar_items(&self, identifier: &str, limit: usize) -> Vec<(String, f64)> {
+        let Some(&target) = self.item_index.get(identifier) else {
+            return Vec::new();
+        };
+        let target_vector = self.item_vector(target);
+        let target_norm = dot(target_vector, target_vector).sqrt();
+
+        let mut scored: Vec<(String, f64)> = (0..self.items.len())
+            .filter(|&index| index != target)
+            .map(|index| {
+                let vector = self.item_vector(index);
+                let norm = target_norm * dot(vector, vector).sqrt();
+                let similarity = if norm > 0.0 { dot(target_vector, vector) / norm } else { 0.0 };
+                (self.items[index].clone(), similarity)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(limit);
+        scored
+    }
+}
+
+/// Holds the currently served model and the trigger for on-demand training.
+#[derive(Debug, Default)]
+pub struct FactorizationState {
+    /// The model used for inference, `None` until the first training run completed
+    pub current: Option<Arc<FactorizationModel>>,
+    /// Wakes the training task before its regular interval elapses
+    pub train_trigger: Arc<Notify>,
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Computes `M^T M` for a row-major `rows x k` matrix.
+fn gram_matrix(matrix: &[f64], k: usize) -> Vec<f64> {
+    let mut gram = vec![0.0; k * k];
+    for row in matrix.chunks_exact(k) {
+        for a in 0..k {
+            for b in 0..k {
+                gram[a * k + b] += row[a] * row[b];
+            }
+        }
+    }
+    gram
+}
+
+/// Solves `A x = b` for a symmetric positive definite `k x k` matrix via Cholesky
+/// decomposition. `a` is overwritten.
+fn cholesky_solve(a: &mut [f64], b: &[f64], k: usize) -> Vec<f64> {
+    for j in 0..k {
+        let mut diagonal = a[j * k + j];
+        for p in 0..j {
+            diagonal -= a[j * k + p] * a[j * k + p];
+        }
+        let diagonal = diagonal.max(1e-12).sqrt();
+        a[j * k + j] = diagonal;
+        for i in (j + 1)..k {
+            let mut value = a[i * k + j];
+            for p in 0..j {
+                value -= a[i * k + p] * a[j * k + p];
+            }
+            a[i * k + j] = value / diagonal;
+        }
+    }
+
+    // Forward substitution (L y = b), then backward substitution (L^T x = y)
+    let mut y = vec![0.0; k];
+    for i in 0..k {
+        let mut value = b[i];
+        for p in 0..i {
+            value -= a[i * k + p] * y[p];
+        }
+        y[i] = value / a[i * k + i];
+    }
+    let mut x = vec![0.0; k];
+    for i in (0..k).rev() {
+        let mut value = y[i];
+        for p in (i + 1)..k {
+            value -= a[p * k + i] * x[p];
+        }
+        x[i] = value / a[i * k + i];
+    }
+    x
+}
+
+/// Computes the least-squares factors of one row (user or item) given the fixed
+/// factors of the other side, following Hu, Koren & Volinsky (2008): every observed
+/// interaction has preference 1 and confidence `1 + alpha`, everything else has
+/// preference 0 and confidence 1.
+fn solve_row(gram: &[f64], fixed: &[f64], observed: &[usize], k: usize, regularization: f64, alpha: f64) -> Vec<f64> {
+    let mut a = gram.to_vec();
+    let mut b = vec![0.0; k];
+    for d in 0..k {
+        a[d * k + d] += regularization;
+    }
+    for &index in observed {
+        let vector = &fixed[index * k..(index + 1) * k];
+        for p in 0..k {
+            b[p] += (1.0 + alpha) * vector[p];
+            for q in 0..k {
+                a[p * k + q] += alpha * vector[p] * vector[q];
+            }
+        }
+    }
+    cholesky_solve(&mut a, &b, k)
+}
+
+/// Trains a factorization model with alternating least squares over `lists`.
+/// Returns `None` if there is nothing to train on.
+pub fn train_factorization(
+    lists: &[Vec<String>],
+    settings: &FactorizationSettings,
+    version: u64,
+    seed: u64,
+) -> Option<FactorizationModel> {
+    let k = settings.factors;
+    let mut item_index: HashMap<String, usize, RandomState> = HashMap::with_hasher(determinism::random_state());
+    let mut items: Vec<String> = Vec::new();
+
+    let users: Vec<Vec<usize>> = lists
+        .iter()
+        .map(|list| {
+            let mut indices: Vec<usize> = list
+                .iter()
+                .map(|id| {
+                    *item_index.entry(id.clone()).or_insert_with(|| {
+                        items.push(id.clone());
+                        items.len() - 1
+                    })
+                })
+                .collect();
+            indices.sort_unstable();
+            indices.dedup();
+            indices
+        })
+        .filter(|indices| !indices.is_empty())
+        .collect();
+    if users.is_empty() || k == 0 {
+        return None;
+    }
+
+    let mut item_users: Vec<Vec<usize>> = vec![Vec::new(); items.len()];
+    for (user, indices) in users.iter().enumerate() {
+        for &index in indices {
+            item_users[index].push(user);
+        }
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut user_factors: Vec<f64> = vec![0.0; users.len() * k];
+    let mut item_factors: Vec<f64> = (0..items.len() * k).map(|_| rng.random::<f64>() * 0.01).collect();
+
+    for _ in 0..settings.iterations {
+        let item_gram = gram_matrix(&item_factors, k);
+        for (user, observed) in users.iter().enumerate() {
+            let row = solve_row(&item_gram, &item_factors, observed, k, settings.regularization, settings.alpha);
+            user_factors[user * k..(user + 1) * k].copy_from_slice(&row);
+        }
+
+        let user_gram = gram_matrix(&user_factors, k);
+        for (item, observed) in item_users.iter().enumerate() {
+            let row = solve_row(&user_gram, &user_factors, observed, k, settings.regularization, settings.alpha);
+            item_factors[item * k..(item + 1) * k].copy_from_slice(&row);
+        }
+    }
+
+    let gram = gram_matrix(&item_factors, k);
+    Some(FactorizationModel {
+        version,
+        trained_at: determinism::now(),
+        item_index,
+        items,
+        item_factors,
+        gram,
+        factors: k,
+        regularization: settings.regularization,
+        alpha: settings.alpha,
+    })
+}
+
+// Function to retrain the factorization model periodically or when triggered
+pub async fn run_factorization_training(
+    recent_lists: Arc<Mutex<RecentLists>>,
+    state: Arc<Mutex<FactorizationState>>,
+    settings: FactorizationSettings,
+) {
+    info!("Factorization training thread started.");
+    let train_trigger = locks::lock(&state, "factorization").train_trigger.clone();
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(tokio::time::Duration::from_secs(settings.training_interval_secs)) => {}
+            _ = train_trigger.notified() => {
+                info!("Factorization training triggered manually.");
+            }
+        }
+
+        let recent_lists = recent_lists.clone();
+        let state = state.clone();
+        let settings = settings.clone();
+
+        // ALS is CPU-bound, so it runs on the blocking thread pool. The previous model
+        // keeps serving until the new one is swapped in.
+        let result = web::block(move || {
+            let lists = locks::lock(&recent_lists, "recent_lists").snapshot();
+            let version = locks::lock(&state, "factorization").current.as_ref().map_or(1, |model| model.version + 1);
+            let model = train_factorization(&lists, &settings, version, determinism::now().timestamp() as u64)?;
+            locks::lock(&state, "factorization").current = Some(Arc::new(model));
+            Some((version, lists.len()))
+        })
+        .await;
+
+        match result {
+            Ok(Some((version, list_count))) => {
+                info!("Trained factorization model version {} from {} lists.", version, list_count);
+            }
+            Ok(None) => {
+                info!("Skipped factorization training: no interaction data yet.");
+            }
+            Err(e) => {
+                error!("Error in factorization training block: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> FactorizationSettings {
+        FactorizationSettings {
+            enabled: true,
+            factors: 4,
+            iterations: 10,
+            regularization: 0.1,
+            alpha: 10.0,
+            training_interval_secs: 3600,
+        }
+    }
+
+    fn list(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_cholesky_solve() {
+        // [[4, 2], [2, 3]] x = [2, 1] => x = [0.5, 0]
+        let mut a = vec![4.0, 2.0, 2.0, 3.0];
+        let x = cholesky_solve(&mut a, &[2.0, 1.0], 2);
+        assert!((x[0] - 0.5).abs() < 1e-9);
+        assert!(x[1].abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_basket_recommendation_prefers_related_items() {
+        let mut lists = Vec::new();
+        for _ in 0..20 {
+            lists.push(list(&["news:a", "news:b", "news:c"]));
+            lists.push(list(&["kids:x", "kids:y", "kids:z"]));
+        }
+        let model = train_factorization(&lists, &settings(), 1, 42).unwrap();
+        assert_eq!(model.version, 1);
+
+        let recommendations = model.recommend_for_basket(&list(&["news:a", "news:b"]), 2);
+        assert_eq!(recommendations[0].0, "news:c");
+
+        let similar = model.similar_items("kids:x", 2);
+        assert!(similar.iter().all(|(id, _)| id.starts_with("kids:")));
+    }
+
+    #[test]
+    fn test_empty_and_unknown_input() {
+        assert!(train_factorization(&[], &settings(), 1, 42).is_none());
+
+        let model = train_factorization(&[list(&["a", "b"])], &settings(), 1, 42).unwrap();
+        assert!(model.recommend_for_basket(&list(&["unknown"]), 10).is_empty());
+        assert!(model.similar_items("unknown", 10).is_empty());
+    }
+}
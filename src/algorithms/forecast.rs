@@ -0,0 +1,111 @@
+// src/algorithms/forecast.rs
+use chrono::{Datelike, NaiveDate, TimeZone};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::algorithms::rotating_counters::{count_of, Counters};
+
+/// Smoothing of the level: how much a new day outweighs the days before.
+const LEVEL_SMOOTHING: f64 = 0.5;
+/// Smoothing of the trend, lower than the level's so a single outlier doesn't turn it.
+const TREND_SMOOTHING: f64 = 0.3;
+/// Completed days every weekday needs in the weekday profile before its pattern is used.
+const MIN_DAYS_PER_WEEKDAY: u32 = 2;
+
+/// The expected count of an identifier on one day.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct ForecastPoint {
+    pub date: NaiveDate,
+    pub expected: f64,
+}
+
+/// The expected counts of the next days, see `forecast`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, ToSchema)]
+pub struct Forecast {
+    /// Whether the weekday pattern was applied, which needs a few weeks of history
+    pub seasonal: bool,
+    /// Today first, as today's bucket isn't complete yet
+    pub days: Vec<ForecastPoint>,
+}
+
+/// Factors by which each weekday (Monday first) deviates from the average day, from the
+/// long-running weekday profile. `None` while the profile is too short or empty for `id`.
+fn weekday_factors(counters: &Counters, id: &str) -> Option<[f64; 7]> {
+    let averages = counters.weekdays.averages(id);
+    if averages.iter().any(|average| average.days < MIN_DAYS_PER_WEEKDAY) {
+        return None;
+    }
+    let mean = averages.iter().map(|average| average.average).sum::<f64>() / 7.0;
+    if mean <= 0.0 {
+        return None;
+    }
+    let mut factors = [1.0; 7];
+    for (factor, average) in factors.iter_mut().zip(&averages) {
+        // A weekday without any plays would zero every forecast for it
+        *factor = (average.average / mean).max(0.1);
+    }
+    Some(factors)
+}
+
+/// Predicts the counts of `id` for `days` days from today with Holt's linear trend method
+/// over the completed daily buckets, adjusted by the weekday profile once it covers a few
+/// weeks. Days before the identifier's first count don't pull the level down. `timezone`
+/// is the rotation time zone, in which the days are dated.
+pub fn forecast<Tz: TimeZone>(counters: &Counters, id: &str, timezone: &Tz, days: usize) -> Forecast {
+    let Some(rotated_at) = counters.last_rotation_at else {
+        return Forecast::default();
+    };
+    let today = rotated_at.with_timezone(timezone).date_naive();
+    let factors = weekday_factors(counters, id);
+    let factor = |date: NaiveDate| factors.map_or(1.0, |factors| factors[date.weekday().num_days_from_monday() as usize]);
+
+    // Oldest first, ending with yesterday
+    let completed = counters.daily.len().saturating_sub(1);
+    let history: Vec<(NaiveDate, u64)> = (1..=completed)
+        .rev()
+        .map(|age| (today - chrono::Duration::days(age as i64), count_of(&counters.daily[age], id)))
+        .skip_while(|&(_, count)| count == 0)
+        .collect();
+
+    let values: Vec<f64> = history.iter().map(|&(date, count)| count as f64 / factor(date)).collect();
+    let mut level = values.first().copied().unwrap_or(0.0);
+    // Started at the first step, so a steady series is followed from the beginning
+    let mut trend = values.get(1).map_or(0.0, |second| second - level);
+    for &value in values.iter().skip(1) {
+        let previous = level;
+        level = LEVEL_SMOOTHING * value + (1.0 - LEVEL_SMOOTHING) * (previous + trend);
+        trend = TREND_SMOOTHING * (level - previous) + (1.0 - TREND_SMOOTHING) * trend;
+    }
+
+    let days = (0..days)
+        .map(|ahead| {
+            let date = today + chrono::Duration::days(ahead as i64);
+            let expected = ((level + trend * (ahead + 1) as f64) * factor(date)).max(0.0);
+            ForecastPoint { date, expected }
+        })
+        .collect();
+    Forecast { seasonal: factors.is_some(), days }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_forecast_follows_the_trend() {
+        let mut counters = Counters::with_depths(3, 6, 4, 3);
+        counters.advance_to(&Utc.with_ymd_and_hms(2025, 3, 3, 12, 0, 0).unwrap());
+        // Growing by 10 a day over the last four days, unseen before
+        for (age, count) in [(4, 10), (3, 20), (2, 30), (1, 40)] {
+            counters.daily[age].insert("a".to_string(), count);
+        }
+
+        let predicted = forecast(&counters, "a", &Utc, 3);
+        assert!(!predicted.seasonal);
+        assert_eq!(predicted.days.iter().map(|point| point.date.to_string()).collect::<Vec<_>>(), ["2025-03-03", "2025-03-04", "2025-03-05"]);
+        let expected: Vec<f64> = predicted.days.iter().map(|point| point.expected.round()).collect();
+        assert_eq!(expected, [50.0, 60.0, 70.0]);
+        assert!(forecast(&counters, "unknown", &Utc, 1).days[0].expected == 0.0);
+    }
+}
@@ -0,0 +1,250 @@
+// src/algorithms/gossip.rs
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use dashmap::DashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::{error, info, warn};
+
+use crate::algorithms::rotating_counters::Granularity;
+use crate::algorithms::Counters;
+use crate::config::CounterSettings;
+use crate::locks;
+
+// Counter synchronization between peers without shared storage (the "gossip" counter
+// backend). Every instance records how much each node contributed to each bucket, keyed
+// by the bucket's period (see `Granularity::period`) rather than its index, so instances
+// rotating a moment apart still agree. A node's contribution to a bucket only ever grows,
+// which makes the ledger a grow-only counter per node: merging takes the maximum of
+// every entry, in any order and as often as needed, and adds the growth to the bucket.
+// Every round, each instance pushes its whole ledger of the current buckets to all peers
+// (POST /admin/gossip), so counts of a peer that was down show up once it is back.
+// Removals and resets only apply locally.
+
+/// Path of the gossip endpoint on the peers.
+pub const GOSSIP_PATH: &str = "/v1/admin/gossip";
+
+/// One node's contribution to an identifier's count in one bucket.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GossipEntry {
+    pub node: String,
+    /// Granularity of the bucket, e.g. "hourly"
+    pub series: String,
+    /// Period of the bucket, e.g. "2026-10-15" for a day
+    pub period: String,
+    pub id: String,
+    pub count: u64,
+}
+
+/// A gossip round's message: all entries the sender knows of.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GossipMessage {
+    /// The sending node
+    pub node: String,
+    pub entries: Vec<GossipEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct SlotKey {
+    node: String,
+    granularity: Granularity,
+    period: String,
+    id: String,
+}
+
+/// The contributions of every node to the current buckets, persisted with the counters.
+/// Only records anything once enabled.
+#[derive(Clone, Debug, Default)]
+pub struct GossipLedger {
+    counts: DashMap<SlotKey, u64>,
+    /// This node's name and the time zone the periods are taken in, once enabled
+    node: Option<(String, Tz)>,
+}
+
+impl Serialize for GossipLedger {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.entries())
+    }
+}
+
+impl<'de> Deserialize<'de> for GossipLedger {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let ledger = GossipLedger::default();
+        for entry in Vec::<GossipEntry>::deserialize(deserializer)? {
+            if let Some(key) = slot_key(&entry) {
+                ledger.counts.insert(key, entry.count);
+            }
+        }
+        Ok(ledger)
+    }
+}
+
+fn slot_key(entry: &GossipEntry) -> Option<SlotKey> {
+    let granularity = Granularity::ALL.into_iter().find(|granularity| granularity.series_name() == entry.series)?;
+    Some(SlotKey { node: entry.node.clone(), granularity, period: entry.period.clone(), id: entry.id.clone() })
+}
+
+impl GossipLedger {
+    /// Starts recording the increments of this instance as those of `node`, in the
+    /// periods of `timezone`.
+    pub fn enable(&mut self, node: String, timezone: Tz) {
+        self.node = Some((node, timezone));
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.node.is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// The time zone of the periods, if enabled.
+    pub fn timezone(&self) -> Option<Tz> {
+        self.node.as_ref().map(|&(_, timezone)| timezone)
+    }
+
+    /// Adds an increment of this instance to the current buckets, whose periods are those
+    /// of `rotated_at` (see `Counters::last_rotation_at`).
+    pub fn record(&self, id: &str, amount: u64, rotated_at: DateTime<Utc>) {
+        let Some((node, timezone)) = &self.node else {
+            return;
+        };
+        let at = rotated_at.with_timezone(timezone);
+        for granularity in Granularity::ALL {
+            let key = SlotKey { node: node.clone(), granularity, period: granularity.period(&at), id: id.to_string() };
+            let mut count = self.counts.entry(key).or_insert(0);
+            *count = count.saturating_add(amount);
+        }
+    }
+
+    /// Raises an entry to `count` if it is lower. Returns by how much it grew.
+    fn raise(&self, key: SlotKey, count: u64) -> Option<u64> {
+        let mut known = self.counts.entry(key).or_insert(0);
+        let growth = count.checked_sub(*known).filter(|&growth| growth > 0)?;
+        *known = count;
+        Some(growth)
+    }
+
+    /// Drops the entries of buckets that were rotated out, keeping those whose period is
+    /// in `current`.
+    pub fn retain_periods(&self, current: &HashMap<(Granularity, String), usize>) {
+        self.counts.retain(|key, _| current.contains_key(&(key.granularity, key.period.clone())));
+    }
+
+    /// Returns all entries, e.g. for the next gossip round.
+    pub fn entries(&self) -> Vec<GossipEntry> {
+        self.counts
+            .iter()
+            .map(|entry| {
+                let key = entry.key();
+                GossipEntry {
+                    node: key.node.clone(),
+                    series: key.granularity.series_name().to_string(),
+                    period: key.period.clone(),
+                    id: key.id.clone(),
+                    count: *entry.value(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Merges the entries of a peer into `counters`: every entry grown since it was last seen
+/// adds its growth to the bucket of its period. Entries of periods this instance doesn't
+/// keep, or hasn't rotated to yet, are skipped, and taken again from a later round.
+/// Returns the number of entries that grew.
+pub fn merge(counters: &mut Counters, entries: &[GossipEntry]) -> usize {
+    let Some(periods) = counters.gossip_periods() else {
+        return 0;
+    };
+    let mut grown = 0;
+    for entry in entries {
+        let Some(key) = slot_key(entry) else {
+            continue;
+        };
+        let Some(&index) = periods.get(&(key.granularity, key.period.clone())) else {
+            continue;
+        };
+        let granularity = key.granularity;
+        if let Some(growth) = counters.gossip.raise(key, entry.count) {
+            counters.add_to_bucket(granularity, index, &entry.id, growth);
+            grown += 1;
+        }
+    }
+    grown
+}
+
+// Function to push this instance's gossip ledger to every peer periodically.
+// Must be spawned on the actix runtime, since the HTTP client is not `Send`.
+pub async fn run_counter_gossip(counters: Arc<RwLock<Counters>>, settings: CounterSettings, token: Option<String>) {
+    info!(peers = ?settings.gossip_peers, node = settings.gossip_node_id, "Counter gossip started.");
+    let client = awc::Client::default();
+    let mut interval = tokio::time::interval(Duration::from_secs(settings.sync_interval_secs));
+
+    loop {
+        interval.tick().await;
+        let counters = Arc::clone(&counters);
+        let entries = match web::block(move || locks::read(&counters, "rotating_counters").gossip.entries()).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Error in counter gossip block: {:?}", e);
+                continue;
+            }
+        };
+        let message = GossipMessage { node: settings.gossip_node_id.clone(), entries };
+        for peer in &settings.gossip_peers {
+            let mut request = client.post(format!("{}{}", peer.trim_end_matches('/'), GOSSIP_PATH));
+            if let Some(token) = &token {
+                request = request.bearer_auth(token);
+            }
+            match request.send_json(&message).await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => warn!("Gossip peer {} responded with status {}.", peer, response.status()),
+                Err(e) => warn!("Failed to gossip with {}: {}", peer, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::algorithms::rotating_counters::count_of;
+
+    fn node(name: &str, at: DateTime<Utc>) -> Counters {
+        let mut counters = Counters::with_depths(3, 3, 1, 1);
+        counters.gossip.enable(name.to_string(), Tz::UTC);
+        counters.advance_to(&at);
+        counters
+    }
+
+    #[test]
+    fn test_peers_converge_whatever_the_order_of_rounds() {
+        let at = Utc.with_ymd_and_hms(2026, 10, 15, 12, 30, 0).unwrap();
+        let (mut a, mut b) = (node("a", at), node("b", at));
+        a.increment("x", 2);
+        b.increment("x", 3);
+        b.increment("y", 1);
+
+        // Rounds may repeat, the counts only grow by what is new
+        let from_b = b.gossip.entries();
+        assert_eq!(merge(&mut a, &from_b), 8);
+        assert_eq!(merge(&mut a, &from_b), 0);
+        merge(&mut b, &a.gossip.entries());
+        for counters in [&a, &b] {
+            assert_eq!(count_of(counters.bucket("today").unwrap(), "x"), 5);
+            assert_eq!(count_of(counters.bucket("this_hour").unwrap(), "y"), 1);
+        }
+
+        // Periods a node has rotated out are skipped
+        let mut late = node("c", at + chrono::Duration::days(5));
+        assert_eq!(merge(&mut late, &from_b), 2);
+        assert_eq!(count_of(late.bucket("this_month").unwrap(), "x"), 3);
+        assert_eq!(count_of(late.bucket("today").unwrap(), "x"), 0);
+    }
+}
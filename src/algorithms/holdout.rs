@@ -0,0 +1,198 @@
+// src/algorithms/holdout.rs
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use actix_web::web;
+use ahash::RandomState;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::algorithms::CoOccurrenceCounter;
+use crate::config::EvaluationSettings;
+use crate::{determinism, locks};
+
+/// Lists held out of the co-occurrences, to measure how well their items are predicted
+/// from each other by the rest. A list is drawn by its hash, so a list submitted again
+/// is held out again. Lists of a single identifier have nothing to predict from and are
+/// never held out.
+#[derive(Debug)]
+pub struct Holdout {
+    /// Share of the lists held out
+    fraction: f64,
+    capacity: usize,
+    /// The most recent held-out lists, oldest first
+    lists: Mutex<VecDeque<Vec<String>>>,
+    hasher: RandomState,
+    report: Mutex<Option<EvaluationReport>>,
+}
+
+/// Accuracy of the co-occurrence recommendations on the held-out lists: for every item
+/// of a list, whether it is among the top `k` neighbors of one of the others.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EvaluationReport {
+    pub evaluated_at: DateTime<Utc>,
+    pub k: usize,
+    /// Number of held-out lists evaluated
+    pub lists: usize,
+    /// Number of held-out items, i.e. predictions checked
+    pub items: usize,
+    /// Share of the lists with at least one item predicted
+    pub hit_rate: f64,
+    /// Share of the items predicted
+    pub recall: f64,
+}
+
+impl Holdout {
+    pub fn new(settings: &EvaluationSettings) -> Self {
+        Holdout {
+            fraction: settings.holdout_fraction,
+            capacity: settings.holdout_capacity,
+            lists: Mutex::new(VecDeque::new()),
+            hasher: determinism::random_state(),
+            report: Mutex::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.fraction > 0.0 && self.capacity > 0
+    }
+
+    /// Whether `identifiers` is a list drawn to be held out.
+    pub fn draws<S: AsRef<str>>(&self, identifiers: &[S]) -> bool {
+        if !self.is_enabled() || identifiers.len() < 2 {
+            return false;
+        }
+        let hash = self.hasher.hash_one(identifiers.iter().map(AsRef::as_ref).collect::<Vec<&str>>());
+        (hash as f64) < self.fraction * u64::MAX as f64
+    }
+
+    /// Keeps `identifiers` for the evaluation if the list is drawn. Returns `true` if
+    /// so, in which case it must not be counted.
+    pub fn hold_out<S: AsRef<str>>(&self, identifiers: &[S]) -> bool {
+        if !self.draws(identifiers) {
+            return false;
+        }
+        let mut lists = locks::lock(&self.lists, "holdout");
+        if lists.len() == self.capacity {
+            lists.pop_front();
+        }
+        lists.push_back(identifiers.iter().map(|identifier| identifier.as_ref().to_string()).collect());
+        true
+    }
+
+    /// The latest report, `None` before the first evaluation.
+    pub fn report(&self) -> Option<EvaluationReport> {
+        locks::lock(&self.report, "holdout_report").clone()
+    }
+
+    /// Evaluates the held-out lists against the current co-occurrences, and keeps the
+    /// result as the latest report.
+    pub fn evaluate(&self, co_occurrence: &RwLock<CoOccurrenceCounter>, k: usize) -> Result<EvaluationReport, String> {
+        // Copied, so lists are held out meanwhile
+        let lists: Vec<Vec<String>> = locks::lock(&self.lists, "holdout").iter().cloned().collect();
+        let identifiers: Vec<&str> = lists.iter().flatten().map(String::as_str).collect::<HashSet<&str>>().into_iter().collect();
+        let neighbors = locks::read(co_occurrence, "co_occurrence").strongest_neighbors(&identifiers, k)?;
+        let report = evaluate(&lists, &neighbors, k, determinism::now());
+        *locks::lock(&self.report, "holdout_report") = Some(report.clone());
+        Ok(report)
+    }
+}
+
+/// Checks for every item of every list whether it is among the top `k` of `neighbors` of
+/// another item of the list.
+fn evaluate(lists: &[Vec<String>], neighbors: &HashMap<String, Vec<String>>, k: usize, now: DateTime<Utc>) -> EvaluationReport {
+    let (mut lists_hit, mut items, mut items_hit) = (0, 0, 0);
+    for list in lists {
+        let hits = list
+            .iter()
+            .enumerate()
+            .filter(|&(i, target)| {
+                list.iter().enumerate().any(|(j, partner)| {
+                    i != j && neighbors.get(partner).is_some_and(|neighbors| neighbors.iter().take(k).any(|neighbor| neighbor == target))
+                })
+            })
+            .count();
+        items += list.len();
+        items_hit += hits;
+        lists_hit += usize::from(hits > 0);
+    }
+    let share = |hits: usize, total: usize| if total == 0 { 0.0 } else { hits as f64 / total as f64 };
+    EvaluationReport {
+        evaluated_at: now,
+        k,
+        lists: lists.len(),
+        items,
+        hit_rate: share(lists_hit, lists.len()),
+        recall: share(items_hit, items),
+    }
+}
+
+// Function to periodically evaluate the recommendations on the held-out lists
+pub async fn run_holdout_evaluation(
+    co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    holdout: Arc<Holdout>,
+    settings: EvaluationSettings,
+) {
+    info!("Holdout evaluation thread started.");
+
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(settings.interval_secs)).await;
+
+        let co_occurrence = co_occurrence.clone();
+        let holdout = holdout.clone();
+        let k = settings.k;
+
+        // Reads all pairs, so it runs on the blocking thread pool
+        let result = web::block(move || holdout.evaluate(&co_occurrence, k)).await;
+
+        match result {
+            Ok(Ok(report)) => {
+                info!(lists = report.lists, hit_rate = report.hit_rate, recall = report.recall, "Evaluated the recommendations on the held-out lists.");
+            }
+            Ok(Err(e)) => {
+                // Won't change until a restart
+                warn!("Stopped evaluating the held-out lists: {}", e);
+                return;
+            }
+            Err(e) => {
+                error!("Error in holdout evaluation block: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(holdout_fraction: f64) -> EvaluationSettings {
+        EvaluationSettings { holdout_fraction, holdout_capacity: 2, k: 1, interval_secs: 3600 }
+    }
+
+    #[test]
+    fn test_held_out_items_are_looked_up_among_their_partners_neighbors() {
+        let holdout = Holdout::new(&settings(1.0));
+        assert!(!holdout.hold_out(&["ard:a"]));
+        assert!(holdout.hold_out(&["ard:x", "ard:y"]));
+        assert!(holdout.hold_out(&["ard:a", "ard:b", "ard:c"]));
+        assert!(holdout.hold_out(&["ard:d", "ard:e"]));
+        assert!(!Holdout::new(&settings(0.0)).hold_out(&["ard:a", "ard:b"]));
+
+        let co_occurrence = RwLock::new(CoOccurrenceCounter::new());
+        {
+            let mut counter = locks::write(&co_occurrence, "co_occurrence");
+            counter.process_list(&["ard:a", "ard:b"]);
+            counter.process_list(&["ard:a", "ard:b"]);
+            counter.process_list(&["ard:a", "ard:c"]);
+            counter.process_list(&["ard:d", "ard:f"]);
+        }
+        let report = holdout.evaluate(&co_occurrence, 1).unwrap();
+        // Only the two most recent lists are kept
+        assert_eq!((report.lists, report.items), (2, 5));
+        // "ard:b" is the top neighbor of "ard:a", and "ard:a" that of "ard:b" and "ard:c"
+        assert_eq!(report.recall, 2.0 / 5.0);
+        assert_eq!(report.hit_rate, 0.5);
+        assert!(holdout.report().is_some());
+    }
+}
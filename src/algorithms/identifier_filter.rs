@@ -0,0 +1,96 @@
+// src/algorithms/identifier_filter.rs
+use std::sync::atomic::{AtomicU64, Ordering};
+use ahash::RandomState;
+
+use crate::config::IdentifierFilterSettings;
+use crate::determinism;
+
+/// Bloom filter of the identifiers the co-occurrences know, checked before taking their
+/// lock so lookups of identifiers never seen are answered right away. Lock-free: bits
+/// are only ever set, so readers see an identifier once its insertion finished, and
+/// never miss one that was known before.
+///
+/// Removed identifiers stay in the filter, which only costs them the regular lookup.
+#[derive(Debug)]
+pub struct IdentifierFilter {
+    /// Empty if disabled, so everything may be known
+    bits: Box<[AtomicU64]>,
+    hashes: u32,
+    hasher: RandomState,
+}
+
+impl Default for IdentifierFilter {
+    fn default() -> Self {
+        IdentifierFilter::disabled()
+    }
+}
+
+impl IdentifierFilter {
+    /// A filter letting every identifier through.
+    pub fn disabled() -> Self {
+        IdentifierFilter { bits: Box::new([]), hashes: 0, hasher: determinism::random_state() }
+    }
+
+    /// Returns a filter sized for `settings.capacity` identifiers at the configured false
+    /// positive rate; disabled for a capacity of 0.
+    pub fn new(settings: &IdentifierFilterSettings) -> Self {
+        if settings.capacity == 0 {
+            return IdentifierFilter::disabled();
+        }
+        let rate = settings.false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(settings.capacity as f64) * rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((bits as f64 / settings.capacity as f64) * ln2).round().clamp(1.0, 16.0) as u32;
+        IdentifierFilter {
+            bits: (0..bits.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            hashes,
+            hasher: determinism::random_state(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.bits.is_empty()
+    }
+
+    /// The positions of `identifier`'s bits, by double hashing.
+    fn positions(&self, identifier: &str) -> impl Iterator<Item = usize> + '_ {
+        let hash = self.hasher.hash_one(identifier);
+        let (h1, h2) = (hash as u32 as u64, (hash >> 32) | 1);
+        let len = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    pub fn insert(&self, identifier: &str) {
+        for position in self.positions(identifier) {
+            self.bits[position / 64].fetch_or(1 << (position % 64), Ordering::Release);
+        }
+    }
+
+    /// Whether `identifier` may be known; `false` only if it certainly isn't.
+    pub fn may_contain(&self, identifier: &str) -> bool {
+        self.positions(identifier).all(|position| self.bits[position / 64].load(Ordering::Acquire) & (1 << (position % 64)) != 0)
+    }
+
+    /// Bytes used by the bits.
+    pub fn bytes(&self) -> usize {
+        self.bits.len() * size_of::<AtomicU64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_identifiers_are_never_filtered() {
+        let filter = IdentifierFilter::new(&IdentifierFilterSettings { capacity: 1000, false_positive_rate: 0.01 });
+        (0..1000).for_each(|i| filter.insert(&format!("ard:{}", i)));
+        assert!((0..1000).all(|i| filter.may_contain(&format!("ard:{}", i))));
+        let false_positives = (0..10_000).filter(|i| filter.may_contain(&format!("zdf:{}", i))).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        let disabled = IdentifierFilter::new(&IdentifierFilterSettings { capacity: 0, false_positive_rate: 0.01 });
+        assert!(!disabled.is_enabled());
+        assert!(disabled.may_contain("ard:1"));
+    }
+}
@@ -0,0 +1,189 @@
+// src/algorithms/interner.rs
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use ahash::RandomState;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{determinism, memory};
+
+/// Two-way mapping between identifier strings and their integer IDs, holding each string
+/// in a single allocation shared by both directions. Reverse lookups are indexed by ID,
+/// so resolving the IDs of a result borrows the strings instead of copying a whole map.
+///
+/// Serialized as a map from identifier to ID, like the `HashMap` it replaced.
+#[derive(Clone)]
+pub struct Interner {
+    ids: HashMap<Arc<str>, u32, RandomState>,
+    /// Per ID, its identifier; `None` for IDs unused since a removal
+    identifiers: Vec<Option<Arc<str>>>,
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner { ids: HashMap::with_hasher(determinism::random_state()), identifiers: Vec::new() }
+    }
+}
+
+impl fmt::Debug for Interner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for Interner {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for Interner {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Ordered, so the IDs are interned in the same order every time
+        let map = BTreeMap::<String, u32>::deserialize(deserializer)?;
+        let mut interner = Interner::default();
+        interner.ids.reserve(map.len());
+        for (identifier, id) in map {
+            interner.insert(&identifier, id);
+        }
+        Ok(interner)
+    }
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    /// Returns the ID of `identifier`, if interned.
+    pub fn get(&self, identifier: &str) -> Option<u32> {
+        self.ids.get(identifier).copied()
+    }
+
+    /// Returns the identifier with the ID `id`, if any.
+    pub fn resolve(&self, id: u32) -> Option<&str> {
+        self.identifiers.get(id as usize)?.as_deref()
+    }
+
+    pub fn contains(&self, identifier: &str) -> bool {
+        self.ids.contains_key(identifier)
+    }
+
+    /// Returns the ID of `identifier`, interning it with the next ID after all used ones
+    /// if it is new.
+    pub fn intern(&mut self, identifier: &str) -> u32 {
+        if let Some(id) = self.get(identifier) {
+            return id;
+        }
+        let id = self.identifiers.len() as u32;
+        self.insert(identifier, id);
+        id
+    }
+
+    /// Interns `identifier` with the given ID, e.g. one assigned by a store. Replaces
+    /// whatever was interned with either before.
+    pub fn insert(&mut self, identifier: &str, id: u32) {
+        if let Some(previous) = self.ids.get(identifier).copied() {
+            self.identifiers[previous as usize] = None;
+        }
+        if let Some(Some(previous)) = self.identifiers.get(id as usize) {
+            self.ids.remove(previous);
+        }
+        let identifier: Arc<str> = Arc::from(identifier);
+        if self.identifiers.len() <= id as usize {
+            self.identifiers.resize(id as usize + 1, None);
+        }
+        self.identifiers[id as usize] = Some(Arc::clone(&identifier));
+        self.ids.insert(identifier, id);
+    }
+
+    /// Number of interned identifiers.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Returns every identifier with its ID, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> + '_ {
+        self.ids.iter().map(|(identifier, &id)| (&**identifier, id))
+    }
+
+    /// Returns every ID in use, in no particular order.
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.ids.values().copied()
+    }
+
+    /// Keeps only the identifiers `keep` returns `true` for.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, u32) -> bool) {
+        let identifiers = &mut self.identifiers;
+        self.ids.retain(|identifier, &mut id| {
+            let kept = keep(identifier, id);
+            if !kept {
+                identifiers[id as usize] = None;
+            }
+            kept
+        });
+    }
+
+    /// Moves every identifier from its ID to `new_ids[id]`. The new IDs have to be unique.
+    pub fn renumber(&mut self, new_ids: &[u32]) {
+        let mut identifiers = vec![None; self.ids.len()];
+        for (identifier, id) in self.ids.iter_mut() {
+            *id = new_ids[*id as usize];
+            if identifiers.len() <= *id as usize {
+                identifiers.resize(*id as usize + 1, None);
+            }
+            identifiers[*id as usize] = Some(Arc::clone(identifier));
+        }
+        self.identifiers = identifiers;
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.ids.shrink_to_fit();
+        while let Some(None) = self.identifiers.last() {
+            self.identifiers.pop();
+        }
+        self.identifiers.shrink_to_fit();
+    }
+
+    /// Estimated bytes used by the mapping, including the identifiers, each counted once.
+    pub fn bytes(&self) -> usize {
+        // An `Arc` allocation holds the strong and weak counts besides the string
+        let identifiers: usize = self.ids.keys().map(|identifier| identifier.len() + 2 * std::mem::size_of::<usize>()).sum();
+        memory::table_bytes::<Arc<str>, u32>(self.ids.capacity())
+            + self.identifiers.capacity() * std::mem::size_of::<Option<Arc<str>>>()
+            + identifiers
+    }
+
+    /// The mapping from identifiers to IDs.
+    #[cfg(test)]
+    pub fn map(&self) -> &HashMap<Arc<str>, u32, RandomState> {
+        &self.ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_both_directions_share_one_string_and_survive_renumbering() {
+        let mut interner = Interner::new();
+        assert_eq!((interner.intern("a"), interner.intern("b"), interner.intern("a")), (0, 1, 0));
+        interner.insert("c", 4);
+        assert_eq!(Arc::strong_count(interner.map().get_key_value("c").unwrap().0), 2);
+
+        interner.retain(|identifier, _| identifier != "b");
+        assert_eq!((interner.resolve(1), interner.get("b")), (None, None));
+        interner.renumber(&[0, u32::MAX, u32::MAX, u32::MAX, 1]);
+        assert_eq!((interner.resolve(1), interner.get("c"), interner.len()), (Some("c"), Some(1), 2));
+        assert_eq!(interner.intern("d"), 2);
+
+        let json = serde_json::to_string(&interner).unwrap();
+        let decoded: Interner = serde_json::from_str(&json).unwrap();
+        assert_eq!((decoded.resolve(2), decoded.get("a")), (Some("d"), Some(0)));
+    }
+}
@@ -0,0 +1,124 @@
+// src/algorithms/maintenance.rs
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::info;
+
+use crate::algorithms::consistent_snapshot;
+use crate::algorithms::eviction::evict_unseen;
+use crate::algorithms::memory_compaction::compact_memory;
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::config::Settings;
+use crate::scheduler::{JobError, Schedule, Scheduler};
+use crate::{determinism, locks, stats};
+
+/// Returns the jobs keeping the co-occurrences and counters of `tenant` ("default" for
+/// requests without one) in shape: the rotation of the counters, their and (if
+/// `snapshot_co_occurrences`, i.e. no store records every list) the co-occurrences'
+/// snapshots, and the configured eviction and compaction. Every job is named after the
+/// tenant, so its log lines tell whose state it works on.
+///
+/// Both persistence jobs write the counters and the co-occurrences together (see
+/// `consistent_snapshot::persist`), so the files always hold the same moment; each
+/// schedule only sets when that happens at the latest.
+pub fn maintenance_jobs(
+    tenant: &str,
+    co_occurrence: &Arc<RwLock<CoOccurrenceCounter>>,
+    counters: &Arc<RwLock<Counters>>,
+    settings: &Settings,
+    snapshot_co_occurrences: bool,
+) -> Scheduler {
+    let mut scheduler = Scheduler::new();
+    let timezone = settings.counters.rotation_timezone;
+
+    // Rotates by however many boundaries were crossed since the last rotation, which is
+    // normally exactly one hour (plus day/week/month at their boundaries). Replicas
+    // receive the primary's rotations instead.
+    let rotated = Arc::clone(counters);
+    scheduler.register(format!("{}/counter_rotation", tenant), Schedule::Hourly(timezone), move || {
+        let mut counters = locks::write(&rotated, "rotating_counters");
+        if !counters.is_following() {
+            counters.advance_to(&determinism::now().with_timezone(&timezone));
+        }
+        Ok(())
+    });
+
+    // Without an interval at the hour boundaries, like the rotation; writes nothing
+    // unless anything changed
+    let (persisted_co_occurrence, persisted) = (Arc::clone(co_occurrence), Arc::clone(counters));
+    let schedule = match settings.counters.persist_interval_secs {
+        0 => Schedule::Hourly(timezone),
+        secs => Schedule::Every(Duration::from_secs(secs)),
+    };
+    scheduler.register(format!("{}/counter_persistence", tenant), schedule, move || {
+        consistent_snapshot::persist(&persisted_co_occurrence, &persisted, snapshot_co_occurrences);
+        Ok(())
+    });
+
+    if snapshot_co_occurrences {
+        let (snapshotted, snapshotted_counters) = (Arc::clone(co_occurrence), Arc::clone(counters));
+        let interval = Duration::from_secs(settings.storage.lists_snapshot_interval_secs);
+        scheduler.register(format!("{}/co_occurrence_persistence", tenant), Schedule::Every(interval), move || {
+            consistent_snapshot::persist(&snapshotted, &snapshotted_counters, true);
+            Ok(())
+        });
+    }
+
+    if settings.eviction.ttl_days > 0 {
+        let (co_occurrence, counters) = (Arc::clone(co_occurrence), Arc::clone(counters));
+        let ttl_days = settings.eviction.ttl_days;
+        let interval = Duration::from_secs(settings.eviction.interval_secs);
+        scheduler.register(format!("{}/identifier_eviction", tenant), Schedule::Every(interval), move || {
+            let cutoff = determinism::now() - chrono::Duration::days(ttl_days as i64);
+            // Not supported by the storage, so it would fail every time
+            let (identifiers, pairs, counter_identifiers) = evict_unseen(&co_occurrence, &counters, cutoff).map_err(JobError::Stop)?;
+            if identifiers > 0 || counter_identifiers > 0 {
+                info!(identifiers, pairs, counter_identifiers, "Evicted identifiers not seen for {} days.", ttl_days);
+            }
+            Ok(())
+        });
+    }
+
+    if settings.compaction.interval_secs > 0 {
+        let (co_occurrence, counters) = (Arc::clone(co_occurrence), Arc::clone(counters));
+        let tenant = tenant.to_string();
+        let interval = Duration::from_secs(settings.compaction.interval_secs);
+        scheduler.register(format!("{}/memory_compaction", tenant), Schedule::Every(interval), move || {
+            let summary = compact_memory(&co_occurrence, &counters);
+            info!(
+                tenant,
+                reclaimed_bytes = summary.reclaimed_bytes,
+                released_ids = summary.released_ids,
+                "Memory compacted."
+            );
+            stats::record_compaction(tenant.clone(), summary);
+            Ok(())
+        });
+    }
+
+    scheduler
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_configured_jobs_are_registered() {
+        let mut settings = Settings::from_env();
+        settings.eviction.ttl_days = 0;
+        settings.compaction.interval_secs = 0;
+        let co_occurrence = Arc::new(RwLock::new(CoOccurrenceCounter::new()));
+        let counters = Arc::new(RwLock::new(Counters::with_depths(3, 3, 1, 1)));
+
+        let scheduler = maintenance_jobs("default", &co_occurrence, &counters, &settings, false);
+        assert_eq!(scheduler.job_names(), ["default/counter_rotation", "default/counter_persistence"]);
+
+        settings.eviction.ttl_days = 7;
+        settings.compaction.interval_secs = 60;
+        let scheduler = maintenance_jobs("news", &co_occurrence, &counters, &settings, true);
+        assert_eq!(
+            scheduler.job_names(),
+            ["news/counter_rotation", "news/counter_persistence", "news/co_occurrence_persistence", "news/identifier_eviction", "news/memory_compaction"]
+        );
+    }
+}
@@ -0,0 +1,63 @@
+// src/algorithms/memory_compaction.rs
+use std::sync::RwLock;
+use std::time::Instant;
+
+use crate::algorithms::rotating_counters::Granularity;
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::{determinism, locks};
+use crate::stats::CompactionSummary;
+
+/// Estimated bytes used by the co-occurrence maps and the counters.
+fn used_bytes(co_occurrence: &CoOccurrenceCounter, counters: &Counters) -> usize {
+    let buckets: usize = Granularity::ALL.into_iter().map(|granularity| counters.bucket_bytes(granularity)).sum();
+    co_occurrence.identifier_map_bytes() + co_occurrence.pair_counts_bytes() + buckets + counters.first_seen_bytes()
+}
+
+/// Shrinks the co-occurrence maps and the counters to their contents (see
+/// `CoOccurrenceCounter::shrink`). The total of reclaimed bytes is left to `stats`.
+pub fn compact_memory(co_occurrence: &RwLock<CoOccurrenceCounter>, counters: &RwLock<Counters>) -> CompactionSummary {
+    let started = Instant::now();
+    let mut co_occurrence = locks::write(co_occurrence, "co_occurrence");
+    let mut counters = locks::write(counters, "rotating_counters");
+    let before = used_bytes(&co_occurrence, &counters);
+    let released_ids = co_occurrence.shrink();
+    counters.shrink();
+    let reclaimed_bytes = before.saturating_sub(used_bytes(&co_occurrence, &counters));
+    CompactionSummary {
+        compacted_at: determinism::now(),
+        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        reclaimed_bytes,
+        total_reclaimed_bytes: reclaimed_bytes,
+        released_ids,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compaction_keeps_the_counts_and_releases_removed_ids() {
+        let co_occurrence = RwLock::new(CoOccurrenceCounter::new());
+        let counters = RwLock::new(Counters::with_depths(3, 3, 1, 1));
+        {
+            let mut co_occurrence = locks::write(&co_occurrence, "co_occurrence");
+            for i in 0..1000 {
+                co_occurrence.process_list(&[format!("ard:{}", i), format!("zdf:{}", i), "arte:1".to_string()]);
+            }
+            co_occurrence.process_list(&["zdf:1".to_string(), "arte:1".to_string()]);
+            assert_eq!(co_occurrence.remove_prefix("ard:"), Ok((1000, 2000)));
+            let counters = locks::read(&counters, "rotating_counters");
+            (0..1000).for_each(|i| counters.increment(&format!("ard:{}", i), 1));
+        }
+        locks::write(&counters, "rotating_counters").remove_prefix("ard:");
+
+        let summary = compact_memory(&co_occurrence, &counters);
+        assert_eq!(summary.released_ids, 1000);
+        assert!(summary.reclaimed_bytes > 0);
+        let co_occurrence = locks::read(&co_occurrence, "co_occurrence");
+        assert_eq!(co_occurrence.get_identifier_to_id_map().values().max(), Some(&1000));
+        assert_eq!(co_occurrence.get_metrics_for_identifier("zdf:1").get("arte:1"), Some(&2));
+        assert_eq!(co_occurrence.pair_count(), 1000);
+    }
+}
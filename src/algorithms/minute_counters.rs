@@ -0,0 +1,122 @@
+// src/algorithms/minute_counters.rs
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::RwLock;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::algorithms::rotating_counters::{count_of, rotate_buckets, Bucket};
+use crate::locks;
+
+/// The count of an identifier in one minute.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct MinutePoint {
+    /// Start of the minute
+    pub minute: DateTime<Utc>,
+    pub count: u64,
+}
+
+/// Per-minute counts of the last minutes, for following live programming closer than the
+/// hourly buckets allow. Disabled without buckets, as every bucket costs as much memory as
+/// an hourly one, and kept in memory only: after a restart, the minutes before are gone
+/// anyway. The ring rotates on its own rather than with the other buckets: every increment
+/// and read first shifts it to the current minute.
+#[derive(Debug, Default)]
+pub struct MinuteCounters {
+    /// `buckets[0]` is the minute `current`, `buckets[1]` the one before and so on
+    buckets: RwLock<Vec<Bucket>>,
+    /// Minutes since the Unix epoch of `buckets[0]`
+    current: AtomicI64,
+}
+
+fn minute_of(at: DateTime<Utc>) -> i64 {
+    at.timestamp().div_euclid(60)
+}
+
+impl MinuteCounters {
+    pub fn new(buckets: usize) -> Self {
+        MinuteCounters { buckets: RwLock::new(vec![Bucket::new(); buckets]), current: AtomicI64::new(0) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !locks::read(&self.buckets, "minute_counters").is_empty()
+    }
+
+    /// Adds `amount` to the bucket of the minute containing `at`. Increments older than
+    /// the oldest bucket, e.g. replayed after a restart, are dropped.
+    pub fn increment(&self, id: &str, amount: u64, at: DateTime<Utc>) {
+        let minute = minute_of(at);
+        self.advance_to(minute);
+        let buckets = locks::read(&self.buckets, "minute_counters");
+        let age = self.current.load(Ordering::Acquire) - minute;
+        if let Some(bucket) = usize::try_from(age).ok().and_then(|age| buckets.get(age)) {
+            let mut count = bucket.entry(id.to_string()).or_insert(0);
+            *count = count.saturating_add(amount);
+        }
+    }
+
+    /// Rotates the ring so that `buckets[0]` is `minute`; earlier minutes are ignored.
+    fn advance_to(&self, minute: i64) {
+        if minute <= self.current.load(Ordering::Acquire) {
+            return;
+        }
+        let mut buckets = locks::write(&self.buckets, "minute_counters");
+        let current = self.current.load(Ordering::Acquire);
+        if minute > current {
+            let steps = usize::try_from(minute - current).unwrap_or(usize::MAX);
+            rotate_buckets(&mut buckets, steps);
+            self.current.store(minute, Ordering::Release);
+        }
+    }
+
+    /// Returns the counts of `id` in every minute up to the one containing `now`, oldest
+    /// first. Minutes without activity for `id` count as 0.
+    pub fn series(&self, id: &str, now: DateTime<Utc>) -> Vec<MinutePoint> {
+        self.advance_to(minute_of(now));
+        let buckets = locks::read(&self.buckets, "minute_counters");
+        let current = self.current.load(Ordering::Acquire);
+        buckets
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(age, bucket)| MinutePoint {
+                minute: DateTime::from_timestamp((current - age as i64) * 60, 0).unwrap_or_default(),
+                count: count_of(bucket, id),
+            })
+            .collect()
+    }
+
+    /// Removes `id` from every minute. Returns whether it had any counts.
+    pub fn remove(&self, id: &str) -> bool {
+        let buckets = locks::read(&self.buckets, "minute_counters");
+        // Removed from every minute first, as `any` stops at the first match
+        let removed: Vec<Option<(String, u64)>> = buckets.iter().map(|bucket| bucket.remove(id)).collect();
+        removed.iter().any(Option::is_some)
+    }
+
+    pub fn clear(&self) {
+        locks::read(&self.buckets, "minute_counters").iter().for_each(Bucket::clear);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minutes_rotate_on_their_own() {
+        let start = DateTime::from_timestamp(600, 0).unwrap();
+        let minutes = MinuteCounters::new(3);
+        minutes.increment("a", 2, start);
+        minutes.increment("a", 1, start + chrono::Duration::seconds(61));
+        // Older than the oldest bucket
+        minutes.increment("a", 5, start - chrono::Duration::minutes(10));
+
+        let series = minutes.series("a", start + chrono::Duration::seconds(150));
+        assert_eq!(series.iter().map(|point| point.count).collect::<Vec<_>>(), [2, 1, 0]);
+        assert_eq!(series[0].minute, start);
+        assert_eq!(minutes.series("a", start + chrono::Duration::hours(1)).iter().map(|point| point.count).sum::<u64>(), 0);
+
+        assert!(!MinuteCounters::new(0).is_enabled());
+    }
+}
@@ -1,6 +1,53 @@
 // src/algorithms/mod.rs
+pub mod association_rules;
+pub mod backup;
+pub mod boosts;
 pub mod co_occurrence;
+pub mod consistent_snapshot;
+pub mod counter_store;
+pub mod digest;
+pub mod embeddings;
+pub mod event_log;
+pub mod eviction;
+pub mod gossip;
+pub mod holdout;
+pub mod identifier_filter;
+pub mod interner;
+pub mod maintenance;
+pub mod factorization;
+pub mod forecast;
+pub mod memory_compaction;
+pub mod minute_counters;
+pub mod namespace_rollups;
+pub mod object_storage;
+pub mod popularity;
+pub mod postgres_store;
+pub mod quality;
+pub mod recent_lists;
+pub mod replication;
 pub mod rotating_counters;
+pub mod scoring;
+pub mod session_dedup;
+pub mod shadow;
+pub mod sled_store;
+pub mod snapshot;
+pub mod spikes;
+pub mod sqlite_store;
+pub mod state_store;
+pub mod tenants;
+pub mod tombstones;
+pub mod transitions;
+pub mod trending;
+pub mod warmup;
 
+pub use self::association_rules::{AssociationRule, RuleSet, run_rule_mining};
 pub use self::co_occurrence::CoOccurrenceCounter;
-pub use self::rotating_counters::{Counters, run_daily_counter_rotation, perform_final_persistence};
+pub use self::digest::run_digest_webhooks;
+pub use self::embeddings::{ItemEmbeddings, run_embedding_training};
+pub use self::gossip::run_counter_gossip;
+pub use self::factorization::{FactorizationState, run_factorization_training};
+pub use self::quality::{QualityMonitor, run_quality_evaluation};
+pub use self::recent_lists::RecentLists;
+pub use self::rotating_counters::{Counters, run_counter_sync, perform_final_persistence};
+pub use self::spikes::{AlertLog, run_spike_detection};
+pub use self::transitions::TransitionCounter;
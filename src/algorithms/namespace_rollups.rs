@@ -0,0 +1,138 @@
+// src/algorithms/namespace_rollups.rs
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::algorithms::rotating_counters::{rotate_buckets, Bucket, Granularity};
+use crate::algorithms::scoring::namespace_of;
+
+/// Key of the plays of identifiers without a namespace, which only count towards the totals.
+const NO_NAMESPACE: &str = "";
+
+/// The plays of one namespace in a window.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct NamespacePlays {
+    pub namespace: String,
+    pub plays: u64,
+    /// Share of all plays in the window, 0–1
+    pub share: f64,
+}
+
+/// Plays per namespace ("ard" of "ard:123", usually the broadcaster) in every bucket of the
+/// counters. Kept up to date along with the buckets on every increment and rotation, so
+/// the traffic shares of the broadcasters don't take a pass over all identifiers.
+#[derive(Debug, Default, Clone)]
+pub struct NamespaceRollups {
+    /// The buckets of every granularity, in the order of `Granularity::ALL`
+    buckets: [Vec<Bucket>; 4],
+}
+
+fn slot(granularity: Granularity) -> usize {
+    match granularity {
+        Granularity::Hour => 0,
+        Granularity::Day => 1,
+        Granularity::Week => 2,
+        Granularity::Month => 3,
+    }
+}
+
+fn key(id: &str) -> &str {
+    namespace_of(id).unwrap_or(NO_NAMESPACE)
+}
+
+impl NamespaceRollups {
+    /// Sums up `buckets`, the buckets of every granularity in the order of `Granularity::ALL`.
+    pub fn of(buckets: [&[Bucket]; 4]) -> Self {
+        let rollups = NamespaceRollups { buckets: buckets.map(|buckets| vec![Bucket::new(); buckets.len()]) };
+        for granularity in Granularity::ALL {
+            for (index, bucket) in buckets[slot(granularity)].iter().enumerate() {
+                for entry in bucket.iter() {
+                    rollups.add_to(granularity, index, entry.key(), *entry.value());
+                }
+            }
+        }
+        rollups
+    }
+
+    /// Adds `amount` plays of `id` to the current bucket of every granularity.
+    pub fn add(&self, id: &str, amount: u64) {
+        for granularity in Granularity::ALL {
+            self.add_to(granularity, 0, id, amount);
+        }
+    }
+
+    pub fn add_to(&self, granularity: Granularity, index: usize, id: &str, amount: u64) {
+        if let Some(bucket) = self.buckets[slot(granularity)].get(index) {
+            let mut plays = bucket.entry(key(id).to_string()).or_insert(0);
+            *plays = plays.saturating_add(amount);
+        }
+    }
+
+    /// Takes back `amount` plays of `id`, e.g. of a removed identifier.
+    pub fn subtract(&self, granularity: Granularity, index: usize, id: &str, amount: u64) {
+        if let Some(bucket) = self.buckets[slot(granularity)].get(index) {
+            if let Some(mut plays) = bucket.get_mut(key(id)) {
+                *plays = plays.saturating_sub(amount);
+            }
+            bucket.remove_if(key(id), |_, plays| *plays == 0);
+        }
+    }
+
+    /// Shifts the buckets of one granularity like the counters' own.
+    pub fn rotate(&mut self, granularity: Granularity, steps: usize) {
+        rotate_buckets(&mut self.buckets[slot(granularity)], steps);
+    }
+
+    /// Clears all buckets, keeping their number.
+    pub fn clear(&mut self) {
+        self.buckets.iter().flatten().for_each(Bucket::clear);
+    }
+
+    /// Returns all plays in bucket `index` of `granularity` and the namespaces by their
+    /// plays (descending, ties by namespace), or `None` if there is no such bucket.
+    pub fn plays(&self, granularity: Granularity, index: usize) -> Option<(u64, Vec<NamespacePlays>)> {
+        let bucket = self.buckets[slot(granularity)].get(index)?;
+        let total: u64 = bucket.iter().map(|entry| *entry.value()).sum();
+        let mut namespaces: Vec<NamespacePlays> = bucket
+            .iter()
+            .filter(|entry| entry.key() != NO_NAMESPACE && *entry.value() > 0)
+            .map(|entry| NamespacePlays {
+                namespace: entry.key().clone(),
+                plays: *entry.value(),
+                share: *entry.value() as f64 / total as f64,
+            })
+            .collect();
+        namespaces.sort_by(|a, b| b.plays.cmp(&a.plays).then_with(|| a.namespace.cmp(&b.namespace)));
+        Some((total, namespaces))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollups_follow_increments_rotations_and_removals() {
+        let hourly = [Bucket::new(), Bucket::new()];
+        hourly[1].insert("zdf:x".to_string(), 4);
+        let rollups = NamespaceRollups::of([&hourly[..], &[Bucket::new()], &[Bucket::new()], &[Bucket::new()]]);
+        assert_eq!(rollups.plays(Granularity::Hour, 1).unwrap().0, 4);
+
+        rollups.add("ard:a", 3);
+        rollups.add("ard:b", 1);
+        rollups.add("zdf:y", 2);
+        rollups.add("untagged", 2);
+        let (total, namespaces) = rollups.plays(Granularity::Day, 0).unwrap();
+        assert_eq!(total, 8);
+        let shares: Vec<(&str, u64, f64)> = namespaces.iter().map(|n| (n.namespace.as_str(), n.plays, n.share)).collect();
+        assert_eq!(shares, [("ard", 4, 0.5), ("zdf", 2, 0.25)]);
+
+        rollups.subtract(Granularity::Day, 0, "zdf:y", 2);
+        assert_eq!(rollups.plays(Granularity::Day, 0).unwrap().1.len(), 1);
+
+        let mut rollups = rollups;
+        rollups.rotate(Granularity::Hour, 1);
+        assert_eq!(rollups.plays(Granularity::Hour, 0).unwrap().0, 0);
+        assert_eq!(rollups.plays(Granularity::Hour, 1).unwrap().0, 8);
+        assert!(rollups.plays(Granularity::Hour, 2).is_none());
+    }
+}
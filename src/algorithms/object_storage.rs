@@ -0,0 +1,250 @@
+// src/algorithms/object_storage.rs
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::algorithms::snapshot;
+use crate::config::ObjectStorageSettings;
+
+/// Timeout of one upload or download, generous as snapshots can be hundreds of MB.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(600);
+/// Largest snapshot downloaded.
+const MAX_DOWNLOAD_BYTES: usize = 16 << 30;
+
+/// Snapshots waiting to be uploaded, once uploads are started (see `start_uploads`). A
+/// static rather than app data, because snapshots are written deep inside code that has
+/// no access to it.
+static UPLOADS: OnceLock<mpsc::UnboundedSender<PathBuf>> = OnceLock::new();
+
+/// An S3-compatible bucket holding copies of the snapshots, one object per snapshot
+/// file, overwritten by every upload. Requests are signed with AWS Signature Version 4.
+#[derive(Debug, Clone)]
+pub struct ObjectStorage {
+    settings: ObjectStorageSettings,
+    bucket: String,
+    /// Host (and port) of the endpoint, as sent in the `Host` header
+    host: String,
+    /// Path of the endpoint, if it has one
+    base_path: String,
+    /// Snapshots are named by their path in here, so those of tenants don't collide
+    data_dir: PathBuf,
+}
+
+/// Percent-encodes everything but unreserved characters (and slashes, if `keep_slashes`),
+/// as required for canonical requests.
+fn uri_encode(value: &str, keep_slashes: bool) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            b'/' if keep_slashes => "/".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Derives the key requests of one day are signed with.
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date);
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, service);
+    hmac_sha256(&key, "aws4_request")
+}
+
+impl ObjectStorage {
+    /// Returns the configured bucket for the snapshots in `data_dir`, or `None` if there
+    /// is none.
+    pub fn new(settings: &ObjectStorageSettings, data_dir: &Path) -> Option<Self> {
+        let bucket = settings.bucket.clone()?;
+        let authority = settings.endpoint.split_once("://").map_or(settings.endpoint.as_str(), |(_, rest)| rest);
+        let (host, base_path) = match authority.find('/') {
+            Some(index) => (&authority[..index], authority[index..].trim_end_matches('/')),
+            None => (authority, ""),
+        };
+        Some(ObjectStorage {
+            settings: settings.clone(),
+            bucket,
+            host: host.to_string(),
+            base_path: base_path.to_string(),
+            data_dir: data_dir.to_path_buf(),
+        })
+    }
+
+    /// Object key of a snapshot file, e.g. "snapshots/rotating_counters.json", or
+    /// "snapshots/tenants/ard/rotating_counters.json" for a tenant.
+    fn key(&self, path: &Path) -> String {
+        let name = match path.strip_prefix(&self.data_dir) {
+            Ok(relative) => relative.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/"),
+            Err(_) => path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned(),
+        };
+        format!("{}{}", self.settings.prefix, name)
+    }
+
+    /// Path of an object, URI-encoded.
+    fn object_path(&self, key: &str) -> String {
+        format!("{}/{}/{}", self.base_path, uri_encode(&self.bucket, false), uri_encode(key, true))
+    }
+
+    fn url(&self, key: &str) -> String {
+        let endpoint = self.settings.endpoint.trim_end_matches('/');
+        let origin = endpoint.strip_suffix(self.base_path.as_str()).unwrap_or(endpoint);
+        format!("{}{}", origin, self.object_path(key))
+    }
+
+    /// Returns the headers signing a request without query parameters.
+    fn signed_headers(&self, method: &str, key: &str, payload: &[u8], at: DateTime<Utc>) -> Vec<(&'static str, String)> {
+        let payload_hash = hex::encode(Sha256::digest(payload));
+        let timestamp = at.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = at.format("%Y%m%d").to_string();
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method,
+            self.object_path(key),
+            self.host,
+            payload_hash,
+            timestamp,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.settings.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.settings.secret_access_key, &date, &self.settings.region, "s3");
+        let signature = hex::encode(hmac_sha256(&key, &string_to_sign));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.settings.access_key_id, scope, signature
+        );
+        vec![("x-amz-content-sha256", payload_hash), ("x-amz-date", timestamp), ("authorization", authorization)]
+    }
+
+    /// Uploads the snapshot file at `path`.
+    pub async fn upload(&self, client: &awc::Client, path: &Path) -> Result<(), String> {
+        let data = tokio::fs::read(path).await.map_err(|e| e.to_string())?;
+        let key = self.key(path);
+        let mut request = client.put(self.url(&key)).timeout(TRANSFER_TIMEOUT);
+        for header in self.signed_headers("PUT", &key, &data, Utc::now()) {
+            request = request.insert_header(header);
+        }
+        let response = request.send_body(data).await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Bucket responded with status {}", response.status()));
+        }
+        Ok(())
+    }
+
+    /// Downloads the snapshot of `path` to it. Returns `false` if the bucket has none.
+    pub async fn download(&self, client: &awc::Client, path: &Path) -> Result<bool, String> {
+        let key = self.key(path);
+        let mut request = client.get(self.url(&key)).timeout(TRANSFER_TIMEOUT);
+        for header in self.signed_headers("GET", &key, b"", Utc::now()) {
+            request = request.insert_header(header);
+        }
+        let mut response = request.send().await.map_err(|e| e.to_string())?;
+        if response.status().as_u16() == 404 {
+            return Ok(false);
+        }
+        if !response.status().is_success() {
+            return Err(format!("Bucket responded with status {}", response.status()));
+        }
+        let data = response.body().limit(MAX_DOWNLOAD_BYTES).await.map_err(|e| e.to_string())?;
+        snapshot::write(path, &data).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
+
+/// Downloads the snapshots at `paths` that don't exist locally, e.g. on a fresh disk.
+/// Must be called on the actix runtime, since the HTTP client is not `Send`.
+pub async fn restore_snapshots(storage: &ObjectStorage, paths: &[PathBuf]) {
+    let client = awc::Client::default();
+    for path in paths.iter().filter(|path| !path.exists()) {
+        match storage.download(&client, path).await {
+            Ok(true) => info!("Restored {} from the bucket.", path.display()),
+            Ok(false) => info!("No snapshot {} in the bucket yet.", path.display()),
+            Err(e) => error!("Failed to restore {} from the bucket: {}", path.display(), e),
+        }
+    }
+}
+
+/// Uploads the snapshots at `paths` that exist, e.g. the final ones on shutdown.
+pub async fn upload_snapshots(storage: &ObjectStorage, paths: &[PathBuf]) {
+    let client = awc::Client::default();
+    for path in paths.iter().filter(|path| path.exists()) {
+        if let Err(e) = storage.upload(&client, path).await {
+            error!("Failed to upload {} to the bucket: {}", path.display(), e);
+        }
+    }
+}
+
+/// Starts queueing every snapshot written for `run_snapshot_uploads`.
+pub fn start_uploads() -> mpsc::UnboundedReceiver<PathBuf> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    if UPLOADS.set(sender).is_err() {
+        warn!("Snapshot uploads were already started.");
+    }
+    receiver
+}
+
+/// Queues the snapshot just written to `path` for uploading, if uploads are started.
+pub fn queue_upload(path: &Path) {
+    if let Some(uploads) = UPLOADS.get() {
+        let _ = uploads.send(path.to_path_buf());
+    }
+}
+
+// Function to upload the snapshots to the bucket as they are written.
+// Must be spawned on the actix runtime, since the HTTP client is not `Send`.
+pub async fn run_snapshot_uploads(storage: ObjectStorage, mut uploads: mpsc::UnboundedReceiver<PathBuf>) {
+    info!("Snapshot uploads started for bucket {}.", storage.bucket);
+    let client = awc::Client::default();
+    while let Some(path) = uploads.recv().await {
+        match storage.upload(&client, &path).await {
+            Ok(()) => info!("Uploaded {} to the bucket.", path.display()),
+            Err(e) => error!("Failed to upload {} to the bucket: {}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_signing_key_matches_aws_example() {
+        // From the AWS documentation on deriving the signing key
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+        assert_eq!(hex::encode(key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+    }
+
+    #[test]
+    fn test_requests_are_signed_for_the_object_path() {
+        let mut settings = crate::config::Settings::from_env().object_storage;
+        (settings.bucket, settings.endpoint, settings.prefix) =
+            (Some("backups".to_string()), "http://minio:9000/".to_string(), "snap shots/".to_string());
+        let storage = ObjectStorage::new(&settings, Path::new("data")).unwrap();
+        let key = storage.key(Path::new("data/rotating_counters.json"));
+        assert_eq!(storage.key(Path::new("data/tenants/ard/rotating_counters.json")), "snap shots/tenants/ard/rotating_counters.json");
+        assert_eq!(storage.url(&key), "http://minio:9000/backups/snap%20shots/rotating_counters.json");
+        assert_eq!(storage.host, "minio:9000");
+
+        let at = Utc.with_ymd_and_hms(2025, 1, 6, 12, 0, 0).unwrap();
+        let headers = storage.signed_headers("PUT", &key, b"{}", at);
+        assert_eq!(headers[1], ("x-amz-date", "20250106T120000Z".to_string()));
+        assert!(headers[2].1.contains("/20250106/us-east-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature="));
+    }
+}
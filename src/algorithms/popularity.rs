@@ -0,0 +1,115 @@
+// src/algorithms/popularity.rs
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+/// Half-life of the popularity scores unless configured otherwise: one day.
+pub const DEFAULT_HALF_LIFE_SECS: u64 = 86_400;
+
+/// A score as of the time it was last updated.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+struct DecayedScore {
+    score: f64,
+    at: DateTime<Utc>,
+}
+
+/// Popularity that decays continuously instead of dropping at bucket rotations: every play
+/// adds its count, and every score halves per half-life since. Only the score and the time
+/// of the last update are kept per identifier, and scores are decayed when read.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DecayedPopularity {
+    #[serde(skip, default = "default_half_life")]
+    half_life_secs: f64,
+    scores: DashMap<String, DecayedScore>,
+}
+
+fn default_half_life() -> f64 {
+    DEFAULT_HALF_LIFE_SECS as f64
+}
+
+impl Default for DecayedPopularity {
+    fn default() -> Self {
+        DecayedPopularity { half_life_secs: default_half_life(), scores: DashMap::new() }
+    }
+}
+
+impl DecayedPopularity {
+    /// Changes the half-life; scores already decayed keep their value.
+    pub fn set_half_life(&mut self, half_life_secs: u64) {
+        self.half_life_secs = half_life_secs.max(1) as f64;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// The factor a score shrinks by from `from` to `to`; 1 if `to` isn't later.
+    fn decay(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+        let elapsed = (to - from).num_milliseconds().max(0) as f64 / 1000.0;
+        0.5f64.powf(elapsed / self.half_life_secs)
+    }
+
+    /// Adds `amount` plays at `at`. Plays older than the last update, e.g. replayed ones,
+    /// are decayed to it instead.
+    pub fn add(&self, id: &str, amount: u64, at: DateTime<Utc>) {
+        self.add_score(id.to_string(), amount as f64, at);
+    }
+
+    fn add_score(&self, id: String, score: f64, at: DateTime<Utc>) {
+        let mut entry = self.scores.entry(id).or_insert(DecayedScore { score: 0.0, at });
+        if at >= entry.at {
+            entry.score = entry.score * self.decay(entry.at, at) + score;
+            entry.at = at;
+        } else {
+            entry.score += score * self.decay(at, entry.at);
+        }
+    }
+
+    /// Adds the scores of another instance.
+    pub fn merge(&self, other: DecayedPopularity) {
+        for (id, other) in other.scores {
+            self.add_score(id, other.score, other.at);
+        }
+    }
+
+    /// Returns the score of `id` at `now`, `None` if it was never played.
+    pub fn score(&self, id: &str, now: DateTime<Utc>) -> Option<f64> {
+        self.scores.get(id).map(|entry| entry.score * self.decay(entry.at, now))
+    }
+
+    /// Returns the score of every identifier at `now`.
+    pub fn scores(&self, now: DateTime<Utc>) -> Vec<(String, f64)> {
+        self.scores.iter().map(|entry| (entry.key().clone(), entry.score * self.decay(entry.at, now))).collect()
+    }
+
+    pub fn remove(&self, id: &str) -> bool {
+        self.scores.remove(id).is_some()
+    }
+
+    pub fn clear(&self) {
+        self.scores.clear();
+    }
+
+    pub fn shrink(&self) {
+        self.scores.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scores_halve_per_half_life() {
+        let mut popularity = DecayedPopularity::default();
+        popularity.set_half_life(3600);
+        let start = DateTime::UNIX_EPOCH;
+        popularity.add("a", 8, start);
+        assert_eq!(popularity.score("a", start + chrono::Duration::hours(2)), Some(2.0));
+        popularity.add("a", 2, start + chrono::Duration::hours(1));
+        // Replayed out of order, so decayed to the last update
+        popularity.add("a", 4, start);
+        assert_eq!(popularity.score("a", start + chrono::Duration::hours(1)), Some(8.0));
+        assert_eq!(popularity.score("b", start), None);
+    }
+}
@@ -0,0 +1,469 @@
+// src/algorithms/postgres_store.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::{Postgres, Transaction};
+use tracing::{info, warn};
+
+use crate::algorithms::co_occurrence::{PairState, PairStore};
+use crate::algorithms::counter_store::{signed_count, CounterStore};
+use crate::algorithms::rotating_counters::{Bucket, Counters, Granularity};
+use crate::config::{CounterSettings, StorageSettings};
+use crate::locks;
+
+/// Schema changes, applied in order on startup and recorded in `schema_migrations`.
+/// Never change a released entry, append a new one instead.
+const MIGRATIONS: [&str; 1] = [
+    // 1: co-occurrences, keyed by identifier as IDs are local to each instance, and the
+    // rotating counter buckets with the period each granularity was last rotated into
+    "CREATE TABLE pairs (
+        first TEXT NOT NULL,
+        second TEXT NOT NULL,
+        count BIGINT NOT NULL,
+        PRIMARY KEY (first, second)
+    );
+    CREATE TABLE counter_buckets (
+        series TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        identifier TEXT NOT NULL,
+        count BIGINT NOT NULL,
+        PRIMARY KEY (series, position, identifier)
+    );
+    CREATE INDEX counter_buckets_identifier ON counter_buckets (identifier);
+    CREATE TABLE counter_periods (
+        series TEXT PRIMARY KEY,
+        period TEXT NOT NULL
+    );",
+];
+
+/// A change to the counters waiting to be written.
+#[derive(Debug)]
+enum Write {
+    /// Increments of the current bucket of every granularity, summed per identifier
+    Increments(HashMap<String, u64>),
+    Add { granularity: Granularity, index: usize, counts: Vec<(String, u64)> },
+    Rotate { granularity: Granularity, steps: usize, period: String },
+    Remove(String),
+    Reset,
+}
+
+/// Changes waiting for the next batch.
+#[derive(Debug, Default)]
+struct Pending {
+    /// Counter changes, in order, as rotations must not overtake increments
+    writes: Vec<Write>,
+    /// Pair increments by identifiers, smaller one first
+    pairs: HashMap<(String, String), u64>,
+}
+
+impl Pending {
+    fn is_empty(&self) -> bool {
+        self.writes.is_empty() && self.pairs.is_empty()
+    }
+
+    /// Puts changes that failed to be written back in front of the ones queued since.
+    fn requeue(&mut self, failed: Pending) {
+        let newer = std::mem::replace(&mut self.writes, failed.writes);
+        self.writes.extend(newer);
+        for (pair, count) in failed.pairs {
+            let total = self.pairs.entry(pair).or_insert(0);
+            *total = total.saturating_add(count);
+        }
+    }
+}
+
+/// Keeps the co-occurrences and the counter buckets in a PostgreSQL database shared by
+/// all instances. Changes are queued and written in batches every flush interval (see
+/// `run_postgres_flush`), so requests never wait for the database. On startup, the
+/// in-memory state is hydrated from the database.
+pub struct PostgresStore {
+    pool: PgPool,
+    /// Number of buckets per granularity, in the order of `Granularity::ALL`
+    depths: [usize; 4],
+    pending: Mutex<Pending>,
+    /// Identifier of every co-occurrence ID handed out in this process, indexed by ID
+    identifiers: Mutex<Vec<String>>,
+    /// The co-occurrences read on startup, until the counter takes them
+    hydrated_pairs: Mutex<Option<PairState>>,
+    /// The buckets as last read from the database
+    buckets: Mutex<Vec<Vec<Bucket>>>,
+}
+
+impl fmt::Debug for PostgresStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostgresStore").field("depths", &self.depths).finish()
+    }
+}
+
+/// Adds to a count, saturating instead of failing on overflow.
+fn saturating_add(table: &str) -> String {
+    format!("LEAST({}.count::numeric + EXCLUDED.count, 9223372036854775807)::bigint", table)
+}
+
+/// Applies the migrations the database hasn't seen yet. Instances starting at the same
+/// time wait for each other, so every migration runs once. Returns how many were applied.
+async fn migrate(pool: &PgPool) -> Result<usize, sqlx::Error> {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    )
+    .execute(pool)
+    .await?;
+    let mut transaction = pool.begin().await?;
+    sqlx::raw_sql("LOCK TABLE schema_migrations IN EXCLUSIVE MODE").execute(&mut *transaction).await?;
+    let (version,): (i32,) =
+        sqlx::query_as("SELECT COALESCE(MAX(version), 0) FROM schema_migrations").fetch_one(&mut *transaction).await?;
+    let version = version.max(0) as usize;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        sqlx::raw_sql(migration).execute(&mut *transaction).await?;
+        sqlx::query("INSERT INTO schema_migrations (version) VALUES ($1)")
+            .bind(index as i32 + 1)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(MIGRATIONS.len().saturating_sub(version))
+}
+
+/// Adds counts to the bucket at `position` of the given series, in one statement.
+async fn add_counts(
+    transaction: &mut Transaction<'_, Postgres>,
+    position: usize,
+    rows: &[(&str, &str, u64)],
+) -> Result<(), sqlx::Error> {
+    let (mut series, mut identifiers, mut counts) = (Vec::new(), Vec::new(), Vec::new());
+    for &(name, identifier, count) in rows {
+        series.push(name);
+        identifiers.push(identifier);
+        counts.push(signed_count(count));
+    }
+    if series.is_empty() {
+        return Ok(());
+    }
+    sqlx::query(&format!(
+        "INSERT INTO counter_buckets (series, position, identifier, count)
+         SELECT series, $1, identifier, count FROM UNNEST($2::text[], $3::text[], $4::bigint[]) AS batch (series, identifier, count)
+         ON CONFLICT (series, position, identifier) DO UPDATE SET count = {}",
+        saturating_add("counter_buckets")
+    ))
+    .bind(position as i32)
+    .bind(series)
+    .bind(identifiers)
+    .bind(counts)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+impl PostgresStore {
+    /// Connects to the database, brings its schema up to date and reads the stored state.
+    pub async fn connect(url: &str, settings: &StorageSettings, counters: &CounterSettings) -> Result<Self, String> {
+        let pool = PgPoolOptions::new()
+            .max_connections(settings.postgres_max_connections.max(1))
+            .connect(url)
+            .await
+            .map_err(|e| e.to_string())?;
+        let migrated = migrate(&pool).await.map_err(|e| format!("Migration failed: {}", e))?;
+        if migrated > 0 {
+            info!("Applied {} schema migrations to PostgreSQL.", migrated);
+        }
+        let store = PostgresStore {
+            pool,
+            depths: [counters.hourly_buckets, counters.daily_buckets, counters.weekly_buckets, counters.monthly_buckets]
+                .map(|depth| depth.max(1)),
+            pending: Mutex::new(Pending::default()),
+            identifiers: Mutex::new(Vec::new()),
+            hydrated_pairs: Mutex::new(None),
+            buckets: Mutex::new(Vec::new()),
+        };
+        store.hydrate_pairs().await.map_err(|e| e.to_string())?;
+        *locks::lock(&store.buckets, "postgres_buckets") = store.fetch_buckets().await.map_err(|e| e.to_string())?;
+        Ok(store)
+    }
+
+    fn depth(&self, granularity: Granularity) -> usize {
+        self.depths[Granularity::ALL.iter().position(|&g| g == granularity).unwrap_or_default()]
+    }
+
+    /// Reads the co-occurrences, numbering the identifiers in the order they come up.
+    async fn hydrate_pairs(&self) -> Result<(), sqlx::Error> {
+        let rows: Vec<(String, String, i64)> = sqlx::query_as("SELECT first, second, count FROM pairs").fetch_all(&self.pool).await?;
+        let mut ids: HashMap<String, u32> = HashMap::new();
+        let mut names = Vec::new();
+        let mut id_of = |identifier: String| {
+            *ids.entry(identifier).or_insert_with_key(|identifier| {
+                names.push(identifier.clone());
+                (names.len() - 1) as u32
+            })
+        };
+        let pairs: Vec<((u32, u32), u64)> = rows
+            .into_iter()
+            .map(|(first, second, count)| {
+                let (id1, id2) = (id_of(first), id_of(second));
+                ((id1.min(id2), id1.max(id2)), count.max(0) as u64)
+            })
+            .collect();
+        let identifiers = names.iter().enumerate().map(|(id, name)| (name.clone(), id as u32)).collect();
+        *locks::lock(&self.identifiers, "postgres_identifiers") = names;
+        *locks::lock(&self.hydrated_pairs, "postgres_hydrated_pairs") = Some((identifiers, pairs));
+        Ok(())
+    }
+
+    /// Reads all buckets, in the order of `Granularity::ALL`.
+    async fn fetch_buckets(&self) -> Result<Vec<Vec<Bucket>>, sqlx::Error> {
+        let rows: Vec<(String, i32, String, i64)> =
+            sqlx::query_as("SELECT series, position, identifier, count FROM counter_buckets").fetch_all(&self.pool).await?;
+        let buckets: Vec<Vec<Bucket>> =
+            Granularity::ALL.into_iter().map(|granularity| vec![Bucket::new(); self.depth(granularity)]).collect();
+        for (series, position, identifier, count) in rows {
+            let Some(granularity) = Granularity::ALL.into_iter().position(|g| g.series_name() == series) else {
+                continue;
+            };
+            // Buckets beyond the configured depth are left out, like in `Counters::resize`
+            if let Some(bucket) = buckets[granularity].get(position.max(0) as usize) {
+                bucket.insert(identifier, count.max(0) as u64);
+            }
+        }
+        Ok(buckets)
+    }
+
+    fn queue(&self, write: Write) {
+        locks::lock(&self.pending, "postgres_pending").writes.push(write);
+    }
+
+    /// Writes all queued changes in one transaction. If that fails, they stay queued for
+    /// the next attempt.
+    pub async fn flush(&self) -> Result<(), String> {
+        let pending = std::mem::take(&mut *locks::lock(&self.pending, "postgres_pending"));
+        if pending.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = self.write(&pending).await {
+            locks::lock(&self.pending, "postgres_pending").requeue(pending);
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+
+    async fn write(&self, pending: &Pending) -> Result<(), sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        for write in &pending.writes {
+            match write {
+                Write::Increments(increments) => {
+                    let mut rows = Vec::new();
+                    for granularity in Granularity::ALL {
+                        rows.extend(increments.iter().map(|(id, &count)| (granularity.series_name(), id.as_str(), count)));
+                    }
+                    add_counts(&mut transaction, 0, &rows).await?;
+                }
+                Write::Add { granularity, index, counts } => {
+                    let rows: Vec<_> = counts.iter().map(|(id, count)| (granularity.series_name(), id.as_str(), *count)).collect();
+                    add_counts(&mut transaction, *index, &rows).await?;
+                }
+                Write::Rotate { granularity, steps, period } => {
+                    let series = granularity.series_name();
+                    // Claims the rotation into `period`; other instances find it claimed
+                    let claimed = sqlx::query(
+                        "INSERT INTO counter_periods (series, period) VALUES ($1, $2)
+                         ON CONFLICT (series) DO UPDATE SET period = EXCLUDED.period
+                         WHERE counter_periods.period <> EXCLUDED.period
+                         RETURNING series",
+                    )
+                    .bind(series)
+                    .bind(period)
+                    .fetch_optional(&mut *transaction)
+                    .await?;
+                    if claimed.is_none() {
+                        continue;
+                    }
+                    sqlx::query("DELETE FROM counter_buckets WHERE series = $1 AND position + $2 >= $3")
+                        .bind(series)
+                        .bind(*steps as i32)
+                        .bind(self.depth(*granularity) as i32)
+                        .execute(&mut *transaction)
+                        .await?;
+                    // Through negative positions, as shifting in place would collide with
+                    // the rows not shifted yet
+                    sqlx::query("UPDATE counter_buckets SET position = -1 - (position + $2) WHERE series = $1")
+                        .bind(series)
+                        .bind(*steps as i32)
+                        .execute(&mut *transaction)
+                        .await?;
+                    sqlx::query("UPDATE counter_buckets SET position = -1 - position WHERE series = $1")
+                        .bind(series)
+                        .execute(&mut *transaction)
+                        .await?;
+                }
+                Write::Remove(id) => {
+                    sqlx::query("DELETE FROM counter_buckets WHERE identifier = $1").bind(id).execute(&mut *transaction).await?;
+                }
+                Write::Reset => {
+                    sqlx::query("DELETE FROM counter_buckets").execute(&mut *transaction).await?;
+                }
+            }
+        }
+        if !pending.pairs.is_empty() {
+            let (mut firsts, mut seconds, mut counts) = (Vec::new(), Vec::new(), Vec::new());
+            for ((first, second), &count) in &pending.pairs {
+                firsts.push(first.as_str());
+                seconds.push(second.as_str());
+                counts.push(signed_count(count));
+            }
+            sqlx::query(&format!(
+                "INSERT INTO pairs (first, second, count)
+                 SELECT * FROM UNNEST($1::text[], $2::text[], $3::bigint[])
+                 ON CONFLICT (first, second) DO UPDATE SET count = {}",
+                saturating_add("pairs")
+            ))
+            .bind(firsts)
+            .bind(seconds)
+            .bind(counts)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await
+    }
+}
+
+impl CounterStore for PostgresStore {
+    fn increment(&self, id: &str, amount: u64) -> Result<(), String> {
+        let mut pending = locks::lock(&self.pending, "postgres_pending");
+        if !matches!(pending.writes.last(), Some(Write::Increments(_))) {
+            pending.writes.push(Write::Increments(HashMap::new()));
+        }
+        if let Some(Write::Increments(increments)) = pending.writes.last_mut() {
+            let total = increments.entry(id.to_string()).or_insert(0);
+            *total = total.saturating_add(amount);
+        }
+        Ok(())
+    }
+
+    fn add(&self, granularity: Granularity, index: usize, bucket: &Bucket) -> Result<(), String> {
+        if index < self.depth(granularity) && !bucket.is_empty() {
+            let counts = bucket.iter().map(|entry| (entry.key().clone(), *entry.value())).collect();
+            self.queue(Write::Add { granularity, index, counts });
+        }
+        Ok(())
+    }
+
+    /// Only queues the rotation, so it always reports `true`; whether this or another
+    /// instance shifts the stored buckets is decided when it is written.
+    fn rotate(&self, granularity: Granularity, steps: usize, period: &str) -> Result<bool, String> {
+        self.queue(Write::Rotate { granularity, steps, period: period.to_string() });
+        Ok(true)
+    }
+
+    fn remove(&self, id: &str) -> Result<(), String> {
+        self.queue(Write::Remove(id.to_string()));
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), String> {
+        self.queue(Write::Reset);
+        Ok(())
+    }
+
+    /// Returns the buckets as last read from the database, plus the increments not written
+    /// yet, so local counts don't drop until the next batch.
+    fn load(&self) -> Result<Vec<Vec<Bucket>>, String> {
+        let buckets = locks::lock(&self.buckets, "postgres_buckets").clone();
+        for write in &locks::lock(&self.pending, "postgres_pending").writes {
+            if let Write::Increments(increments) = write {
+                for (id, &count) in increments {
+                    for current in buckets.iter().filter_map(|buckets| buckets.first()) {
+                        let mut total = current.entry(id.clone()).or_insert(0);
+                        *total = total.saturating_add(count);
+                    }
+                }
+            }
+        }
+        Ok(buckets)
+    }
+
+    fn is_durable(&self) -> bool {
+        true
+    }
+}
+
+impl PairStore for PostgresStore {
+    fn add_list(&self, new_identifiers: &[(String, u32)], pairs: &[(u32, u32)], weight: u64) -> Result<(), String> {
+        let mut identifiers = locks::lock(&self.identifiers, "postgres_identifiers");
+        for (identifier, id) in new_identifiers {
+            let id = *id as usize;
+            if identifiers.len() <= id {
+                identifiers.resize(id + 1, String::new());
+            }
+            identifiers[id] = identifier.clone();
+        }
+        let mut pending = locks::lock(&self.pending, "postgres_pending");
+        for &(id1, id2) in pairs {
+            let (first, second) = (&identifiers[id1 as usize], &identifiers[id2 as usize]);
+            let pair = if first <= second { (first.clone(), second.clone()) } else { (second.clone(), first.clone()) };
+            *pending.pairs.entry(pair).or_insert(0) += weight;
+        }
+        Ok(())
+    }
+
+    fn load_pairs(&self) -> Result<PairState, String> {
+        Ok(locks::lock(&self.hydrated_pairs, "postgres_hydrated_pairs").take().unwrap_or_default())
+    }
+}
+
+// Function to write the queued changes to PostgreSQL in batches, and to reload the
+// counters written by all instances every sync interval
+pub async fn run_postgres_flush(store: Arc<PostgresStore>, counters: Arc<RwLock<Counters>>, flush_interval: Duration, sync_interval: Duration) {
+    info!("PostgreSQL writer started.");
+    let mut last_synced_at = Instant::now();
+
+    loop {
+        tokio::time::sleep(flush_interval).await;
+        if let Err(e) = store.flush().await {
+            warn!("Failed to write to PostgreSQL, retrying with the next batch: {}", e);
+            continue;
+        }
+        // Right after a batch, so the buckets read include this instance's changes
+        if last_synced_at.elapsed() >= sync_interval && counters_use(&counters, &store) {
+            match store.fetch_buckets().await {
+                Ok(buckets) => {
+                    *locks::lock(&store.buckets, "postgres_buckets") = buckets;
+                    if let Ok(buckets) = store.load() {
+                        locks::write(&counters, "rotating_counters").replace_buckets(buckets);
+                    }
+                }
+                Err(e) => warn!("Failed to read counters from PostgreSQL: {}", e),
+            }
+            last_synced_at = Instant::now();
+        }
+    }
+}
+
+/// Whether the counters are kept in `store`, rather than e.g. in Redis.
+fn counters_use(counters: &RwLock<Counters>, store: &Arc<PostgresStore>) -> bool {
+    locks::read(counters, "rotating_counters")
+        .store()
+        .is_some_and(|counter_store| std::ptr::addr_eq(Arc::as_ptr(&counter_store), Arc::as_ptr(store)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_batches_are_requeued_in_order() {
+        let mut pending = Pending::default();
+        pending.writes.push(Write::Reset);
+        pending.pairs.insert(("a".to_string(), "b".to_string()), 2);
+
+        let mut failed = Pending::default();
+        failed.writes.push(Write::Remove("x".to_string()));
+        failed.pairs.insert(("a".to_string(), "b".to_string()), 3);
+        pending.requeue(failed);
+
+        assert!(matches!(pending.writes[..], [Write::Remove(_), Write::Reset]));
+        assert_eq!(pending.pairs[&("a".to_string(), "b".to_string())], 5);
+    }
+}
@@ -0,0 +1,203 @@
+// src/algorithms/quality.rs
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::algorithms::co_occurrence::Neighborhoods;
+use crate::algorithms::CoOccurrenceCounter;
+use crate::config::QualitySettings;
+use crate::{determinism, locks};
+
+/// How long the neighbor lists are compared against for the churn.
+const CHURN_PERIOD_HOURS: i64 = 24;
+
+/// Health of the co-occurrence recommendations, as of one evaluation.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QualityReport {
+    pub evaluated_at: DateTime<Utc>,
+    /// Number of identifiers evaluated
+    pub identifiers: usize,
+    /// Share of the identifiers with at least `min_neighbors` co-occurring ones
+    pub coverage: f64,
+    pub min_neighbors: usize,
+    /// Average number of lists of the neighbors recommended for the sampled identifiers,
+    /// relative to that of all identifiers; above 1 if popular items are favored
+    pub popularity_bias: f64,
+    /// Share of the neighbors recommended for the sampled identifiers that weren't a day
+    /// earlier; unknown until the evaluations span a day
+    pub churn: Option<f64>,
+    /// Start of the day the churn was measured over
+    pub churn_since: Option<DateTime<Utc>>,
+    /// Number of most frequent identifiers whose neighbor lists were evaluated
+    pub sampled_identifiers: usize,
+}
+
+/// The neighbor lists of the sampled identifiers, by identifier.
+type NeighborLists = HashMap<String, Vec<String>>;
+
+/// The latest quality report, and the neighbor lists the churn is measured against.
+#[derive(Debug, Default)]
+pub struct QualityMonitor {
+    report: Option<QualityReport>,
+    /// The neighbor lists of the sampled identifiers at the start of the current day
+    baseline: Option<(DateTime<Utc>, NeighborLists)>,
+    /// The churn over the last full day, with its start
+    churn: Option<(DateTime<Utc>, f64)>,
+}
+
+impl QualityMonitor {
+    pub fn new() -> Self {
+        QualityMonitor::default()
+    }
+
+    /// The latest report, `None` before the first evaluation.
+    pub fn report(&self) -> Option<&QualityReport> {
+        self.report.as_ref()
+    }
+
+    /// Evaluates `neighborhoods` as of `now`. Once the baseline is a day old, the churn
+    /// is measured against it, and the current neighbor lists become the next baseline.
+    pub fn update(&mut self, neighborhoods: Neighborhoods, now: DateTime<Utc>, settings: &QualitySettings) -> &QualityReport {
+        let lists: NeighborLists = neighborhoods
+            .sampled
+            .iter()
+            .map(|(identifier, neighbors)| (identifier.clone(), neighbors.iter().map(|(neighbor, _)| neighbor.clone()).collect()))
+            .collect();
+        match self.baseline.take() {
+            Some((since, baseline)) if now - since >= chrono::Duration::hours(CHURN_PERIOD_HOURS) => {
+                self.churn = churn(&baseline, &lists).map(|churn| (since, churn));
+                self.baseline = Some((now, lists));
+            }
+            Some(baseline) => self.baseline = Some(baseline),
+            None => self.baseline = Some((now, lists)),
+        }
+
+        self.report.insert(QualityReport {
+            evaluated_at: now,
+            identifiers: neighborhoods.neighbor_counts.len(),
+            coverage: coverage(&neighborhoods.neighbor_counts, settings.min_neighbors),
+            min_neighbors: settings.min_neighbors,
+            popularity_bias: popularity_bias(&neighborhoods),
+            churn: self.churn.map(|(_, churn)| churn),
+            churn_since: self.churn.map(|(since, _)| since),
+            sampled_identifiers: neighborhoods.sampled.len(),
+        })
+    }
+}
+
+/// Share of the identifiers with at least `min_neighbors` neighbors; 0 without identifiers.
+fn coverage(neighbor_counts: &[usize], min_neighbors: usize) -> f64 {
+    if neighbor_counts.is_empty() {
+        return 0.0;
+    }
+    neighbor_counts.iter().filter(|&&count| count >= min_neighbors).count() as f64 / neighbor_counts.len() as f64
+}
+
+/// Average lists of the recommended neighbors over the average lists of all identifiers;
+/// 0 if nothing is recommended.
+fn popularity_bias(neighborhoods: &Neighborhoods) -> f64 {
+    let recommended: Vec<u64> = neighborhoods.sampled.iter().flat_map(|(_, neighbors)| neighbors.iter().map(|&(_, lists)| lists)).collect();
+    let catalog = neighborhoods.occurrences.iter().map(|&lists| lists as f64).sum::<f64>() / neighborhoods.occurrences.len().max(1) as f64;
+    if recommended.is_empty() || catalog == 0.0 {
+        return 0.0;
+    }
+    let average = recommended.iter().map(|&lists| lists as f64).sum::<f64>() / recommended.len() as f64;
+    average / catalog
+}
+
+/// Average share of the neighbors in `current` that weren't in `baseline`, over the
+/// identifiers with neighbors in both; `None` if there are none.
+fn churn(baseline: &NeighborLists, current: &NeighborLists) -> Option<f64> {
+    let shares: Vec<f64> = current
+        .iter()
+        .filter(|(_, neighbors)| !neighbors.is_empty())
+        .filter_map(|(identifier, neighbors)| {
+            let before: HashSet<&String> = baseline.get(identifier).filter(|before| !before.is_empty())?.iter().collect();
+            let new = neighbors.iter().filter(|neighbor| !before.contains(neighbor)).count();
+            Some(new as f64 / neighbors.len() as f64)
+        })
+        .collect();
+    (!shares.is_empty()).then(|| shares.iter().sum::<f64>() / shares.len() as f64)
+}
+
+// Function to periodically evaluate the quality of the co-occurrence recommendations
+pub async fn run_quality_evaluation(
+    co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    monitor: Arc<Mutex<QualityMonitor>>,
+    settings: QualitySettings,
+) {
+    info!("Quality evaluation thread started.");
+
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(settings.interval_secs)).await;
+
+        let co_occurrence = co_occurrence.clone();
+        let monitor = monitor.clone();
+        let settings = settings.clone();
+
+        // Reads all pairs, so it runs on the blocking thread pool. Only shared access
+        // is needed, so lookups go on meanwhile.
+        let result = web::block(move || {
+            let neighborhoods = locks::read(&co_occurrence, "co_occurrence").neighborhoods(settings.sample_size, settings.neighbors)?;
+            let report = locks::lock(&monitor, "quality_monitor").update(neighborhoods, determinism::now(), &settings).clone();
+            Ok::<_, String>(report)
+        })
+        .await;
+
+        match result {
+            Ok(Ok(report)) => {
+                info!(coverage = report.coverage, popularity_bias = report.popularity_bias, churn = ?report.churn, "Evaluated the recommendation quality.");
+            }
+            Ok(Err(e)) => {
+                // Won't change until a restart
+                warn!("Stopped evaluating the recommendation quality: {}", e);
+                return;
+            }
+            Err(e) => {
+                error!("Error in quality evaluation block: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn neighborhoods(sampled: &[(&str, &[(&str, u64)])]) -> Neighborhoods {
+        Neighborhoods {
+            neighbor_counts: vec![0, 1, 5, 7],
+            occurrences: vec![10, 10, 20, 40],
+            sampled: sampled
+                .iter()
+                .map(|(identifier, neighbors)| (identifier.to_string(), neighbors.iter().map(|&(neighbor, lists)| (neighbor.to_string(), lists)).collect()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_churn_is_measured_against_the_day_before() {
+        let settings = QualitySettings { interval_secs: 3600, min_neighbors: 5, neighbors: 2, sample_size: 10 };
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut monitor = QualityMonitor::new();
+
+        let report = monitor.update(neighborhoods(&[("ard:a", &[("ard:b", 40), ("ard:c", 20)])]), start, &settings).clone();
+        assert_eq!(report.coverage, 0.5);
+        assert_eq!(report.popularity_bias, 30.0 / 20.0);
+        assert_eq!(report.churn, None);
+
+        let later = start + chrono::Duration::hours(12);
+        let report = monitor.update(neighborhoods(&[("ard:a", &[("ard:b", 40), ("ard:d", 10)])]), later, &settings).clone();
+        assert_eq!(report.churn, None);
+
+        let next_day = start + chrono::Duration::hours(25);
+        let report = monitor.update(neighborhoods(&[("ard:a", &[("ard:b", 40), ("ard:d", 10)]), ("ard:x", &[("ard:a", 10)])]), next_day, &settings).clone();
+        assert_eq!(report.churn, Some(0.5));
+        assert_eq!(report.churn_since, Some(start));
+        assert_eq!(report.sampled_identifiers, 2);
+    }
+}
@@ -0,0 +1,54 @@
+// src/algorithms/recent_lists.rs
+use std::collections::VecDeque;
+
+/// A bounded buffer of the most recently ingested lists.
+///
+/// The counting models only keep aggregated counts, but offline passes (like rule
+/// mining) need the raw lists. Once `capacity` is reached, the oldest list is dropped.
+#[derive(Debug)]
+pub struct RecentLists {
+    lists: VecDeque<Vec<String>>,
+    capacity: usize,
+}
+
+impl RecentLists {
+    /// Creates a new, empty buffer holding at most `capacity` lists.
+    pub fn new(capacity: usize) -> Self {
+        RecentLists {
+            lists: VecDeque::with_capacity(capacity.min(1024)),
+            capacity,
+        }
+    }
+
+    /// Appends a list, evicting the oldest one if the buffer is full.
+    pub fn push<S: AsRef<str>>(&mut self, identifiers: &[S]) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lists.len() == self.capacity {
+            self.lists.pop_front();
+        }
+        self.lists.push_back(identifiers.iter().map(|identifier| identifier.as_ref().to_string()).collect());
+    }
+
+    /// Returns a copy of all buffered lists, oldest first, so they can be processed
+    /// without holding the lock.
+    pub fn snapshot(&self) -> Vec<Vec<String>> {
+        self.lists.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_lists_are_evicted() {
+        let mut recent = RecentLists::new(2);
+        recent.push(&["a".to_string()]);
+        recent.push(&["b".to_string()]);
+        recent.push(&["c".to_string()]);
+
+        assert_eq!(recent.snapshot(), vec![vec!["b".to_string()], vec!["c".to_string()]]);
+    }
+}
@@ -0,0 +1,315 @@
+// src/algorithms/replication.rs
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use actix_web::web::{self, Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+use crate::algorithms::event_log::{is_unit_weight, unit_weight};
+use crate::algorithms::{backup, CoOccurrenceCounter, Counters};
+use crate::config::ReplicationSettings;
+use crate::determinism;
+use crate::locks;
+
+// Primary-replica replication of the default state. A replica requests the primary's
+// change stream (GET /admin/replication/stream), which starts with a backup archive of
+// the whole state (see `backup`) taken while no change can slip in, followed by every
+// change applied afterwards. The stream is a sequence of frames, each a 4 byte big-endian
+// length and as many bytes: the archive first, then one JSON-encoded `Change` per frame.
+// Empty frames are heartbeats. Whenever the primary's state changes in a way that isn't
+// streamed, or a replica falls behind by more than the buffer, the stream ends and the
+// replica loads the whole state again.
+
+/// Path of the change stream on the primary.
+pub const STREAM_PATH: &str = "/v1/admin/replication/stream";
+/// How long a replica waits before connecting again after a failure; doubled after every
+/// failure up to `MAX_RETRY_DELAY`.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// A change to the default state, as streamed to replicas.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Change {
+    List {
+        identifiers: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        times: Option<Vec<i64>>,
+        #[serde(default = "unit_weight", skip_serializing_if = "is_unit_weight")]
+        weight: u64,
+    },
+    Increment { id: String, count: u64 },
+    Remove { id: String },
+    Reset,
+    /// The counters were rotated up to `at`
+    Rotate { at: DateTime<Utc> },
+    /// The state changed in a way that isn't streamed, e.g. by a purge or restore, so
+    /// replicas have to load it again
+    Resync,
+}
+
+/// Broadcasts the changes of the default state to the subscribed replicas.
+#[derive(Debug)]
+pub struct ChangeFeed {
+    sender: broadcast::Sender<Arc<Change>>,
+}
+
+impl ChangeFeed {
+    /// Creates a feed buffering up to `capacity` changes per subscriber.
+    pub fn new(capacity: usize) -> Self {
+        ChangeFeed { sender: broadcast::channel(capacity.max(1)).0 }
+    }
+
+    /// Sends a change to all subscribers. Without any, the change isn't even built.
+    pub fn publish(&self, change: impl FnOnce() -> Change) {
+        if self.sender.receiver_count() > 0 {
+            let _ = self.sender.send(Arc::new(change()));
+        }
+    }
+
+    /// Subscribes to the changes published from now on. Only consistent with a copy of
+    /// the state if both are taken under the same locks (see `subscribe_with_state`).
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Change>> {
+        self.sender.subscribe()
+    }
+}
+
+/// Takes a backup archive of the state and subscribes to the changes following it. The
+/// counters are write-locked, so no increment (which only takes a read lock) falls
+/// between the two.
+pub fn subscribe_with_state(
+    co_occurrence: &RwLock<CoOccurrenceCounter>,
+    counters: &RwLock<Counters>,
+    feed: &ChangeFeed,
+) -> Result<(Vec<u8>, broadcast::Receiver<Arc<Change>>), String> {
+    let co_occurrence = locks::read(co_occurrence, "co_occurrence");
+    let counters = locks::write(counters, "rotating_counters");
+    let (_, archive) = backup::encode(&co_occurrence, &counters)?;
+    Ok((archive, feed.subscribe()))
+}
+
+/// Prefixes a frame with its length.
+pub fn frame(payload: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame.freeze()
+}
+
+/// Splits received bytes into frames, whatever chunks they arrive in.
+#[derive(Debug, Default)]
+struct FrameReader {
+    buffer: BytesMut,
+}
+
+impl FrameReader {
+    fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Returns the next complete frame, if one was received.
+    fn next_frame(&mut self) -> Option<Bytes> {
+        let length = u32::from_be_bytes(self.buffer.get(..4)?.try_into().ok()?) as usize;
+        if self.buffer.len() < 4 + length {
+            return None;
+        }
+        let _ = self.buffer.split_to(4);
+        Some(self.buffer.split_to(length).freeze())
+    }
+}
+
+/// Whether this instance follows a primary, and how far it got. Shared by the follower
+/// task, the write guard and the admin endpoints.
+#[derive(Debug, Default)]
+pub struct ReplicationState {
+    following: AtomicBool,
+    connected: AtomicBool,
+    applied: AtomicU64,
+}
+
+impl ReplicationState {
+    pub fn new(following: bool) -> Self {
+        ReplicationState { following: AtomicBool::new(following), ..ReplicationState::default() }
+    }
+
+    /// Whether this instance is a replica, which rejects writes.
+    pub fn is_following(&self) -> bool {
+        self.following.load(Ordering::Relaxed)
+    }
+
+    /// Whether a replica currently receives the primary's changes.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Number of changes applied since the replica last loaded the whole state.
+    pub fn applied(&self) -> u64 {
+        self.applied.load(Ordering::Relaxed)
+    }
+
+    /// Stops following the primary. Returns whether this instance was a replica.
+    pub fn promote(&self) -> bool {
+        self.connected.store(false, Ordering::Relaxed);
+        self.following.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Applies a streamed change. Returns false for `Resync`, which ends the stream.
+pub fn apply(change: Change, co_occurrence: &RwLock<CoOccurrenceCounter>, counters: &RwLock<Counters>, timezone: &Tz) -> bool {
+    match change {
+        Change::List { identifiers, times, weight } => {
+            locks::write(co_occurrence, "co_occurrence").process_timed_list(&identifiers, times.as_deref(), weight)
+        }
+        Change::Increment { id, count } => locks::read(counters, "rotating_counters").increment(&id, count),
+        Change::Remove { id } => {
+            locks::write(counters, "rotating_counters").remove(&id);
+        }
+        Change::Reset => locks::write(counters, "rotating_counters").reset(),
+        Change::Rotate { at } => {
+            locks::write(counters, "rotating_counters").advance_to(&at.with_timezone(timezone));
+        }
+        Change::Resync => return false,
+    }
+    true
+}
+
+/// Follows the change stream until it ends. Returns an error for streams that couldn't
+/// be opened or broke off.
+async fn follow(
+    client: &awc::Client,
+    co_occurrence: &Arc<RwLock<CoOccurrenceCounter>>,
+    counters: &Arc<RwLock<Counters>>,
+    timezone: Tz,
+    settings: &ReplicationSettings,
+    state: &ReplicationState,
+) -> Result<(), String> {
+    let primary_url = settings.primary_url.as_deref().unwrap_or_default();
+    let mut request = client.get(format!("{}{}", primary_url.trim_end_matches('/'), STREAM_PATH)).timeout(Duration::MAX);
+    if let Some(token) = &settings.primary_token {
+        request = request.bearer_auth(token);
+    }
+    let mut response = request.send().await.map_err(|e| format!("Failed to connect to the primary: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("The primary responded with status {}", response.status()));
+    }
+
+    // Twice the heartbeat interval, so one late heartbeat doesn't end the stream
+    let idle_timeout = Duration::from_secs(settings.heartbeat_secs * 2);
+    let mut reader = FrameReader::default();
+    let mut bootstrapped = false;
+    loop {
+        while let Some(payload) = reader.next_frame() {
+            if payload.is_empty() || !state.is_following() {
+                continue;
+            }
+            if !bootstrapped {
+                let (co_occurrence, counters) = (Arc::clone(co_occurrence), Arc::clone(counters));
+                let metadata = web::block(move || {
+                    let metadata = backup::restore(&payload, &co_occurrence, &counters, timezone)?;
+                    locks::write(&counters, "rotating_counters").persist();
+                    Ok::<_, String>(metadata)
+                })
+                .await
+                .map_err(|e| e.to_string())??;
+                info!("Loaded the primary's state ({} identifiers, {} pairs).", metadata.identifiers, metadata.pairs);
+                bootstrapped = true;
+                state.applied.store(0, Ordering::Relaxed);
+                state.connected.store(true, Ordering::Relaxed);
+                continue;
+            }
+            let change: Change = serde_json::from_slice(&payload).map_err(|e| format!("Invalid change: {}", e))?;
+            let (co_occurrence, counters) = (Arc::clone(co_occurrence), Arc::clone(counters));
+            if !web::block(move || apply(change, &co_occurrence, &counters, &timezone)).await.map_err(|e| e.to_string())? {
+                info!("The primary asked for a resync.");
+                return Ok(());
+            }
+            state.applied.fetch_add(1, Ordering::Relaxed);
+        }
+        if !state.is_following() {
+            return Ok(());
+        }
+        match tokio::time::timeout(idle_timeout, response.next()).await {
+            Ok(Some(Ok(chunk))) => reader.push(&chunk),
+            Ok(Some(Err(e))) => return Err(format!("The change stream broke off: {}", e)),
+            Ok(None) => return Ok(()),
+            Err(_) => return Err("The primary stopped sending heartbeats".to_string()),
+        }
+    }
+}
+
+// Function to follow the primary's change stream while this instance is a replica,
+// connecting again whenever the stream ends.
+// Must be spawned on the actix runtime, since the HTTP client is not `Send`.
+pub async fn run_replication(
+    co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    counters: Arc<RwLock<Counters>>,
+    timezone: Tz,
+    settings: ReplicationSettings,
+    state: Arc<ReplicationState>,
+) {
+    info!("Replication thread started, following {}.", settings.primary_url.as_deref().unwrap_or_default());
+    let client = awc::Client::default();
+    let mut retry_delay = MIN_RETRY_DELAY;
+
+    while state.is_following() {
+        locks::write(&counters, "rotating_counters").set_following(true);
+        let result = follow(&client, &co_occurrence, &counters, timezone, &settings, &state).await;
+        let was_connected = state.connected.swap(false, Ordering::Relaxed);
+        match result {
+            Ok(()) => retry_delay = MIN_RETRY_DELAY,
+            Err(e) => {
+                if was_connected {
+                    retry_delay = MIN_RETRY_DELAY;
+                }
+                warn!("{}; retrying in {} s.", e, retry_delay.as_secs());
+                tokio::time::sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+    }
+    locks::write(&counters, "rotating_counters").set_following(false);
+    info!("Promoted to primary, stopped following {}.", settings.primary_url.as_deref().unwrap_or_default());
+    if let Err(e) = web::block(move || locks::write(&counters, "rotating_counters").advance_to(&determinism::now().with_timezone(&timezone))).await {
+        error!("Failed to rotate the counters after the promotion: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_are_split_across_chunks() {
+        let stream = [frame(b"archive"), frame(b""), frame(br#"{"op":"reset"}"#)].concat();
+        let mut reader = FrameReader::default();
+        let mut frames = Vec::new();
+        for chunk in stream.chunks(3) {
+            reader.push(chunk);
+            frames.extend(std::iter::from_fn(|| reader.next_frame()));
+        }
+        assert_eq!(frames, [&b"archive"[..], b"", br#"{"op":"reset"}"#]);
+        assert_eq!(serde_json::from_slice::<Change>(&frames[2]).unwrap(), Change::Reset);
+    }
+
+    #[test]
+    fn test_subscribers_receive_the_changes_after_the_archive() {
+        let co_occurrence = RwLock::new(CoOccurrenceCounter::new());
+        let counters = RwLock::new(Counters::with_depths(3, 3, 1, 1));
+        let feed = Arc::new(ChangeFeed::new(16));
+        locks::write(&co_occurrence, "co_occurrence").attach_change_feed(Arc::clone(&feed));
+        locks::write(&counters, "rotating_counters").attach_change_feed(Arc::clone(&feed));
+        locks::read(&counters, "rotating_counters").increment("a", 2);
+
+        let (_, mut receiver) = subscribe_with_state(&co_occurrence, &counters, &feed).unwrap();
+        locks::write(&co_occurrence, "co_occurrence").process_list(&["a".to_string(), "b".to_string()]);
+        locks::read(&counters, "rotating_counters").increment("b", 1);
+        assert_eq!(*receiver.try_recv().unwrap(), Change::List { identifiers: vec!["a".to_string(), "b".to_string()], times: None, weight: 1 });
+        assert_eq!(*receiver.try_recv().unwrap(), Change::Increment { id: "b".to_string(), count: 1 });
+        assert!(receiver.try_recv().is_err());
+    }
+}
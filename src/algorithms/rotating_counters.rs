@@ -1,186 +1,1984 @@
 // src/algorithms/rotating_counters.rs
-use std::sync::{Arc, Mutex}; // Ensure these are imported at the top of this file
+use std::sync::{Arc, Mutex, RwLock}; // Ensure these are imported at the top of this file
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs;
+use std::io;
+use std::path::Path;
+use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
-use chrono::{Local, Timelike, Datelike};
+use utoipa::ToSchema;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use actix_web::{web};
+use tracing::{error, info, warn};
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+use crate::algorithms::counter_store::CounterStore;
+use crate::algorithms::event_log::{read_entries, CounterEvent, EventLog};
+use crate::algorithms::gossip::GossipLedger;
+use crate::algorithms::minute_counters::MinuteCounters;
+use crate::algorithms::namespace_rollups::{NamespacePlays, NamespaceRollups};
+use crate::algorithms::popularity::DecayedPopularity;
+use crate::algorithms::replication::{Change, ChangeFeed};
+use crate::algorithms::state_store::{self, FileStore, StateStore};
+use crate::config::{CounterBackend, CounterSettings, SnapshotSettings, StorageSettings};
+use crate::determinism;
+use crate::locks;
+use crate::memory;
+use crate::stats;
+
+pub const SNAPSHOT_PATH: &str = "rotating_counters.json";
+const EVENT_LOG_PATH: &str = "rotating_counters.log";
+
+/// Rolling windows summed from the hourly or daily buckets, with the number of buckets
+/// they cover (including the current hour or day). They are only available if enough
+/// buckets of their granularity are kept.
+const ROLLING_WINDOWS: [(&str, Granularity, usize); 4] = [
+    ("last_24h", Granularity::Hour, 24),
+    ("last_48h", Granularity::Hour, 48),
+    ("last_7d", Granularity::Day, 7),
+    ("last_12d", Granularity::Day, 12),
+];
+
+/// A single counter bucket: identifier -> count. The map is sharded internally, so
+/// increments of different identifiers don't contend with each other.
+pub type Bucket = DashMap<String, u64>;
+
+/// The granularities the rotating counters are kept in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Granularity {
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+impl Granularity {
+    pub const ALL: [Granularity; 4] = [Granularity::Hour, Granularity::Day, Granularity::Week, Granularity::Month];
+
+    /// Names of the current and the previous bucket, and the prefix of all older ones.
+    fn names(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Granularity::Hour => ("this_hour", "last_hour", "hour_minus_"),
+            Granularity::Day => ("today", "yesterday", "day_minus_"),
+            Granularity::Week => ("this_week", "last_week", "week_minus_"),
+            Granularity::Month => ("this_month", "last_month", "month_minus_"),
+        }
+    }
+
+    /// Returns the name of the granularity's bucket series, e.g. "hourly".
+    pub fn series_name(self) -> &'static str {
+        match self {
+            Granularity::Hour => "hourly",
+            Granularity::Day => "daily",
+            Granularity::Week => "weekly",
+            Granularity::Month => "monthly",
+        }
+    }
+
+    /// Returns the public name of the bucket at `index`, e.g. "this_hour", "last_hour",
+    /// "hour_minus_2", ... or "today", "yesterday", "day_minus_2", ...
+    pub fn bucket_name(self, index: usize) -> String {
+        let (current, previous, prefix) = self.names();
+        match index {
+            0 => current.to_string(),
+            1 => previous.to_string(),
+            n => format!("{}{}", prefix, n),
+        }
+    }
+
+    /// Returns an identifier of the hour/day/week/month containing `at`, which changes
+    /// exactly when the buckets of this granularity rotate.
+    pub fn period<Tz: TimeZone>(self, at: &DateTime<Tz>) -> String {
+        self.period_before(at, 0)
+    }
+
+    /// Returns the period `steps` hours/days/weeks/months before the one containing `at`,
+    /// i.e. the one of the bucket at index `steps` if the buckets were rotated up to `at`.
+    pub fn period_before<Tz: TimeZone>(self, at: &DateTime<Tz>, steps: usize) -> String {
+        let steps = steps as i64;
+        let date = at.date_naive();
+        match self {
+            // On the absolute time line, like the rotation, so repeated DST hours differ
+            Granularity::Hour => (at.timestamp().div_euclid(3600) - steps).to_string(),
+            Granularity::Day => (date - chrono::Duration::days(steps)).to_string(),
+            Granularity::Week => {
+                let date = date - chrono::Duration::days(7 * steps);
+                format!("{}-W{:02}", date.iso_week().year(), date.iso_week().week())
+            }
+            Granularity::Month => {
+                let months = date.year() as i64 * 12 + date.month0() as i64 - steps;
+                format!("{}-{:02}", months.div_euclid(12), months.rem_euclid(12) + 1)
+            }
+        }
+    }
+
+    /// Returns the start and end of the period `steps` hours/days/weeks/months before the
+    /// one containing `at`, i.e. the time covered by the bucket at index `steps`.
+    pub fn span_before<Tz: TimeZone>(self, at: &DateTime<Tz>, steps: usize) -> (DateTime<Utc>, DateTime<Utc>) {
+        let steps = steps as i64;
+        let timezone = at.timezone();
+        // Midnight can fall into a DST gap, then the day starts when the clocks jump
+        let start_of = |date: NaiveDate| -> DateTime<Utc> {
+            let midnight = date.and_time(NaiveTime::MIN);
+            timezone.from_local_datetime(&midnight).earliest().map_or_else(|| midnight.and_utc(), |start| start.with_timezone(&Utc))
+        };
+        let date = at.date_naive();
+        match self {
+            Granularity::Hour => {
+                let start = DateTime::from_timestamp((at.timestamp().div_euclid(3600) - steps) * 3600, 0).unwrap_or_default();
+                (start, start + chrono::Duration::hours(1))
+            }
+            Granularity::Day => {
+                let day = date - chrono::Duration::days(steps);
+                (start_of(day), start_of(day + chrono::Duration::days(1)))
+            }
+            Granularity::Week => {
+                let monday = date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64 + 7 * steps);
+                (start_of(monday), start_of(monday + chrono::Duration::days(7)))
+            }
+            Granularity::Month => {
+                let first_day = |months: i64| {
+                    NaiveDate::from_ymd_opt(months.div_euclid(12) as i32, months.rem_euclid(12) as u32 + 1, 1).unwrap_or(NaiveDate::MIN)
+                };
+                let months = date.year() as i64 * 12 + date.month0() as i64 - steps;
+                (start_of(first_day(months)), start_of(first_day(months + 1)))
+            }
+        }
+    }
+
+    /// The inverse of `bucket_name`.
+    fn parse_bucket_name(name: &str) -> Option<(Granularity, usize)> {
+        Granularity::ALL.into_iter().find_map(|granularity| {
+            let (current, previous, prefix) = granularity.names();
+            if name == current {
+                Some((granularity, 0))
+            } else if name == previous {
+                Some((granularity, 1))
+            } else {
+                let index = name.strip_prefix(prefix)?.parse().ok().filter(|&n| n >= 2)?;
+                Some((granularity, index))
+            }
+        })
+    }
+}
+
+/// Rotating popularity counters, kept as ring buffers of buckets per granularity.
+///
+/// `hourly[0]` is the current hour, `hourly[1]` the previous one and so on; the same
+/// applies to `daily` with `daily[0]` being today, and to the weekly and monthly
+/// aggregates. The number of buckets is taken from the configuration.
+///
+/// Increments only need shared access (`&self`); rotation, removal and persistence
+/// take exclusive access.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(from = "PersistedCounters")]
 pub struct Counters {
-    pub this_hour: HashMap<String, u32>,
-    pub last_hour: HashMap<String, u32>,
-    pub hour_minus_2: HashMap<String, u32>,
-    pub today: HashMap<String, u32>,
-    pub yesterday: HashMap<String, u32>,
-    pub day_minus_2: HashMap<String, u32>,
-    pub day_minus_3: HashMap<String, u32>,
-    pub day_minus_4: HashMap<String, u32>,
-    pub day_minus_5: HashMap<String, u32>,
-    pub day_minus_6: HashMap<String, u32>,
-    pub day_minus_7: HashMap<String, u32>,
-    pub day_minus_8: HashMap<String, u32>,
-    pub day_minus_9: HashMap<String, u32>,
-    pub day_minus_10: HashMap<String, u32>,
-    pub day_minus_11: HashMap<String, u32>,
-    pub day_minus_12: HashMap<String, u32>,
+    pub hourly: Vec<Bucket>,
+    pub daily: Vec<Bucket>,
+    pub weekly: Vec<Bucket>,
+    pub monthly: Vec<Bucket>,
+    /// The point in time the buckets have been rotated up to: `hourly[0]` holds the
+    /// hour containing this instant, `daily[0]` its day and so on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_rotation_at: Option<DateTime<Utc>>,
+    /// Per-weekday sums of all completed days, for seasonality profiles
+    pub weekdays: WeekdayProfile,
+    /// When each identifier was incremented for the first time
+    pub first_seen: DashMap<String, DateTime<Utc>>,
+    /// When each identifier was incremented for the last time
+    pub last_seen: DashMap<String, DateTime<Utc>>,
+    /// Sequence number of the last event log entry contained in this state
+    log_sequence: AtomicU64,
+    /// ID of the last snapshot of both the counters and the co-occurrences (see
+    /// `consistent_snapshot`); 0 if none
+    pub snapshot_id: u64,
 
     #[serde(skip)]
-    pub dirty: bool,
+    dirty: AtomicBool,
+    /// Number of changes so far, for telling clients whether the counts changed
+    #[serde(skip)]
+    changes: AtomicU64,
+    /// Number of changes so far that affected more than the current buckets
+    #[serde(skip)]
+    history_changes: AtomicU64,
+    /// When the snapshot was last written successfully by this process
+    #[serde(skip)]
+    pub last_persisted_at: Option<DateTime<Utc>>,
+    /// Number of snapshots started, so one written without the lock can tell whether
+    /// another was written meanwhile (see `prepare_snapshot`)
+    #[serde(skip)]
+    snapshot_writes: u64,
+    /// Records every change until the next snapshot, if enabled
+    #[serde(skip)]
+    event_log: Mutex<Option<EventLog>>,
+    /// Shared storage the buckets are mirrored to; `None` keeps them in memory only
+    #[serde(skip)]
+    store: Option<Arc<dyn CounterStore>>,
+    /// How snapshots are written
+    #[serde(skip)]
+    snapshots: SnapshotSettings,
+    /// Where snapshots are written to
+    #[serde(skip)]
+    state: Arc<dyn StateStore>,
+    /// Where changes are streamed to replicas, if anywhere
+    #[serde(skip)]
+    change_feed: Option<Arc<ChangeFeed>>,
+    /// Whether the counters follow a primary, which rotates them (see `replication`)
+    #[serde(skip)]
+    following: bool,
+    /// Contributions of every gossip peer to the current buckets (see `gossip`)
+    #[serde(skip_serializing_if = "GossipLedger::is_empty")]
+    pub gossip: GossipLedger,
+    /// Per-minute counts of the last minutes, if enabled
+    #[serde(skip)]
+    pub minutes: MinuteCounters,
+    /// Continuously decayed popularity of every identifier
+    #[serde(skip_serializing_if = "DecayedPopularity::is_empty")]
+    pub popularity: DecayedPopularity,
+    /// Plays per namespace in every bucket, derived from the buckets
+    #[serde(skip)]
+    pub namespaces: NamespaceRollups,
+}
+
+/// A copy of the counters taken by `Counters::prepare_snapshot`, to be written without
+/// holding the lock.
+pub struct PendingSnapshot {
+    copy: Counters,
+    /// Where it is written to
+    state: Arc<dyn StateStore>,
+    snapshots: SnapshotSettings,
+    /// Value of `snapshot_writes` once it was copied
+    ticket: u64,
+}
+
+impl PendingSnapshot {
+    /// Writes the snapshot and returns the bytes written.
+    pub fn write(&self) -> io::Result<u64> {
+        state_store::save(&*self.state, SNAPSHOT_PATH, &self.copy, self.snapshots)
+    }
+}
+
+/// All persistence formats `Counters` can be loaded from.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PersistedCounters {
+    Current(Box<CurrentCounters>),
+    /// The original format with one hard-coded field per bucket
+    Legacy(Box<LegacyCounters>),
+}
+
+#[derive(Deserialize)]
+struct CurrentCounters {
+    hourly: Vec<Bucket>,
+    daily: Vec<Bucket>,
+    // Added after the first ring-buffer format, so they may be missing
+    #[serde(default)]
+    weekly: Vec<Bucket>,
+    #[serde(default)]
+    monthly: Vec<Bucket>,
+    #[serde(default)]
+    last_rotation_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    weekdays: WeekdayProfile,
+    first_seen: Option<DashMap<String, DateTime<Utc>>>,
+    #[serde(default)]
+    last_seen: DashMap<String, DateTime<Utc>>,
+    #[serde(default)]
+    log_sequence: u64,
+    #[serde(default)]
+    snapshot_id: u64,
+    #[serde(default)]
+    gossip: GossipLedger,
+    #[serde(default)]
+    popularity: DecayedPopularity,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct LegacyCounters {
+    this_hour: Bucket,
+    last_hour: Bucket,
+    hour_minus_2: Bucket,
+    today: Bucket,
+    yesterday: Bucket,
+    day_minus_2: Bucket,
+    day_minus_3: Bucket,
+    day_minus_4: Bucket,
+    day_minus_5: Bucket,
+    day_minus_6: Bucket,
+    day_minus_7: Bucket,
+    day_minus_8: Bucket,
+    day_minus_9: Bucket,
+    day_minus_10: Bucket,
+    day_minus_11: Bucket,
+    day_minus_12: Bucket,
+}
+
+impl From<PersistedCounters> for Counters {
+    fn from(persisted: PersistedCounters) -> Self {
+        match persisted {
+            PersistedCounters::Current(current) => {
+                let CurrentCounters {
+                    hourly,
+                    daily,
+                    weekly,
+                    monthly,
+                    last_rotation_at,
+                    weekdays,
+                    first_seen,
+                    last_seen,
+                    log_sequence,
+                    snapshot_id,
+                    gossip,
+                    popularity,
+                } = *current;
+                // Files written before first-seen tracking existed don't have the field
+                let backdate = first_seen.is_none();
+                let mut counters = Counters {
+                    hourly,
+                    daily,
+                    weekly,
+                    monthly,
+                    last_rotation_at,
+                    weekdays,
+                    first_seen: first_seen.unwrap_or_default(),
+                    last_seen,
+                    log_sequence: AtomicU64::new(log_sequence),
+                    snapshot_id,
+                    dirty: AtomicBool::new(false),
+                    changes: AtomicU64::new(0),
+                    history_changes: AtomicU64::new(0),
+                    last_persisted_at: None,
+                    snapshot_writes: 0,
+                    event_log: Mutex::new(None),
+                    store: None,
+                    snapshots: SnapshotSettings::default(),
+                    state: Arc::new(FileStore::default()),
+                    change_feed: None,
+                    following: false,
+                    gossip,
+                    minutes: MinuteCounters::default(),
+                    popularity,
+                    namespaces: NamespaceRollups::default(),
+                };
+                if backdate {
+                    counters.backdate_first_seen();
+                }
+                counters.rebuild_namespaces();
+                counters
+            }
+            PersistedCounters::Legacy(legacy) => {
+                info!("Migrating rotating counters from the legacy persistence format.");
+                let mut counters = Counters {
+                    hourly: vec![legacy.this_hour, legacy.last_hour, legacy.hour_minus_2],
+                    daily: vec![
+                        legacy.today,
+                        legacy.yesterday,
+                        legacy.day_minus_2,
+                        legacy.day_minus_3,
+                        legacy.day_minus_4,
+                        legacy.day_minus_5,
+                        legacy.day_minus_6,
+                        legacy.day_minus_7,
+                        legacy.day_minus_8,
+                        legacy.day_minus_9,
+                        legacy.day_minus_10,
+                        legacy.day_minus_11,
+                        legacy.day_minus_12,
+                    ],
+                    weekly: Vec::new(),
+                    monthly: Vec::new(),
+                    // Unknown, so no catch-up is possible for legacy files
+                    last_rotation_at: None,
+                    weekdays: WeekdayProfile::default(),
+                    first_seen: DashMap::new(),
+                    last_seen: DashMap::new(),
+                    log_sequence: AtomicU64::new(0),
+                    snapshot_id: 0,
+                    // Make sure the next persist writes the new format
+                    dirty: AtomicBool::new(true),
+                    changes: AtomicU64::new(0),
+                    history_changes: AtomicU64::new(0),
+                    last_persisted_at: None,
+                    snapshot_writes: 0,
+                    event_log: Mutex::new(None),
+                    store: None,
+                    snapshots: SnapshotSettings::default(),
+                    state: Arc::new(FileStore::default()),
+                    change_feed: None,
+                    following: false,
+                    gossip: GossipLedger::default(),
+                    minutes: MinuteCounters::default(),
+                    popularity: DecayedPopularity::default(),
+                    namespaces: NamespaceRollups::default(),
+                };
+                counters.backdate_first_seen();
+                counters.rebuild_namespaces();
+                counters
+            }
+        }
+    }
+}
+
+/// One point of an identifier's time series.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct TimeSeriesPoint {
+    pub bucket: String,
+    pub count: u64,
+}
+
+/// The counts of one identifier across all buckets, oldest bucket first.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct CounterTimeSeries {
+    pub hourly: Vec<TimeSeriesPoint>,
+    pub daily: Vec<TimeSeriesPoint>,
+    pub weekly: Vec<TimeSeriesPoint>,
+    pub monthly: Vec<TimeSeriesPoint>,
+}
+
+/// The counts of one identifier in the rolling windows, including the current hour or
+/// day, so clients don't have to sum up buckets across rotations. Windows needing more
+/// buckets than are kept are left out.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, ToSchema)]
+pub struct RollingCounts {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_24h: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_48h: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_7d: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_12d: Option<u64>,
+}
+
+impl RollingCounts {
+    /// Names of the windows with a count, e.g. for labelling them.
+    pub fn window_names(&self) -> impl Iterator<Item = &str> + '_ {
+        let windows = [("last_24h", self.last_24h), ("last_48h", self.last_48h), ("last_7d", self.last_7d), ("last_12d", self.last_12d)];
+        windows.into_iter().filter(|(_, count)| count.is_some()).map(|(name, _)| name)
+    }
+}
+
+/// The hourly and daily counts of one identifier as bare arrays, oldest first, for
+/// rendering many sparklines at once.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct Sparkline {
+    pub hourly: Vec<u64>,
+    pub daily: Vec<u64>,
+}
+
+/// A bucket summed up by `Counters::range_count`.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct RangeBucket {
+    pub bucket: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub count: u64,
+    /// Whether the bucket reaches beyond the requested range, so its count may include
+    /// plays outside of it
+    pub partial: bool,
+}
+
+/// The count of an identifier in a time range.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, ToSchema)]
+pub struct CounterRange {
+    pub count: u64,
+    /// Whether any of the buckets is partial
+    pub partial: bool,
+    /// The buckets summed up, oldest first. Gaps between them are periods no bucket is
+    /// kept for any more.
+    pub buckets: Vec<RangeBucket>,
+}
+
+/// An identifier with its count in one bucket.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct CountEntry {
+    pub id: String,
+    pub count: u64,
+}
+
+/// Returns the count of `id` in `bucket`, 0 if it has none.
+pub fn count_of(bucket: &Bucket, id: &str) -> u64 {
+    bucket.get(id).map_or(0, |count| *count)
+}
+
+/// Returns the entries of `bucket` sorted by count (descending, ties by identifier),
+/// skipping `offset` entries and returning at most `limit`.
+pub fn top_entries(bucket: &Bucket, offset: usize, limit: usize) -> Vec<CountEntry> {
+    let mut entries: Vec<CountEntry> = bucket
+        .iter()
+        .map(|entry| CountEntry { id: entry.key().clone(), count: *entry.value() })
+        .collect();
+    entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.id.cmp(&b.id)));
+    entries.into_iter().skip(offset).take(limit).collect()
+}
+
+/// The position of one identifier within a bucket.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct CounterRank {
+    pub count: u64,
+    /// 1-based; identifiers with the same count share a rank
+    pub rank: usize,
+    /// Number of identifiers in the bucket
+    pub total: usize,
+    /// Share of identifiers with a lower count, in percent
+    pub percentile: f64,
+    /// `rank` relative to `total` in percent, e.g. 1.0 for "top 1%"
+    pub top_percent: f64,
+}
+
+/// Returns the rank of `id` within `bucket`, or `None` if it has no count there.
+pub fn rank_in(bucket: &Bucket, id: &str) -> Option<CounterRank> {
+    let count = *bucket.get(id)?;
+    let total = bucket.len();
+    let higher = bucket.iter().filter(|other| *other.value() > count).count();
+    let lower = bucket.iter().filter(|other| *other.value() < count).count();
+    Some(CounterRank {
+        count,
+        rank: higher + 1,
+        total,
+        percentile: lower as f64 * 100.0 / total as f64,
+        top_percent: (higher + 1) as f64 * 100.0 / total as f64,
+    })
+}
+
+/// Sums of all completed days per weekday (index 0 = Monday), together with the number
+/// of days that went into each sum, so that averages per weekday can be derived.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WeekdayProfile {
+    totals: Vec<HashMap<String, u64>>,
+    days: Vec<u32>,
+}
+
+/// The average count of an identifier on one weekday.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct WeekdayAverage {
+    pub weekday: &'static str,
+    pub average: f64,
+    /// Number of completed days the average is based on
+    pub days: u32,
+}
+
+const WEEKDAY_NAMES: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+impl WeekdayProfile {
+    /// Adds a completed day (with its daily bucket, if it was still available) to the profile.
+    fn record_day(&mut self, weekday: chrono::Weekday, bucket: Option<&Bucket>) {
+        self.totals.resize_with(7, HashMap::new);
+        self.days.resize(7, 0);
+        let index = weekday.num_days_from_monday() as usize;
+        self.days[index] += 1;
+        for entry in bucket.into_iter().flat_map(|bucket| bucket.iter()) {
+            let total = self.totals[index].entry(entry.key().clone()).or_insert(0);
+            *total = total.saturating_add(*entry.value());
+        }
+    }
+
+    /// Returns every identifier with a total, on any weekday.
+    fn ids(&self) -> impl Iterator<Item = &String> {
+        self.totals.iter().flat_map(HashMap::keys)
+    }
+
+    /// Removes `id` from all weekday totals. Returns whether it was present.
+    fn remove(&mut self, id: &str) -> bool {
+        let mut removed = false;
+        for totals in &mut self.totals {
+            removed |= totals.remove(id).is_some();
+        }
+        removed
+    }
+
+    fn shrink(&mut self) {
+        self.totals.iter_mut().for_each(HashMap::shrink_to_fit);
+    }
+
+    /// Adds another instance's totals. Both instances see the same calendar days, so the
+    /// day counts are not summed but the larger one is kept.
+    fn merge(&mut self, other: WeekdayProfile) {
+        self.totals.resize_with(other.totals.len().max(self.totals.len()), HashMap::new);
+        for (totals, other_totals) in self.totals.iter_mut().zip(other.totals) {
+            for (id, count) in other_totals {
+                let total = totals.entry(id).or_insert(0);
+                *total = total.saturating_add(count);
+            }
+        }
+        self.days.resize(other.days.len().max(self.days.len()), 0);
+        for (days, other_days) in self.days.iter_mut().zip(other.days) {
+            *days = (*days).max(other_days);
+        }
+    }
+
+    /// Returns the average count of `id` per weekday, Monday first.
+    pub fn averages(&self, id: &str) -> Vec<WeekdayAverage> {
+        WEEKDAY_NAMES
+            .iter()
+            .enumerate()
+            .map(|(index, &weekday)| {
+                let days = self.days.get(index).copied().unwrap_or(0);
+                let total = self.totals.get(index).and_then(|bucket| bucket.get(id)).copied().unwrap_or(0);
+                WeekdayAverage {
+                    weekday,
+                    average: if days > 0 { total as f64 / days as f64 } else { 0.0 },
+                    days,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Shifts every bucket `steps` positions towards the end of `buckets`, dropping the
+/// oldest ones and leaving empty buckets at the front.
+pub(crate) fn rotate_buckets(buckets: &mut [Bucket], steps: usize) {
+    let steps = steps.min(buckets.len());
+    if steps == 0 {
+        return;
+    }
+    buckets.rotate_right(steps);
+    buckets[..steps].iter().for_each(Bucket::clear);
+}
+
+/// Counts the hour/day/week/month boundaries crossed between `from` and `to`, both
+/// interpreted in the time zone of `to`. Returns zeros if `to` is not after `from`.
+fn boundaries_between<Tz: TimeZone>(from: DateTime<Utc>, to: &DateTime<Tz>) -> [(Granularity, usize); 4] {
+    let from = from.with_timezone(&to.timezone());
+    let count = |n: i64| n.max(0) as usize;
+
+    // Hours are compared on the absolute time line so DST changes don't skew the count
+    let hours = count(to.timestamp().div_euclid(3600) - from.timestamp().div_euclid(3600));
+    let days = count((to.date_naive() - from.date_naive()).num_days());
+    let week_start = |date: chrono::NaiveDate| date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64);
+    let weeks = count((week_start(to.date_naive()) - week_start(from.date_naive())).num_days() / 7);
+    let months = count((to.year() as i64 * 12 + to.month0() as i64) - (from.year() as i64 * 12 + from.month0() as i64));
+
+    [
+        (Granularity::Hour, hours),
+        (Granularity::Day, days),
+        (Granularity::Week, weeks),
+        (Granularity::Month, months),
+    ]
 }
 
 impl Counters {
-    pub fn new() -> Self {
-        if let Ok(data) = fs::read_to_string("rotating_counters.json") {
-            if let Ok(c) = serde_json::from_str(&data) {
-                println!("Loaded rotating counters from rotating_counters.json");
-                return c;
+    /// Creates empty counters with the given number of buckets per granularity.
+    pub fn with_depths(hourly_buckets: usize, daily_buckets: usize, weekly_buckets: usize, monthly_buckets: usize) -> Self {
+        let mut counters = Counters {
+            hourly: vec![Bucket::new(); hourly_buckets.max(1)],
+            daily: vec![Bucket::new(); daily_buckets.max(1)],
+            weekly: vec![Bucket::new(); weekly_buckets.max(1)],
+            monthly: vec![Bucket::new(); monthly_buckets.max(1)],
+            last_rotation_at: None,
+            weekdays: WeekdayProfile::default(),
+            first_seen: DashMap::new(),
+            last_seen: DashMap::new(),
+            log_sequence: AtomicU64::new(0),
+            snapshot_id: 0,
+            dirty: AtomicBool::new(false),
+            changes: AtomicU64::new(0),
+            history_changes: AtomicU64::new(0),
+            last_persisted_at: None,
+            snapshot_writes: 0,
+            event_log: Mutex::new(None),
+            store: None,
+            snapshots: SnapshotSettings::default(),
+            state: Arc::new(FileStore::default()),
+            change_feed: None,
+            following: false,
+            gossip: GossipLedger::default(),
+            minutes: MinuteCounters::default(),
+            popularity: DecayedPopularity::default(),
+            namespaces: NamespaceRollups::default(),
+        };
+        counters.rebuild_namespaces();
+        counters
+    }
+
+    /// Loads the last snapshot from `state`, replays the event log in the data directory on
+    /// top of it and starts a new log. With a store, the buckets are taken from it instead
+    /// (see `attach_store`).
+    pub fn new(settings: &CounterSettings, storage: &StorageSettings, state: Arc<dyn StateStore>, store: Option<Arc<dyn CounterStore>>) -> Self {
+        let event_log_path = storage.data_path(EVENT_LOG_PATH);
+        let mut c = match state_store::load::<Counters>(&*state, SNAPSHOT_PATH) {
+            Some(mut c) => {
+                info!("Loaded rotating counters from {}", state.location(SNAPSHOT_PATH));
+                c.resize(settings);
+                c
+            }
+            None => {
+                info!("Initialized new rotating counters.");
+                Counters::with_depths(settings.hourly_buckets, settings.daily_buckets, settings.weekly_buckets, settings.monthly_buckets)
             }
+        };
+
+        c.snapshots = storage.snapshots;
+        c.state = state;
+        c.minutes = MinuteCounters::new(settings.minute_buckets);
+        c.popularity.set_half_life(settings.popularity_half_life_secs);
+        // Before replaying, so the replayed increments are gossiped as well
+        if settings.backend == CounterBackend::Gossip {
+            c.gossip.enable(settings.gossip_node_id.clone(), settings.rotation_timezone);
+        }
+        let replayed = c.replay(&event_log_path, &settings.rotation_timezone);
+        if replayed > 0 {
+            info!("Replayed {} counter events from {}", replayed, event_log_path.display());
         }
-        println!("Initialized new rotating counters.");
-        Counters::default()
+        if let Some(store) = store {
+            c.attach_store(store);
+        }
+        // Shift out whatever happened before a downtime, before serving traffic
+        c.advance_to(&determinism::now().with_timezone(&settings.rotation_timezone));
+
+        // A durable store already records every change
+        if settings.event_log && !c.has_durable_store() {
+            match EventLog::open(&event_log_path, settings.event_log_sync_batch, *c.log_sequence.get_mut()) {
+                Ok(log) => *c.event_log.get_mut().unwrap() = Some(log),
+                Err(e) => error!("Failed to open counter event log {}: {}", event_log_path.display(), e),
+            }
+        }
+        if replayed > 0 {
+            // Fold the replayed events into a fresh snapshot, which also empties the log
+            c.persist();
+        }
+        c
     }
 
-    pub fn persist(&self) {
-        if self.dirty {
-            if let Ok(data) = serde_json::to_string(&self) {
-                let _ = fs::write("rotating_counters.json", data);
-                println!("Rotating counters persisted.");
-            } else {
-                eprintln!("Failed to serialize rotating counters for persistence.");
+    /// Takes the buckets from `store` and mirrors every change to it from now on. A store
+    /// without any counts yet starts out with the current ones, e.g. from the last snapshot.
+    fn attach_store(&mut self, store: Arc<dyn CounterStore>) {
+        match store.load() {
+            Ok(buckets) if buckets.iter().flatten().all(|bucket| bucket.is_empty()) => {
+                for granularity in Granularity::ALL {
+                    for (index, bucket) in self.buckets(granularity).iter().enumerate() {
+                        if let Err(e) = store.add(granularity, index, bucket) {
+                            error!("Failed to copy counters to the store: {}", e);
+                        }
+                    }
+                }
             }
+            Ok(buckets) => self.replace_buckets(buckets),
+            Err(e) => error!("Failed to load counters from the store: {}", e),
         }
+        self.store = Some(store);
     }
 
-    pub fn rotate_hour(&mut self) {
-        if !self.this_hour.is_empty() { // Only rotate if there was activity
-            self.hour_minus_2 = self.last_hour.clone();
-            self.last_hour = self.this_hour.clone();
-            self.this_hour.clear();
-            self.dirty = true;
-            println!("Hourly counters rotated.");
+    fn has_durable_store(&self) -> bool {
+        self.store.as_ref().is_some_and(|store| store.is_durable())
+    }
+
+    /// Hands a change to the shared store, if there is one. Failures are logged; the
+    /// local counts are updated either way.
+    fn mirror<T>(&self, change: impl FnOnce(&dyn CounterStore) -> Result<T, String>) {
+        if let Some(store) = &self.store {
+            if let Err(e) = change(store.as_ref()) {
+                error!("Failed to update the counter store: {}", e);
+            }
         }
     }
 
-    pub fn rotate_day(&mut self) {
-        if !self.today.is_empty() { // Only rotate if there was activity
-            self.day_minus_12 = self.day_minus_11.clone();
-            self.day_minus_11 = self.day_minus_10.clone();
-            self.day_minus_10 = self.day_minus_9.clone();
-            self.day_minus_9 = self.day_minus_8.clone();
-            self.day_minus_8 = self.day_minus_7.clone();
-            self.day_minus_7 = self.day_minus_6.clone();
-            self.day_minus_6 = self.day_minus_5.clone();
-            self.day_minus_5 = self.day_minus_4.clone();
-            self.day_minus_4 = self.day_minus_3.clone();
-            self.day_minus_3 = self.day_minus_2.clone();
-            self.day_minus_2 = self.yesterday.clone();
-            self.yesterday = self.today.clone();
-            self.today.clear();
-            self.dirty = true;
-            println!("Rotating counters rotated.");
+    /// Returns the shared store, if the counters have one.
+    pub fn store(&self) -> Option<Arc<dyn CounterStore>> {
+        self.store.clone()
+    }
+
+    /// Replaces the buckets of every granularity (in the order of `Granularity::ALL`),
+    /// e.g. with the ones loaded from the shared store.
+    pub fn replace_buckets(&mut self, buckets: Vec<Vec<Bucket>>) {
+        for (granularity, buckets) in Granularity::ALL.into_iter().zip(buckets) {
+            if !buckets.is_empty() {
+                *self.buckets_mut(granularity) = buckets;
+            }
         }
+        self.rebuild_namespaces();
+        self.mark_history_changed();
     }
 
-    pub fn increment(&mut self, id: &str) {
-        *self.this_hour.entry(id.to_string()).or_insert(0) += 1;
-        *self.today.entry(id.to_string()).or_insert(0) += 1;
-        self.dirty = true;
+    /// Sums up the plays per namespace from scratch, after the buckets were replaced.
+    fn rebuild_namespaces(&mut self) {
+        self.namespaces = NamespaceRollups::of(Granularity::ALL.map(|granularity| self.buckets(granularity)));
     }
-}
 
-// Function to handle the periodic rotation and persistence of rotating counters
-pub async fn run_daily_counter_rotation(counters: std::sync::Arc<std::sync::Mutex<Counters>>) {
-    let mut last_hour = Local::now().hour();
-    let mut last_day = Local::now().day();
-    let mut minutes_since_persist = 0;
+    /// Applies all entries of the event log at `path` that are newer than this state,
+    /// rotating the buckets to each entry's time first. Returns the number of entries applied.
+    fn replay(&mut self, path: &Path, timezone: &Tz) -> usize {
+        let mut replayed = 0;
+        for entry in read_entries::<CounterEvent>(path) {
+            if entry.seq <= *self.log_sequence.get_mut() {
+                continue;
+            }
+            self.advance_to(&entry.at.with_timezone(timezone));
+            match entry.event {
+                CounterEvent::Increment { id, count } => self.apply_increment(&id, count, entry.at),
+                CounterEvent::Remove { id } => {
+                    self.apply_remove(&id);
+                }
+                CounterEvent::Reset => self.apply_reset(),
+            }
+            *self.log_sequence.get_mut() = entry.seq;
+            replayed += 1;
+        }
+        replayed
+    }
 
-    println!("Rotating counter thread started.");
+    /// Records a change in the event log, if one is open. Appends to the single log file
+    /// are serialized, but the lock is only held for one write.
+    fn log_event(&self, at: DateTime<Utc>, event: impl FnOnce() -> CounterEvent) {
+        let mut event_log = locks::lock(&self.event_log, "counter_event_log");
+        let Some(log) = event_log.as_mut() else {
+            return;
+        };
+        match log.append(at, event()) {
+            Ok(sequence) => {
+                self.log_sequence.fetch_max(sequence, Ordering::Relaxed);
+            }
+            Err(e) => error!("Failed to append to counter event log: {}", e),
+        }
+    }
 
-    loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    /// Streams every change from now on to `feed`. Merges, which replicas can't follow
+    /// change by change, make them load the whole state again.
+    pub fn attach_change_feed(&mut self, feed: Arc<ChangeFeed>) {
+        self.change_feed = Some(feed);
+    }
 
-        minutes_since_persist += 1;
-        if minutes_since_persist < 60 {
-            continue;
+    /// Returns the feed the changes are streamed to, if any.
+    pub fn change_feed(&self) -> Option<&Arc<ChangeFeed>> {
+        self.change_feed.as_ref()
+    }
+
+    fn publish(&self, change: impl FnOnce() -> Change) {
+        if let Some(feed) = &self.change_feed {
+            feed.publish(change);
         }
-        minutes_since_persist = 0;
+    }
 
-        let now = Local::now();
-        let current_counters_arc = counters.clone();
+    /// Marks the counters as following a primary, whose rotations they receive instead
+    /// of rotating on their own (see `maintenance::maintenance_jobs`).
+    pub fn set_following(&mut self, following: bool) {
+        self.following = following;
+    }
 
-        // The result of web::block is Result<T, BlockingError>, where T is what your closure returns.
-        // In our case, the closure returns Result<(u32, u32), ()>, so T is Result<(u32, u32), ()>.
-        let result = web::block(move || {
-            let mut c = current_counters_arc.lock().unwrap();
-            let mut rotated = false;
+    pub fn is_following(&self) -> bool {
+        self.following
+    }
 
-            if now.hour() != last_hour {
-                c.rotate_hour();
-                rotated = true;
+    /// A copy of the persisted state and the namespace rollups, detached from the logs,
+    /// stores and replicas, so it can be encoded without holding the lock.
+    pub fn detached_copy(&self) -> Counters {
+        let mut copy = Counters::with_depths(1, 1, 1, 1);
+        copy.hourly = self.hourly.clone();
+        copy.daily = self.daily.clone();
+        copy.weekly = self.weekly.clone();
+        copy.monthly = self.monthly.clone();
+        copy.last_rotation_at = self.last_rotation_at;
+        copy.weekdays = self.weekdays.clone();
+        copy.first_seen = self.first_seen.clone();
+        copy.last_seen = self.last_seen.clone();
+        copy.log_sequence = AtomicU64::new(self.log_sequence.load(Ordering::Relaxed));
+        copy.snapshot_id = self.snapshot_id;
+        copy.gossip = self.gossip.clone();
+        copy.popularity = self.popularity.clone();
+        copy.namespaces = self.namespaces.clone();
+        copy
+    }
+
+    /// Whether anything changed since the last snapshot.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+        self.changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks a change that affected more than the current buckets, e.g. a rotation.
+    fn mark_history_changed(&self) {
+        self.history_changes.fetch_add(1, Ordering::Relaxed);
+        self.mark_dirty();
+    }
+
+    /// Returns a version of the counts that changes whenever they do. Versions are only
+    /// comparable within one process.
+    pub fn version(&self) -> u64 {
+        self.changes.load(Ordering::Relaxed)
+    }
+
+    /// Returns a version of the bucket or rolling window `name` (see `window`), or `None`
+    /// if there is no such window. Only the current buckets and the rolling windows change
+    /// with every increment; older buckets only change when they are rotated, removed
+    /// from, reset or merged into.
+    pub fn window_version(&self, name: &str) -> Option<u64> {
+        match Granularity::parse_bucket_name(name) {
+            Some((granularity, index)) if index < self.buckets(granularity).len() => Some(match index {
+                0 => self.version(),
+                _ => self.history_changes.load(Ordering::Relaxed),
+            }),
+            Some(_) => None,
+            None => {
+                let &(_, granularity, length) = ROLLING_WINDOWS.iter().find(|&&(window, _, _)| window == name)?;
+                (self.buckets(granularity).len() >= length).then(|| self.version())
             }
+        }
+    }
 
-            if now.day() != last_day {
-                c.rotate_day();
-                rotated = true;
+    /// Adjusts the number of buckets to the configured depths. Surplus (oldest) buckets
+    /// are dropped, missing ones are added empty.
+    pub fn resize(&mut self, settings: &CounterSettings) {
+        let depths = [
+            (&mut self.hourly, settings.hourly_buckets),
+            (&mut self.daily, settings.daily_buckets),
+            (&mut self.weekly, settings.weekly_buckets),
+            (&mut self.monthly, settings.monthly_buckets),
+        ];
+        for (buckets, depth) in depths {
+            let depth = depth.max(1);
+            if buckets.len() != depth {
+                buckets.resize_with(depth, Bucket::new);
+                *self.dirty.get_mut() = true;
             }
+        }
+        self.rebuild_namespaces();
+    }
 
-            if c.dirty || rotated {
-                c.persist();
-                c.dirty = false;
+    /// Writes a snapshot if anything changed. Afterwards the event log is emptied, as all
+    /// its entries are contained in the snapshot. Buckets kept in a durable store are
+    /// left out.
+    #[tracing::instrument(skip_all)]
+    pub fn persist(&mut self) {
+        if self.is_dirty() {
+            if let Err(e) = self.write_snapshot() {
+                error!("Failed to write {}: {}", self.state.location(SNAPSHOT_PATH), e);
             }
-            Ok::<_, ()>((now.hour(), now.day())) // Inner Result: Ok(hour, day) or Err(())
-        }).await; // Outer Result: Ok(InnerResult) or Err(BlockingError)
+        }
+    }
+
+    /// Writes a snapshot even if nothing changed since the last one, e.g. before a
+    /// planned maintenance. Returns where it was written to and its size.
+    pub fn flush(&mut self) -> io::Result<(String, u64)> {
+        let bytes = self.write_snapshot()?;
+        Ok((self.state.location(SNAPSHOT_PATH), bytes))
+    }
+
+    /// Copies the state for a snapshot with ID `id` if anything changed, and starts
+    /// tracking changes anew, so the copy can be written without holding the lock. Like
+    /// `persist`, leaves out buckets kept in a durable store. Once written, the outcome
+    /// is handed to `finish_snapshot`.
+    pub fn prepare_snapshot(&mut self, id: u64) -> Option<PendingSnapshot> {
+        if !self.is_dirty() {
+            return None;
+        }
+        self.snapshot_id = id;
+        self.snapshot_writes += 1;
+        let mut copy = self.detached_copy();
+        if self.has_durable_store() {
+            Granularity::ALL.into_iter().for_each(|granularity| copy.buckets_mut(granularity).clear());
+        }
+        *self.dirty.get_mut() = false;
+        Some(PendingSnapshot { copy, state: Arc::clone(&self.state), snapshots: self.snapshots, ticket: self.snapshot_writes })
+    }
 
+    /// Records the outcome of writing `pending`. Once it is written, the event log is
+    /// emptied unless changes were logged since the copy, which are replayed on top of it
+    /// anyway. If it couldn't be written, the next `persist` tries again; if another
+    /// snapshot was written meanwhile, which it may have overwritten, one is written
+    /// right away.
+    pub fn finish_snapshot(&mut self, mut pending: PendingSnapshot, result: io::Result<u64>) {
         match result {
-            // First, match the outer Result: if the blocking task itself completed successfully
-            Ok(inner_result) => {
-                // Then, match the inner Result: if the operation *inside* the blocking task was successful
-                match inner_result {
-                    Ok((new_hour, new_day)) => {
-                        last_hour = new_hour;
-                        last_day = new_day;
-                    }
-                    Err(()) => {
-                        // This case handles the `Err(())` from our closure.
-                        // In our current closure, it's unreachable as we always return `Ok`.
-                        // But it's good practice to acknowledge the possibility.
-                        eprintln!("Error within rotating counter rotation logic (inner Err).");
-                    }
+            Ok(_) if self.snapshot_writes == pending.ticket => {
+                info!("Rotating counters persisted.");
+                self.last_persisted_at = Some(determinism::now());
+                if *self.log_sequence.get_mut() == *pending.copy.log_sequence.get_mut() {
+                    self.truncate_event_log();
                 }
             }
-            // If the web::block task itself failed (e.g., cancelled or panicking in the spawned thread)
+            Ok(_) => {
+                *self.dirty.get_mut() = true;
+                self.persist();
+            }
             Err(e) => {
-                eprintln!("Error in rotating counter rotation block (outer BlockingError): {:?}", e);
+                error!("Failed to write {}: {}", self.state.location(SNAPSHOT_PATH), e);
+                *self.dirty.get_mut() = true;
+            }
+        }
+    }
+
+    /// Returns the bytes written.
+    fn write_snapshot(&mut self) -> io::Result<u64> {
+        self.snapshot_writes += 1;
+        let stored_buckets = self
+            .has_durable_store()
+            .then(|| Granularity::ALL.map(|granularity| std::mem::take(self.buckets_mut(granularity))));
+        let result = state_store::save(&*self.state, SNAPSHOT_PATH, &*self, self.snapshots);
+        for (granularity, buckets) in Granularity::ALL.into_iter().zip(stored_buckets.into_iter().flatten()) {
+            *self.buckets_mut(granularity) = buckets;
+        }
+        let bytes = result?;
+        info!("Rotating counters persisted.");
+        *self.dirty.get_mut() = false;
+        self.last_persisted_at = Some(determinism::now());
+        self.truncate_event_log();
+        Ok(bytes)
+    }
+
+    fn truncate_event_log(&mut self) {
+        if let Some(log) = self.event_log.get_mut().unwrap().as_mut() {
+            if let Err(e) = log.truncate() {
+                error!("Failed to truncate counter event log: {}", e);
+            }
+        }
+    }
+
+    /// Returns the buckets of one granularity, current bucket first.
+    pub fn buckets(&self, granularity: Granularity) -> &[Bucket] {
+        match granularity {
+            Granularity::Hour => &self.hourly,
+            Granularity::Day => &self.daily,
+            Granularity::Week => &self.weekly,
+            Granularity::Month => &self.monthly,
+        }
+    }
+
+    /// Estimated bytes used by all buckets of one granularity, including the identifiers.
+    pub fn bucket_bytes(&self, granularity: Granularity) -> usize {
+        self.buckets(granularity).iter().map(memory::string_dash_map_bytes).sum()
+    }
+
+    /// Estimated bytes used by the first- and last-seen timestamps, including the
+    /// identifiers.
+    pub fn first_seen_bytes(&self) -> usize {
+        memory::string_dash_map_bytes(&self.first_seen) + memory::string_dash_map_bytes(&self.last_seen)
+    }
+
+    fn buckets_mut(&mut self, granularity: Granularity) -> &mut Vec<Bucket> {
+        match granularity {
+            Granularity::Hour => &mut self.hourly,
+            Granularity::Day => &mut self.daily,
+            Granularity::Week => &mut self.weekly,
+            Granularity::Month => &mut self.monthly,
+        }
+    }
+
+    /// Shifts the buckets of one granularity by `steps` positions.
+    pub fn rotate(&mut self, granularity: Granularity, steps: usize) {
+        rotate_buckets(self.buckets_mut(granularity), steps);
+        self.namespaces.rotate(granularity, steps);
+        self.mark_history_changed();
+        info!("{:?} counters rotated by {}.", granularity, steps);
+    }
+
+    /// Rotates all buckets by the number of hour/day/week/month boundaries crossed since
+    /// `last_rotation_at`, so that the current buckets belong to `now`. This covers
+    /// both the regular rotation and catching up after a downtime.
+    /// Returns whether any buckets were rotated.
+    #[tracing::instrument(skip_all)]
+    pub fn advance_to<Tz: TimeZone>(&mut self, now: &DateTime<Tz>) -> bool {
+        let Some(last_rotation_at) = self.last_rotation_at else {
+            self.last_rotation_at = Some(now.with_timezone(&Utc));
+            stats::record_rotation(now.with_timezone(&Utc));
+            self.mark_dirty();
+            return false;
+        };
+        // Also when nothing rotates, so the rotation restored on startup is reported
+        stats::record_rotation(last_rotation_at);
+
+        let mut rotated = false;
+        for (granularity, steps) in boundaries_between(last_rotation_at, now) {
+            if granularity == Granularity::Day && steps > 0 {
+                // Fold every completed day into the weekday profile before it is rotated.
+                // Only the first one has data; days missed during a downtime count as empty.
+                let first_day = last_rotation_at.with_timezone(&now.timezone()).date_naive();
+                for (offset, day) in first_day.iter_days().take(steps).enumerate() {
+                    let bucket = if offset == 0 { self.daily.first() } else { None };
+                    self.weekdays.record_day(day.weekday(), bucket);
+                }
+            }
+            if steps > 0 {
+                self.rotate(granularity, steps);
+                // Instances sharing a store all rotate; only the first one shifts the store
+                self.mirror(|store| store.rotate(granularity, steps, &granularity.period(now)));
+                rotated = true;
+            }
+        }
+        if rotated {
+            self.last_rotation_at = Some(now.with_timezone(&Utc));
+            stats::record_rotation(now.with_timezone(&Utc));
+            self.publish(|| Change::Rotate { at: now.with_timezone(&Utc) });
+            if let Some(periods) = self.gossip_periods() {
+                self.gossip.retain_periods(&periods);
+            }
+        }
+        rotated
+    }
+
+    /// Identifiers that were counted before first-seen tracking existed get the Unix
+    /// epoch as first-seen time, so they are never mistaken for new content.
+    fn backdate_first_seen(&mut self) {
+        let buckets = self.hourly.iter().chain(&self.daily).chain(&self.weekly).chain(&self.monthly);
+        for bucket in buckets {
+            for entry in bucket.iter() {
+                self.first_seen.entry(entry.key().clone()).or_insert(DateTime::UNIX_EPOCH);
+            }
+        }
+    }
+
+    /// Adds `amount` to the current bucket of every granularity. An amount of 0 is a no-op.
+    pub fn increment(&self, id: &str, amount: u64) {
+        if amount == 0 {
+            return;
+        }
+        let now = determinism::now();
+        self.log_event(now, || CounterEvent::Increment { id: id.to_string(), count: amount });
+        self.publish(|| Change::Increment { id: id.to_string(), count: amount });
+        self.apply_increment(id, amount, now);
+        self.mirror(|store| store.increment(id, amount));
+        stats::record_ingest(now);
+    }
+
+    fn apply_increment(&self, id: &str, amount: u64, at: DateTime<Utc>) {
+        self.gossip.record(id, amount, self.last_rotation_at.unwrap_or(at));
+        if !self.first_seen.contains_key(id) {
+            self.first_seen.entry(id.to_string()).or_insert(at);
+        }
+        match self.last_seen.get_mut(id) {
+            Some(mut last_seen) => *last_seen = (*last_seen).max(at),
+            None => {
+                self.last_seen.insert(id.to_string(), at);
+            }
+        }
+        for buckets in [&self.hourly, &self.daily, &self.weekly, &self.monthly] {
+            // Saturate, as client-supplied amounts can be arbitrarily large
+            let mut count = buckets[0].entry(id.to_string()).or_insert(0);
+            *count = count.saturating_add(amount);
+        }
+        self.namespaces.add(id, amount);
+        self.minutes.increment(id, amount, at);
+        self.popularity.add(id, amount, at);
+        self.mark_dirty();
+    }
+
+    /// Returns all plays in the bucket `name` (e.g. "today") and the plays per namespace,
+    /// see `NamespaceRollups::plays`, or `None` if there is no such bucket.
+    pub fn namespace_plays(&self, name: &str) -> Option<(u64, Vec<NamespacePlays>)> {
+        let (granularity, index) = Granularity::parse_bucket_name(name)?;
+        self.namespaces.plays(granularity, index)
+    }
+
+    /// Adds `amount` to one bucket, e.g. for the counts of a gossip peer.
+    pub fn add_to_bucket(&self, granularity: Granularity, index: usize, id: &str, amount: u64) {
+        let Some(bucket) = self.buckets(granularity).get(index) else {
+            return;
+        };
+        let mut count = bucket.entry(id.to_string()).or_insert(0);
+        *count = count.saturating_add(amount);
+        drop(count);
+        self.namespaces.add_to(granularity, index, id, amount);
+        if !self.first_seen.contains_key(id) {
+            self.first_seen.entry(id.to_string()).or_insert_with(determinism::now);
+        }
+        if index == 0 {
+            self.mark_dirty();
+        } else {
+            self.mark_history_changed();
+        }
+    }
+
+    /// Returns the index of every bucket by its granularity and period, if gossip is
+    /// enabled and the counters were rotated at least once.
+    pub fn gossip_periods(&self) -> Option<HashMap<(Granularity, String), usize>> {
+        let at = self.last_rotation_at?.with_timezone(&self.gossip.timezone()?);
+        Some(
+            Granularity::ALL
+                .into_iter()
+                .flat_map(|granularity| {
+                    (0..self.buckets(granularity).len()).map(move |index| ((granularity, granularity.period_before(&at, index)), index))
+                })
+                .collect(),
+        )
+    }
+
+    /// Removes `id` from every bucket and profile, e.g. for depublished items.
+    /// Returns whether anything was removed.
+    pub fn remove(&mut self, id: &str) -> bool {
+        let removed = self.apply_remove(id);
+        if removed {
+            self.log_event(determinism::now(), || CounterEvent::Remove { id: id.to_string() });
+            self.publish(|| Change::Remove { id: id.to_string() });
+            self.mirror(|store| store.remove(id));
+        }
+        removed
+    }
+
+    /// Removes every identifier starting with `prefix` like `remove`, e.g. all items of
+    /// a broadcaster. Returns the number of identifiers removed.
+    pub fn remove_prefix(&mut self, prefix: &str) -> usize {
+        let mut ids: Vec<String> = self.first_seen.iter().map(|entry| entry.key().clone()).filter(|id| id.starts_with(prefix)).collect();
+        for bucket in self.hourly.iter().chain(&self.daily).chain(&self.weekly).chain(&self.monthly) {
+            ids.extend(bucket.iter().map(|entry| entry.key().clone()).filter(|id| id.starts_with(prefix)));
+        }
+        ids.extend(self.weekdays.ids().filter(|id| id.starts_with(prefix)).cloned());
+        ids.sort_unstable();
+        ids.dedup();
+        ids.iter().filter(|id| self.remove(id)).count()
+    }
+
+    /// Removes every identifier not incremented since `cutoff` like `remove`. Returns the
+    /// number of identifiers removed. Identifiers counted before last-seen tracking
+    /// existed count as seen now, so they get the full time to show up again.
+    pub fn evict_unseen(&mut self, cutoff: DateTime<Utc>) -> usize {
+        let now = determinism::now();
+        for entry in self.first_seen.iter() {
+            if !self.last_seen.contains_key(entry.key()) {
+                self.last_seen.insert(entry.key().clone(), now);
+            }
+        }
+        let ids: Vec<String> =
+            self.last_seen.iter().filter(|entry| *entry.value() < cutoff).map(|entry| entry.key().clone()).collect();
+        ids.iter().filter(|id| self.remove(id)).count()
+    }
+
+    fn apply_remove(&mut self, id: &str) -> bool {
+        let mut removed = self.weekdays.remove(id);
+        removed |= self.minutes.remove(id);
+        removed |= self.popularity.remove(id);
+        removed |= self.first_seen.remove(id).is_some();
+        removed |= self.last_seen.remove(id).is_some();
+        for granularity in Granularity::ALL {
+            for (index, bucket) in self.buckets(granularity).iter().enumerate() {
+                if let Some((_, count)) = bucket.remove(id) {
+                    self.namespaces.subtract(granularity, index, id, count);
+                    removed = true;
+                }
+            }
+        }
+        if removed {
+            self.mark_history_changed();
+        }
+        removed
+    }
+
+    /// Shrinks the buckets, profiles and timestamps to their contents. Buckets keep their
+    /// capacity when they are cleared by a rotation or lose identifiers.
+    pub fn shrink(&mut self) {
+        for bucket in self.hourly.iter().chain(&self.daily).chain(&self.weekly).chain(&self.monthly) {
+            bucket.shrink_to_fit();
+        }
+        self.first_seen.shrink_to_fit();
+        self.last_seen.shrink_to_fit();
+        self.weekdays.shrink();
+        self.popularity.shrink();
+    }
+
+    /// Clears all counts and profiles. The bucket depths and the rotation state are kept.
+    pub fn reset(&mut self) {
+        self.log_event(determinism::now(), || CounterEvent::Reset);
+        self.publish(|| Change::Reset);
+        self.apply_reset();
+        self.mirror(|store| store.reset());
+    }
+
+    fn apply_reset(&mut self) {
+        for bucket in self.hourly.iter_mut().chain(&mut self.daily).chain(&mut self.weekly).chain(&mut self.monthly) {
+            bucket.clear();
+        }
+        self.namespaces.clear();
+        self.weekdays = WeekdayProfile::default();
+        self.first_seen.clear();
+        self.last_seen.clear();
+        self.minutes.clear();
+        self.popularity.clear();
+        self.mark_history_changed();
+    }
+
+    /// Adds the counts of another instance bucket by bucket. Both sides should have been
+    /// advanced to the same point in time, so that equal indices cover the same period.
+    /// Buckets beyond this instance's depth are dropped.
+    pub fn merge(&mut self, other: Counters) {
+        self.publish(|| Change::Resync);
+        for granularity in Granularity::ALL {
+            for (index, bucket) in other.buckets(granularity).iter().enumerate() {
+                self.mirror(|store| store.add(granularity, index, bucket));
+            }
+        }
+        let pairs = [
+            (&mut self.hourly, other.hourly),
+            (&mut self.daily, other.daily),
+            (&mut self.weekly, other.weekly),
+            (&mut self.monthly, other.monthly),
+        ];
+        for (buckets, other_buckets) in pairs {
+            for (bucket, other_bucket) in buckets.iter_mut().zip(other_buckets) {
+                for (id, count) in other_bucket {
+                    let mut total = bucket.entry(id).or_insert(0);
+                    *total = total.saturating_add(count);
+                }
+            }
+        }
+        self.weekdays.merge(other.weekdays);
+        self.popularity.merge(other.popularity);
+        for (id, first_seen) in other.first_seen {
+            let mut earliest = self.first_seen.entry(id).or_insert(first_seen);
+            *earliest = (*earliest).min(first_seen);
+        }
+        for (id, last_seen) in other.last_seen {
+            let mut latest = self.last_seen.entry(id).or_insert(last_seen);
+            *latest = (*latest).max(last_seen);
+        }
+        self.rebuild_namespaces();
+        self.mark_history_changed();
+    }
+
+    /// Returns the counts of `id` in every bucket as chronological series (oldest first,
+    /// ending with the current hour/day/...). Buckets without activity for `id` count as 0.
+    pub fn time_series(&self, id: &str) -> CounterTimeSeries {
+        let series = |granularity: Granularity| -> Vec<TimeSeriesPoint> {
+            self.buckets(granularity)
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(i, bucket)| TimeSeriesPoint {
+                    bucket: granularity.bucket_name(i),
+                    count: count_of(bucket, id),
+                })
+                .collect()
+        };
+
+        CounterTimeSeries {
+            hourly: series(Granularity::Hour),
+            daily: series(Granularity::Day),
+            weekly: series(Granularity::Week),
+            monthly: series(Granularity::Month),
+        }
+    }
+
+    /// Returns the hourly and daily counts of `id` in the order of `time_series`, without
+    /// the bucket names.
+    pub fn sparkline(&self, id: &str) -> Sparkline {
+        let counts = |granularity: Granularity| -> Vec<u64> { self.buckets(granularity).iter().rev().map(|bucket| count_of(bucket, id)).collect() };
+        Sparkline { hourly: counts(Granularity::Hour), daily: counts(Granularity::Day) }
+    }
+
+    /// Sums the counts of `id` between `from` and `to` from whichever buckets intersect
+    /// the range, without counting any period twice: the buckets within the range are
+    /// taken coarsest first, then those reaching beyond it finest first, which are marked
+    /// partial. The buckets' boundaries are those of the rotation `timezone`.
+    pub fn range_count<Tz: TimeZone>(&self, id: &str, from: DateTime<Utc>, to: DateTime<Utc>, timezone: &Tz) -> CounterRange {
+        let Some(rotated_at) = self.last_rotation_at else {
+            return CounterRange::default();
+        };
+        let rotated_at = rotated_at.with_timezone(timezone);
+        // The current buckets only reach until now
+        let now = determinism::now();
+        let mut candidates = Vec::new();
+        for (fineness, granularity) in Granularity::ALL.into_iter().enumerate() {
+            for (index, bucket) in self.buckets(granularity).iter().enumerate() {
+                let (start, end) = granularity.span_before(&rotated_at, index);
+                if start >= to || end.min(now) <= from {
+                    continue;
+                }
+                let partial = start < from || end.min(now) > to;
+                let order = if partial { (1, fineness) } else { (0, Granularity::ALL.len() - fineness) };
+                candidates.push((order, RangeBucket { bucket: granularity.bucket_name(index), from: start, to: end, count: count_of(bucket, id), partial }));
             }
         }
+        candidates.sort_by_key(|(order, _)| *order);
+
+        let mut buckets: Vec<RangeBucket> = Vec::new();
+        for (_, candidate) in candidates {
+            if buckets.iter().all(|chosen| chosen.to <= candidate.from || chosen.from >= candidate.to) {
+                buckets.push(candidate);
+            }
+        }
+        buckets.sort_by_key(|bucket| bucket.from);
+        CounterRange {
+            count: buckets.iter().fold(0, |sum: u64, bucket| sum.saturating_add(bucket.count)),
+            partial: buckets.iter().any(|bucket| bucket.partial),
+            buckets,
+        }
+    }
+
+    /// Looks up a bucket by its public name (see `Granularity::bucket_name`).
+    pub fn bucket(&self, name: &str) -> Option<&Bucket> {
+        let (granularity, index) = Granularity::parse_bucket_name(name)?;
+        self.buckets(granularity).get(index)
+    }
+
+    /// Looks up a bucket or a rolling window (like "last_24h") by name.
+    pub fn window(&self, name: &str) -> Option<Cow<'_, Bucket>> {
+        self.bucket(name).map(Cow::Borrowed).or_else(|| self.rolling_window(name).map(Cow::Owned))
+    }
+
+    /// Returns the buckets the rolling window `name` sums up, or `None` if the window is
+    /// unknown or not enough buckets of its granularity are kept.
+    fn rolling_buckets(&self, name: &str) -> Option<&[Bucket]> {
+        let &(_, granularity, length) = ROLLING_WINDOWS.iter().find(|&&(window, _, _)| window == name)?;
+        self.buckets(granularity).get(..length)
+    }
+
+    /// Sums the buckets of the rolling window `name`, or returns `None` if the window is
+    /// unknown or not enough buckets of its granularity are kept.
+    fn rolling_window(&self, name: &str) -> Option<Bucket> {
+        let buckets = self.rolling_buckets(name)?;
+        let sum = Bucket::new();
+        for entry in buckets.iter().flat_map(|bucket| bucket.iter()) {
+            let mut total = sum.entry(entry.key().clone()).or_insert(0);
+            *total = total.saturating_add(*entry.value());
+        }
+        Some(sum)
+    }
+
+    /// Returns all rolling windows available with the current number of buckets.
+    pub fn rolling_windows(&self) -> Vec<(String, Bucket)> {
+        ROLLING_WINDOWS
+            .iter()
+            .filter_map(|&(name, _, _)| Some((name.to_string(), self.rolling_window(name)?)))
+            .collect()
+    }
+
+    /// Returns the counts of `id` in the rolling windows, summed without copying the
+    /// windows. Windows not available with the current number of buckets are `None`.
+    pub fn rolling_counts(&self, id: &str) -> RollingCounts {
+        let count = |name: &str| self.rolling_buckets(name).map(|buckets| buckets.iter().map(|bucket| count_of(bucket, id)).sum());
+        RollingCounts { last_24h: count("last_24h"), last_48h: count("last_48h"), last_7d: count("last_7d"), last_12d: count("last_12d") }
+    }
+
+    /// Returns all buckets with their public names: hourly buckets first, then daily,
+    /// weekly and monthly ones.
+    pub fn named_buckets(&self) -> Vec<(String, &Bucket)> {
+        Granularity::ALL
+            .into_iter()
+            .flat_map(|granularity| {
+                self.buckets(granularity)
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, bucket)| (granularity.bucket_name(i), bucket))
+            })
+            .collect()
+    }
+}
+
+// Function to reload the buckets from the shared store periodically, so the counts of
+// other instances show up here
+pub async fn run_counter_sync(counters: Arc<RwLock<Counters>>, interval_secs: u64) {
+    let Some(store) = locks::read(&counters, "rotating_counters").store() else {
+        return;
+    };
+    info!("Counter sync started.");
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+
+    loop {
+        interval.tick().await;
+        // Read without holding the lock, so increments go on meanwhile
+        let store = Arc::clone(&store);
+        match web::block(move || store.load()).await {
+            Ok(Ok(buckets)) => locks::write(&counters, "rotating_counters").replace_buckets(buckets),
+            Ok(Err(e)) => warn!("Failed to load counters from the shared store: {}", e),
+            Err(e) => error!("Error in counter sync block: {:?}", e),
+        }
     }
 }
 
-pub async fn perform_final_persistence(counters_arc: Arc<Mutex<Counters>>) {
-    println!("Server shutting down. Attempting final persistence for rotating counters...");
+pub async fn perform_final_persistence(counters_arc: Arc<RwLock<Counters>>) {
+    info!("Server shutting down. Attempting final persistence for rotating counters...");
 
     // Use web::block to run the potentially blocking persistence operation
     // This is crucial to avoid blocking the main Tokio runtime thread during shutdown.
     let persist_result = web::block(move || {
-        if let Ok(mut counters_lock) = counters_arc.lock() {
-            if counters_lock.dirty { // Only persist if there are pending changes
-                println!("Performing final persist for rotating counters...");
-                counters_lock.persist();
-                counters_lock.dirty = false; // Reset dirty flag after final persist
+        if let Ok(mut counters_lock) = counters_arc.write() {
+            if counters_lock.is_dirty() { // Only persist if there are pending changes
+                info!("Performing final persist for rotating counters...");
+                counters_lock.persist(); // Also resets the dirty flag
             } else {
-                println!("No pending changes for rotating counters to persist on shutdown.");
+                warn!("No pending changes for rotating counters to persist on shutdown.");
             }
         } else {
-            eprintln!("Failed to acquire rotating counters lock for final persistence on shutdown.");
+            error!("Failed to acquire rotating counters lock for final persistence on shutdown.");
         }
         Ok::<(), ()>(()) // web::block expects a Result
     })
     .await;
 
     if let Err(e) = persist_result {
-        eprintln!("Error during final rotating counters persistence block: {:?}", e);
+        error!("Error during final rotating counters persistence block: {:?}", e);
     } else {
-        println!("Final rotating counters persistence attempt completed.");
+        info!("Final rotating counters persistence attempt completed.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const LEGACY_JSON: &str = r#"{
+        "this_hour": {"a": 1}, "last_hour": {"a": 2}, "hour_minus_2": {},
+        "today": {"a": 3}, "yesterday": {}, "day_minus_2": {}, "day_minus_3": {},
+        "day_minus_4": {}, "day_minus_5": {}, "day_minus_6": {}, "day_minus_7": {},
+        "day_minus_8": {}, "day_minus_9": {}, "day_minus_10": {}, "day_minus_11": {},
+        "day_minus_12": {"b": 12}
+    }"#;
+
+    #[test]
+    fn test_legacy_format_is_migrated() {
+        let counters: Counters = serde_json::from_str(LEGACY_JSON).unwrap();
+        assert_eq!(counters.hourly.len(), 3);
+        assert_eq!(counters.daily.len(), 13);
+        assert_eq!(count_of(&counters.hourly[1], "a"), 2);
+        assert_eq!(count_of(&counters.daily[0], "a"), 3);
+        assert_eq!(count_of(&counters.daily[12], "b"), 12);
+        assert!(counters.is_dirty());
+        assert_eq!(*counters.first_seen.get("b").unwrap(), DateTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_current_format_roundtrip() {
+        let counters = Counters::with_depths(2, 4, 4, 3);
+        counters.increment("a", 1);
+        let json = serde_json::to_string(&counters).unwrap();
+        let loaded: Counters = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.hourly.len(), 2);
+        assert_eq!(loaded.daily.len(), 4);
+        assert_eq!(count_of(&loaded.daily[0], "a"), 1);
+        assert!(!loaded.is_dirty());
+    }
+
+    #[test]
+    fn test_rotation_shifts_and_drops_oldest() {
+        let mut counters = Counters::with_depths(2, 3, 4, 3);
+        counters.increment("a", 1);
+        counters.rotate(Granularity::Hour, 1);
+        counters.rotate(Granularity::Day, 1);
+        counters.increment("b", 1);
+        counters.rotate(Granularity::Hour, 1);
+
+        assert!(counters.hourly[0].is_empty());
+        assert_eq!(count_of(&counters.hourly[1], "b"), 1);
+        // "a" fell out of the last hourly bucket
+        assert!(counters.hourly.iter().all(|bucket| !bucket.contains_key("a")));
+        assert_eq!(count_of(&counters.daily[0], "b"), 1);
+        assert_eq!(count_of(&counters.daily[1], "a"), 1);
+    }
+
+    #[test]
+    fn test_weighted_increments() {
+        let counters = Counters::with_depths(3, 13, 4, 3);
+        counters.increment("a", 5);
+        counters.increment("a", 1);
+        counters.increment("b", 0);
+
+        assert_eq!(count_of(&counters.hourly[0], "a"), 6);
+        assert_eq!(count_of(&counters.monthly[0], "a"), 6);
+        assert!(!counters.daily[0].contains_key("b"));
+    }
+
+    #[test]
+    fn test_counts_exceed_32_bits() {
+        // A snapshot written while counts were still 32 bit
+        let counters: Counters = serde_json::from_str(r#"{"hourly": [{"a": 4294967295}], "daily": [{}], "weekly": [{}], "monthly": [{}]}"#).unwrap();
+        counters.increment("a", 1);
+        assert_eq!(count_of(&counters.hourly[0], "a"), u32::MAX as u64 + 1);
+
+        let loaded: Counters = serde_json::from_str(&serde_json::to_string(&counters).unwrap()).unwrap();
+        assert_eq!(count_of(&loaded.hourly[0], "a"), 1 << 32);
+    }
+
+    #[test]
+    fn test_weekly_and_monthly_aggregates() {
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        counters.increment("a", 1);
+        counters.rotate(Granularity::Day, 1);
+        counters.increment("a", 1);
+        assert_eq!(count_of(counters.bucket("this_week").unwrap(), "a"), 2);
+        assert_eq!(count_of(counters.bucket("this_month").unwrap(), "a"), 2);
+
+        counters.rotate(Granularity::Week, 1);
+        counters.increment("a", 1);
+        assert_eq!(count_of(counters.bucket("this_week").unwrap(), "a"), 1);
+        assert_eq!(count_of(counters.bucket("last_week").unwrap(), "a"), 2);
+        assert_eq!(count_of(counters.bucket("this_month").unwrap(), "a"), 3);
+
+        counters.rotate(Granularity::Month, 1);
+        assert!(counters.bucket("this_month").unwrap().is_empty());
+        assert_eq!(count_of(counters.bucket("last_month").unwrap(), "a"), 3);
+    }
+
+    #[test]
+    fn test_advance_to_catches_up_missed_boundaries() {
+        let start = Utc.with_ymd_and_hms(2025, 1, 30, 22, 30, 0).unwrap();
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        counters.advance_to(&start);
+        counters.increment("a", 1);
+
+        // Same hour: nothing to do
+        assert!(!counters.advance_to(&Utc.with_ymd_and_hms(2025, 1, 30, 22, 59, 0).unwrap()));
+        assert_eq!(count_of(&counters.hourly[0], "a"), 1);
+
+        // Down from Thursday 22:30 until Saturday 01:10 (next month, same ISO week)
+        let later = Utc.with_ymd_and_hms(2025, 2, 1, 1, 10, 0).unwrap();
+        assert!(counters.advance_to(&later));
+        assert!(counters.hourly.iter().all(|bucket| bucket.is_empty()), "27 hours exceed the 3 hourly buckets");
+        assert_eq!(count_of(&counters.daily[2], "a"), 1);
+        assert_eq!(count_of(&counters.weekly[0], "a"), 1);
+        assert_eq!(count_of(&counters.monthly[1], "a"), 1);
+        assert_eq!(counters.last_rotation_at, Some(later));
+
+        // Time going backwards never rotates
+        assert!(!counters.advance_to(&start));
+    }
+
+    #[test]
+    fn test_range_counts_take_each_period_once() {
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        counters.advance_to(&Utc.with_ymd_and_hms(2025, 1, 30, 22, 30, 0).unwrap());
+        counters.increment("a", 1);
+        counters.advance_to(&Utc.with_ymd_and_hms(2025, 2, 1, 1, 10, 0).unwrap());
+        counters.increment("a", 2);
+
+        // Whole days, then the hours of today; the hour before midnight is in a day already
+        let range = counters.range_count("a", Utc.with_ymd_and_hms(2025, 1, 30, 0, 0, 0).unwrap(), Utc.with_ymd_and_hms(2025, 2, 1, 2, 0, 0).unwrap(), &Utc);
+        let names: Vec<&str> = range.buckets.iter().map(|bucket| bucket.bucket.as_str()).collect();
+        assert_eq!(names, ["day_minus_2", "yesterday", "last_hour", "this_hour"]);
+        assert_eq!((range.count, range.partial), (3, false));
+
+        let range = counters.range_count("a", Utc.with_ymd_and_hms(2025, 1, 30, 12, 0, 0).unwrap(), Utc.with_ymd_and_hms(2025, 2, 1, 2, 0, 0).unwrap(), &Utc);
+        assert_eq!((range.count, range.partial), (3, true));
+        assert!(range.buckets[0].partial);
+    }
+
+    #[test]
+    fn test_day_boundaries_follow_the_rotation_timezone() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        // 22:30 UTC is 23:30 in Berlin (CET)
+        counters.advance_to(&Utc.with_ymd_and_hms(2025, 1, 15, 22, 30, 0).unwrap().with_timezone(&berlin));
+        counters.increment("a", 1);
+
+        // 23:10 UTC is already the next day in Berlin, but not in UTC
+        let after_midnight = Utc.with_ymd_and_hms(2025, 1, 15, 23, 10, 0).unwrap();
+        let mut utc_counters: Counters = serde_json::from_str(&serde_json::to_string(&counters).unwrap()).unwrap();
+        counters.advance_to(&after_midnight.with_timezone(&berlin));
+        utc_counters.advance_to(&after_midnight);
+
+        assert_eq!(count_of(&counters.daily[1], "a"), 1);
+        assert_eq!(count_of(&utc_counters.daily[0], "a"), 1);
+    }
+
+    #[test]
+    fn test_weekday_profile() {
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        // Monday, 2025-01-06
+        counters.advance_to(&Utc.with_ymd_and_hms(2025, 1, 6, 12, 0, 0).unwrap());
+        counters.increment("a", 4);
+        counters.advance_to(&Utc.with_ymd_and_hms(2025, 1, 7, 12, 0, 0).unwrap());
+        counters.increment("a", 1);
+        // Down from Tuesday until the following Tuesday
+        counters.advance_to(&Utc.with_ymd_and_hms(2025, 1, 14, 12, 0, 0).unwrap());
+
+        let averages = counters.weekdays.averages("a");
+        assert_eq!(averages.len(), 7);
+        assert_eq!(averages[0].weekday, "monday");
+        assert_eq!(averages[0].days, 2);
+        assert!((averages[0].average - 2.0).abs() < 1e-9);
+        assert_eq!(averages[1].days, 1);
+        assert!((averages[1].average - 1.0).abs() < 1e-9);
+        assert_eq!(averages[6].days, 1);
+        assert_eq!(averages[6].average, 0.0);
+        assert!(counters.weekdays.averages("unknown").iter().all(|a| a.average == 0.0));
+    }
+
+    #[test]
+    fn test_merge() {
+        let monday = Utc.with_ymd_and_hms(2025, 1, 6, 12, 0, 0).unwrap();
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        counters.advance_to(&monday);
+        counters.increment("a", 2);
+        counters.first_seen.insert("a".to_string(), monday);
+
+        let mut other = Counters::with_depths(3, 2, 4, 3);
+        other.advance_to(&monday);
+        other.increment("a", 3);
+        other.increment("b", 1);
+        other.first_seen.insert("a".to_string(), monday - chrono::Duration::days(1));
+        other.rotate(Granularity::Hour, 1);
+        other.weekdays.record_day(chrono::Weekday::Sun, Some(&other.daily[0]));
+
+        counters.merge(other);
+        assert_eq!(count_of(&counters.daily[0], "a"), 5);
+        assert_eq!(count_of(&counters.hourly[1], "b"), 1);
+        assert_eq!(count_of(&counters.hourly[0], "a"), 2);
+        assert_eq!(*counters.first_seen.get("a").unwrap(), monday - chrono::Duration::days(1));
+        assert_eq!(counters.weekdays.averages("a")[6].average, 3.0);
+        assert_eq!(counters.daily.len(), 13);
+        assert!(counters.is_dirty());
+    }
+
+    #[test]
+    fn test_time_series_is_chronological() {
+        let mut counters = Counters::with_depths(2, 3, 4, 3);
+        counters.increment("a", 1);
+        counters.rotate(Granularity::Hour, 1);
+        counters.rotate(Granularity::Day, 1);
+        counters.increment("a", 1);
+        counters.increment("a", 1);
+
+        let series = counters.time_series("a");
+        let hourly: Vec<(&str, u64)> = series.hourly.iter().map(|p| (p.bucket.as_str(), p.count)).collect();
+        assert_eq!(hourly, [("last_hour", 1), ("this_hour", 2)]);
+        let daily: Vec<(&str, u64)> = series.daily.iter().map(|p| (p.bucket.as_str(), p.count)).collect();
+        assert_eq!(daily, [("day_minus_2", 0), ("yesterday", 1), ("today", 2)]);
+
+        assert!(counters.time_series("unknown").daily.iter().all(|p| p.count == 0));
+        assert_eq!(counters.sparkline("a"), Sparkline { hourly: vec![1, 2], daily: vec![0, 1, 2] });
+    }
+
+    #[test]
+    fn test_bucket_lookup_and_top_entries() {
+        let counters = Counters::with_depths(3, 13, 4, 3);
+        counters.increment("a", 1);
+        counters.increment("b", 1);
+        counters.increment("b", 1);
+        counters.increment("c", 1);
+
+        let today = counters.bucket("today").unwrap();
+        let top = top_entries(today, 0, 2);
+        assert_eq!(top, vec![
+            CountEntry { id: "b".to_string(), count: 2 },
+            CountEntry { id: "a".to_string(), count: 1 },
+        ]);
+        assert_eq!(top_entries(today, 2, 10), vec![CountEntry { id: "c".to_string(), count: 1 }]);
+
+        assert!(counters.bucket("hour_minus_2").is_some());
+        assert!(counters.bucket("day_minus_12").is_some());
+        assert!(counters.bucket("day_minus_13").is_none());
+        assert!(counters.bucket("day_minus_1").is_none());
+        assert!(counters.bucket("tomorrow").is_none());
+    }
+
+    #[test]
+    fn test_concurrent_increments() {
+        let counters = Counters::with_depths(3, 13, 4, 3);
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let counters = &counters;
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        counters.increment(&format!("item{}", i % 10), 1);
+                        counters.increment("shared", 1);
+                    }
+                    counters.increment(&format!("thread{}", thread), 1);
+                });
+            }
+        });
+
+        assert_eq!(count_of(&counters.hourly[0], "shared"), 4000);
+        assert_eq!(count_of(&counters.daily[0], "item3"), 400);
+        assert_eq!(counters.first_seen.len(), 15);
+    }
+
+    #[test]
+    fn test_event_log_replay() {
+        let path = std::env::temp_dir().join(format!("mediathek_counter_replay_{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let monday = Utc.with_ymd_and_hms(2025, 1, 6, 12, 0, 0).unwrap();
+
+        let mut log = EventLog::open(&path, 32, 0).unwrap();
+        log.append(monday, CounterEvent::Increment { id: "a".to_string(), count: 2 }).unwrap();
+        log.append(monday, CounterEvent::Increment { id: "b".to_string(), count: 1 }).unwrap();
+        log.append(monday + chrono::Duration::days(1), CounterEvent::Increment { id: "a".to_string(), count: 5 }).unwrap();
+        log.append(monday + chrono::Duration::days(1), CounterEvent::Remove { id: "b".to_string() }).unwrap();
+
+        // The snapshot already contains the first entry
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        counters.advance_to(&monday);
+        counters.increment("a", 2);
+        *counters.log_sequence.get_mut() = 1;
+
+        assert_eq!(counters.replay(&path, &Tz::UTC), 3);
+        assert_eq!(*counters.log_sequence.get_mut(), 4);
+        assert_eq!(count_of(&counters.daily[0], "a"), 5);
+        assert_eq!(count_of(&counters.daily[1], "a"), 2);
+        assert!(!counters.first_seen.contains_key("b"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_and_reset() {
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        counters.increment("a", 2);
+        counters.increment("b", 1);
+        counters.rotate(Granularity::Hour, 1);
+        counters.increment("a", 1);
+        counters.weekdays.record_day(chrono::Weekday::Mon, Some(&counters.daily[0].clone()));
+        *counters.dirty.get_mut() = false;
+
+        assert!(counters.remove("a"));
+        assert!(counters.is_dirty());
+        assert!(counters.named_buckets().iter().all(|(_, bucket)| !bucket.contains_key("a")));
+        assert_eq!(counters.weekdays.averages("a")[0].average, 0.0);
+        assert!(!counters.first_seen.contains_key("a"));
+        assert!(!counters.remove("a"));
+
+        counters.reset();
+        assert!(counters.named_buckets().iter().all(|(_, bucket)| bucket.is_empty()));
+        assert_eq!(counters.hourly.len(), 3);
+        assert!(counters.first_seen.is_empty());
+    }
+
+    #[test]
+    fn test_rank_in_bucket() {
+        let counters = Counters::with_depths(3, 13, 4, 3);
+        for (id, count) in [("a", 10), ("b", 5), ("c", 5), ("d", 1)] {
+            counters.increment(id, count);
+        }
+        let today = counters.bucket("today").unwrap();
+
+        let rank = rank_in(today, "a").unwrap();
+        assert_eq!((rank.rank, rank.total), (1, 4));
+        assert_eq!(rank.percentile, 75.0);
+        assert_eq!(rank.top_percent, 25.0);
+
+        // Ties share the better rank
+        assert_eq!(rank_in(today, "c").unwrap().rank, 2);
+        assert_eq!(rank_in(today, "d").unwrap().percentile, 0.0);
+        assert!(rank_in(today, "unknown").is_none());
+    }
+
+    #[test]
+    fn test_rolling_windows() {
+        let counters = Counters::with_depths(48, 13, 4, 3);
+        counters.increment("a", 1);
+        counters.hourly[23].insert("a".to_string(), 2);
+        counters.hourly[24].insert("a".to_string(), 4);
+
+        assert_eq!(count_of(&counters.window("last_24h").unwrap(), "a"), 3);
+        assert_eq!(count_of(&counters.window("last_48h").unwrap(), "a"), 7);
+        assert_eq!(count_of(&counters.window("today").unwrap(), "a"), 1);
+        assert_eq!(counters.rolling_windows().len(), 4);
+        counters.daily[6].insert("a".to_string(), 5);
+        counters.daily[7].insert("a".to_string(), 8);
+        assert_eq!(count_of(&counters.window("last_7d").unwrap(), "a"), 6);
+        assert_eq!(
+            counters.rolling_counts("a"),
+            RollingCounts { last_24h: Some(3), last_48h: Some(7), last_7d: Some(6), last_12d: Some(14) }
+        );
+
+        let short = Counters::with_depths(3, 5, 4, 3);
+        assert!(short.window("last_24h").is_none());
+        assert!(short.rolling_windows().is_empty());
+        assert_eq!(short.rolling_counts("a"), RollingCounts::default());
+    }
+
+    #[test]
+    fn test_resize_and_bucket_names() {
+        let mut counters: Counters = serde_json::from_str(LEGACY_JSON).unwrap();
+        counters.resize(&CounterSettings {
+            hourly_buckets: 48,
+            daily_buckets: 7,
+            weekly_buckets: 2,
+            monthly_buckets: 3,
+            rotation_timezone: Tz::UTC,
+            event_log: false,
+            event_log_sync_batch: 32,
+            backend: crate::config::CounterBackend::Memory,
+            redis_url: String::new(),
+            redis_key_prefix: String::new(),
+            gossip_peers: Vec::new(),
+            gossip_node_id: String::new(),
+            sync_interval_secs: 5,
+            persist_interval_secs: 0,
+            minute_buckets: 0,
+            popularity_half_life_secs: 3600,
+            labels: crate::config::BucketLabels::default(),
+        });
+        assert_eq!(counters.hourly.len(), 48);
+        assert_eq!(counters.daily.len(), 7);
+        assert_eq!(counters.weekly.len(), 2);
+
+        let names: Vec<String> = counters.named_buckets().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(&names[..3], ["this_hour", "last_hour", "hour_minus_2"]);
+        assert_eq!(names[48], "today");
+        assert_eq!(names[49], "yesterday");
+        assert_eq!(names[54], "day_minus_6");
+        assert_eq!(&names[55..], ["this_week", "last_week", "this_month", "last_month", "month_minus_2"]);
+        assert!(counters.bucket("month_minus_2").is_some());
+    }
+
+    #[test]
+    fn test_window_versions() {
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        let today = counters.window_version("today").unwrap();
+        let yesterday = counters.window_version("yesterday").unwrap();
+
+        counters.increment("a", 1);
+        assert_ne!(counters.window_version("today"), Some(today));
+        assert_eq!(counters.window_version("yesterday"), Some(yesterday));
+
+        counters.rotate(Granularity::Day, 1);
+        assert_ne!(counters.window_version("yesterday"), Some(yesterday));
+        assert!(counters.window_version("day_minus_13").is_none());
+        assert!(counters.window_version("last_24h").is_none());
+        assert!(counters.window_version("nonsense").is_none());
+    }
+
+    /// Records the changes handed to it, and serves fixed buckets.
+    #[derive(Debug, Default)]
+    struct RecordingStore {
+        changes: Mutex<Vec<String>>,
+    }
+
+    impl CounterStore for RecordingStore {
+        fn increment(&self, id: &str, amount: u64) -> Result<(), String> {
+            self.changes.lock().unwrap().push(format!("increment {} {}", id, amount));
+            Ok(())
+        }
+        fn add(&self, granularity: Granularity, index: usize, bucket: &Bucket) -> Result<(), String> {
+            self.changes.lock().unwrap().push(format!("add {} {} {}", granularity.series_name(), index, bucket.len()));
+            Ok(())
+        }
+        fn rotate(&self, granularity: Granularity, steps: usize, period: &str) -> Result<bool, String> {
+            self.changes.lock().unwrap().push(format!("rotate {} {} {}", granularity.series_name(), steps, period));
+            Ok(true)
+        }
+        fn remove(&self, id: &str) -> Result<(), String> {
+            self.changes.lock().unwrap().push(format!("remove {}", id));
+            Ok(())
+        }
+        fn reset(&self) -> Result<(), String> {
+            Err("unavailable".to_string())
+        }
+        fn load(&self) -> Result<Vec<Vec<Bucket>>, String> {
+            Ok(vec![vec![Bucket::from_iter([("shared".to_string(), 7)])]])
+        }
+    }
+
+    #[test]
+    fn test_changes_are_mirrored_to_the_store() {
+        let store = Arc::new(RecordingStore::default());
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        counters.store = Some(store.clone());
+        counters.advance_to(&Utc.with_ymd_and_hms(2025, 1, 5, 23, 30, 0).unwrap());
+        counters.increment("a", 2);
+        counters.advance_to(&Utc.with_ymd_and_hms(2025, 1, 6, 0, 10, 0).unwrap());
+        counters.remove("a");
+        // A failing store doesn't keep the local counts from changing
+        counters.increment("b", 1);
+        counters.reset();
+        assert!(counters.daily[0].is_empty());
+
+        assert_eq!(
+            *store.changes.lock().unwrap(),
+            [
+                "increment a 2",
+                "rotate hourly 1 482256",
+                "rotate daily 1 2025-01-06",
+                "rotate weekly 1 2025-W02",
+                "remove a",
+                "increment b 1",
+            ]
+        );
+
+        counters.replace_buckets(store.load().unwrap());
+        assert_eq!(count_of(&counters.hourly[0], "shared"), 7);
+        // Granularities the store returned nothing for are kept
+        assert_eq!(counters.daily.len(), 13);
     }
 }
@@ -0,0 +1,373 @@
+// src/algorithms/scoring.rs
+use std::collections::{HashMap, HashSet};
+
+use crate::algorithms::boosts::Boost;
+use crate::algorithms::rotating_counters::{top_entries, Bucket};
+use crate::config::{ScoringSettings, ScoringStage};
+
+// Ranking of recommendation candidates in stages. The models propose candidates in their
+// order (rules before factorization before co-occurrences); every stage of a pipeline then
+// rescores them, after which they are sorted by score again, so a stage sees the ranking
+// of the stages before. Which stages run is configured per endpoint or experiment variant
+// (`MEDIATHEK_SCORING_PIPELINES`), so a formula can be tried without a new build.
+// Editorial boosts (see `boosts`) apply after all stages, so no formula outranks a pin.
+
+/// A recommendation candidate as the stages see it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub identifier: String,
+    /// Score of the model that proposed the candidate, in the model's own measure
+    pub model_score: f64,
+    /// The model: "rules", "factorization", "co_occurrence" or "two_hop"; "editorial" for
+    /// items only pinned by an editor, "popularity" for the fallback of cross-namespace
+    /// discovery
+    pub source: &'static str,
+    /// The score ranked by, set by the stages
+    pub score: f64,
+    /// Number of lists shared with the input items
+    pub pair_count: u64,
+    /// Plays today
+    pub popularity: u64,
+    /// Number of lists the candidate was in, 0 if unknown
+    pub occurrences: u64,
+    /// What the stages did to the score, in order
+    pub adjustments: Vec<String>,
+}
+
+impl Candidate {
+    pub fn new(identifier: String, model_score: f64, source: &'static str) -> Self {
+        Candidate { identifier, model_score, source, score: model_score, pair_count: 0, popularity: 0, occurrences: 0, adjustments: Vec::new() }
+    }
+}
+
+/// A stage of a scoring pipeline.
+pub trait Scorer: Send + Sync {
+    /// Rescores `candidates`, which come ranked by the stages before.
+    fn score(&self, candidates: &mut [Candidate]);
+}
+
+/// Turns the order of the models into a score from 1 down, comparable across models.
+pub struct SimilarityScorer;
+
+impl Scorer for SimilarityScorer {
+    fn score(&self, candidates: &mut [Candidate]) {
+        let len = candidates.len() as f64;
+        for (rank, candidate) in candidates.iter_mut().enumerate() {
+            candidate.score = 1.0 - rank as f64 / len;
+        }
+    }
+}
+
+/// Blends today's plays, relative to the most played candidate, into the score.
+pub struct PopularityScorer {
+    pub weight: f64,
+}
+
+impl Scorer for PopularityScorer {
+    fn score(&self, candidates: &mut [Candidate]) {
+        let Some(most) = candidates.iter().map(|candidate| candidate.popularity).max().filter(|&most| most > 0) else {
+            return;
+        };
+        for candidate in candidates.iter_mut() {
+            let popularity = candidate.popularity as f64 / most as f64;
+            let score = (1.0 - self.weight) * candidate.score + self.weight * popularity;
+            candidate.adjustments.push(format!("popularity {:+.3}", score - candidate.score));
+            candidate.score = score;
+        }
+    }
+}
+
+/// Scales down candidates sharing fewer than `min_pair_count` lists with the input, whose
+/// scores rest on little evidence.
+pub struct PenaltyScorer {
+    pub min_pair_count: u64,
+    pub factor: f64,
+}
+
+impl Scorer for PenaltyScorer {
+    fn score(&self, candidates: &mut [Candidate]) {
+        for candidate in candidates.iter_mut().filter(|candidate| candidate.pair_count < self.min_pair_count) {
+            candidate.score *= self.factor;
+            candidate.adjustments.push(format!("penalty x{} (pair count {} < {})", self.factor, candidate.pair_count, self.min_pair_count));
+        }
+    }
+}
+
+/// Scales down every further candidate of the same prefix ("news" of "news:123") once
+/// more, so a single category doesn't fill all slots. Identifiers without one are left
+/// alone.
+pub struct DiversityScorer {
+    pub factor: f64,
+}
+
+impl Scorer for DiversityScorer {
+    fn score(&self, candidates: &mut [Candidate]) {
+        let mut seen: HashMap<String, i32> = HashMap::new();
+        for candidate in candidates.iter_mut() {
+            let Some(prefix) = namespace_of(&candidate.identifier) else {
+                continue;
+            };
+            let before = seen.entry(prefix.to_string()).or_insert(0);
+            if *before > 0 {
+                let factor = self.factor.powi(*before);
+                candidate.score *= factor;
+                candidate.adjustments.push(format!("diversity x{:.3} ({} before of '{}')", factor, before, prefix));
+            }
+            *before += 1;
+        }
+    }
+}
+
+/// Scales down candidates in many lists, like a news show co-occurring with everything, so
+/// the recommendations are more specific to the input: by the list count of the least
+/// frequent candidate relative to their own, to the power of `weight`. Candidates with an
+/// unknown list count are left alone.
+pub struct InverseFrequencyScorer {
+    pub weight: f64,
+}
+
+impl Scorer for InverseFrequencyScorer {
+    fn score(&self, candidates: &mut [Candidate]) {
+        let Some(fewest) = candidates.iter().map(|candidate| candidate.occurrences).filter(|&lists| lists > 0).min() else {
+            return;
+        };
+        for candidate in candidates.iter_mut().filter(|candidate| candidate.occurrences > fewest) {
+            let factor = (fewest as f64 / candidate.occurrences as f64).powf(self.weight);
+            candidate.score *= factor;
+            candidate.adjustments.push(format!("idf x{:.3} ({} lists)", factor, candidate.occurrences));
+        }
+    }
+}
+
+/// The namespace of an identifier, the prefix before the first ':' ("news" of "news:123"),
+/// usually the broadcaster or category.
+pub fn namespace_of(identifier: &str) -> Option<&str> {
+    identifier.split_once(':').map(|(namespace, _)| namespace)
+}
+
+/// Cross-namespace discovery ("something different"): only identifiers of a namespace none
+/// of the seeds belongs to qualify. Identifiers without a namespace never do, as nothing
+/// tells them apart from the seeds.
+#[derive(Debug, Default)]
+pub struct NamespaceFilter {
+    seeds: HashSet<String>,
+}
+
+impl NamespaceFilter {
+    pub fn new<'a>(seeds: impl IntoIterator<Item = &'a str>) -> Self {
+        NamespaceFilter { seeds: seeds.into_iter().filter_map(namespace_of).map(str::to_string).collect() }
+    }
+
+    pub fn accepts(&self, identifier: &str) -> bool {
+        namespace_of(identifier).is_some_and(|namespace| !self.seeds.contains(namespace))
+    }
+
+    /// The most played identifiers of `bucket` that are accepted, and pass `allowed` (e.g.
+    /// aren't excluded or candidates already), as candidates of their own: few neighbors
+    /// of a seed usually come from other namespaces, so they fill the slots left.
+    pub fn popular(&self, bucket: &Bucket, limit: usize, allowed: impl Fn(&str) -> bool) -> Vec<Candidate> {
+        top_entries(bucket, 0, usize::MAX)
+            .into_iter()
+            .filter(|entry| self.accepts(&entry.id) && allowed(&entry.id))
+            .take(limit)
+            .map(|entry| Candidate { popularity: entry.count, ..Candidate::new(entry.id, entry.count as f64, "popularity") })
+            .collect()
+    }
+}
+
+/// Bounds on the plays today of the candidates (`min_popularity` and `max_popularity`),
+/// e.g. to leave barely watched items or mega-hits out of a rail. Pinned items are
+/// exempt, as editors put them there on purpose.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PopularityRange {
+    min: Option<u64>,
+    max: Option<u64>,
+}
+
+impl PopularityRange {
+    pub fn new(min: Option<u64>, max: Option<u64>) -> Result<Self, String> {
+        match (min, max) {
+            (Some(min), Some(max)) if min > max => Err(format!("min_popularity {} exceeds max_popularity {}", min, max)),
+            _ => Ok(PopularityRange { min, max }),
+        }
+    }
+
+    pub fn accepts(&self, popularity: u64) -> bool {
+        self.min.is_none_or(|min| popularity >= min) && self.max.is_none_or(|max| popularity <= max)
+    }
+
+    /// Removes the candidates whose plays today (`popularity`, set before) are out of range.
+    pub fn retain(&self, candidates: &mut Vec<Candidate>) {
+        candidates.retain(|candidate| self.accepts(candidate.popularity));
+    }
+}
+
+/// Multiplies the scores of boosted candidates by their factor.
+pub struct BoostScorer {
+    pub boosts: Vec<Boost>,
+}
+
+impl Scorer for BoostScorer {
+    fn score(&self, candidates: &mut [Candidate]) {
+        for boost in &self.boosts {
+            let factor = boost.factor.unwrap_or(1.0);
+            if let Some(candidate) = candidates.iter_mut().find(|candidate| candidate.identifier == boost.identifier) {
+                candidate.score *= factor;
+                candidate.adjustments.push(format!("boost x{}", factor));
+            }
+        }
+    }
+}
+
+/// The stages an endpoint ranks its candidates with.
+pub struct Pipeline {
+    stages: Vec<Box<dyn Scorer>>,
+    /// Boosts pinning an item, by position
+    pins: Vec<Boost>,
+}
+
+impl Pipeline {
+    pub fn new(stages: &[ScoringStage]) -> Self {
+        let stages = stages
+            .iter()
+            .map(|stage| -> Box<dyn Scorer> {
+                match *stage {
+                    ScoringStage::Similarity => Box::new(SimilarityScorer),
+                    ScoringStage::Popularity { weight } => Box::new(PopularityScorer { weight }),
+                    ScoringStage::Penalty { min_pair_count, factor } => Box::new(PenaltyScorer { min_pair_count, factor }),
+                    ScoringStage::Diversity { factor } => Box::new(DiversityScorer { factor }),
+                    ScoringStage::Idf { weight } => Box::new(InverseFrequencyScorer { weight }),
+                }
+            })
+            .collect();
+        Pipeline { stages, pins: Vec::new() }
+    }
+
+    /// Applies `boosts` after the stages: factors first, then pins.
+    pub fn with_boosts(mut self, boosts: Vec<Boost>) -> Self {
+        let factors: Vec<Boost> = boosts.iter().filter(|boost| boost.factor.is_some()).cloned().collect();
+        if !factors.is_empty() {
+            self.stages.push(Box::new(BoostScorer { boosts: factors }));
+        }
+        self.pins = boosts.into_iter().filter(|boost| boost.pin_position.is_some()).collect();
+        self.pins.sort_by_key(|boost| boost.pin_position);
+        self
+    }
+
+    /// The pipeline of `variant` if given, else the one of `endpoint`, else similarity
+    /// alone. Fails for unknown variants rather than quietly ranking an experiment's
+    /// requests like all others.
+    pub fn for_endpoint(settings: &ScoringSettings, endpoint: &str, variant: Option<&str>) -> Result<Self, String> {
+        let stages = match variant {
+            Some(variant) => settings.pipelines.0.get(variant).ok_or_else(|| format!("Unknown scoring variant '{}'", variant))?,
+            None => settings.pipelines.0.get(endpoint).map_or(&[ScoringStage::Similarity][..], Vec::as_slice),
+        };
+        Ok(Pipeline::new(stages))
+    }
+
+    /// Runs every stage on `candidates`, sorting them by score (descending, ties in their
+    /// previous order) after each, then moves the pinned items into their slots, adding
+    /// those no model proposed. Callers remove the items that mustn't be recommended, e.g.
+    /// the input's, afterwards.
+    pub fn rank(&self, candidates: &mut Vec<Candidate>) {
+        for stage in &self.stages {
+            stage.score(candidates);
+            candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        }
+        // By position, so pins further down don't shift those above them
+        for pin in &self.pins {
+            let position = pin.pin_position.unwrap_or(1).max(1) - 1;
+            let mut candidate = match candidates.iter().position(|candidate| candidate.identifier == pin.identifier) {
+                Some(index) => candidates.remove(index),
+                None => Candidate::new(pin.identifier.clone(), 0.0, "editorial"),
+            };
+            candidate.adjustments.push(format!("pinned at {}", position + 1));
+            candidates.insert(position.min(candidates.len()), candidate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(identifier: &str, pair_count: u64, popularity: u64) -> Candidate {
+        Candidate { pair_count, popularity, ..Candidate::new(identifier.to_string(), 0.5, "co_occurrence") }
+    }
+
+    fn identifiers(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|candidate| candidate.identifier.as_str()).collect()
+    }
+
+    #[test]
+    fn test_stages_rerank_in_order() {
+        let mut candidates = vec![candidate("news:a", 9, 0), candidate("news:b", 9, 0), candidate("kids:c", 1, 50), candidate("kids:d", 9, 10)];
+        Pipeline::new(&[ScoringStage::Similarity]).rank(&mut candidates);
+        assert_eq!(identifiers(&candidates), ["news:a", "news:b", "kids:c", "kids:d"]);
+
+        let stages: Vec<ScoringStage> = ["similarity", "popularity:0.5", "penalty:2:0.1", "diversity:0.5"].iter().map(|stage| stage.parse().unwrap()).collect();
+        Pipeline::new(&stages).rank(&mut candidates);
+        // "kids:c" gains the most by popularity, but rests on a single list; "news:b" is
+        // the second of its prefix
+        assert_eq!(identifiers(&candidates), ["news:a", "kids:d", "news:b", "kids:c"]);
+        assert_eq!(candidates[3].adjustments.len(), 3);
+
+        let settings = ScoringSettings { pipelines: "fresh=similarity>diversity:0.5".parse().unwrap(), shadow: Default::default(), shrinkage: Default::default() };
+        assert!(Pipeline::for_endpoint(&settings, "recommendations", Some("fresh")).is_ok());
+        assert!(Pipeline::for_endpoint(&settings, "recommendations", Some("other")).is_err());
+        assert!("x=popularity:2".parse::<crate::config::ScoringPipelines>().is_err());
+    }
+
+    #[test]
+    fn test_idf_scales_down_frequent_candidates() {
+        let mut candidates = vec![candidate("hit", 9, 0), candidate("niche", 2, 0), candidate("pinned", 0, 0)];
+        candidates[0].occurrences = 400;
+        candidates[1].occurrences = 4;
+        let stages: Vec<ScoringStage> = ["similarity", "idf:0.5"].iter().map(|stage| stage.parse().unwrap()).collect();
+        Pipeline::new(&stages).rank(&mut candidates);
+        // The hit keeps a tenth of its score, the one of unknown frequency all of it
+        assert_eq!(identifiers(&candidates), ["niche", "pinned", "hit"]);
+        assert!((candidates[2].score - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_namespace_filter_keeps_other_namespaces() {
+        let filter = NamespaceFilter::new(["ard:1", "ard:2", "plain"]);
+        assert!(filter.accepts("zdf:3"));
+        assert!(!filter.accepts("ard:4"));
+        assert!(!filter.accepts("other"));
+
+        let bucket = Bucket::new();
+        for (id, count) in [("ard:5", 9), ("zdf:6", 5), ("arte:7", 7), ("zdf:8", 3), ("x", 8)] {
+            bucket.insert(id.to_string(), count);
+        }
+        let popular = filter.popular(&bucket, 2, |id| id != "arte:7");
+        assert_eq!(identifiers(&popular), ["zdf:6", "zdf:8"]);
+        assert_eq!((popular[0].source, popular[0].popularity), ("popularity", 5));
+    }
+
+    #[test]
+    fn test_popularity_range_bounds_the_plays_today() {
+        let mut candidates: Vec<Candidate> =
+            [("rare", 1), ("solid", 20), ("hit", 5000)].into_iter().map(|(id, popularity)| Candidate { popularity, ..Candidate::new(id.to_string(), 1.0, "co_occurrence") }).collect();
+        PopularityRange::new(Some(5), Some(1000)).unwrap().retain(&mut candidates);
+        assert_eq!(identifiers(&candidates), ["solid"]);
+        assert!(PopularityRange::new(None, Some(1000)).unwrap().accepts(0));
+        assert!(PopularityRange::new(Some(10), Some(5)).is_err());
+    }
+
+    #[test]
+    fn test_boosts_multiply_and_pins_take_their_slot() {
+        let boost = |identifier: &str, factor: Option<f64>, pin_position: Option<usize>| Boost {
+            identifier: identifier.to_string(),
+            factor,
+            pin_position,
+            expires_at: chrono::DateTime::UNIX_EPOCH,
+        };
+        let mut candidates = vec![candidate("a", 1, 0), candidate("b", 1, 0), candidate("c", 1, 0)];
+        let boosts = vec![boost("c", Some(10.0), None), boost("x", None, Some(1)), boost("a", None, Some(9))];
+        Pipeline::new(&[ScoringStage::Similarity]).with_boosts(boosts).rank(&mut candidates);
+        assert_eq!(identifiers(&candidates), ["x", "c", "b", "a"]);
+        assert_eq!(candidates[0].source, "editorial");
+    }
+}
@@ -0,0 +1,80 @@
+// src/algorithms/session_dedup.rs
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use ahash::RandomState;
+use chrono::{DateTime, Duration, Utc};
+use lru::LruCache;
+
+use crate::config::SessionDedupSettings;
+use crate::{determinism, locks};
+
+/// Hashes of recently ingested lists, so that the same list submitted again within the
+/// window (reload loops, clients retrying without an idempotency key) isn't counted twice.
+/// Only exact duplicates, in the same order, are caught. The least recently seen hashes
+/// make room for new ones when full.
+#[derive(Debug)]
+pub struct SessionDedup {
+    /// When each list was last seen, `None` if disabled
+    seen: Option<Mutex<LruCache<u64, DateTime<Utc>>>>,
+    window: Duration,
+    hasher: RandomState,
+}
+
+impl SessionDedup {
+    pub fn new(settings: &SessionDedupSettings) -> Self {
+        SessionDedup {
+            seen: NonZeroUsize::new(settings.capacity).map(|capacity| Mutex::new(LruCache::new(capacity))),
+            window: Duration::seconds(settings.window_secs as i64),
+            hasher: determinism::random_state(),
+        }
+    }
+
+    /// Records `identifiers` as seen at `now`. Returns `true` if the same list was seen
+    /// within the window before, so it should be skipped. A list repeated continuously
+    /// stays a duplicate, as every sighting restarts its window.
+    pub fn is_duplicate<S: AsRef<str>>(&self, identifiers: &[S], now: DateTime<Utc>) -> bool {
+        let Some(seen) = &self.seen else {
+            return false;
+        };
+        let hash = self.hash_of(identifiers);
+        let mut seen = locks::lock(seen, "session_dedup");
+        let duplicate = seen.get(&hash).is_some_and(|&seen_at| now - seen_at < self.window);
+        seen.put(hash, now);
+        duplicate
+    }
+
+    /// Whether `is_duplicate` would skip `identifiers` at `now`, without recording them.
+    pub fn would_skip<S: AsRef<str>>(&self, identifiers: &[S], now: DateTime<Utc>) -> bool {
+        let Some(seen) = &self.seen else {
+            return false;
+        };
+        let hash = self.hash_of(identifiers);
+        locks::lock(seen, "session_dedup").peek(&hash).is_some_and(|&seen_at| now - seen_at < self.window)
+    }
+
+    fn hash_of<S: AsRef<str>>(&self, identifiers: &[S]) -> u64 {
+        self.hasher.hash_one(identifiers.iter().map(AsRef::as_ref).collect::<Vec<&str>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_exact_lists_within_the_window_are_duplicates() {
+        let dedup = SessionDedup::new(&SessionDedupSettings { capacity: 10, window_secs: 60 });
+        let now = DateTime::UNIX_EPOCH;
+        assert!(!dedup.is_duplicate(&["a", "b"], now));
+        assert!(dedup.is_duplicate(&["a", "b"], now + Duration::seconds(59)));
+        // Another order is another session
+        assert!(!dedup.is_duplicate(&["b", "a"], now + Duration::seconds(59)));
+        assert!(!dedup.is_duplicate(&["a", "b"], now + Duration::seconds(200)));
+        // Checked without being recorded
+        assert!(dedup.would_skip(&["a", "b"], now + Duration::seconds(201)));
+        assert!(!dedup.would_skip(&["c"], now) && !dedup.is_duplicate(&["c"], now));
+
+        let disabled = SessionDedup::new(&SessionDedupSettings { capacity: 0, window_secs: 60 });
+        assert!(!disabled.is_duplicate(&["a"], now) && !disabled.is_duplicate(&["a"], now));
+    }
+}
@@ -0,0 +1,195 @@
+// src/algorithms/shadow.rs
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::algorithms::boosts::Boost;
+use crate::algorithms::scoring::{Candidate, Pipeline};
+use crate::config::{ScoringSettings, ScoringStage};
+use crate::locks;
+
+/// Agreement of the shadow pipelines (`MEDIATHEK_SCORING_SHADOW`) with the served rankings,
+/// per endpoint. A shadow pipeline ranks a copy of every request's candidates, and only
+/// the comparison is kept, so the responses are the same as without it.
+#[derive(Debug, Default)]
+pub struct ShadowScoring {
+    endpoints: Mutex<HashMap<String, Agreement>>,
+}
+
+/// Sums over the requests compared since the shadow pipeline of an endpoint was set.
+#[derive(Debug)]
+struct Agreement {
+    stages: Vec<ScoringStage>,
+    since: DateTime<Utc>,
+    requests: u64,
+    top_matches: u64,
+    overlap: f64,
+    displacement: f64,
+    /// Requests with items in both rankings, the only ones with a displacement
+    displaced_requests: u64,
+}
+
+/// How far the rankings of a shadow pipeline agreed with the served ones.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ShadowAgreement {
+    /// The stages of the shadow pipeline, e.g. "similarity>idf:0.5"
+    pub shadow: String,
+    /// Start of the comparison; restarts when the shadow pipeline changes
+    pub since: DateTime<Utc>,
+    pub requests: u64,
+    /// Share of the requests with the same first item
+    pub top_agreement: f64,
+    /// Average share of the served items the shadow ranking returned as well
+    pub overlap: f64,
+    /// Average number of positions the items of both rankings moved
+    pub mean_rank_difference: f64,
+}
+
+/// The shadow pipeline of an endpoint, with the boosts of the served one.
+pub struct ShadowPipeline {
+    endpoint: &'static str,
+    stages: Vec<ScoringStage>,
+    pipeline: Pipeline,
+}
+
+impl ShadowPipeline {
+    /// The shadow pipeline of `endpoint`, `None` if it has none.
+    pub fn for_endpoint(settings: &ScoringSettings, endpoint: &'static str, boosts: Vec<Boost>) -> Option<Self> {
+        let stages = settings.shadow.0.get(endpoint)?.clone();
+        let pipeline = Pipeline::new(&stages).with_boosts(boosts);
+        Some(ShadowPipeline { endpoint, stages, pipeline })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ShadowReport {
+    pub endpoints: BTreeMap<String, ShadowAgreement>,
+}
+
+impl ShadowScoring {
+    pub fn new() -> Self {
+        ShadowScoring::default()
+    }
+
+    /// Ranks `candidates`, unranked, with `shadow` as the served ones were, keeps the
+    /// first `limit` that pass `allowed`, and records how far they agree with the `served`
+    /// identifiers.
+    pub fn compare(
+        &self,
+        shadow: &ShadowPipeline,
+        mut candidates: Vec<Candidate>,
+        allowed: impl Fn(&str) -> bool,
+        served: &[&str],
+        limit: usize,
+        now: DateTime<Utc>,
+    ) {
+        let (endpoint, stages) = (shadow.endpoint, shadow.stages.as_slice());
+        shadow.pipeline.rank(&mut candidates);
+        let ranked: Vec<&str> =
+            candidates.iter().map(|candidate| candidate.identifier.as_str()).filter(|identifier| allowed(identifier)).take(limit).collect();
+        let comparison = compare(served, &ranked);
+        debug!(endpoint, overlap = comparison.overlap, rank_difference = ?comparison.displacement, "Compared the shadow ranking.");
+
+        let mut endpoints = locks::lock(&self.endpoints, "shadow_scoring");
+        let agreement = endpoints.entry(endpoint.to_string()).or_insert_with(|| Agreement::new(stages, now));
+        if agreement.stages != stages {
+            *agreement = Agreement::new(stages, now);
+        }
+        agreement.requests += 1;
+        agreement.top_matches += u64::from(comparison.top_match);
+        agreement.overlap += comparison.overlap;
+        if let Some(displacement) = comparison.displacement {
+            agreement.displacement += displacement;
+            agreement.displaced_requests += 1;
+        }
+    }
+
+    pub fn report(&self) -> ShadowReport {
+        let endpoints = locks::lock(&self.endpoints, "shadow_scoring");
+        let average = |sum: f64, count: u64| if count == 0 { 0.0 } else { sum / count as f64 };
+        let endpoints = endpoints
+            .iter()
+            .map(|(endpoint, agreement)| {
+                let report = ShadowAgreement {
+                    shadow: agreement.stages.iter().map(ToString::to_string).collect::<Vec<_>>().join(">"),
+                    since: agreement.since,
+                    requests: agreement.requests,
+                    top_agreement: average(agreement.top_matches as f64, agreement.requests),
+                    overlap: average(agreement.overlap, agreement.requests),
+                    mean_rank_difference: average(agreement.displacement, agreement.displaced_requests),
+                };
+                (endpoint.clone(), report)
+            })
+            .collect();
+        ShadowReport { endpoints }
+    }
+}
+
+impl Agreement {
+    fn new(stages: &[ScoringStage], since: DateTime<Utc>) -> Self {
+        Agreement { stages: stages.to_vec(), since, requests: 0, top_matches: 0, overlap: 0.0, displacement: 0.0, displaced_requests: 0 }
+    }
+}
+
+struct Comparison {
+    top_match: bool,
+    overlap: f64,
+    displacement: Option<f64>,
+}
+
+/// Compares two rankings: whether they start alike, the share of the longer one in
+/// both (1 if both are empty), and the average distance of the positions of the items
+/// in both, `None` without any.
+fn compare(served: &[&str], shadow: &[&str]) -> Comparison {
+    let positions: HashMap<&str, usize> = shadow.iter().enumerate().map(|(position, &identifier)| (identifier, position)).collect();
+    let distances: Vec<usize> =
+        served.iter().enumerate().filter_map(|(position, identifier)| positions.get(identifier).map(|&other| position.abs_diff(other))).collect();
+    let longer = served.len().max(shadow.len());
+    Comparison {
+        top_match: served.first() == shadow.first(),
+        overlap: if longer == 0 { 1.0 } else { distances.len() as f64 / longer as f64 },
+        displacement: (!distances.is_empty()).then(|| distances.iter().sum::<usize>() as f64 / distances.len() as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(identifier: &str, popularity: u64) -> Candidate {
+        Candidate { popularity, ..Candidate::new(identifier.to_string(), 0.5, "co_occurrence") }
+    }
+
+    fn pipeline(shadow: &str) -> ShadowPipeline {
+        let settings = ScoringSettings { pipelines: Default::default(), shadow: shadow.parse().unwrap(), shrinkage: Default::default() };
+        ShadowPipeline::for_endpoint(&settings, "recommendations", Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn test_shadow_rankings_are_compared_with_the_served_ones() {
+        let shadow = ShadowScoring::new();
+        let popular = pipeline("recommendations=similarity>popularity:1");
+        let candidates = vec![candidate("a", 0), candidate("b", 5), candidate("c", 9), candidate("d", 1)];
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        // The shadow ranks c, b, d, a, of which a is excluded
+        shadow.compare(&popular, candidates.clone(), |identifier| identifier != "a", &["b", "c", "d"], 3, now);
+        let report = shadow.report();
+        let agreement = &report.endpoints["recommendations"];
+        assert_eq!(agreement.shadow, "similarity>popularity:1");
+        assert_eq!((agreement.requests, agreement.top_agreement, agreement.overlap), (1, 0.0, 1.0));
+        assert!((agreement.mean_rank_difference - 2.0 / 3.0).abs() < 1e-9);
+
+        shadow.compare(&popular, candidates.clone(), |_| true, &["c", "x"], 2, now);
+        assert_eq!(shadow.report().endpoints["recommendations"].top_agreement, 0.5);
+
+        // A different shadow pipeline starts over
+        let later = now + chrono::Duration::hours(1);
+        shadow.compare(&pipeline("recommendations=similarity"), candidates, |_| true, &["a"], 1, later);
+        let agreement = &shadow.report().endpoints["recommendations"];
+        assert_eq!((agreement.requests, agreement.top_agreement, agreement.since), (1, 1.0, later));
+    }
+}
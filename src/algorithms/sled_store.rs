@@ -0,0 +1,256 @@
+// src/algorithms/sled_store.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use lru::LruCache;
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use sled::{Db, Tree};
+
+use crate::algorithms::co_occurrence::{PairState, PairStore};
+use crate::config::StorageSettings;
+use crate::locks;
+
+/// Key of the number of distinct pairs in the `meta` tree.
+const PAIR_COUNT_KEY: &[u8] = b"pair_count";
+
+/// Keeps the co-occurrence matrix in an embedded sled database instead of in memory, so
+/// it can grow beyond the RAM. Every pair is stored under both orders of its IDs
+/// (`<id>` `<other id>`, big-endian), so all co-occurrences of an identifier are one
+/// prefix scan. The rows of the most recently requested identifiers are kept in memory
+/// and updated in place by new lists, so hot items don't hit the disk.
+///
+/// Lists are written in one transaction each; sled flushes them to disk every 500ms.
+pub struct SledStore {
+    db: Db,
+    /// Identifier to ID
+    identifiers: Tree,
+    /// Pair of IDs to count
+    pairs: Tree,
+    meta: Tree,
+    /// Number of distinct pairs, also kept in `meta`
+    pair_count: AtomicU64,
+    /// Co-occurrences of hot IDs, by the other ID; `None` if disabled
+    hot_rows: Option<Mutex<LruCache<u32, HashMap<u32, u64>>>>,
+}
+
+impl fmt::Debug for SledStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SledStore").field("pair_count", &self.pair_count).finish()
+    }
+}
+
+fn pair_key(id: u32, other: u32) -> [u8; 8] {
+    let mut key = [0; 8];
+    key[..4].copy_from_slice(&id.to_be_bytes());
+    key[4..].copy_from_slice(&other.to_be_bytes());
+    key
+}
+
+fn decode_u32(bytes: &[u8]) -> Result<u32, String> {
+    bytes.try_into().map(u32::from_be_bytes).map_err(|_| format!("Invalid ID of {} bytes", bytes.len()))
+}
+
+fn decode_u64(bytes: &[u8]) -> Result<u64, String> {
+    bytes.try_into().map(u64::from_be_bytes).map_err(|_| format!("Invalid count of {} bytes", bytes.len()))
+}
+
+impl SledStore {
+    /// Opens (or creates) the database in the directory at `path`.
+    pub fn open(path: &Path, settings: &StorageSettings) -> Result<Self, String> {
+        let db = sled::Config::new()
+            .path(path)
+            .cache_capacity(settings.pairs_page_cache_mb.saturating_mul(1024 * 1024))
+            .open()
+            .map_err(|e| e.to_string())?;
+        let open_tree = |name: &str| db.open_tree(name).map_err(|e| e.to_string());
+        let (identifiers, pairs, meta) = (open_tree("identifiers")?, open_tree("pairs")?, open_tree("meta")?);
+        let pair_count = match meta.get(PAIR_COUNT_KEY).map_err(|e| e.to_string())? {
+            Some(count) => decode_u64(&count)?,
+            None => 0,
+        };
+        Ok(SledStore {
+            db,
+            identifiers,
+            pairs,
+            meta,
+            pair_count: AtomicU64::new(pair_count),
+            hot_rows: NonZeroUsize::new(settings.pairs_hot_cache).map(|capacity| Mutex::new(LruCache::new(capacity))),
+        })
+    }
+
+    /// Reads all co-occurrences of `id` from disk.
+    fn read_row(&self, id: u32) -> Result<HashMap<u32, u64>, String> {
+        self.pairs
+            .scan_prefix(id.to_be_bytes())
+            .map(|entry| {
+                let (key, count) = entry.map_err(|e| e.to_string())?;
+                Ok((decode_u32(&key[4..])?, decode_u64(&count)?))
+            })
+            .collect()
+    }
+}
+
+impl Drop for SledStore {
+    fn drop(&mut self) {
+        // Writes the lists of the last moments, which the periodic flush hasn't yet
+        let _ = self.db.flush();
+    }
+}
+
+impl PairStore for SledStore {
+    fn add_list(&self, new_identifiers: &[(String, u32)], pairs: &[(u32, u32)], weight: u64) -> Result<(), String> {
+        // Every pair once, as a list may contain it repeatedly
+        let mut increments: HashMap<(u32, u32), u64> = HashMap::new();
+        for &pair in pairs {
+            *increments.entry(pair).or_insert(0) += weight;
+        }
+        let new_pairs = (&self.identifiers, &self.pairs, &self.meta)
+            .transaction(|(identifiers, pairs, meta)| {
+                for (identifier, id) in new_identifiers {
+                    identifiers.insert(identifier.as_bytes(), &id.to_be_bytes())?;
+                }
+                let mut new_pairs = 0;
+                for (&(first, second), &increment) in &increments {
+                    let count = match pairs.get(pair_key(first, second))? {
+                        Some(count) => decode_u64(&count).map_err(ConflictableTransactionError::Abort)?,
+                        None => {
+                            new_pairs += 1;
+                            0
+                        }
+                    };
+                    let count = count.saturating_add(increment).to_be_bytes();
+                    pairs.insert(&pair_key(first, second), &count)?;
+                    if first != second {
+                        pairs.insert(&pair_key(second, first), &count)?;
+                    }
+                }
+                if new_pairs > 0 {
+                    let total = match meta.get(PAIR_COUNT_KEY)? {
+                        Some(total) => decode_u64(&total).map_err(ConflictableTransactionError::Abort)?,
+                        None => 0,
+                    };
+                    meta.insert(PAIR_COUNT_KEY, &(total + new_pairs).to_be_bytes())?;
+                }
+                Ok(new_pairs)
+            })
+            .map_err(|e: TransactionError<String>| e.to_string())?;
+        self.pair_count.fetch_add(new_pairs, Ordering::Relaxed);
+
+        if let Some(hot_rows) = &self.hot_rows {
+            let mut hot_rows = locks::lock(hot_rows, "sled_hot_rows");
+            for (&(first, second), &increment) in &increments {
+                for (id, other) in [(first, second), (second, first)] {
+                    if let Some(row) = hot_rows.peek_mut(&id) {
+                        let count = row.entry(other).or_insert(0);
+                        *count = count.saturating_add(increment);
+                    }
+                    if first == second {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the identifiers only, the pairs stay on disk.
+    fn load_pairs(&self) -> Result<PairState, String> {
+        let identifiers = self
+            .identifiers
+            .iter()
+            .map(|entry| {
+                let (identifier, id) = entry.map_err(|e| e.to_string())?;
+                Ok((String::from_utf8_lossy(&identifier).into_owned(), decode_u32(&id)?))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok((identifiers, Vec::new()))
+    }
+
+    fn serves_lookups(&self) -> bool {
+        true
+    }
+
+    fn pairs_with(&self, id: u32) -> Result<HashMap<u32, u64>, String> {
+        let Some(hot_rows) = &self.hot_rows else {
+            return self.read_row(id);
+        };
+        if let Some(row) = locks::lock(hot_rows, "sled_hot_rows").get(&id) {
+            return Ok(row.clone());
+        }
+        let row = self.read_row(id)?;
+        locks::lock(hot_rows, "sled_hot_rows").put(id, row.clone());
+        Ok(row)
+    }
+
+    fn pair_count(&self) -> usize {
+        self.pair_count.load(Ordering::Relaxed) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::CoOccurrenceCounter;
+    use crate::config::Settings;
+    use std::sync::Arc;
+
+    fn open(name: &str) -> (Arc<SledStore>, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("{}_{}.sled", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let mut settings = Settings::from_env().storage;
+        settings.pairs_hot_cache = 2;
+        (Arc::new(SledStore::open(&path, &settings).unwrap()), path)
+    }
+
+    fn list(identifiers: &[&str]) -> Vec<String> {
+        identifiers.iter().map(|identifier| identifier.to_string()).collect()
+    }
+
+    #[test]
+    fn test_co_occurrences_are_served_from_disk() {
+        let (store, path) = open("pairs_on_disk");
+        let mut counter = CoOccurrenceCounter::new();
+        counter.attach_store(store.clone());
+        counter.process_list(&list(&["a", "b", "c"]));
+        counter.process_list(&list(&["a", "b", "a"]));
+        assert_eq!(counter.pair_count(), 4);
+        assert!(counter.get_co_occurrence_counts().is_empty());
+        drop((counter, store));
+
+        // sled's flusher thread releases the lock on the files shortly after the drop
+        let mut reopened = SledStore::open(&path, &Settings::from_env().storage);
+        for _ in 0..50 {
+            if reopened.is_ok() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            reopened = SledStore::open(&path, &Settings::from_env().storage);
+        }
+        let store = Arc::new(reopened.unwrap());
+        let mut reloaded = CoOccurrenceCounter::new();
+        reloaded.attach_store(store);
+        let metrics = reloaded.get_metrics_for_identifier("a");
+        assert_eq!((metrics.get("a"), metrics.get("b"), metrics.get("c")), (Some(&1), Some(&3), Some(&1)));
+        assert_eq!(reloaded.get_metrics_for_identifier("c").get("b"), Some(&1));
+        assert_eq!(reloaded.pair_count(), 4);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_hot_rows_are_updated_in_place() {
+        let (store, path) = open("hot_rows");
+        let mut counter = CoOccurrenceCounter::new();
+        counter.attach_store(store.clone());
+        counter.process_list(&list(&["a", "b"]));
+        assert_eq!(counter.get_metrics_for_identifier("a").get("b"), Some(&1));
+        counter.process_list(&list(&["a", "b", "c"]));
+
+        let hot_row = locks::lock(store.hot_rows.as_ref().unwrap(), "sled_hot_rows").peek(&0).cloned();
+        assert_eq!(hot_row, Some(HashMap::from([(1, 2), (2, 1)])));
+        assert_eq!(store.pairs_with(0).unwrap(), store.read_row(0).unwrap());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,485 @@
+// src/algorithms/snapshot.rs
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::algorithms::object_storage;
+use crate::config::{SnapshotFormat, SnapshotSettings};
+use crate::determinism;
+use crate::stats::{self, SnapshotRecovery, SnapshotSummary};
+
+/// Start of binary snapshots, followed by the version of the binary format. JSON
+/// snapshots start with `{` instead, so both are told apart on load.
+const MAGIC: &[u8; 8] = b"MEDIATHK";
+/// Version 1: MessagePack with named fields, so the same serde attributes (defaults,
+/// skipped fields, the untagged legacy formats) work as for JSON.
+const BINARY_VERSION: u8 = 1;
+
+/// Start of snapshots with a checksum, followed by the SHA-256 of the rest of the file,
+/// so a damaged snapshot is rejected on load instead of parsing to wrong counts. Files
+/// without it, written before, are read unchecked.
+const CHECKSUM_MAGIC: &[u8; 8] = b"MEDIASUM";
+const CHECKSUM_HEADER_LEN: usize = CHECKSUM_MAGIC.len() + 32;
+
+/// Format of the timestamps versions of a snapshot are named by, e.g.
+/// "rotating_counters.json.20261015T060107Z".
+const VERSION_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// A retained version of a snapshot.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct SnapshotVersion {
+    /// Timestamp naming the version, as accepted for restoring it
+    pub version: String,
+    pub written_at: DateTime<Utc>,
+    /// Size on disk
+    pub bytes: u64,
+}
+
+/// `path` with `suffix` appended, e.g. "rotating_counters.json.bak".
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Start of zstd frames, which compressed snapshots are.
+const ZSTD_MAGIC: &[u8; 4] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Encodes a snapshot in `format`.
+fn encode<T: Serialize + ?Sized>(value: &T, format: SnapshotFormat) -> io::Result<Vec<u8>> {
+    match format {
+        SnapshotFormat::Json => serde_json::to_vec(value).map_err(io::Error::other),
+        SnapshotFormat::MessagePack => {
+            let mut data = MAGIC.to_vec();
+            data.push(BINARY_VERSION);
+            rmp_serde::encode::write_named(&mut data, value).map_err(io::Error::other)?;
+            Ok(data)
+        }
+    }
+}
+
+/// Prefixes `data` with its checksum.
+fn with_checksum(data: Vec<u8>) -> Vec<u8> {
+    let mut checked = Vec::with_capacity(CHECKSUM_HEADER_LEN + data.len());
+    checked.extend_from_slice(CHECKSUM_MAGIC);
+    checked.extend_from_slice(&Sha256::digest(&data));
+    checked.extend(data);
+    checked
+}
+
+/// Returns `data` without its checksum, or an error if it doesn't match. Data without a
+/// checksum is returned as is.
+fn verify_checksum(data: &[u8]) -> Result<&[u8], String> {
+    let Some(rest) = data.strip_prefix(CHECKSUM_MAGIC) else {
+        return Ok(data);
+    };
+    let (checksum, payload) = rest.split_at_checked(32).ok_or("Truncated checksum")?;
+    if Sha256::digest(payload).as_slice() != checksum {
+        return Err("Checksum mismatch, the file is corrupt".to_string());
+    }
+    Ok(payload)
+}
+
+/// Decodes a snapshot in any format `encode_compressed` writes, compressed or not,
+/// recognized by its header.
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, String> {
+    let data = verify_checksum(data)?;
+    if data.starts_with(ZSTD_MAGIC) {
+        let data = zstd::decode_all(data).map_err(|e| format!("Failed to decompress: {}", e))?;
+        return decode(&data);
+    }
+    match data.strip_prefix(MAGIC) {
+        Some([BINARY_VERSION, payload @ ..]) => rmp_serde::from_slice(payload).map_err(|e| e.to_string()),
+        Some(rest) => Err(format!("Unsupported binary snapshot version {:?}", rest.first())),
+        None => serde_json::from_slice(data).map_err(|e| e.to_string()),
+    }
+}
+
+/// Returns the encoding of a snapshot and whether it is compressed.
+pub fn format_of(data: &[u8]) -> Result<(SnapshotFormat, bool), String> {
+    let data = verify_checksum(data)?;
+    if data.starts_with(ZSTD_MAGIC) {
+        let data = zstd::decode_all(data).map_err(|e| format!("Failed to decompress: {}", e))?;
+        return format_of(&data).map(|(format, _)| (format, true));
+    }
+    let format = if data.starts_with(MAGIC) { SnapshotFormat::MessagePack } else { SnapshotFormat::Json };
+    Ok((format, false))
+}
+
+/// Writes `value` as the snapshot at `path`, encoded and compressed as configured, and
+/// reports its size and how long that took.
+pub fn save<T: Serialize + ?Sized>(path: impl AsRef<Path>, value: &T, settings: SnapshotSettings) -> io::Result<()> {
+    let path = path.as_ref();
+    let started = Instant::now();
+    let (data, uncompressed_bytes) = encode_compressed(value, settings)?;
+    write_versioned(path, &data, settings)?;
+    report_saved(&path.display().to_string(), data.len() as u64, uncompressed_bytes, started);
+    Ok(())
+}
+
+/// Replaces the snapshot at `path` with `data` like `write`, keeps a version of it if
+/// configured and queues it for the upload to object storage.
+pub fn write_versioned(path: &Path, data: &[u8], settings: SnapshotSettings) -> io::Result<()> {
+    write(path, data)?;
+    if settings.keeps_versions() {
+        if let Err(e) = keep_version(path, determinism::now(), settings) {
+            error!("Failed to keep a version of {}: {}", path.display(), e);
+        }
+    }
+    object_storage::queue_upload(path);
+    Ok(())
+}
+
+/// Logs and records that a snapshot of `bytes` was written to `location`, started at
+/// `started`.
+pub fn report_saved(location: &str, bytes: u64, uncompressed_bytes: u64, started: Instant) {
+    let summary = SnapshotSummary {
+        written_at: determinism::now(),
+        bytes,
+        uncompressed_bytes,
+        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+    };
+    info!(
+        path = location,
+        bytes = summary.bytes,
+        uncompressed_bytes,
+        duration_ms = summary.duration_ms,
+        "Snapshot written."
+    );
+    stats::record_persist(summary.written_at);
+    stats::record_snapshot(location.to_string(), summary);
+}
+
+/// Creates the data directory if it's missing and makes sure files can be written to it,
+/// so a misconfigured directory is reported on startup rather than at the first snapshot.
+pub fn prepare_data_dir(directory: &Path) -> Result<(), String> {
+    fs::create_dir_all(directory).map_err(|e| format!("Failed to create the data directory {}: {}", directory.display(), e))?;
+    let probe = directory.join(".write_test");
+    File::create(&probe)
+        .and_then(|mut file| file.write_all(b"ok"))
+        .and_then(|()| fs::remove_file(&probe))
+        .map_err(|e| format!("The data directory {} isn't writable: {}", directory.display(), e))
+}
+
+/// Encodes and compresses `value` as configured, with a checksum. Also returns the size
+/// before compression.
+pub fn encode_compressed<T: Serialize + ?Sized>(value: &T, settings: SnapshotSettings) -> io::Result<(Vec<u8>, u64)> {
+    let data = encode(value, settings.format)?;
+    let uncompressed_bytes = data.len() as u64;
+    let data = match settings.compression_level {
+        0 => data,
+        level => zstd::encode_all(data.as_slice(), level)?,
+    };
+    Ok((with_checksum(data), uncompressed_bytes))
+}
+
+/// Writes `value` to `path` like `save`, but without keeping versions, uploading or
+/// reporting it, e.g. for changes on top of a snapshot. Returns the bytes written.
+pub fn save_unversioned<T: Serialize + ?Sized>(path: impl AsRef<Path>, value: &T, settings: SnapshotSettings) -> io::Result<u64> {
+    let (data, _) = encode_compressed(value, settings)?;
+    write(path.as_ref(), &data)?;
+    stats::record_persist(determinism::now());
+    Ok(data.len() as u64)
+}
+
+/// Replaces the snapshot at `path` with `data`. The data is written to a temporary file
+/// and synced before it is renamed into place, so a crash while writing leaves the
+/// previous snapshot intact. That one is kept as `<path>.bak`.
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temporary = with_suffix(path, ".tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    if path.exists() {
+        fs::rename(path, with_suffix(path, ".bak"))?;
+    }
+    fs::rename(&temporary, path)?;
+    // Makes the renames themselves durable
+    let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(directory)?.sync_all()
+}
+
+/// Reads the snapshot at `path`, or the previous one (`<path>.bak`) if it is missing or
+/// can't be parsed, and then the newest retained version that can. Returns `None` if none
+/// is there, e.g. on the first start.
+///
+/// Corrupt files are reported loudly and counted (see `stats::snapshot_recoveries`), and
+/// the snapshot and its backup are moved aside to `<file>.corrupt`, so the next snapshot
+/// doesn't rotate a corrupt file over a valid backup.
+pub fn read<T: DeserializeOwned>(path: impl AsRef<Path>) -> Option<T> {
+    let mut snapshot = None;
+    read_with(path, &mut |data| {
+        snapshot = Some(decode(data)?);
+        Ok(())
+    });
+    snapshot
+}
+
+/// Like `read`, but passes the files to `parse` until it accepts one. Returns whether one
+/// was accepted.
+pub fn read_with(path: impl AsRef<Path>, parse: &mut dyn FnMut(&[u8]) -> Result<(), String>) -> bool {
+    let path = path.as_ref();
+    let backup = with_suffix(path, ".bak");
+    let versions = versions(path).into_iter().map(|version| with_suffix(path, &format!(".{}", version.version)));
+    let mut corrupt = Vec::new();
+    for candidate in [path.to_path_buf(), backup.clone()].into_iter().chain(versions) {
+        let data = match fs::read(&candidate) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                error!("Failed to read {}: {}", candidate.display(), e);
+                corrupt.push(candidate);
+                continue;
+            }
+        };
+        match parse(&data) {
+            Ok(()) => {
+                if !corrupt.is_empty() {
+                    report_recovery(path, corrupt, Some(candidate));
+                } else if candidate != path {
+                    warn!("Recovered from the previous snapshot {}", candidate.display());
+                }
+                return true;
+            }
+            Err(e) => {
+                error!("Failed to parse {}: {}", candidate.display(), e);
+                if candidate == path || candidate == backup {
+                    let aside = with_suffix(&candidate, ".corrupt");
+                    match fs::rename(&candidate, &aside) {
+                        Ok(()) => warn!("Moved the corrupt snapshot {} to {}", candidate.display(), aside.display()),
+                        Err(e) => error!("Failed to move the corrupt snapshot {} aside: {}", candidate.display(), e),
+                    }
+                }
+                corrupt.push(candidate);
+            }
+        }
+    }
+    if !corrupt.is_empty() {
+        report_recovery(path, corrupt, None);
+    }
+    false
+}
+
+/// Logs and records that the snapshot at `path` was loaded from `recovered_from` because
+/// the `corrupt` files couldn't be, or not at all if `None`.
+fn report_recovery(path: &Path, corrupt: Vec<PathBuf>, recovered_from: Option<PathBuf>) {
+    let corrupt: Vec<String> = corrupt.iter().map(|file| file.display().to_string()).collect();
+    match &recovered_from {
+        Some(file) => error!(
+            corrupt = ?corrupt,
+            "SNAPSHOT CORRUPT: {} couldn't be loaded, recovered from the older {}. Changes written after it are lost.",
+            path.display(),
+            file.display()
+        ),
+        None => error!(
+            corrupt = ?corrupt,
+            "SNAPSHOT CORRUPT: {} couldn't be loaded and no valid older version exists. Starting with an empty state.",
+            path.display()
+        ),
+    }
+    let recovery = SnapshotRecovery {
+        recovered_at: determinism::now(),
+        corrupt_files: corrupt,
+        recovered_from: recovered_from.map(|file| file.display().to_string()),
+        recoveries: 1,
+    };
+    stats::record_snapshot_recovery(path.display().to_string(), recovery);
+}
+
+/// Keeps the snapshot just written to `path` as the version of `at`, and deletes the
+/// versions no longer retained. The version is a hard link, so it takes no extra space
+/// until the snapshot is replaced.
+fn keep_version(path: &Path, at: DateTime<Utc>, settings: SnapshotSettings) -> io::Result<()> {
+    let version_path = with_suffix(path, &format!(".{}", at.format(VERSION_FORMAT)));
+    if version_path.exists() {
+        fs::remove_file(&version_path)?;
+    }
+    if fs::hard_link(path, &version_path).is_err() {
+        fs::copy(path, &version_path)?;
+    }
+
+    let versions = versions(path);
+    let written_at: Vec<DateTime<Utc>> = versions.iter().map(|version| version.written_at).collect();
+    for (version, retained) in versions.iter().zip(retained(&written_at, settings)) {
+        if !retained {
+            fs::remove_file(with_suffix(path, &format!(".{}", version.version)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Decides which versions, newest first, are retained: the `keep_last` newest ones, the
+/// newest one of each of the last `keep_daily` days and of the last `keep_weekly` weeks
+/// that have any.
+fn retained(written_at: &[DateTime<Utc>], settings: SnapshotSettings) -> Vec<bool> {
+    let mut retained: Vec<bool> = (0..written_at.len()).map(|index| index < settings.keep_last).collect();
+    retain_newest_per_period(written_at, settings.keep_daily, |at| (at.year(), at.ordinal()), &mut retained);
+    retain_newest_per_period(written_at, settings.keep_weekly, |at| (at.iso_week().year(), at.iso_week().week()), &mut retained);
+    retained
+}
+
+/// Marks the newest of the versions (newest first) in each of the last `keep` periods as retained.
+fn retain_newest_per_period(written_at: &[DateTime<Utc>], keep: usize, period_of: impl Fn(&DateTime<Utc>) -> (i32, u32), retained: &mut [bool]) {
+    let mut last_period = None;
+    let mut kept = 0;
+    for (index, at) in written_at.iter().enumerate() {
+        let period = period_of(at);
+        if kept < keep && last_period != Some(period) {
+            retained[index] = true;
+            kept += 1;
+        }
+        last_period = Some(period);
+    }
+}
+
+/// Lists the retained versions of the snapshot at `path`, newest first.
+pub fn versions(path: impl AsRef<Path>) -> Vec<SnapshotVersion> {
+    let path = path.as_ref();
+    let prefix = format!("{}.", path.file_name().unwrap_or_default().to_string_lossy());
+    let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut versions: Vec<SnapshotVersion> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let version = entry.file_name().to_string_lossy().strip_prefix(&prefix)?.to_string();
+            let written_at = NaiveDateTime::parse_from_str(&version, VERSION_FORMAT).ok()?.and_utc();
+            Some(SnapshotVersion { version, written_at, bytes: entry.metadata().ok()?.len() })
+        })
+        .collect();
+    versions.sort_by_key(|version| std::cmp::Reverse(version.written_at));
+    versions
+}
+
+/// Reads one retained version of the snapshot at `path`, e.g. to restore it.
+pub fn read_version<T: DeserializeOwned>(path: impl AsRef<Path>, version: &str) -> Result<T, String> {
+    // Only timestamps, so the version can't point outside the directory
+    NaiveDateTime::parse_from_str(version, VERSION_FORMAT).map_err(|_| format!("Invalid version '{}'", version))?;
+    let version_path = with_suffix(path.as_ref(), &format!(".{}", version));
+    let data = fs::read(&version_path).map_err(|e| format!("Failed to read {}: {}", version_path.display(), e))?;
+    decode(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_previous_snapshot_is_kept_and_used_if_corrupt() {
+        let path = std::env::temp_dir().join(format!("mediathek_snapshot_{}.json", std::process::id()));
+        let backup = with_suffix(&path, ".bak");
+        let _ = (fs::remove_file(&path), fs::remove_file(&backup));
+        assert_eq!(read::<Vec<u32>>(&path), None);
+
+        write(&path, b"[1]").unwrap();
+        write(&path, b"[1, 2]").unwrap();
+        assert_eq!(read::<Vec<u32>>(&path), Some(vec![1, 2]));
+        assert_eq!(fs::read(&backup).unwrap(), b"[1]");
+
+        // Cut off, as by a crash of a plain write
+        fs::write(&path, b"[1, ").unwrap();
+        assert_eq!(read::<Vec<u32>>(&path), Some(vec![1]));
+        let _ = (fs::remove_file(&backup), fs::remove_file(with_suffix(&path, ".corrupt")));
+    }
+
+    #[test]
+    fn test_corrupt_snapshots_fall_back_to_the_newest_valid_version() {
+        let path = std::env::temp_dir().join(format!("mediathek_corrupt_snapshot_{}.json", std::process::id()));
+        let settings = SnapshotSettings { keep_last: 3, ..Default::default() };
+        let (data, _) = encode_compressed(&vec![7u32], settings).unwrap();
+        let mut flipped = data.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(decode::<Vec<u32>>(&flipped).unwrap_err().contains("Checksum mismatch"));
+
+        for day in [14, 15] {
+            write(&path, &encode_compressed(&vec![day], settings).unwrap().0).unwrap();
+            keep_version(&path, Utc.with_ymd_and_hms(2026, 10, day, 6, 0, 0).unwrap(), settings).unwrap();
+        }
+        // Damaged in new files, so the versions linked to the old ones stay intact
+        let backup = with_suffix(&path, ".bak");
+        for file in [&path, &backup] {
+            fs::remove_file(file).unwrap();
+            fs::write(file, &flipped).unwrap();
+        }
+        assert_eq!(read::<Vec<u32>>(&path), Some(vec![15]));
+        assert!(!path.exists() && with_suffix(&path, ".corrupt").exists() && with_suffix(&backup, ".corrupt").exists());
+        let recovery = &stats::snapshot_recoveries()[&path.display().to_string()];
+        assert_eq!(recovery.corrupt_files.len(), 2);
+        assert_eq!(recovery.recovered_from, Some(with_suffix(&path, ".20261015T060000Z").display().to_string()));
+
+        // Without a valid version the state starts out empty, and that is reported too
+        let versions = versions(&path);
+        for version in &versions {
+            fs::remove_file(with_suffix(&path, &format!(".{}", version.version))).unwrap();
+        }
+        fs::write(&path, &flipped).unwrap();
+        assert_eq!(read::<Vec<u32>>(&path), None);
+        let recovery = &stats::snapshot_recoveries()[&path.display().to_string()];
+        assert_eq!((recovery.recovered_from.as_deref(), recovery.recoveries), (None, 2));
+        for file in [with_suffix(&path, ".corrupt"), with_suffix(&backup, ".corrupt")] {
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn test_compressed_snapshots_are_recognized() {
+        let path = std::env::temp_dir().join(format!("mediathek_compressed_snapshot_{}.json", std::process::id()));
+        let values: Vec<u32> = (0..1000).map(|value| value % 7).collect();
+        let settings = SnapshotSettings { format: SnapshotFormat::MessagePack, compression_level: 3, ..Default::default() };
+        save(&path, &values, settings).unwrap();
+
+        assert!(fs::read(&path).unwrap()[CHECKSUM_HEADER_LEN..].starts_with(ZSTD_MAGIC));
+        assert_eq!(read::<Vec<u32>>(&path), Some(values));
+        let summary = &stats::snapshot_summaries()[&path.display().to_string()];
+        assert!(summary.bytes < summary.uncompressed_bytes);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_counters_round_trip_in_both_formats() {
+        let counters = crate::algorithms::Counters::with_depths(2, 2, 1, 1);
+        counters.increment("a", 3);
+        for format in [SnapshotFormat::Json, SnapshotFormat::MessagePack] {
+            let data = encode(&counters, format).unwrap();
+            assert_eq!(data.starts_with(MAGIC), format == SnapshotFormat::MessagePack);
+            let decoded: crate::algorithms::Counters = decode(&data).unwrap();
+            assert_eq!(decoded.daily.len(), 2);
+            assert_eq!(decoded.daily[0].get("a").map(|count| *count), Some(3));
+            assert!(decoded.first_seen.contains_key("a"));
+        }
+        assert!(decode::<Vec<u32>>(b"MEDIATHK\x02").unwrap_err().contains("version"));
+    }
+
+    #[test]
+    fn test_versions_are_retained_per_policy() {
+        let at = |day: u32, hour: u32| Utc.with_ymd_and_hms(2026, 10, day, hour, 0, 0).unwrap();
+        // Newest first: Thursday to the Sunday of the week before
+        let written_at = [at(15, 12), at(15, 6), at(14, 12), at(13, 12), at(12, 18), at(11, 12)];
+        let settings = SnapshotSettings { keep_last: 1, keep_daily: 3, keep_weekly: 2, ..Default::default() };
+        assert_eq!(retained(&written_at, settings), [true, false, true, true, false, true]);
+
+        let path = std::env::temp_dir().join(format!("mediathek_versions_{}.json", std::process::id()));
+        let settings = SnapshotSettings { keep_last: 2, ..Default::default() };
+        for (index, at) in written_at.iter().rev().enumerate() {
+            write(&path, format!("[{}]", index).as_bytes()).unwrap();
+            keep_version(&path, *at, settings).unwrap();
+        }
+        let versions = versions(&path);
+        assert_eq!(versions.iter().map(|version| version.written_at).collect::<Vec<_>>(), written_at[..2]);
+        assert_eq!(read_version::<Vec<u32>>(&path, &versions[1].version), Ok(vec![4]));
+        assert!(read_version::<Vec<u32>>(&path, "../../etc/passwd").is_err());
+        for file in [path.clone(), with_suffix(&path, ".bak")].into_iter().chain(versions.iter().map(|v| with_suffix(&path, &format!(".{}", v.version)))) {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+// src/algorithms/spikes.rs
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use tracing::{error, info, warn};
+
+use crate::algorithms::rotating_counters::{count_of, Counters};
+use crate::config::AlertSettings;
+use crate::determinism;
+use crate::locks;
+
+/// Pseudo-count added to both sides of the spike ratio, like the trending score.
+const SPIKE_SMOOTHING: f64 = 1.0;
+/// Number of past days whose hourly average forms the long-term baseline.
+const TRAILING_DAYS: usize = 7;
+
+/// An identifier whose current-hour count exceeded its trailing baseline.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct SpikeAlert {
+    pub id: String,
+    pub detected_at: DateTime<Utc>,
+    /// Count in the current hour
+    pub current: u64,
+    /// Expected count per hour
+    pub baseline: f64,
+    /// Smoothed ratio of `current` to `baseline`
+    pub ratio: f64,
+}
+
+/// The most recent alerts, plus when each identifier was last alerted so a
+/// long-running spike doesn't fire on every check.
+#[derive(Debug)]
+pub struct AlertLog {
+    alerts: VecDeque<SpikeAlert>,
+    capacity: usize,
+    last_alerted: HashMap<String, DateTime<Utc>>,
+}
+
+impl AlertLog {
+    /// Creates a new, empty log keeping at most `capacity` alerts.
+    pub fn new(capacity: usize) -> Self {
+        AlertLog {
+            alerts: VecDeque::new(),
+            capacity,
+            last_alerted: HashMap::new(),
+        }
+    }
+
+    /// Records `alert` unless its identifier was already alerted within `cooldown`.
+    /// Returns whether the alert was recorded.
+    pub fn record(&mut self, alert: &SpikeAlert, cooldown: chrono::Duration) -> bool {
+        if let Some(&last) = self.last_alerted.get(&alert.id) {
+            if alert.detected_at - last < cooldown {
+                return false;
+            }
+        }
+        self.last_alerted.insert(alert.id.clone(), alert.detected_at);
+        // Forget identifiers whose cooldown has long passed
+        self.last_alerted.retain(|_, &mut last| alert.detected_at - last < cooldown);
+
+        if self.capacity > 0 {
+            if self.alerts.len() == self.capacity {
+                self.alerts.pop_front();
+            }
+            self.alerts.push_back(alert.clone());
+        }
+        true
+    }
+
+    /// Returns up to `limit` alerts, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<SpikeAlert> {
+        self.alerts.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+/// Compares every identifier's current-hour count against its trailing baseline and
+/// returns those with at least `min_count` events and a ratio of at least `min_ratio`,
+/// strongest spike first.
+///
+/// The baseline is the larger of the average of the previous hours and the average
+/// hour of the trailing week, so items that are always busy at this time of day
+/// (prime time) aren't mistaken for spikes.
+pub fn detect_spikes(counters: &Counters, now: DateTime<Utc>, min_count: u64, min_ratio: f64) -> Vec<SpikeAlert> {
+    let previous_hours = &counters.hourly[1..];
+    let previous_days = &counters.daily[1..counters.daily.len().min(TRAILING_DAYS + 1)];
+
+    let mut spikes: Vec<SpikeAlert> = counters.hourly[0]
+        .iter()
+        .filter(|entry| *entry.value() >= min_count)
+        .filter_map(|entry| {
+            let (id, current) = (entry.key(), *entry.value());
+            let hourly_baseline = average(previous_hours.iter().map(|b| count_of(b, id)));
+            let daily_baseline = average(previous_days.iter().map(|b| count_of(b, id))) / 24.0;
+            let baseline = hourly_baseline.max(daily_baseline);
+            let ratio = (current as f64 + SPIKE_SMOOTHING) / (baseline + SPIKE_SMOOTHING);
+            (ratio >= min_ratio).then(|| SpikeAlert {
+                id: id.clone(),
+                detected_at: now,
+                current,
+                baseline,
+                ratio,
+            })
+        })
+        .collect();
+
+    spikes.sort_by(|a, b| b.ratio.total_cmp(&a.ratio).then_with(|| a.id.cmp(&b.id)));
+    spikes
+}
+
+fn average(counts: impl ExactSizeIterator<Item = u64>) -> f64 {
+    let len = counts.len();
+    if len == 0 {
+        return 0.0;
+    }
+    counts.sum::<u64>() as f64 / len as f64
+}
+
+/// Posts newly detected spikes to the configured webhook as `{"alerts": [...]}`.
+async fn send_webhook(client: &awc::Client, url: &str, alerts: &[SpikeAlert]) {
+    let body = serde_json::json!({ "alerts": alerts });
+    match client.post(url).send_json(&body).await {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!("Alert webhook responded with status {}.", response.status()),
+        Err(e) => error!("Failed to send alert webhook: {}", e),
+    }
+}
+
+// Function to periodically check the counters for spikes.
+// Must be spawned on the actix runtime, since the webhook client is not `Send`.
+pub async fn run_spike_detection(
+    counters: Arc<RwLock<Counters>>,
+    alert_log: Arc<Mutex<AlertLog>>,
+    settings: AlertSettings,
+) {
+    info!("Spike detection thread started.");
+    let client = awc::Client::default();
+    let cooldown = chrono::Duration::seconds(settings.cooldown_secs as i64);
+
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(settings.check_interval_secs)).await;
+
+        let spikes = detect_spikes(&locks::read(&counters, "rotating_counters"), determinism::now(), settings.min_count, settings.spike_ratio);
+        let new_alerts: Vec<SpikeAlert> = {
+            let mut log = locks::lock(&alert_log, "alert_log");
+            spikes.into_iter().filter(|spike| log.record(spike, cooldown)).collect()
+        };
+        if new_alerts.is_empty() {
+            continue;
+        }
+
+        info!("Detected {} counter spikes.", new_alerts.len());
+        if let Some(url) = &settings.webhook_url {
+            send_webhook(&client, url, &new_alerts).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::rotating_counters::Granularity;
+
+    #[test]
+    fn test_spikes_compare_against_trailing_baseline() {
+        let now = Utc::now();
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        counters.increment("steady", 48);
+        counters.increment("viral", 1);
+        counters.rotate(Granularity::Hour, 1);
+        counters.increment("steady", 50);
+        counters.increment("viral", 40);
+
+        let spikes = detect_spikes(&counters, now, 10, 5.0);
+        assert_eq!(spikes.len(), 1);
+        assert_eq!(spikes[0].id, "viral");
+        assert!((spikes[0].baseline - 0.5).abs() < 1e-9);
+
+        // The trailing week explains a busy hour of a daily regular
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        counters.increment("regular", 7 * 24 * 100);
+        counters.rotate(Granularity::Day, 1);
+        counters.rotate(Granularity::Hour, 3);
+        counters.increment("regular", 100);
+        assert!(detect_spikes(&counters, now, 10, 5.0).is_empty());
+    }
+
+    #[test]
+    fn test_alert_log_cooldown_and_capacity() {
+        let now = Utc::now();
+        let alert = |id: &str, at: DateTime<Utc>| SpikeAlert {
+            id: id.to_string(),
+            detected_at: at,
+            current: 10,
+            baseline: 0.0,
+            ratio: 11.0,
+        };
+        let cooldown = chrono::Duration::hours(1);
+        let mut log = AlertLog::new(2);
+
+        assert!(log.record(&alert("a", now), cooldown));
+        assert!(!log.record(&alert("a", now + chrono::Duration::minutes(30)), cooldown));
+        assert!(log.record(&alert("b", now), cooldown));
+        assert!(log.record(&alert("a", now + chrono::Duration::hours(2)), cooldown));
+
+        let recent = log.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].id, "a");
+        assert_eq!(recent[1].id, "b");
+    }
+}
@@ -0,0 +1,303 @@
+// src/algorithms/sqlite_store.rs
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::info;
+
+use crate::algorithms::co_occurrence::{PairState, PairStore};
+use crate::algorithms::counter_store::{signed_count, CounterStore};
+use crate::algorithms::rotating_counters::{Bucket, Granularity};
+use crate::config::CounterSettings;
+use crate::locks;
+
+/// Schema changes, applied in order on startup. `PRAGMA user_version` records how many
+/// of them a database has seen, so only new ones run. Never change a released entry,
+/// append a new one instead.
+const MIGRATIONS: [&str; 2] = [
+    // 1: co-occurrences
+    "CREATE TABLE identifiers (
+        id INTEGER PRIMARY KEY,
+        identifier TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE pairs (
+        first INTEGER NOT NULL,
+        second INTEGER NOT NULL,
+        count INTEGER NOT NULL,
+        PRIMARY KEY (first, second)
+    ) WITHOUT ROWID;",
+    // 2: rotating counter buckets, and the period each granularity was last rotated into
+    "CREATE TABLE counter_buckets (
+        series TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        identifier TEXT NOT NULL,
+        count INTEGER NOT NULL,
+        PRIMARY KEY (series, position, identifier)
+    ) WITHOUT ROWID;
+    CREATE INDEX counter_buckets_identifier ON counter_buckets (identifier);
+    CREATE TABLE counter_periods (
+        series TEXT PRIMARY KEY,
+        period TEXT NOT NULL
+    );",
+];
+
+/// Adds to a count, saturating instead of overflowing into a float.
+const SATURATING_ADD: &str = "CASE WHEN count > 9223372036854775807 - excluded.count THEN 9223372036854775807 ELSE count + excluded.count END";
+
+/// Keeps the co-occurrences and the counter buckets in a SQLite database. Every change
+/// is written as it happens, in its own transaction, so there is no snapshot to take and
+/// nothing to lose in a crash.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+    /// Number of buckets per granularity, in the order of `Granularity::ALL`
+    depths: [usize; 4],
+}
+
+impl fmt::Debug for SqliteStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqliteStore").field("depths", &self.depths).finish()
+    }
+}
+
+/// Applies the migrations the database hasn't seen yet. Returns how many were applied.
+fn migrate(connection: &mut Connection) -> Result<usize, String> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0)).map_err(|e| e.to_string())?;
+    if version > MIGRATIONS.len() {
+        return Err(format!("Schema version {} is newer than this server's ({})", version, MIGRATIONS.len()));
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let apply = |connection: &mut Connection| -> rusqlite::Result<()> {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()
+        };
+        apply(connection).map_err(|e| format!("Migration {} failed: {}", index + 1, e))?;
+    }
+    Ok(MIGRATIONS.len() - version)
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path` and brings its schema up to date.
+    pub fn open(path: &Path, counters: &CounterSettings) -> Result<Self, String> {
+        let mut connection = Connection::open(path).map_err(|e| e.to_string())?;
+        // Readers don't block the writer, and commits don't wait for an fsync; the
+        // write-ahead log still survives a crash of the process
+        connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+            .map_err(|e| e.to_string())?;
+        connection.pragma_update(None, "synchronous", "NORMAL").map_err(|e| e.to_string())?;
+        let migrated = migrate(&mut connection)?;
+        if migrated > 0 {
+            info!("Applied {} schema migrations to {}.", migrated, path.display());
+        }
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+            depths: [counters.hourly_buckets, counters.daily_buckets, counters.weekly_buckets, counters.monthly_buckets]
+                .map(|depth| depth.max(1)),
+        })
+    }
+
+    fn depth(&self, granularity: Granularity) -> usize {
+        self.depths[Granularity::ALL.iter().position(|&g| g == granularity).unwrap_or_default()]
+    }
+
+    /// Runs `f` in a transaction, committed if it succeeds.
+    fn transaction<T>(&self, f: impl FnOnce(&rusqlite::Transaction) -> rusqlite::Result<T>) -> Result<T, String> {
+        let mut connection = locks::lock(&self.connection, "sqlite_connection");
+        let transaction = connection.transaction().map_err(|e| e.to_string())?;
+        let result = f(&transaction).map_err(|e| e.to_string())?;
+        transaction.commit().map_err(|e| e.to_string())?;
+        Ok(result)
+    }
+
+    /// Adds `count` to `identifier` in one bucket.
+    fn add_count(transaction: &rusqlite::Transaction, series: &str, position: usize, identifier: &str, count: u64) -> rusqlite::Result<()> {
+        let sql = format!(
+            "INSERT INTO counter_buckets (series, position, identifier, count) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (series, position, identifier) DO UPDATE SET count = {}",
+            SATURATING_ADD
+        );
+        transaction.prepare_cached(&sql)?.execute(params![series, position, identifier, signed_count(count)])?;
+        Ok(())
+    }
+}
+
+impl CounterStore for SqliteStore {
+    fn increment(&self, id: &str, amount: u64) -> Result<(), String> {
+        self.transaction(|transaction| {
+            for granularity in Granularity::ALL {
+                Self::add_count(transaction, granularity.series_name(), 0, id, amount)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn add(&self, granularity: Granularity, index: usize, bucket: &Bucket) -> Result<(), String> {
+        if index >= self.depth(granularity) || bucket.is_empty() {
+            return Ok(());
+        }
+        self.transaction(|transaction| {
+            for entry in bucket.iter() {
+                Self::add_count(transaction, granularity.series_name(), index, entry.key(), *entry.value())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn rotate(&self, granularity: Granularity, steps: usize, period: &str) -> Result<bool, String> {
+        let series = granularity.series_name();
+        let depth = self.depth(granularity);
+        self.transaction(|transaction| {
+            let current: Option<String> = transaction
+                .query_row("SELECT period FROM counter_periods WHERE series = ?1", [series], |row| row.get(0))
+                .optional()?;
+            if current.as_deref() == Some(period) {
+                return Ok(false);
+            }
+            transaction.execute(
+                "DELETE FROM counter_buckets WHERE series = ?1 AND position + ?2 >= ?3",
+                params![series, steps, depth],
+            )?;
+            // Through negative positions, as shifting in place would collide with the
+            // rows not shifted yet
+            transaction.execute(
+                "UPDATE counter_buckets SET position = -1 - (position + ?2) WHERE series = ?1",
+                params![series, steps],
+            )?;
+            transaction.execute("UPDATE counter_buckets SET position = -1 - position WHERE series = ?1", [series])?;
+            transaction.execute(
+                "INSERT INTO counter_periods (series, period) VALUES (?1, ?2)
+                 ON CONFLICT (series) DO UPDATE SET period = excluded.period",
+                [series, period],
+            )?;
+            Ok(true)
+        })
+    }
+
+    fn remove(&self, id: &str) -> Result<(), String> {
+        self.transaction(|transaction| transaction.execute("DELETE FROM counter_buckets WHERE identifier = ?1", [id]).map(drop))
+    }
+
+    fn reset(&self) -> Result<(), String> {
+        self.transaction(|transaction| transaction.execute("DELETE FROM counter_buckets", []).map(drop))
+    }
+
+    fn load(&self) -> Result<Vec<Vec<Bucket>>, String> {
+        let buckets: Vec<Vec<Bucket>> =
+            Granularity::ALL.into_iter().map(|granularity| vec![Bucket::new(); self.depth(granularity)]).collect();
+        self.transaction(|transaction| {
+            let mut statement = transaction.prepare("SELECT series, position, identifier, count FROM counter_buckets")?;
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                let series: String = row.get(0)?;
+                let Some(granularity) = Granularity::ALL.into_iter().position(|g| g.series_name() == series) else {
+                    continue;
+                };
+                // Buckets beyond the configured depth are left out, like in `Counters::resize`
+                if let Some(bucket) = buckets[granularity].get(row.get::<_, usize>(1)?) {
+                    bucket.insert(row.get(2)?, row.get::<_, i64>(3)?.max(0) as u64);
+                }
+            }
+            Ok(())
+        })?;
+        Ok(buckets)
+    }
+
+    fn is_durable(&self) -> bool {
+        true
+    }
+}
+
+impl PairStore for SqliteStore {
+    fn add_list(&self, new_identifiers: &[(String, u32)], pairs: &[(u32, u32)], weight: u64) -> Result<(), String> {
+        self.transaction(|transaction| {
+            let mut insert = transaction.prepare_cached("INSERT INTO identifiers (id, identifier) VALUES (?1, ?2)")?;
+            for (identifier, id) in new_identifiers {
+                insert.execute(params![id, identifier])?;
+            }
+            let mut increment = transaction.prepare_cached(&format!(
+                "INSERT INTO pairs (first, second, count) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (first, second) DO UPDATE SET count = {}",
+                SATURATING_ADD
+            ))?;
+            for &(first, second) in pairs {
+                increment.execute(params![first, second, signed_count(weight)])?;
+            }
+            Ok(())
+        })
+    }
+
+    fn load_pairs(&self) -> Result<PairState, String> {
+        self.transaction(|transaction| {
+            let identifiers = transaction
+                .prepare("SELECT identifier, id FROM identifiers")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            let pairs = transaction
+                .prepare("SELECT first, second, count FROM pairs")?
+                .query_map([], |row| Ok(((row.get(0)?, row.get(1)?), row.get::<_, i64>(2)?.max(0) as u64)))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok((identifiers, pairs))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::rotating_counters::count_of;
+    use crate::algorithms::CoOccurrenceCounter;
+    use crate::config::Settings;
+    use std::sync::Arc;
+
+    fn open(name: &str) -> (SqliteStore, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("{}_{}.sqlite3", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut settings = Settings::from_env().counters;
+        (settings.hourly_buckets, settings.daily_buckets, settings.weekly_buckets, settings.monthly_buckets) = (3, 3, 2, 2);
+        (SqliteStore::open(&path, &settings).unwrap(), path)
+    }
+
+    #[test]
+    fn test_counter_buckets_are_written_and_rotated() {
+        let (store, path) = open("counter_buckets");
+        store.increment("a", 2).unwrap();
+        assert!(store.rotate(Granularity::Day, 1, "2025-01-06").unwrap());
+        // Already rotated into that day, e.g. by the same call before a restart
+        assert!(!store.rotate(Granularity::Day, 1, "2025-01-06").unwrap());
+        store.increment("a", 1).unwrap();
+        store.increment("b", 1).unwrap();
+        assert!(store.rotate(Granularity::Day, 2, "2025-01-08").unwrap());
+
+        let buckets = store.load().unwrap();
+        let daily = &buckets[1];
+        assert!(daily[0].is_empty() && daily[1].is_empty());
+        assert_eq!(count_of(&daily[2], "a"), 1);
+        assert_eq!(count_of(&buckets[0][0], "a"), 3);
+
+        store.remove("a").unwrap();
+        assert_eq!(count_of(&store.load().unwrap()[0][0], "a"), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_co_occurrences_survive_a_restart() {
+        let (store, path) = open("pairs");
+        let mut counter = CoOccurrenceCounter::new();
+        counter.attach_store(Arc::new(store));
+        counter.process_list(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        counter.process_list(&["a".to_string(), "b".to_string()]);
+        drop(counter);
+
+        let store = SqliteStore::open(&path, &Settings::from_env().counters).unwrap();
+        let mut reloaded = CoOccurrenceCounter::new();
+        reloaded.attach_store(Arc::new(store));
+        assert_eq!(reloaded.get_metrics_for_identifier("a").get("b"), Some(&2));
+        // New identifiers continue after the loaded IDs
+        reloaded.process_list(&["d".to_string(), "a".to_string()]);
+        assert_eq!(reloaded.get_metrics_for_identifier("d").get("a"), Some(&1));
+        assert_eq!(reloaded.identifier_count(), 4);
+        let _ = std::fs::remove_file(&path);
+    }
+}
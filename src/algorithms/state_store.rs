@@ -0,0 +1,162 @@
+// src/algorithms/state_store.rs
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::algorithms::snapshot;
+use crate::config::{SnapshotSettings, StateBackend, StorageSettings};
+use crate::locks;
+
+/// Where persisted state is kept, by name, e.g. the snapshot of the rotating counters.
+/// Backends only move bytes: encoding, compression and checksums are up to `save` and
+/// `load`, so every backend reads what any other one wrote.
+pub trait StateStore: Send + Sync + fmt::Debug {
+    /// Passes the state saved as `name` to `parse`, and then older copies of it as long as
+    /// `parse` rejects them. Returns whether one was accepted; `false` if none was saved,
+    /// e.g. on the first start.
+    fn load(&self, name: &str, parse: &mut dyn FnMut(&[u8]) -> Result<(), String>) -> bool;
+
+    /// Replaces the state saved as `name` with `data`, so that a crash leaves either one.
+    fn save(&self, name: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Appends `data` to the state saved as `name`, e.g. a line of a log.
+    fn append(&self, name: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Where `name` is saved, for logs and reports.
+    fn location(&self, name: &str) -> String;
+}
+
+/// Returns the store configured for the persisted state.
+pub fn open_state_store(storage: &StorageSettings) -> Arc<dyn StateStore> {
+    match storage.state_backend {
+        StateBackend::File => Arc::new(FileStore::new(storage)),
+        StateBackend::Memory => {
+            info!("Keeping the state in memory only, so it is lost on restart.");
+            Arc::new(MemoryStore::default())
+        }
+    }
+}
+
+/// Reads the state saved as `name`, in any format `save` writes.
+pub fn load<T: DeserializeOwned>(store: &dyn StateStore, name: &str) -> Option<T> {
+    let mut state = None;
+    store.load(name, &mut |data| {
+        state = Some(snapshot::decode(data)?);
+        Ok(())
+    });
+    state
+}
+
+/// Saves `value` as `name`, encoded and compressed as configured, and reports its size
+/// and how long that took. Returns the bytes written.
+pub fn save<T: Serialize + ?Sized>(store: &dyn StateStore, name: &str, value: &T, settings: SnapshotSettings) -> io::Result<u64> {
+    let started = Instant::now();
+    let (data, uncompressed_bytes) = snapshot::encode_compressed(value, settings)?;
+    store.save(name, &data)?;
+    snapshot::report_saved(&store.location(name), data.len() as u64, uncompressed_bytes, started);
+    Ok(data.len() as u64)
+}
+
+/// Keeps the state in files in the data directory, as `snapshot` writes them: replaced
+/// atomically with a backup, versions retained and uploaded as configured.
+#[derive(Debug, Default)]
+pub struct FileStore {
+    /// The data directory; empty for the working directory
+    directory: PathBuf,
+    snapshots: SnapshotSettings,
+}
+
+impl FileStore {
+    pub fn new(storage: &StorageSettings) -> Self {
+        FileStore { directory: storage.data_path(""), snapshots: storage.snapshots }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.directory.join(name)
+    }
+}
+
+impl StateStore for FileStore {
+    fn load(&self, name: &str, parse: &mut dyn FnMut(&[u8]) -> Result<(), String>) -> bool {
+        snapshot::read_with(self.path(name), parse)
+    }
+
+    fn save(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        snapshot::write_versioned(&self.path(name), data, self.snapshots)
+    }
+
+    fn append(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        OpenOptions::new().create(true).append(true).open(self.path(name))?.write_all(data)
+    }
+
+    fn location(&self, name: &str) -> String {
+        self.path(name).display().to_string()
+    }
+}
+
+/// Keeps the state in memory only, e.g. for tests.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    states: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl StateStore for MemoryStore {
+    fn load(&self, name: &str, parse: &mut dyn FnMut(&[u8]) -> Result<(), String>) -> bool {
+        let states = locks::lock(&self.states, "state_store");
+        let Some(data) = states.get(name) else {
+            return false;
+        };
+        match parse(data) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to parse the state {}: {}", name, e);
+                false
+            }
+        }
+    }
+
+    fn save(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        locks::lock(&self.states, "state_store").insert(name.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn append(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        locks::lock(&self.states, "state_store").entry(name.to_string()).or_default().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn location(&self, name: &str) -> String {
+        format!("memory:{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SnapshotFormat;
+
+    #[test]
+    fn test_state_round_trips_through_a_memory_store() {
+        let store = MemoryStore::default();
+        assert_eq!(load::<Vec<u32>>(&store, "state.json"), None);
+
+        let settings = SnapshotSettings { format: SnapshotFormat::MessagePack, compression_level: 3, ..SnapshotSettings::default() };
+        let bytes = save(&store, "state.json", &vec![1u32, 2, 3], settings).unwrap();
+        assert!(bytes > 0);
+        assert_eq!(load::<Vec<u32>>(&store, "state.json"), Some(vec![1, 2, 3]));
+
+        store.append("state.json", b"garbage").unwrap();
+        assert_eq!(load::<Vec<u32>>(&store, "state.json"), None);
+        store.append("log", b"a\n").unwrap();
+        store.append("log", b"b\n").unwrap();
+        assert!(store.load("log", &mut |data| if data == b"a\nb\n" { Ok(()) } else { Err("unexpected".to_string()) }));
+        assert_eq!(store.location("log"), "memory:log");
+    }
+}
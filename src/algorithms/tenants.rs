@@ -0,0 +1,175 @@
+// src/algorithms/tenants.rs
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use actix_web::web;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+
+use crate::algorithms::counter_store::open_counter_store;
+use crate::algorithms::identifier_filter::IdentifierFilter;
+use crate::algorithms::snapshot;
+use crate::algorithms::state_store::open_state_store;
+use crate::algorithms::maintenance::maintenance_jobs;
+use crate::algorithms::{perform_final_persistence, run_counter_sync, CoOccurrenceCounter, Counters};
+use crate::config::{CounterBackend, Settings, StorageSettings};
+use crate::locks;
+
+/// Directory in the data directory holding the persisted files of each tenant.
+const TENANTS_DIR: &str = "tenants";
+
+/// The co-occurrences and counters of one tenant, apart from those of requests without a
+/// tenant and of every other tenant. The other models are shared.
+pub struct Tenant {
+    pub name: String,
+    pub co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    /// The identifiers `co_occurrence` knows, see `CoOccurrenceCounter::identifier_filter`
+    pub identifier_filter: Arc<IdentifierFilter>,
+    pub counters: Arc<RwLock<Counters>>,
+    /// The storage settings, with the tenant's directory as the data directory
+    pub storage: StorageSettings,
+}
+
+/// The allowed tenants, and the state of those that were used since the start.
+pub struct Tenants {
+    allowed: HashSet<String>,
+    /// The settings the state of every tenant is created with
+    settings: Settings,
+    loaded: Mutex<HashMap<String, Arc<Tenant>>>,
+    /// Hands new tenants to `run_tenant_tasks`
+    created: mpsc::UnboundedSender<Arc<Tenant>>,
+}
+
+/// Whether `name` can be used as a tenant, and as the name of its directory.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Tenants {
+    /// Returns the registry of the configured tenants, and the receiving end of the
+    /// tenants it creates, for `run_tenant_tasks`.
+    pub fn new(settings: &Settings) -> (Self, mpsc::UnboundedReceiver<Arc<Tenant>>) {
+        let mut allowed = HashSet::new();
+        for name in &settings.tenants.names {
+            if is_valid_name(name) {
+                allowed.insert(name.clone());
+            } else {
+                warn!("Ignoring tenant '{}', names may only contain letters, digits, '-' and '_'.", name);
+            }
+        }
+        // Tenants keep their state in files; the databases only hold the default state
+        let mut settings = settings.clone();
+        (settings.storage.postgres_url, settings.storage.sqlite_path, settings.storage.pairs_path) = (None, None, None);
+        let (created, receiver) = mpsc::unbounded_channel();
+        let tenants = Tenants {
+            allowed,
+            settings,
+            loaded: Mutex::new(HashMap::new()),
+            created,
+        };
+        (tenants, receiver)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed.is_empty()
+    }
+
+    /// Returns the tenant `name`, loading its persisted state on first use, or `None` if
+    /// it isn't allowed. Blocks while loading.
+    pub fn get(&self, name: &str) -> Result<Option<Arc<Tenant>>, String> {
+        if !self.allowed.contains(name) {
+            return Ok(None);
+        }
+        let mut loaded = locks::lock(&self.loaded, "tenants");
+        if let Some(tenant) = loaded.get(name) {
+            return Ok(Some(Arc::clone(tenant)));
+        }
+
+        let mut storage = self.settings.storage.clone();
+        storage.data_dir = self.settings.storage.data_dir.join(TENANTS_DIR).join(name);
+        snapshot::prepare_data_dir(&storage.data_dir)?;
+        // Shared counters get a key prefix of their own
+        let mut counters = self.settings.counters.clone();
+        counters.redis_key_prefix = format!("{}{}:", counters.redis_key_prefix, name);
+        // Only the state without a tenant is gossiped
+        if counters.backend == CounterBackend::Gossip {
+            counters.backend = CounterBackend::Memory;
+        }
+        let counter_store = open_counter_store(&counters, None);
+        let mut co_occurrence = CoOccurrenceCounter::with_metrics_cache(&self.settings.metrics_cache);
+        co_occurrence.set_pair_strategy(self.settings.pair_strategy);
+        co_occurrence.set_co_visitation_window(self.settings.co_visitation_window_secs);
+        co_occurrence.set_cardinality_limit(&self.settings.cardinality);
+        co_occurrence.set_identifier_filter(&self.settings.identifier_filter);
+        co_occurrence.recover(&storage);
+        let tenant = Arc::new(Tenant {
+            name: name.to_string(),
+            identifier_filter: co_occurrence.identifier_filter(),
+            co_occurrence: Arc::new(RwLock::new(co_occurrence)),
+            counters: Arc::new(RwLock::new(Counters::new(&counters, &storage, open_state_store(&storage), counter_store))),
+            storage,
+        });
+        info!(tenant = name, "Tenant loaded.");
+        loaded.insert(name.to_string(), Arc::clone(&tenant));
+        let _ = self.created.send(Arc::clone(&tenant));
+        Ok(Some(tenant))
+    }
+
+    /// Returns the tenants loaded so far.
+    pub fn loaded(&self) -> Vec<Arc<Tenant>> {
+        locks::lock(&self.loaded, "tenants").values().cloned().collect()
+    }
+}
+
+// Function to run the rotation, persistence, eviction and compaction tasks of every tenant as it is created,
+// like those of the default state. Aborting it stops them all.
+pub async fn run_tenant_tasks(mut created: mpsc::UnboundedReceiver<Arc<Tenant>>, settings: Settings) {
+    let mut tasks = JoinSet::new();
+    while let Some(tenant) = created.recv().await {
+        tasks.spawn(maintenance_jobs(&tenant.name, &tenant.co_occurrence, &tenant.counters, &settings, true).run());
+        if settings.counters.backend == CounterBackend::Redis {
+            tasks.spawn(run_counter_sync(Arc::clone(&tenant.counters), settings.counters.sync_interval_secs));
+        }
+    }
+}
+
+/// Persists the state of every loaded tenant, on shutdown.
+pub async fn perform_final_tenant_persistence(tenants: &Tenants) {
+    for tenant in tenants.loaded() {
+        info!(tenant = tenant.name, "Persisting tenant.");
+        perform_final_persistence(Arc::clone(&tenant.counters)).await;
+        let co_occurrence = Arc::clone(&tenant.co_occurrence);
+        if let Err(e) = web::block(move || locks::write(&co_occurrence, "co_occurrence").compact()).await {
+            error!("Error during final co-occurrence persistence block of tenant {}: {:?}", tenant.name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::rotating_counters::{count_of, Granularity};
+
+    #[test]
+    fn test_tenants_are_allowlisted_and_kept_apart() {
+        let mut settings = Settings::from_env();
+        settings.storage.data_dir = std::env::temp_dir().join(format!("mediathek_tenants_{}", std::process::id()));
+        settings.counters.event_log = false;
+        settings.tenants.names = vec!["ard".to_string(), "zdf".to_string(), "../etc".to_string()];
+        let (tenants, mut created) = Tenants::new(&settings);
+
+        assert!(tenants.get("arte").unwrap().is_none());
+        assert!(tenants.get("../etc").unwrap().is_none());
+        let ard = tenants.get("ard").unwrap().unwrap();
+        let zdf = tenants.get("zdf").unwrap().unwrap();
+        assert!(Arc::ptr_eq(&ard, &tenants.get("ard").unwrap().unwrap()));
+        locks::read(&ard.counters, "rotating_counters").increment("ard:1", 2);
+        assert_eq!(count_of(&locks::read(&ard.counters, "rotating_counters").buckets(Granularity::Hour)[0], "ard:1"), 2);
+        assert_eq!(count_of(&locks::read(&zdf.counters, "rotating_counters").buckets(Granularity::Hour)[0], "ard:1"), 0);
+        assert_eq!(ard.storage.data_path("rotating_counters.json"), settings.storage.data_dir.join("tenants/ard/rotating_counters.json"));
+        assert_eq!(created.try_recv().unwrap().name, "ard");
+        assert_eq!(created.try_recv().unwrap().name, "zdf");
+
+        std::fs::remove_dir_all(&settings.storage.data_dir).unwrap();
+    }
+}
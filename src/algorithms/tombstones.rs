@@ -0,0 +1,175 @@
+// src/algorithms/tombstones.rs
+use std::collections::HashMap;
+use std::path::PathBuf;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::algorithms::snapshot;
+use crate::config::{TombstoneMode, TombstoneSettings};
+
+/// Path of the tombstones file, relative to the data directory.
+pub const SNAPSHOT_PATH: &str = "tombstones.json";
+
+/// A deleted identifier, or prefix of deleted identifiers, kept out of the ingestion
+/// until it expires.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Tombstone {
+    /// The deleted identifier, or with `prefix` the start of every deleted one
+    pub identifier: String,
+    pub prefix: bool,
+    pub deleted_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The identifiers deleted within the configured time to live, so that the next list or
+/// play containing one doesn't silently re-create it. Kept in a file in the data
+/// directory, so they survive restarts.
+#[derive(Debug, Default)]
+pub struct Tombstones {
+    identifiers: HashMap<String, Tombstone>,
+    /// Few, from purges, so they are searched one by one
+    prefixes: Vec<Tombstone>,
+    ttl: Duration,
+    mode: TombstoneMode,
+    path: Option<PathBuf>,
+}
+
+impl Tombstones {
+    pub fn new(settings: &TombstoneSettings) -> Self {
+        Tombstones { ttl: Duration::seconds(settings.ttl_secs as i64), mode: settings.mode, ..Default::default() }
+    }
+
+    /// Loads the tombstones from `path`, which receives every change.
+    pub fn load(settings: &TombstoneSettings, path: PathBuf) -> Self {
+        let mut tombstones = Tombstones::new(settings);
+        for tombstone in snapshot::read::<Vec<Tombstone>>(&path).unwrap_or_default() {
+            tombstones.insert(tombstone);
+        }
+        tombstones.path = Some(path);
+        tombstones
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.ttl > Duration::zero()
+    }
+
+    /// Keeps `identifier`, or every identifier starting with it if `prefix`, out of the
+    /// ingestion for the time to live from `now`. Drops the expired tombstones.
+    pub fn bury(&mut self, identifier: &str, prefix: bool, now: DateTime<Utc>) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.identifiers.retain(|_, tombstone| tombstone.expires_at > now);
+        self.prefixes.retain(|tombstone| tombstone.expires_at > now);
+        self.insert(Tombstone { identifier: identifier.to_string(), prefix, deleted_at: now, expires_at: now + self.ttl });
+        self.persist();
+    }
+
+    fn insert(&mut self, tombstone: Tombstone) {
+        if tombstone.prefix {
+            self.prefixes.retain(|existing| existing.identifier != tombstone.identifier);
+            self.prefixes.push(tombstone);
+        } else {
+            self.identifiers.insert(tombstone.identifier.clone(), tombstone);
+        }
+    }
+
+    /// Returns the tombstone keeping `identifier` out at `now`, if any.
+    pub fn find(&self, identifier: &str, now: DateTime<Utc>) -> Option<&Tombstone> {
+        self.identifiers
+            .get(identifier)
+            .into_iter()
+            .chain(self.prefixes.iter().filter(|tombstone| identifier.starts_with(&tombstone.identifier)))
+            .find(|tombstone| tombstone.expires_at > now)
+    }
+
+    /// Applies the tombstones to a list about to be ingested: deleted identifiers are left
+    /// out, or with `TombstoneMode::Reject` the whole list is rejected with the reason.
+    pub fn filter_list<S: AsRef<str>>(&self, identifiers: &mut Vec<S>, now: DateTime<Utc>) -> Result<(), String> {
+        if self.identifiers.is_empty() && self.prefixes.is_empty() {
+            return Ok(());
+        }
+        if self.mode == TombstoneMode::Reject {
+            return identifiers.iter().try_for_each(|identifier| self.admits(identifier.as_ref(), now).map(|_| ()));
+        }
+        identifiers.retain(|identifier| self.find(identifier.as_ref(), now).is_none());
+        Ok(())
+    }
+
+    /// Whether a play of `identifier` is counted at `now`: `Ok(false)` if it is dropped,
+    /// an error with the reason if it is rejected.
+    pub fn admits(&self, identifier: &str, now: DateTime<Utc>) -> Result<bool, String> {
+        match (self.find(identifier, now), self.mode) {
+            (None, _) => Ok(true),
+            (Some(_), TombstoneMode::Drop) => Ok(false),
+            (Some(tombstone), TombstoneMode::Reject) => Err(format!(
+                "Identifier '{}' was deleted and can't be ingested before {}",
+                identifier,
+                tombstone.expires_at.to_rfc3339()
+            )),
+        }
+    }
+
+    /// Returns the tombstones that haven't expired at `now`, most recent first.
+    pub fn active(&self, now: DateTime<Utc>) -> Vec<Tombstone> {
+        let mut active: Vec<Tombstone> =
+            self.identifiers.values().chain(&self.prefixes).filter(|tombstone| tombstone.expires_at > now).cloned().collect();
+        active.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then_with(|| a.identifier.cmp(&b.identifier)));
+        active
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let tombstones: Vec<&Tombstone> = self.identifiers.values().chain(&self.prefixes).collect();
+        let result = serde_json::to_vec(&tombstones).map_err(|e| e.to_string()).and_then(|data| snapshot::write(path, &data).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("Failed to save the tombstones to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(mode: TombstoneMode) -> TombstoneSettings {
+        TombstoneSettings { ttl_secs: 3600, mode }
+    }
+
+    #[test]
+    fn test_deleted_identifiers_are_dropped_until_they_expire() {
+        let now = DateTime::UNIX_EPOCH;
+        let mut tombstones = Tombstones::new(&settings(TombstoneMode::Drop));
+        tombstones.bury("ard:a", false, now);
+        tombstones.bury("zdf:", true, now);
+
+        let mut list = vec!["ard:a", "ard:b", "zdf:c"];
+        tombstones.filter_list(&mut list, now).unwrap();
+        assert_eq!(list, ["ard:b"]);
+        assert_eq!(tombstones.admits("zdf:d", now), Ok(false));
+        assert_eq!(tombstones.active(now).len(), 2);
+
+        let later = now + Duration::hours(2);
+        assert_eq!(tombstones.admits("ard:a", later), Ok(true));
+        assert!(tombstones.active(later).is_empty());
+
+        let mut disabled = Tombstones::new(&TombstoneSettings { ttl_secs: 0, mode: TombstoneMode::Drop });
+        disabled.bury("ard:a", false, now);
+        assert_eq!(disabled.admits("ard:a", now), Ok(true));
+    }
+
+    #[test]
+    fn test_reject_mode_rejects_the_whole_list() {
+        let now = DateTime::UNIX_EPOCH;
+        let mut tombstones = Tombstones::new(&settings(TombstoneMode::Reject));
+        tombstones.bury("ard:a", false, now);
+        let mut list = vec!["ard:b".to_string(), "ard:a".to_string()];
+        assert!(tombstones.filter_list(&mut list, now).is_err());
+        assert_eq!(list.len(), 2);
+        assert!(tombstones.admits("ard:a", now).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+// src/algorithms/transitions.rs
+use std::collections::HashMap;
+use ahash::RandomState;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::algorithms::interner::Interner;
+use crate::determinism;
+
+/// A single predicted next item, as returned by `TransitionCounter::get_next_items`.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct NextItem {
+    pub identifier: String,
+    /// How often the transition target -> identifier has been observed.
+    pub count: u32,
+    /// Share of all observed transitions leaving the target that went to this identifier.
+    pub probability: f64,
+}
+
+/// A first-order Markov model over ordered identifier lists.
+///
+/// Unlike `CoOccurrenceCounter`, the order of a list matters here: only directly
+/// consecutive identifiers (A followed by B) are counted, and A -> B is tracked
+/// separately from B -> A.
+#[derive(Debug)]
+pub struct TransitionCounter {
+    /// Maps identifier strings to their unique integer IDs and back.
+    identifiers: Interner,
+    /// Stores the counts for each directed (from, to) pair of IDs.
+    transition_counts: HashMap<(u32, u32), u32, RandomState>,
+    /// Total number of outgoing transitions per ID, used to derive probabilities.
+    outgoing_totals: HashMap<u32, u32, RandomState>,
+}
+
+impl Default for TransitionCounter {
+    fn default() -> Self {
+        TransitionCounter::new()
+    }
+}
+
+impl TransitionCounter {
+    /// Creates a new, empty TransitionCounter.
+    pub fn new() -> Self {
+        TransitionCounter {
+            identifiers: Interner::new(),
+            transition_counts: HashMap::with_hasher(determinism::random_state()),
+            outgoing_totals: HashMap::with_hasher(determinism::random_state()),
+        }
+    }
+
+    /// Returns whether `identifier` has been part of any processed list.
+    pub fn contains(&self, identifier: &str) -> bool {
+        self.identifiers.contains(identifier)
+    }
+
+    /// Processes an ordered list of identifiers, counting each consecutive transition.
+    /// Immediate repetitions (A followed by A) are ignored.
+    pub fn process_sequence(&mut self, identifiers: &[String]) {
+        let ids: Vec<u32> = identifiers.iter().map(|s| self.identifiers.intern(s)).collect();
+
+        for window in ids.windows(2) {
+            let (from, to) = (window[0], window[1]);
+            if from == to {
+                continue;
+            }
+            *self.transition_counts.entry((from, to)).or_insert(0) += 1;
+            *self.outgoing_totals.entry(from).or_insert(0) += 1;
+        }
+    }
+
+    /// Returns the most likely next items after `target_id_str`, sorted by count
+    /// (descending) and limited to `limit` entries.
+    pub fn get_next_items(&self, target_id_str: &str, limit: usize) -> Vec<NextItem> {
+        let Some(target_id) = self.identifiers.get(target_id_str) else {
+            return Vec::new();
+        };
+        let Some(&total) = self.outgoing_totals.get(&target_id) else {
+            return Vec::new();
+        };
+
+        let mut next_items: Vec<NextItem> = self
+            .transition_counts
+            .iter()
+            .filter(|(&(from, _), _)| from == target_id)
+            .map(|(&(_, to), &count)| NextItem {
+                identifier: self.identifiers.resolve(to).unwrap_or_default().to_string(),
+                count,
+                probability: count as f64 / total as f64,
+            })
+            .collect();
+
+        // Sort by count, then by identifier so that ties are stable between calls
+        next_items.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.identifier.cmp(&b.identifier)));
+        next_items.truncate(limit);
+        next_items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID1_STR: &str = "ard:Y3JpZDovL2Rhc2Vyc3RlLmRlL3RhZ2Vzc2NoYXUyNA";
+    const ID2_STR: &str = "zdf:zdf-magazin-royale-102";
+    const ID3_STR: &str = "arte:RC-026195_de";
+
+    #[test]
+    fn test_transitions_are_directed() {
+        let mut counter = TransitionCounter::new();
+        counter.process_sequence(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+
+        let next = counter.get_next_items(ID1_STR, 10);
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].identifier, ID2_STR);
+        assert_eq!(next[0].count, 1);
+
+        // Nothing has ever followed ID2
+        assert!(counter.get_next_items(ID2_STR, 10).is_empty());
+    }
+
+    #[test]
+    fn test_probabilities_and_ordering() {
+        let mut counter = TransitionCounter::new();
+        counter.process_sequence(&[ID1_STR.to_string(), ID2_STR.to_string()]);
+        counter.process_sequence(&[ID1_STR.to_string(), ID3_STR.to_string()]);
+        counter.process_sequence(&[ID1_STR.to_string(), ID3_STR.to_string(), ID1_STR.to_string()]);
+
+        let next = counter.get_next_items(ID1_STR, 10);
+        assert_eq!(next.len(), 2);
+        assert_eq!(next[0].identifier, ID3_STR);
+        assert_eq!(next[0].count, 2);
+        assert!((next[0].probability - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(next[1].identifier, ID2_STR);
+
+        let limited = counter.get_next_items(ID1_STR, 1);
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].identifier, ID3_STR);
+    }
+
+    #[test]
+    fn test_repetitions_and_unknown_identifiers() {
+        let mut counter = TransitionCounter::new();
+        counter.process_sequence(&[ID1_STR.to_string(), ID1_STR.to_string()]);
+        assert!(counter.get_next_items(ID1_STR, 10).is_empty());
+        assert!(counter.get_next_items("non_existent_id", 10).is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+// src/algorithms/trending.rs
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::algorithms::rotating_counters::{count_of, Bucket, Counters};
+use crate::determinism;
+
+/// Pseudo-count added to both sides of the growth ratio so that items going from
+/// 0 to 1 don't get an infinite score.
+const GROWTH_SMOOTHING: f64 = 1.0;
+/// Number of past days averaged for the daily baseline.
+const TRAILING_DAYS: usize = 7;
+
+/// Which windows are compared when computing growth.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TrendingBasis {
+    /// The current hour compared to the previous hour
+    Hour,
+    /// Today compared to the average of the trailing week
+    Day,
+    /// Not growth, but the continuously decayed popularity (see `popularity`), which has
+    /// no cliffs at bucket rotations
+    Decayed,
+}
+
+/// A trending item with its growth score.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct TrendingItem {
+    pub id: String,
+    /// Smoothed ratio of `current` to `baseline`; > 1 means the item is growing. The
+    /// decayed popularity for the "decayed" basis.
+    pub score: f64,
+    pub current: u64,
+    /// 0 for the "decayed" basis
+    pub baseline: f64,
+}
+
+/// Ranks identifiers by relative growth instead of absolute counts, so that items
+/// taking off are surfaced ahead of evergreen shows with constantly high counts.
+/// Items with fewer than `min_count` events in the current window are ignored.
+pub fn trending(counters: &Counters, basis: TrendingBasis, min_count: u64, limit: usize) -> Vec<TrendingItem> {
+    let (current_bucket, baseline_buckets): (&Bucket, &[Bucket]) = match basis {
+        TrendingBasis::Hour => (&counters.hourly[0], &counters.hourly[1..counters.hourly.len().min(2)]),
+        TrendingBasis::Day => (&counters.daily[0], &counters.daily[1..counters.daily.len().min(TRAILING_DAYS + 1)]),
+        TrendingBasis::Decayed => return decayed(counters, min_count, limit),
+    };
+
+    let mut items: Vec<TrendingItem> = current_bucket
+        .iter()
+        .filter(|entry| *entry.value() >= min_count)
+        .map(|entry| {
+            let (id, current) = (entry.key(), *entry.value());
+            let baseline = if baseline_buckets.is_empty() {
+                0.0
+            } else {
+                let total: u64 = baseline_buckets.iter().map(|b| count_of(b, id)).sum();
+                total as f64 / baseline_buckets.len() as f64
+            };
+            TrendingItem {
+                id: id.clone(),
+                score: (current as f64 + GROWTH_SMOOTHING) / (baseline + GROWTH_SMOOTHING),
+                current,
+                baseline,
+            }
+        })
+        .collect();
+
+    items.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| b.current.cmp(&a.current))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    items.truncate(limit);
+    items
+}
+
+/// Ranks identifiers by their decayed popularity. `min_count` applies to today's count,
+/// which is reported as `current`.
+fn decayed(counters: &Counters, min_count: u64, limit: usize) -> Vec<TrendingItem> {
+    let mut items: Vec<TrendingItem> = counters
+        .popularity
+        .scores(determinism::now())
+        .into_iter()
+        .map(|(id, score)| TrendingItem { current: count_of(&counters.daily[0], &id), id, score, baseline: 0.0 })
+        .filter(|item| item.current >= min_count)
+        .collect();
+    items.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.id.cmp(&b.id)));
+    items.truncate(limit);
+    items
+}
+
+/// A newly appearing item with its velocity.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct RisingStar {
+    pub id: String,
+    pub first_seen: DateTime<Utc>,
+    /// Count since the item was first seen
+    pub count: u64,
+    /// Count per hour since the item was first seen
+    pub velocity: f64,
+}
+
+/// Ranks identifiers first seen within the last `max_age_hours` by their velocity
+/// (count per hour since first seen), so brand-new content that is taking off is
+/// discovered before it dominates the absolute counts.
+pub fn rising_stars(
+    counters: &Counters,
+    now: DateTime<Utc>,
+    max_age_hours: u32,
+    min_count: u64,
+    limit: usize,
+) -> Vec<RisingStar> {
+    let max_age = chrono::Duration::hours(max_age_hours as i64);
+
+    let mut stars: Vec<RisingStar> = counters
+        .first_seen
+        .iter()
+        .filter(|entry| now - *entry.value() <= max_age)
+        .filter_map(|entry| {
+            let (id, first_seen) = (entry.key(), *entry.value());
+            // Everything since first seen is contained in the daily buckets back to that day
+            let days = (now.date_naive() - first_seen.date_naive()).num_days().max(0) as usize;
+            let count: u64 = counters.daily.iter().take(days + 1).map(|b| count_of(b, id)).sum();
+            if count < min_count {
+                return None;
+            }
+            // At least one hour, so a burst in the first minutes doesn't explode the score
+            let age_hours = ((now - first_seen).num_seconds() as f64 / 3600.0).max(1.0);
+            Some(RisingStar {
+                id: id.clone(),
+                first_seen,
+                count,
+                velocity: count as f64 / age_hours,
+            })
+        })
+        .collect();
+
+    stars.sort_by(|a, b| b.velocity.total_cmp(&a.velocity).then_with(|| a.id.cmp(&b.id)));
+    stars.truncate(limit);
+    stars
+}
+
+/// An item whose count changed between two windows.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct Mover {
+    pub id: String,
+    pub current: u64,
+    pub previous: u64,
+    /// `current` minus `previous`
+    pub change: i64,
+    /// Smoothed relative change, e.g. 1.0 for a doubling and -0.5 for a halving
+    pub relative_change: f64,
+}
+
+/// The items that changed most between two windows, each list most changed first.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct Movers {
+    /// Largest increases in plays
+    pub gainers: Vec<Mover>,
+    /// Largest decreases in plays
+    pub losers: Vec<Mover>,
+    /// Largest relative increases
+    pub relative_gainers: Vec<Mover>,
+    /// Largest relative decreases
+    pub relative_losers: Vec<Mover>,
+}
+
+/// Compares the counts of `current` with those of `previous`, e.g. today with yesterday.
+/// Items with fewer than `min_count` plays in both windows are ignored, so a single play
+/// more doesn't top the relative lists.
+pub fn movers(current: &Bucket, previous: &Bucket, min_count: u64, limit: usize) -> Movers {
+    let ids = current.iter().map(|entry| entry.key().clone()).chain(
+        previous.iter().filter(|entry| !current.contains_key(entry.key())).map(|entry| entry.key().clone()),
+    );
+    let changed: Vec<Mover> = ids
+        .filter_map(|id| {
+            let (now, before) = (count_of(current, &id), count_of(previous, &id));
+            if now.max(before) < min_count || now == before {
+                return None;
+            }
+            Some(Mover {
+                id,
+                current: now,
+                previous: before,
+                change: now as i64 - before as i64,
+                relative_change: (now as f64 + GROWTH_SMOOTHING) / (before as f64 + GROWTH_SMOOTHING) - 1.0,
+            })
+        })
+        .collect();
+
+    let top = |increasing: bool, key: fn(&Mover) -> f64| {
+        let mut items: Vec<Mover> = changed.iter().filter(|item| (item.change > 0) == increasing).cloned().collect();
+        items.sort_by(|a, b| {
+            let order = if increasing { key(b).total_cmp(&key(a)) } else { key(a).total_cmp(&key(b)) };
+            order.then_with(|| a.id.cmp(&b.id))
+        });
+        items.truncate(limit);
+        items
+    };
+    Movers {
+        gainers: top(true, |item| item.change as f64),
+        losers: top(false, |item| item.change as f64),
+        relative_gainers: top(true, |item| item.relative_change),
+        relative_losers: top(false, |item| item.relative_change),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::rotating_counters::Granularity;
+
+    fn increment_n(counters: &mut Counters, id: &str, n: u64) {
+        counters.increment(id, n);
+    }
+
+    #[test]
+    fn test_growth_beats_absolute_counts() {
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        increment_n(&mut counters, "evergreen", 100);
+        increment_n(&mut counters, "new_hit", 2);
+        counters.rotate(Granularity::Hour, 1);
+        increment_n(&mut counters, "evergreen", 100);
+        increment_n(&mut counters, "new_hit", 30);
+
+        let items = trending(&counters, TrendingBasis::Hour, 1, 10);
+        assert_eq!(items[0].id, "new_hit");
+        assert_eq!(items[0].current, 30);
+        assert!((items[0].baseline - 2.0).abs() < 1e-9);
+        assert_eq!(items[1].id, "evergreen");
+    }
+
+    #[test]
+    fn test_daily_basis_uses_trailing_average() {
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        increment_n(&mut counters, "a", 4);
+        counters.rotate(Granularity::Day, 1);
+        increment_n(&mut counters, "a", 10);
+
+        let items = trending(&counters, TrendingBasis::Day, 1, 10);
+        assert_eq!(items.len(), 1);
+        // 4 plays spread over the 7 trailing days
+        assert!((items[0].baseline - 4.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rising_stars_only_include_new_items() {
+        let now = Utc::now();
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        increment_n(&mut counters, "old", 100);
+        increment_n(&mut counters, "fast", 20);
+        increment_n(&mut counters, "slow", 20);
+        counters.first_seen.insert("old".to_string(), now - chrono::Duration::days(3));
+        counters.first_seen.insert("fast".to_string(), now - chrono::Duration::hours(2));
+        counters.first_seen.insert("slow".to_string(), now - chrono::Duration::hours(10));
+
+        let stars = rising_stars(&counters, now, 24, 1, 10);
+        let ids: Vec<&str> = stars.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["fast", "slow"]);
+        assert!((stars[0].velocity - 10.0).abs() < 1e-6);
+
+        assert_eq!(rising_stars(&counters, now, 24, 21, 10).len(), 0);
+    }
+
+    #[test]
+    fn test_min_count_and_limit() {
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        increment_n(&mut counters, "a", 1);
+        increment_n(&mut counters, "b", 5);
+        increment_n(&mut counters, "c", 6);
+
+        assert_eq!(trending(&counters, TrendingBasis::Day, 5, 10).len(), 2);
+        assert_eq!(trending(&counters, TrendingBasis::Day, 1, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_movers_are_ranked_by_absolute_and_relative_change() {
+        let mut counters = Counters::with_depths(3, 13, 4, 3);
+        increment_n(&mut counters, "evergreen", 100);
+        increment_n(&mut counters, "fading", 10);
+        increment_n(&mut counters, "steady", 5);
+        counters.rotate(Granularity::Day, 1);
+        increment_n(&mut counters, "evergreen", 120);
+        increment_n(&mut counters, "new_hit", 15);
+        increment_n(&mut counters, "steady", 5);
+        increment_n(&mut counters, "noise", 1);
+
+        let changes = movers(&counters.daily[0], &counters.daily[1], 3, 10);
+        let ids = |items: &[Mover]| items.iter().map(|item| item.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&changes.gainers), ["evergreen", "new_hit"]);
+        assert_eq!(ids(&changes.relative_gainers), ["new_hit", "evergreen"]);
+        assert_eq!(ids(&changes.losers), ["fading"]);
+        assert_eq!((changes.losers[0].change, changes.losers[0].relative_change), (-10, 1.0 / 11.0 - 1.0));
+        assert_eq!(changes.relative_gainers[0].relative_change, 15.0);
+    }
+}
@@ -0,0 +1,113 @@
+// src/algorithms/warmup.rs
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use actix_web::web;
+use chrono_tz::Tz;
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+use crate::algorithms::backup;
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::config::WarmupSettings;
+use crate::locks;
+
+/// How long downloading the backup may take.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(600);
+/// Largest backup downloaded.
+const MAX_BACKUP_BYTES: usize = 16 << 30;
+/// Largest checksum file downloaded, which holds a single line.
+const MAX_CHECKSUM_BYTES: usize = 4096;
+
+/// Whether neither the co-occurrences nor the counters hold anything, i.e. the instance
+/// starts on a fresh disk rather than from its own snapshots.
+fn is_empty(co_occurrence: &RwLock<CoOccurrenceCounter>, counters: &RwLock<Counters>) -> bool {
+    locks::read(co_occurrence, "co_occurrence").identifier_count() == 0 && locks::read(counters, "rotating_counters").first_seen.is_empty()
+}
+
+/// Returns the hex-encoded SHA-256 of a checksum file in the format of `sha256sum`: the
+/// checksum, optionally followed by the file name.
+fn parse_checksum(file: &[u8]) -> Option<String> {
+    let checksum = std::str::from_utf8(file).ok()?.split_whitespace().next()?;
+    (checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit())).then(|| checksum.to_ascii_lowercase())
+}
+
+/// Fails unless the SHA-256 of `data` is `expected` (hex-encoded, in either case).
+fn verify(data: &[u8], expected: &str) -> Result<(), String> {
+    let actual = hex::encode(Sha256::digest(data));
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(format!("Checksum mismatch: expected {}, got {}", expected.trim(), actual));
+    }
+    Ok(())
+}
+
+async fn download(client: &awc::Client, url: &str, limit: usize) -> Result<web::Bytes, String> {
+    let mut response = client.get(url).timeout(DOWNLOAD_TIMEOUT).send().await.map_err(|e| format!("Failed to request {}: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("{} responded with status {}", url, response.status()));
+    }
+    response.body().limit(limit).await.map_err(|e| format!("Failed to download {}: {}", url, e))
+}
+
+/// Downloads the backup, verifies its checksum and loads it.
+async fn load(settings: &WarmupSettings, url: &str, co_occurrence: &Arc<RwLock<CoOccurrenceCounter>>, counters: &Arc<RwLock<Counters>>, timezone: Tz) -> Result<backup::BackupMetadata, String> {
+    let client = awc::Client::default();
+    let expected = match &settings.sha256 {
+        Some(checksum) => checksum.clone(),
+        None => {
+            let file = download(&client, &format!("{}.sha256", url), MAX_CHECKSUM_BYTES).await?;
+            parse_checksum(&file).ok_or("The checksum file holds no SHA-256")?
+        }
+    };
+    let data = download(&client, url, MAX_BACKUP_BYTES).await?;
+    verify(&data, &expected)?;
+
+    let (co_occurrence, counters) = (Arc::clone(co_occurrence), Arc::clone(counters));
+    web::block(move || {
+        let metadata = backup::restore(&data, &co_occurrence, &counters, timezone)?;
+        locks::write(&counters, "rotating_counters").persist();
+        Ok(metadata)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Loads the backup at the configured URL into an instance starting without any state,
+/// e.g. a new one of an autoscaling group, before it serves requests. Instances with state
+/// of their own keep it. On any failure, including a checksum mismatch, the instance
+/// starts empty. Must be called on the actix runtime, since the HTTP client is not `Send`.
+pub async fn warm_up(settings: &WarmupSettings, co_occurrence: &Arc<RwLock<CoOccurrenceCounter>>, counters: &Arc<RwLock<Counters>>, timezone: Tz) {
+    let Some(url) = &settings.url else {
+        return;
+    };
+    if !is_empty(co_occurrence, counters) {
+        info!("Skipped the warm-up, the state was loaded from the data directory.");
+        return;
+    }
+    match load(settings, url, co_occurrence, counters, timezone).await {
+        Ok(metadata) => info!(
+            identifiers = metadata.identifiers,
+            pairs = metadata.pairs,
+            created_at = %metadata.created_at,
+            "Warmed up from {}.",
+            url
+        ),
+        Err(e) => error!("Failed to warm up from {}, starting empty: {}", url, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums_are_verified() {
+        let checksum = "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+        assert!(verify(b"foo", checksum).is_ok());
+        assert!(verify(b"foo", &checksum.to_ascii_uppercase()).is_ok());
+        assert!(verify(b"bar", checksum).is_err());
+
+        let file = format!("{}  mediathek-backup.bin\n", checksum.to_ascii_uppercase());
+        assert_eq!(parse_checksum(file.as_bytes()).as_deref(), Some(checksum));
+        assert_eq!(parse_checksum(b"not a checksum"), None);
+    }
+}
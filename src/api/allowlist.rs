@@ -0,0 +1,68 @@
+// src/api/allowlist.rs
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, ResponseError};
+
+use crate::api::error::ApiError;
+use crate::api::{is_read, route_pattern};
+use crate::config::{AllowlistSettings, SharedSettings};
+
+/// Returns the client's address: the peer address, or the one reported by a trusted proxy.
+fn client_ip(req: &ServiceRequest, trust_proxy: bool) -> Option<IpAddr> {
+    if !trust_proxy {
+        return req.peer_addr().map(|address| address.ip());
+    }
+    let address = req.connection_info().realip_remote_addr()?.to_string();
+    address.parse().ok().or_else(|| address.parse::<SocketAddr>().ok().map(|address| address.ip()))
+}
+
+/// Whether a request is restricted to the allowed networks: the admin endpoints, and
+/// writes as well if configured.
+fn is_restricted(req: &ServiceRequest, settings: &AllowlistSettings) -> bool {
+    route_pattern(req).starts_with("/admin") || (settings.include_writes && !is_read(req))
+}
+
+/// Middleware rejecting restricted requests from outside the allowed networks with 403.
+/// Without any configured networks, all requests pass.
+pub async fn ip_allowlist(
+    settings: web::Data<Arc<SharedSettings>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let settings = settings.current();
+    let allowlist = &settings.allowlist;
+    if !allowlist.networks.0.is_empty() && is_restricted(&req, allowlist) {
+        let allowed = client_ip(&req, allowlist.trust_proxy)
+            .is_some_and(|ip| allowlist.networks.0.iter().any(|network| network.contains(ip)));
+        if !allowed {
+            let error = ApiError::Forbidden("Not allowed from this address".to_string());
+            return Ok(req.into_response(error.error_response()).map_into_right_body());
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{IpNetwork, IpNetworks};
+
+    #[test]
+    fn test_networks_contain_addresses() {
+        let networks: IpNetworks = "10.0.0.0/8, 192.168.1.7, fd00::/8".parse().unwrap();
+        let allowed = |address: &str| networks.0.iter().any(|network| network.contains(address.parse().unwrap()));
+        assert!(allowed("10.1.2.3"));
+        assert!(allowed("::ffff:10.1.2.3"));
+        assert!(allowed("192.168.1.7"));
+        assert!(!allowed("192.168.1.8"));
+        assert!(!allowed("11.0.0.1"));
+        assert!(allowed("fd12::1"));
+        assert!(!allowed("fe80::1"));
+
+        assert!("0.0.0.0/0".parse::<IpNetwork>().unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("10.0.0/8".parse::<IpNetwork>().is_err());
+    }
+}
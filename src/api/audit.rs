@@ -0,0 +1,177 @@
+// src/api/audit.rs
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::api::auth::ApiClient;
+use crate::api::tenants::RequestTenant;
+use crate::api::{is_read, route_pattern};
+use crate::{determinism, locks};
+
+/// Path of the audit log, relative to the data directory.
+pub const AUDIT_LOG_PATH: &str = "audit.log";
+
+/// An administrative operation, as recorded in the audit log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    /// Name of the API key the request was made with, if any
+    pub client: Option<String>,
+    /// Whether the request carried an admin token
+    pub admin_token: bool,
+    /// Peer address of the connection
+    pub peer: Option<String>,
+    pub tenant: Option<String>,
+    pub method: String,
+    /// Path and query as sent
+    pub path: String,
+    pub status: u16,
+}
+
+/// Append-only log of the administrative operations, one JSON line per entry, kept in the
+/// data directory. Never truncated by the server; rotate it with external tools if needed.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    /// `None` if disabled, or if the file couldn't be opened
+    file: Mutex<Option<File>>,
+    path: Option<PathBuf>,
+}
+
+impl AuditLog {
+    /// Opens (or creates) the log at `path`. If that fails, the error is logged and
+    /// operations go unrecorded.
+    pub fn open(path: PathBuf) -> Self {
+        let file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                error!("Failed to open the audit log {}: {}", path.display(), e);
+                None
+            }
+        };
+        AuditLog { file: Mutex::new(file), path: Some(path) }
+    }
+
+    /// Appends `entry` and syncs it to disk, as entries are rare and must not get lost.
+    pub fn append(&self, entry: &AuditEntry) {
+        let mut file = locks::lock(&self.file, "audit_log");
+        let Some(file) = file.as_mut() else {
+            return;
+        };
+        let result = serde_json::to_vec(entry).map_err(io::Error::other).and_then(|mut line| {
+            line.push(b'\n');
+            file.write_all(&line)?;
+            file.sync_data()
+        });
+        if let Err(e) = result {
+            error!("Failed to record {} {} in the audit log: {}", entry.method, entry.path, e);
+        }
+    }
+
+    /// Returns the last `limit` entries at or after `since`, most recent first. Lines that
+    /// can't be parsed, e.g. cut off by a crash, are skipped.
+    pub fn read(&self, since: Option<DateTime<Utc>>, limit: usize) -> io::Result<Vec<AuditEntry>> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = VecDeque::with_capacity(limit.min(1024));
+        for line in BufReader::new(file).lines() {
+            let Ok(entry) = serde_json::from_str::<AuditEntry>(&line?) else {
+                continue;
+            };
+            if since.is_some_and(|since| entry.at < since) || limit == 0 {
+                continue;
+            }
+            if entries.len() == limit {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+        Ok(entries.into_iter().rev().collect())
+    }
+}
+
+/// Whether a request is recorded: everything changing state under /admin but the
+/// exchange between gossip peers, which happens every few seconds, and deletes.
+fn is_audited(req: &ServiceRequest) -> bool {
+    let pattern = route_pattern(req);
+    !is_read(req) && ((pattern.starts_with("/admin") && pattern != "/admin/gossip") || req.method() == Method::DELETE)
+}
+
+/// Middleware recording administrative operations in the audit log, with who made them
+/// and how they ended. Runs after the authentication, so the API key is known.
+pub async fn audit_trail(
+    audit_log: web::Data<Arc<AuditLog>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if !is_audited(&req) {
+        return next.call(req).await;
+    }
+    let tenant = req.extensions().get::<RequestTenant>().map(|tenant| (tenant.tenant.name.clone(), tenant.original_path.clone()));
+    let (tenant, original_path) = tenant.unzip();
+    let mut entry = AuditEntry {
+        at: determinism::now(),
+        client: req.extensions().get::<ApiClient>().map(|client| client.name.clone()),
+        admin_token: req.headers().contains_key(header::AUTHORIZATION),
+        peer: req.peer_addr().map(|address| address.ip().to_string()),
+        tenant,
+        method: req.method().to_string(),
+        path: original_path.flatten().unwrap_or_else(|| req.uri().path_and_query().map_or(req.path(), |path| path.as_str()).to_string()),
+        status: 0,
+    };
+    let result = next.call(req).await;
+    entry.status = match &result {
+        Ok(response) => response.status().as_u16(),
+        Err(e) => e.as_response_error().status_code().as_u16(),
+    };
+    audit_log.append(&entry);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_are_appended_and_read_back_newest_first() {
+        let path = std::env::temp_dir().join(format!("mediathek_audit_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog::open(path.clone());
+        let entry = |minute: i64, path: &str| AuditEntry {
+            at: DateTime::UNIX_EPOCH + chrono::Duration::minutes(minute),
+            client: Some("ops".to_string()),
+            admin_token: true,
+            peer: None,
+            tenant: None,
+            method: "POST".to_string(),
+            path: path.to_string(),
+            status: 200,
+        };
+        log.append(&entry(1, "/v1/admin/purge"));
+        log.append(&entry(2, "/v1/admin/restore"));
+        log.append(&entry(3, "/v1/admin/settings/reload"));
+
+        let read = AuditLog::open(path.clone()).read(None, 2).unwrap();
+        assert_eq!(read, [entry(3, "/v1/admin/settings/reload"), entry(2, "/v1/admin/restore")]);
+        let since = log.read(Some(DateTime::UNIX_EPOCH + chrono::Duration::minutes(2)), 10).unwrap();
+        assert_eq!(since.len(), 2);
+        assert!(AuditLog::default().read(None, 10).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+// src/api/auth.rs
+use std::sync::Arc;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, ResponseError};
+
+use crate::api::error::ApiError;
+use crate::api::{is_read, route_pattern};
+use crate::config::{ApiKey, Settings, SharedSettings};
+
+/// Name of the header clients send their API key in.
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// The authenticated client of a request, stored in the request extensions.
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    /// Name of the key the client sent
+    pub name: String,
+}
+
+/// Compares two byte strings in time independent of where they differ, so keys can't be
+/// guessed byte by byte from response times.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Returns the configured key matching `candidate`, if any.
+pub fn find_key<'a>(keys: &'a [ApiKey], candidate: &str) -> Option<&'a ApiKey> {
+    keys.iter().find(|key| constant_time_eq(key.key.as_bytes(), candidate.as_bytes()))
+}
+
+/// Whether a request needs a key: everything that changes state, reads as well if
+/// configured, and the admin endpoints unless they are protected by their own token.
+fn requires_key(req: &ServiceRequest, settings: &Settings) -> bool {
+    let is_admin = route_pattern(req).starts_with("/admin");
+    settings.auth.protect_reads || !is_read(req) || (is_admin && settings.admin.token.is_none())
+}
+
+/// Middleware checking the `X-Api-Key` header. Known keys are recorded as `ApiClient`
+/// (and in the request span) even where no key is required; unknown keys are rejected.
+/// Without any configured keys, all requests pass.
+pub async fn authenticate(
+    settings: web::Data<Arc<SharedSettings>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let settings = settings.current();
+    let keys = &settings.auth.api_keys.0;
+    // Clients that signed the request were authenticated already
+    if keys.is_empty() || req.extensions().contains::<ApiClient>() {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let sent_key = req.headers().get(API_KEY_HEADER).map(|value| value.to_str().unwrap_or_default());
+    let rejection = match sent_key.map(|sent_key| find_key(keys, sent_key)) {
+        Some(Some(key)) => {
+            tracing::Span::current().record("client", key.name.as_str());
+            req.extensions_mut().insert(ApiClient { name: key.name.clone() });
+            None
+        }
+        Some(None) => Some("Invalid API key"),
+        None if requires_key(&req, &settings) => Some("Missing API key in the X-Api-Key header"),
+        None => None,
+    };
+    if let Some(message) = rejection {
+        let error = ApiError::Unauthorized(message.to_string());
+        return Ok(req.into_response(error.error_response()).map_into_right_body());
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+/// Middleware of the /admin scope checking the `Authorization: Bearer <token>` header
+/// against the admin token. Without a configured token, all requests pass (they then need
+/// an API key, see `requires_key`).
+pub async fn require_admin_token(
+    settings: web::Data<Arc<SharedSettings>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let settings = settings.current();
+    if let Some(token) = &settings.admin.token {
+        let sent_token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let rejection = match sent_token {
+            Some(sent_token) if constant_time_eq(token.as_bytes(), sent_token.trim().as_bytes()) => None,
+            Some(_) => Some("Invalid admin token"),
+            None => Some("Missing admin token in the Authorization header"),
+        };
+        if let Some(message) = rejection {
+            let error = ApiError::Unauthorized(message.to_string());
+            return Ok(req.into_response(error.error_response()).map_into_right_body());
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_key() {
+        let keys: crate::config::ApiKeys = "ingest=abc123, dashboard=xyz".parse().unwrap();
+        assert_eq!(find_key(&keys.0, "xyz").unwrap().name, "dashboard");
+        assert!(find_key(&keys.0, "abc12").is_none());
+        assert!(find_key(&keys.0, "").is_none());
+        assert!("ingest=".parse::<crate::config::ApiKeys>().is_err());
+    }
+}
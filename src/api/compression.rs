@@ -0,0 +1,139 @@
+// src/api/compression.rs
+use std::io::Write;
+use std::sync::Arc;
+use actix_web::body::{self, BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web::Bytes;
+use actix_web::{error, web, Error};
+use flate2::write::GzEncoder;
+
+use crate::config::SharedSettings;
+
+/// A content coding the server can produce.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn header_value(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        })
+    }
+
+    fn compress(self, data: &[u8], level: u32) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut output = Vec::with_capacity(data.len() / 4);
+                let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, level, 22);
+                encoder.write_all(data)?;
+                drop(encoder);
+                Ok(output)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 4), flate2::Compression::new(level.min(9)));
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Picks the encoding with the highest quality value in an `Accept-Encoding` header,
+/// preferring brotli on ties. Returns `None` if the client accepts neither.
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';');
+        let encoding = match parts.next().unwrap_or_default().trim().to_ascii_lowercase().as_str() {
+            "br" => Encoding::Brotli,
+            "gzip" | "x-gzip" => Encoding::Gzip,
+            _ => continue,
+        };
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(1.0, |quality| quality.trim().parse().unwrap_or(0.0));
+        let better = match best {
+            None => true,
+            Some((_, best_quality)) => quality > best_quality || (quality == best_quality && encoding == Encoding::Brotli),
+        };
+        if quality > 0.0 && better {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Middleware compressing response bodies with brotli or gzip, as the client's
+/// `Accept-Encoding` allows. Only complete bodies of at least the configured size are
+/// compressed; the compression itself runs on the blocking thread pool.
+pub async fn compress(
+    settings: web::Data<Arc<SharedSettings>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let settings = settings.current();
+    let compression = &settings.compression;
+    let encoding = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(negotiate)
+        .filter(|_| compression.enabled);
+    let mut res = next.call(req).await?;
+    if compression.enabled {
+        res.headers_mut().append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+
+    let large_enough = matches!(res.response().body().size(), BodySize::Sized(size) if size >= compression.min_bytes as u64);
+    let Some(encoding) = encoding.filter(|_| large_enough && !res.headers().contains_key(header::CONTENT_ENCODING)) else {
+        return Ok(res.map_into_left_body());
+    };
+
+    let (req, res) = res.into_parts();
+    let (mut res, body) = res.into_parts();
+    let data = body::to_bytes(body).await.map_err(|e| error::ErrorInternalServerError(e.into()))?;
+    let level = compression.level;
+    let compressed = web::block(move || encoding.compress(&data, level)).await??;
+
+    res.headers_mut().insert(header::CONTENT_ENCODING, encoding.header_value());
+    res.headers_mut().remove(header::CONTENT_LENGTH);
+    let res = res.set_body(Bytes::from(compressed));
+    Ok(ServiceResponse::new(req, res).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, identity"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn test_compress_round_trip() {
+        let data = br#"{"counts":[1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1]}"#.repeat(20);
+
+        let gzip = Encoding::Gzip.compress(&data, 6).unwrap();
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&gzip[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        let brotli = Encoding::Brotli.compress(&data, 11).unwrap();
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(&brotli[..], 4096).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        assert!(brotli.len() < data.len() / 10);
+    }
+}
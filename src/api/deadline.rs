@@ -0,0 +1,85 @@
+// src/api/deadline.rs
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+
+use crate::api::error::ApiError;
+use crate::config::SharedSettings;
+
+/// Header in which clients give the milliseconds they wait for a response.
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout";
+
+/// When a request has to be answered by: after the milliseconds of the `X-Request-Timeout`
+/// header or the configured default (`MEDIATHEK_REQUEST_TIMEOUT_MS`), whichever is shorter.
+/// Heavy handlers `check` it between chunks of work, so a request the client gave up on
+/// stops holding the locks.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// A deadline `budget` from now; none without a budget.
+    pub fn after(budget: Option<Duration>) -> Self {
+        Deadline(budget.map(|budget| Instant::now() + budget))
+    }
+
+    pub fn instant(&self) -> Option<Instant> {
+        self.0
+    }
+
+    /// Fails with 504 once the deadline passed.
+    pub fn check(&self) -> Result<(), ApiError> {
+        match self.0 {
+            Some(at) if Instant::now() >= at => Err(ApiError::DeadlineExceeded),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The budget of a request with the `header` value, given the server's `default_ms` (0 for none).
+fn budget(header: Option<&str>, default_ms: u64) -> Result<Option<Duration>, ApiError> {
+    let requested = header
+        .map(|value| value.trim().parse::<u64>())
+        .transpose()
+        .map_err(|_| ApiError::BadRequest(format!("{} must be a number of milliseconds", REQUEST_TIMEOUT_HEADER)))?;
+    let milliseconds = match (requested, default_ms) {
+        (Some(requested), 0) => Some(requested),
+        (Some(requested), default_ms) => Some(requested.min(default_ms)),
+        (None, 0) => None,
+        (None, default_ms) => Some(default_ms),
+    };
+    Ok(milliseconds.map(Duration::from_millis))
+}
+
+impl FromRequest for Deadline {
+    type Error = ApiError;
+    type Future = std::future::Ready<Result<Deadline, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let header = req.headers().get(REQUEST_TIMEOUT_HEADER).map(|value| value.to_str().unwrap_or_default());
+        let default_ms = req
+            .app_data::<web::Data<Arc<SharedSettings>>>()
+            .map_or(0, |settings| settings.current().overload.request_timeout_ms);
+        std::future::ready(budget(header, default_ms).map(Deadline::after))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shorter_of_header_and_default_applies() {
+        assert_eq!(budget(None, 0).unwrap(), None);
+        assert_eq!(budget(None, 500).unwrap(), Some(Duration::from_millis(500)));
+        assert_eq!(budget(Some("200"), 0).unwrap(), Some(Duration::from_millis(200)));
+        assert_eq!(budget(Some(" 200 "), 500).unwrap(), Some(Duration::from_millis(200)));
+        // Clients can't wait longer than the server allows
+        assert_eq!(budget(Some("2000"), 500).unwrap(), Some(Duration::from_millis(500)));
+        assert!(matches!(budget(Some("2s"), 0), Err(ApiError::BadRequest(_))));
+
+        assert!(Deadline::after(None).check().is_ok());
+        assert!(Deadline::after(Some(Duration::from_secs(60))).check().is_ok());
+        assert!(matches!(Deadline::after(Some(Duration::ZERO)).check(), Err(ApiError::DeadlineExceeded)));
+    }
+}
@@ -0,0 +1,188 @@
+// src/api/encoding.rs
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use actix_web::dev::Payload;
+use actix_web::error::JsonPayloadError;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::api::error::ApiError;
+
+/// Largest body `Body` accepts, the same as actix's default for JSON.
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// An encoding of the API models. All of them carry the same fields; MessagePack and
+/// CBOR are just more compact, e.g. for embedded clients.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Format {
+    fn from_media_type(media_type: &str) -> Option<Format> {
+        match media_type.trim().to_ascii_lowercase().as_str() {
+            "application/json" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Format::MessagePack),
+            "application/cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    /// Picks the format with the highest quality value in an `Accept` header. Clients
+    /// accepting none of them (or anything) get JSON.
+    fn negotiate(accept: &str) -> Format {
+        let mut best = (Format::Json, 0.0);
+        for entry in accept.split(',') {
+            let mut parts = entry.split(';');
+            let Some(format) = Format::from_media_type(parts.next().unwrap_or_default()) else {
+                continue;
+            };
+            let quality: f32 = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(1.0, |quality| quality.trim().parse().unwrap_or(0.0));
+            if quality > best.1 {
+                best = (format, quality);
+            }
+        }
+        best.0
+    }
+
+    fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, ApiError> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| ApiError::Internal(e.to_string())),
+            // Named, so structs become maps with the same keys as in JSON
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| ApiError::Internal(e.to_string())),
+            Format::Cbor => {
+                let mut data = Vec::new();
+                ciborium::into_writer(value, &mut data).map_err(|e| ApiError::Internal(e.to_string()))?;
+                Ok(data)
+            }
+        }
+    }
+
+    /// Decodes a body; malformed bodies are 400, well-formed ones with the wrong shape 422.
+    fn decode<T: DeserializeOwned>(self, data: &[u8]) -> Result<T, ApiError> {
+        match self {
+            Format::Json => serde_json::from_slice(data).map_err(|e| JsonPayloadError::Deserialize(e).into()),
+            Format::MessagePack => rmp_serde::from_slice(data).map_err(|e| match e {
+                rmp_serde::decode::Error::Syntax(message) => ApiError::Unprocessable(message),
+                e => ApiError::BadRequest(e.to_string()),
+            }),
+            Format::Cbor => ciborium::from_reader(data).map_err(|e| match e {
+                ciborium::de::Error::Semantic(_, message) => ApiError::Unprocessable(message),
+                e => ApiError::BadRequest(e.to_string()),
+            }),
+        }
+    }
+}
+
+/// The format a client wants its response in, from the `Accept` header.
+impl FromRequest for Format {
+    type Error = ApiError;
+    type Future = std::future::Ready<Result<Format, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let accept = req.headers().get(header::ACCEPT).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        std::future::ready(Ok(Format::negotiate(accept)))
+    }
+}
+
+/// Finishes `response` with `value` encoded in `format`.
+pub fn respond<T: Serialize>(format: Format, response: &mut HttpResponseBuilder, value: &T) -> Result<HttpResponse, ApiError> {
+    let body = format.encode(value)?;
+    Ok(response
+        .content_type(format.content_type())
+        .append_header((header::VARY, HeaderValue::from_static("accept")))
+        .body(body))
+}
+
+/// A request body in any of the formats, chosen by the `Content-Type` header (JSON if
+/// there is none). Used like `web::Json`.
+#[derive(Debug)]
+pub struct Body<T>(pub T);
+
+impl<T> Deref for Body<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Body<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Body<T> {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Body<T>, ApiError>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let format = match req.headers().get(header::CONTENT_TYPE) {
+            None => Ok(Format::Json),
+            Some(value) => {
+                let media_type = value.to_str().unwrap_or_default().split(';').next().unwrap_or_default();
+                Format::from_media_type(media_type)
+                    .ok_or_else(|| ApiError::UnsupportedMediaType(format!("Unsupported content type '{}'", media_type)))
+            }
+        };
+        let payload = web::Payload::from_request(req, payload);
+        Box::pin(async move {
+            let format = format?;
+            let data = match payload.await.map_err(|e| ApiError::BadRequest(e.to_string()))?.to_bytes_limited(MAX_BODY_BYTES).await {
+                Ok(data) => data.map_err(|e| ApiError::BadRequest(e.to_string()))?,
+                Err(_) => return Err(ApiError::PayloadTooLarge(format!("Payload exceeds {} bytes", MAX_BODY_BYTES))),
+            };
+            format.decode(&data).map(Body)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Model {
+        id: String,
+        counts: HashMap<String, u64>,
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Format::negotiate("application/msgpack"), Format::MessagePack);
+        assert_eq!(Format::negotiate("application/json;q=0.5, application/cbor"), Format::Cbor);
+        assert_eq!(Format::negotiate("text/html, */*"), Format::Json);
+        assert_eq!(Format::negotiate(""), Format::Json);
+    }
+
+    #[test]
+    fn test_formats_round_trip() {
+        let model = Model { id: "a".to_string(), counts: HashMap::from([("b".to_string(), 3)]) };
+        for format in [Format::Json, Format::MessagePack, Format::Cbor] {
+            let data = format.encode(&model).unwrap();
+            assert_eq!(format.decode::<Model>(&data).unwrap(), model);
+            // A well-formed body of the wrong shape
+            let wrong_shape = format.encode(&vec![1, 2]).unwrap();
+            assert!(matches!(format.decode::<Model>(&wrong_shape), Err(ApiError::Unprocessable(_))), "{:?}", format);
+        }
+        assert!(matches!(Format::MessagePack.decode::<Model>(&[0xc1]), Err(ApiError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,212 @@
+// src/api/error.rs
+use actix_web::body::{BoxBody, EitherBody};
+use actix_web::dev::ServiceResponse;
+use actix_web::error::{BlockingError, JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::{ErrorHandlerResponse, ErrorHandlers};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Body of every failed request.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Machine-readable error code, e.g. "not_found" or "invalid_body"
+    pub code: &'static str,
+    /// Human-readable description of what went wrong
+    pub message: String,
+}
+
+/// An error returned by a handler, rendered as an `ErrorResponse` with a matching status.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request is malformed (400)
+    BadRequest(String),
+    /// The API key is missing or unknown (401)
+    Unauthorized(String),
+    /// The client may not call this endpoint (403)
+    Forbidden(String),
+    /// The identifier or route doesn't exist (404)
+    NotFound(String),
+    /// The body is larger than the endpoint accepts (413)
+    PayloadTooLarge(String),
+    /// The body is in a format the endpoint doesn't accept (415)
+    UnsupportedMediaType(String),
+    /// The body is well-formed, but its content is invalid (422)
+    Unprocessable(String),
+    /// The client exceeded its rate limit and may retry after this many seconds (429)
+    RateLimited(u64),
+    /// The API key used up its daily quota, which resets in this many seconds (429)
+    QuotaExceeded(u64),
+    /// The ingest queue is full, retry after this many seconds (429)
+    QueueFull(u64),
+    /// Too many requests are in flight or the data is locked for too long, retry after
+    /// this many seconds (503)
+    Unavailable(u64),
+    /// Something failed on our side (500)
+    Internal(String),
+    /// A shard behind this router failed or couldn't be reached (502)
+    BadGateway(String),
+    /// The request's time budget ran out before it was answered (504)
+    DeadlineExceeded,
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::Unprocessable(_) => "invalid_body",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::QuotaExceeded(_) => "quota_exceeded",
+            ApiError::QueueFull(_) => "queue_full",
+            ApiError::Unavailable(_) => "overloaded",
+            ApiError::Internal(_) => "internal_error",
+            ApiError::BadGateway(_) => "bad_gateway",
+            ApiError::DeadlineExceeded => "deadline_exceeded",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::UnsupportedMediaType(message)
+            | ApiError::Unprocessable(message)
+            | ApiError::Internal(message)
+            | ApiError::BadGateway(message) => message.clone(),
+            ApiError::RateLimited(retry_after) => format!("Rate limit exceeded, retry in {} seconds", retry_after),
+            ApiError::QuotaExceeded(retry_after) => format!("Daily quota exceeded, retry in {} seconds", retry_after),
+            ApiError::QueueFull(retry_after) => format!("Ingest queue is full, retry in {} seconds", retry_after),
+            ApiError::Unavailable(retry_after) => format!("Server is overloaded, retry in {} seconds", retry_after),
+            ApiError::DeadlineExceeded => "Request timeout exceeded".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message())
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::RateLimited(_) | ApiError::QuotaExceeded(_) | ApiError::QueueFull(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::RateLimited(retry_after) | ApiError::QuotaExceeded(retry_after) | ApiError::QueueFull(retry_after) | ApiError::Unavailable(retry_after) = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(ErrorResponse {
+            code: self.code(),
+            message: self.message(),
+        })
+    }
+}
+
+impl From<JsonPayloadError> for ApiError {
+    fn from(error: JsonPayloadError) -> Self {
+        match &error {
+            JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+                ApiError::PayloadTooLarge(error.to_string())
+            }
+            // Syntactically valid JSON that doesn't match the expected shape
+            JsonPayloadError::Deserialize(e) if e.is_data() => ApiError::Unprocessable(error.to_string()),
+            _ => ApiError::BadRequest(error.to_string()),
+        }
+    }
+}
+
+impl From<BlockingError> for ApiError {
+    fn from(error: BlockingError) -> Self {
+        ApiError::Internal(error.to_string())
+    }
+}
+
+/// Extractor settings turning rejected bodies, query strings and paths into `ApiError`s
+/// instead of actix's plain-text errors.
+pub fn config_extractors(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::JsonConfig::default().error_handler(|error, _| ApiError::from(error).into()))
+        .app_data(web::QueryConfig::default().error_handler(|error: QueryPayloadError, _| {
+            ApiError::BadRequest(error.to_string()).into()
+        }))
+        .app_data(web::PathConfig::default().error_handler(|error: PathError, _| {
+            ApiError::BadRequest(error.to_string()).into()
+        }));
+}
+
+/// Fallback for requests no route matches.
+pub async fn route_not_found(req: HttpRequest) -> HttpResponse {
+    ApiError::NotFound(format!("No route for {} {}", req.method(), req.path())).error_response()
+}
+
+/// Middleware giving error responses that don't have a JSON body yet (e.g. 405 from
+/// actix's routing) an `ErrorResponse` body, so clients only have to handle one shape.
+pub fn json_error_bodies<B: 'static>() -> ErrorHandlers<B> {
+    ErrorHandlers::new().default_handler(|res: ServiceResponse<B>| {
+        let is_json = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+        if is_json {
+            return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+        }
+
+        let status = res.status();
+        let message = match res.response().error() {
+            Some(error) => error.to_string(),
+            None => status.canonical_reason().unwrap_or("Error").to_string(),
+        };
+        let code = match status {
+            StatusCode::NOT_FOUND => "not_found",
+            StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+            status if status.is_server_error() => "internal_error",
+            _ => "bad_request",
+        };
+        let (req, _) = res.into_parts();
+        let response: HttpResponse<EitherBody<B, BoxBody>> = HttpResponse::build(status)
+            .json(ErrorResponse { code, message })
+            .map_into_right_body();
+        Ok(ErrorHandlerResponse::Response(ServiceResponse::new(req, response)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body_error(json: &str) -> ApiError {
+        let error = serde_json::from_str::<Vec<u32>>(json).unwrap_err();
+        ApiError::from(JsonPayloadError::Deserialize(error))
+    }
+
+    #[test]
+    fn test_body_errors_are_classified() {
+        assert_eq!(body_error("[1, 2").status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_error("{\"a\": 1}").status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body_error("{\"a\": 1}").code(), "invalid_body");
+    }
+}
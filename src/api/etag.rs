@@ -0,0 +1,45 @@
+// src/api/etag.rs
+use std::sync::LazyLock;
+use actix_web::http::header::{EntityTag, IfNoneMatch, ETag};
+use actix_web::{web, HttpResponse};
+
+/// Random per-process prefix of all entity tags, as the versions they are built from
+/// start over on every restart.
+static INSTANCE: LazyLock<u64> = LazyLock::new(rand::random);
+
+/// Builds a weak entity tag from the versions of everything a response is computed from.
+/// Weak, because compressed and uncompressed responses share the same tag.
+pub fn entity_tag(versions: &[u64]) -> EntityTag {
+    let tag = versions.iter().fold(format!("{:x}", *INSTANCE), |tag, version| format!("{}-{:x}", tag, version));
+    EntityTag::new_weak(tag)
+}
+
+/// Returns a 304 response if the client's `If-None-Match` header matches `etag`, i.e.
+/// its copy is still current.
+pub fn not_modified(if_none_match: Option<&web::Header<IfNoneMatch>>, etag: &EntityTag) -> Option<HttpResponse> {
+    let is_current = match if_none_match.map(|header| &header.0) {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => false,
+    };
+    is_current.then(|| HttpResponse::NotModified().insert_header(ETag(etag.clone())).finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_not_modified() {
+        let etag = entity_tag(&[3, 7]);
+        assert_ne!(etag, entity_tag(&[3, 8]));
+
+        let header = |tags: Vec<EntityTag>| web::Header(IfNoneMatch::Items(tags));
+        // Clients may send the tag back as strong one
+        let strong = EntityTag::new_strong(etag.tag().to_string());
+        assert!(not_modified(Some(&header(vec![entity_tag(&[1]), strong])), &etag).is_some());
+        assert!(not_modified(Some(&header(vec![entity_tag(&[3, 8])])), &etag).is_none());
+        assert!(not_modified(Some(&web::Header(IfNoneMatch::Any)), &etag).is_some());
+        assert!(not_modified(None, &etag).is_none());
+    }
+}
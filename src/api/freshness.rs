@@ -0,0 +1,49 @@
+// src/api/freshness.rs
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::Error;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::api::{is_read, route_pattern};
+use crate::stats;
+
+/// When anything was last ingested (lists or plays).
+pub const LAST_INGEST_AT_HEADER: &str = "last-ingest-at";
+/// When the counters last rotated.
+pub const LAST_ROTATION_AT_HEADER: &str = "last-rotation-at";
+/// When a snapshot or delta was last written.
+pub const LAST_PERSIST_AT_HEADER: &str = "last-persist-at";
+
+/// Middleware telling clients and monitors how stale the served state is: successful reads
+/// and recommendations get the times of the last ingest, rotation and persistence (see
+/// `stats::freshness`) as RFC 3339 headers. Times not known yet are left out.
+pub async fn freshness_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let serves_state = is_read(&req) || route_pattern(&req) == "/recommendations";
+    let mut response = next.call(req).await?;
+    if !serves_state || !response.status().is_success() {
+        return Ok(response);
+    }
+
+    let freshness = stats::freshness();
+    let headers = response.headers_mut();
+    let times = [
+        (LAST_INGEST_AT_HEADER, freshness.last_ingest_at),
+        (LAST_ROTATION_AT_HEADER, freshness.last_rotation_at),
+        (LAST_PERSIST_AT_HEADER, freshness.last_persist_at),
+    ];
+    for (name, at) in times {
+        if let Some(value) = at.map(format_time).and_then(|value| HeaderValue::from_str(&value).ok()) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    }
+    Ok(response)
+}
+
+fn format_time(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
@@ -0,0 +1,222 @@
+// src/api/grpc.rs
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use tonic::{Request, Response, Status};
+
+use crate::algorithms::rotating_counters::top_entries;
+use crate::algorithms::tenants::Tenants;
+use crate::algorithms::tombstones::Tombstones;
+use crate::algorithms::{CoOccurrenceCounter, Counters, RecentLists};
+use crate::api::auth::find_key;
+use crate::api::error::ApiError;
+use crate::api::validation::normalize_identifier;
+use crate::config::SharedSettings;
+use crate::ingest::pipeline::{Admission, PlayVerdict};
+use crate::ingest::rules;
+use crate::locks;
+
+/// The generated messages and service traits of proto/mediathek.proto.
+pub mod proto {
+    tonic::include_proto!("mediathek.v1");
+}
+
+use proto::recommendations_server::{Recommendations, RecommendationsServer};
+
+/// Metadata key clients send their API key in, like the `X-Api-Key` header.
+const API_KEY_METADATA: &str = "x-api-key";
+
+impl From<ApiError> for Status {
+    fn from(error: ApiError) -> Self {
+        let message = error.to_string();
+        match error {
+            ApiError::BadRequest(_) | ApiError::UnsupportedMediaType(_) | ApiError::Unprocessable(_) => {
+                Status::invalid_argument(message)
+            }
+            ApiError::Unauthorized(_) => Status::unauthenticated(message),
+            ApiError::Forbidden(_) => Status::permission_denied(message),
+            ApiError::NotFound(_) => Status::not_found(message),
+            ApiError::PayloadTooLarge(_) | ApiError::RateLimited(_) | ApiError::QuotaExceeded(_) | ApiError::QueueFull(_) => {
+                Status::resource_exhausted(message)
+            }
+            ApiError::Internal(_) => Status::internal(message),
+            ApiError::BadGateway(_) | ApiError::Unavailable(_) => Status::unavailable(message),
+            ApiError::DeadlineExceeded => Status::deadline_exceeded(message),
+        }
+    }
+}
+
+/// The gRPC service, working on the same state as the HTTP handlers.
+pub struct RecommendationService {
+    co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    recent_lists: Arc<Mutex<RecentLists>>,
+    counters: Arc<RwLock<Counters>>,
+    tombstones: Arc<RwLock<Tombstones>>,
+    tenants: Arc<Tenants>,
+    settings: Arc<SharedSettings>,
+}
+
+impl RecommendationService {
+    pub fn new(
+        co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+        recent_lists: Arc<Mutex<RecentLists>>,
+        counters: Arc<RwLock<Counters>>,
+        tombstones: Arc<RwLock<Tombstones>>,
+        tenants: Arc<Tenants>,
+        settings: Arc<SharedSettings>,
+    ) -> Self {
+        RecommendationService { co_occurrence, recent_lists, counters, tombstones, tenants, settings }
+    }
+
+    /// Checks the `x-api-key` metadata by the same rules as the HTTP API: writes need a
+    /// key, reads only if configured. Without any configured keys, all calls pass.
+    fn authenticate<T>(&self, request: &Request<T>, is_write: bool) -> Result<(), ApiError> {
+        let settings = self.settings.current();
+        let keys = &settings.auth.api_keys.0;
+        let sent_key = request.metadata().get(API_KEY_METADATA).map(|value| value.to_str().unwrap_or_default());
+        match sent_key {
+            _ if keys.is_empty() => Ok(()),
+            Some(sent_key) if find_key(keys, sent_key).is_some() => Ok(()),
+            Some(_) => Err(ApiError::Unauthorized("Unknown API key".to_string())),
+            None if is_write || settings.auth.protect_reads => Err(ApiError::Unauthorized("Missing API key".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Recommendations for RecommendationService {
+    async fn add_list(&self, request: Request<proto::AddListRequest>) -> Result<Response<proto::AddListResponse>, Status> {
+        self.authenticate(&request, true)?;
+        let settings = self.settings.current();
+        let mut identifiers = request.into_inner().identifiers;
+        let routed = Admission::new(&settings, &self.tombstones, Some(&self.co_occurrence)).list(&mut identifiers)?;
+        let weight = settings.source_weights.of(None);
+        routed.count(&self.tenants, weight);
+        locks::write(&self.co_occurrence, "co_occurrence").process_weighted_list(&identifiers, weight);
+        // Keep the raw list around for offline mining passes
+        locks::lock(&self.recent_lists, "recent_lists").push(&identifiers);
+        Ok(Response::new(proto::AddListResponse {}))
+    }
+
+    async fn get_recommendations(
+        &self,
+        request: Request<proto::GetRecommendationsRequest>,
+    ) -> Result<Response<proto::GetRecommendationsResponse>, Status> {
+        self.authenticate(&request, false)?;
+        let mut request = request.into_inner();
+        request.identifier = normalize_identifier(&request.identifier, &self.settings.current().validation.normalization).into_owned();
+        let counter_lock = locks::read(&self.co_occurrence, "co_occurrence");
+        if counter_lock.version_of(&request.identifier).is_none() {
+            return Err(Status::not_found(format!("Unknown identifier '{}'", request.identifier)));
+        }
+        let co_occurrences = counter_lock.cached_metrics_for_identifier(&request.identifier);
+        drop(counter_lock);
+
+        let mut recommendations: Vec<proto::Recommendation> = co_occurrences
+            .into_iter()
+            .map(|(identifier, count)| proto::Recommendation { identifier, count })
+            .collect();
+        recommendations.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.identifier.cmp(&b.identifier)));
+        if request.limit > 0 {
+            recommendations.truncate(request.limit as usize);
+        }
+        Ok(Response::new(proto::GetRecommendationsResponse { target_identifier: request.identifier, recommendations }))
+    }
+
+    async fn increment(&self, request: Request<proto::IncrementRequest>) -> Result<Response<proto::IncrementResponse>, Status> {
+        self.authenticate(&request, true)?;
+        let mut request = request.into_inner();
+        let settings = self.settings.current();
+        let verdict = Admission::new(&settings, &self.tombstones, None).play(&mut request.id)?;
+        match verdict {
+            PlayVerdict::Count => locks::read(&self.counters, "rotating_counters").increment(&request.id, request.count.unwrap_or(1)),
+            PlayVerdict::Route(tenant) => rules::route_play(&self.tenants, tenant, &request.id, request.count.unwrap_or(1)),
+            PlayVerdict::Dropped | PlayVerdict::Deleted => {}
+        }
+        Ok(Response::new(proto::IncrementResponse {}))
+    }
+
+    async fn get_counters(
+        &self,
+        request: Request<proto::GetCountersRequest>,
+    ) -> Result<Response<proto::GetCountersResponse>, Status> {
+        self.authenticate(&request, false)?;
+        let request = request.into_inner();
+        let window = if request.window.is_empty() { "today".to_string() } else { request.window };
+        let limit = if request.limit == 0 { usize::MAX } else { request.limit as usize };
+
+        let counters_lock = locks::read(&self.counters, "rotating_counters");
+        let Some(bucket) = counters_lock.window(&window) else {
+            return Err(Status::invalid_argument(format!("Unknown window '{}'", window)));
+        };
+        let items = top_entries(&bucket, request.offset as usize, limit)
+            .into_iter()
+            .map(|entry| proto::CountEntry { id: entry.id, count: entry.count })
+            .collect();
+        let total = bucket.len() as u64;
+        Ok(Response::new(proto::GetCountersResponse { window, total, items }))
+    }
+}
+
+/// Serves the gRPC API on `address` until the process exits.
+pub async fn serve(service: RecommendationService, address: SocketAddr) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(RecommendationsServer::new(service))
+        .serve(address)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiKey, ApiKeys, Settings};
+
+    fn service(api_keys: Vec<ApiKey>) -> RecommendationService {
+        let mut settings = Settings::from_env();
+        settings.auth.api_keys = ApiKeys(api_keys);
+        RecommendationService::new(
+            Arc::new(RwLock::new(CoOccurrenceCounter::new())),
+            Arc::new(Mutex::new(RecentLists::new(10))),
+            Arc::new(RwLock::new(Counters::with_depths(3, 3, 1, 1))),
+            Arc::new(RwLock::new(Tombstones::default())),
+            Arc::new(Tenants::new(&settings).0),
+            Arc::new(SharedSettings::new(settings)),
+        )
+    }
+
+    #[actix_web::test]
+    async fn test_calls_share_the_http_state() {
+        let service = service(Vec::new());
+        let identifiers = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        service.add_list(Request::new(proto::AddListRequest { identifiers: identifiers.clone() })).await.unwrap();
+        service.add_list(Request::new(proto::AddListRequest { identifiers: identifiers[..2].to_vec() })).await.unwrap();
+
+        let request = proto::GetRecommendationsRequest { identifier: "a".to_string(), limit: 1 };
+        let response = service.get_recommendations(Request::new(request)).await.unwrap().into_inner();
+        assert_eq!(response.recommendations, vec![proto::Recommendation { identifier: "b".to_string(), count: 2 }]);
+        let request = proto::GetRecommendationsRequest { identifier: "x".to_string(), limit: 0 };
+        assert_eq!(service.get_recommendations(Request::new(request)).await.unwrap_err().code(), tonic::Code::NotFound);
+
+        service.increment(Request::new(proto::IncrementRequest { id: "a".to_string(), count: Some(3) })).await.unwrap();
+        let request = proto::GetCountersRequest::default();
+        let response = service.get_counters(Request::new(request)).await.unwrap().into_inner();
+        assert_eq!((response.window.as_str(), response.total), ("today", 1));
+        assert_eq!(response.items, vec![proto::CountEntry { id: "a".to_string(), count: 3 }]);
+    }
+
+    #[actix_web::test]
+    async fn test_writes_need_an_api_key() {
+        let service = service(vec![ApiKey { name: "app".to_string(), key: "k3y".to_string() }]);
+        let increment = || Request::new(proto::IncrementRequest { id: "a".to_string(), count: None });
+        assert_eq!(service.increment(increment()).await.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+        let mut request = increment();
+        request.metadata_mut().insert(API_KEY_METADATA, "wrong".parse().unwrap());
+        assert_eq!(service.increment(request).await.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+        let mut request = increment();
+        request.metadata_mut().insert(API_KEY_METADATA, "k3y".parse().unwrap());
+        assert!(service.increment(request).await.is_ok());
+        assert!(service.get_counters(Request::new(proto::GetCountersRequest::default())).await.is_ok());
+    }
+}
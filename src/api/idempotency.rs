@@ -0,0 +1,90 @@
+// src/api/idempotency.rs
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_web::{HttpMessage, HttpRequest};
+use lru::LruCache;
+
+use crate::api::auth::ApiClient;
+use crate::api::error::ApiError;
+use crate::config::IdempotencySettings;
+use crate::locks;
+
+/// Header clients send a unique key per request in, the same for its retries.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Header marking the response to a retry that wasn't processed again.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+/// Longest key accepted.
+const MAX_KEY_LENGTH: usize = 255;
+
+/// An idempotency key scoped by client and route: (client, route, key).
+type ScopedKey = (String, String, String);
+/// When each key was first used.
+type KeyCache = Mutex<LruCache<ScopedKey, Instant>>;
+
+/// The idempotency keys of recently processed writes, so that retries of a request that
+/// timed out on the client are acknowledged without counting it again. Keys are scoped by
+/// client and route. The least recently used keys make room for new ones when full.
+#[derive(Debug)]
+pub struct IdempotencyKeys {
+    /// When each key was first used, `None` if disabled
+    keys: Option<KeyCache>,
+    window: Duration,
+}
+
+impl IdempotencyKeys {
+    pub fn new(settings: &IdempotencySettings) -> Self {
+        IdempotencyKeys {
+            keys: NonZeroUsize::new(settings.capacity).map(|capacity| Mutex::new(LruCache::new(capacity))),
+            window: Duration::from_secs(settings.window_secs),
+        }
+    }
+
+    /// Records the idempotency key of `req` on `route`, if it sent one. Returns `false` if
+    /// the key was used within the window already, i.e. the request is a retry.
+    pub fn claim(&self, req: &HttpRequest, route: &str) -> Result<bool, ApiError> {
+        let (Some(_), Some(key)) = (&self.keys, req.headers().get(IDEMPOTENCY_KEY_HEADER)) else {
+            return Ok(true);
+        };
+        let key = key.to_str().ok().filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LENGTH).ok_or_else(|| {
+            ApiError::BadRequest(format!("The Idempotency-Key must be 1 to {} visible ASCII characters", MAX_KEY_LENGTH))
+        })?;
+        let client = req.extensions().get::<ApiClient>().map(|client| client.name.clone()).unwrap_or_default();
+        Ok(self.claim_key((client, route.to_string(), key.to_string()), Instant::now()))
+    }
+
+    fn claim_key(&self, key: ScopedKey, now: Instant) -> bool {
+        let Some(keys) = &self.keys else {
+            return true;
+        };
+        let mut keys = locks::lock(keys, "idempotency_keys");
+        match keys.get(&key) {
+            Some(&used_at) if now.saturating_duration_since(used_at) < self.window => false,
+            _ => {
+                keys.put(key, now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_repeat_only_within_the_window() {
+        let keys = IdempotencyKeys::new(&IdempotencySettings { capacity: 1, window_secs: 60 });
+        let key = |name: &str| (String::new(), "POST /lists".to_string(), name.to_string());
+        let now = Instant::now();
+        assert!(keys.claim_key(key("a"), now));
+        assert!(!keys.claim_key(key("a"), now + Duration::from_secs(59)));
+        assert!(keys.claim_key(key("a"), now + Duration::from_secs(60)));
+        // Evicted by the next key, as the capacity is 1
+        assert!(keys.claim_key(key("b"), now));
+        assert!(keys.claim_key(key("a"), now + Duration::from_secs(61)));
+
+        let disabled = IdempotencyKeys::new(&IdempotencySettings { capacity: 0, window_secs: 60 });
+        assert!(disabled.claim_key(key("a"), now) && disabled.claim_key(key("a"), now));
+    }
+}
@@ -0,0 +1,191 @@
+// src/api/ingest_queue.rs
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use actix_web::web;
+use tokio::sync::mpsc::{self, error::TrySendError, Permit, Receiver, Sender};
+use tracing::{error, info};
+
+use crate::algorithms::{CoOccurrenceCounter, RecentLists};
+use crate::api::error::ApiError;
+use crate::config::IngestQueueSettings;
+use crate::locks;
+
+/// Lists the worker counts under one write lock at most, so reads get their turn in
+/// between.
+const BATCH_SIZE: usize = 64;
+
+/// A list accepted by POST /lists, with the state of its tenant to count it in.
+pub struct QueuedList {
+    pub identifiers: Vec<String>,
+    /// When each identifier was watched, in seconds since the Unix epoch
+    pub times: Option<Vec<i64>>,
+    pub weight: u64,
+    pub co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    pub recent_lists: Arc<Mutex<RecentLists>>,
+}
+
+/// Bounded queue between POST /lists and the worker counting the lists, so a burst of
+/// lists is answered right away instead of waiting for the co-occurrence lock. Disabled
+/// with a capacity of 0, in which case the handler counts every list itself.
+pub struct IngestQueue {
+    sender: Option<Sender<QueuedList>>,
+    /// Taken by the worker while it waits, and by `drain` on shutdown
+    receiver: tokio::sync::Mutex<Option<Receiver<QueuedList>>>,
+    /// Lists turned away because the queue was full
+    rejected: AtomicU64,
+}
+
+/// A place in the queue, reserved before the list is checked any further, so a full queue
+/// is noticed before the list is e.g. marked as seen.
+pub struct QueueSlot<'a>(Permit<'a, QueuedList>);
+
+impl QueueSlot<'_> {
+    pub fn send(self, list: QueuedList) {
+        self.0.send(list);
+    }
+}
+
+impl IngestQueue {
+    pub fn new(settings: &IngestQueueSettings) -> Self {
+        let (sender, receiver) = match settings.capacity {
+            0 => (None, None),
+            capacity => {
+                let (sender, receiver) = mpsc::channel(capacity);
+                (Some(sender), Some(receiver))
+            }
+        };
+        IngestQueue { sender, receiver: tokio::sync::Mutex::new(receiver), rejected: AtomicU64::new(0) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Reserves a place for a list. Returns `Ok(None)` if the queue is disabled, and an
+    /// error telling the client to retry after `retry_after_secs` if it is full.
+    pub fn reserve(&self, retry_after_secs: u64) -> Result<Option<QueueSlot<'_>>, ApiError> {
+        let Some(sender) = &self.sender else {
+            return Ok(None);
+        };
+        match sender.try_reserve() {
+            Ok(permit) => Ok(Some(QueueSlot(permit))),
+            Err(TrySendError::Full(_) | TrySendError::Closed(_)) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(ApiError::QueueFull(retry_after_secs))
+            }
+        }
+    }
+
+    /// Lists waiting to be counted.
+    pub fn depth(&self) -> usize {
+        self.sender.as_ref().map_or(0, |sender| sender.max_capacity() - sender.capacity())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.sender.as_ref().map_or(0, Sender::max_capacity)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Counts the lists still queued, once the server stopped accepting new ones, so they
+    /// are part of the final persistence.
+    pub async fn drain(&self) {
+        let mut receiver = self.receiver.lock().await;
+        let Some(receiver) = receiver.as_mut() else {
+            return;
+        };
+        let mut lists = Vec::new();
+        while let Ok(list) = receiver.try_recv() {
+            lists.push(list);
+        }
+        if lists.is_empty() {
+            return;
+        }
+        let count = lists.len();
+        match web::block(move || apply(lists)).await {
+            Ok(()) => info!(lists = count, "Counted the queued lists."),
+            Err(e) => error!("Error counting the queued lists: {:?}", e),
+        }
+    }
+}
+
+/// Counts `lists`, taking the locks once per run of lists of the same tenant.
+fn apply(lists: Vec<QueuedList>) {
+    for run in lists.chunk_by(|a, b| Arc::ptr_eq(&a.co_occurrence, &b.co_occurrence) && Arc::ptr_eq(&a.recent_lists, &b.recent_lists)) {
+        let mut counter = locks::write(&run[0].co_occurrence, "co_occurrence");
+        for list in run {
+            counter.process_timed_list(&list.identifiers, list.times.as_deref(), list.weight);
+        }
+        drop(counter);
+        // Keep the raw lists around for offline mining passes
+        let mut recent_lists = locks::lock(&run[0].recent_lists, "recent_lists");
+        for list in run {
+            recent_lists.push(&list.identifiers);
+        }
+    }
+}
+
+// Function to count the queued lists as they arrive
+pub async fn run_ingest_queue(queue: Arc<IngestQueue>) {
+    info!("Ingest queue worker started.");
+
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    loop {
+        {
+            let mut receiver = queue.receiver.lock().await;
+            let Some(receiver) = receiver.as_mut() else {
+                return;
+            };
+            if receiver.recv_many(&mut batch, BATCH_SIZE).await == 0 {
+                return;
+            }
+        }
+
+        // Takes the write lock, so it runs on the blocking thread pool
+        let lists = std::mem::take(&mut batch);
+        if let Err(e) = web::block(move || apply(lists)).await {
+            error!("Error in ingest queue block: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(identifiers: &[&str], co_occurrence: &Arc<RwLock<CoOccurrenceCounter>>, recent_lists: &Arc<Mutex<RecentLists>>) -> QueuedList {
+        QueuedList {
+            identifiers: identifiers.iter().map(|identifier| identifier.to_string()).collect(),
+            times: None,
+            weight: 1,
+            co_occurrence: Arc::clone(co_occurrence),
+            recent_lists: Arc::clone(recent_lists),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_full_queue_rejects_lists_until_drained() {
+        let queue = IngestQueue::new(&IngestQueueSettings { capacity: 2 });
+        let co_occurrence = Arc::new(RwLock::new(CoOccurrenceCounter::new()));
+        let recent_lists = Arc::new(Mutex::new(RecentLists::new(10)));
+
+        queue.reserve(1).unwrap().unwrap().send(list(&["ard:a", "ard:b"], &co_occurrence, &recent_lists));
+        // A reserved place counts towards the depth until it is given up
+        let slot = queue.reserve(1).unwrap().unwrap();
+        assert_eq!(queue.depth(), 2);
+        assert!(queue.reserve(1).is_err());
+        drop(slot);
+        queue.reserve(1).unwrap().unwrap().send(list(&["ard:a", "ard:c"], &co_occurrence, &recent_lists));
+        assert_eq!((queue.depth(), queue.capacity(), queue.rejected()), (2, 2, 1));
+
+        queue.drain().await;
+        assert_eq!(queue.depth(), 0);
+        assert_eq!(locks::read(&co_occurrence, "co_occurrence").cached_metrics_for_identifier("ard:a").len(), 2);
+        assert_eq!(locks::lock(&recent_lists, "recent_lists").snapshot().len(), 2);
+
+        let disabled = IngestQueue::new(&IngestQueueSettings { capacity: 0 });
+        assert!(!disabled.is_enabled() && disabled.reserve(1).unwrap().is_none());
+    }
+}
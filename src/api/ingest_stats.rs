@@ -0,0 +1,289 @@
+// src/api/ingest_stats.rs
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Extensions, ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage};
+use ahash::RandomState;
+use chrono::{DateTime, Datelike, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::algorithms::rotating_counters::Granularity;
+use crate::api::auth::ApiClient;
+use crate::api::tenants::RequestTenant;
+use crate::api::{route_pattern, INGEST_ROUTES};
+use crate::config::SharedSettings;
+use crate::{determinism, locks};
+
+/// Number of hourly buckets, including the current hour, so "last_24h" is available.
+const HOURLY_BUCKETS: usize = 24;
+/// Number of daily buckets, including today, so "last_7d" is available.
+const DAILY_BUCKETS: usize = 7;
+/// Distinct identifiers counted per client and bucket at most, bounding the memory a
+/// client sending random identifiers takes.
+pub const MAX_UNIQUE_IDENTIFIERS: usize = 100_000;
+
+/// Who ingested: the tenant, the API key and the `source` of the lists. Each is `None` if
+/// the request had none; plays and rejected requests never have a source.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IngestClient {
+    pub tenant: Option<String>,
+    pub key: Option<String>,
+    pub source: Option<String>,
+}
+
+impl IngestClient {
+    /// The client of a request, from what the tenant resolution and the authentication
+    /// recorded in its `extensions`.
+    pub fn of(extensions: &Extensions, source: Option<&str>) -> Self {
+        IngestClient {
+            tenant: extensions.get::<RequestTenant>().map(|tenant| tenant.tenant.name.clone()),
+            key: extensions.get::<ApiClient>().map(|client| client.name.clone()),
+            source: source.map(str::to_string),
+        }
+    }
+}
+
+/// What a client ingested in one bucket.
+#[derive(Debug, Clone, Default)]
+struct Tally {
+    lists: u64,
+    /// Identifiers of all lists, for the average length
+    list_identifiers: u64,
+    plays: u64,
+    rejected: u64,
+    /// Hashes of the distinct identifiers, at most `MAX_UNIQUE_IDENTIFIERS`
+    identifiers: HashSet<u64>,
+}
+
+impl Tally {
+    fn add_identifier(&mut self, hash: u64) {
+        if self.identifiers.len() < MAX_UNIQUE_IDENTIFIERS {
+            self.identifiers.insert(hash);
+        }
+    }
+
+    fn merge(&mut self, other: &Tally) {
+        self.lists += other.lists;
+        self.list_identifiers += other.list_identifiers;
+        self.plays += other.plays;
+        self.rejected += other.rejected;
+        for &hash in &other.identifiers {
+            self.add_identifier(hash);
+        }
+    }
+}
+
+/// What a client ingested in a bucket or window.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct ClientIngestStats {
+    pub tenant: Option<String>,
+    /// Name of the API key, none for requests without one
+    pub key: Option<String>,
+    /// The `source` field of the lists
+    pub source: Option<String>,
+    pub lists: u64,
+    pub average_list_length: f64,
+    /// Plays counted by POST /counters and /counters/batch
+    pub plays: u64,
+    /// Requests and lines of POST /lists/stream rejected as invalid
+    pub rejected: u64,
+    /// Distinct identifiers of the lists and plays, counted up to 100000
+    pub unique_identifiers: usize,
+}
+
+#[derive(Debug)]
+struct Rings {
+    /// `hours[0]` is the hour `hour`, `hours[1]` the one before and so on
+    hours: Vec<HashMap<IngestClient, Tally>>,
+    days: Vec<HashMap<IngestClient, Tally>>,
+    /// Hours since the Unix epoch of `hours[0]`
+    hour: i64,
+    /// Days since the Common Era of `days[0]`, in the rotation time zone
+    day: i64,
+}
+
+/// Shifts every bucket `steps` positions towards the end, leaving empty ones at the front.
+fn rotate(buckets: &mut [HashMap<IngestClient, Tally>], steps: i64) {
+    let steps = usize::try_from(steps).unwrap_or(0).min(buckets.len());
+    buckets.rotate_right(steps);
+    buckets[..steps].iter_mut().for_each(HashMap::clear);
+}
+
+/// Lists, plays and rejections per client in hourly and daily buckets that rotate like the
+/// rotating counters': at the hour boundaries, and at midnight in the rotation time zone.
+/// Kept in memory only, so a restart resets them.
+#[derive(Debug)]
+pub struct IngestStats {
+    /// Read for the rotation time zone
+    settings: Arc<SharedSettings>,
+    rings: Mutex<Rings>,
+    hasher: RandomState,
+}
+
+impl IngestStats {
+    pub fn new(settings: Arc<SharedSettings>) -> Self {
+        let rings = Rings { hours: vec![HashMap::new(); HOURLY_BUCKETS], days: vec![HashMap::new(); DAILY_BUCKETS], hour: 0, day: 0 };
+        IngestStats { settings, rings: Mutex::new(rings), hasher: determinism::random_state() }
+    }
+
+    /// Rotates the buckets up to `now`; earlier times count towards the current buckets.
+    fn advance(rings: &mut Rings, now: DateTime<Utc>, timezone: &Tz) {
+        let hour = now.timestamp().div_euclid(3600);
+        if hour > rings.hour {
+            rotate(&mut rings.hours, hour - rings.hour);
+            rings.hour = hour;
+        }
+        let day = now.with_timezone(timezone).date_naive().num_days_from_ce() as i64;
+        if day > rings.day {
+            rotate(&mut rings.days, day - rings.day);
+            rings.day = day;
+        }
+    }
+
+    /// Applies `update` to the current hour's and day's tally of `client`.
+    fn record(&self, client: IngestClient, now: DateTime<Utc>, update: impl Fn(&mut Tally)) {
+        let timezone = self.settings.current().counters.rotation_timezone;
+        let mut rings = locks::lock(&self.rings, "ingest_stats");
+        IngestStats::advance(&mut rings, now, &timezone);
+        let Rings { hours, days, .. } = &mut *rings;
+        update(hours[0].entry(client.clone()).or_default());
+        update(days[0].entry(client).or_default());
+    }
+
+    /// Counts an accepted list.
+    pub fn record_list<S: AsRef<str>>(&self, client: IngestClient, identifiers: &[S], now: DateTime<Utc>) {
+        let hashes: Vec<u64> = identifiers.iter().map(|identifier| self.hasher.hash_one(identifier.as_ref())).collect();
+        self.record(client, now, |tally| {
+            tally.lists += 1;
+            tally.list_identifiers += hashes.len() as u64;
+            hashes.iter().for_each(|&hash| tally.add_identifier(hash));
+        });
+    }
+
+    /// Counts accepted plays of `ids`, one per increment no matter its count.
+    pub fn record_plays<'a>(&self, client: IngestClient, ids: impl IntoIterator<Item = &'a str>, now: DateTime<Utc>) {
+        let hashes: Vec<u64> = ids.into_iter().map(|id| self.hasher.hash_one(id)).collect();
+        self.record(client, now, |tally| {
+            tally.plays += hashes.len() as u64;
+            hashes.iter().for_each(|&hash| tally.add_identifier(hash));
+        });
+    }
+
+    /// Counts `count` rejected requests or lines.
+    pub fn record_rejections(&self, client: IngestClient, count: u64, now: DateTime<Utc>) {
+        if count > 0 {
+            self.record(client, now, |tally| tally.rejected += count);
+        }
+    }
+
+    /// The stats per client of a bucket like "this_hour", "yesterday" or "day_minus_3",
+    /// or of the windows "last_24h" and "last_7d"; `None` for other names.
+    pub fn window(&self, name: &str, now: DateTime<Utc>) -> Option<Vec<ClientIngestStats>> {
+        let timezone = self.settings.current().counters.rotation_timezone;
+        let mut rings = locks::lock(&self.rings, "ingest_stats");
+        IngestStats::advance(&mut rings, now, &timezone);
+        let buckets: &[HashMap<IngestClient, Tally>] = match name {
+            "last_24h" => &rings.hours,
+            "last_7d" => &rings.days,
+            _ => {
+                let hour = (0..HOURLY_BUCKETS).find(|&index| Granularity::Hour.bucket_name(index) == name);
+                let day = (0..DAILY_BUCKETS).find(|&index| Granularity::Day.bucket_name(index) == name);
+                match (hour, day) {
+                    (Some(index), _) => std::slice::from_ref(&rings.hours[index]),
+                    (_, Some(index)) => std::slice::from_ref(&rings.days[index]),
+                    _ => return None,
+                }
+            }
+        };
+        let mut merged: BTreeMap<&IngestClient, Tally> = BTreeMap::new();
+        for (client, tally) in buckets.iter().flatten() {
+            merged.entry(client).or_default().merge(tally);
+        }
+        let stats = merged
+            .into_iter()
+            .map(|(client, tally)| ClientIngestStats {
+                tenant: client.tenant.clone(),
+                key: client.key.clone(),
+                source: client.source.clone(),
+                lists: tally.lists,
+                average_list_length: if tally.lists == 0 { 0.0 } else { tally.list_identifiers as f64 / tally.lists as f64 },
+                plays: tally.plays,
+                rejected: tally.rejected,
+                unique_identifiers: tally.identifiers.len(),
+            })
+            .collect();
+        Some(stats)
+    }
+}
+
+/// Whether a response status means the ingested data was rejected, rather than the client
+/// (401, 403) or its rate (429).
+fn is_rejection(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 400 | 413 | 415 | 422)
+}
+
+/// Middleware counting the ingest requests rejected as invalid per client. The accepted
+/// ones are counted by their handlers, which know the lists' sources.
+pub async fn count_rejections(
+    ingest_stats: web::Data<Arc<IngestStats>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if req.method() != Method::POST || !INGEST_ROUTES.contains(&route_pattern(&req).as_str()) {
+        return next.call(req).await;
+    }
+    let client = IngestClient::of(&req.extensions(), None);
+    let result = next.call(req).await;
+    let status = match &result {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    if is_rejection(status) {
+        ingest_stats.record_rejections(client, 1, determinism::now());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::config::Settings;
+
+    fn client(key: &str, source: Option<&str>) -> IngestClient {
+        IngestClient { tenant: None, key: Some(key.to_string()), source: source.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_stats_rotate_like_the_counters() {
+        let mut settings = Settings::from_env();
+        settings.counters.rotation_timezone = "Europe/Berlin".parse().unwrap();
+        let stats = IngestStats::new(Arc::new(SharedSettings::new(settings)));
+        // 23:30 in Berlin
+        let now = Utc.with_ymd_and_hms(2025, 3, 3, 22, 30, 0).unwrap();
+        stats.record_list(client("app", Some("web")), &["ard:a", "ard:b", "ard:c"], now);
+        stats.record_list(client("app", Some("web")), &["ard:a"], now);
+        stats.record_plays(client("app", None), ["ard:a", "ard:d"], now);
+        stats.record_rejections(client("partner", None), 2, now);
+
+        let today = stats.window("today", now).unwrap();
+        assert_eq!(today.len(), 3);
+        let web = &today[1];
+        assert_eq!((web.source.as_deref(), web.lists, web.average_list_length, web.unique_identifiers), (Some("web"), 2, 2.0, 3));
+        assert_eq!((today[0].plays, today[2].rejected), (2, 2));
+
+        // Past midnight in Berlin
+        let later = now + chrono::Duration::hours(1);
+        stats.record_list(client("app", Some("web")), &["ard:e", "ard:a"], later);
+        assert_eq!(stats.window("yesterday", later).unwrap().len(), 3);
+        assert_eq!(stats.window("last_hour", later).unwrap(), stats.window("yesterday", later).unwrap());
+        let week = stats.window("last_7d", later).unwrap();
+        assert_eq!((week[1].lists, week[1].unique_identifiers), (3, 4));
+        assert!(stats.window("last_month", later).is_none());
+    }
+}
@@ -1,101 +1,102 @@
 // src/api/mod.rs
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-
-use actix_web::{web, HttpResponse, Responder, get, post};
-use serde::{Deserialize, Serialize};
-
-// Import the CoOccurrenceCounter from our algorithms module
-use crate::algorithms::CoOccurrenceCounter;
-use crate::algorithms::Counters;
-
-// --- API Data Models for Co-Occurence ---
-
-/// Struct for the POST /add_list request body
-#[derive(Debug, Deserialize)]
-pub struct AddListRequest {
-    pub identifiers: Vec<String>,
-}
-
-/// Struct for the /metrics/{identifier} response
-#[derive(Debug, Serialize)]
-pub struct CoOccurrenceMetricsResponse { // Renamed for clarity
-    pub target_identifier: String,
-    pub co_occurrences: HashMap<String, u32>,
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::middleware::{self, Next};
+use actix_web::{web, Error};
+
+use crate::config::Settings;
+
+pub mod allowlist;
+pub mod audit;
+pub mod auth;
+pub mod compression;
+pub mod deadline;
+pub mod encoding;
+pub mod error;
+pub mod etag;
+pub mod freshness;
+pub mod grpc;
+pub mod idempotency;
+pub mod ingest_queue;
+pub mod ingest_stats;
+pub mod overload;
+pub mod quota;
+pub mod rate_limit;
+pub mod recorder;
+pub mod replica;
+pub mod signing;
+pub mod tenants;
+pub mod validation;
+pub mod v1;
+
+/// `Deprecation` header value of the legacy routes: the day /v1 was introduced
+/// (2026-10-15), as an RFC 9745 structured date.
+const LEGACY_DEPRECATED_SINCE: &str = "@1792022400";
+
+// Every API version lives in its own module with its own models, handlers and
+// `config_routes`, mounted under its prefix below. Versions only share the algorithms,
+// so response shapes can change in a new version without affecting older clients.
+
+/// Configures the routes of all API versions.
+pub fn config_routes(cfg: &mut web::ServiceConfig, settings: &Settings) {
+    cfg.configure(error::config_extractors)
+        .service(
+            web::scope("/v1")
+                .configure(|cfg| v1::config_routes(cfg, settings))
+                .default_service(web::to(error::route_not_found)),
+        );
+
+    #[cfg(feature = "swagger-ui")]
+    cfg.service(
+        utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/{_:.*}")
+            .config(utoipa_swagger_ui::Config::from("/v1/openapi.json")),
+    );
+
+    // The unprefixed routes from before versioning, kept as deprecated aliases of v1.
+    // Registered last, as this scope matches every path.
+    cfg.service(
+        web::scope("")
+            .wrap(middleware::from_fn(deprecated_alias))
+            .configure(|cfg| v1::config_routes(cfg, settings))
+            .default_service(web::to(error::route_not_found)),
+    );
 }
 
-// --- API Data Models for Rotating Counters ---
-
-#[derive(Debug, Deserialize)]
-pub struct IncrementCounterRequest {
-    pub id: String,
+/// Middleware marking responses of the legacy routes as deprecated and linking to the v1 route replacing them.
+async fn deprecated_alias(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let successor = format!("</v1{}>; rel=\"successor-version\"", req.path());
+    let mut response = next.call(req).await?;
+
+    let headers = response.headers_mut();
+    headers.insert(HeaderName::from_static("deprecation"), HeaderValue::from_static(LEGACY_DEPRECATED_SINCE));
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.insert(header::LINK, link);
+    }
+    Ok(response)
 }
 
-#[derive(Debug, Serialize)]
-pub struct DailyCountersResponse {
-    // We can just serialize the entire Counters struct
-    #[serde(flatten)] // Flatten to avoid a nested "counters" field in JSON
-    pub counters: Counters,
-}
+/// Route patterns of the ingestion of lists and plays, all POSTed.
+pub const INGEST_ROUTES: [&str; 4] = ["/lists", "/lists/stream", "/counters", "/counters/batch"];
 
-// --- API Handlers (for Co-Occurence) ---
-
-#[post("/lists")]
-pub async fn add_list_handler(
-    req_body: web::Json<AddListRequest>,
-    counter_data: web::Data<Arc<Mutex<CoOccurrenceCounter>>>,
-) -> impl Responder {
-    let mut counter_lock = counter_data.lock().unwrap();
-    counter_lock.process_list(&req_body.identifiers);
-    HttpResponse::Ok().json(HashMap::from([("status", "success")]))
+/// Whether a request only reads state: GET, HEAD and OPTIONS requests, and GraphQL
+/// queries, which are POSTed but can't change anything.
+pub fn is_read(req: &ServiceRequest) -> bool {
+    matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) || route_pattern(req) == "/graphql"
 }
 
-#[get("/lists/{identifier}")]
-pub async fn get_co_occurrence_metrics_handler(
-    path: web::Path<String>, // Captures the 'identifier' from the URL
-    counter_data: web::Data<Arc<Mutex<CoOccurrenceCounter>>>,
-) -> impl Responder {
-    let identifier = path.into_inner(); // Extract the String from web::Path
-    let counter_lock = counter_data.lock().unwrap();
-    let co_occurrences = counter_lock.get_metrics_for_identifier(&identifier);
-
-    let response = CoOccurrenceMetricsResponse {
-        target_identifier: identifier,
-        co_occurrences,
-    };
-    HttpResponse::Ok().json(response)
+/// Returns the route pattern a request matches without the version prefix, e.g.
+/// "/counters/{id}" for both "/v1/counters/abc" and the legacy "/counters/abc".
+pub fn route_pattern(req: &ServiceRequest) -> String {
+    let pattern = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    match pattern.strip_prefix("/v") {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => {
+            rest.trim_start_matches(|c: char| c.is_ascii_digit()).to_string()
+        }
+        _ => pattern,
+    }
 }
-
-// --- API Handlers (for Rotating Counters) ---
-
-#[post("/counters")]
-pub async fn increment_daily_counter_handler(
-    req_body: web::Json<IncrementCounterRequest>,
-    rotating_counters_data: web::Data<Arc<Mutex<Counters>>>, 
-) -> impl Responder {
-    let mut counters_lock = rotating_counters_data.lock().unwrap();
-    counters_lock.increment(&req_body.id);
-    HttpResponse::Ok().json(HashMap::from([("status", "success")]))
-}
-
-#[get("/counters")]
-pub async fn get_rotating_counters_handler(
-    rotating_counters_data: web::Data<Arc<Mutex<Counters>>>,
-) -> impl Responder {
-    let counters_lock = rotating_counters_data.lock().unwrap();
-    let counters = counters_lock.clone(); // Clone the data for the response
-
-    let response = DailyCountersResponse { counters };
-    HttpResponse::Ok().json(response)
-}
-
-
-// --- Route Configuration ---
-
-/// Configures the routes for all API endpoints.
-pub fn config_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(add_list_handler)
-       .service(get_co_occurrence_metrics_handler) 
-       .service(increment_daily_counter_handler)  
-       .service(get_rotating_counters_handler);     
-}
\ No newline at end of file
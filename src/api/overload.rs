@@ -0,0 +1,115 @@
+// src/api/overload.rs
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, ResponseError};
+use dashmap::DashMap;
+
+use crate::api::error::ApiError;
+use crate::api::rate_limit::route_key;
+use crate::config::{OverloadSettings, SharedSettings};
+use crate::locks;
+
+/// Requests in flight per route, so a slow route can't tie up every worker while the
+/// others still respond.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    /// Read on every request, so reloaded limits apply right away
+    settings: Arc<SharedSettings>,
+    in_flight: DashMap<String, Arc<AtomicUsize>>,
+}
+
+/// A request counted as in flight until dropped.
+#[derive(Debug)]
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(settings: Arc<SharedSettings>) -> Self {
+        ConcurrencyLimiter { settings, in_flight: DashMap::new() }
+    }
+
+    /// Counts a request to `route` as in flight unless `limit` (0 for unlimited) of them
+    /// already are.
+    fn enter(&self, route: &str, limit: usize) -> Option<InFlight> {
+        let counter = match self.in_flight.get(route) {
+            Some(counter) => Arc::clone(&counter),
+            None => Arc::clone(&self.in_flight.entry(route.to_string()).or_default()),
+        };
+        let admitted = counter.fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+            (limit == 0 || in_flight < limit).then_some(in_flight + 1)
+        });
+        admitted.ok().map(|_| InFlight(counter))
+    }
+}
+
+/// Middleware rejecting requests with 503 while their route serves as many requests as
+/// it may at once, instead of queueing them up behind the others.
+pub async fn limit_concurrency(
+    limiter: web::Data<Arc<ConcurrencyLimiter>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let settings = limiter.settings.current();
+    let route = route_key(&req);
+    let Some(_in_flight) = limiter.enter(&route, settings.overload.limit_for(&route)) else {
+        // Responded to directly like the rate limiting, so the access log sees it
+        let error = ApiError::Unavailable(settings.overload.retry_after_secs);
+        return Ok(req.into_response(error.error_response()).map_into_right_body());
+    };
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+/// Acquires shared access to `lock`, or fails with 503 if it isn't free within the
+/// configured lock timeout.
+pub fn read_or_unavailable<'a, T>(
+    lock: &'a RwLock<T>,
+    name: &'static str,
+    settings: &OverloadSettings,
+) -> Result<RwLockReadGuard<'a, T>, ApiError> {
+    locks::read_within(lock, name, settings.lock_timeout()).ok_or(ApiError::Unavailable(settings.retry_after_secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RouteConcurrencyLimits, Settings};
+
+    #[test]
+    fn test_routes_are_limited_independently() {
+        let mut settings = Settings::from_env();
+        settings.overload.max_in_flight = 0;
+        settings.overload.route_limits = "GET /lists/{identifier}=2".parse::<RouteConcurrencyLimits>().unwrap();
+        let limiter = ConcurrencyLimiter::new(Arc::new(SharedSettings::new(settings.clone())));
+
+        let route = "GET /lists/{identifier}";
+        let limit = settings.overload.limit_for(route);
+        let first = limiter.enter(route, limit).unwrap();
+        let _second = limiter.enter(route, limit).unwrap();
+        assert!(limiter.enter(route, limit).is_none());
+        assert!(limiter.enter("GET /counters", settings.overload.limit_for("GET /counters")).is_some());
+
+        drop(first);
+        assert!(limiter.enter(route, limit).is_some());
+        assert!("GET /lists=many".parse::<RouteConcurrencyLimits>().is_err());
+    }
+
+    #[test]
+    fn test_locks_time_out() {
+        let mut settings = Settings::from_env().overload;
+        settings.lock_timeout_ms = 5;
+        let lock = RwLock::new(1);
+        let held = lock.write().unwrap();
+        assert!(matches!(read_or_unavailable(&lock, "test", &settings), Err(ApiError::Unavailable(_))));
+        drop(held);
+        let _reader = read_or_unavailable(&lock, "test", &settings).unwrap();
+        assert_eq!(*read_or_unavailable(&lock, "test", &settings).unwrap(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+// src/api/quota.rs
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, RwLock};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, ResponseError};
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::algorithms::rotating_counters::{count_of, rotate_buckets, Bucket};
+use crate::api::auth::ApiClient;
+use crate::api::error::ApiError;
+use crate::config::SharedSettings;
+use crate::{determinism, locks};
+
+/// Number of days the usage of every key is kept for.
+pub const USAGE_DAYS: usize = 30;
+
+/// The requests of a key on one day.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct UsageDay {
+    pub date: NaiveDate,
+    pub requests: u64,
+}
+
+/// The usage of one API key.
+#[derive(Serialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct KeyUsage {
+    /// Name of the API key
+    pub key: String,
+    /// Requests today, in the rotation time zone
+    pub today: u64,
+    /// Requests per day, `None` if unlimited
+    pub quota: Option<u64>,
+    /// Requests left today, `None` if unlimited
+    pub remaining: Option<u64>,
+    /// Oldest first, ending with today
+    pub days: Vec<UsageDay>,
+}
+
+/// Requests per API key and day, counted in daily buckets that rotate on their own at
+/// midnight in the rotation time zone, like the per-minute counters. Kept in memory only,
+/// so a restart resets today's usage along with the history.
+#[derive(Debug)]
+pub struct UsageMeter {
+    /// Read on every request, so reloaded quotas apply right away
+    settings: Arc<SharedSettings>,
+    /// `buckets[0]` is the day `current`, `buckets[1]` the one before and so on
+    buckets: RwLock<Vec<Bucket>>,
+    /// Days since the Common Era of `buckets[0]`
+    current: AtomicI64,
+}
+
+fn date_of(at: DateTime<Utc>, timezone: &Tz) -> NaiveDate {
+    at.with_timezone(timezone).date_naive()
+}
+
+fn day_of(date: NaiveDate) -> i64 {
+    date.num_days_from_ce() as i64
+}
+
+/// Seconds from `now` until the next midnight in `timezone`, when the quotas reset.
+fn seconds_until_midnight(now: DateTime<Utc>, timezone: &Tz) -> u64 {
+    let midnight = date_of(now, timezone)
+        .succ_opt()
+        .and_then(|tomorrow| tomorrow.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| timezone.from_local_datetime(&midnight).earliest());
+    midnight.map_or(1, |midnight| (midnight.with_timezone(&Utc) - now).num_seconds().max(1) as u64)
+}
+
+impl UsageMeter {
+    pub fn new(settings: Arc<SharedSettings>) -> Self {
+        UsageMeter { settings, buckets: RwLock::new(vec![Bucket::new(); USAGE_DAYS]), current: AtomicI64::new(0) }
+    }
+
+    /// Rotates the ring so that `buckets[0]` is `day`; earlier days are ignored.
+    fn advance_to(&self, day: i64) {
+        if day <= self.current.load(Ordering::Acquire) {
+            return;
+        }
+        let mut buckets = locks::write(&self.buckets, "usage_meter");
+        let current = self.current.load(Ordering::Acquire);
+        if day > current {
+            let steps = usize::try_from(day - current).unwrap_or(usize::MAX);
+            rotate_buckets(&mut buckets, steps);
+            self.current.store(day, Ordering::Release);
+        }
+    }
+
+    /// Counts a request of `key` at `now` unless that would exceed `quota`; returns
+    /// whether it was counted. Checked and counted at once, so concurrent requests can't
+    /// slip past the quota.
+    fn admit(&self, key: &str, quota: Option<u64>, now: DateTime<Utc>, timezone: &Tz) -> bool {
+        let day = day_of(date_of(now, timezone));
+        self.advance_to(day);
+        let buckets = locks::read(&self.buckets, "usage_meter");
+        let age = self.current.load(Ordering::Acquire) - day;
+        let Some(bucket) = usize::try_from(age).ok().and_then(|age| buckets.get(age)) else {
+            return true;
+        };
+        let mut count = bucket.entry(key.to_string()).or_insert(0);
+        if quota.is_some_and(|quota| *count >= quota) {
+            return false;
+        }
+        *count = count.saturating_add(1);
+        true
+    }
+
+    /// Returns the usage of every key that made requests in the kept days or has a quota,
+    /// by name.
+    pub fn usage(&self, now: DateTime<Utc>) -> Vec<KeyUsage> {
+        let settings = self.settings.current();
+        let timezone = settings.counters.rotation_timezone;
+        let today = date_of(now, &timezone);
+        self.advance_to(day_of(today));
+        let buckets = locks::read(&self.buckets, "usage_meter");
+        let mut keys: BTreeSet<String> = settings.auth.daily_quotas.0.keys().cloned().collect();
+        keys.extend(buckets.iter().flat_map(|bucket| bucket.iter().map(|entry| entry.key().clone()).collect::<Vec<_>>()));
+        keys.into_iter()
+            .map(|key| {
+                let days: Vec<UsageDay> = buckets
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(age, bucket)| UsageDay { date: today - chrono::Duration::days(age as i64), requests: count_of(bucket, &key) })
+                    .collect();
+                let requests_today = days.last().map_or(0, |day| day.requests);
+                let quota = settings.auth.daily_quotas.of(&key);
+                KeyUsage { remaining: quota.map(|quota| quota.saturating_sub(requests_today)), key, today: requests_today, quota, days }
+            })
+            .collect()
+    }
+}
+
+/// Middleware counting the requests of every API key, and rejecting them with 429 once
+/// the key used up its daily quota. Requests without a key aren't counted.
+pub async fn enforce_quota(
+    meter: web::Data<Arc<UsageMeter>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let key = req.extensions().get::<ApiClient>().map(|client| client.name.clone());
+    if let Some(key) = key {
+        let settings = meter.settings.current();
+        let timezone = settings.counters.rotation_timezone;
+        let now = determinism::now();
+        if !meter.admit(&key, settings.auth.daily_quotas.of(&key), now, &timezone) {
+            // Responded to directly like the rate limiting, so the access log sees it
+            let error = ApiError::QuotaExceeded(seconds_until_midnight(now, &timezone));
+            return Ok(req.into_response(error.error_response()).map_into_right_body());
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DailyQuotas, Settings};
+
+    #[test]
+    fn test_quotas_reset_at_midnight() {
+        let mut settings = Settings::from_env();
+        let timezone: Tz = "Europe/Berlin".parse().unwrap();
+        settings.counters.rotation_timezone = timezone;
+        settings.auth.daily_quotas = "partner=2; dashboard = 5".parse::<DailyQuotas>().unwrap();
+        let meter = UsageMeter::new(Arc::new(SharedSettings::new(settings)));
+        // 23:30 in Berlin
+        let now = Utc.with_ymd_and_hms(2025, 3, 3, 22, 30, 0).unwrap();
+        assert!(meter.admit("partner", Some(2), now, &timezone));
+        assert!(meter.admit("partner", Some(2), now, &timezone));
+        assert!(!meter.admit("partner", Some(2), now, &timezone));
+        assert!(meter.admit("ingest", None, now, &timezone));
+        assert_eq!(seconds_until_midnight(now, &timezone), 1800);
+
+        let tomorrow = now + chrono::Duration::hours(1);
+        assert!(meter.admit("partner", Some(2), tomorrow, &timezone));
+        let usage = meter.usage(tomorrow);
+        assert_eq!(usage.iter().map(|usage| usage.key.as_str()).collect::<Vec<_>>(), ["dashboard", "ingest", "partner"]);
+        let partner = &usage[2];
+        assert_eq!((partner.today, partner.quota, partner.remaining), (1, Some(2), Some(1)));
+        assert_eq!(partner.days.len(), USAGE_DAYS);
+        assert_eq!(partner.days[USAGE_DAYS - 2].requests, 2);
+        assert!("partner=many".parse::<DailyQuotas>().is_err());
+    }
+}
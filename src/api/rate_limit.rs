@@ -0,0 +1,179 @@
+// src/api/rate_limit.rs
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, ResponseError};
+use dashmap::DashMap;
+
+use crate::api::auth::ApiClient;
+use crate::api::error::ApiError;
+use crate::api::route_pattern;
+use crate::config::{RateLimit, RateLimitSettings, SharedSettings};
+
+/// Number of checks between two sweeps of idle buckets.
+const SWEEP_INTERVAL: u64 = 4096;
+
+/// A token bucket: holds up to `burst` tokens, refilled at `per_second`. Every request
+/// takes one token.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        TokenBucket { tokens: limit.burst as f64, refilled_at: now }
+    }
+
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.refilled_at = now;
+    }
+
+    /// Takes a token, or returns how long to wait until one is available.
+    fn take(&mut self, limit: RateLimit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / limit.per_second))
+        }
+    }
+}
+
+/// Per-client, per-route token buckets.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Read on every check, so reloaded limits apply right away
+    settings: Arc<SharedSettings>,
+    /// Keyed by (client, route)
+    buckets: DashMap<(String, String), TokenBucket>,
+    checks: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(settings: Arc<SharedSettings>) -> Self {
+        RateLimiter {
+            settings,
+            buckets: DashMap::new(),
+            checks: AtomicU64::new(0),
+        }
+    }
+
+    fn limit_for(settings: &RateLimitSettings, route: &str) -> RateLimit {
+        settings.route_limits.0.get(route).copied().unwrap_or(settings.default_limit)
+    }
+
+    /// Takes a token from the bucket of `client` on `route`, or returns how long the
+    /// client has to wait.
+    fn check(&self, client: &str, route: &str, now: Instant) -> Result<(), Duration> {
+        if self.checks.fetch_add(1, Ordering::Relaxed) % SWEEP_INTERVAL == SWEEP_INTERVAL - 1 {
+            self.sweep(now);
+        }
+        let limit = Self::limit_for(&self.settings.current().rate_limit, route);
+        self.buckets
+            .entry((client.to_string(), route.to_string()))
+            .or_insert_with(|| TokenBucket::full(limit, now))
+            .take(limit, now)
+    }
+
+    /// Drops buckets that have refilled completely; they behave like new ones, so
+    /// clients that went away don't take up memory forever.
+    fn sweep(&self, now: Instant) {
+        let settings = self.settings.current();
+        self.buckets.retain(|(_, route), bucket| {
+            let limit = Self::limit_for(&settings.rate_limit, route);
+            bucket.refill(limit, now);
+            bucket.tokens < limit.burst as f64
+        });
+    }
+}
+
+/// Returns the route a request is limited under: method and pattern without the version
+/// prefix, so all versions and the legacy aliases of a route share one limit.
+pub(crate) fn route_key(req: &ServiceRequest) -> String {
+    format!("{} {}", req.method(), route_pattern(req))
+}
+
+/// Returns who a request is counted against: the authenticated API client if there is
+/// one, otherwise the client's IP address.
+fn client_key(req: &ServiceRequest, trust_proxy: bool) -> String {
+    if let Some(client) = req.extensions().get::<ApiClient>() {
+        return format!("key:{}", client.name);
+    }
+    let connection = req.connection_info();
+    let address = if trust_proxy { connection.realip_remote_addr() } else { connection.peer_addr() };
+    format!("ip:{}", address.unwrap_or("unknown"))
+}
+
+/// Middleware rejecting requests with 429 once the client used up its bucket for the route.
+pub async fn rate_limit(
+    limiter: web::Data<Arc<RateLimiter>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let settings = limiter.settings.current();
+    if settings.rate_limit.enabled {
+        let client = client_key(&req, settings.rate_limit.trust_proxy);
+        if let Err(wait) = limiter.check(&client, &route_key(&req), Instant::now()) {
+            // Responded to directly rather than returned as an error, so the outer
+            // middlewares (e.g. the access log) still see the request
+            let error = ApiError::RateLimited(wait.as_secs_f64().ceil().max(1.0) as u64);
+            return Ok(req.into_response(error.error_response()).map_into_right_body());
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RouteRateLimits, Settings};
+
+    fn limiter() -> RateLimiter {
+        let mut settings = Settings::from_env();
+        settings.rate_limit = RateLimitSettings {
+            enabled: true,
+            default_limit: RateLimit { per_second: 1.0, burst: 2 },
+            route_limits: "POST /counters=10:1".parse::<RouteRateLimits>().unwrap(),
+            trust_proxy: false,
+        };
+        RateLimiter::new(Arc::new(SharedSettings::new(settings)))
+    }
+
+    #[test]
+    fn test_burst_then_refill() {
+        let limiter = limiter();
+        let now = Instant::now();
+        assert!(limiter.check("a", "GET /lists/{identifier}", now).is_ok());
+        assert!(limiter.check("a", "GET /lists/{identifier}", now).is_ok());
+        let wait = limiter.check("a", "GET /lists/{identifier}", now).unwrap_err();
+        assert!((wait.as_secs_f64() - 1.0).abs() < 1e-6);
+
+        // Other clients and routes have their own buckets
+        assert!(limiter.check("b", "GET /lists/{identifier}", now).is_ok());
+        assert!(limiter.check("a", "POST /counters", now).is_ok());
+        assert!(limiter.check("a", "POST /counters", now).is_err());
+
+        let later = now + Duration::from_millis(1100);
+        assert!(limiter.check("a", "GET /lists/{identifier}", later).is_ok());
+        assert!(limiter.check("a", "POST /counters", later).is_ok());
+    }
+
+    #[test]
+    fn test_sweep_drops_refilled_buckets() {
+        let limiter = limiter();
+        let now = Instant::now();
+        limiter.check("a", "GET /rules", now).unwrap();
+        limiter.sweep(now);
+        assert_eq!(limiter.buckets.len(), 1);
+        limiter.sweep(now + Duration::from_secs(5));
+        assert!(limiter.buckets.is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+// src/api/recorder.rs
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::web::Bytes;
+use actix_web::{web, Error, HttpMessage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::api::{route_pattern, INGEST_ROUTES};
+use crate::api::tenants::RequestTenant;
+use crate::config::RecorderSettings;
+use crate::{determinism, locks};
+
+/// Path of the recording, relative to the data directory.
+pub const RECORDING_PATH: &str = "requests.log";
+
+/// An ingest request as recorded, without anything identifying the client.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordedRequest {
+    pub at: DateTime<Utc>,
+    pub method: String,
+    /// Path and query as sent, with the tenant prefix if any
+    pub path: String,
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    /// The body as sent if it is UTF-8, else hex-encoded
+    pub body: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hex: bool,
+    /// Status the request was answered with
+    pub status: u16,
+}
+
+impl RecordedRequest {
+    /// The body as sent.
+    pub fn body_bytes(&self) -> Result<Vec<u8>, String> {
+        if self.hex {
+            return hex::decode(&self.body).map_err(|e| format!("Invalid hex-encoded body: {}", e));
+        }
+        Ok(self.body.clone().into_bytes())
+    }
+}
+
+/// Rolling recording of the ingest requests, one JSON line per request. Once the file
+/// reaches its size limit, it is renamed to "<file>.1" and a new one started, so at most
+/// twice the limit is kept.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    /// The file and its size; `None` if disabled, or if the file couldn't be opened
+    file: Mutex<Option<(File, u64)>>,
+    path: PathBuf,
+    max_file_bytes: u64,
+    max_body_bytes: usize,
+}
+
+impl Recorder {
+    /// Opens (or creates) the recording at `path` if enabled. If that fails, the error is
+    /// logged and requests go unrecorded.
+    pub fn open(path: PathBuf, settings: &RecorderSettings) -> Self {
+        let file = if settings.enabled { open_file(&path) } else { None };
+        Recorder { file: Mutex::new(file), path, max_file_bytes: settings.max_file_bytes, max_body_bytes: settings.max_body_bytes }
+    }
+
+    fn is_enabled(&self) -> bool {
+        locks::lock(&self.file, "recorder").is_some()
+    }
+
+    /// Appends `request`, rolling the file over first if it is full.
+    pub fn append(&self, request: &RecordedRequest) {
+        let mut file = locks::lock(&self.file, "recorder");
+        if file.as_ref().is_some_and(|&(_, size)| size >= self.max_file_bytes) {
+            let rolled = PathBuf::from(format!("{}.1", self.path.display()));
+            if let Err(e) = fs::rename(&self.path, &rolled) {
+                error!("Failed to roll over the recording {}: {}", self.path.display(), e);
+            }
+            *file = open_file(&self.path);
+        }
+        let Some((file, size)) = file.as_mut() else {
+            return;
+        };
+        let result = serde_json::to_vec(request).map_err(io::Error::other).and_then(|mut line| {
+            line.push(b'\n');
+            file.write_all(&line)?;
+            Ok(line.len() as u64)
+        });
+        match result {
+            Ok(written) => *size += written,
+            Err(e) => error!("Failed to record {} {}: {}", request.method, request.path, e),
+        }
+    }
+}
+
+fn open_file(path: &Path) -> Option<(File, u64)> {
+    let result = OpenOptions::new().create(true).append(true).open(path).and_then(|file| Ok((file.metadata()?.len(), file)));
+    match result {
+        Ok((size, file)) => Some((file, size)),
+        Err(e) => {
+            error!("Failed to open the recording {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Reads a recording, skipping lines that can't be parsed, e.g. cut off by a crash.
+pub fn read_recording(path: &Path) -> io::Result<Vec<RecordedRequest>> {
+    let mut requests = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Ok(request) = serde_json::from_str(&line?) {
+            requests.push(request);
+        }
+    }
+    Ok(requests)
+}
+
+/// Middleware recording the ingest requests if enabled. Bodies are only read up front if
+/// their size is known and within the limit, so large streams pass through untouched.
+pub async fn record_ingest(
+    recorder: web::Data<Arc<Recorder>>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let length = req.headers().get(header::CONTENT_LENGTH).and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+    let recorded = req.method() == Method::POST && INGEST_ROUTES.contains(&route_pattern(&req).as_str());
+    if !recorded || length.is_none_or(|length| length > recorder.max_body_bytes) || !recorder.is_enabled() {
+        return next.call(req).await;
+    }
+    // Read here and put back for the handler
+    let body = req.extract::<Bytes>().await?;
+    req.set_payload(Payload::from(body.clone()));
+    let header_value = |name| req.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+    let original_path = req.extensions().get::<RequestTenant>().and_then(|tenant| tenant.original_path.clone());
+    let (body, hex) = match String::from_utf8(body.to_vec()) {
+        Ok(body) => (body, false),
+        Err(e) => (hex::encode(e.as_bytes()), true),
+    };
+    let mut request = RecordedRequest {
+        at: determinism::now(),
+        method: req.method().to_string(),
+        path: original_path.unwrap_or_else(|| req.uri().path_and_query().map_or(req.path(), |path| path.as_str()).to_string()),
+        content_type: header_value(header::CONTENT_TYPE),
+        content_encoding: header_value(header::CONTENT_ENCODING),
+        body,
+        hex,
+        status: 0,
+    };
+    let result = next.call(req).await;
+    request.status = match &result {
+        Ok(response) => response.status().as_u16(),
+        Err(e) => e.as_response_error().status_code().as_u16(),
+    };
+    recorder.append(&request);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_rolls_over_when_full() {
+        let path = std::env::temp_dir().join(format!("mediathek_requests_{}.log", std::process::id()));
+        let rolled = PathBuf::from(format!("{}.1", path.display()));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&rolled);
+        let request = |body: &str, hex: bool| RecordedRequest {
+            at: DateTime::UNIX_EPOCH,
+            method: "POST".to_string(),
+            path: "/v1/lists".to_string(),
+            content_type: Some("application/json".to_string()),
+            content_encoding: None,
+            body: body.to_string(),
+            hex,
+            status: 200,
+        };
+        let settings = RecorderSettings { enabled: true, max_file_bytes: 200, max_body_bytes: 1024 };
+        let recorder = Recorder::open(path.clone(), &settings);
+        recorder.append(&request(r#"{"identifiers":["ard:a","ard:b"]}"#, false));
+        recorder.append(&request("81a3", true));
+        // Over the limit after the second line
+        recorder.append(&request(r#"{"id":"ard:a"}"#, false));
+
+        let rolled_requests = read_recording(&rolled).unwrap();
+        assert_eq!(rolled_requests, [request(r#"{"identifiers":["ard:a","ard:b"]}"#, false), request("81a3", true)]);
+        assert_eq!(rolled_requests[1].body_bytes().unwrap(), [0x81, 0xa3]);
+        assert_eq!(read_recording(&path).unwrap(), [request(r#"{"id":"ard:a"}"#, false)]);
+        assert!(!Recorder::open(path.clone(), &RecorderSettings { enabled: false, ..settings }).is_enabled());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rolled).unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+// src/api/replica.rs
+use std::sync::Arc;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, ResponseError};
+
+use crate::algorithms::replication::ReplicationState;
+use crate::api::error::ApiError;
+use crate::api::tenants::RequestTenant;
+use crate::api::{is_read, route_pattern};
+
+/// Whether a replica serves a request: reads, the replication endpoints (so it can be
+/// promoted), and requests for tenants, whose state isn't replicated.
+fn replica_serves(req: &ServiceRequest) -> bool {
+    is_read(req) || route_pattern(req).starts_with("/admin/replication") || req.extensions().contains::<RequestTenant>()
+}
+
+/// Middleware rejecting writes with 403 while this instance is a replica (see
+/// `ReplicationSettings`), as they would be overwritten by the primary's state.
+pub async fn reject_replica_writes(
+    state: web::Data<Arc<ReplicationState>>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if state.is_following() && !replica_serves(&req) {
+        let error = ApiError::Forbidden("This instance is a replica; send writes to the primary".to_string());
+        return Ok(req.into_response(error.error_response()).map_into_right_body());
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
@@ -0,0 +1,126 @@
+// src/api/signing.rs
+use std::sync::Arc;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, ResponseError};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::api::auth::ApiClient;
+use crate::api::is_read;
+use crate::api::tenants::RequestTenant;
+use crate::api::error::ApiError;
+use crate::config::SharedSettings;
+
+/// Header naming the client whose secret signed the request.
+const CLIENT_HEADER: &str = "x-client-id";
+/// Header with the signing time, in seconds since the Unix epoch.
+const TIMESTAMP_HEADER: &str = "x-timestamp";
+/// Header with the hex-encoded HMAC-SHA256 signature.
+const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Returns the HMAC-SHA256 of "<timestamp>\n<METHOD>\n<path and query>\n<body>" under
+/// `secret`, the message clients sign.
+fn signature_mac(secret: &[u8], timestamp: &str, method: &Method, path: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    for part in [timestamp.as_bytes(), method.as_str().as_bytes(), path.as_bytes()] {
+        mac.update(part);
+        mac.update(b"\n");
+    }
+    mac.update(body);
+    mac
+}
+
+/// Checks `signature` against `mac`, the expected one, and that the request was signed
+/// within the allowed window; returns why it is rejected if it is.
+fn verify(mac: Hmac<Sha256>, timestamp: &str, signature: &str, max_skew_secs: u64, now: i64) -> Result<(), &'static str> {
+    let signed_at: i64 = timestamp.parse().map_err(|_| "Invalid X-Timestamp")?;
+    if signed_at.abs_diff(now) > max_skew_secs {
+        return Err("X-Timestamp outside of the allowed window");
+    }
+    let signature = hex::decode(signature).map_err(|_| "X-Signature is not hex-encoded")?;
+    mac.verify_slice(&signature).map_err(|_| "Invalid signature")
+}
+
+/// Middleware verifying signed requests: `X-Client-Id`, `X-Timestamp` and `X-Signature`
+/// headers, the latter an HMAC-SHA256 with the client's secret (see `signature_mac`).
+/// Signed clients are recorded as `ApiClient` and don't need an API key. Unsigned
+/// requests pass, unless signatures are required for writes. Without any configured
+/// secrets, all requests pass. Signed bodies are read whole, up to
+/// `SigningSettings::max_body_bytes`, and only the timestamp window guards against replays.
+pub async fn verify_signature(
+    settings: web::Data<Arc<SharedSettings>>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let settings = settings.current();
+    let signing = &settings.signing;
+    if signing.client_secrets.0.is_empty() {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let header = |req: &ServiceRequest, name| {
+        req.headers().get(name).map(|value| value.to_str().unwrap_or_default().to_string())
+    };
+    let rejection = match (header(&req, CLIENT_HEADER), header(&req, TIMESTAMP_HEADER), header(&req, SIGNATURE_HEADER)) {
+        (Some(client), Some(timestamp), Some(signature)) => {
+            match signing.client_secrets.0.iter().find(|secret| secret.name == client) {
+                Some(secret) => {
+                    // The body is needed for the signature, so it is read here and put back
+                    let body = match req.extract::<web::Payload>().await?.to_bytes_limited(signing.max_body_bytes).await {
+                        Ok(body) => body?,
+                        Err(_) => {
+                            let error = ApiError::PayloadTooLarge(format!("Signed payload exceeds {} bytes", signing.max_body_bytes));
+                            return Ok(req.into_response(error.error_response()).map_into_right_body());
+                        }
+                    };
+                    // Signed as sent, before a tenant prefix was removed
+                    let original_path = req.extensions().get::<RequestTenant>().and_then(|tenant| tenant.original_path.clone());
+                    let path = original_path.unwrap_or_else(|| req.uri().path_and_query().map_or(req.path(), |path| path.as_str()).to_string());
+                    let mac = signature_mac(secret.key.as_bytes(), &timestamp, req.method(), &path, &body);
+                    let result = verify(mac, &timestamp, &signature, signing.max_skew_secs, chrono::Utc::now().timestamp());
+                    req.set_payload(Payload::from(body));
+                    match result {
+                        Ok(()) => {
+                            tracing::Span::current().record("client", secret.name.as_str());
+                            req.extensions_mut().insert(ApiClient { name: secret.name.clone() });
+                            None
+                        }
+                        Err(message) => Some(message),
+                    }
+                }
+                None => Some("Unknown X-Client-Id"),
+            }
+        }
+        (None, None, None) => (signing.required && !is_read(&req)).then_some("Missing request signature"),
+        _ => Some("Signed requests need the X-Client-Id, X-Timestamp and X-Signature headers"),
+    };
+    if let Some(message) = rejection {
+        let error = ApiError::Unauthorized(message.to_string());
+        return Ok(req.into_response(error.error_response()).map_into_right_body());
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_signature() {
+        let body = br#"{"id":"a"}"#;
+        let mac = |timestamp, path, body: &[u8]| signature_mac(b"s3cret", timestamp, &Method::POST, path, body);
+        let signature = hex::encode(mac("1000", "/v1/counters", body).finalize().into_bytes());
+
+        assert!(verify(mac("1000", "/v1/counters", body), "1000", &signature, 300, 1200).is_ok());
+        assert_eq!(
+            verify(mac("1000", "/v1/counters", body), "1000", &signature, 300, 1301),
+            Err("X-Timestamp outside of the allowed window")
+        );
+        assert_eq!(verify(mac("1000", "/v1/counters", b"{}"), "1000", &signature, 300, 1000), Err("Invalid signature"));
+        assert_eq!(verify(mac("1000", "/v1/lists", body), "1000", &signature, 300, 1000), Err("Invalid signature"));
+        assert_eq!(verify(mac("1001", "/v1/counters", body), "1001", &signature, 300, 1000), Err("Invalid signature"));
+    }
+}
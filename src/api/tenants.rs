@@ -0,0 +1,108 @@
+// src/api/tenants.rs
+use std::rc::Rc;
+use std::sync::Arc;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Extensions, ServiceRequest, ServiceResponse};
+use actix_web::http::Uri;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, ResponseError};
+
+use crate::algorithms::tenants::{Tenant, Tenants};
+use crate::api::error::ApiError;
+use crate::server::AppState;
+
+/// Header naming the tenant of a request, as an alternative to the `/t/{tenant}` prefix.
+const TENANT_HEADER: &str = "x-tenant";
+
+/// The tenant of a request, stored in the request extensions.
+#[derive(Clone)]
+pub struct RequestTenant {
+    pub tenant: Arc<Tenant>,
+    /// Path and query as sent, if the `/t/{tenant}` prefix was removed from them
+    pub original_path: Option<String>,
+}
+
+/// Splits "/t/{tenant}/rest" into the tenant and "/rest".
+fn split_tenant_path(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix("/t/")?;
+    match rest.find('/') {
+        Some(index) => Some((&rest[..index], &rest[index..])),
+        None => Some((rest, "/")),
+    }
+}
+
+/// Middleware serving requests for a tenant (see `TenantSettings`) from the tenant's
+/// co-occurrences and counters: they are put in front of the default ones, so the
+/// handlers pick them up like any other app data. The `/t/{tenant}` prefix is removed
+/// before routing, so tenants have all the routes. Unknown tenants get a 404.
+pub async fn resolve_tenant(
+    tenants: web::Data<Arc<Tenants>>,
+    state: web::Data<AppState>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let from_path = split_tenant_path(req.path()).map(|(name, rest)| (name.to_string(), rest.to_string()));
+    let from_header = req.headers().get(TENANT_HEADER).map(|value| value.to_str().unwrap_or_default().to_string());
+    let name = match (&from_path, from_header) {
+        (Some((name, _)), Some(header)) if *name != header => {
+            let error = ApiError::BadRequest("The tenant of the path and the X-Tenant header differ".to_string());
+            return Ok(req.into_response(error.error_response()).map_into_right_body());
+        }
+        (Some((name, _)), _) => name.clone(),
+        (None, Some(header)) => header,
+        (None, None) => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+    };
+
+    let lookup = {
+        let tenants = tenants.get_ref().clone();
+        let name = name.clone();
+        web::block(move || tenants.get(&name)).await?
+    };
+    let tenant = match lookup {
+        Ok(Some(tenant)) => tenant,
+        Ok(None) => {
+            let error = ApiError::NotFound(format!("Unknown tenant '{}'", name));
+            return Ok(req.into_response(error.error_response()).map_into_right_body());
+        }
+        Err(e) => {
+            let error = ApiError::Internal(format!("Failed to load tenant '{}': {}", name, e));
+            return Ok(req.into_response(error.error_response()).map_into_right_body());
+        }
+    };
+
+    let mut original_path = None;
+    if let Some((_, rest)) = from_path {
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{}?{}", rest, query),
+            None => rest,
+        };
+        let mut parts = req.uri().clone().into_parts();
+        parts.path_and_query = Some(path_and_query.parse().map_err(|_| ApiError::BadRequest("Invalid path".to_string()))?);
+        let uri = Uri::from_parts(parts).map_err(|_| ApiError::BadRequest("Invalid path".to_string()))?;
+        original_path = req.uri().path_and_query().map(|path| path.to_string());
+        req.match_info_mut().get_mut().update(&uri);
+        req.head_mut().uri = uri;
+    }
+
+    let mut data = Extensions::new();
+    data.insert(web::Data::new(Arc::clone(&tenant.co_occurrence)));
+    data.insert(web::Data::new(Arc::clone(&tenant.identifier_filter)));
+    data.insert(web::Data::new(Arc::clone(&tenant.counters)));
+    data.insert(web::Data::new(state.for_tenant(&tenant)));
+    req.add_data_container(Rc::new(data));
+    req.extensions_mut().insert(RequestTenant { tenant, original_path });
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_prefix_is_split_off() {
+        assert_eq!(split_tenant_path("/t/ard/v1/lists/abc"), Some(("ard", "/v1/lists/abc")));
+        assert_eq!(split_tenant_path("/t/ard"), Some(("ard", "/")));
+        assert_eq!(split_tenant_path("/counters"), None);
+        assert_eq!(split_tenant_path("/tags"), None);
+    }
+}
@@ -0,0 +1,92 @@
+// src/api/v1/boosts.rs
+use std::sync::{Arc, RwLock};
+
+use actix_web::{get, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::algorithms::boosts::{Boost, Boosts};
+use crate::api::error::{ApiError, ErrorResponse};
+use crate::api::validation::{normalize_identifier, validate_identifier};
+use crate::config::SharedSettings;
+use crate::{determinism, locks};
+
+/// Struct for the POST /admin/boosts request body. At least one of `factor` and
+/// `pin_position` is required.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BoostRequest {
+    pub identifier: String,
+    /// Multiplies the item's score wherever it is recommended, e.g. 2.0
+    pub factor: Option<f64>,
+    /// 1-based slot the item is put in
+    pub pin_position: Option<usize>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Struct for the GET /admin/boosts response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BoostsResponse {
+    pub boosts: Vec<Boost>,
+}
+
+/// Boosts or pins an item in the recommendations until the given time, replacing an
+/// earlier boost of the item.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    request_body = BoostRequest,
+    responses(
+        (status = 200, description = "The active boosts", body = BoostsResponse),
+        (status = 400, description = "Neither a factor nor a position, or already expired", body = ErrorResponse),
+        (status = 422, description = "The identifier violates the identifier limits", body = ErrorResponse),
+    )
+)]
+#[post("/boosts")]
+pub async fn set_boost_handler(
+    req_body: web::Json<BoostRequest>,
+    boosts_data: web::Data<Arc<RwLock<Boosts>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let mut request = req_body.into_inner();
+    let settings = settings.current();
+    request.identifier = normalize_identifier(&request.identifier, &settings.validation.normalization).into_owned();
+    validate_identifier(&request.identifier, &settings.validation)?;
+    if request.factor.is_none() && request.pin_position.is_none() {
+        return Err(ApiError::BadRequest("Either \"factor\" or \"pin_position\" is required".to_string()));
+    }
+    if request.factor.is_some_and(|factor| !factor.is_finite() || factor <= 0.0) {
+        return Err(ApiError::BadRequest("The factor must be positive".to_string()));
+    }
+    if request.pin_position == Some(0) {
+        return Err(ApiError::BadRequest("Positions start at 1".to_string()));
+    }
+    let now = determinism::now();
+    if request.expires_at <= now {
+        return Err(ApiError::BadRequest("The boost has expired already".to_string()));
+    }
+
+    let boost = Boost { identifier: request.identifier, factor: request.factor, pin_position: request.pin_position, expires_at: request.expires_at };
+    let boosts_data = boosts_data.get_ref().clone();
+    let boosts = web::block(move || {
+        let mut boosts = locks::write(&boosts_data, "boosts");
+        boosts.set(boost, now);
+        boosts.active(now)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(BoostsResponse { boosts }))
+}
+
+/// Lists the boosts that haven't expired.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "The active boosts", body = BoostsResponse),
+    )
+)]
+#[get("/boosts")]
+pub async fn get_boosts_handler(boosts_data: web::Data<Arc<RwLock<Boosts>>>) -> HttpResponse {
+    let boosts = locks::read(&boosts_data, "boosts").active(determinism::now());
+    HttpResponse::Ok().json(BoostsResponse { boosts })
+}
@@ -0,0 +1,119 @@
+// src/api/v1/business_metrics.rs
+use std::sync::{Arc, RwLock};
+
+use actix_web::{get, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+
+use crate::algorithms::rotating_counters::{top_entries, Granularity};
+use crate::algorithms::Counters;
+use crate::config::SharedSettings;
+use crate::{locks, stats};
+
+/// Content type of the OpenMetrics text format.
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Escapes a label value: backslashes, double quotes and line feeds.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Appends a gauge family with a single unlabeled sample, left out entirely without a value.
+fn push_timestamp(body: &mut String, name: &str, help: &str, at: Option<DateTime<Utc>>) {
+    let Some(at) = at else {
+        return;
+    };
+    body.push_str(&format!("# TYPE {} gauge\n", name));
+    body.push_str(&format!("# UNIT {} seconds\n", name));
+    body.push_str(&format!("# HELP {} {}\n", name, help));
+    body.push_str(&format!("{} {}\n", name, at.timestamp()));
+}
+
+/// Renders the plays of the current and the previous bucket of every granularity, the
+/// `top_n` most played items of each, and when the counters were last rotated and
+/// incremented, in the OpenMetrics text format.
+fn render(counters: &Counters, last_ingest_at: Option<DateTime<Utc>>, top_n: usize) -> String {
+    let windows: Vec<(String, &_)> = Granularity::ALL
+        .into_iter()
+        .flat_map(|granularity| {
+            let buckets = counters.buckets(granularity).iter().take(2).enumerate();
+            buckets.map(move |(index, bucket)| (granularity.bucket_name(index), bucket))
+        })
+        .collect();
+
+    let mut body = String::new();
+    body.push_str("# TYPE mediathek_business_plays gauge\n");
+    body.push_str("# HELP mediathek_business_plays Plays counted in a window.\n");
+    for (window, bucket) in &windows {
+        let plays: u64 = bucket.iter().map(|entry| *entry.value()).sum();
+        body.push_str(&format!("mediathek_business_plays{{window=\"{}\"}} {}\n", window, plays));
+    }
+    body.push_str("# TYPE mediathek_business_items gauge\n");
+    body.push_str("# HELP mediathek_business_items Distinct items played in a window.\n");
+    for (window, bucket) in &windows {
+        body.push_str(&format!("mediathek_business_items{{window=\"{}\"}} {}\n", window, bucket.len()));
+    }
+    body.push_str("# TYPE mediathek_business_item_plays gauge\n");
+    body.push_str("# HELP mediathek_business_item_plays Plays of the most played items in a window.\n");
+    for (window, bucket) in &windows {
+        for entry in top_entries(bucket, 0, top_n) {
+            body.push_str(&format!("mediathek_business_item_plays{{window=\"{}\",item=\"{}\"}} {}\n", window, escape_label(&entry.id), entry.count));
+        }
+    }
+
+    push_timestamp(
+        &mut body,
+        "mediathek_business_last_rotation_timestamp_seconds",
+        "When the windows were last rotated, in seconds since the Unix epoch.",
+        counters.last_rotation_at,
+    );
+    push_timestamp(
+        &mut body,
+        "mediathek_business_last_ingest_timestamp_seconds",
+        "When plays or lists were last ingested, in seconds since the Unix epoch.",
+        last_ingest_at,
+    );
+    body.push_str("# EOF\n");
+    body
+}
+
+/// Exposes the content KPIs, i.e. the plays and the most played items per window and the
+/// rotation times, as gauges in the OpenMetrics text format, for alerting on them.
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "Metrics in the OpenMetrics text format", body = String, content_type = "application/openmetrics-text"),
+    )
+)]
+#[get("/metrics/business")]
+pub async fn get_business_metrics_handler(
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> impl Responder {
+    let top_n = settings.current().business_metrics.top_n;
+    let body = render(&locks::read(&rotating_counters_data, "rotating_counters"), stats::freshness().last_ingest_at, top_n);
+    HttpResponse::Ok().content_type(OPENMETRICS_CONTENT_TYPE).body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_business_metrics_export_the_top_items_per_window() {
+        let counters = Counters::with_depths(3, 13, 4, 3);
+        counters.increment("ard:a", 3);
+        counters.increment("ard:\"b\"", 5);
+        counters.increment("ard:c", 1);
+
+        let body = render(&counters, None, 2);
+        assert!(body.contains("mediathek_business_plays{window=\"today\"} 9\n"));
+        assert!(body.contains("mediathek_business_items{window=\"this_week\"} 3\n"));
+        assert!(body.contains("mediathek_business_item_plays{window=\"this_hour\",item=\"ard:\\\"b\\\"\"} 5\n"));
+        assert!(body.contains("mediathek_business_item_plays{window=\"this_month\",item=\"ard:a\"} 3\n"));
+        assert!(!body.contains("item=\"ard:c\""));
+        assert!(body.contains("mediathek_business_plays{window=\"yesterday\"} 0\n"));
+        // Never ingested, so left out rather than reported as the epoch
+        assert!(!body.contains("mediathek_business_last_ingest_timestamp_seconds"));
+        assert!(body.ends_with("# EOF\n"));
+    }
+}
@@ -0,0 +1,331 @@
+// src/api/v1/export.rs
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::algorithms::rotating_counters::Granularity;
+use crate::algorithms::Counters;
+use crate::api::error::{ApiError, ErrorResponse};
+use crate::config::SharedSettings;
+use crate::{determinism, locks};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CounterExportQuery {
+    /// "csv" (default) or "parquet"
+    pub format: Option<String>,
+}
+
+/// One row of the export: the count of an identifier in one bucket.
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    identifier: String,
+    /// The bucket's name, e.g. "yesterday"
+    window: String,
+    count: u64,
+    /// Start of the hour/day/week/month the bucket covers
+    window_start: DateTime<Utc>,
+}
+
+/// Reads the rows of bucket `index` of `granularity`, or `None` if there is no such bucket.
+fn rows(counters: &Counters, granularity: Granularity, index: usize, timezone: &Tz) -> Option<Vec<Row>> {
+    let bucket = counters.buckets(granularity).get(index)?;
+    let at = counters.last_rotation_at.unwrap_or_else(determinism::now).with_timezone(timezone);
+    let (window_start, _) = granularity.span_before(&at, index);
+    let window = granularity.bucket_name(index);
+    Some(
+        bucket
+            .iter()
+            .map(|entry| Row { identifier: entry.key().clone(), window: window.clone(), count: *entry.value(), window_start })
+            .collect(),
+    )
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break (RFC 4180).
+fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+const CSV_HEADER: &str = "identifier,window,count,window_start\n";
+
+fn csv_rows(rows: &[Row]) -> String {
+    let mut chunk = String::new();
+    for row in rows {
+        chunk.push_str(&format!(
+            "{},{},{},{}\n",
+            csv_field(&row.identifier),
+            row.window,
+            row.count,
+            row.window_start.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ));
+    }
+    chunk
+}
+
+/// Turns the rows into the bytes of the requested format, bucket by bucket.
+enum Encoder {
+    Csv,
+    #[cfg(feature = "parquet")]
+    Parquet(parquet_export::ParquetEncoder),
+}
+
+impl Encoder {
+    fn new(format: &str) -> Result<Self, ApiError> {
+        match format {
+            "csv" => Ok(Encoder::Csv),
+            #[cfg(feature = "parquet")]
+            "parquet" => parquet_export::ParquetEncoder::new().map(Encoder::Parquet).map_err(ApiError::Internal),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err(ApiError::BadRequest("Parquet exports need a server built with --features parquet".to_string())),
+            format => Err(ApiError::BadRequest(format!("Unknown format '{}', expected csv or parquet", format))),
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Encoder::Csv => "text/csv; charset=utf-8",
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(_) => "application/vnd.apache.parquet",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Encoder::Csv => "csv",
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(_) => "parquet",
+        }
+    }
+
+    fn header(&mut self) -> io::Result<Bytes> {
+        match self {
+            Encoder::Csv => Ok(Bytes::from_static(CSV_HEADER.as_bytes())),
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(encoder) => encoder.take(),
+        }
+    }
+
+    fn rows(&mut self, rows: &[Row]) -> io::Result<Bytes> {
+        match self {
+            Encoder::Csv => Ok(Bytes::from(csv_rows(rows))),
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(encoder) => encoder.write(rows),
+        }
+    }
+
+    fn finish(self) -> io::Result<Bytes> {
+        match self {
+            Encoder::Csv => Ok(Bytes::new()),
+            #[cfg(feature = "parquet")]
+            Encoder::Parquet(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// State of an export in progress: the buckets still to read, one per chunk, so the read
+/// lock is only held for one bucket at a time.
+struct Export {
+    counters: Arc<RwLock<Counters>>,
+    timezone: Tz,
+    buckets: std::vec::IntoIter<(Granularity, usize)>,
+    encoder: Option<Encoder>,
+    started: bool,
+}
+
+impl Export {
+    /// Returns the next chunk, or `None` once the export is complete.
+    fn next(&mut self) -> Option<io::Result<Bytes>> {
+        let encoder = self.encoder.as_mut()?;
+        if !self.started {
+            self.started = true;
+            return Some(encoder.header());
+        }
+        for (granularity, index) in self.buckets.by_ref() {
+            let rows = rows(&locks::read(&self.counters, "rotating_counters"), granularity, index, &self.timezone);
+            match rows {
+                Some(rows) if !rows.is_empty() => return Some(encoder.rows(&rows)),
+                _ => continue,
+            }
+        }
+        self.encoder.take().map(Encoder::finish)
+    }
+}
+
+/// Exports the counts of every identifier in every bucket as a long-format table with the
+/// columns identifier, window (the bucket's name), count and window_start (start of the
+/// period it covers), for loading into a data warehouse. Streamed bucket by bucket, so a
+/// rotation during the export shifts the buckets not exported yet.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    params(CounterExportQuery),
+    responses(
+        (status = 200, description = "The table", content((String = "text/csv"), (Vec<u8> = "application/vnd.apache.parquet"))),
+        (status = 400, description = "Unknown format, or Parquet on a server built without it", body = ErrorResponse),
+    )
+)]
+#[get("/export/counters")]
+pub async fn export_counter_history_handler(
+    query: web::Query<CounterExportQuery>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let encoder = Encoder::new(query.format.as_deref().unwrap_or("csv"))?;
+    let counters = rotating_counters_data.get_ref().clone();
+    let buckets: Vec<(Granularity, usize)> = {
+        let counters_lock = locks::read(&counters, "rotating_counters");
+        Granularity::ALL
+            .into_iter()
+            .flat_map(|granularity| (0..counters_lock.buckets(granularity).len()).map(move |index| (granularity, index)))
+            .collect()
+    };
+
+    let filename = format!("counters-{}.{}", determinism::now().format("%Y%m%dT%H%M%SZ"), encoder.extension());
+    let content_type = encoder.content_type();
+    let export = Export {
+        counters,
+        timezone: settings.current().counters.rotation_timezone,
+        buckets: buckets.into_iter(),
+        encoder: Some(encoder),
+        started: false,
+    };
+    let body = futures_util::stream::unfold(export, |mut export| async move {
+        let chunk = export.next()?;
+        Some((chunk, export))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(body))
+}
+
+/// Parquet, with one row group per bucket.
+#[cfg(feature = "parquet")]
+mod parquet_export {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::web::Bytes;
+    use parquet::basic::{Compression, ZstdLevel};
+    use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+    use parquet::errors::ParquetError;
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+
+    use super::Row;
+    use crate::locks;
+
+    const SCHEMA: &str = "
+        message counters {
+            required binary identifier (STRING);
+            required binary window (STRING);
+            required int64 count;
+            required int64 window_start (TIMESTAMP(MILLIS, true));
+        }
+    ";
+
+    /// The bytes written so far and not sent yet.
+    #[derive(Clone, Default)]
+    struct Pending(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Pending {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            locks::lock(&self.0, "parquet_export").extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn io_error(error: ParquetError) -> io::Error {
+        io::Error::other(error.to_string())
+    }
+
+    pub struct ParquetEncoder {
+        writer: SerializedFileWriter<Pending>,
+        pending: Pending,
+    }
+
+    impl ParquetEncoder {
+        pub fn new() -> Result<Self, String> {
+            let schema = Arc::new(parse_message_type(SCHEMA).map_err(|e| e.to_string())?);
+            let properties = WriterProperties::builder().set_compression(Compression::ZSTD(ZstdLevel::default())).build();
+            let pending = Pending::default();
+            let writer = SerializedFileWriter::new(pending.clone(), schema, Arc::new(properties)).map_err(|e| e.to_string())?;
+            Ok(ParquetEncoder { writer, pending })
+        }
+
+        /// Takes the bytes written so far.
+        pub fn take(&mut self) -> io::Result<Bytes> {
+            Ok(Bytes::from(std::mem::take(&mut *locks::lock(&self.pending.0, "parquet_export"))))
+        }
+
+        pub fn write(&mut self, rows: &[Row]) -> io::Result<Bytes> {
+            let strings = |field: fn(&Row) -> &str| -> Vec<ByteArray> { rows.iter().map(|row| ByteArray::from(field(row))).collect() };
+            let identifiers = strings(|row| row.identifier.as_str());
+            let windows = strings(|row| row.window.as_str());
+            let counts: Vec<i64> = rows.iter().map(|row| i64::try_from(row.count).unwrap_or(i64::MAX)).collect();
+            let starts: Vec<i64> = rows.iter().map(|row| row.window_start.timestamp_millis()).collect();
+
+            let mut row_group = self.writer.next_row_group().map_err(io_error)?;
+            for values in [&identifiers, &windows] {
+                let mut column = row_group.next_column().map_err(io_error)?.ok_or_else(|| io::Error::other("Missing column"))?;
+                column.typed::<ByteArrayType>().write_batch(values, None, None).map_err(io_error)?;
+                column.close().map_err(io_error)?;
+            }
+            for values in [&counts, &starts] {
+                let mut column = row_group.next_column().map_err(io_error)?.ok_or_else(|| io::Error::other("Missing column"))?;
+                column.typed::<Int64Type>().write_batch(values, None, None).map_err(io_error)?;
+                column.close().map_err(io_error)?;
+            }
+            row_group.close().map_err(io_error)?;
+            self.take()
+        }
+
+        /// Writes the footer and returns the rest of the file.
+        pub fn finish(self) -> io::Result<Bytes> {
+            let ParquetEncoder { writer, pending } = self;
+            writer.close().map_err(io_error)?;
+            Ok(Bytes::from(std::mem::take(&mut *locks::lock(&pending.0, "parquet_export"))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_buckets_export_as_csv_rows() {
+        let mut counters = Counters::with_depths(2, 2, 1, 1);
+        counters.last_rotation_at = Some(Utc.with_ymd_and_hms(2026, 3, 2, 10, 30, 0).unwrap());
+        counters.increment("ard:a", 3);
+        counters.increment("ard:\"b\",c", 1);
+        counters.daily[1].insert("zdf:x".to_string(), 7);
+
+        let mut today = rows(&counters, Granularity::Day, 0, &chrono_tz::Europe::Berlin).unwrap();
+        today.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+        assert_eq!(csv_rows(&today), "\"ard:\"\"b\"\",c\",today,1,2026-03-01T23:00:00Z\nard:a,today,3,2026-03-01T23:00:00Z\n");
+        let yesterday = rows(&counters, Granularity::Day, 1, &chrono_tz::UTC).unwrap();
+        assert_eq!(csv_rows(&yesterday), "zdf:x,yesterday,7,2026-03-01T00:00:00Z\n");
+        assert!(rows(&counters, Granularity::Day, 2, &chrono_tz::UTC).is_none());
+    }
+}
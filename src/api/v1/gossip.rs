@@ -0,0 +1,57 @@
+// src/api/v1/gossip.rs
+use std::sync::{Arc, RwLock};
+
+use actix_web::error::JsonPayloadError;
+use actix_web::{post, web, HttpResponse};
+use serde::Serialize;
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::algorithms::gossip::{self, GossipMessage};
+use crate::algorithms::Counters;
+use crate::api::error::{ApiError, ErrorResponse};
+use crate::locks;
+
+/// Result of a gossip round received from a peer.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GossipResponse {
+    pub status: &'static str,
+    /// Entries of the peer that grew since its last round
+    pub merged: usize,
+}
+
+/// Receives a peer's counts of the current buckets (see the "gossip" counter backend) and
+/// adds whatever grew since its last round. Sent by the peers themselves.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    request_body(content = Object, description = "The peer's node name and entries"),
+    responses(
+        (status = 200, description = "Success", body = GossipResponse),
+        (status = 400, description = "Malformed JSON", body = ErrorResponse),
+        (status = 404, description = "Counters are not gossiped by this instance", body = ErrorResponse),
+        (status = 413, description = "Payload too large", body = ErrorResponse),
+    )
+)]
+#[post("/gossip")]
+pub async fn gossip_handler(
+    payload: web::Payload,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> Result<HttpResponse, ApiError> {
+    let body = match payload.to_bytes_limited(super::MAX_MERGE_PAYLOAD_BYTES).await {
+        Ok(body) => body.map_err(|e| ApiError::BadRequest(e.to_string()))?,
+        Err(_) => return Err(ApiError::PayloadTooLarge(format!("Payload exceeds {} bytes", super::MAX_MERGE_PAYLOAD_BYTES))),
+    };
+    let message: GossipMessage = serde_json::from_slice(&body).map_err(JsonPayloadError::Deserialize)?;
+
+    let counters = rotating_counters_data.get_ref().clone();
+    if !locks::read(&counters, "rotating_counters").gossip.is_enabled() {
+        return Err(ApiError::NotFound("Counters are not gossiped by this instance".to_string()));
+    }
+    let node = message.node;
+    let entries = message.entries;
+    let merged = web::block(move || gossip::merge(&mut locks::write(&counters, "rotating_counters"), &entries)).await?;
+    debug!(node, merged, "Merged a gossip round.");
+
+    Ok(HttpResponse::Ok().json(GossipResponse { status: "success", merged }))
+}
@@ -0,0 +1,226 @@
+// src/api/v1/graphql.rs
+use std::sync::{Arc, RwLock};
+
+use actix_web::{route, web};
+use async_graphql::{ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Result, Schema, SimpleObject};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+
+use crate::algorithms::rotating_counters::{count_of, top_entries, CounterTimeSeries, TimeSeriesPoint};
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::api::validation::normalize_identifier;
+use crate::config::{NormalizationSettings, SharedSettings};
+use crate::locks;
+
+/// Deepest nesting of fields a query may have, e.g. neighbors of neighbors of an item.
+const MAX_QUERY_DEPTH: usize = 8;
+/// Largest query complexity: every field counts 1, lists of neighbors or items as often
+/// as their limit allows. Keeps a single query from walking the whole graph.
+const MAX_QUERY_COMPLEXITY: usize = 10_000;
+
+/// The schema of POST /graphql. Read-only; writes go through the REST endpoints.
+pub type MediathekSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Builds the schema with its depth and complexity limits.
+pub fn schema() -> MediathekSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// An item by its identifier. Unknown identifiers resolve to an item without
+    /// neighbors and counts, like the REST endpoints.
+    async fn item(&self, ctx: &Context<'_>, id: String) -> Item {
+        Item::normalized(ctx, id)
+    }
+
+    /// Several items at once, in the given order.
+    #[graphql(complexity = "ids.len() * child_complexity")]
+    async fn items(&self, ctx: &Context<'_>, ids: Vec<String>) -> Vec<Item> {
+        ids.into_iter().map(|id| Item::normalized(ctx, id)).collect()
+    }
+
+    /// The most counted items of a counter window ("today", "last_24h", ...), highest first.
+    #[graphql(complexity = "limit * child_complexity")]
+    async fn top_items(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = "today")] window: String,
+        #[graphql(default = 10)] limit: usize,
+    ) -> Result<Vec<RankedItem>> {
+        let counters_lock = locks::read(ctx.data::<Arc<RwLock<Counters>>>()?, "rotating_counters");
+        let bucket = counters_lock.window(&window).ok_or_else(|| format!("Unknown window '{}'", window))?;
+        Ok(top_entries(&bucket, 0, limit)
+            .into_iter()
+            .map(|entry| RankedItem { count: entry.count, item: Item { id: entry.id } })
+            .collect())
+    }
+}
+
+/// An item, i.e. an identifier seen in lists or counters.
+pub struct Item {
+    id: String,
+}
+
+impl Item {
+    /// The item of an identifier given in a query, normalized like in the REST endpoints.
+    fn normalized(ctx: &Context<'_>, id: String) -> Item {
+        match ctx.data_opt::<NormalizationSettings>() {
+            Some(settings) => Item { id: normalize_identifier(&id, settings).into_owned() },
+            None => Item { id },
+        }
+    }
+}
+
+#[Object]
+impl Item {
+    async fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Count of the item in a counter window ("today", "last_24h", ...).
+    async fn count(&self, ctx: &Context<'_>, #[graphql(default = "today")] window: String) -> Result<u64> {
+        let counters_lock = locks::read(ctx.data::<Arc<RwLock<Counters>>>()?, "rotating_counters");
+        let bucket = counters_lock.window(&window).ok_or_else(|| format!("Unknown window '{}'", window))?;
+        Ok(count_of(&bucket, &self.id))
+    }
+
+    /// Items co-occurring with this one in lists, most frequent first.
+    #[graphql(complexity = "limit * child_complexity")]
+    async fn neighbors(&self, ctx: &Context<'_>, #[graphql(default = 10)] limit: usize) -> Result<Vec<Neighbor>> {
+        let co_occurrences =
+            locks::read(ctx.data::<Arc<RwLock<CoOccurrenceCounter>>>()?, "co_occurrence").cached_metrics_for_identifier(&self.id);
+        let mut neighbors: Vec<Neighbor> =
+            co_occurrences.into_iter().map(|(id, count)| Neighbor { count, item: Item { id } }).collect();
+        neighbors.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.item.id.cmp(&b.item.id)));
+        neighbors.truncate(limit);
+        Ok(neighbors)
+    }
+
+    /// Counts of the item across all buckets, oldest first.
+    async fn time_series(&self, ctx: &Context<'_>) -> Result<TimeSeries> {
+        let series = locks::read(ctx.data::<Arc<RwLock<Counters>>>()?, "rotating_counters").time_series(&self.id);
+        Ok(TimeSeries::from(series))
+    }
+}
+
+/// An item co-occurring with another one.
+#[derive(SimpleObject)]
+pub struct Neighbor {
+    /// Number of lists containing both items
+    count: u64,
+    item: Item,
+}
+
+/// An item with its count in a counter window.
+#[derive(SimpleObject)]
+pub struct RankedItem {
+    count: u64,
+    item: Item,
+}
+
+/// The counts of an item per bucket, like GET /counters/{id}.
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct TimeSeries {
+    hourly: Vec<Point>,
+    daily: Vec<Point>,
+    weekly: Vec<Point>,
+    monthly: Vec<Point>,
+}
+
+#[ComplexObject]
+impl TimeSeries {
+    /// Sum of the daily buckets.
+    async fn total(&self) -> u64 {
+        self.daily.iter().map(|point| point.count).sum()
+    }
+}
+
+/// The count of an item in one bucket.
+#[derive(SimpleObject)]
+pub struct Point {
+    bucket: String,
+    count: u64,
+}
+
+impl From<CounterTimeSeries> for TimeSeries {
+    fn from(series: CounterTimeSeries) -> Self {
+        let points = |points: Vec<TimeSeriesPoint>| {
+            points.into_iter().map(|point| Point { bucket: point.bucket, count: point.count }).collect()
+        };
+        TimeSeries {
+            hourly: points(series.hourly),
+            daily: points(series.daily),
+            weekly: points(series.weekly),
+            monthly: points(series.monthly),
+        }
+    }
+}
+
+/// Answers GraphQL queries (as POST body or GET query string) against the in-memory
+/// state, so a page can fetch recommendations and popularity in one round trip.
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(content = Object, description = "A GraphQL request: `query`, optional `variables` and `operationName`"),
+    responses(
+        (status = 200, description = "The GraphQL response, with `data` and/or `errors`", body = Object),
+    )
+)]
+#[route("/graphql", method = "GET", method = "POST")]
+pub async fn graphql_handler(
+    request: GraphQLRequest,
+    schema: web::Data<MediathekSchema>,
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> GraphQLResponse {
+    let request = request
+        .into_inner()
+        .data(counter_data.get_ref().clone())
+        .data(rotating_counters_data.get_ref().clone())
+        .data(settings.current().validation.normalization.clone());
+    schema.execute(request).await.into()
+}
+
+/// Registers the GraphQL route and its schema.
+pub fn config_routes(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::Data::new(schema())).service(graphql_handler);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_items_resolve_neighbors_and_counts() {
+        let mut co_occurrence = CoOccurrenceCounter::new();
+        co_occurrence.process_list(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        co_occurrence.process_list(&["a".to_string(), "b".to_string()]);
+        let counters = Counters::with_depths(3, 3, 1, 1);
+        counters.increment("b", 5);
+
+        let query = r#"{
+            item(id: "a") { neighbors(limit: 1) { count item { id count timeSeries { total } } } }
+            topItems(limit: 5) { count item { id } }
+        }"#;
+        let request = async_graphql::Request::new(query)
+            .data(Arc::new(RwLock::new(co_occurrence)))
+            .data(Arc::new(RwLock::new(counters)));
+        let response = schema().execute(request).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({
+                "item": {"neighbors": [{"count": 2, "item": {"id": "b", "count": 5, "timeSeries": {"total": 5}}}]},
+                "topItems": [{"count": 5, "item": {"id": "b"}}],
+            })
+        );
+    }
+}
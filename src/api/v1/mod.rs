@@ -0,0 +1,2977 @@
+// src/api/v1/mod.rs
+mod boosts;
+mod business_metrics;
+mod export;
+mod gossip;
+mod graphql;
+mod openapi;
+mod page;
+mod replication;
+mod sse;
+mod ws;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use actix_web::{middleware, web, HttpMessage, HttpRequest, HttpResponse, Responder, delete, get, post};
+use actix_web::error::JsonPayloadError;
+use futures_util::StreamExt;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType, ETag, IfNoneMatch};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+// Import the CoOccurrenceCounter from our algorithms module
+use crate::algorithms::CoOccurrenceCounter;
+use crate::algorithms::co_occurrence::{ConditionalNeighbor, ListDryRun};
+use crate::algorithms::identifier_filter::IdentifierFilter;
+use crate::algorithms::Counters;
+use crate::algorithms::rotating_counters::{rank_in, top_entries, Bucket, Granularity, CountEntry, CounterRange, CounterRank, CounterTimeSeries, RollingCounts, Sparkline, WeekdayAverage};
+use crate::algorithms::TransitionCounter;
+use crate::algorithms::trending::{movers, rising_stars, trending, Movers, RisingStar, TrendingBasis, TrendingItem};
+use crate::algorithms::transitions::NextItem;
+use crate::algorithms::{AssociationRule, RuleSet};
+use crate::algorithms::quality::{QualityMonitor, QualityReport};
+use crate::algorithms::holdout::{EvaluationReport, Holdout};
+use crate::algorithms::ItemEmbeddings;
+use crate::algorithms::embeddings::SimilarItem;
+use crate::algorithms::minute_counters::MinutePoint;
+use crate::algorithms::namespace_rollups::NamespacePlays;
+use crate::algorithms::forecast::{self, Forecast};
+use crate::algorithms::scoring::{Candidate, NamespaceFilter, Pipeline, PopularityRange};
+use crate::algorithms::shadow::{ShadowPipeline, ShadowReport, ShadowScoring};
+use crate::algorithms::tombstones::{Tombstone, Tombstones};
+use crate::algorithms::FactorizationState;
+use crate::algorithms::AlertLog;
+use crate::algorithms::spikes::SpikeAlert;
+use crate::algorithms::{backup, co_occurrence, rotating_counters, snapshot};
+use crate::algorithms::backup::BackupMetadata;
+use crate::algorithms::snapshot::SnapshotVersion;
+use crate::config::{Settings, SharedSettings, StorageSettings};
+use crate::determinism;
+use crate::ingest::import::{self, Import, ImportFormat, ImportSummary};
+use crate::ingest::pipeline::{Admission, PlayVerdict};
+use crate::ingest::rules::{self, RuleTarget};
+use crate::ingest::Ingestor;
+use crate::locks;
+use crate::server::AppState;
+use crate::stats::{self, CardinalitySummary, CompactionSummary, LatencySummary, SnapshotRecovery, SnapshotSummary};
+use crate::api::audit::{AuditEntry, AuditLog};
+use crate::api::deadline::Deadline;
+use crate::api::quota::{KeyUsage, UsageMeter};
+use crate::api::auth;
+use crate::api::encoding::{self, Body, Format};
+use crate::api::etag;
+use crate::api::idempotency::IDEMPOTENT_REPLAYED_HEADER;
+use crate::api::ingest_queue::{IngestQueue, QueuedList};
+use crate::api::ingest_stats::{ClientIngestStats, IngestClient, IngestStats};
+use crate::api::overload::read_or_unavailable;
+use crate::api::tenants::RequestTenant;
+use crate::api::error::{ApiError, ErrorResponse};
+use crate::api::validation::{normalize_list, validate_list, IdentifierPath};
+use self::openapi::StatusResponse;
+
+// --- API Data Models for Co-Occurence ---
+
+/// Struct for the POST /add_list request body
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AddListRequest {
+    pub identifiers: Vec<String>,
+    /// When each identifier was watched, one per identifier. If given, only the pairs
+    /// watched within `MEDIATHEK_LISTS_CO_VISITATION_WINDOW_SECS` of each other are counted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<Vec<chrono::DateTime<chrono::Utc>>>,
+    /// Context the list comes from, e.g. "playlist" or "search", weighted as configured
+    /// in `MEDIATHEK_SOURCE_WEIGHTS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// A line of a POST /lists/stream body, borrowing the identifiers from the body where
+/// they contain no escapes, so only identifiers new to the model are allocated.
+#[derive(Debug, Deserialize)]
+struct BorrowedListRequest<'a> {
+    #[serde(borrow)]
+    identifiers: Vec<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    source: Option<Cow<'a, str>>,
+}
+
+/// Struct for the POST /lists/stream response
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct StreamIngestResponse {
+    pub status: &'static str,
+    /// Number of lines processed as lists
+    pub processed: usize,
+    /// Number of lines skipped because they are malformed or violate the identifier limits
+    pub rejected: usize,
+    /// Number of lines skipped because the same list was submitted shortly before
+    pub duplicates: usize,
+    /// The first rejected lines with the reason
+    pub errors: Vec<LineError>,
+}
+
+/// Struct for the POST /lists/dry_run response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ListDryRunResponse {
+    /// The identifiers as they would be counted: normalized, rewritten by the ingest rules,
+    /// and without the ones dropped as deleted, by the rules or routed to a tenant
+    pub identifiers: Vec<String>,
+    /// Identifiers that would be left out, as they were deleted or by the ingest rules
+    pub dropped: Vec<String>,
+    /// Identifiers the ingest rules would count for another tenant, by tenant
+    pub routed: BTreeMap<String, Vec<String>>,
+    /// Every reason POST /lists would reject the list for; empty if it would be accepted
+    pub violations: Vec<String>,
+    /// Whether the list would be skipped as submitted shortly before
+    pub duplicate: bool,
+    /// Whether the list would be held out of the counts for the evaluation
+    pub held_out: bool,
+    /// Weight of the list's source, which its pairs are counted with
+    pub weight: u64,
+    /// What counting the list would change, reported even if it wouldn't be counted
+    #[serde(flatten)]
+    pub changes: ListDryRun,
+}
+
+/// A rejected line of a POST /lists/stream or POST /admin/import body
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LineError {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+/// Pages through the co-occurring items of GET /lists/{identifier}, which are ordered by
+/// count (descending, ties by identifier). Without either, all items are returned.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CoOccurrencePageQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// Struct for the /metrics/{identifier} response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CoOccurrenceMetricsResponse { // Renamed for clarity
+    pub target_identifier: String,
+    /// All co-occurring items, or the requested page of them
+    pub co_occurrences: HashMap<String, u64>,
+    /// Number of co-occurring items, over all pages
+    #[serde(default)]
+    pub total: usize,
+    /// Most similar items by latent factors, only present if factorization is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub factorization_neighbors: Option<HashMap<String, f64>>,
+}
+
+/// Struct for the POST /lists/metrics request body
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchMetricsRequest {
+    pub identifiers: Vec<String>,
+    /// Neighbors returned per identifier, the most frequent first (default 10)
+    pub limit: Option<usize>,
+}
+
+/// Struct for the POST /lists/metrics response
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct BatchMetricsResponse {
+    /// The top co-occurring items of every known identifier
+    pub metrics: HashMap<String, HashMap<String, u64>>,
+    /// Requested identifiers the model doesn't know
+    pub unknown: Vec<String>,
+}
+
+/// Struct for the GET /lists/{identifier}/conditional response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConditionalResponse {
+    pub target_identifier: String,
+    /// Number of lists the target was in, the denominator of the probabilities
+    pub occurrences: u64,
+    /// Co-occurring items by P(item | target), or the requested page of them
+    pub neighbors: Vec<ConditionalNeighbor>,
+    /// Number of co-occurring items, over all pages
+    pub total: usize,
+}
+
+// --- API Data Models for Rotating Counters ---
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IncrementCounterRequest {
+    pub id: String,
+    /// How much to add, e.g. for batch-imported plays or to weight full views higher
+    /// than previews. Defaults to 1.
+    #[serde(alias = "weight")]
+    pub count: Option<u64>,
+}
+
+/// Struct for the POST /counters/batch response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchIncrementResponse {
+    pub status: &'static str,
+    /// Number of increments that changed the counters, those of the tenants the ingest rules
+    /// route to included (entries with a count of 0 are skipped)
+    pub applied: usize,
+}
+
+/// Struct for the GET /counters response: one top-level field per bucket
+/// ("this_hour", ..., "today", ..., "this_week", ..., "this_month", ...), in rotation order,
+/// followed by the rolling windows ("last_24h", ..., "last_7d", ...) if enough buckets are kept.
+#[derive(Debug)]
+pub struct DailyCountersResponse {
+    pub buckets: Vec<(String, Bucket)>,
+    /// Display labels by bucket name, if requested; serialized as one more entry, "labels"
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Serialize for DailyCountersResponse {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.buckets.len() + usize::from(!self.labels.is_empty())))?;
+        for (name, bucket) in &self.buckets {
+            map.serialize_entry(name, bucket)?;
+        }
+        if !self.labels.is_empty() {
+            map.serialize_entry("labels", &self.labels)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SparklinesQuery {
+    /// Comma-separated identifiers, e.g. "ard:a,zdf:b"
+    pub ids: String,
+}
+
+/// Struct for the GET /counters/sparklines response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SparklinesResponse {
+    /// Hourly and daily counts by identifier, oldest first
+    pub sparklines: HashMap<String, Sparkline>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CountersQuery {
+    /// Only return this bucket ("today", "last_hour", "last_24h", ...) as a ranked list
+    pub window: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// Language of display labels to add for the buckets, e.g. "de" or "en"
+    pub labels: Option<String>,
+}
+
+/// Struct for the GET /counters?window=... response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CounterWindowResponse {
+    pub window: String,
+    /// Number of identifiers in the window, regardless of limit/offset
+    pub total: usize,
+    pub items: Vec<CountEntry>,
+    /// Display label of the window by its name, if requested with `labels`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Struct for the GET /counters/{id} response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CounterTimeSeriesResponse {
+    pub id: String,
+    #[serde(flatten)]
+    pub series: CounterTimeSeries,
+    /// The counts in the rolling windows ("last_24h", "last_7d", ...) that are available
+    #[serde(flatten)]
+    pub rolling: RollingCounts,
+    /// The count between `from` and `to`, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<CounterRange>,
+    /// Display labels of the buckets and rolling windows by name, if requested with `labels`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CounterRangeQuery {
+    /// Start of a time range to sum up the counts of, e.g. "2025-01-30T12:00:00Z"
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// End of the range, default now
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    /// Language of display labels to add for the buckets, e.g. "de" or "en"
+    pub labels: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CounterRankQuery {
+    /// The bucket to rank in ("today", "last_hour", "last_24h", ...), defaults to "today"
+    pub window: Option<String>,
+    /// Language of a display label to add for the window, e.g. "de" or "en"
+    pub labels: Option<String>,
+}
+
+/// Struct for the GET /counters/{id}/rank response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CounterRankResponse {
+    pub id: String,
+    pub window: String,
+    #[serde(flatten)]
+    pub rank: CounterRank,
+    /// Display label of the window by its name, if requested with `labels`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// When one subsystem first and last saw an identifier
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SeenTimes {
+    /// Unknown for identifiers counted before it was tracked
+    pub first_seen: Option<chrono::DateTime<chrono::Utc>>,
+    /// Unknown for counter identifiers not played since a restart
+    pub last_seen: Option<chrono::DateTime<chrono::Utc>>,
+    /// When the identifier is evicted unless seen again; only with an eviction TTL
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Struct for the GET /items/{identifier}/lifecycle response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ItemLifecycleResponse {
+    pub identifier: String,
+    /// Absent if the identifier was never in a list
+    pub co_occurrence: Option<SeenTimes>,
+    /// Absent if the identifier was never played
+    pub counters: Option<SeenTimes>,
+}
+
+/// Struct for the GET /counters/{id}/minutes response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MinuteSeriesResponse {
+    pub id: String,
+    /// Count per minute, oldest first, ending with the current minute
+    pub minutes: Vec<MinutePoint>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ForecastQuery {
+    /// Number of days to predict, today included, 1 to 3 (default 3)
+    pub days: Option<usize>,
+}
+
+/// Struct for the GET /counters/{id}/forecast response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ForecastResponse {
+    pub id: String,
+    #[serde(flatten)]
+    pub forecast: Forecast,
+}
+
+/// Struct for the GET /counters/{id}/seasonality response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SeasonalityResponse {
+    pub id: String,
+    /// Average count per weekday over all completed days, Monday first
+    pub weekdays: Vec<WeekdayAverage>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TrendingQuery {
+    /// "hour" (this hour vs. last hour), "day" (today vs. the trailing week, default) or
+    /// "decayed" (by decayed popularity, without growth)
+    pub basis: Option<TrendingBasis>,
+    /// Minimum count in the current window for an item to be considered
+    pub min_count: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MoversQuery {
+    /// The window to compare ("today", "this_hour", "last_7d", ...), defaults to "today"
+    pub current: Option<String>,
+    /// The window to compare with, defaults to "yesterday"
+    pub previous: Option<String>,
+    /// Minimum count in either window for an item to be considered
+    pub min_count: Option<u64>,
+    /// Number of items per list
+    pub limit: Option<usize>,
+}
+
+/// Struct for the GET /counters/movers response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MoversResponse {
+    pub current: String,
+    pub previous: String,
+    #[serde(flatten)]
+    pub movers: Movers,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NamespacesQuery {
+    /// Only this bucket ("today", "this_hour", ...), defaults to all of them
+    pub window: Option<String>,
+}
+
+/// The plays per namespace in one bucket.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NamespaceWindow {
+    pub window: String,
+    /// All plays in the bucket, including those of identifiers without a namespace
+    pub total: u64,
+    /// Most played namespace first
+    pub namespaces: Vec<NamespacePlays>,
+}
+
+/// Struct for the GET /counters/namespaces response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NamespacesResponse {
+    pub windows: Vec<NamespaceWindow>,
+}
+
+/// Struct for the GET /popularity/{id} response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PopularityResponse {
+    pub id: String,
+    pub score: f64,
+    pub half_life_secs: u64,
+}
+
+/// Struct for the GET /trending response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrendingResponse {
+    pub items: Vec<TrendingItem>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RisingStarsQuery {
+    /// Only items first seen within this many hours are considered
+    pub max_age_hours: Option<u32>,
+    pub min_count: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+/// Struct for the GET /trending/new response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RisingStarsResponse {
+    pub items: Vec<RisingStar>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AlertsQuery {
+    pub limit: Option<usize>,
+}
+
+/// Struct for the GET /alerts response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AlertsResponse {
+    /// Newest first
+    pub alerts: Vec<SpikeAlert>,
+}
+
+/// Longest line accepted by POST /lists/stream
+const MAX_STREAM_LINE_BYTES: usize = 1024 * 1024;
+/// Number of rejected lines POST /lists/stream reports in detail
+const MAX_REPORTED_LINE_ERRORS: usize = 10;
+/// Largest counter state accepted by POST /admin/counters/merge
+const MAX_MERGE_PAYLOAD_BYTES: usize = 256 * 1024 * 1024;
+/// Largest archive accepted by POST /admin/restore
+const MAX_RESTORE_PAYLOAD_BYTES: usize = 1024 * 1024 * 1024;
+/// Header carrying the format version of backups
+const BACKUP_FORMAT_VERSION_HEADER: &str = "x-backup-format-version";
+/// Header carrying the snapshot ID shared by both halves of a backup
+const SNAPSHOT_ID_HEADER: &str = "x-snapshot-id";
+/// Number of alerts returned by GET /alerts if no limit is given
+const DEFAULT_ALERTS_LIMIT: usize = 100;
+/// Number of entries returned by GET /admin/audit if no limit is given
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+/// Maximum age of items on GET /trending/new if none is given
+const DEFAULT_RISING_STARS_MAX_AGE_HOURS: u32 = 24;
+/// Number of items returned by GET /trending if no limit is given
+const DEFAULT_TRENDING_LIMIT: usize = 20;
+/// Minimum current count for GET /trending if none is given, filters out noise
+const DEFAULT_TRENDING_MIN_COUNT: u64 = 3;
+/// Most days GET /counters/{id}/forecast predicts, beyond which a trend means little
+const MAX_FORECAST_DAYS: usize = 3;
+
+// --- API Data Models for Transitions ---
+
+/// Struct for the POST /sequences request body. The order of `identifiers` matters.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AddSequenceRequest {
+    pub identifiers: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NextItemsQuery {
+    pub limit: Option<usize>,
+}
+
+/// Struct for the /next/{identifier} response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NextItemsResponse {
+    pub target_identifier: String,
+    pub next_items: Vec<NextItem>,
+}
+
+/// Number of next items returned by GET /next/{identifier} if no limit is given
+const DEFAULT_NEXT_ITEMS_LIMIT: usize = 10;
+
+// --- API Data Models for Association Rules ---
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RulesQuery {
+    /// Only return rules whose antecedent contains this identifier
+    pub identifier: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Struct for the GET /rules response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RulesResponse {
+    pub mined_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub list_count: usize,
+    pub rules: Vec<AssociationRule>,
+}
+
+/// Struct for the POST /recommendations request body
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BasketRecommendationRequest {
+    /// The items already in the basket (e.g. the user's current session)
+    pub identifiers: Vec<String>,
+    pub limit: Option<usize>,
+    /// Items not to recommend, e.g. those the user has watched already. Left out before
+    /// the limit applies, so the slots fill up with others.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RecommendationsQuery {
+    /// Adds normalized scores and what they are based on to every recommendation
+    pub explain: Option<bool>,
+    /// Scoring pipeline of an experiment variant to rank with (see `MEDIATHEK_SCORING_PIPELINES`)
+    pub variant: Option<String>,
+    /// Only recommends items of other namespaces (the prefix before ':', e.g. the
+    /// broadcaster) than the seeds', filling up with the most played ones of today
+    pub cross_namespace: Option<bool>,
+    /// Fills the slots the direct co-occurrences leave with the neighbors of the seeds'
+    /// strongest neighbors, for seeds with few neighbors (see `MEDIATHEK_TWO_HOP_DECAY`)
+    pub two_hop: Option<bool>,
+    /// Leaves out items played fewer times today, e.g. barely watched ones
+    pub min_popularity: Option<u64>,
+    /// Leaves out items played more times today, e.g. mega-hits on a discovery rail
+    pub max_popularity: Option<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BasketRecommendation {
+    pub identifier: String,
+    pub score: f64,
+    /// Which model produced the recommendation: "rules", "factorization" or "co_occurrence";
+    /// "two_hop" for the neighbors of neighbors of `two_hop=true`, "editorial" for items
+    /// pinned by an editor that no model proposed, "popularity" for the fallback of
+    /// `cross_namespace=true`
+    pub source: &'static str,
+    /// `score` scaled to 0–1 within its source, only present with `explain=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized_score: Option<f64>,
+    /// Only present with `explain=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Explanation>,
+}
+
+/// What a recommendation's score is based on.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Explanation {
+    /// What `score` measures: "confidence" (rules), "dot_product" (factorization),
+    /// "pair_count" (co_occurrence), "decayed_pair_count" (two_hop), "plays_today"
+    /// (popularity) or "pin" (editorial, without a score)
+    pub metric: &'static str,
+    /// Score the recommendations were ranked by, after all scoring stages
+    pub ranking_score: f64,
+    /// Number of lists the item shared with the basket's items, summed over the basket
+    pub pair_count: u64,
+    /// Plays of the item today
+    pub popularity: u64,
+    /// Boosts and penalties applied to the score, in order
+    pub adjustments: Vec<String>,
+}
+
+/// Struct for the POST /recommendations response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BasketRecommendationsResponse {
+    pub recommendations: Vec<BasketRecommendation>,
+}
+
+// --- API Data Models for Factorization ---
+
+/// Struct for the POST /admin/purge request body
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PurgeRequest {
+    /// Identifiers starting with this are removed, e.g. "zdf:"
+    pub prefix: String,
+}
+
+/// Struct for the POST /admin/purge response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PurgeResponse {
+    pub prefix: String,
+    /// Identifiers removed from the co-occurrences
+    pub co_occurrence_identifiers: usize,
+    /// Pairs removed from the co-occurrences
+    pub co_occurrence_pairs: usize,
+    /// Identifiers removed from the counter buckets
+    pub counter_identifiers: usize,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    /// Only operations at or after this time, e.g. "2025-01-30T12:00:00Z"
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Maximum number of operations, default 100
+    pub limit: Option<usize>,
+}
+
+/// Struct for the GET /admin/audit response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditResponse {
+    pub entries: Vec<AuditEntry>,
+}
+
+/// A snapshot written by POST /admin/flush
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FlushedFile {
+    pub path: String,
+    /// Size as written
+    pub bytes: u64,
+    pub duration_ms: f64,
+}
+
+/// Struct for the POST /admin/flush response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FlushResponse {
+    /// The counters' snapshot, then the co-occurrences' unless a database records every list
+    pub files: Vec<FlushedFile>,
+}
+
+/// Struct for the GET /admin/usage response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UsageResponse {
+    pub keys: Vec<KeyUsage>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IngestStatsQuery {
+    /// Bucket like "this_hour", "hour_minus_5", "today" or "day_minus_3", or "last_24h" or
+    /// "last_7d"; default "today"
+    pub window: Option<String>,
+}
+
+/// Struct for the GET /admin/ingest-stats response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IngestStatsResponse {
+    pub window: String,
+    /// Per tenant, API key and source, ordered by them
+    pub clients: Vec<ClientIngestStats>,
+}
+
+/// Struct for the GET /admin/tombstones response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TombstonesResponse {
+    pub tombstones: Vec<Tombstone>,
+}
+
+/// Struct for the POST /admin/train response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrainResponse {
+    pub status: &'static str,
+    /// Version of the model currently serving, if any has been trained yet
+    pub current_version: Option<u64>,
+    pub current_trained_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// --- API Data Models for Runtime Stats ---
+
+/// Struct for the GET /admin/stats response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatsResponse {
+    /// Request count and latency per route, keyed by "METHOD /route/{pattern}"
+    pub routes: BTreeMap<String, LatencySummary>,
+    /// How long acquiring each shared lock took, keyed by lock name
+    pub lock_waits: BTreeMap<String, LatencySummary>,
+    /// Distinct identifiers known to the co-occurrence model
+    pub identifier_count: usize,
+    /// Distinct pairs stored by the co-occurrence model
+    pub pair_count: usize,
+    /// Lists and identifiers turned away by `MEDIATHEK_CARDINALITY_MAX_IDENTIFIERS`
+    pub cardinality: CardinalitySummary,
+    /// `None` if the counters haven't been persisted since the server started
+    pub seconds_since_last_persistence: Option<i64>,
+    /// The last snapshot written since the server started, keyed by file
+    pub snapshots: BTreeMap<String, SnapshotSummary>,
+    /// Snapshots found corrupt when loading them on startup, keyed by file
+    pub recoveries: BTreeMap<String, SnapshotRecovery>,
+    /// The last memory compaction since the server started, keyed by tenant ("default"
+    /// for the state of requests without one)
+    pub compactions: BTreeMap<String, CompactionSummary>,
+}
+
+/// Struct for the GET /admin/snapshots response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SnapshotVersionsResponse {
+    /// Retained versions per snapshot file, newest first
+    pub files: BTreeMap<String, Vec<SnapshotVersion>>,
+}
+
+/// Struct for the GET /admin/memory response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MemoryResponse {
+    /// Estimated bytes per component: "identifier_to_id", "co_occurrence_counts",
+    /// "counters_hourly", "counters_daily", "counters_weekly", "counters_monthly"
+    /// and "counters_first_seen"
+    pub components: BTreeMap<String, usize>,
+    pub total_bytes: usize,
+}
+
+/// Struct for the POST /admin/clock request body. Either field moves the clock.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ClockRequest {
+    /// Time to move the clock to
+    pub at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Seconds to move the clock forward by
+    pub advance_secs: Option<u64>,
+}
+
+/// Struct for the POST /admin/clock response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ClockResponse {
+    pub now: chrono::DateTime<chrono::Utc>,
+}
+
+/// Number of latent-factor neighbors added to GET /lists/{identifier} when factorization is enabled
+const FACTORIZATION_NEIGHBORS_LIMIT: usize = 20;
+/// Number of neighbors per identifier returned by POST /lists/metrics if no limit is given
+pub const DEFAULT_BATCH_METRICS_LIMIT: usize = 10;
+/// Number of rules returned by GET /rules if no limit is given
+const DEFAULT_RULES_LIMIT: usize = 100;
+/// Number of recommendations returned by POST /recommendations if no limit is given
+const DEFAULT_RECOMMENDATIONS_LIMIT: usize = 10;
+
+/// Candidates per requested recommendation the scoring stages rank.
+const CANDIDATE_POOL_FACTOR: usize = 3;
+
+// --- API Data Models for Embeddings ---
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SimilarItemsQuery {
+    pub limit: Option<usize>,
+}
+
+/// Struct for the /embeddings/{identifier}/similar response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SimilarItemsResponse {
+    pub target_identifier: String,
+    pub trained_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub similar: Vec<SimilarItem>,
+}
+
+/// Number of neighbors returned by GET /embeddings/{identifier}/similar if no limit is given
+const DEFAULT_SIMILAR_ITEMS_LIMIT: usize = 10;
+
+// --- API Handlers (for Co-Occurence) ---
+
+#[utoipa::path(
+    tag = "co_occurrence",
+    request_body(content((AddListRequest = "application/json"), (AddListRequest = "application/msgpack"), (AddListRequest = "application/cbor"))),
+    responses(
+        (status = 200, description = "Success, a retry acknowledged without processing it again, or status \"duplicate\" if the same list was submitted shortly before and skipped", content((StatusResponse = "application/json"), (StatusResponse = "application/msgpack"), (StatusResponse = "application/cbor"))),
+        (status = 202, description = "Status \"queued\": accepted and counted shortly after, if the ingest queue is enabled", content((StatusResponse = "application/json"), (StatusResponse = "application/msgpack"), (StatusResponse = "application/cbor"))),
+        (status = 400, description = "Invalid Idempotency-Key", body = ErrorResponse),
+        (status = 415, description = "Unsupported content type", body = ErrorResponse),
+        (status = 422, description = "The body doesn't match the expected shape, violates the identifier limits, has not one timestamp per identifier or would exceed the limit of distinct identifiers", body = ErrorResponse),
+        (status = 429, description = "The ingest queue is full", body = ErrorResponse),
+    )
+)]
+#[post("/lists")]
+pub async fn add_list_handler(
+    req: HttpRequest,
+    mut req_body: Body<AddListRequest>,
+    format: Format,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = state.settings.current();
+    // Filtered along with their identifiers
+    let mut timed = match req_body.timestamps.take() {
+        Some(timestamps) if timestamps.len() != req_body.identifiers.len() => {
+            return Err(ApiError::Unprocessable(format!(
+                "Expected one timestamp per identifier, got {} for {}",
+                timestamps.len(),
+                req_body.identifiers.len()
+            )));
+        }
+        timestamps => TimedIdentifier::zip(std::mem::take(&mut req_body.identifiers), timestamps),
+    };
+    // The parts of the list the ingest rules route to other tenants
+    let routed = Admission::new(&settings, &state.tombstones, Some(&state.co_occurrence)).list(&mut timed)?;
+    let (identifiers, times) = TimedIdentifier::unzip(timed);
+    req_body.identifiers = identifiers;
+    // Before the list is marked as seen, so a retry after a 429 isn't taken for a duplicate
+    let queue_slot = state.ingest_queue.reserve(settings.overload.retry_after_secs)?;
+    if !state.idempotency_keys.claim(&req, "POST /lists")? {
+        return encoding::respond(format, HttpResponse::Ok().insert_header((IDEMPOTENT_REPLAYED_HEADER, "true")), &HashMap::from([("status", "success")]));
+    }
+    if state.session_dedup.is_duplicate(&req_body.identifiers, determinism::now()) {
+        return encoding::respond(format, &mut HttpResponse::Ok(), &HashMap::from([("status", "duplicate")]));
+    }
+    state.ingest_stats.record_list(IngestClient::of(&req.extensions(), req_body.source.as_deref()), &req_body.identifiers, determinism::now());
+    let weight = settings.source_weights.of(req_body.source.as_deref());
+    routed.count(&state.tenants, weight);
+    // Accepted like any other list, but only evaluated against
+    if state.holdout.hold_out(&req_body.identifiers) {
+        return encoding::respond(format, &mut HttpResponse::Ok(), &HashMap::from([("status", "success")]));
+    }
+    if let Some(queue_slot) = queue_slot {
+        queue_slot.send(QueuedList {
+            identifiers: std::mem::take(&mut req_body.identifiers),
+            times,
+            weight,
+            co_occurrence: Arc::clone(&state.co_occurrence),
+            recent_lists: Arc::clone(&state.recent_lists),
+        });
+        return encoding::respond(format, &mut HttpResponse::Accepted(), &HashMap::from([("status", "queued")]));
+    }
+    let mut counter_lock = locks::write(&state.co_occurrence, "co_occurrence");
+    counter_lock.process_timed_list(&req_body.identifiers, times.as_deref(), weight);
+    drop(counter_lock);
+    // Keep the raw list around for offline mining passes
+    locks::lock(&state.recent_lists, "recent_lists").push(&req_body.identifiers);
+    encoding::respond(format, &mut HttpResponse::Ok(), &HashMap::from([("status", "success")]))
+}
+
+/// Reports what POST /lists would do with a list, without changing any state: the
+/// identifiers after normalization, which of them are new, the pairs that would be
+/// counted and every limit the list violates. Meant for debugging client integrations.
+#[utoipa::path(
+    tag = "co_occurrence",
+    request_body(content((AddListRequest = "application/json"), (AddListRequest = "application/msgpack"), (AddListRequest = "application/cbor"))),
+    responses(
+        (status = 200, description = "What processing the list would do", content((ListDryRunResponse = "application/json"), (ListDryRunResponse = "application/msgpack"), (ListDryRunResponse = "application/cbor"))),
+        (status = 415, description = "Unsupported content type", body = ErrorResponse),
+        (status = 422, description = "The body doesn't match the expected shape", body = ErrorResponse),
+    )
+)]
+#[post("/lists/dry_run")]
+pub async fn dry_run_list_handler(
+    mut req_body: Body<AddListRequest>,
+    format: Format,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = state.settings.current();
+    let now = determinism::now();
+    let mut timestamp_violation = None;
+    let timestamps = match req_body.timestamps.take() {
+        Some(timestamps) if timestamps.len() != req_body.identifiers.len() => {
+            timestamp_violation = Some(format!("Expected one timestamp per identifier, got {} for {}", timestamps.len(), req_body.identifiers.len()));
+            None
+        }
+        timestamps => timestamps,
+    };
+    let mut timed = TimedIdentifier::zip(std::mem::take(&mut req_body.identifiers), timestamps);
+
+    // Reviewed one by one, so every violation and deleted identifier is reported
+    let review = Admission::new(&settings, &state.tombstones, Some(&state.co_occurrence)).review_list(&mut timed);
+    let (identifiers, times) = TimedIdentifier::unzip(timed);
+    let routed: BTreeMap<String, Vec<String>> = review.routed.0.into_iter().map(|(tenant, part)| (tenant, TimedIdentifier::unzip(part).0)).collect();
+    let (dropped, mut violations) = (review.dropped, review.violations);
+    violations.extend(timestamp_violation);
+
+    let weight = settings.source_weights.of(req_body.source.as_deref());
+    let counter = locks::read(&state.co_occurrence, "co_occurrence");
+    let changes = counter.dry_run(&identifiers, times.as_deref(), weight).map_err(ApiError::Internal)?;
+    drop(counter);
+
+    let response = ListDryRunResponse {
+        duplicate: state.session_dedup.would_skip(&identifiers, now),
+        held_out: state.holdout.draws(&identifiers),
+        identifiers,
+        dropped,
+        routed,
+        violations,
+        weight,
+        changes,
+    };
+    encoding::respond(format, &mut HttpResponse::Ok(), &response)
+}
+
+/// An identifier of a list with when it was watched (seconds since the Unix epoch), if
+/// the list has timestamps, so the ingestion steps leave out both together.
+struct TimedIdentifier(String, Option<i64>);
+
+impl TimedIdentifier {
+    fn zip(identifiers: Vec<String>, timestamps: Option<Vec<chrono::DateTime<chrono::Utc>>>) -> Vec<TimedIdentifier> {
+        match timestamps {
+            Some(timestamps) => identifiers.into_iter().zip(timestamps).map(|(identifier, at)| TimedIdentifier(identifier, Some(at.timestamp()))).collect(),
+            None => identifiers.into_iter().map(|identifier| TimedIdentifier(identifier, None)).collect(),
+        }
+    }
+
+    fn unzip(timed: Vec<TimedIdentifier>) -> (Vec<String>, Option<Vec<i64>>) {
+        let times = timed.iter().map(RuleTarget::time).collect();
+        (timed.into_iter().map(|timed| timed.0).collect(), times)
+    }
+}
+
+impl AsRef<str> for TimedIdentifier {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl RuleTarget for TimedIdentifier {
+    fn rewrite(&mut self, identifier: String) {
+        self.0 = identifier;
+    }
+
+    fn time(&self) -> Option<i64> {
+        self.1
+    }
+}
+
+/// Processes the lines in `data`, taking each lock once for all of them. `line_number`
+/// is the number of lines seen before, for reporting rejected ones; `client` is the
+/// client of the request, whose lists' sources are added per line.
+fn ingest_lines(data: &[u8], line_number: &mut usize, summary: &mut StreamIngestResponse, state: &AppState, client: &IngestClient, settings: &Settings) {
+    let AppState { co_occurrence: counter_data, recent_lists: recent_lists_data, session_dedup, tombstones, holdout, ingest_stats, tenants, .. } = state;
+    let now = determinism::now();
+    // Checked against the identifiers known before the chunk
+    let admission = Admission::new(settings, tombstones, Some(counter_data));
+    let (mut lists, mut routed_lists) = (Vec::new(), Vec::new());
+    for line in data.split(|&byte| byte == b'\n') {
+        *line_number += 1;
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let parsed = serde_json::from_slice::<BorrowedListRequest>(line)
+            .map_err(|e| e.to_string())
+            .and_then(|mut list| admission.list(&mut list.identifiers).map(|routed| (list, routed)).map_err(|e| e.to_string()));
+        let duplicate = parsed.as_ref().is_ok_and(|(list, _)| session_dedup.is_duplicate(&list.identifiers, now));
+        match parsed {
+            Ok(_) if duplicate => summary.duplicates += 1,
+            Ok((list, routed)) => {
+                let source = list.source.as_deref().map(str::to_string);
+                ingest_stats.record_list(IngestClient { source, ..client.clone() }, &list.identifiers, now);
+                let weight = settings.source_weights.of(list.source.as_deref());
+                routed_lists.push((routed, weight));
+                if holdout.hold_out(&list.identifiers) {
+                    summary.processed += 1;
+                } else {
+                    lists.push((weight, list.identifiers));
+                }
+            }
+            Err(message) => {
+                ingest_stats.record_rejections(client.clone(), 1, now);
+                summary.rejected += 1;
+                if summary.errors.len() < MAX_REPORTED_LINE_ERRORS {
+                    summary.errors.push(LineError { line: *line_number, message });
+                }
+            }
+        }
+    }
+    drop(admission);
+    for (routed, weight) in &routed_lists {
+        routed.count(tenants, *weight);
+    }
+    if lists.is_empty() {
+        return;
+    }
+
+    let mut counter_lock = locks::write(counter_data, "co_occurrence");
+    for (weight, identifiers) in &lists {
+        counter_lock.process_weighted_list(identifiers, *weight);
+    }
+    drop(counter_lock);
+    let mut recent_lists_lock = locks::lock(recent_lists_data, "recent_lists");
+    for (_, identifiers) in &lists {
+        recent_lists_lock.push(identifiers);
+    }
+    summary.processed += lists.len();
+}
+
+/// Ingests newline-delimited lists (`{"identifiers": [...]}` per line) as they arrive,
+/// without buffering the whole body, e.g. for replaying historical session dumps.
+/// Invalid lines are skipped and reported, as are lists submitted shortly before if
+/// deduplication is on; all other lines are processed.
+#[utoipa::path(
+    tag = "co_occurrence",
+    request_body(content = String, description = "One AddListRequest as JSON per line", content_type = "application/x-ndjson"),
+    responses(
+        (status = 200, description = "Lines processed", body = StreamIngestResponse),
+        (status = 413, description = "A line exceeds the maximum length", body = ErrorResponse),
+    )
+)]
+#[post("/lists/stream")]
+pub async fn stream_lists_handler(
+    req: HttpRequest,
+    mut payload: web::Payload,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = state.settings.current();
+    let client = IngestClient::of(&req.extensions(), None);
+    let mut summary = StreamIngestResponse { status: "success", ..Default::default() };
+    let mut line_number = 0;
+    let mut buffer = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        buffer.extend_from_slice(&chunk.map_err(|e| ApiError::BadRequest(e.to_string()))?);
+        // Process everything up to the last complete line, keep the rest for the next chunk
+        if let Some(end) = buffer.iter().rposition(|&byte| byte == b'\n') {
+            ingest_lines(&buffer[..end], &mut line_number, &mut summary, &state, &client, &settings);
+            buffer.drain(..=end);
+        }
+        if buffer.len() > MAX_STREAM_LINE_BYTES {
+            return Err(ApiError::PayloadTooLarge(format!(
+                "Line {} exceeds {} bytes ({} lines processed before)",
+                line_number + 1,
+                MAX_STREAM_LINE_BYTES,
+                summary.processed
+            )));
+        }
+    }
+    // The last line may lack its newline
+    ingest_lines(&buffer, &mut line_number, &mut summary, &state, &client, &settings);
+
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+#[utoipa::path(
+    tag = "co_occurrence",
+    params(
+        ("identifier" = String, Path, description = "The identifier to look up"),
+        CoOccurrencePageQuery,
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a previous response"),
+    ),
+    responses(
+        (status = 200, description = "Co-occurrence counts of the identifier", content((CoOccurrenceMetricsResponse = "application/json"), (CoOccurrenceMetricsResponse = "application/msgpack"), (CoOccurrenceMetricsResponse = "application/cbor"))),
+        (status = 304, description = "Unchanged since the response with the given ETag"),
+        (status = 404, description = "Unknown identifier", body = ErrorResponse),
+        (status = 503, description = "Overloaded, retry after the Retry-After header", body = ErrorResponse),
+    )
+)]
+#[get("/lists/{identifier}")]
+pub async fn get_co_occurrence_metrics_handler(
+    path: IdentifierPath, // Captures the 'identifier' from the URL
+    query: web::Query<CoOccurrencePageQuery>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    format: Format,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = state.settings.current();
+    let identifier = path.into_inner(); // Extract the normalized String from the path
+    // Most lookups of unknown identifiers end here, without waiting for the lock
+    if !state.identifier_filter.may_contain(&identifier) {
+        return Err(unknown_identifier(&identifier));
+    }
+    let model = if settings.factorization.enabled {
+        locks::lock(&state.factorization, "factorization").current.clone()
+    } else {
+        None
+    };
+
+    let counter_lock = read_or_unavailable(&state.co_occurrence, "co_occurrence", &settings.overload)?;
+    let Some(version) = counter_lock.version_of(&identifier) else {
+        return Err(unknown_identifier(&identifier));
+    };
+    let etag = etag::entity_tag(&[version, model.as_ref().map_or(0, |model| model.version)]);
+    if let Some(response) = etag::not_modified(if_none_match.as_ref(), &etag) {
+        return Ok(response);
+    }
+    let mut co_occurrences = counter_lock.cached_metrics_for_identifier(&identifier);
+    drop(counter_lock);
+    let total = co_occurrences.len();
+    if query.limit.is_some() || query.offset.is_some() {
+        co_occurrences = page_of(co_occurrences, query.offset.unwrap_or(0), query.limit.unwrap_or(usize::MAX));
+    }
+
+    let factorization_neighbors =
+        model.map(|model| model.similar_items(&identifier, FACTORIZATION_NEIGHBORS_LIMIT).into_iter().collect());
+
+    let response = CoOccurrenceMetricsResponse {
+        target_identifier: identifier,
+        co_occurrences,
+        total,
+        factorization_neighbors,
+    };
+    encoding::respond(format, HttpResponse::Ok().insert_header(ETag(etag)), &response)
+}
+
+/// Looks up the top co-occurring items of several identifiers at once, e.g. for the
+/// tiles of a grid, under a single acquisition of the co-occurrence lock.
+#[utoipa::path(
+    tag = "co_occurrence",
+    params(("X-Request-Timeout" = Option<u64>, Header, description = "Milliseconds to answer within, else 504")),
+    request_body = BatchMetricsRequest,
+    responses(
+        (status = 200, description = "Top co-occurring items by identifier", body = BatchMetricsResponse),
+        (status = 400, description = "Malformed X-Request-Timeout header", body = ErrorResponse),
+        (status = 422, description = "The body doesn't match the expected shape or violates the identifier limits", body = ErrorResponse),
+        (status = 503, description = "Overloaded, retry after the Retry-After header", body = ErrorResponse),
+        (status = 504, description = "Not answered within the request timeout", body = ErrorResponse),
+    )
+)]
+#[post("/lists/metrics")]
+pub async fn batch_metrics_handler(
+    mut req_body: web::Json<BatchMetricsRequest>,
+    deadline: Deadline,
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    identifier_filter: web::Data<Arc<IdentifierFilter>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    normalize_list(&mut req_body.identifiers, &settings.validation.normalization);
+    validate_list(&req_body.identifiers, &settings.validation)?;
+    let limit = req_body.limit.unwrap_or(DEFAULT_BATCH_METRICS_LIMIT);
+
+    let mut response = BatchMetricsResponse::default();
+    // Without the lock if none of them can be known
+    let counter_lock = if req_body.identifiers.iter().any(|identifier| identifier_filter.may_contain(identifier)) {
+        Some(read_or_unavailable(&counter_data, "co_occurrence", &settings.overload)?)
+    } else {
+        None
+    };
+    for identifier in &req_body.identifiers {
+        deadline.check()?;
+        if response.metrics.contains_key(identifier) || response.unknown.contains(identifier) {
+            continue;
+        }
+        let counter_lock = match &counter_lock {
+            Some(lock) if identifier_filter.may_contain(identifier) && lock.version_of(identifier).is_some() => lock,
+            _ => {
+                response.unknown.push(identifier.clone());
+                continue;
+            }
+        };
+        let co_occurrences = counter_lock.cached_metrics_for_identifier(identifier);
+        response.metrics.insert(identifier.clone(), co_occurrences);
+    }
+    drop(counter_lock);
+    // Sorted outside of the lock
+    for co_occurrences in response.metrics.values_mut() {
+        *co_occurrences = page_of(std::mem::take(co_occurrences), 0, limit);
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// The directional counterpart of GET /lists/{identifier}: of the lists with the target,
+/// the share that also had each co-occurring item, i.e. count(target, item) / count(target),
+/// damped for targets in few lists by the shrinkage (`MEDIATHEK_SCORING_SHRINKAGE_*`).
+#[utoipa::path(
+    tag = "co_occurrence",
+    params(("identifier" = String, Path, description = "The identifier to look up"), CoOccurrencePageQuery),
+    responses(
+        (status = 200, description = "Co-occurring items by conditional probability", body = ConditionalResponse),
+        (status = 404, description = "Unknown identifier", body = ErrorResponse),
+        (status = 503, description = "Overloaded, retry after the Retry-After header", body = ErrorResponse),
+    )
+)]
+#[get("/lists/{identifier}/conditional")]
+pub async fn get_conditional_handler(
+    path: IdentifierPath,
+    query: web::Query<CoOccurrencePageQuery>,
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    identifier_filter: web::Data<Arc<IdentifierFilter>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    let identifier = path.into_inner();
+    if !identifier_filter.may_contain(&identifier) {
+        return Err(unknown_identifier(&identifier));
+    }
+    let counter_lock = read_or_unavailable(&counter_data, "co_occurrence", &settings.overload)?;
+    let Some((occurrences, neighbors)) = counter_lock.conditional_for_identifier(&identifier, &settings.scoring.shrinkage) else {
+        return Err(unknown_identifier(&identifier));
+    };
+    drop(counter_lock);
+    let total = neighbors.len();
+    let neighbors = neighbors.into_iter().skip(query.offset.unwrap_or(0)).take(query.limit.unwrap_or(usize::MAX)).collect();
+    Ok(HttpResponse::Ok().json(ConditionalResponse { target_identifier: identifier, occurrences, neighbors, total }))
+}
+
+fn unknown_identifier(identifier: &str) -> ApiError {
+    ApiError::NotFound(format!("Unknown identifier '{}'", identifier))
+}
+
+/// Returns the display labels of the bucket and window `names` in `language`, as asked for
+/// with `?labels=`, or none without it. The names stay the keys of the responses, so only
+/// dashboards showing them depend on the labels.
+fn bucket_labels(
+    language: Option<&str>,
+    names: &mut dyn Iterator<Item = &str>,
+    settings: &Settings,
+) -> Result<BTreeMap<String, String>, ApiError> {
+    let Some(language) = language else {
+        return Ok(BTreeMap::new());
+    };
+    let labels = &settings.counters.labels;
+    if !labels.knows(language) {
+        return Err(ApiError::BadRequest(format!("Unknown label language '{}'", language)));
+    }
+    Ok(names.map(|name| (name.to_string(), labels.label(language, name))).collect())
+}
+
+/// Returns the entries of `co_occurrences` sorted by count (descending, ties by
+/// identifier), skipping `offset` entries and returning at most `limit`.
+fn page_of(co_occurrences: HashMap<String, u64>, offset: usize, limit: usize) -> HashMap<String, u64> {
+    let mut entries: Vec<(String, u64)> = co_occurrences.into_iter().collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.into_iter().skip(offset).take(limit).collect()
+}
+
+// --- API Handlers (for Rotating Counters) ---
+
+#[utoipa::path(
+    tag = "counters",
+    request_body(content((IncrementCounterRequest = "application/json"), (IncrementCounterRequest = "application/msgpack"), (IncrementCounterRequest = "application/cbor"))),
+    responses(
+        (status = 200, description = "Success, or a retry acknowledged without processing it again; status \"dropped\" or \"routed\" if an ingest rule left the play out or counted it for another tenant", content((StatusResponse = "application/json"), (StatusResponse = "application/msgpack"), (StatusResponse = "application/cbor"))),
+        (status = 400, description = "Invalid Idempotency-Key", body = ErrorResponse),
+        (status = 415, description = "Unsupported content type", body = ErrorResponse),
+        (status = 422, description = "The body doesn't match the expected shape or violates the identifier limits", body = ErrorResponse),
+    )
+)]
+#[post("/counters")]
+pub async fn increment_daily_counter_handler(
+    req: HttpRequest,
+    mut req_body: Body<IncrementCounterRequest>,
+    format: Format,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = state.settings.current();
+    let routed_to = match Admission::new(&settings, &state.tombstones, None).play(&mut req_body.id)? {
+        PlayVerdict::Count => None,
+        PlayVerdict::Route(tenant) => Some(tenant),
+        PlayVerdict::Dropped => return encoding::respond(format, &mut HttpResponse::Ok(), &HashMap::from([("status", "dropped")])),
+        PlayVerdict::Deleted => return encoding::respond(format, &mut HttpResponse::Ok(), &HashMap::from([("status", "deleted")])),
+    };
+    if !state.idempotency_keys.claim(&req, "POST /counters")? {
+        return encoding::respond(format, HttpResponse::Ok().insert_header((IDEMPOTENT_REPLAYED_HEADER, "true")), &HashMap::from([("status", "success")]));
+    }
+    if let Some(tenant) = routed_to {
+        rules::route_play(&state.tenants, tenant, &req_body.id, req_body.count.unwrap_or(1));
+        return encoding::respond(format, &mut HttpResponse::Ok(), &HashMap::from([("status", "routed")]));
+    }
+    let counters_lock = locks::read(&state.counters, "rotating_counters");
+    counters_lock.increment(&req_body.id, req_body.count.unwrap_or(1));
+    drop(counters_lock);
+    state.ingest_stats.record_plays(IngestClient::of(&req.extensions(), None), [req_body.id.as_str()], determinism::now());
+    encoding::respond(format, &mut HttpResponse::Ok(), &HashMap::from([("status", "success")]))
+}
+
+/// Applies a whole batch of increments (`[{"id": ..., "count": ...}, ...]`) under a
+/// single lock acquisition, for clients that buffer events locally.
+#[utoipa::path(
+    tag = "counters",
+    request_body(content((Vec<IncrementCounterRequest> = "application/json"), (Vec<IncrementCounterRequest> = "application/msgpack"), (Vec<IncrementCounterRequest> = "application/cbor"))),
+    responses(
+        (status = 200, description = "Increments applied", content((BatchIncrementResponse = "application/json"), (BatchIncrementResponse = "application/msgpack"), (BatchIncrementResponse = "application/cbor"))),
+        (status = 415, description = "Unsupported content type", body = ErrorResponse),
+        (status = 422, description = "The body doesn't match the expected shape or violates the identifier limits", body = ErrorResponse),
+    )
+)]
+#[post("/counters/batch")]
+pub async fn batch_increment_handler(
+    req: HttpRequest,
+    mut req_body: Body<Vec<IncrementCounterRequest>>,
+    format: Format,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = state.settings.current();
+    let now = determinism::now();
+    // Validate the whole batch first, so it's either applied completely or not at all
+    let admission = Admission::new(&settings, &state.tombstones, None);
+    let verdicts = req_body.iter_mut().map(|increment| admission.play(&mut increment.id)).collect::<Result<Vec<PlayVerdict>, ApiError>>()?;
+    drop(admission);
+    let mut routed_plays = 0;
+    for (increment, verdict) in req_body.iter().zip(&verdicts) {
+        let amount = increment.count.unwrap_or(1);
+        if let (PlayVerdict::Route(tenant), true) = (verdict, amount > 0) {
+            rules::route_play(&state.tenants, tenant, &increment.id, amount);
+            routed_plays += 1;
+        }
+    }
+    let counters_lock = locks::read(&state.counters, "rotating_counters");
+    let mut applied_ids = Vec::with_capacity(req_body.len());
+    for (increment, _) in req_body.iter().zip(&verdicts).filter(|(_, verdict)| **verdict == PlayVerdict::Count) {
+        let amount = increment.count.unwrap_or(1);
+        if amount > 0 {
+            counters_lock.increment(&increment.id, amount);
+            applied_ids.push(increment.id.as_str());
+        }
+    }
+    drop(counters_lock);
+    let applied = applied_ids.len() + routed_plays;
+    state.ingest_stats.record_plays(IngestClient::of(&req.extensions(), None), applied_ids, now);
+
+    encoding::respond(format, &mut HttpResponse::Ok(), &BatchIncrementResponse { status: "success", applied })
+}
+
+/// Without parameters, returns every bucket. With `window`, returns only that bucket as
+/// a list sorted by count; `limit`/`offset` page through it. Without `window`,
+/// `limit`/`offset` are applied to each bucket individually. With `labels`, the display
+/// labels of the buckets in that language are added under "labels".
+#[utoipa::path(
+    tag = "counters",
+    params(
+        CountersQuery,
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a previous response"),
+        ("X-Request-Timeout" = Option<u64>, Header, description = "Milliseconds to answer within, else 504"),
+    ),
+    responses(
+        (status = 200, description = "All buckets by name (and their labels under \"labels\" if requested), or the requested window as a ranked list if `window` is given", content((HashMap<String, HashMap<String, u64>> = "application/json"), (HashMap<String, HashMap<String, u64>> = "application/msgpack"), (HashMap<String, HashMap<String, u64>> = "application/cbor"))),
+        (status = 304, description = "Unchanged since the response with the given ETag"),
+        (status = 400, description = "Unknown window or label language, or a malformed X-Request-Timeout header", body = ErrorResponse),
+        (status = 503, description = "Overloaded, retry after the Retry-After header", body = ErrorResponse),
+        (status = 504, description = "Not answered within the request timeout", body = ErrorResponse),
+    )
+)]
+#[get("/counters")]
+pub async fn get_rotating_counters_handler(
+    query: web::Query<CountersQuery>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    format: Format,
+    deadline: Deadline,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    let offset = query.offset.unwrap_or(0);
+    // Checked up front, so an unknown language isn't answered with 304
+    bucket_labels(query.labels.as_deref(), &mut std::iter::empty(), &settings)?;
+    let counters_lock = read_or_unavailable(&rotating_counters_data, "rotating_counters", &settings.overload)?;
+
+    let version = match &query.window {
+        Some(window) => counters_lock
+            .window_version(window)
+            .ok_or_else(|| ApiError::BadRequest(format!("Unknown window '{}'", window)))?,
+        None => counters_lock.version(),
+    };
+    let etag = etag::entity_tag(&[version]);
+    if let Some(response) = etag::not_modified(if_none_match.as_ref(), &etag) {
+        return Ok(response);
+    }
+
+    if let Some(window) = &query.window {
+        let Some(bucket) = counters_lock.window(window) else {
+            return Err(ApiError::BadRequest(format!("Unknown window '{}'", window)));
+        };
+        let response = CounterWindowResponse {
+            window: window.clone(),
+            total: bucket.len(),
+            items: top_entries(&bucket, offset, query.limit.unwrap_or(usize::MAX)),
+            labels: bucket_labels(query.labels.as_deref(), &mut std::iter::once(window.as_str()), &settings)?,
+        };
+        return encoding::respond(format, HttpResponse::Ok().insert_header(ETag(etag)), &response);
+    }
+
+    // Clone the data for the response; rolling windows (if any) follow the regular buckets
+    let buckets: Vec<(String, Bucket)> = counters_lock
+        .named_buckets()
+        .into_iter()
+        .map(|(name, bucket)| (name, Cow::Borrowed(bucket)))
+        .chain(counters_lock.rolling_windows().into_iter().map(|(name, bucket)| (name, Cow::Owned(bucket))))
+        .map(|(name, bucket)| {
+            deadline.check()?;
+            if query.limit.is_none() && query.offset.is_none() {
+                return Ok((name, bucket.into_owned()));
+            }
+            let top = top_entries(&bucket, offset, query.limit.unwrap_or(usize::MAX));
+            Ok((name, top.into_iter().map(|entry| (entry.id, entry.count)).collect()))
+        })
+        .collect::<Result<_, ApiError>>()?;
+    drop(counters_lock);
+
+    let labels = bucket_labels(query.labels.as_deref(), &mut buckets.iter().map(|(name, _)| name.as_str()), &settings)?;
+    let response = DailyCountersResponse { buckets, labels };
+    encoding::respond(format, HttpResponse::Ok().insert_header(ETag(etag)), &response)
+}
+
+/// Returns the counts of a single identifier across all hourly and daily buckets,
+/// oldest first, e.g. for rendering sparklines. With `from`, also the count in that time
+/// range, summed up from whichever buckets intersect it, so clients don't depend on the
+/// bucket layout.
+#[utoipa::path(
+    tag = "counters",
+    params(("id" = String, Path, description = "The identifier"), CounterRangeQuery),
+    responses(
+        (status = 200, description = "Counts per bucket, oldest first", content((CounterTimeSeriesResponse = "application/json"), (CounterTimeSeriesResponse = "application/msgpack"), (CounterTimeSeriesResponse = "application/cbor"))),
+        (status = 400, description = "`to` without `from`, `from` not before `to`, or an unknown label language", body = ErrorResponse),
+        (status = 503, description = "Overloaded, retry after the Retry-After header", body = ErrorResponse),
+    )
+)]
+#[get("/counters/{id}")]
+pub async fn get_counter_time_series_handler(
+    path: IdentifierPath,
+    query: web::Query<CounterRangeQuery>,
+    format: Format,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let range = match (query.from, query.to) {
+        (None, None) => None,
+        (None, Some(_)) => return Err(ApiError::BadRequest("\"to\" requires \"from\"".to_string())),
+        (Some(from), to) => {
+            let to = to.unwrap_or_else(determinism::now);
+            if from >= to {
+                return Err(ApiError::BadRequest("\"from\" has to be before \"to\"".to_string()));
+            }
+            Some((from, to))
+        }
+    };
+    let settings = settings.current();
+    let counters_lock = read_or_unavailable(&rotating_counters_data, "rotating_counters", &settings.overload)?;
+    let series = counters_lock.time_series(&id);
+    let rolling = counters_lock.rolling_counts(&id);
+    let timezone = settings.counters.rotation_timezone;
+    let range = range.map(|(from, to)| counters_lock.range_count(&id, from, to, &timezone));
+    drop(counters_lock);
+
+    let labels = bucket_labels(
+        query.labels.as_deref(),
+        &mut [&series.hourly, &series.daily, &series.weekly, &series.monthly]
+            .into_iter()
+            .flatten()
+            .map(|point| point.bucket.as_str())
+            .chain(rolling.window_names()),
+        &settings,
+    )?;
+    let response = CounterTimeSeriesResponse { id, series, rolling, range, labels };
+    encoding::respond(format, &mut HttpResponse::Ok(), &response)
+}
+
+/// Returns the hourly and daily counts of several identifiers as bare arrays, oldest
+/// first, so dashboards render their sparklines from a single request.
+#[utoipa::path(
+    tag = "counters",
+    params(SparklinesQuery),
+    responses(
+        (status = 200, description = "Counts per identifier, oldest first", content((SparklinesResponse = "application/json"), (SparklinesResponse = "application/msgpack"), (SparklinesResponse = "application/cbor"))),
+        (status = 422, description = "Too many identifiers, or one violating the identifier limits", body = ErrorResponse),
+        (status = 503, description = "Overloaded, retry after the Retry-After header", body = ErrorResponse),
+    )
+)]
+#[get("/counters/sparklines")]
+pub async fn get_sparklines_handler(
+    query: web::Query<SparklinesQuery>,
+    format: Format,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    let mut ids: Vec<String> = query.ids.split(',').map(str::trim).filter(|id| !id.is_empty()).map(str::to_string).collect();
+    normalize_list(&mut ids, &settings.validation.normalization);
+    validate_list(&ids, &settings.validation)?;
+    let counters_lock = read_or_unavailable(&rotating_counters_data, "rotating_counters", &settings.overload)?;
+    let sparklines = ids
+        .into_iter()
+        .map(|id| {
+            let sparkline = counters_lock.sparkline(&id);
+            (id, sparkline)
+        })
+        .collect();
+    drop(counters_lock);
+
+    encoding::respond(format, &mut HttpResponse::Ok(), &SparklinesResponse { sparklines })
+}
+
+/// Returns the items whose counts rose or fell most between two windows, by plays and
+/// relative to before, e.g. the biggest movers since yesterday.
+#[utoipa::path(
+    tag = "counters",
+    params(MoversQuery),
+    responses(
+        (status = 200, description = "The largest increases and decreases, most changed first", body = MoversResponse),
+        (status = 400, description = "Unknown window", body = ErrorResponse),
+        (status = 503, description = "Overloaded, retry after the Retry-After header", body = ErrorResponse),
+    )
+)]
+#[get("/counters/movers")]
+pub async fn get_counter_movers_handler(
+    query: web::Query<MoversQuery>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    let current = query.current.clone().unwrap_or_else(|| "today".to_string());
+    let previous = query.previous.clone().unwrap_or_else(|| "yesterday".to_string());
+    let min_count = query.min_count.unwrap_or(DEFAULT_TRENDING_MIN_COUNT);
+    let limit = query.limit.unwrap_or(DEFAULT_TRENDING_LIMIT);
+    let counters_lock = read_or_unavailable(&rotating_counters_data, "rotating_counters", &settings.overload)?;
+
+    let window = |name: &str| counters_lock.window(name).ok_or_else(|| ApiError::BadRequest(format!("Unknown window '{}'", name)));
+    let movers = movers(&*window(&current)?, &*window(&previous)?, min_count, limit);
+    drop(counters_lock);
+
+    Ok(HttpResponse::Ok().json(MoversResponse { current, previous, movers }))
+}
+
+/// Returns the plays per namespace ("ard" of "ard:123", usually the broadcaster) and their
+/// share of all plays in every bucket, e.g. for the traffic shares of the broadcasters.
+#[utoipa::path(
+    tag = "counters",
+    params(NamespacesQuery),
+    responses(
+        (status = 200, description = "Plays per namespace by bucket, current hour first", body = NamespacesResponse),
+        (status = 400, description = "Unknown window", body = ErrorResponse),
+        (status = 503, description = "Overloaded, retry after the Retry-After header", body = ErrorResponse),
+    )
+)]
+#[get("/counters/namespaces")]
+pub async fn get_counter_namespaces_handler(
+    query: web::Query<NamespacesQuery>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    let counters_lock = read_or_unavailable(&rotating_counters_data, "rotating_counters", &settings.overload)?;
+    let names: Vec<String> = match &query.window {
+        Some(window) => vec![window.clone()],
+        None => counters_lock.named_buckets().into_iter().map(|(name, _)| name).collect(),
+    };
+    let windows = names
+        .into_iter()
+        .map(|window| {
+            let (total, namespaces) =
+                counters_lock.namespace_plays(&window).ok_or_else(|| ApiError::BadRequest(format!("Unknown window '{}'", window)))?;
+            Ok(NamespaceWindow { window, total, namespaces })
+        })
+        .collect::<Result<_, ApiError>>()?;
+    drop(counters_lock);
+
+    Ok(HttpResponse::Ok().json(NamespacesResponse { windows }))
+}
+
+/// Returns the counts of a single identifier in each of the last minutes, oldest first,
+/// e.g. for following a live event. Only available if minute buckets are configured.
+#[utoipa::path(
+    tag = "counters",
+    params(("id" = String, Path, description = "The identifier")),
+    responses(
+        (status = 200, description = "Counts per minute, oldest first", content((MinuteSeriesResponse = "application/json"), (MinuteSeriesResponse = "application/msgpack"), (MinuteSeriesResponse = "application/cbor"))),
+        (status = 404, description = "Minute buckets are disabled", body = ErrorResponse),
+    )
+)]
+#[get("/counters/{id}/minutes")]
+pub async fn get_minute_series_handler(
+    path: IdentifierPath,
+    format: Format,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let counters_lock = locks::read(&rotating_counters_data, "rotating_counters");
+    if !counters_lock.minutes.is_enabled() {
+        return Err(ApiError::NotFound("Minute buckets are disabled, see MEDIATHEK_COUNTERS_MINUTE_BUCKETS".to_string()));
+    }
+    let minutes = counters_lock.minutes.series(&id, determinism::now());
+    drop(counters_lock);
+
+    encoding::respond(format, &mut HttpResponse::Ok(), &MinuteSeriesResponse { id, minutes })
+}
+
+/// Predicts the counts of an identifier for today and the next days from the daily
+/// buckets and the weekday profile, e.g. for scheduling pushes before an item peaks.
+#[utoipa::path(
+    tag = "counters",
+    params(("id" = String, Path, description = "The identifier"), ForecastQuery),
+    responses(
+        (status = 200, description = "Expected counts per day, today first", body = ForecastResponse),
+        (status = 400, description = "`days` out of range", body = ErrorResponse),
+    )
+)]
+#[get("/counters/{id}/forecast")]
+pub async fn get_forecast_handler(
+    path: IdentifierPath,
+    query: web::Query<ForecastQuery>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let days = query.days.unwrap_or(MAX_FORECAST_DAYS);
+    if !(1..=MAX_FORECAST_DAYS).contains(&days) {
+        return Err(ApiError::BadRequest(format!("\"days\" has to be between 1 and {}", MAX_FORECAST_DAYS)));
+    }
+    let id = path.into_inner();
+    let timezone = settings.current().counters.rotation_timezone;
+    let forecast = forecast::forecast(&locks::read(&rotating_counters_data, "rotating_counters"), &id, &timezone, days);
+
+    Ok(HttpResponse::Ok().json(ForecastResponse { id, forecast }))
+}
+
+/// Returns the average count of an identifier per weekday.
+#[utoipa::path(
+    tag = "counters",
+    params(("id" = String, Path, description = "The identifier")),
+    responses(
+        (status = 200, description = "Average count per weekday", body = SeasonalityResponse),
+    )
+)]
+#[get("/counters/{id}/seasonality")]
+pub async fn get_seasonality_handler(
+    path: IdentifierPath,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let weekdays = locks::read(&rotating_counters_data, "rotating_counters").weekdays.averages(&id);
+
+    HttpResponse::Ok().json(SeasonalityResponse { id, weekdays })
+}
+
+/// Removes an identifier from all counter buckets, e.g. after it was depublished. With
+/// `MEDIATHEK_TOMBSTONES_TTL_SECS`, plays and lists of it are kept out for that long.
+#[utoipa::path(
+    tag = "counters",
+    params(("id" = String, Path, description = "The identifier to remove")),
+    responses(
+        (status = 200, description = "Success", body = StatusResponse),
+        (status = 404, description = "The identifier has no counts", body = ErrorResponse),
+    )
+)]
+#[delete("/counters/{id}")]
+pub async fn delete_counter_handler(
+    path: IdentifierPath,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    tombstones_data: web::Data<Arc<RwLock<Tombstones>>>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    if !locks::write(&rotating_counters_data, "rotating_counters").remove(&id) {
+        return Err(ApiError::NotFound(format!("No counts for '{}'", id)));
+    }
+    locks::write(&tombstones_data, "tombstones").bury(&id, false, determinism::now());
+    Ok(HttpResponse::Ok().json(HashMap::from([("status", "success")])))
+}
+
+/// Removes every identifier starting with a prefix from the co-occurrences and all counter
+/// buckets, e.g. when a broadcaster's content has to be deleted. The co-occurrences are
+/// persisted right away, the counters through their event log. Retained snapshot versions
+/// keep the identifiers until they expire. With `MEDIATHEK_TOMBSTONES_TTL_SECS`, lists and
+/// plays of identifiers with the prefix are kept out for that long.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    request_body = PurgeRequest,
+    responses(
+        (status = 200, description = "Number of removed entries", body = PurgeResponse),
+        (status = 422, description = "The prefix is empty", body = ErrorResponse),
+        (status = 500, description = "The co-occurrences are kept in a database, which can't be purged", body = ErrorResponse),
+    )
+)]
+#[post("/purge")]
+pub async fn purge_handler(
+    req_body: web::Json<PurgeRequest>,
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    tombstones_data: web::Data<Arc<RwLock<Tombstones>>>,
+) -> Result<HttpResponse, ApiError> {
+    let prefix = req_body.into_inner().prefix;
+    if prefix.is_empty() {
+        return Err(ApiError::Unprocessable("The prefix must not be empty".to_string()));
+    }
+    let counter = counter_data.get_ref().clone();
+    let counters = rotating_counters_data.get_ref().clone();
+    let purge_prefix = prefix.clone();
+    let (co_occurrence_identifiers, co_occurrence_pairs, counter_identifiers) = web::block(move || {
+        let (identifiers, pairs) = locks::write(&counter, "co_occurrence").remove_prefix(&purge_prefix)?;
+        let counter_identifiers = locks::write(&counters, "rotating_counters").remove_prefix(&purge_prefix);
+        Ok::<_, String>((identifiers, pairs, counter_identifiers))
+    })
+    .await?
+    .map_err(ApiError::Internal)?;
+    locks::write(&tombstones_data, "tombstones").bury(&prefix, true, determinism::now());
+
+    Ok(HttpResponse::Ok().json(PurgeResponse { prefix, co_occurrence_identifiers, co_occurrence_pairs, counter_identifiers }))
+}
+
+/// Lists the identifiers and prefixes deleted recently, which the ingestion drops or
+/// rejects until they expire (see `MEDIATHEK_TOMBSTONES_MODE`).
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "The active tombstones, most recent first", body = TombstonesResponse),
+    )
+)]
+#[get("/tombstones")]
+pub async fn get_tombstones_handler(tombstones_data: web::Data<Arc<RwLock<Tombstones>>>) -> impl Responder {
+    let tombstones = locks::read(&tombstones_data, "tombstones").active(determinism::now());
+    HttpResponse::Ok().json(TombstonesResponse { tombstones })
+}
+
+/// Imports historical lists and plays, e.g. to warm up a fresh instance: one POST /lists
+/// or POST /counters body as JSON per line, or CSV with Content-Type text/csv (see
+/// `ingest::import`). The body is processed as it arrives; invalid lines are skipped and
+/// reported. Afterwards the state is persisted.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    request_body(description = "Lists and plays, one per line", content((String = "application/x-ndjson"), (String = "text/csv"))),
+    responses(
+        (status = 200, description = "Lines imported", body = ImportSummary),
+        (status = 413, description = "A line exceeds the maximum length", body = ErrorResponse),
+    )
+)]
+#[post("/import")]
+pub async fn import_handler(
+    req: HttpRequest,
+    mut payload: web::Payload,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let format = if req.content_type() == "text/csv" { ImportFormat::Csv } else { ImportFormat::Ndjson };
+    let ingestor = Ingestor::new(
+        Arc::clone(&state.co_occurrence),
+        Arc::clone(&state.recent_lists),
+        Arc::clone(&state.counters),
+        Arc::clone(&state.tombstones),
+        Arc::clone(&state.holdout),
+        Arc::clone(&state.tenants),
+        Arc::clone(&state.settings),
+    );
+    let mut import = Import::new(&ingestor, format);
+    while let Some(chunk) = payload.next().await {
+        import.push(&chunk.map_err(|e| ApiError::BadRequest(e.to_string()))?).map_err(ApiError::PayloadTooLarge)?;
+    }
+    let summary = import.finish();
+    let summary = web::block(move || {
+        import::persist(&ingestor, &summary);
+        summary
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+/// Returns the co-occurrences and the counters as a single archive, taken at one point in
+/// time, e.g. for off-host backups or to move the state to another environment. The
+/// archive's format version is in the X-Backup-Format-Version header, the snapshot ID both
+/// halves share in X-Snapshot-Id.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "The archive", content_type = "application/octet-stream", body = Vec<u8>,
+            headers(
+                ("X-Backup-Format-Version" = u32, description = "Format version of the archive"),
+                ("X-Snapshot-Id" = u64, description = "ID of the snapshot pair in the archive, increasing over time"),
+            )),
+        (status = 500, description = "The co-occurrences are kept in a database, which can't be backed up", body = ErrorResponse),
+    )
+)]
+#[get("/backup")]
+pub async fn backup_handler(
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> Result<HttpResponse, ApiError> {
+    let counter = counter_data.get_ref().clone();
+    let counters = rotating_counters_data.get_ref().clone();
+    let (metadata, data) = web::block(move || backup::create(&counter, &counters)).await?.map_err(ApiError::Internal)?;
+
+    let filename = format!("mediathek-backup-{}.bin", metadata.created_at.format("%Y%m%dT%H%M%SZ"));
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .insert_header((BACKUP_FORMAT_VERSION_HEADER, metadata.format_version.to_string()))
+        .insert_header((SNAPSHOT_ID_HEADER, metadata.snapshot_id.to_string()))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .body(data))
+}
+
+/// Writes full snapshots of the counters and the co-occurrences right away, even if
+/// nothing changed, e.g. before a planned maintenance.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "The snapshots written", body = FlushResponse),
+        (status = 500, description = "A snapshot couldn't be written", body = ErrorResponse),
+    )
+)]
+#[post("/flush")]
+pub async fn flush_handler(
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> Result<HttpResponse, ApiError> {
+    let counter = counter_data.get_ref().clone();
+    let counters = rotating_counters_data.get_ref().clone();
+    let files = web::block(move || -> std::io::Result<Vec<FlushedFile>> {
+        let flushed = |path: String, bytes: u64, started: Instant| FlushedFile {
+            path,
+            bytes,
+            duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        };
+        let mut files = Vec::with_capacity(2);
+        let started = Instant::now();
+        let (location, bytes) = locks::write(&counters, "rotating_counters").flush()?;
+        files.push(flushed(location, bytes, started));
+        let started = Instant::now();
+        if let Some(path) = locks::write(&counter, "co_occurrence").flush()? {
+            files.push(flushed(path.display().to_string(), std::fs::metadata(&path)?.len(), started));
+        }
+        Ok(files)
+    })
+    .await?
+    .map_err(|e| ApiError::Internal(format!("Failed to write a snapshot: {}", e)))?;
+    Ok(HttpResponse::Ok().json(FlushResponse { files }))
+}
+
+/// Replaces the co-occurrences and the counters with an archive from GET /admin/backup
+/// and persists them right away. If the X-Backup-Format-Version header is sent, it has to
+/// match the archive's version.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    request_body(content = Vec<u8>, description = "Archive as returned by GET /admin/backup", content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "The restored archive", body = BackupMetadata),
+        (status = 413, description = "Payload too large", body = ErrorResponse),
+        (status = 422, description = "Invalid archive or unsupported format version", body = ErrorResponse),
+    )
+)]
+#[post("/restore")]
+pub async fn restore_handler(
+    req: HttpRequest,
+    payload: web::Payload,
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(version) = req.headers().get(BACKUP_FORMAT_VERSION_HEADER) {
+        if version.to_str().ok().and_then(|version| version.parse::<u32>().ok()) != Some(backup::FORMAT_VERSION) {
+            return Err(ApiError::Unprocessable(format!(
+                "Unsupported backup format version {:?}, expected {}",
+                version, backup::FORMAT_VERSION
+            )));
+        }
+    }
+    let body = match payload.to_bytes_limited(MAX_RESTORE_PAYLOAD_BYTES).await {
+        Ok(body) => body.map_err(|e| ApiError::BadRequest(e.to_string()))?,
+        Err(_) => return Err(ApiError::PayloadTooLarge(format!("Payload exceeds {} bytes", MAX_RESTORE_PAYLOAD_BYTES))),
+    };
+
+    let counter = counter_data.get_ref().clone();
+    let counters = rotating_counters_data.get_ref().clone();
+    let timezone = settings.current().counters.rotation_timezone;
+    let metadata = web::block(move || {
+        let metadata = backup::restore(&body, &counter, &counters, timezone)?;
+        locks::write(&counters, "rotating_counters").persist();
+        Ok::<_, String>(metadata)
+    })
+    .await?
+    .map_err(ApiError::Unprocessable)?;
+    Ok(HttpResponse::Ok().json(metadata))
+}
+
+/// Clears all rotating counters. The change is persisted with the next rotation.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "Success", body = StatusResponse),
+    )
+)]
+#[post("/counters/reset")]
+pub async fn reset_counters_handler(
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> impl Responder {
+    locks::write(&rotating_counters_data, "rotating_counters").reset();
+    HttpResponse::Ok().json(HashMap::from([("status", "success")]))
+}
+
+/// Returns the full counter state, in the format POST /admin/counters/merge accepts.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    params(("X-Request-Timeout" = Option<u64>, Header, description = "Milliseconds to answer within, else 504")),
+    responses(
+        (status = 200, description = "The full counter state", body = Object),
+        (status = 400, description = "Malformed X-Request-Timeout header", body = ErrorResponse),
+        (status = 504, description = "Not answered within the request timeout", body = ErrorResponse),
+    )
+)]
+#[get("/counters/export")]
+pub async fn export_counters_handler(
+    deadline: Deadline,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> Result<HttpResponse, ApiError> {
+    let counters_lock = locks::read(&rotating_counters_data, "rotating_counters");
+    // Waiting for the lock behind a rotation may have used up the budget
+    deadline.check()?;
+    Ok(HttpResponse::Ok().json(&*counters_lock))
+}
+
+/// Adds another instance's exported counters to this instance's, bucket by bucket.
+/// Both states are advanced to the current time first so their buckets line up; the
+/// result is persisted right away, as merges are not part of the event log.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    request_body(content = Object, description = "Counter state as returned by GET /admin/counters/export"),
+    responses(
+        (status = 200, description = "Success", body = StatusResponse),
+        (status = 400, description = "Malformed JSON", body = ErrorResponse),
+        (status = 413, description = "Payload too large", body = ErrorResponse),
+        (status = 422, description = "Invalid counter state", body = ErrorResponse),
+    )
+)]
+#[post("/counters/merge")]
+pub async fn merge_counters_handler(
+    payload: web::Payload,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    let body = match payload.to_bytes_limited(MAX_MERGE_PAYLOAD_BYTES).await {
+        Ok(body) => body.map_err(|e| ApiError::BadRequest(e.to_string()))?,
+        Err(_) => return Err(ApiError::PayloadTooLarge(format!("Payload exceeds {} bytes", MAX_MERGE_PAYLOAD_BYTES))),
+    };
+    let mut other: Counters = serde_json::from_slice(&body).map_err(JsonPayloadError::Deserialize)?;
+
+    let counters = rotating_counters_data.get_ref().clone();
+    let timezone = settings.counters.rotation_timezone;
+    web::block(move || {
+        let now = determinism::now().with_timezone(&timezone);
+        other.advance_to(&now);
+        let mut counters_lock = locks::write(&counters, "rotating_counters");
+        counters_lock.advance_to(&now);
+        counters_lock.merge(other);
+        counters_lock.persist();
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(HashMap::from([("status", "success")])))
+}
+
+/// Returns the rank and percentile of an identifier within one bucket, e.g. for
+/// "top 1% today" badges.
+#[utoipa::path(
+    tag = "counters",
+    params(("id" = String, Path, description = "The identifier"), CounterRankQuery),
+    responses(
+        (status = 200, description = "Rank of the identifier in the window", body = CounterRankResponse),
+        (status = 400, description = "Unknown window or label language", body = ErrorResponse),
+        (status = 404, description = "The identifier has no count in the window", body = ErrorResponse),
+    )
+)]
+#[get("/counters/{id}/rank")]
+pub async fn get_counter_rank_handler(
+    path: IdentifierPath,
+    query: web::Query<CounterRankQuery>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let window = query.window.clone().unwrap_or_else(|| "today".to_string());
+    let labels = bucket_labels(query.labels.as_deref(), &mut std::iter::once(window.as_str()), &settings.current())?;
+    let counters_lock = locks::read(&rotating_counters_data, "rotating_counters");
+
+    let Some(bucket) = counters_lock.window(&window) else {
+        return Err(ApiError::BadRequest(format!("Unknown window '{}'", window)));
+    };
+    let Some(rank) = rank_in(&bucket, &id) else {
+        return Err(ApiError::NotFound(format!("No count for '{}' in '{}'", id, window)));
+    };
+
+    Ok(HttpResponse::Ok().json(CounterRankResponse { id, window, rank, labels }))
+}
+
+/// Returns when the co-occurrences and the counters first and last saw an identifier, and
+/// when it expires with an eviction TTL, e.g. to tell stale items from new ones.
+#[utoipa::path(
+    tag = "co_occurrence",
+    params(("identifier" = String, Path, description = "The identifier to look up")),
+    responses(
+        (status = 200, description = "First- and last-seen times of the identifier", body = ItemLifecycleResponse),
+        (status = 404, description = "Unknown identifier", body = ErrorResponse),
+        (status = 503, description = "Overloaded, retry after the Retry-After header", body = ErrorResponse),
+    )
+)]
+#[get("/items/{identifier}/lifecycle")]
+pub async fn get_item_lifecycle_handler(
+    path: IdentifierPath,
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    identifier_filter: web::Data<Arc<IdentifierFilter>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    let identifier = path.into_inner();
+    let ttl = (settings.eviction.ttl_days > 0).then(|| chrono::Duration::days(settings.eviction.ttl_days as i64));
+    let seen_times = |first_seen, last_seen: Option<chrono::DateTime<chrono::Utc>>| SeenTimes {
+        first_seen,
+        last_seen,
+        expires_at: last_seen.zip(ttl).map(|(last_seen, ttl)| last_seen + ttl),
+    };
+
+    let co_occurrence = if identifier_filter.may_contain(&identifier) {
+        let counter_lock = read_or_unavailable(&counter_data, "co_occurrence", &settings.overload)?;
+        counter_lock.lifecycle_of(&identifier).map(|(first_seen, last_seen)| {
+            let at = |secs| chrono::DateTime::from_timestamp(secs, 0);
+            seen_times(first_seen.and_then(at), at(last_seen))
+        })
+    } else {
+        None
+    };
+    let counters_lock = locks::read(&rotating_counters_data, "rotating_counters");
+    let counters = counters_lock.first_seen.get(&identifier).map(|first_seen| {
+        // Identifiers counted before first-seen times were kept are backdated to the epoch
+        let first_seen = Some(*first_seen).filter(|&at| at != chrono::DateTime::UNIX_EPOCH);
+        seen_times(first_seen, counters_lock.last_seen.get(&identifier).map(|at| *at))
+    });
+    drop(counters_lock);
+    if co_occurrence.is_none() && counters.is_none() {
+        return Err(unknown_identifier(&identifier));
+    }
+
+    Ok(HttpResponse::Ok().json(ItemLifecycleResponse { identifier, co_occurrence, counters }))
+}
+
+/// Ranks items by relative growth rather than absolute counts, or by their decayed
+/// popularity.
+#[utoipa::path(
+    tag = "trending",
+    params(TrendingQuery),
+    responses(
+        (status = 200, description = "Items ranked by growth or decayed popularity", body = TrendingResponse),
+    )
+)]
+#[get("/trending")]
+pub async fn get_trending_handler(
+    query: web::Query<TrendingQuery>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> impl Responder {
+    let basis = query.basis.unwrap_or(TrendingBasis::Day);
+    let min_count = query.min_count.unwrap_or(DEFAULT_TRENDING_MIN_COUNT);
+    let limit = query.limit.unwrap_or(DEFAULT_TRENDING_LIMIT);
+    let counters_lock = locks::read(&rotating_counters_data, "rotating_counters");
+    let items = trending(&counters_lock, basis, min_count, limit);
+
+    HttpResponse::Ok().json(TrendingResponse { items })
+}
+
+/// Returns the continuously decayed popularity of an identifier: the sum of its plays, each
+/// halved per half-life since, so it changes smoothly instead of at bucket rotations.
+#[utoipa::path(
+    tag = "trending",
+    params(("id" = String, Path, description = "The identifier")),
+    responses(
+        (status = 200, description = "The decayed popularity", body = PopularityResponse),
+        (status = 404, description = "The identifier was never played", body = ErrorResponse),
+    )
+)]
+#[get("/popularity/{id}")]
+pub async fn get_popularity_handler(
+    path: IdentifierPath,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let Some(score) = locks::read(&rotating_counters_data, "rotating_counters").popularity.score(&id, determinism::now()) else {
+        return Err(ApiError::NotFound(format!("No plays of '{}'", id)));
+    };
+    let half_life_secs = settings.current().counters.popularity_half_life_secs;
+
+    Ok(HttpResponse::Ok().json(PopularityResponse { id, score, half_life_secs }))
+}
+
+/// Surfaces identifiers first seen recently, ranked by velocity.
+#[utoipa::path(
+    tag = "trending",
+    params(RisingStarsQuery),
+    responses(
+        (status = 200, description = "New items ranked by velocity", body = RisingStarsResponse),
+    )
+)]
+#[get("/trending/new")]
+pub async fn get_rising_stars_handler(
+    query: web::Query<RisingStarsQuery>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> impl Responder {
+    let max_age_hours = query.max_age_hours.unwrap_or(DEFAULT_RISING_STARS_MAX_AGE_HOURS);
+    let min_count = query.min_count.unwrap_or(DEFAULT_TRENDING_MIN_COUNT);
+    let limit = query.limit.unwrap_or(DEFAULT_TRENDING_LIMIT);
+    let counters_lock = locks::read(&rotating_counters_data, "rotating_counters");
+    let items = rising_stars(&counters_lock, determinism::now(), max_age_hours, min_count, limit);
+
+    HttpResponse::Ok().json(RisingStarsResponse { items })
+}
+
+/// Lists the most recent spikes found by the spike detection.
+#[utoipa::path(
+    tag = "alerts",
+    params(AlertsQuery),
+    responses(
+        (status = 200, description = "Recent spikes, newest first", body = AlertsResponse),
+    )
+)]
+#[get("/alerts")]
+pub async fn get_alerts_handler(
+    query: web::Query<AlertsQuery>,
+    alert_log_data: web::Data<Arc<Mutex<AlertLog>>>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(DEFAULT_ALERTS_LIMIT);
+    let alerts = locks::lock(&alert_log_data, "alert_log").recent(limit);
+
+    HttpResponse::Ok().json(AlertsResponse { alerts })
+}
+
+
+// --- API Handlers (for Transitions) ---
+
+#[utoipa::path(
+    tag = "transitions",
+    request_body = AddSequenceRequest,
+    responses(
+        (status = 200, description = "Success", body = StatusResponse),
+        (status = 422, description = "The body doesn't match the expected shape or violates the identifier limits", body = ErrorResponse),
+    )
+)]
+#[post("/sequences")]
+pub async fn add_sequence_handler(
+    mut req_body: web::Json<AddSequenceRequest>,
+    transitions_data: web::Data<Arc<Mutex<TransitionCounter>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    normalize_list(&mut req_body.identifiers, &settings.validation.normalization);
+    validate_list(&req_body.identifiers, &settings.validation)?;
+    let mut transitions_lock = locks::lock(&transitions_data, "transitions");
+    transitions_lock.process_sequence(&req_body.identifiers);
+    Ok(HttpResponse::Ok().json(HashMap::from([("status", "success")])))
+}
+
+#[utoipa::path(
+    tag = "transitions",
+    params(("identifier" = String, Path, description = "The current item"), NextItemsQuery),
+    responses(
+        (status = 200, description = "Likely next items", body = NextItemsResponse),
+        (status = 404, description = "Unknown identifier", body = ErrorResponse),
+    )
+)]
+#[get("/next/{identifier}")]
+pub async fn get_next_items_handler(
+    path: IdentifierPath,
+    query: web::Query<NextItemsQuery>,
+    transitions_data: web::Data<Arc<Mutex<TransitionCounter>>>,
+) -> Result<HttpResponse, ApiError> {
+    let identifier = path.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_NEXT_ITEMS_LIMIT);
+    let transitions_lock = locks::lock(&transitions_data, "transitions");
+    if !transitions_lock.contains(&identifier) {
+        return Err(ApiError::NotFound(format!("Unknown identifier '{}'", identifier)));
+    }
+    let next_items = transitions_lock.get_next_items(&identifier, limit);
+
+    let response = NextItemsResponse {
+        target_identifier: identifier,
+        next_items,
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// --- API Handlers (for Association Rules) ---
+
+#[utoipa::path(
+    tag = "recommendations",
+    params(RulesQuery),
+    responses(
+        (status = 200, description = "Mined association rules", body = RulesResponse),
+    )
+)]
+#[get("/rules")]
+pub async fn get_rules_handler(
+    query: web::Query<RulesQuery>,
+    rule_set_data: web::Data<Arc<Mutex<RuleSet>>>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(DEFAULT_RULES_LIMIT);
+    let rule_set_lock = locks::lock(&rule_set_data, "rule_set");
+
+    let response = RulesResponse {
+        mined_at: rule_set_lock.mined_at,
+        list_count: rule_set_lock.list_count,
+        rules: rule_set_lock.rules_involving(query.identifier.as_deref(), limit),
+    };
+    HttpResponse::Ok().json(response)
+}
+
+/// Recommends items for a basket of seed identifiers. Mined association rules are
+/// used first, followed by the factorization model (if enabled); remaining slots are
+/// filled with the summed co-occurrence counts of all seeds. The candidates are then
+/// ranked by the scoring pipeline of the endpoint or the requested variant. With
+/// `two_hop=true`, slots still left are filled with neighbors of the strongest neighbors,
+/// which helps long-tail seeds with only a few direct ones. With
+/// `cross_namespace=true`, only items of other namespaces than the seeds' are proposed,
+/// and as those are rarer neighbors, the most played of them today fill the slots left.
+#[utoipa::path(
+    tag = "recommendations",
+    params(
+        RecommendationsQuery,
+        ("X-Request-Timeout" = Option<u64>, Header, description = "Milliseconds to answer within, else 504"),
+    ),
+    request_body = BasketRecommendationRequest,
+    responses(
+        (status = 200, description = "Recommendations for the basket", body = BasketRecommendationsResponse),
+        (status = 400, description = "Unknown scoring variant, min_popularity above max_popularity, or a malformed X-Request-Timeout header", body = ErrorResponse),
+        (status = 422, description = "The body doesn't match the expected shape", body = ErrorResponse),
+        (status = 504, description = "Not answered within the request timeout", body = ErrorResponse),
+    )
+)]
+#[post("/recommendations")]
+pub async fn basket_recommendations_handler(
+    mut req_body: web::Json<BasketRecommendationRequest>,
+    query: web::Query<RecommendationsQuery>,
+    deadline: Deadline,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = state.settings.current();
+    normalize_list(&mut req_body.identifiers, &settings.validation.normalization);
+    normalize_list(&mut req_body.exclude, &settings.validation.normalization);
+    let boosts = locks::read(&state.boosts, "boosts").active(determinism::now());
+    // Experiments are compared with each other, not with the shadow
+    let shadow = query.variant.is_none().then(|| ShadowPipeline::for_endpoint(&settings.scoring, "recommendations", boosts.clone())).flatten();
+    let pipeline = Pipeline::for_endpoint(&settings.scoring, "recommendations", query.variant.as_deref())
+        .map_err(ApiError::BadRequest)?
+        .with_boosts(boosts);
+    let popularity = PopularityRange::new(query.min_popularity, query.max_popularity).map_err(ApiError::BadRequest)?;
+    let limit = req_body.limit.unwrap_or(DEFAULT_RECOMMENDATIONS_LIMIT);
+    // The scoring stages pick from more candidates than they return
+    let pool = limit.saturating_mul(CANDIDATE_POOL_FACTOR);
+    let basket = &req_body.identifiers;
+    if req_body.exclude.len() > settings.validation.max_list_identifiers {
+        return Err(ApiError::Unprocessable(format!("At most {} identifiers can be excluded", settings.validation.max_list_identifiers)));
+    }
+    let excluded: HashSet<&str> = basket.iter().chain(&req_body.exclude).map(String::as_str).collect();
+    let namespaces = query.cross_namespace.unwrap_or(false).then(|| NamespaceFilter::new(basket.iter().map(String::as_str)));
+    let allowed = |identifier: &str| !excluded.contains(identifier) && namespaces.as_ref().is_none_or(|filter| filter.accepts(identifier));
+    // The models may propose excluded items, which would take the place of others
+    let proposals = pool.saturating_add(excluded.len());
+
+    let mut candidates: Vec<Candidate> = locks::lock(&state.rule_set, "rule_set")
+        .recommend_for_basket(basket, proposals)
+        .into_iter()
+        .filter(|(identifier, _)| allowed(identifier.as_str()))
+        .take(pool)
+        .map(|(identifier, score)| Candidate::new(identifier, score, "rules"))
+        .collect();
+
+    if settings.factorization.enabled && candidates.len() < pool {
+        let model = locks::lock(&state.factorization, "factorization").current.clone();
+        if let Some(model) = model {
+            let remaining = pool - candidates.len();
+            let factorization_candidates: Vec<Candidate> = model
+                .recommend_for_basket(basket, proposals)
+                .into_iter()
+                .filter(|(identifier, _)| allowed(identifier.as_str()))
+                .filter(|(identifier, _)| !candidates.iter().any(|c| &c.identifier == identifier))
+                .take(remaining)
+                .map(|(identifier, score)| Candidate::new(identifier, score, "factorization"))
+                .collect();
+            candidates.extend(factorization_candidates);
+        }
+    }
+
+    // Needed for the pair counts of all candidates, not only to fill the remaining slots
+    let mut co_occurrence_scores: HashMap<String, u64> = HashMap::new();
+    deadline.check()?;
+    let counter_lock = locks::read(&state.co_occurrence, "co_occurrence");
+    for seed in basket {
+        deadline.check()?;
+        for (identifier, count) in counter_lock.cached_metrics_for_identifier(seed) {
+            *co_occurrence_scores.entry(identifier).or_insert(0) += count;
+        }
+    }
+    drop(counter_lock);
+
+    if candidates.len() < pool {
+        let mut fallback: Vec<(&String, u64)> = co_occurrence_scores
+            .iter()
+            .map(|(identifier, &count)| (identifier, count))
+            .filter(|(identifier, _)| allowed(identifier.as_str()))
+            .filter(|(identifier, _)| !candidates.iter().any(|c| &c.identifier == *identifier))
+            .collect();
+        fallback.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        fallback.truncate(pool - candidates.len());
+        candidates.extend(fallback.into_iter().map(|(identifier, count)| Candidate::new(identifier.clone(), count as f64, "co_occurrence")));
+    }
+
+    if query.two_hop.unwrap_or(false) && candidates.len() < pool {
+        let second_hop = locks::read(&state.co_occurrence, "co_occurrence").second_hop(basket, &co_occurrence_scores, &settings.two_hop, deadline.instant());
+        let expansion: Vec<Candidate> = second_hop
+            .ok_or(ApiError::DeadlineExceeded)?
+            .into_iter()
+            .filter(|(identifier, _)| allowed(identifier.as_str()))
+            .filter(|(identifier, _)| !candidates.iter().any(|c| &c.identifier == identifier))
+            .take(pool - candidates.len())
+            .map(|(identifier, score)| Candidate::new(identifier, score, "two_hop"))
+            .collect();
+        candidates.extend(expansion);
+    }
+
+    let counters_lock = locks::read(&state.counters, "rotating_counters");
+    if let Some(filter) = namespaces.as_ref().filter(|_| candidates.len() < pool) {
+        let popular = filter.popular(&counters_lock.daily[0], pool - candidates.len(), |identifier| {
+            allowed(identifier) && !candidates.iter().any(|c| c.identifier == identifier)
+        });
+        candidates.extend(popular);
+    }
+    for candidate in candidates.iter_mut() {
+        candidate.pair_count = co_occurrence_scores.get(&candidate.identifier).copied().unwrap_or(0);
+        candidate.popularity = rotating_counters::count_of(&counters_lock.daily[0], &candidate.identifier);
+    }
+    drop(counters_lock);
+    popularity.retain(&mut candidates);
+    let counter_lock = locks::read(&state.co_occurrence, "co_occurrence");
+    for candidate in candidates.iter_mut() {
+        candidate.occurrences = counter_lock.occurrences_of(&candidate.identifier);
+    }
+    drop(counter_lock);
+    let shadow_candidates = shadow.as_ref().map(|_| candidates.clone());
+    pipeline.rank(&mut candidates);
+    // Pins of the seeds' namespaces included
+    candidates.retain(|candidate| allowed(candidate.identifier.as_str()));
+    candidates.truncate(limit);
+    if let (Some(shadow), Some(shadow_candidates)) = (&shadow, shadow_candidates) {
+        let served: Vec<&str> = candidates.iter().map(|candidate| candidate.identifier.as_str()).collect();
+        state.shadow_scoring.compare(shadow, shadow_candidates, allowed, &served, limit, determinism::now());
+    }
+
+    let explain = query.explain.unwrap_or(false);
+    let mut recommendations: Vec<BasketRecommendation> =
+        candidates.into_iter().map(|candidate| BasketRecommendation::from_candidate(candidate, explain)).collect();
+    if explain {
+        normalize_scores(&mut recommendations);
+    }
+
+    Ok(HttpResponse::Ok().json(BasketRecommendationsResponse { recommendations }))
+}
+
+impl BasketRecommendation {
+    fn from_candidate(candidate: Candidate, explain: bool) -> Self {
+        let explanation = explain.then_some(Explanation {
+            metric: match candidate.source {
+                "rules" => "confidence",
+                "factorization" => "dot_product",
+                "editorial" => "pin",
+                "popularity" => "plays_today",
+                "two_hop" => "decayed_pair_count",
+                _ => "pair_count",
+            },
+            ranking_score: candidate.score,
+            pair_count: candidate.pair_count,
+            popularity: candidate.popularity,
+            adjustments: candidate.adjustments,
+        });
+        BasketRecommendation {
+            identifier: candidate.identifier,
+            score: candidate.model_score,
+            source: candidate.source,
+            normalized_score: None,
+            explanation,
+        }
+    }
+}
+
+/// Adds normalized scores to `recommendations`. Scores of different sources measure
+/// different things, so each is scaled by the highest of its source; rule confidences are
+/// in 0–1 already.
+fn normalize_scores(recommendations: &mut [BasketRecommendation]) {
+    let mut highest: HashMap<&'static str, f64> = HashMap::new();
+    for recommendation in recommendations.iter() {
+        let score = highest.entry(recommendation.source).or_insert(0.0);
+        *score = score.max(recommendation.score);
+    }
+    for recommendation in recommendations.iter_mut() {
+        let normalized = match recommendation.source {
+            "rules" => recommendation.score,
+            source if highest[source] > 0.0 => recommendation.score / highest[source],
+            _ => 0.0,
+        };
+        recommendation.normalized_score = Some(normalized.clamp(0.0, 1.0));
+    }
+}
+
+// --- API Handlers (for Embeddings) ---
+
+#[utoipa::path(
+    tag = "embeddings",
+    params(("identifier" = String, Path, description = "The identifier"), SimilarItemsQuery),
+    responses(
+        (status = 200, description = "Nearest neighbors in embedding space", body = SimilarItemsResponse),
+        (status = 404, description = "No embedding for the identifier", body = ErrorResponse),
+    )
+)]
+#[get("/embeddings/{identifier}/similar")]
+pub async fn get_similar_items_handler(
+    path: IdentifierPath,
+    query: web::Query<SimilarItemsQuery>,
+    embeddings_data: web::Data<Arc<Mutex<ItemEmbeddings>>>,
+) -> Result<HttpResponse, ApiError> {
+    let identifier = path.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_SIMILAR_ITEMS_LIMIT);
+    let embeddings_lock = locks::lock(&embeddings_data, "embeddings");
+    // Unknown identifiers and ones too rare to be embedded have no vector
+    let Some(similar) = embeddings_lock.most_similar(&identifier, limit) else {
+        return Err(ApiError::NotFound(format!("No embedding for '{}'", identifier)));
+    };
+
+    let response = SimilarItemsResponse {
+        target_identifier: identifier,
+        trained_at: embeddings_lock.trained_at,
+        similar,
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// --- API Handlers (for Factorization) ---
+
+/// Wakes the factorization training task so a new model version is trained right away.
+/// Training happens in the background; the current model keeps serving until it is done.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 202, description = "Training scheduled", body = TrainResponse),
+    )
+)]
+#[post("/train")]
+pub async fn trigger_training_handler(
+    factorization_data: web::Data<Arc<Mutex<FactorizationState>>>,
+) -> impl Responder {
+    let state_lock = locks::lock(&factorization_data, "factorization");
+    state_lock.train_trigger.notify_one();
+
+    let response = TrainResponse {
+        status: "training scheduled",
+        current_version: state_lock.current.as_ref().map(|model| model.version),
+        current_trained_at: state_lock.current.as_ref().map(|model| model.trained_at),
+    };
+    HttpResponse::Accepted().json(response)
+}
+
+// --- API Handlers (for Runtime Stats) ---
+
+/// Returns rolled-up runtime statistics of this instance since it started.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "Runtime statistics", body = StatsResponse),
+    )
+)]
+#[get("/stats")]
+pub async fn get_stats_handler(
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> impl Responder {
+    let (identifier_count, pair_count) = {
+        let counter_lock = locks::read(&counter_data, "co_occurrence");
+        (counter_lock.identifier_count(), counter_lock.pair_count())
+    };
+    let last_persisted_at = locks::read(&rotating_counters_data, "rotating_counters").last_persisted_at;
+
+    let response = StatsResponse {
+        // Collected last, so the locks taken above are included
+        routes: stats::route_summaries(),
+        lock_waits: stats::lock_summaries(),
+        identifier_count,
+        pair_count,
+        cardinality: stats::cardinality(),
+        seconds_since_last_persistence: last_persisted_at.map(|at| (determinism::now() - at).num_seconds()),
+        snapshots: stats::snapshot_summaries(),
+        recoveries: stats::snapshot_recoveries(),
+        compactions: stats::compaction_summaries(),
+    };
+    HttpResponse::Ok().json(response)
+}
+
+/// Returns the latest accuracy of the co-occurrence recommendations on the held-out lists:
+/// the share of lists and of items predicted from the other items of their list.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "Latest evaluation on the held-out lists", body = EvaluationReport),
+        (status = 404, description = "Not evaluated yet, or no lists are held out", body = ErrorResponse),
+    )
+)]
+#[get("/evaluation")]
+pub async fn get_evaluation_handler(holdout: web::Data<Arc<Holdout>>) -> Result<HttpResponse, ApiError> {
+    let Some(report) = holdout.report() else {
+        return Err(ApiError::NotFound("No held-out lists were evaluated yet".to_string()));
+    };
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Returns how far the rankings of the shadow pipelines (`MEDIATHEK_SCORING_SHADOW`)
+/// agreed with the served ones, per endpoint, since each pipeline was set.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "Agreement of the shadow rankings, empty without shadow pipelines", body = ShadowReport),
+    )
+)]
+#[get("/scoring/shadow")]
+pub async fn get_shadow_scoring_handler(shadow_scoring_data: web::Data<Arc<ShadowScoring>>) -> HttpResponse {
+    HttpResponse::Ok().json(shadow_scoring_data.report())
+}
+
+/// Returns the latest evaluation of the recommendation quality: the coverage, the
+/// popularity bias and the day-over-day churn of the co-occurrence neighbors.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "Latest quality report", body = QualityReport),
+        (status = 404, description = "Not evaluated yet, or evaluations are disabled", body = ErrorResponse),
+    )
+)]
+#[get("/quality")]
+pub async fn get_quality_handler(
+    quality_monitor_data: web::Data<Arc<Mutex<QualityMonitor>>>,
+) -> Result<HttpResponse, ApiError> {
+    let monitor_lock = locks::lock(&quality_monitor_data, "quality_monitor");
+    let Some(report) = monitor_lock.report() else {
+        return Err(ApiError::NotFound("The recommendation quality wasn't evaluated yet".to_string()));
+    };
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Estimates the memory used by the co-occurrence model and the rotating counters.
+/// Both are walked in full, so this takes a moment on large models.
+fn memory_usage(
+    counter_data: &RwLock<CoOccurrenceCounter>,
+    rotating_counters_data: &RwLock<Counters>,
+) -> MemoryResponse {
+    let mut components = BTreeMap::new();
+    {
+        let counter_lock = locks::read(counter_data, "co_occurrence");
+        components.insert("identifier_to_id".to_string(), counter_lock.identifier_map_bytes());
+        components.insert("co_occurrence_counts".to_string(), counter_lock.pair_counts_bytes());
+        components.insert("co_occurrence_cache".to_string(), counter_lock.metrics_cache_bytes());
+        components.insert("identifier_filter".to_string(), counter_lock.identifier_filter().bytes());
+    }
+    {
+        let counters_lock = locks::read(rotating_counters_data, "rotating_counters");
+        for granularity in Granularity::ALL {
+            components.insert(format!("counters_{}", granularity.series_name()), counters_lock.bucket_bytes(granularity));
+        }
+        components.insert("counters_first_seen".to_string(), counters_lock.first_seen_bytes());
+    }
+    let total_bytes = components.values().sum();
+    MemoryResponse { components, total_bytes }
+}
+
+/// Returns the estimated memory used by the in-memory models, per component.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "Estimated memory usage", body = MemoryResponse),
+    )
+)]
+#[get("/memory")]
+pub async fn get_memory_handler(
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+) -> impl Responder {
+    HttpResponse::Ok().json(memory_usage(&counter_data, &rotating_counters_data))
+}
+
+// --- API Handlers (for Snapshots) ---
+
+/// Returns the storage settings of the request's tenant, whose snapshots live in a
+/// directory of their own, or the default ones.
+fn tenant_storage(settings: &SharedSettings, tenant: Option<web::ReqData<RequestTenant>>) -> StorageSettings {
+    match tenant {
+        Some(tenant) => tenant.tenant.storage.clone(),
+        None => settings.current().storage.clone(),
+    }
+}
+
+/// Lists the recorded administrative operations (see `api::audit`), most recent first.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Recorded operations", body = AuditResponse),
+    )
+)]
+#[get("/audit")]
+pub async fn get_audit_handler(query: web::Query<AuditQuery>, audit_log: web::Data<Arc<AuditLog>>) -> Result<HttpResponse, ApiError> {
+    let (since, limit) = (query.since, query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT));
+    let audit_log = audit_log.get_ref().clone();
+    let entries = web::block(move || audit_log.read(since, limit)).await?.map_err(|e| ApiError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(AuditResponse { entries }))
+}
+
+/// Returns the requests per API key over the last days, with the daily quotas (see
+/// `MEDIATHEK_AUTH_DAILY_QUOTAS`). Counted in memory only, so a restart resets them.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "Usage per API key, by name", body = UsageResponse),
+    )
+)]
+#[get("/usage")]
+pub async fn get_usage_handler(usage_meter: web::Data<Arc<UsageMeter>>) -> impl Responder {
+    HttpResponse::Ok().json(UsageResponse { keys: usage_meter.usage(determinism::now()) })
+}
+
+/// Returns what every client ingested in a bucket or window: the lists, their average
+/// length, the plays, the rejected requests and the distinct identifiers, per tenant, API
+/// key and source of the lists. The buckets rotate like the counters', and are kept in
+/// memory only.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    params(IngestStatsQuery),
+    responses(
+        (status = 200, description = "Ingestion per client", body = IngestStatsResponse),
+        (status = 400, description = "Unknown window", body = ErrorResponse),
+    )
+)]
+#[get("/ingest-stats")]
+pub async fn get_ingest_stats_handler(
+    query: web::Query<IngestStatsQuery>,
+    ingest_stats: web::Data<Arc<IngestStats>>,
+) -> Result<HttpResponse, ApiError> {
+    let window = query.into_inner().window.unwrap_or_else(|| "today".to_string());
+    let Some(clients) = ingest_stats.window(&window, determinism::now()) else {
+        return Err(ApiError::BadRequest(format!("Unknown window '{}'", window)));
+    };
+    Ok(HttpResponse::Ok().json(IngestStatsResponse { window, clients }))
+}
+
+/// Lists the retained versions of the counter and co-occurrence snapshots.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "Retained snapshot versions", body = SnapshotVersionsResponse),
+    )
+)]
+#[get("/snapshots")]
+pub async fn get_snapshot_versions_handler(
+    settings: web::Data<Arc<SharedSettings>>,
+    tenant: Option<web::ReqData<RequestTenant>>,
+) -> Result<HttpResponse, ApiError> {
+    let storage = tenant_storage(&settings, tenant);
+    let files = web::block(move || {
+        [rotating_counters::SNAPSHOT_PATH, co_occurrence::SNAPSHOT_PATH]
+            .into_iter()
+            .map(|file| (file.to_string(), snapshot::versions(storage.data_path(file))))
+            .collect()
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(SnapshotVersionsResponse { files }))
+}
+
+/// Replaces the rotating counters or the co-occurrences with a retained version of their
+/// snapshot, which becomes the current snapshot right away. Restored counters are
+/// advanced to the current time like merged ones.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    params(
+        ("file" = String, Path, description = "The snapshot file, as listed by GET /admin/snapshots"),
+        ("version" = String, Path, description = "The version to restore"),
+    ),
+    responses(
+        (status = 200, description = "Success", body = StatusResponse),
+        (status = 404, description = "Unknown file or version", body = ErrorResponse),
+    )
+)]
+#[post("/snapshots/{file}/{version}/restore")]
+pub async fn restore_snapshot_handler(
+    path: web::Path<(String, String)>,
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+    tenant: Option<web::ReqData<RequestTenant>>,
+) -> Result<HttpResponse, ApiError> {
+    let storage = tenant_storage(&settings, tenant);
+    let settings = settings.current();
+    let (file, version) = path.into_inner();
+    if ![rotating_counters::SNAPSHOT_PATH, co_occurrence::SNAPSHOT_PATH].contains(&file.as_str()) {
+        return Err(ApiError::NotFound(format!("Unknown snapshot file '{}'", file)));
+    }
+    let path = storage.data_path(&file);
+    let versions = {
+        let path = path.clone();
+        web::block(move || snapshot::versions(path)).await?
+    };
+    if !versions.iter().any(|retained| retained.version == version) {
+        return Err(ApiError::NotFound(format!("No version '{}' of {}", version, file)));
+    }
+
+    let counter = counter_data.get_ref().clone();
+    let counters = rotating_counters_data.get_ref().clone();
+    let timezone = settings.counters.rotation_timezone;
+    web::block(move || {
+        if file == co_occurrence::SNAPSHOT_PATH {
+            return locks::write(&counter, "co_occurrence").restore(&version);
+        }
+        let mut restored: Counters = snapshot::read_version(&path, &version)?;
+        let now = determinism::now().with_timezone(&timezone);
+        restored.advance_to(&now);
+        let mut counters_lock = locks::write(&counters, "rotating_counters");
+        counters_lock.advance_to(&now);
+        counters_lock.reset();
+        counters_lock.merge(restored);
+        counters_lock.persist();
+        Ok(())
+    })
+    .await?
+    .map_err(ApiError::Internal)?;
+
+    Ok(HttpResponse::Ok().json(HashMap::from([("status", "success")])))
+}
+
+/// Reads the settings again from the config file and the environment, and applies the
+/// ones that can change at runtime (see `SharedSettings::reload_from`), like a SIGHUP.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "Success", body = StatusResponse),
+        (status = 500, description = "The config file can't be read; the current settings stay in place", body = ErrorResponse),
+    )
+)]
+#[post("/reload")]
+pub async fn reload_settings_handler(settings: web::Data<Arc<SharedSettings>>) -> Result<HttpResponse, ApiError> {
+    let settings = settings.get_ref().clone();
+    web::block(move || settings.reload()).await?.map_err(ApiError::Internal)?;
+    Ok(HttpResponse::Ok().json(HashMap::from([("status", "success")])))
+}
+
+/// Moves the clock of deterministic mode (see `MEDIATHEK_DETERMINISTIC`) forward, e.g. to
+/// make an integration test cross an hour boundary. The counters rotate right after.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    request_body = ClockRequest,
+    responses(
+        (status = 200, description = "The time the clock is at now", body = ClockResponse),
+        (status = 400, description = "Neither field is given, or the time is before the clock's", body = ErrorResponse),
+        (status = 404, description = "Deterministic mode is off", body = ErrorResponse),
+    )
+)]
+#[post("/clock")]
+pub async fn set_clock_handler(req_body: web::Json<ClockRequest>) -> Result<HttpResponse, ApiError> {
+    let Some(clock) = determinism::manual_clock() else {
+        return Err(ApiError::NotFound("Deterministic mode is off, the system clock can't be moved".to_string()));
+    };
+    let at = match (req_body.at, req_body.advance_secs) {
+        (Some(at), _) => at,
+        (None, Some(secs)) => clock.now() + chrono::Duration::seconds(secs.min(i64::MAX as u64) as i64),
+        (None, None) => return Err(ApiError::BadRequest("Either \"at\" or \"advance_secs\" is required".to_string())),
+    };
+    let now = clock.set(at).map_err(ApiError::BadRequest)?;
+    Ok(HttpResponse::Ok().json(ClockResponse { now }))
+}
+
+/// Exposes the memory estimates, the last snapshots, the identifiers and their limit, the
+/// ingest queue and the latest quality report as gauges in the Prometheus text format.
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain"),
+    )
+)]
+#[get("/metrics")]
+pub async fn get_prometheus_metrics_handler(
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    quality_monitor_data: web::Data<Arc<Mutex<QualityMonitor>>>,
+    ingest_queue: web::Data<Arc<IngestQueue>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> impl Responder {
+    let usage = memory_usage(&counter_data, &rotating_counters_data);
+
+    let mut body = String::new();
+    body.push_str("# HELP mediathek_memory_bytes Estimated bytes used by a component of the in-memory model.\n");
+    body.push_str("# TYPE mediathek_memory_bytes gauge\n");
+    for (component, bytes) in &usage.components {
+        body.push_str(&format!("mediathek_memory_bytes{{component=\"{}\"}} {}\n", component, bytes));
+    }
+    body.push_str("# HELP mediathek_memory_total_bytes Estimated bytes used by the in-memory model.\n");
+    body.push_str("# TYPE mediathek_memory_total_bytes gauge\n");
+    body.push_str(&format!("mediathek_memory_total_bytes {}\n", usage.total_bytes));
+
+    let snapshots = stats::snapshot_summaries();
+    body.push_str("# HELP mediathek_snapshot_bytes Size on disk of the last snapshot written to a file.\n");
+    body.push_str("# TYPE mediathek_snapshot_bytes gauge\n");
+    for (file, summary) in &snapshots {
+        body.push_str(&format!("mediathek_snapshot_bytes{{file=\"{}\"}} {}\n", file, summary.bytes));
+    }
+    body.push_str("# HELP mediathek_snapshot_uncompressed_bytes Size before compression of the last snapshot written to a file.\n");
+    body.push_str("# TYPE mediathek_snapshot_uncompressed_bytes gauge\n");
+    for (file, summary) in &snapshots {
+        body.push_str(&format!("mediathek_snapshot_uncompressed_bytes{{file=\"{}\"}} {}\n", file, summary.uncompressed_bytes));
+    }
+    body.push_str("# HELP mediathek_snapshot_duration_seconds Time taken to write the last snapshot to a file.\n");
+    body.push_str("# TYPE mediathek_snapshot_duration_seconds gauge\n");
+    for (file, summary) in &snapshots {
+        body.push_str(&format!("mediathek_snapshot_duration_seconds{{file=\"{}\"}} {}\n", file, summary.duration_ms / 1000.0));
+    }
+    let recoveries = stats::snapshot_recoveries();
+    body.push_str("# HELP mediathek_snapshot_recoveries_total Times a snapshot was found corrupt on load.\n");
+    body.push_str("# TYPE mediathek_snapshot_recoveries_total counter\n");
+    for (file, recovery) in &recoveries {
+        body.push_str(&format!("mediathek_snapshot_recoveries_total{{file=\"{}\"}} {}\n", file, recovery.recoveries));
+    }
+    body.push_str("# HELP mediathek_snapshot_unrecovered Whether the last corrupt snapshot of a file had no valid older version, so the state started out empty.\n");
+    body.push_str("# TYPE mediathek_snapshot_unrecovered gauge\n");
+    for (file, recovery) in &recoveries {
+        body.push_str(&format!("mediathek_snapshot_unrecovered{{file=\"{}\"}} {}\n", file, u8::from(recovery.recovered_from.is_none())));
+    }
+
+    let identifier_count = locks::read(&counter_data, "co_occurrence").identifier_count();
+    body.push_str("# HELP mediathek_identifiers Distinct identifiers known to the co-occurrence model.\n");
+    body.push_str("# TYPE mediathek_identifiers gauge\n");
+    body.push_str(&format!("mediathek_identifiers {}\n", identifier_count));
+    let settings = settings.current();
+    if settings.cardinality.max_identifiers > 0 {
+        let cardinality = stats::cardinality();
+        body.push_str("# HELP mediathek_identifiers_limit Distinct identifiers the co-occurrence model keeps at most.\n");
+        body.push_str("# TYPE mediathek_identifiers_limit gauge\n");
+        body.push_str(&format!("mediathek_identifiers_limit {}\n", settings.cardinality.max_identifiers));
+        body.push_str("# HELP mediathek_cardinality_rejected_lists_total Lists rejected for exceeding the limit of distinct identifiers.\n");
+        body.push_str("# TYPE mediathek_cardinality_rejected_lists_total counter\n");
+        body.push_str(&format!("mediathek_cardinality_rejected_lists_total {}\n", cardinality.rejected_lists));
+        body.push_str("# HELP mediathek_cardinality_evicted_identifiers_total Identifiers evicted for exceeding the limit of distinct identifiers.\n");
+        body.push_str("# TYPE mediathek_cardinality_evicted_identifiers_total counter\n");
+        body.push_str(&format!("mediathek_cardinality_evicted_identifiers_total {}\n", cardinality.evicted_identifiers));
+    }
+
+    if ingest_queue.is_enabled() {
+        body.push_str("# HELP mediathek_ingest_queue_depth Lists accepted by POST /lists and not yet counted.\n");
+        body.push_str("# TYPE mediathek_ingest_queue_depth gauge\n");
+        body.push_str(&format!("mediathek_ingest_queue_depth {}\n", ingest_queue.depth()));
+        body.push_str("# HELP mediathek_ingest_queue_capacity Lists the ingest queue holds at most.\n");
+        body.push_str("# TYPE mediathek_ingest_queue_capacity gauge\n");
+        body.push_str(&format!("mediathek_ingest_queue_capacity {}\n", ingest_queue.capacity()));
+        body.push_str("# HELP mediathek_ingest_queue_rejected_total Lists rejected with 429 because the ingest queue was full.\n");
+        body.push_str("# TYPE mediathek_ingest_queue_rejected_total counter\n");
+        body.push_str(&format!("mediathek_ingest_queue_rejected_total {}\n", ingest_queue.rejected()));
+    }
+
+    // Left out until the first evaluation, rather than reported as 0
+    if let Some(report) = locks::lock(&quality_monitor_data, "quality_monitor").report() {
+        body.push_str("# HELP mediathek_quality_coverage Share of the identifiers with enough co-occurring ones.\n");
+        body.push_str("# TYPE mediathek_quality_coverage gauge\n");
+        body.push_str(&format!("mediathek_quality_coverage {}\n", report.coverage));
+        body.push_str("# HELP mediathek_quality_popularity_bias Popularity of the recommended neighbors relative to all identifiers.\n");
+        body.push_str("# TYPE mediathek_quality_popularity_bias gauge\n");
+        body.push_str(&format!("mediathek_quality_popularity_bias {}\n", report.popularity_bias));
+        if let Some(churn) = report.churn {
+            body.push_str("# HELP mediathek_quality_churn Share of the recommended neighbors that are new since the day before.\n");
+            body.push_str("# TYPE mediathek_quality_churn gauge\n");
+            body.push_str(&format!("mediathek_quality_churn {}\n", churn));
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+
+// --- Route Configuration ---
+
+/// Configures the routes of all v1 endpoints, relative to the scope they are mounted in.
+pub fn config_routes(cfg: &mut web::ServiceConfig, settings: &Settings) {
+    cfg.service(add_list_handler)
+       .service(stream_lists_handler)
+       .service(dry_run_list_handler)
+       .service(batch_metrics_handler)
+       .service(get_co_occurrence_metrics_handler) 
+       .service(get_conditional_handler)
+       .service(increment_daily_counter_handler)
+       .service(batch_increment_handler)  
+       .service(get_rotating_counters_handler)
+       // Before /counters/{id}, which would match them as well
+       .service(sse::counter_stream_handler)
+       .service(get_sparklines_handler)
+       .service(get_counter_movers_handler)
+       .service(get_counter_namespaces_handler)
+       .service(get_counter_time_series_handler)
+       .service(get_seasonality_handler)
+       .service(get_minute_series_handler)
+       .service(get_forecast_handler)
+       .service(get_counter_rank_handler)
+       .service(get_item_lifecycle_handler)
+       .service(delete_counter_handler)
+       .service(get_trending_handler)
+       .service(get_popularity_handler)
+       .service(get_rising_stars_handler)
+       .service(ws::trending_ws_handler)
+       .service(get_alerts_handler)
+       .service(add_sequence_handler)
+       .service(get_next_items_handler)
+       .service(get_rules_handler)
+       .service(basket_recommendations_handler)
+       .service(page::get_page_handler)
+       .service(get_similar_items_handler)
+       .service(get_prometheus_metrics_handler)
+       .service(business_metrics::get_business_metrics_handler)
+       .configure(graphql::config_routes)
+       .configure(openapi::config_routes);
+
+    // Left out entirely if disabled, so the admin endpoints answer with 404
+    if settings.admin.enabled {
+        cfg.service(
+            web::scope("/admin")
+                .wrap(middleware::from_fn(auth::require_admin_token))
+                .service(reset_counters_handler)
+                .service(purge_handler)
+                .service(get_tombstones_handler)
+                .service(import_handler)
+                .service(backup_handler)
+                .service(flush_handler)
+                .service(restore_handler)
+                .service(replication::replication_stream_handler)
+                .service(replication::promote_handler)
+                .service(gossip::gossip_handler)
+                .service(export_counters_handler)
+                .service(export::export_counter_history_handler)
+                .service(merge_counters_handler)
+                .service(trigger_training_handler)
+                .service(get_stats_handler)
+                .service(get_quality_handler)
+                .service(get_evaluation_handler)
+                .service(get_shadow_scoring_handler)
+                .service(get_memory_handler)
+                .service(get_snapshot_versions_handler)
+                .service(get_audit_handler)
+                .service(get_usage_handler)
+                .service(get_ingest_stats_handler)
+                .service(restore_snapshot_handler)
+                .service(reload_settings_handler)
+                .service(set_clock_handler)
+                .service(boosts::set_boost_handler)
+                .service(boosts::get_boosts_handler),
+        );
+    }
+}
\ No newline at end of file
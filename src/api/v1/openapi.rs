@@ -0,0 +1,119 @@
+// src/api/v1/openapi.rs
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+
+use super::*;
+
+/// The generated OpenAPI specification of all v1 endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Mediathek Recommendation Server"),
+    servers((url = "/v1")),
+    paths(
+        add_list_handler,
+        stream_lists_handler,
+        dry_run_list_handler,
+        batch_metrics_handler,
+        get_co_occurrence_metrics_handler,
+        get_conditional_handler,
+        increment_daily_counter_handler,
+        batch_increment_handler,
+        get_rotating_counters_handler,
+        sse::counter_stream_handler,
+        get_sparklines_handler,
+        get_counter_movers_handler,
+        get_counter_namespaces_handler,
+        get_counter_time_series_handler,
+        get_seasonality_handler,
+        get_minute_series_handler,
+        get_forecast_handler,
+        get_counter_rank_handler,
+        get_item_lifecycle_handler,
+        delete_counter_handler,
+        get_trending_handler,
+        get_popularity_handler,
+        get_rising_stars_handler,
+        ws::trending_ws_handler,
+        get_alerts_handler,
+        add_sequence_handler,
+        get_next_items_handler,
+        get_rules_handler,
+        basket_recommendations_handler,
+        page::get_page_handler,
+        get_similar_items_handler,
+        graphql::graphql_handler,
+        reset_counters_handler,
+        purge_handler,
+        get_tombstones_handler,
+        get_audit_handler,
+        get_usage_handler,
+        get_ingest_stats_handler,
+        import_handler,
+        backup_handler,
+        flush_handler,
+        restore_handler,
+        replication::replication_stream_handler,
+        replication::promote_handler,
+        gossip::gossip_handler,
+        export_counters_handler,
+        export::export_counter_history_handler,
+        merge_counters_handler,
+        trigger_training_handler,
+        get_stats_handler,
+        get_quality_handler,
+        get_evaluation_handler,
+        get_shadow_scoring_handler,
+        get_memory_handler,
+        get_snapshot_versions_handler,
+        restore_snapshot_handler,
+        reload_settings_handler,
+        set_clock_handler,
+        boosts::set_boost_handler,
+        boosts::get_boosts_handler,
+        get_prometheus_metrics_handler,
+        business_metrics::get_business_metrics_handler,
+    ),
+    tags(
+        (name = "co_occurrence", description = "Ingesting lists and looking up co-occurring items"),
+        (name = "counters", description = "Rotating popularity counters"),
+        (name = "trending", description = "Items growing fastest"),
+        (name = "alerts", description = "Detected counter spikes"),
+        (name = "transitions", description = "Sequence-aware next-item predictions"),
+        (name = "recommendations", description = "Association rules and basket recommendations"),
+        (name = "embeddings", description = "Similar items by learned item vectors"),
+        (name = "graphql", description = "Items, their neighbors and counts in one query"),
+        (name = "admin", description = "Operations and introspection"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Body of write endpoints without further output: `{"status": "success"}`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatusResponse {
+    pub status: String,
+}
+
+/// Serves the OpenAPI specification, e.g. for generating typed clients.
+#[get("/openapi.json")]
+pub async fn get_openapi_handler() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Registers the specification route.
+pub fn config_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_openapi_handler);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_specification_covers_all_routes() {
+        let spec = ApiDoc::openapi();
+        assert_eq!(spec.paths.paths.len(), 59);
+        assert!(spec.paths.paths.contains_key("/counters/{id}/rank"));
+        assert!(spec.components.unwrap().schemas.contains_key("ErrorResponse"));
+    }
+}
@@ -0,0 +1,231 @@
+// src/api/v1/page.rs
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::algorithms::boosts::Boosts;
+use crate::algorithms::rotating_counters::{count_of, CountEntry};
+use crate::algorithms::scoring::{Candidate, NamespaceFilter, Pipeline, PopularityRange};
+use crate::algorithms::shadow::{ShadowPipeline, ShadowScoring};
+use crate::algorithms::trending::{trending, TrendingBasis, TrendingItem};
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::api::error::{ApiError, ErrorResponse};
+use crate::api::validation::{normalize_identifier, IdentifierPath};
+use crate::config::SharedSettings;
+use crate::{determinism, locks};
+
+/// Number of neighbors returned if not given.
+const DEFAULT_PAGE_NEIGHBORS_LIMIT: usize = 10;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// Number of co-occurring items, default 10
+    pub neighbors_limit: Option<usize>,
+    /// Number of trending items, default 20
+    pub trending_limit: Option<usize>,
+    /// Basis of the trending list, as for GET /trending
+    pub basis: Option<TrendingBasis>,
+    /// Scoring pipeline of an experiment variant to rank the neighbors with
+    pub variant: Option<String>,
+    /// Comma-separated items to leave out of the neighbors and trending items, e.g. those
+    /// the user has watched already. Left out before the limits apply.
+    pub exclude: Option<String>,
+    /// Only shows neighbors of other namespaces (the prefix before ':') than the item's,
+    /// filled up with the most played ones of today; the trending items are unaffected
+    pub cross_namespace: Option<bool>,
+    /// Leaves neighbors played fewer times today out
+    pub min_popularity: Option<u64>,
+    /// Leaves neighbors played more times today out
+    pub max_popularity: Option<u64>,
+}
+
+impl PageQuery {
+    fn excluded(&self) -> HashSet<&str> {
+        self.exclude.as_deref().unwrap_or_default().split(',').map(str::trim).filter(|id| !id.is_empty()).collect()
+    }
+}
+
+/// What the counters know about an item of a GET /page response.
+#[derive(Debug, Serialize, ToSchema, PartialEq)]
+pub struct ItemMetadata {
+    /// Plays today
+    pub today: u64,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Struct for the GET /page/{identifier} response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PageResponse {
+    pub target_identifier: String,
+    /// Items most often in the same lists as the target, ranked by the scoring pipeline.
+    /// With `cross_namespace=true`, popular items filling up the neighbors count 0.
+    pub neighbors: Vec<CountEntry>,
+    pub trending: Vec<TrendingItem>,
+    /// Per item of the page, the target included, if it was ever played
+    pub metadata: BTreeMap<String, ItemMetadata>,
+}
+
+/// Assembles the page of `identifier` from the co-occurrences and the counters. The
+/// neighbors are also ranked by the `shadow` pipeline if given, for comparison only.
+fn build_page(
+    co_occurrence: &RwLock<CoOccurrenceCounter>,
+    counters: &RwLock<Counters>,
+    pipeline: &Pipeline,
+    popularity: PopularityRange,
+    shadow: Option<(&ShadowPipeline, &ShadowScoring)>,
+    identifier: String,
+    query: &PageQuery,
+) -> PageResponse {
+    let limit = query.neighbors_limit.unwrap_or(DEFAULT_PAGE_NEIGHBORS_LIMIT);
+    let excluded = query.excluded();
+    let namespaces = query.cross_namespace.unwrap_or(false).then(|| NamespaceFilter::new([identifier.as_str()]));
+    let allowed = |id: &str| id != identifier && !excluded.contains(id) && namespaces.as_ref().is_none_or(|filter| filter.accepts(id));
+    let counter = locks::read(co_occurrence, "co_occurrence");
+    let mut candidates: Vec<Candidate> = counter
+        .cached_metrics_for_identifier(&identifier)
+        .into_iter()
+        .filter(|(id, _)| allowed(id.as_str()))
+        .map(|(id, count)| Candidate { pair_count: count, ..Candidate::new(id, count as f64, "co_occurrence") })
+        .collect();
+    for candidate in candidates.iter_mut() {
+        candidate.occurrences = counter.occurrences_of(&candidate.identifier);
+    }
+    drop(counter);
+    candidates.sort_by(|a, b| b.pair_count.cmp(&a.pair_count).then_with(|| a.identifier.cmp(&b.identifier)));
+    // The scoring stages pick from more candidates than they return
+    let pool = limit.saturating_mul(super::CANDIDATE_POOL_FACTOR);
+    candidates.truncate(pool);
+
+    let counters = locks::read(counters, "rotating_counters");
+    if let Some(filter) = namespaces.as_ref().filter(|_| candidates.len() < pool) {
+        let popular = filter.popular(&counters.daily[0], pool - candidates.len(), |id| allowed(id) && !candidates.iter().any(|c| c.identifier == id));
+        candidates.extend(popular);
+    }
+    for candidate in candidates.iter_mut() {
+        candidate.popularity = count_of(&counters.daily[0], &candidate.identifier);
+    }
+    popularity.retain(&mut candidates);
+    let shadow_candidates = shadow.map(|_| candidates.clone());
+    pipeline.rank(&mut candidates);
+    candidates.retain(|candidate| allowed(candidate.identifier.as_str()));
+    let neighbors: Vec<CountEntry> =
+        candidates.into_iter().take(limit).map(|candidate| CountEntry { id: candidate.identifier, count: candidate.pair_count }).collect();
+    if let (Some((shadow, shadow_scoring)), Some(shadow_candidates)) = (shadow, shadow_candidates) {
+        let served: Vec<&str> = neighbors.iter().map(|entry| entry.id.as_str()).collect();
+        shadow_scoring.compare(shadow, shadow_candidates, allowed, &served, limit, determinism::now());
+    }
+
+    let trending_limit = query.trending_limit.unwrap_or(super::DEFAULT_TRENDING_LIMIT);
+    let mut trending = trending(
+        &counters,
+        query.basis.unwrap_or(TrendingBasis::Day),
+        super::DEFAULT_TRENDING_MIN_COUNT,
+        trending_limit.saturating_add(excluded.len()),
+    );
+    trending.retain(|item| !excluded.contains(item.id.as_str()));
+    trending.truncate(trending_limit);
+
+    let ids = std::iter::once(identifier.as_str())
+        .chain(neighbors.iter().map(|entry| entry.id.as_str()))
+        .chain(trending.iter().map(|item| item.id.as_str()));
+    let mut metadata = BTreeMap::new();
+    for id in ids {
+        let first_seen = counters.first_seen.get(id).map(|at| *at);
+        if first_seen.is_none() || metadata.contains_key(id) {
+            continue;
+        }
+        let item = ItemMetadata {
+            today: count_of(&counters.daily[0], id),
+            first_seen,
+            last_seen: counters.last_seen.get(id).map(|at| *at),
+        };
+        metadata.insert(id.to_string(), item);
+    }
+    drop(counters);
+
+    PageResponse { target_identifier: identifier, neighbors, trending, metadata }
+}
+
+/// Everything an item's page shows in one request: the items co-occurring with it, the
+/// trending items and what the counters know about all of them. Unknown identifiers get a
+/// page without neighbors, as the trending items are still worth showing. The neighbors
+/// are ranked by the scoring pipeline "page" or the requested variant, and with
+/// `cross_namespace=true` limited to other namespaces than the item's.
+#[utoipa::path(
+    tag = "recommendations",
+    params(("identifier" = String, Path, description = "The item of the page"), PageQuery),
+    responses(
+        (status = 200, description = "Neighbors, trending items and their metadata", body = PageResponse),
+        (status = 400, description = "Unknown scoring variant, too many excluded items, or min_popularity above max_popularity", body = ErrorResponse),
+    )
+)]
+#[get("/page/{identifier}")]
+pub async fn get_page_handler(
+    path: IdentifierPath,
+    mut query: web::Query<PageQuery>,
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    boosts_data: web::Data<Arc<RwLock<Boosts>>>,
+    shadow_scoring_data: web::Data<Arc<ShadowScoring>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    if let Some(exclude) = &query.exclude {
+        let normalized = exclude.split(',').map(|id| normalize_identifier(id.trim(), &settings.validation.normalization)).collect::<Vec<_>>().join(",");
+        query.exclude = Some(normalized);
+    }
+    if query.excluded().len() > settings.validation.max_list_identifiers {
+        return Err(ApiError::BadRequest(format!("At most {} identifiers can be excluded", settings.validation.max_list_identifiers)));
+    }
+    let boosts = locks::read(&boosts_data, "boosts").active(determinism::now());
+    let shadow = query.variant.is_none().then(|| ShadowPipeline::for_endpoint(&settings.scoring, "page", boosts.clone())).flatten();
+    let pipeline = Pipeline::for_endpoint(&settings.scoring, "page", query.variant.as_deref())
+        .map_err(ApiError::BadRequest)?
+        .with_boosts(boosts);
+    let popularity = PopularityRange::new(query.min_popularity, query.max_popularity).map_err(ApiError::BadRequest)?;
+    let shadow = shadow.as_ref().map(|shadow| (shadow, &***shadow_scoring_data));
+    Ok(HttpResponse::Ok().json(build_page(&counter_data, &rotating_counters_data, &pipeline, popularity, shadow, path.into_inner(), &query)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ScoringStage;
+
+    #[test]
+    fn test_page_limits_are_independent_and_metadata_covers_every_item() {
+        let mut co_occurrence = CoOccurrenceCounter::new();
+        co_occurrence.process_list(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        co_occurrence.process_list(&["a".to_string(), "b".to_string()]);
+        let counters = Counters::with_depths(3, 13, 4, 3);
+        counters.increment("a", 1);
+        counters.increment("d", 5);
+        counters.increment("e", 4);
+        // Watched already, so left out although trending most
+        counters.increment("f", 6);
+
+        let query = PageQuery {
+            neighbors_limit: Some(1),
+            trending_limit: Some(2),
+            basis: None,
+            variant: None,
+            exclude: Some("f".to_string()),
+            cross_namespace: None,
+            min_popularity: None,
+            max_popularity: None,
+        };
+        let pipeline = Pipeline::new(&[ScoringStage::Similarity]);
+        let page = build_page(&RwLock::new(co_occurrence), &RwLock::new(counters), &pipeline, PopularityRange::default(), None, "a".to_string(), &query);
+        assert_eq!(page.neighbors, vec![CountEntry { id: "b".to_string(), count: 2 }]);
+        assert_eq!(page.trending.iter().map(|item| item.id.as_str()).collect::<Vec<_>>(), ["d", "e"]);
+        // "b" was never played
+        assert_eq!(page.metadata.keys().map(String::as_str).collect::<Vec<_>>(), ["a", "d", "e"]);
+        assert_eq!(page.metadata["d"].today, 5);
+    }
+}
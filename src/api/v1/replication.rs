@@ -0,0 +1,131 @@
+// src/api/v1/replication.rs
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::web::Bytes;
+use actix_web::{get, post, web, HttpResponse};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::algorithms::replication::{self, Change, ReplicationState};
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::api::error::{ApiError, ErrorResponse};
+use crate::config::SharedSettings;
+use crate::locks;
+
+/// Replication role of an instance.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReplicationStatusResponse {
+    /// "primary" or "replica"
+    pub role: String,
+    /// Whether a replica currently receives the primary's changes
+    pub connected: bool,
+    /// Changes a replica applied since it last loaded the primary's whole state
+    pub applied_changes: u64,
+}
+
+impl ReplicationStatusResponse {
+    fn of(state: &ReplicationState) -> Self {
+        ReplicationStatusResponse {
+            role: if state.is_following() { "replica" } else { "primary" }.to_string(),
+            connected: state.is_connected(),
+            applied_changes: state.applied(),
+        }
+    }
+}
+
+/// State of one change stream: the archive to send first, then the changes.
+struct ChangeStream {
+    archive: Option<Vec<u8>>,
+    receiver: broadcast::Receiver<Arc<Change>>,
+    heartbeat: Duration,
+    ended: bool,
+}
+
+impl ChangeStream {
+    /// Returns the next frame, or `None` once the stream ended.
+    async fn next(&mut self) -> Option<Bytes> {
+        if let Some(archive) = self.archive.take() {
+            return Some(replication::frame(&archive));
+        }
+        if self.ended {
+            return None;
+        }
+        let change = match tokio::time::timeout(self.heartbeat, self.receiver.recv()).await {
+            Err(_) => return Some(replication::frame(b"")),
+            Ok(Ok(change)) => change,
+            Ok(Err(RecvError::Lagged(missed))) => {
+                warn!("A replica fell behind by {} changes; it has to load the whole state again.", missed);
+                Arc::new(Change::Resync)
+            }
+            Ok(Err(RecvError::Closed)) => return None,
+        };
+        self.ended = *change == Change::Resync;
+        Some(replication::frame(&serde_json::to_vec(&*change).unwrap_or_default()))
+    }
+}
+
+/// Streams the state to a replica: a backup archive (see GET /admin/backup) followed by
+/// every list, play, removal and rotation applied afterwards. Each frame is a 4 byte
+/// big-endian length followed by as many bytes; changes are JSON, and empty frames are
+/// heartbeats. The stream ends when the replica has to load the whole state again.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "The change stream", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 404, description = "Requested for a tenant, whose state isn't replicated", body = ErrorResponse),
+        (status = 500, description = "The co-occurrences are kept in a database, which can't be replicated", body = ErrorResponse),
+    )
+)]
+#[get("/replication/stream")]
+pub async fn replication_stream_handler(
+    counter_data: web::Data<Arc<RwLock<CoOccurrenceCounter>>>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let counter = counter_data.get_ref().clone();
+    let counters = rotating_counters_data.get_ref().clone();
+    let Some(feed) = locks::read(&counters, "rotating_counters").change_feed().cloned() else {
+        return Err(ApiError::NotFound("Only the state without a tenant is replicated".to_string()));
+    };
+    let (archive, receiver) =
+        web::block(move || replication::subscribe_with_state(&counter, &counters, &feed)).await?.map_err(ApiError::Internal)?;
+    info!("A replica subscribed to the changes.");
+
+    let stream = ChangeStream {
+        archive: Some(archive),
+        receiver,
+        heartbeat: Duration::from_secs(settings.current().replication.heartbeat_secs),
+        ended: false,
+    };
+    let body = futures_util::stream::unfold(stream, |mut stream| async move {
+        let frame = stream.next().await?;
+        Some((Ok::<_, Infallible>(frame), stream))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(body))
+}
+
+/// Promotes a replica to a primary: it stops following the primary and accepts writes
+/// from now on. Does nothing on a primary.
+#[utoipa::path(
+    tag = "admin",
+    context_path = "/admin",
+    responses(
+        (status = 200, description = "The role after the promotion", body = ReplicationStatusResponse),
+    )
+)]
+#[post("/replication/promote")]
+pub async fn promote_handler(state: web::Data<Arc<ReplicationState>>) -> HttpResponse {
+    if state.promote() {
+        info!("Promoted to primary.");
+    }
+    HttpResponse::Ok().json(ReplicationStatusResponse::of(&state))
+}
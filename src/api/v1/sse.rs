@@ -0,0 +1,204 @@
+// src/api/v1/sse.rs
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use actix_web::web::Bytes;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+use crate::algorithms::rotating_counters::count_of;
+use crate::algorithms::Counters;
+use crate::api::error::{ApiError, ErrorResponse};
+use crate::api::validation::{normalize_identifier, validate_identifier};
+use crate::config::SharedSettings;
+use crate::locks;
+
+/// How often subscribed counts are compared with the thresholds.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the stream may be silent before a comment is sent, so proxies keep it open.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CounterStreamQuery {
+    /// Comma-separated identifiers to watch
+    pub ids: String,
+    /// Comma-separated counts; an event is sent whenever a watched count reaches one of them
+    pub thresholds: String,
+    /// The bucket or rolling window the counts are taken from, "today" by default
+    pub window: Option<String>,
+}
+
+/// Data of a `threshold` event: a watched count reached a threshold.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ThresholdEvent {
+    pub id: String,
+    pub window: String,
+    pub threshold: u64,
+    pub count: u64,
+}
+
+/// Data of a `rotation` event: the buckets were rotated, so counts of the current
+/// buckets start over.
+#[derive(Debug, Serialize)]
+pub struct RotationEvent {
+    pub rotated_at: DateTime<Utc>,
+}
+
+/// Returns the thresholds reached by a count going from `previous` to `current`.
+fn crossed(previous: u64, current: u64, thresholds: &[u64]) -> impl Iterator<Item = u64> + '_ {
+    thresholds.iter().copied().filter(move |&threshold| previous < threshold && threshold <= current)
+}
+
+/// Formats an event in the `text/event-stream` format.
+fn event(name: &str, data: &impl Serialize) -> Bytes {
+    let data = serde_json::to_string(data).unwrap_or_default();
+    Bytes::from(format!("event: {}\ndata: {}\n\n", name, data))
+}
+
+/// The state of one open stream.
+struct CounterStream {
+    counters: Arc<RwLock<Counters>>,
+    window: String,
+    ids: Vec<String>,
+    thresholds: Vec<u64>,
+    /// Count of every watched identifier at the last check
+    counts: Vec<u64>,
+    last_rotation_at: Option<DateTime<Utc>>,
+    checks: tokio::time::Interval,
+    last_sent_at: Instant,
+    pending: VecDeque<Bytes>,
+}
+
+impl CounterStream {
+    /// Compares the counts with the last check and queues the events for the changes.
+    fn check(&mut self) {
+        let counters_lock = locks::read(&self.counters, "rotating_counters");
+        if counters_lock.last_rotation_at != self.last_rotation_at {
+            self.last_rotation_at = counters_lock.last_rotation_at;
+            if let Some(rotated_at) = self.last_rotation_at {
+                self.pending.push_back(event("rotation", &RotationEvent { rotated_at }));
+            }
+        }
+        let Some(bucket) = counters_lock.window(&self.window) else {
+            return;
+        };
+        for (id, previous) in self.ids.iter().zip(&mut self.counts) {
+            let count = count_of(&bucket, id);
+            for threshold in crossed(*previous, count, &self.thresholds) {
+                let data = ThresholdEvent { id: id.clone(), window: self.window.clone(), threshold, count };
+                self.pending.push_back(event("threshold", &data));
+            }
+            *previous = count;
+        }
+    }
+
+    /// Waits for the next event, or the next keep-alive comment.
+    async fn next(&mut self) -> Bytes {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                self.last_sent_at = Instant::now();
+                return event;
+            }
+            self.checks.tick().await;
+            self.check();
+            if self.pending.is_empty() && self.last_sent_at.elapsed() >= KEEP_ALIVE_INTERVAL {
+                self.pending.push_back(Bytes::from_static(b": keep-alive\n\n"));
+            }
+        }
+    }
+}
+
+/// Streams live counter changes as Server-Sent Events: a `threshold` event whenever the
+/// count of a watched identifier reaches one of the thresholds, and a `rotation` event
+/// whenever the buckets are rotated. Counts at the time of connecting are the baseline,
+/// so only thresholds reached afterwards are reported.
+#[utoipa::path(
+    tag = "counters",
+    params(CounterStreamQuery),
+    responses(
+        (status = 200, description = "An event stream of `threshold` (ThresholdEvent) and `rotation` (RotationEvent) events", content_type = "text/event-stream"),
+        (status = 400, description = "Invalid identifiers or thresholds, or unknown window", body = ErrorResponse),
+    )
+)]
+#[get("/counters/stream")]
+pub async fn counter_stream_handler(
+    query: web::Query<CounterStreamQuery>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    let ids: Vec<String> = query
+        .ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| normalize_identifier(id, &settings.validation.normalization).into_owned())
+        .collect();
+    if ids.is_empty() || ids.len() > settings.validation.max_list_identifiers {
+        return Err(ApiError::BadRequest(format!(
+            "Between 1 and {} identifiers can be watched",
+            settings.validation.max_list_identifiers
+        )));
+    }
+    for id in &ids {
+        validate_identifier(id, &settings.validation)?;
+    }
+    let thresholds = query
+        .thresholds
+        .split(',')
+        .map(|threshold| threshold.trim().parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ApiError::BadRequest("Thresholds must be comma-separated counts".to_string()))?;
+    let window = query.window.clone().unwrap_or_else(|| "today".to_string());
+
+    let counters = rotating_counters_data.get_ref().clone();
+    let (counts, last_rotation_at) = {
+        let counters_lock = locks::read(&counters, "rotating_counters");
+        let Some(bucket) = counters_lock.window(&window) else {
+            return Err(ApiError::BadRequest(format!("Unknown window '{}'", window)));
+        };
+        (ids.iter().map(|id| count_of(&bucket, id)).collect(), counters_lock.last_rotation_at)
+    };
+
+    let stream = CounterStream {
+        counters,
+        window,
+        ids,
+        thresholds,
+        counts,
+        last_rotation_at,
+        checks: tokio::time::interval(CHECK_INTERVAL),
+        last_sent_at: Instant::now(),
+        // Sent right away, so clients (and proxies) see the stream is open
+        pending: VecDeque::from([Bytes::from_static(b": connected\n\n")]),
+    };
+    let body = futures_util::stream::unfold(stream, |mut stream| async move {
+        let event = stream.next().await;
+        Some((Ok::<_, Infallible>(event), stream))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crossed_thresholds() {
+        let thresholds = [10, 100, 1000];
+        assert_eq!(crossed(5, 150, &thresholds).collect::<Vec<_>>(), [10, 100]);
+        assert_eq!(crossed(9, 10, &thresholds).collect::<Vec<_>>(), [10]);
+        assert_eq!(crossed(10, 99, &thresholds).count(), 0);
+        // Counts dropping after a rotation don't cross anything
+        assert_eq!(crossed(150, 0, &thresholds).count(), 0);
+    }
+}
@@ -0,0 +1,142 @@
+// src/api/v1/ws.rs
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_ws::{Message, Session};
+
+use crate::algorithms::trending::{trending, TrendingBasis, TrendingItem};
+use crate::algorithms::Counters;
+use crate::api::error::{ApiError, ErrorResponse};
+use crate::config::{SharedSettings, WebSocketSettings};
+use crate::locks;
+use super::{TrendingQuery, TrendingResponse, DEFAULT_TRENDING_LIMIT, DEFAULT_TRENDING_MIN_COUNT};
+
+/// Whether `current` differs enough from the list last sent to be pushed: items entered,
+/// left or changed places, or an item's count changed by at least `min_change` (relative).
+fn changed_materially(previous: &[TrendingItem], current: &[TrendingItem], min_change: f64) -> bool {
+    previous.len() != current.len()
+        || previous.iter().zip(current).any(|(previous, current)| {
+            previous.id != current.id
+                || current.current.abs_diff(previous.current) as f64 >= min_change * previous.current.max(1) as f64
+        })
+}
+
+/// Streams the trending list (as in GET /trending, with the same query parameters) over
+/// a WebSocket: once on connect, then whenever it changes materially and at least every
+/// `MEDIATHEK_WS_PUSH_INTERVAL_SECS`. Each message is a JSON `TrendingResponse`.
+#[utoipa::path(
+    tag = "trending",
+    params(TrendingQuery),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol; messages are TrendingResponse objects", body = TrendingResponse),
+        (status = 400, description = "Not a WebSocket handshake", body = ErrorResponse),
+    )
+)]
+#[get("/ws")]
+pub async fn trending_ws_handler(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<TrendingQuery>,
+    rotating_counters_data: web::Data<Arc<RwLock<Counters>>>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    let (response, session, mut messages) =
+        actix_ws::handle(&req, body).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let query = query.into_inner();
+    let counters = rotating_counters_data.get_ref().clone();
+    let settings = settings.websocket.clone();
+
+    actix_web::rt::spawn(async move {
+        let push = push_trending(session.clone(), query, counters, settings);
+        // Answer pings until the client goes away; the pushes stop with the connection
+        let receive = async {
+            let mut session = session;
+            while let Some(Ok(message)) = messages.recv().await {
+                match message {
+                    Message::Ping(data) if session.pong(&data).await.is_err() => return,
+                    Message::Close(reason) => {
+                        let _ = session.close(reason).await;
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        };
+        tokio::select! {
+            _ = push => {}
+            _ = receive => {}
+        }
+    });
+    Ok(response)
+}
+
+/// Sends the trending list to `session` as described on `trending_ws_handler`, until the
+/// session is closed.
+async fn push_trending(
+    mut session: Session,
+    query: TrendingQuery,
+    counters: Arc<RwLock<Counters>>,
+    settings: WebSocketSettings,
+) {
+    let basis = query.basis.unwrap_or(TrendingBasis::Day);
+    let min_count = query.min_count.unwrap_or(DEFAULT_TRENDING_MIN_COUNT);
+    let limit = query.limit.unwrap_or(DEFAULT_TRENDING_LIMIT);
+    let push_interval = (settings.push_interval_secs > 0).then(|| Duration::from_secs(settings.push_interval_secs));
+
+    let mut checks = tokio::time::interval(Duration::from_secs(settings.check_interval_secs));
+    let mut sent: Option<(Vec<TrendingItem>, Instant, u64)> = None;
+    loop {
+        checks.tick().await;
+        let due = match &sent {
+            None => true,
+            Some((_, sent_at, _)) => push_interval.is_some_and(|interval| sent_at.elapsed() >= interval),
+        };
+        let (version, items) = {
+            let counters_lock = locks::read(&counters, "rotating_counters");
+            let version = counters_lock.version();
+            // Nothing to compare if no counter changed since the last check
+            if !due && sent.as_ref().is_some_and(|(_, _, sent_version)| *sent_version == version) {
+                continue;
+            }
+            (version, trending(&counters_lock, basis, min_count, limit))
+        };
+
+        match &mut sent {
+            Some((previous, _, sent_version)) if !due && !changed_materially(previous, &items, settings.min_change) => {
+                // Keep comparing with the list sent, so small changes add up until they are material
+                *sent_version = version;
+                continue;
+            }
+            _ => {}
+        }
+        let response = TrendingResponse { items };
+        let Ok(message) = serde_json::to_string(&response) else {
+            continue;
+        };
+        if session.text(message).await.is_err() {
+            return;
+        }
+        sent = Some((response.items, Instant::now(), version));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, current: u64) -> TrendingItem {
+        TrendingItem { id: id.to_string(), score: 1.0, current, baseline: 0.0 }
+    }
+
+    #[test]
+    fn test_changed_materially() {
+        let sent = vec![item("a", 100), item("b", 50)];
+        assert!(!changed_materially(&sent, &[item("a", 105), item("b", 54)], 0.1));
+        assert!(changed_materially(&sent, &[item("a", 110), item("b", 50)], 0.1));
+        assert!(changed_materially(&sent, &[item("b", 100), item("a", 50)], 0.1));
+        assert!(changed_materially(&sent, &[item("a", 100)], 0.1));
+        assert!(changed_materially(&[], &[item("a", 3)], 0.1));
+    }
+}
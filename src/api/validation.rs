@@ -0,0 +1,222 @@
+// src/api/validation.rs
+use std::borrow::Cow;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+
+use crate::api::error::ApiError;
+use crate::config::{CaseFold, NormalizationSettings, SharedSettings, ValidationSettings};
+
+/// Brings `identifier` into its canonical form: percent-decoded, trimmed, case-folded and
+/// rewritten as configured. Borrows `identifier` if nothing changed.
+pub fn normalize_identifier<'a>(identifier: &'a str, settings: &NormalizationSettings) -> Cow<'a, str> {
+    let mut normalized = Cow::Borrowed(identifier);
+    if settings.percent_decode && identifier.contains('%') {
+        if let Some(decoded) = percent_decode(identifier).filter(|decoded| decoded != identifier) {
+            normalized = Cow::Owned(decoded);
+        }
+    }
+    if settings.trim && normalized.trim() != normalized {
+        normalized = Cow::Owned(normalized.trim().to_string());
+    }
+    match settings.case_fold {
+        CaseFold::None => {}
+        CaseFold::Scheme => {
+            if let Some((scheme, rest)) = normalized.split_once(':') {
+                if scheme.chars().any(char::is_uppercase) {
+                    normalized = Cow::Owned(format!("{}:{}", scheme.to_lowercase(), rest));
+                }
+            }
+        }
+        CaseFold::All => {
+            if normalized.chars().any(char::is_uppercase) {
+                normalized = Cow::Owned(normalized.to_lowercase());
+            }
+        }
+    }
+    for (pattern, replacement) in &settings.rewrites.0 {
+        let rewritten = match pattern.replace_all(&normalized, replacement.as_str()) {
+            Cow::Owned(rewritten) => Some(rewritten),
+            Cow::Borrowed(_) => None,
+        };
+        if let Some(rewritten) = rewritten {
+            normalized = Cow::Owned(rewritten);
+        }
+    }
+    normalized
+}
+
+/// Normalizes every identifier of `identifiers` in place, see `normalize_identifier`.
+pub fn normalize_list(identifiers: &mut [String], settings: &NormalizationSettings) {
+    if !settings.is_enabled() {
+        return;
+    }
+    for identifier in identifiers {
+        let normalized = match normalize_identifier(identifier, settings) {
+            Cow::Owned(normalized) => Some(normalized),
+            Cow::Borrowed(_) => None,
+        };
+        if let Some(normalized) = normalized {
+            *identifier = normalized;
+        }
+    }
+}
+
+/// Decodes the %XX escapes of `s`; `None` if the result isn't UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// The `{identifier}` of a route in its canonical form, see `normalize_identifier`. Used
+/// like `web::Path<String>`.
+#[derive(Debug)]
+pub struct IdentifierPath(String);
+
+impl IdentifierPath {
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl FromRequest for IdentifierPath {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<IdentifierPath, actix_web::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let path = web::Path::<String>::from_request(req, payload);
+        let settings = req.app_data::<web::Data<Arc<SharedSettings>>>().map(|settings| settings.current());
+        Box::pin(async move {
+            let identifier = path.await?.into_inner();
+            Ok(IdentifierPath(match settings {
+                Some(settings) => normalize_identifier(&identifier, &settings.validation.normalization).into_owned(),
+                None => identifier,
+            }))
+        })
+    }
+}
+
+/// Checks a single identifier against the configured length limit and pattern.
+pub fn validate_identifier(identifier: &str, settings: &ValidationSettings) -> Result<(), ApiError> {
+    if identifier.len() > settings.max_identifier_length {
+        return Err(ApiError::Unprocessable(format!(
+            "Identifier '{}...' exceeds {} bytes",
+            truncate(identifier, 32),
+            settings.max_identifier_length
+        )));
+    }
+    if !settings.identifier_pattern.is_match(identifier) {
+        return Err(ApiError::Unprocessable(format!(
+            "Identifier '{}' doesn't match {}",
+            identifier,
+            settings.identifier_pattern
+        )));
+    }
+    Ok(())
+}
+
+fn validate_length(length: usize, settings: &ValidationSettings) -> Result<(), ApiError> {
+    if length > settings.max_list_identifiers {
+        return Err(ApiError::Unprocessable(format!(
+            "List has {} identifiers, at most {} are allowed",
+            length,
+            settings.max_list_identifiers
+        )));
+    }
+    Ok(())
+}
+
+/// Checks the length of a list and every identifier in it.
+pub fn validate_list<S: AsRef<str>>(identifiers: &[S], settings: &ValidationSettings) -> Result<(), ApiError> {
+    validate_length(identifiers.len(), settings)?;
+    identifiers.iter().try_for_each(|identifier| validate_identifier(identifier.as_ref(), settings))
+}
+
+/// Like `validate_list`, but returns every violation rather than the first.
+pub fn list_violations<S: AsRef<str>>(identifiers: &[S], settings: &ValidationSettings) -> Vec<String> {
+    validate_length(identifiers.len(), settings)
+        .err()
+        .into_iter()
+        .chain(identifiers.iter().filter_map(|identifier| validate_identifier(identifier.as_ref(), settings).err()))
+        .map(|e| e.to_string())
+        .collect()
+}
+
+/// Returns at most the first `max_chars` characters of `s`.
+fn truncate(s: &str, max_chars: usize) -> &str {
+    s.char_indices().nth(max_chars).map_or(s, |(end, _)| &s[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn settings() -> ValidationSettings {
+        ValidationSettings {
+            max_list_identifiers: 3,
+            max_identifier_length: 16,
+            identifier_pattern: Regex::new("^(ard|zdf):[a-z0-9-]+$").unwrap(),
+            normalization: NormalizationSettings::default(),
+        }
+    }
+
+    fn list(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_limits_and_pattern() {
+        let settings = settings();
+        assert!(validate_list(&list(&["ard:a", "zdf:b-1"]), &settings).is_ok());
+        assert!(validate_list(&list(&["ard:a", "ard:b", "ard:c", "ard:d"]), &settings).is_err());
+        assert!(validate_identifier("ard:much-too-long-identifier", &settings).is_err());
+        assert!(validate_identifier("arte:a", &settings).is_err());
+        assert!(validate_identifier("ard:with space", &settings).is_err());
+        assert_eq!(list_violations(&list(&["ard:a", "arte:b", "ard:c", "ard:D"]), &settings).len(), 3);
+    }
+
+    #[test]
+    fn test_normalization() {
+        let settings = NormalizationSettings {
+            percent_decode: true,
+            trim: true,
+            case_fold: CaseFold::Scheme,
+            rewrites: "^arte:([a-z0-9-]+)-de$=>arte:$1".parse().unwrap(),
+        };
+        for variant in ["ARD:xyz", "ard:xyz ", "ard%3Axyz", "%20Ard%3axyz"] {
+            assert_eq!(normalize_identifier(variant, &settings), "ard:xyz");
+        }
+        // Only the scheme is case-folded, and broken escapes are kept
+        assert_eq!(normalize_identifier("ZDF:AbC", &settings), "zdf:AbC");
+        assert_eq!(normalize_identifier("ard:100%", &settings), "ard:100%");
+        assert_eq!(normalize_identifier("arte:abc-de", &settings), "arte:abc");
+        assert!(matches!(normalize_identifier("ard:xyz", &settings), Cow::Borrowed(_)));
+
+        let mut identifiers = list(&["ARD:a", "ard:b"]);
+        normalize_list(&mut identifiers, &settings);
+        assert_eq!(identifiers, ["ard:a", "ard:b"]);
+        assert_eq!(normalize_identifier(" ARD:a", &NormalizationSettings::default()), " ARD:a");
+    }
+}
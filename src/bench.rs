@@ -0,0 +1,227 @@
+// src/bench.rs
+use std::time::{Duration, Instant};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::algorithms::rotating_counters::Granularity;
+use crate::config::Settings;
+use crate::engine::RecommendationEngine;
+use crate::locks;
+use crate::stats::{Histogram, LatencySummary};
+
+// Synthetic load against a fresh in-memory engine, to get numbers before capacity changes.
+// Sessions are drawn from a catalog whose popularity follows a Zipf distribution, as real
+// viewing does: the identifier at rank k is picked with a weight of 1/k^exponent. Every
+// session is added as a list, and each of its identifiers as a play. Queries of related
+// items and of the most played ones follow the same distribution, and are interleaved
+// with the ingest, so they see the model as it grows.
+
+/// Number of recommendations requested per query.
+const QUERY_LIMIT: usize = 10;
+/// Number of memory samples taken during the ingest, evenly spread.
+const MEMORY_SAMPLES: usize = 10;
+
+/// What to generate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchOptions {
+    pub sessions: usize,
+    /// Size of the catalog the identifiers are drawn from
+    pub identifiers: usize,
+    /// Exponent of the Zipf distribution; 0 picks every identifier equally often
+    pub exponent: f64,
+    /// Average number of identifiers per session
+    pub session_length: usize,
+    /// Queries per 100 sessions
+    pub query_rate: usize,
+    /// Seed of the generator, so runs can be compared
+    pub seed: u64,
+}
+
+/// Measurements of a benchmark run.
+#[derive(Debug)]
+pub struct BenchReport {
+    pub sessions: usize,
+    pub plays: usize,
+    /// Time spent adding lists and plays, without the queries
+    pub ingest: Duration,
+    pub list_latency: LatencySummary,
+    pub play_latency: LatencySummary,
+    pub related_latency: LatencySummary,
+    pub top_latency: LatencySummary,
+    /// Estimated bytes of the models after every tenth of the sessions, with the number of
+    /// sessions ingested so far
+    pub memory: Vec<(usize, usize)>,
+    /// Distinct identifiers the sessions contained
+    pub distinct_identifiers: usize,
+}
+
+impl BenchReport {
+    /// Lists and plays ingested per second.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.ingest.as_secs_f64();
+        if seconds > 0.0 { (self.sessions + self.plays) as f64 / seconds } else { 0.0 }
+    }
+}
+
+/// Draws ranks from a Zipf distribution over `0..len`, by a binary search over the
+/// cumulative weights.
+struct Zipf {
+    cumulative: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(len: usize, exponent: f64) -> Self {
+        let mut total = 0.0;
+        let cumulative = (1..=len.max(1))
+            .map(|rank| {
+                total += 1.0 / (rank as f64).powf(exponent);
+                total
+            })
+            .collect();
+        Zipf { cumulative }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> usize {
+        let total = self.cumulative.last().copied().unwrap_or_default();
+        let target = rng.random::<f64>() * total;
+        self.cumulative.partition_point(|&weight| weight <= target).min(self.cumulative.len() - 1)
+    }
+}
+
+fn identifier(rank: usize) -> String {
+    format!("item-{}", rank)
+}
+
+/// Estimated bytes of the co-occurrences and counters of `engine`, as GET /admin/memory
+/// reports them.
+fn model_bytes(engine: &RecommendationEngine) -> usize {
+    let co_occurrence = {
+        let counter = locks::read(engine.co_occurrence_counter(), "co_occurrence");
+        counter.identifier_map_bytes() + counter.pair_counts_bytes() + counter.metrics_cache_bytes()
+    };
+    let counters = locks::read(engine.counters(), "rotating_counters");
+    let buckets: usize = Granularity::ALL.into_iter().map(|granularity| counters.bucket_bytes(granularity)).sum();
+    co_occurrence + buckets + counters.first_seen_bytes()
+}
+
+fn timed(histogram: &mut Histogram, f: impl FnOnce()) -> Duration {
+    let started = Instant::now();
+    f();
+    let elapsed = started.elapsed();
+    histogram.record(elapsed);
+    elapsed
+}
+
+/// Runs the synthetic load of `options` against an engine with the counter settings of
+/// `settings`.
+pub fn bench(settings: &Settings, options: &BenchOptions) -> BenchReport {
+    let engine = RecommendationEngine::in_memory(&settings.counters);
+    let zipf = Zipf::new(options.identifiers, options.exponent);
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let (mut lists, mut plays, mut related, mut top) = (Histogram::default(), Histogram::default(), Histogram::default(), Histogram::default());
+    let mut ingest = Duration::ZERO;
+    let mut play_count = 0;
+    let mut seen = vec![false; options.identifiers.max(1)];
+    let mut memory = Vec::with_capacity(MEMORY_SAMPLES + 1);
+    memory.push((0, model_bytes(&engine)));
+    let sample_every = options.sessions.div_ceil(MEMORY_SAMPLES).max(1);
+
+    for session in 1..=options.sessions {
+        // Lengths vary uniformly around the average
+        let length = rng.random_range(1..=options.session_length.max(1) * 2 - 1);
+        let ranks: Vec<usize> = (0..length).map(|_| zipf.sample(&mut rng)).collect();
+        let identifiers: Vec<String> = ranks.iter().map(|&rank| identifier(rank)).collect();
+        for &rank in &ranks {
+            seen[rank] = true;
+        }
+
+        ingest += timed(&mut lists, || engine.add_list(&identifiers));
+        for id in &identifiers {
+            ingest += timed(&mut plays, || engine.record_play(id, 1));
+            play_count += 1;
+        }
+
+        let queries = (session * options.query_rate / 100) - ((session - 1) * options.query_rate / 100);
+        for _ in 0..queries {
+            let seed = identifier(zipf.sample(&mut rng));
+            timed(&mut related, || drop(engine.related(&seed, QUERY_LIMIT)));
+            timed(&mut top, || drop(engine.top("today", QUERY_LIMIT)));
+        }
+
+        if session % sample_every == 0 || session == options.sessions {
+            memory.push((session, model_bytes(&engine)));
+        }
+    }
+
+    BenchReport {
+        sessions: options.sessions,
+        plays: play_count,
+        ingest,
+        list_latency: lists.summary(),
+        play_latency: plays.summary(),
+        related_latency: related.summary(),
+        top_latency: top.summary(),
+        memory,
+        distinct_identifiers: seen.iter().filter(|&&seen| seen).count(),
+    }
+}
+
+fn print_latency(name: &str, summary: &LatencySummary) {
+    println!(
+        "  {}: {} calls, mean {:.3} ms, p50 {:.3} ms, p95 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+        name, summary.count, summary.mean_ms, summary.p50_ms, summary.p95_ms, summary.p99_ms, summary.max_ms
+    );
+}
+
+/// Runs the `bench` subcommand and prints the report.
+pub fn run(settings: &Settings, options: &BenchOptions) -> Result<(), String> {
+    if options.identifiers == 0 || options.session_length == 0 {
+        return Err("The catalog and the sessions need at least one identifier".to_string());
+    }
+    println!(
+        "Generating {} sessions over {} identifiers (Zipf exponent {}, seed {})",
+        options.sessions, options.identifiers, options.exponent, options.seed
+    );
+    let report = bench(settings, options);
+
+    println!("Ingest: {} lists and {} plays in {:.3} s, {:.0} per second", report.sessions, report.plays, report.ingest.as_secs_f64(), report.throughput());
+    print_latency("Lists", &report.list_latency);
+    print_latency("Plays", &report.play_latency);
+    println!("Queries:");
+    print_latency("Related", &report.related_latency);
+    print_latency("Top today", &report.top_latency);
+    println!("Memory (estimated), {} distinct identifiers:", report.distinct_identifiers);
+    for (sessions, bytes) in &report.memory {
+        println!("  after {:>10} sessions: {:>14} bytes", sessions, bytes);
+    }
+    if let (Some((_, first)), Some((sessions, last))) = (report.memory.first(), report.memory.last()) {
+        if *sessions > 0 {
+            println!("  growth: {:.0} bytes per session", (*last as f64 - *first as f64) / *sessions as f64);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zipf_favors_the_first_ranks_and_bench_covers_every_session() {
+        let zipf = Zipf::new(100, 1.0);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts = vec![0; 100];
+        for _ in 0..10_000 {
+            counts[zipf.sample(&mut rng)] += 1;
+        }
+        assert!(counts[0] > counts[9] && counts[9] > counts[99]);
+
+        let options = BenchOptions { sessions: 50, identifiers: 100, exponent: 1.0, session_length: 4, query_rate: 10, seed: 7 };
+        let report = bench(&Settings::from_env(), &options);
+        assert_eq!(report.list_latency.count, 50);
+        assert_eq!(report.play_latency.count as usize, report.plays);
+        assert_eq!(report.related_latency.count, 5);
+        assert_eq!(report.memory.len(), MEMORY_SAMPLES + 1);
+        assert!(report.memory.last().unwrap().1 > report.memory[0].1);
+    }
+}
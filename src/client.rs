@@ -0,0 +1,155 @@
+// src/client.rs
+use reqwest::{RequestBuilder, Response, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+pub use crate::algorithms::rotating_counters::CountEntry;
+pub use crate::api::v1::{AddListRequest, CoOccurrenceMetricsResponse, CounterWindowResponse, IncrementCounterRequest};
+
+// Typed client of the v1 HTTP API, covering the same calls as the gRPC service. The
+// request and response structs are the ones the server uses, so both sides can't drift
+// apart.
+
+/// Name of the header the API key is sent in.
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// A failed call.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server couldn't be reached, or its response couldn't be read
+    Http(reqwest::Error),
+    /// The server rejected the request, with the `code` and `message` of its error body
+    Api { status: u16, code: String, message: String },
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "{}", e),
+            ClientError::Api { status, code, message } => write!(f, "{} ({}): {}", status, code, message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+/// Error body of the server, see `api::error::ErrorResponse`.
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: String,
+    message: String,
+}
+
+/// Client of one server, or one tenant of it. Cheap to clone; clones share the
+/// connection pool.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: Url,
+    api_key: Option<String>,
+}
+
+impl Client {
+    /// Creates a client of the server at `base_url`, e.g. "http://localhost:3030" or
+    /// "http://localhost:3030/t/acme" for a tenant.
+    pub fn new(base_url: &str) -> Result<Self, String> {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Like `new`, with a preconfigured `reqwest::Client`, e.g. for timeouts or TLS.
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Result<Self, String> {
+        let mut base_url = Url::parse(base_url).map_err(|e| format!("Invalid base URL '{}': {}", base_url, e))?;
+        if base_url.cannot_be_a_base() {
+            return Err(format!("Invalid base URL '{}'", base_url));
+        }
+        // Paths are appended to the base path, which needs a trailing slash for that
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        Ok(Client { http, base_url, api_key: None })
+    }
+
+    /// Sends `key` with every request, as configured in `MEDIATHEK_API_KEYS`.
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Records a list of identifiers that occurred together (POST /v1/lists).
+    pub async fn add_list(&self, identifiers: &[String]) -> Result<(), ClientError> {
+        let body = AddListRequest { identifiers: identifiers.to_vec(), timestamps: None, source: None };
+        self.send(self.http.post(self.url(&["lists"])).json(&body)).await?;
+        Ok(())
+    }
+
+    /// Returns the identifiers co-occurring with `identifier` and their counts
+    /// (GET /v1/lists/{identifier}).
+    pub async fn get_recommendations(&self, identifier: &str) -> Result<CoOccurrenceMetricsResponse, ClientError> {
+        self.json(self.http.get(self.url(&["lists", identifier]))).await
+    }
+
+    /// Adds `count` plays of `id`, 1 if `None` (POST /v1/counters).
+    pub async fn increment(&self, id: &str, count: Option<u64>) -> Result<(), ClientError> {
+        let body = IncrementCounterRequest { id: id.to_string(), count };
+        self.send(self.http.post(self.url(&["counters"])).json(&body)).await?;
+        Ok(())
+    }
+
+    /// Returns a counter window like "today" as a ranked list, `limit` entries from
+    /// `offset` on (GET /v1/counters?window=...).
+    pub async fn get_counters(&self, window: &str, limit: Option<usize>, offset: Option<usize>) -> Result<CounterWindowResponse, ClientError> {
+        let mut query = vec![("window", window.to_string())];
+        query.extend(limit.map(|limit| ("limit", limit.to_string())));
+        query.extend(offset.map(|offset| ("offset", offset.to_string())));
+        self.json(self.http.get(self.url(&["counters"])).query(&query)).await
+    }
+
+    /// The URL of a v1 route, with `segments` percent-encoded.
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut().expect("checked in new").pop_if_empty().push("v1").extend(segments);
+        url
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let request = match &self.api_key {
+            Some(key) => request.header(API_KEY_HEADER, key),
+            None => request,
+        };
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.bytes().await?;
+        let (code, message) = match serde_json::from_slice::<ErrorBody>(&body) {
+            Ok(error) => (error.code, error.message),
+            Err(_) => (String::new(), String::from_utf8_lossy(&body).into_owned()),
+        };
+        Err(ClientError::Api { status: status.as_u16(), code, message })
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ClientError> {
+        Ok(self.send(request).await?.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_urls_keep_the_base_path_and_encode_identifiers() {
+        let client = Client::new("http://localhost:3030/t/acme").unwrap();
+        assert_eq!(client.url(&["lists", "ard:a/b c"]).as_str(), "http://localhost:3030/t/acme/v1/lists/ard:a%2Fb%20c");
+        let client = Client::new("http://localhost:3030").unwrap();
+        assert_eq!(client.url(&["counters"]).as_str(), "http://localhost:3030/v1/counters");
+        assert!(Client::new("localhost").is_err());
+    }
+}
@@ -0,0 +1,2405 @@
+// src/config.rs
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use std::{env, fs};
+use actix_web::web;
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand};
+use regex::Regex;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+use crate::locks;
+
+/// Runtime settings of the server.
+///
+/// Every value is taken from the first of these that sets it (see `Settings::load`):
+///
+/// 1. the command line: `--bind`, `--port` and `--data-dir`, and any other setting as
+///    `--set <key>=<value>`, e.g. `--set counters.hourly_buckets=48`
+/// 2. the environment variable documented next to the field
+/// 3. the TOML config file given by `--config` or `MEDIATHEK_CONFIG`
+/// 4. the default documented next to the field
+///
+/// In the config file and `--set`, a setting is named by its environment variable without
+/// the `MEDIATHEK_` prefix, in lower case. Tables and dots stand for underscores, so
+/// `MEDIATHEK_COUNTERS_HOURLY_BUCKETS` can be `hourly_buckets = 48` in a `[counters]`
+/// table. Arrays are joined into comma-separated lists. Values that aren't parseable fall
+/// back to the default as well.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub server: ServerSettings,
+    /// Maximum number of ingested lists kept for offline mining passes
+    /// (`MEDIATHEK_RECENT_LISTS_CAPACITY`, default 10000).
+    pub recent_lists_capacity: usize,
+    /// How much more a list of a given `source` counts towards the pair counts than one
+    /// without, e.g. "playlist=4;continue_watching=2" (`MEDIATHEK_SOURCE_WEIGHTS`, default:
+    /// none, so every list counts once).
+    pub source_weights: SourceWeights,
+    /// What happens to test content, internal or deprecated identifiers in ingested lists
+    /// and plays, e.g. "prefix:test:=>drop" (`MEDIATHEK_INGEST_RULES`, default: none).
+    pub ingest_rules: IngestRules,
+    /// Which pairs of a list are counted, to bound the work of long lists: "all", or e.g.
+    /// "sample:5000", "window:10" or "chunk:50" (`MEDIATHEK_LISTS_PAIR_STRATEGY`, default "all").
+    pub pair_strategy: PairStrategy,
+    /// Seconds apart items of lists with timestamps may have been seen to count as a pair,
+    /// on top of the pair strategy (`MEDIATHEK_LISTS_CO_VISITATION_WINDOW_SECS`, default
+    /// 1800; 0 ignores the timestamps). Lists without timestamps count all their pairs.
+    pub co_visitation_window_secs: u64,
+    pub metrics_cache: MetricsCacheSettings,
+    pub identifier_filter: IdentifierFilterSettings,
+    pub counters: CounterSettings,
+    pub storage: StorageSettings,
+    pub association_rules: AssociationRuleSettings,
+    pub quality: QualitySettings,
+    pub evaluation: EvaluationSettings,
+    pub embeddings: EmbeddingSettings,
+    pub factorization: FactorizationSettings,
+    pub alerts: AlertSettings,
+    pub digest: DigestSettings,
+    pub logging: LogSettings,
+    pub validation: ValidationSettings,
+    pub rate_limit: RateLimitSettings,
+    pub overload: OverloadSettings,
+    pub auth: AuthSettings,
+    pub admin: AdminSettings,
+    pub allowlist: AllowlistSettings,
+    pub signing: SigningSettings,
+    pub tls: TlsSettings,
+    pub compression: CompressionSettings,
+    pub grpc: GrpcSettings,
+    pub websocket: WebSocketSettings,
+    pub kafka: KafkaSettings,
+    pub nats: NatsSettings,
+    pub object_storage: ObjectStorageSettings,
+    pub tenants: TenantSettings,
+    pub eviction: EvictionSettings,
+    pub compaction: CompactionSettings,
+    pub replication: ReplicationSettings,
+    pub shards: ShardSettings,
+    pub determinism: DeterminismSettings,
+    pub scoring: ScoringSettings,
+    pub idempotency: IdempotencySettings,
+    pub session_dedup: SessionDedupSettings,
+    pub warmup: WarmupSettings,
+    pub tombstones: TombstoneSettings,
+    pub recorder: RecorderSettings,
+    pub ingest_queue: IngestQueueSettings,
+    pub cardinality: CardinalitySettings,
+    pub two_hop: TwoHopSettings,
+    pub business_metrics: BusinessMetricsSettings,
+}
+
+/// Settings for the HTTP listener.
+#[derive(Debug, Clone)]
+pub struct ServerSettings {
+    /// Address the HTTP and gRPC APIs listen on (`MEDIATHEK_BIND_ADDRESS` or `--bind`,
+    /// default 127.0.0.1).
+    pub bind_address: IpAddr,
+    /// Port of the HTTP API (`MEDIATHEK_PORT` or `--port`, default 3030).
+    pub port: u16,
+    /// Number of HTTP worker threads (`MEDIATHEK_WORKERS`, default 0, which starts one
+    /// per physical CPU core).
+    pub workers: usize,
+    /// Seconds an idle connection is kept open for further requests
+    /// (`MEDIATHEK_KEEP_ALIVE_SECS`, default 5; 0 closes it after every response).
+    pub keep_alive_secs: u64,
+    /// Milliseconds a client has to send the request headers before it gets a 408
+    /// (`MEDIATHEK_CLIENT_TIMEOUT_MS`, default 5000; 0 waits forever).
+    pub client_timeout_ms: u64,
+    /// Seconds running requests get to finish on shutdown before they are cancelled and
+    /// the state is persisted (`MEDIATHEK_SHUTDOWN_TIMEOUT_SECS`, default 30). Should stay
+    /// well below the time the service manager waits before killing the process.
+    pub shutdown_timeout_secs: u64,
+    /// Unix domain socket to serve plain HTTP on as well, e.g. for a reverse proxy on the
+    /// same host (`MEDIATHEK_SOCKET_PATH`, default: none). A stale socket file left by an
+    /// earlier run is replaced. Clients on the socket have no IP address, so allowlists
+    /// and rate limits only tell them apart with `MEDIATHEK_ALLOWLIST_TRUST_PROXY` and
+    /// `MEDIATHEK_RATE_LIMIT_TRUST_PROXY`.
+    pub socket_path: Option<PathBuf>,
+    /// Permissions of the socket, in octal (`MEDIATHEK_SOCKET_MODE`, default 660).
+    pub socket_mode: FileMode,
+    /// Whether to serve only on the socket and not on `bind_address` and `port`
+    /// (`MEDIATHEK_SOCKET_ONLY`, default false).
+    pub socket_only: bool,
+}
+
+/// Unix file permissions, written in octal like "660".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMode(pub u32);
+
+impl FromStr for FileMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match u32::from_str_radix(value, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(FileMode(mode)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Command-line flags, see `Settings`.
+#[derive(Debug, Parser)]
+#[command(version, about = "Recommendation server of the Mediathek app")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML file with settings, overridden by the environment and the flags
+    /// [env: MEDIATHEK_CONFIG]
+    #[arg(long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
+    /// Address to listen on [env: MEDIATHEK_BIND_ADDRESS]
+    #[arg(long, value_name = "ADDRESS", global = true)]
+    bind: Option<String>,
+    /// Port of the HTTP API [env: MEDIATHEK_PORT]
+    #[arg(long, global = true)]
+    port: Option<String>,
+    /// Directory of all persisted files [env: MEDIATHEK_DATA_DIR]
+    #[arg(long, value_name = "PATH", global = true)]
+    data_dir: Option<String>,
+    /// NDJSON or CSV file of historical lists and plays to import before serving
+    /// [env: MEDIATHEK_IMPORT_PATH]
+    #[arg(long, value_name = "PATH", global = true)]
+    import: Option<String>,
+    /// Any setting by its name in the config file, e.g. `counters.hourly_buckets=48`
+    #[arg(long = "set", short = 's', value_name = "KEY=VALUE", global = true)]
+    settings: Vec<String>,
+    /// The port as the only argument, as in earlier versions
+    #[arg(hide = true, conflicts_with = "port")]
+    legacy_port: Option<String>,
+}
+
+/// What the binary does. Everything but `serve` and `replay` works offline, without a
+/// running server.
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Run the server (the default)
+    Serve,
+    /// Print the size, counts and top entries of a co-occurrence or counter snapshot
+    Inspect {
+        path: PathBuf,
+        /// Number of top entries to print
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Print the most played identifiers in a window of a counter snapshot
+    Top {
+        path: PathBuf,
+        /// Bucket or window, e.g. "today", "last_hour" or "last_24h"
+        #[arg(long, default_value = "today")]
+        window: String,
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Write a snapshot in another encoding, e.g. JSON as compressed MessagePack
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        /// "json" or "msgpack"
+        #[arg(long, value_parser = parse_snapshot_format)]
+        format: SnapshotFormat,
+        /// zstd level from 1 to 22, 0 leaves the output uncompressed
+        #[arg(long, default_value_t = 0)]
+        compression_level: i32,
+    },
+    /// Replay recorded event logs against a fresh engine with the current settings and
+    /// print how well it predicted the lists and plays
+    Simulate {
+        /// Event logs like co_occurrences.log and rotating_counters.log
+        #[arg(required = true)]
+        logs: Vec<PathBuf>,
+        /// Number of recommendations evaluated per prediction
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        /// Replay at this many times the recorded speed; 0 replays as fast as possible
+        #[arg(long, default_value_t = 0.0)]
+        speed: f64,
+    },
+    /// Generate synthetic sessions against a fresh engine with the current settings and
+    /// print ingest throughput, query latencies and memory growth
+    Bench {
+        #[arg(long, default_value_t = 100_000)]
+        sessions: usize,
+        /// Size of the catalog the sessions are drawn from
+        #[arg(long, default_value_t = 10_000)]
+        identifiers: usize,
+        /// Exponent of the Zipf distribution of the identifiers' popularity
+        #[arg(long, default_value_t = 1.0)]
+        exponent: f64,
+        /// Average number of identifiers per session
+        #[arg(long, default_value_t = 5)]
+        session_length: usize,
+        /// Related and top queries per 100 sessions
+        #[arg(long, default_value_t = 10)]
+        query_rate: usize,
+        /// Seed of the generator, so runs can be compared
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+    /// Send recorded ingest requests (see `MEDIATHEK_RECORDER_ENABLED`) to a running
+    /// instance, e.g. a local one to reproduce an issue of production
+    Replay {
+        /// Recordings like requests.log.1 and requests.log, replayed in the given order
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Base URL of the instance
+        #[arg(long, default_value = "http://127.0.0.1:3030")]
+        url: String,
+        /// API key to send the requests with, if the instance requires one
+        #[arg(long)]
+        api_key: Option<String>,
+        /// Replay at this many times the recorded speed; 0 replays as fast as possible
+        #[arg(long, default_value_t = 0.0)]
+        speed: f64,
+    },
+}
+
+impl Command {
+    /// Reads the subcommand from the command line `args` (starting with the program name),
+    /// `Serve` if there is none. Exits with a usage message on invalid flags and `--help`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        Cli::parse_from(args).command.unwrap_or(Command::Serve)
+    }
+}
+
+fn parse_snapshot_format(value: &str) -> Result<SnapshotFormat, String> {
+    value.parse().map_err(|_| format!("Unknown snapshot format '{}', expected \"json\" or \"msgpack\"", value))
+}
+
+/// Settings for the cache of co-occurrence lookups.
+#[derive(Debug, Clone)]
+pub struct MetricsCacheSettings {
+    /// Number of identifiers whose co-occurrences are cached (`MEDIATHEK_METRICS_CACHE_CAPACITY`,
+    /// default 10000; 0 disables the cache).
+    pub capacity: usize,
+    /// Seconds a cached result is served at most, even if no list changed it
+    /// (`MEDIATHEK_METRICS_CACHE_TTL_SECS`, default 60).
+    pub ttl_secs: u64,
+}
+
+/// Settings for the Bloom filter answering lookups of unknown identifiers without taking
+/// the co-occurrence lock.
+#[derive(Debug, Clone)]
+pub struct IdentifierFilterSettings {
+    /// Number of identifiers the filter is sized for; beyond that, more unknown ones slip
+    /// through to the regular lookup (`MEDIATHEK_IDENTIFIER_FILTER_CAPACITY`, default
+    /// 1000000; 0 disables the filter).
+    pub capacity: usize,
+    /// Share of unknown identifiers slipping through at capacity
+    /// (`MEDIATHEK_IDENTIFIER_FILTER_FALSE_POSITIVE_RATE`, default 0.01).
+    pub false_positive_rate: f64,
+}
+
+/// Settings for the rotating popularity counters.
+#[derive(Debug, Clone)]
+pub struct CounterSettings {
+    /// Number of hourly buckets, including the current hour (`MEDIATHEK_COUNTERS_HOURLY_BUCKETS`,
+    /// default 3, or 48 if `MEDIATHEK_COUNTERS_EXTENDED_HOURLY` is true). With at least 24/48
+    /// buckets, the rolling "last_24h"/"last_48h" windows become available.
+    pub hourly_buckets: usize,
+    /// Number of daily buckets, including today (`MEDIATHEK_COUNTERS_DAILY_BUCKETS`, default 13).
+    /// With at least 7/12 buckets, the rolling "last_7d"/"last_12d" windows become available.
+    pub daily_buckets: usize,
+    /// Number of weekly aggregate buckets, including this week (`MEDIATHEK_COUNTERS_WEEKLY_BUCKETS`, default 4).
+    pub weekly_buckets: usize,
+    /// Number of monthly aggregate buckets, including this month (`MEDIATHEK_COUNTERS_MONTHLY_BUCKETS`, default 3).
+    pub monthly_buckets: usize,
+    /// IANA time zone in which hour/day/week/month boundaries are detected
+    /// (`MEDIATHEK_ROTATION_TIMEZONE`, e.g. "Europe/Berlin", default: the host's time zone).
+    pub rotation_timezone: Tz,
+    /// Whether every change is appended to an event log that is replayed on startup, so a crash
+    /// doesn't lose the changes since the last snapshot (`MEDIATHEK_COUNTERS_EVENT_LOG`, default true).
+    pub event_log: bool,
+    /// Number of event log entries written before they are fsynced (`MEDIATHEK_COUNTERS_EVENT_LOG_SYNC_BATCH`, default 32).
+    pub event_log_sync_batch: usize,
+    /// Where the counts are shared besides memory, "memory" (not shared), "redis" or
+    /// "gossip" (`MEDIATHEK_COUNTERS_BACKEND`, default "memory").
+    pub backend: CounterBackend,
+    /// Redis server of the "redis" backend (`MEDIATHEK_REDIS_URL`, default "redis://127.0.0.1/").
+    pub redis_url: String,
+    /// Prefix of the Redis keys, so several deployments can share a server
+    /// (`MEDIATHEK_REDIS_KEY_PREFIX`, default "mediathek:counters").
+    pub redis_key_prefix: String,
+    /// Base URLs of the other instances of the "gossip" backend, e.g.
+    /// "http://node-2:3030,http://node-3:3030" (`MEDIATHEK_COUNTERS_GOSSIP_PEERS`, default:
+    /// none). Their admin token has to be the same as this instance's.
+    pub gossip_peers: Vec<String>,
+    /// Name of this instance among its gossip peers, which has to be unique and stay the
+    /// same across restarts (`MEDIATHEK_COUNTERS_GOSSIP_NODE_ID`, default: the host name and
+    /// the port).
+    pub gossip_node_id: String,
+    /// Seconds between two reloads of the shared counts into memory, or two gossip rounds,
+    /// i.e. how long counts of other instances take to show up
+    /// (`MEDIATHEK_COUNTERS_SYNC_INTERVAL_SECS`, default 5, at least 1).
+    pub sync_interval_secs: u64,
+    /// Seconds between two snapshots of changed counters (`MEDIATHEK_COUNTERS_PERSIST_INTERVAL_SECS`,
+    /// default 0, which writes them at the hour boundaries, along with the rotation).
+    pub persist_interval_secs: u64,
+    /// Number of per-minute buckets, including the current minute, e.g. 120 during live
+    /// events (`MEDIATHEK_COUNTERS_MINUTE_BUCKETS`, default 0, which disables them). They
+    /// are kept in memory only.
+    pub minute_buckets: usize,
+    /// Seconds after which a play counts half towards the decayed popularity score of
+    /// GET /popularity/{id} (`MEDIATHEK_COUNTERS_POPULARITY_HALF_LIFE_SECS`, default 86400).
+    pub popularity_half_life_secs: u64,
+    /// Display labels of the buckets and windows returned with `?labels=<language>`, on top
+    /// of the built-in German and English ones, e.g. "de.today=Heute;de.day_minus_{n}=vor {n} Tagen"
+    /// (`MEDIATHEK_COUNTERS_LABELS`, default: none).
+    pub labels: BucketLabels,
+}
+
+/// Display labels of the counter buckets by language and bucket name, parsed from
+/// "<language>.<bucket>=<label>;...". Names of older buckets can be given once for all of
+/// them with "{n}" in place of their index, which is then replaced in the label as well.
+#[derive(Debug, Clone, Default)]
+pub struct BucketLabels(pub HashMap<String, HashMap<String, String>>);
+
+impl BucketLabels {
+    /// Whether labels in `language` are known, configured or built in.
+    pub fn knows(&self, language: &str) -> bool {
+        self.0.contains_key(language) || builtin_bucket_label(language, "today").is_some()
+    }
+
+    /// The label of the bucket or window `name` in `language`, or the name itself if there is
+    /// none.
+    pub fn label(&self, language: &str, name: &str) -> String {
+        let configured = self.0.get(language);
+        if let Some(label) = configured.and_then(|labels| labels.get(name)).cloned().or_else(|| builtin_bucket_label(language, name).map(str::to_string)) {
+            return label;
+        }
+        // "day_minus_3" as "day_minus_{n}"
+        let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+        if prefix.len() < name.len() {
+            let pattern = format!("{}{{n}}", prefix);
+            let template = configured.and_then(|labels| labels.get(&pattern).map(String::as_str)).or_else(|| builtin_bucket_label(language, &pattern));
+            if let Some(template) = template {
+                return template.replace("{n}", &name[prefix.len()..]);
+            }
+        }
+        name.to_string()
+    }
+}
+
+impl FromStr for BucketLabels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut labels = BucketLabels::default();
+        for entry in s.split(';').filter(|entry| !entry.trim().is_empty()) {
+            let (key, label) = entry.split_once('=').ok_or("expected <language>.<bucket>=<label>")?;
+            let (language, bucket) = key.trim().split_once('.').ok_or_else(|| format!("expected <language>.<bucket>, got '{}'", key.trim()))?;
+            labels.0.entry(language.to_string()).or_default().insert(bucket.to_string(), label.trim().to_string());
+        }
+        Ok(labels)
+    }
+}
+
+/// The built-in labels, for the languages of the editorial tools.
+fn builtin_bucket_label(language: &str, name: &str) -> Option<&'static str> {
+    let label = match (language, name) {
+        ("de", "this_hour") => "diese Stunde",
+        ("de", "last_hour") => "letzte Stunde",
+        ("de", "hour_minus_{n}") => "vor {n} Stunden",
+        ("de", "today") => "heute",
+        ("de", "yesterday") => "gestern",
+        ("de", "day_minus_{n}") => "vor {n} Tagen",
+        ("de", "this_week") => "diese Woche",
+        ("de", "last_week") => "letzte Woche",
+        ("de", "week_minus_{n}") => "vor {n} Wochen",
+        ("de", "this_month") => "dieser Monat",
+        ("de", "last_month") => "letzter Monat",
+        ("de", "month_minus_{n}") => "vor {n} Monaten",
+        ("de", "last_24h") => "letzte 24 Stunden",
+        ("de", "last_48h") => "letzte 48 Stunden",
+        ("de", "last_7d") => "letzte 7 Tage",
+        ("de", "last_12d") => "letzte 12 Tage",
+        ("en", "this_hour") => "this hour",
+        ("en", "last_hour") => "last hour",
+        ("en", "hour_minus_{n}") => "{n} hours ago",
+        ("en", "today") => "today",
+        ("en", "yesterday") => "yesterday",
+        ("en", "day_minus_{n}") => "{n} days ago",
+        ("en", "this_week") => "this week",
+        ("en", "last_week") => "last week",
+        ("en", "week_minus_{n}") => "{n} weeks ago",
+        ("en", "this_month") => "this month",
+        ("en", "last_month") => "last month",
+        ("en", "month_minus_{n}") => "{n} months ago",
+        ("en", "last_24h") => "last 24 hours",
+        ("en", "last_48h") => "last 48 hours",
+        ("en", "last_7d") => "last 7 days",
+        ("en", "last_12d") => "last 12 days",
+        _ => return None,
+    };
+    Some(label)
+}
+
+/// Settings for persisting the state in snapshot files or a database.
+#[derive(Clone)]
+pub struct StorageSettings {
+    /// Directory all persisted files are kept in, created on startup if missing
+    /// (`MEDIATHEK_DATA_DIR` or `--data-dir`, default: the working directory). Relative
+    /// database paths below are resolved against it as well.
+    pub data_dir: PathBuf,
+    /// SQLite database every change to the co-occurrences and the counter buckets is written
+    /// to (`MEDIATHEK_SQLITE_PATH`, default: none, which keeps the co-occurrences in memory
+    /// only and the counters in snapshots). Counter buckets shared through Redis stay there.
+    pub sqlite_path: Option<PathBuf>,
+    /// PostgreSQL database shared by all instances, e.g. "postgres://user:password@db/mediathek"
+    /// (`MEDIATHEK_POSTGRES_URL`, default: none). Takes precedence over `sqlite_path`.
+    pub postgres_url: Option<String>,
+    /// Size of the PostgreSQL connection pool (`MEDIATHEK_POSTGRES_MAX_CONNECTIONS`, default 4).
+    pub postgres_max_connections: u32,
+    /// Milliseconds between two batched writes to PostgreSQL, i.e. how many changes a crash
+    /// can lose at most (`MEDIATHEK_POSTGRES_FLUSH_INTERVAL_MS`, default 1000, at least 10).
+    pub postgres_flush_interval_ms: u64,
+    /// Directory of an embedded database holding the co-occurrence matrix on disk instead of
+    /// in memory, so it may outgrow the RAM (`MEDIATHEK_PAIRS_PATH`, default: none). Takes
+    /// precedence over the databases above for the co-occurrences.
+    pub pairs_path: Option<PathBuf>,
+    /// Number of identifiers whose co-occurrences are kept in memory, and updated in place
+    /// by new lists (`MEDIATHEK_PAIRS_HOT_CACHE`, default 10000).
+    pub pairs_hot_cache: usize,
+    /// Megabytes of the embedded database's page cache (`MEDIATHEK_PAIRS_PAGE_CACHE_MB`,
+    /// default 256).
+    pub pairs_page_cache_mb: u64,
+    /// Whether processed lists are appended to a write-ahead log that is replayed on startup,
+    /// when no database records them (`MEDIATHEK_LISTS_WAL`, default true).
+    pub lists_wal: bool,
+    /// Number of logged lists after which the write-ahead log is synced to disk
+    /// (`MEDIATHEK_LISTS_WAL_SYNC_BATCH`, default 32).
+    pub lists_wal_sync_batch: usize,
+    /// Seconds between two snapshots of the co-occurrences, each of which empties the
+    /// write-ahead log (`MEDIATHEK_LISTS_SNAPSHOT_INTERVAL_SECS`, default 3600, at least 1).
+    pub lists_snapshot_interval_secs: u64,
+    /// Number of snapshots of the co-occurrences written as deltas, holding only the pairs
+    /// changed since the previous one, before the next one is a full snapshot again
+    /// (`MEDIATHEK_LISTS_COMPACTION_DELTAS`, default 24; 0 writes full snapshots only).
+    pub lists_compaction_deltas: usize,
+    /// How the snapshots of the counters and the co-occurrences are written.
+    pub snapshots: SnapshotSettings,
+    /// Where the snapshot of the counters is kept, see `algorithms::state_store`: "file"
+    /// in the data directory, or "memory", which loses it on restart
+    /// (`MEDIATHEK_STATE_BACKEND`, default "file").
+    pub state_backend: StateBackend,
+    /// File of historical lists and plays imported on startup, before the server starts
+    /// serving, so a fresh instance isn't cold (`MEDIATHEK_IMPORT_PATH` or `--import`,
+    /// default: none). See `ingest::import` for the formats. Imported again on every
+    /// start it is given for, so it is best only passed on the command line.
+    pub import_path: Option<PathBuf>,
+}
+
+impl StorageSettings {
+    /// Resolves a path of a persisted file against the data directory. Absolute paths are
+    /// kept as they are.
+    pub fn data_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        // Keeps the names of files in the working directory as short as they used to be
+        if self.data_dir == Path::new(".") {
+            return path.as_ref().to_path_buf();
+        }
+        self.data_dir.join(path)
+    }
+}
+
+impl std::fmt::Debug for StorageSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StorageSettings")
+            .field("data_dir", &self.data_dir)
+            .field("sqlite_path", &self.sqlite_path)
+            .field("postgres_url", &self.postgres_url.as_ref().map(|_| "<redacted>"))
+            .field("postgres_max_connections", &self.postgres_max_connections)
+            .field("postgres_flush_interval_ms", &self.postgres_flush_interval_ms)
+            .field("pairs_path", &self.pairs_path)
+            .field("pairs_hot_cache", &self.pairs_hot_cache)
+            .field("pairs_page_cache_mb", &self.pairs_page_cache_mb)
+            .field("lists_wal", &self.lists_wal)
+            .field("lists_wal_sync_batch", &self.lists_wal_sync_batch)
+            .field("lists_snapshot_interval_secs", &self.lists_snapshot_interval_secs)
+            .field("lists_compaction_deltas", &self.lists_compaction_deltas)
+            .field("snapshots", &self.snapshots)
+            .field("state_backend", &self.state_backend)
+            .field("import_path", &self.import_path)
+            .finish()
+    }
+}
+
+/// Settings for writing snapshots. All formats and compressions are recognized on load,
+/// so they can be changed at any time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SnapshotSettings {
+    /// Encoding, "json" or the faster and smaller "msgpack" (`MEDIATHEK_SNAPSHOT_FORMAT`,
+    /// default "json").
+    pub format: SnapshotFormat,
+    /// zstd level the snapshots are compressed with, from 1 (fastest) to 22 (smallest)
+    /// (`MEDIATHEK_SNAPSHOT_COMPRESSION_LEVEL`, default 0, which leaves them uncompressed).
+    pub compression_level: i32,
+    /// Number of the most recent snapshots kept as timestamped versions next to the
+    /// current one (`MEDIATHEK_SNAPSHOT_KEEP_LAST`, default 0).
+    pub keep_last: usize,
+    /// Number of days whose last snapshot is kept as well (`MEDIATHEK_SNAPSHOT_KEEP_DAILY`,
+    /// default 0).
+    pub keep_daily: usize,
+    /// Number of weeks whose last snapshot is kept as well (`MEDIATHEK_SNAPSHOT_KEEP_WEEKLY`,
+    /// default 0). Without any of the three, no versions are kept.
+    pub keep_weekly: usize,
+}
+
+impl SnapshotSettings {
+    /// Whether timestamped versions of the snapshots are kept.
+    pub fn keeps_versions(&self) -> bool {
+        self.keep_last > 0 || self.keep_daily > 0 || self.keep_weekly > 0
+    }
+}
+
+/// Storage backends of the rotating counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterBackend {
+    /// Counts only live in this process, and its snapshots or database
+    Memory,
+    /// Counts are shared through Redis, one hash per bucket
+    Redis,
+    /// Counts are exchanged with the peers directly (see `algorithms::gossip`)
+    Gossip,
+}
+
+impl FromStr for CounterBackend {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "memory" => Ok(CounterBackend::Memory),
+            "redis" => Ok(CounterBackend::Redis),
+            "gossip" => Ok(CounterBackend::Gossip),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Storage backends of the persisted state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateBackend {
+    /// Files in the data directory
+    File,
+    /// Only this process's memory, e.g. for tests
+    Memory,
+}
+
+impl FromStr for StateBackend {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "file" => Ok(StateBackend::File),
+            "memory" => Ok(StateBackend::Memory),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Encodings of the snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SnapshotFormat {
+    #[default]
+    Json,
+    /// MessagePack, behind a header telling it apart from JSON
+    MessagePack,
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(SnapshotFormat::Json),
+            "msgpack" => Ok(SnapshotFormat::MessagePack),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Settings for the Apriori-style association rule mining.
+#[derive(Debug, Clone)]
+pub struct AssociationRuleSettings {
+    /// Minimum share of lists an itemset must appear in (`MEDIATHEK_RULES_MIN_SUPPORT`, default 0.01).
+    pub min_support: f64,
+    /// Minimum confidence of a rule (`MEDIATHEK_RULES_MIN_CONFIDENCE`, default 0.2).
+    pub min_confidence: f64,
+    /// Largest itemset (antecedent + consequent) considered (`MEDIATHEK_RULES_MAX_ITEMSET_SIZE`, default 3).
+    pub max_itemset_size: usize,
+    /// Seconds between two mining passes (`MEDIATHEK_RULES_MINING_INTERVAL_SECS`, default 600).
+    pub mining_interval_secs: u64,
+}
+
+/// Settings for the evaluation of the recommendation quality behind GET /admin/quality.
+#[derive(Debug, Clone)]
+pub struct QualitySettings {
+    /// Seconds between two evaluations (`MEDIATHEK_QUALITY_INTERVAL_SECS`, default 3600; 0
+    /// disables them). Each one reads all pairs, like a snapshot.
+    pub interval_secs: u64,
+    /// Co-occurring identifiers an identifier needs to count as covered
+    /// (`MEDIATHEK_QUALITY_MIN_NEIGHBORS`, default 5).
+    pub min_neighbors: usize,
+    /// Length of the neighbor lists evaluated for popularity bias and churn
+    /// (`MEDIATHEK_QUALITY_NEIGHBORS`, default 10).
+    pub neighbors: usize,
+    /// Number of most frequent identifiers whose neighbor lists are evaluated
+    /// (`MEDIATHEK_QUALITY_SAMPLE_SIZE`, default 1000).
+    pub sample_size: usize,
+}
+
+/// Settings for measuring the accuracy of the recommendations on held-out lists, behind
+/// GET /admin/evaluation.
+#[derive(Debug, Clone)]
+pub struct EvaluationSettings {
+    /// Share of the ingested lists held out of the co-occurrences and kept for the
+    /// evaluation instead (`MEDIATHEK_EVALUATION_HOLDOUT_FRACTION`, default 0, which holds
+    /// none out). Held-out lists are lost on restarts, as they are kept in memory only.
+    pub holdout_fraction: f64,
+    /// Number of most recent held-out lists kept (`MEDIATHEK_EVALUATION_HOLDOUT_CAPACITY`,
+    /// default 10000).
+    pub holdout_capacity: usize,
+    /// Number of recommendations a held-out item has to be among to count as hit
+    /// (`MEDIATHEK_EVALUATION_K`, default 10).
+    pub k: usize,
+    /// Seconds between two evaluations (`MEDIATHEK_EVALUATION_INTERVAL_SECS`, default
+    /// 3600). Each one reads all pairs, like a snapshot.
+    pub interval_secs: u64,
+}
+
+/// Settings for recording the ingest requests, to reproduce issues with `replay`.
+#[derive(Debug, Clone)]
+pub struct RecorderSettings {
+    /// Whether the bodies of POST /lists, /lists/stream, /counters and /counters/batch are
+    /// appended to "requests.log" in the data directory (`MEDIATHEK_RECORDER_ENABLED`,
+    /// default false). Headers other than the content type and encoding, and peer
+    /// addresses, aren't recorded.
+    pub enabled: bool,
+    /// Bytes of the file before it is rolled over to "requests.log.1", replacing the one
+    /// before (`MEDIATHEK_RECORDER_MAX_FILE_BYTES`, default 104857600).
+    pub max_file_bytes: u64,
+    /// Bodies above this size, or of unknown size, are passed on unrecorded
+    /// (`MEDIATHEK_RECORDER_MAX_BODY_BYTES`, default 262144, the most the handlers read
+    /// in one piece).
+    pub max_body_bytes: usize,
+}
+
+/// Settings for queueing the lists of POST /lists, to absorb bursts of them.
+#[derive(Debug, Clone)]
+pub struct IngestQueueSettings {
+    /// Lists accepted (202) but not yet counted at most; beyond that POST /lists answers
+    /// 429 (`MEDIATHEK_INGEST_QUEUE_CAPACITY`, default 0, which counts every list before
+    /// answering).
+    pub capacity: usize,
+}
+
+/// Settings for the item2vec embedding training.
+#[derive(Debug, Clone)]
+pub struct EmbeddingSettings {
+    /// Length of each item vector (`MEDIATHEK_EMBEDDINGS_DIMENSIONS`, default 32).
+    pub dimensions: usize,
+    /// Number of positions on each side counted as context (`MEDIATHEK_EMBEDDINGS_WINDOW`, default 5).
+    pub window: usize,
+    /// Negative samples per positive pair (`MEDIATHEK_EMBEDDINGS_NEGATIVE_SAMPLES`, default 5).
+    pub negative_samples: usize,
+    /// Passes over the recent lists per training run (`MEDIATHEK_EMBEDDINGS_EPOCHS`, default 5).
+    pub epochs: usize,
+    /// Initial learning rate (`MEDIATHEK_EMBEDDINGS_LEARNING_RATE`, default 0.025).
+    pub learning_rate: f32,
+    /// Identifiers seen less often are not embedded (`MEDIATHEK_EMBEDDINGS_MIN_COUNT`, default 2).
+    pub min_count: u32,
+    /// Seconds between two training runs (`MEDIATHEK_EMBEDDINGS_TRAINING_INTERVAL_SECS`, default 3600).
+    pub training_interval_secs: u64,
+}
+
+/// Settings for the implicit-feedback matrix factorization (ALS).
+#[derive(Debug, Clone)]
+pub struct FactorizationSettings {
+    /// Whether the recommendation handlers use the trained model (`MEDIATHEK_FACTORIZATION_ENABLED`, default false).
+    pub enabled: bool,
+    /// Number of latent factors (`MEDIATHEK_FACTORIZATION_FACTORS`, default 16).
+    pub factors: usize,
+    /// Alternating least squares iterations per training run (`MEDIATHEK_FACTORIZATION_ITERATIONS`, default 10).
+    pub iterations: usize,
+    /// L2 regularization (`MEDIATHEK_FACTORIZATION_REGULARIZATION`, default 0.1).
+    pub regularization: f64,
+    /// Confidence scaling of observed interactions (`MEDIATHEK_FACTORIZATION_ALPHA`, default 40).
+    pub alpha: f64,
+    /// Seconds between two training runs (`MEDIATHEK_FACTORIZATION_TRAINING_INTERVAL_SECS`, default 3600).
+    pub training_interval_secs: u64,
+}
+
+/// Settings for the spike detection on the rotating counters.
+#[derive(Debug, Clone)]
+pub struct AlertSettings {
+    /// Minimum ratio of the current hour to the trailing baseline (`MEDIATHEK_ALERTS_SPIKE_RATIO`, default 5).
+    pub spike_ratio: f64,
+    /// Minimum count in the current hour before an item can spike (`MEDIATHEK_ALERTS_MIN_COUNT`, default 50).
+    pub min_count: u64,
+    /// Seconds between two checks (`MEDIATHEK_ALERTS_CHECK_INTERVAL_SECS`, default 60).
+    pub check_interval_secs: u64,
+    /// Seconds before the same identifier can be alerted again (`MEDIATHEK_ALERTS_COOLDOWN_SECS`, default 3600).
+    pub cooldown_secs: u64,
+    /// Number of alerts kept for GET /alerts (`MEDIATHEK_ALERTS_HISTORY`, default 1000).
+    pub history: usize,
+    /// URL new alerts are POSTed to (`MEDIATHEK_ALERTS_WEBHOOK_URL`, default: none).
+    pub webhook_url: Option<String>,
+}
+
+/// Settings for the trending digests POSTed to webhooks.
+#[derive(Debug, Clone)]
+pub struct DigestSettings {
+    /// URLs the digests are POSTed to, comma-separated (`MEDIATHEK_DIGEST_WEBHOOK_URLS`,
+    /// default: none, which disables the digests).
+    pub webhook_urls: Vec<String>,
+    /// Seconds between two scheduled digests (`MEDIATHEK_DIGEST_INTERVAL_SECS`, default 0,
+    /// which sends none on a schedule).
+    pub interval_secs: u64,
+    /// Whether a digest is sent after each daily rotation, i.e. when today's counts
+    /// become yesterday's (`MEDIATHEK_DIGEST_AFTER_ROTATION`, default true).
+    pub after_rotation: bool,
+    /// Buckets or rolling windows whose top items are included, comma-separated
+    /// (`MEDIATHEK_DIGEST_WINDOWS`, default "today,yesterday,this_week").
+    pub windows: Vec<String>,
+    /// Number of items per list (`MEDIATHEK_DIGEST_LIMIT`, default 10).
+    pub limit: usize,
+    /// Number of retries of a failed delivery (`MEDIATHEK_DIGEST_MAX_RETRIES`, default 5).
+    pub max_retries: u32,
+    /// Seconds before the first retry; doubled for every further one
+    /// (`MEDIATHEK_DIGEST_RETRY_BACKOFF_SECS`, default 2).
+    pub retry_backoff_secs: u64,
+}
+
+/// Settings for the log output.
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    /// Level filter in `tracing` directive syntax, e.g. "info" or "info,mediathek_rs=debug"
+    /// (`MEDIATHEK_LOG_LEVEL`, default "info").
+    pub level: String,
+    /// Whether log lines are written as JSON objects instead of plain text (`MEDIATHEK_LOG_JSON`, default true).
+    pub json: bool,
+    /// OTLP/HTTP traces endpoint spans are exported to, e.g. "http://collector:4318/v1/traces"
+    /// (`MEDIATHEK_OTLP_ENDPOINT`, default: none). Requires the `otel` feature.
+    pub otlp_endpoint: Option<String>,
+    /// Sentry DSN panics and error log events are reported to (`MEDIATHEK_SENTRY_DSN`,
+    /// default: none). Requires the `sentry` feature.
+    pub sentry_dsn: Option<String>,
+    /// Environment tag of the reported events (`MEDIATHEK_SENTRY_ENVIRONMENT`, default "production").
+    pub sentry_environment: String,
+    /// Release tag of the reported events (`MEDIATHEK_SENTRY_RELEASE`, default: the server's version).
+    pub sentry_release: String,
+}
+
+/// Limits enforced on the identifiers sent to the ingestion endpoints.
+#[derive(Debug, Clone)]
+pub struct ValidationSettings {
+    /// Maximum number of identifiers per list or sequence; the pairs counted per list grow
+    /// quadratically with it (`MEDIATHEK_VALIDATION_MAX_LIST_IDENTIFIERS`, default 200).
+    pub max_list_identifiers: usize,
+    /// Maximum length of an identifier in bytes (`MEDIATHEK_VALIDATION_MAX_IDENTIFIER_LENGTH`, default 256).
+    pub max_identifier_length: usize,
+    /// Pattern every identifier has to match, e.g. "^(ard|zdf|arte):[A-Za-z0-9_-]+$" to only allow
+    /// known namespaces (`MEDIATHEK_VALIDATION_IDENTIFIER_PATTERN`, default: printable ASCII without spaces).
+    pub identifier_pattern: Regex,
+    /// How identifiers are brought into their canonical form before they're validated,
+    /// counted or looked up.
+    pub normalization: NormalizationSettings,
+}
+
+/// Normalization of identifiers on ingest and query, so that e.g. "ARD:xyz", "ard:xyz "
+/// and "ard%3Axyz" are counted as one. The steps run in the order of the fields.
+#[derive(Debug, Clone, Default)]
+pub struct NormalizationSettings {
+    /// Whether %XX escapes are decoded; identifiers that don't decode to UTF-8 are kept
+    /// (`MEDIATHEK_NORMALIZE_PERCENT_DECODE`, default false).
+    pub percent_decode: bool,
+    /// Whether leading and trailing whitespace is removed (`MEDIATHEK_NORMALIZE_TRIM`, default false).
+    pub trim: bool,
+    /// Which part is lowercased: "none", "scheme" for the namespace before the first ':',
+    /// or "all" (`MEDIATHEK_NORMALIZE_CASE_FOLD`, default "none").
+    pub case_fold: CaseFold,
+    /// Regex replacements applied last, in order, as "<pattern>=><replacement>;..." with
+    /// `$1`-style groups, e.g. "^arte:([a-z0-9-]+)-de$=>arte:$1"
+    /// (`MEDIATHEK_NORMALIZE_REWRITES`, default: none).
+    pub rewrites: IdentifierRewrites,
+}
+
+impl NormalizationSettings {
+    /// Whether any step is configured, so identifiers can be passed through unchanged.
+    pub fn is_enabled(&self) -> bool {
+        self.percent_decode || self.trim || self.case_fold != CaseFold::None || !self.rewrites.0.is_empty()
+    }
+}
+
+/// The part of identifiers that is lowercased by the normalization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseFold {
+    #[default]
+    None,
+    /// Only the namespace before the first ':', which is case-insensitive
+    Scheme,
+    All,
+}
+
+impl FromStr for CaseFold {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(CaseFold::None),
+            "scheme" => Ok(CaseFold::Scheme),
+            "all" => Ok(CaseFold::All),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Regex replacements of identifiers, parsed from "<pattern>=><replacement>;...". Patterns
+/// can't contain ';'.
+#[derive(Debug, Clone, Default)]
+pub struct IdentifierRewrites(pub Vec<(Regex, String)>);
+
+impl FromStr for IdentifierRewrites {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (pattern, replacement) = entry.split_once("=>").ok_or("expected <pattern>=><replacement>")?;
+                let pattern = Regex::new(pattern.trim()).map_err(|e| e.to_string())?;
+                Ok((pattern, replacement.trim().to_string()))
+            })
+            .collect::<Result<_, String>>()
+            .map(IdentifierRewrites)
+    }
+}
+
+/// Which identifiers an ingest rule applies to.
+#[derive(Debug, Clone)]
+pub enum RuleMatcher {
+    Prefix(String),
+    Pattern(Regex),
+}
+
+/// What an ingest rule does with the identifiers it applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleAction {
+    /// Leaves them out, as if they weren't sent
+    Drop,
+    /// Replaces the prefix, or the match of the pattern with `$1`-style groups
+    Rewrite(String),
+    /// Counts them in the state of this tenant instead
+    Route(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct IngestRule {
+    pub matcher: RuleMatcher,
+    pub action: RuleAction,
+}
+
+/// What the ingest rules make of an identifier.
+#[derive(Debug, PartialEq)]
+pub enum RuleVerdict<'a> {
+    Keep,
+    Drop,
+    Rewrite(String),
+    /// To the tenant of that name
+    Route(&'a str),
+}
+
+/// Rules for the identifiers of ingested lists and plays, applied after the normalization
+/// and validation, parsed from "<matcher>=><action>;...". A matcher is "prefix:<prefix>"
+/// or "regex:<pattern>", an action "drop", "rewrite:<replacement>" or "tenant:<name>",
+/// e.g. "prefix:test:=>drop;regex:^br:(.+)$=>rewrite:ard:$1;prefix:kika:=>tenant:kika".
+/// Only the first matching rule applies. Patterns can't contain ';'.
+#[derive(Debug, Clone, Default)]
+pub struct IngestRules(pub Vec<IngestRule>);
+
+impl IngestRules {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn verdict(&self, identifier: &str) -> RuleVerdict<'_> {
+        let rule = self.0.iter().find(|rule| match &rule.matcher {
+            RuleMatcher::Prefix(prefix) => identifier.starts_with(prefix.as_str()),
+            RuleMatcher::Pattern(pattern) => pattern.is_match(identifier),
+        });
+        let Some(rule) = rule else {
+            return RuleVerdict::Keep;
+        };
+        match (&rule.action, &rule.matcher) {
+            (RuleAction::Drop, _) => RuleVerdict::Drop,
+            (RuleAction::Route(tenant), _) => RuleVerdict::Route(tenant),
+            (RuleAction::Rewrite(replacement), RuleMatcher::Prefix(prefix)) => {
+                RuleVerdict::Rewrite(format!("{}{}", replacement, &identifier[prefix.len()..]))
+            }
+            (RuleAction::Rewrite(replacement), RuleMatcher::Pattern(pattern)) => {
+                RuleVerdict::Rewrite(pattern.replace(identifier, replacement.as_str()).into_owned())
+            }
+        }
+    }
+
+    /// The tenants the rules route identifiers to.
+    pub fn tenants(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().filter_map(|rule| match &rule.action {
+            RuleAction::Route(tenant) => Some(tenant.as_str()),
+            _ => None,
+        })
+    }
+}
+
+impl FromStr for IngestRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (matcher, action) = entry.split_once("=>").ok_or("expected <matcher>=><action>")?;
+                let matcher = match matcher.trim().split_once(':') {
+                    Some(("prefix", prefix)) if !prefix.is_empty() => RuleMatcher::Prefix(prefix.to_string()),
+                    Some(("regex", pattern)) => RuleMatcher::Pattern(Regex::new(pattern).map_err(|e| e.to_string())?),
+                    _ => return Err(format!("invalid matcher '{}', expected prefix:<prefix> or regex:<pattern>", matcher.trim())),
+                };
+                let action = match action.trim().split_once(':') {
+                    None if action.trim() == "drop" => RuleAction::Drop,
+                    Some(("rewrite", replacement)) => RuleAction::Rewrite(replacement.to_string()),
+                    Some(("tenant", tenant)) if !tenant.is_empty() => RuleAction::Route(tenant.to_string()),
+                    _ => return Err(format!("invalid action '{}', expected drop, rewrite:<replacement> or tenant:<name>", action.trim())),
+                };
+                Ok(IngestRule { matcher, action })
+            })
+            .collect::<Result<_, String>>()
+            .map(IngestRules)
+    }
+}
+
+/// Settings for the per-client rate limiting.
+#[derive(Debug, Clone)]
+pub struct RateLimitSettings {
+    /// Whether requests are rate limited at all (`MEDIATHEK_RATE_LIMIT_ENABLED`, default false).
+    pub enabled: bool,
+    /// Limit of every route without its own limit (`MEDIATHEK_RATE_LIMIT_DEFAULT`, default "50:100").
+    pub default_limit: RateLimit,
+    /// Limits of individual routes, given without version prefix, e.g.
+    /// "POST /counters=20:40;GET /lists/{identifier}=100:200" (`MEDIATHEK_RATE_LIMIT_ROUTES`, default: none).
+    pub route_limits: RouteRateLimits,
+    /// Whether clients are identified by the Forwarded/X-Forwarded-For headers instead of the
+    /// peer address, when running behind a reverse proxy (`MEDIATHEK_RATE_LIMIT_TRUST_PROXY`, default false).
+    pub trust_proxy: bool,
+}
+
+/// Settings for shedding load with 503 instead of letting requests queue up behind the
+/// locks of the ingestion.
+#[derive(Debug, Clone)]
+pub struct OverloadSettings {
+    /// Requests a route may serve at once (`MEDIATHEK_OVERLOAD_MAX_IN_FLIGHT`, default 0,
+    /// which doesn't limit them)
+    pub max_in_flight: usize,
+    /// Limits of individual routes, given without version prefix, e.g.
+    /// "GET /lists/{identifier}=64;POST /recommendations=16" (`MEDIATHEK_OVERLOAD_ROUTES`, default: none).
+    pub route_limits: RouteConcurrencyLimits,
+    /// Milliseconds the read endpoints wait for the co-occurrence and counter locks before
+    /// giving up (`MEDIATHEK_OVERLOAD_LOCK_TIMEOUT_MS`, default 0, which waits as long as it takes)
+    pub lock_timeout_ms: u64,
+    /// Seconds clients are told to wait in the Retry-After header of a 503
+    /// (`MEDIATHEK_OVERLOAD_RETRY_AFTER_SECS`, default 1)
+    pub retry_after_secs: u64,
+    /// Milliseconds the heavy endpoints (exports, baskets, batches) work on a request before
+    /// answering 504, unless its X-Request-Timeout header asks for less
+    /// (`MEDIATHEK_REQUEST_TIMEOUT_MS`, default 0, which leaves it to the header)
+    pub request_timeout_ms: u64,
+}
+
+impl OverloadSettings {
+    /// The number of requests `route` may serve at once; 0 if unlimited.
+    pub fn limit_for(&self, route: &str) -> usize {
+        self.route_limits.0.get(route).copied().unwrap_or(self.max_in_flight)
+    }
+
+    pub fn lock_timeout(&self) -> Option<Duration> {
+        (self.lock_timeout_ms > 0).then(|| Duration::from_millis(self.lock_timeout_ms))
+    }
+}
+
+/// Concurrency limits by route ("METHOD /pattern"), parsed from "<route>=<limit>;...".
+#[derive(Debug, Clone, Default)]
+pub struct RouteConcurrencyLimits(pub HashMap<String, usize>);
+
+impl FromStr for RouteConcurrencyLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (route, limit) = entry.rsplit_once('=').ok_or("expected <route>=<limit>")?;
+                let limit = limit.trim().parse::<usize>().map_err(|_| format!("invalid limit '{}'", limit.trim()))?;
+                Ok((route.trim().to_string(), limit))
+            })
+            .collect::<Result<_, String>>()
+            .map(RouteConcurrencyLimits)
+    }
+}
+
+/// A token bucket limit, written as "<requests per second>:<burst>", e.g. "20:40".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    /// Number of requests a client can make at once after being idle
+    pub burst: u32,
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (per_second, burst) = s.trim().split_once(':').ok_or("expected <per second>:<burst>")?;
+        let per_second: f64 = per_second.parse().map_err(|_| "invalid rate")?;
+        let burst: u32 = burst.parse().map_err(|_| "invalid burst")?;
+        if per_second <= 0.0 || burst == 0 {
+            return Err("rate and burst must be positive".to_string());
+        }
+        Ok(RateLimit { per_second, burst })
+    }
+}
+
+/// Rate limits by route ("METHOD /pattern"), parsed from "<route>=<limit>;<route>=<limit>;...".
+#[derive(Debug, Clone, Default)]
+pub struct RouteRateLimits(pub HashMap<String, RateLimit>);
+
+impl FromStr for RouteRateLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (route, limit) = entry.rsplit_once('=').ok_or("expected <route>=<limit>")?;
+                Ok((route.trim().to_string(), limit.parse()?))
+            })
+            .collect::<Result<_, String>>()
+            .map(RouteRateLimits)
+    }
+}
+
+/// Weights of list sources, parsed from "<source>=<weight>;<source>=<weight>;...". The
+/// counts are whole numbers, so the weights are as well: the pairs of a list are counted
+/// `weight` times, and weak signals are weighted 1 against higher weights of strong ones.
+#[derive(Debug, Clone, Default)]
+pub struct SourceWeights(pub HashMap<String, u64>);
+
+impl SourceWeights {
+    /// The weight of `source`; 1 for lists without a source or with an unknown one.
+    pub fn of(&self, source: Option<&str>) -> u64 {
+        source.and_then(|source| self.0.get(source)).copied().unwrap_or(1)
+    }
+}
+
+impl FromStr for SourceWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (source, weight) = entry.rsplit_once('=').ok_or("expected <source>=<weight>")?;
+                match weight.trim().parse::<u64>() {
+                    Ok(weight) if weight >= 1 => Ok((source.trim().to_string(), weight)),
+                    _ => Err(format!("invalid weight '{}', expected a whole number from 1", weight.trim())),
+                }
+            })
+            .collect::<Result<_, String>>()
+            .map(SourceWeights)
+    }
+}
+
+/// Which pairs of a list are counted. A list of n identifiers has n(n - 1)/2 pairs, so a
+/// single list of 500 updates about 125000 counts under the co-occurrence lock; the other
+/// strategies bound that, at the price of pairs of distant items.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PairStrategy {
+    /// "all": every pair
+    #[default]
+    All,
+    /// "sample:<max pairs>": at most that many pairs, spread evenly over all of them
+    Sample(usize),
+    /// "window:<k>": the pairs of identifiers at most k positions apart
+    Window(usize),
+    /// "chunk:<size>": the pairs within each run of that many identifiers, as if the list
+    /// were several shorter ones
+    Chunk(usize),
+}
+
+impl FromStr for PairStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, argument) = s.trim().split_once(':').unwrap_or((s.trim(), ""));
+        let size = || match argument.trim().parse::<usize>() {
+            Ok(size) if size >= 1 => Ok(size),
+            _ => Err(format!("expected a whole number from 1 for {} instead of '{}'", name, argument.trim())),
+        };
+        match name {
+            "all" if argument.is_empty() => Ok(PairStrategy::All),
+            "sample" => Ok(PairStrategy::Sample(size()?)),
+            "window" => Ok(PairStrategy::Window(size()?)),
+            "chunk" => Ok(PairStrategy::Chunk(size()?)),
+            _ => Err(format!("unknown pair strategy '{}', expected all, sample:<n>, window:<k> or chunk:<size>", s.trim())),
+        }
+    }
+}
+
+/// Settings for the API key authentication.
+#[derive(Debug, Clone)]
+pub struct AuthSettings {
+    /// Named keys accepted in the `X-Api-Key` header, e.g. "ingest=3f9a...,dashboard=81bc..."
+    /// (`MEDIATHEK_API_KEYS`, default: none, which disables authentication). Only the names
+    /// show up in logs.
+    pub api_keys: ApiKeys,
+    /// Whether read endpoints require a key as well (`MEDIATHEK_AUTH_PROTECT_READS`, default false).
+    /// Write endpoints always do, and so do the admin endpoints unless `MEDIATHEK_ADMIN_TOKEN` is set.
+    pub protect_reads: bool,
+    /// Requests per day each API key may make, e.g. "dashboard=100000;partner=5000"
+    /// (`MEDIATHEK_AUTH_DAILY_QUOTAS`, default: none). Keys not listed are unlimited.
+    pub daily_quotas: DailyQuotas,
+}
+
+/// Requests per day of API keys by name, from "<key name>=<requests>;...".
+#[derive(Debug, Clone, Default)]
+pub struct DailyQuotas(pub HashMap<String, u64>);
+
+impl DailyQuotas {
+    /// The quota of the key `name`; `None` if it is unlimited.
+    pub fn of(&self, name: &str) -> Option<u64> {
+        self.0.get(name).copied()
+    }
+}
+
+impl FromStr for DailyQuotas {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (name, quota) = entry.rsplit_once('=').ok_or("expected <key name>=<requests>")?;
+                match quota.trim().parse::<u64>() {
+                    Ok(quota) => Ok((name.trim().to_string(), quota)),
+                    _ => Err(format!("invalid quota '{}', expected a whole number", quota.trim())),
+                }
+            })
+            .collect::<Result<_, String>>()
+            .map(DailyQuotas)
+    }
+}
+
+/// Settings for the /admin endpoints.
+#[derive(Clone)]
+pub struct AdminSettings {
+    /// Whether the /admin endpoints are served at all (`MEDIATHEK_ADMIN_ENABLED`, default true).
+    /// Disable them in production if they are not needed there.
+    pub enabled: bool,
+    /// Token the /admin endpoints require as `Authorization: Bearer <token>`
+    /// (`MEDIATHEK_ADMIN_TOKEN`, default: none). Without one, they require an API key
+    /// like the write endpoints.
+    pub token: Option<String>,
+}
+
+impl std::fmt::Debug for AdminSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdminSettings")
+            .field("enabled", &self.enabled)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// Settings for serving HTTPS instead of plain HTTP.
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    /// PEM file with the server certificate chain (`MEDIATHEK_TLS_CERT`, default: none).
+    /// TLS is enabled if this and the key are set.
+    pub cert_path: Option<PathBuf>,
+    /// PEM file with the private key of the certificate (`MEDIATHEK_TLS_KEY`, default: none).
+    pub key_path: Option<PathBuf>,
+    /// PEM file with the CAs client certificates must be signed by (`MEDIATHEK_TLS_CLIENT_CA`,
+    /// default: none). If set, clients without a valid certificate are rejected (mutual TLS).
+    pub client_ca_path: Option<PathBuf>,
+}
+
+/// Settings for restricting sensitive routes to known networks.
+#[derive(Debug, Clone)]
+pub struct AllowlistSettings {
+    /// Networks allowed to call the /admin endpoints, e.g. "10.0.0.0/8,fd00::/8"
+    /// (`MEDIATHEK_ALLOWLIST_NETWORKS`, default: none, which allows every address).
+    pub networks: IpNetworks,
+    /// Whether write endpoints are restricted to these networks as well
+    /// (`MEDIATHEK_ALLOWLIST_WRITES`, default false).
+    pub include_writes: bool,
+    /// Whether the client address is taken from the Forwarded/X-Forwarded-For headers instead
+    /// of the peer address, when running behind a reverse proxy (`MEDIATHEK_ALLOWLIST_TRUST_PROXY`,
+    /// default false).
+    pub trust_proxy: bool,
+}
+
+/// An IP network in CIDR notation, e.g. "192.168.0.0/16". A plain address is a network
+/// of just that address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpNetwork {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        // Clients connecting to a dual-stack socket over IPv4 show up as ::ffff:a.b.c.d
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s.trim(), None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| format!("invalid address '{}'", address))?;
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().ok().filter(|&len| len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length '{}'", prefix_len))?,
+            None => max_len,
+        };
+        Ok(IpNetwork { address, prefix_len })
+    }
+}
+
+/// IP networks, parsed from "<network>,<network>,...".
+#[derive(Debug, Clone, Default)]
+pub struct IpNetworks(pub Vec<IpNetwork>);
+
+impl FromStr for IpNetworks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, String>>()
+            .map(IpNetworks)
+    }
+}
+
+/// Settings for HMAC-SHA256 request signatures.
+#[derive(Debug, Clone)]
+pub struct SigningSettings {
+    /// Shared secret per client, in the format of `MEDIATHEK_API_KEYS`
+    /// (`MEDIATHEK_SIGNING_SECRETS`, default: none, which disables signature checks).
+    pub client_secrets: ApiKeys,
+    /// Whether write requests must be signed (`MEDIATHEK_SIGNING_REQUIRED`, default false).
+    /// Otherwise only requests carrying a signature are verified.
+    pub required: bool,
+    /// How far the signed timestamp may be off from the server's clock, in seconds
+    /// (`MEDIATHEK_SIGNING_MAX_SKEW_SECS`, default 300). This window is the only replay
+    /// protection: there is no nonce, so a captured request can be replayed within it.
+    pub max_skew_secs: u64,
+    /// Most bytes of a signed request's body (`MEDIATHEK_SIGNING_MAX_BODY_BYTES`, default
+    /// 16777216); larger ones are rejected with 413. The body is held in memory until the
+    /// signature is verified, so signed uploads aren't streamed to the handler; clients
+    /// streaming larger bodies authenticate with an API key instead.
+    pub max_body_bytes: usize,
+}
+
+/// Settings for compressing response bodies.
+#[derive(Debug, Clone)]
+pub struct CompressionSettings {
+    /// Whether responses are compressed for clients accepting gzip or brotli
+    /// (`MEDIATHEK_COMPRESSION_ENABLED`, default true).
+    pub enabled: bool,
+    /// Compression level, 0 (fastest) to 9 for gzip and 11 for brotli; higher values are
+    /// capped for gzip (`MEDIATHEK_COMPRESSION_LEVEL`, default 5).
+    pub level: u32,
+    /// Bodies smaller than this many bytes are sent uncompressed (`MEDIATHEK_COMPRESSION_MIN_BYTES`, default 1024).
+    pub min_bytes: usize,
+}
+
+/// Settings for the gRPC API.
+#[derive(Debug, Clone)]
+pub struct GrpcSettings {
+    /// Whether the gRPC API is served next to the HTTP API (`MEDIATHEK_GRPC_ENABLED`, default false).
+    pub enabled: bool,
+    /// Port the gRPC API listens on (`MEDIATHEK_GRPC_PORT`, default 50051).
+    pub port: u16,
+}
+
+/// Settings for the /ws stream of trending items.
+#[derive(Debug, Clone)]
+pub struct WebSocketSettings {
+    /// Seconds between two checks whether the trending list changed (`MEDIATHEK_WS_CHECK_INTERVAL_SECS`, default 2, at least 1).
+    pub check_interval_secs: u64,
+    /// Relative change of an item's count that counts as material, if the ranking itself
+    /// is unchanged (`MEDIATHEK_WS_MIN_CHANGE`, default 0.1, i.e. 10%).
+    pub min_change: f64,
+    /// Seconds after which the list is sent even without material changes
+    /// (`MEDIATHEK_WS_PUSH_INTERVAL_SECS`, default 30; 0 sends changes only).
+    pub push_interval_secs: u64,
+}
+
+/// Settings for ingesting from Kafka. Requires the `kafka` feature.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+pub struct KafkaSettings {
+    /// Comma-separated bootstrap brokers, e.g. "kafka-1:9092,kafka-2:9092"
+    /// (`MEDIATHEK_KAFKA_BROKERS`, default: none, which disables the consumer).
+    pub brokers: Option<String>,
+    /// Consumer group; instances in the same group share the partitions
+    /// (`MEDIATHEK_KAFKA_GROUP`, default "mediathek-recommendations").
+    pub group: String,
+    /// Topic of session lists, JSON like the body of POST /lists (`MEDIATHEK_KAFKA_LISTS_TOPIC`, default: none).
+    pub lists_topic: Option<String>,
+    /// Topic of play events, JSON like the body of POST /counters (`MEDIATHEK_KAFKA_PLAYS_TOPIC`, default: none).
+    pub plays_topic: Option<String>,
+}
+
+/// Settings for ingesting from a NATS JetStream stream. Requires the `nats` feature.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "nats"), allow(dead_code))]
+pub struct NatsSettings {
+    /// Server URL, e.g. "nats://nats:4222" (`MEDIATHEK_NATS_URL`, default: none, which
+    /// disables the subscriber).
+    pub url: Option<String>,
+    /// Stream the subjects are stored in; created if it doesn't exist (`MEDIATHEK_NATS_STREAM`,
+    /// default "MEDIATHEK_EVENTS").
+    pub stream: String,
+    /// Durable consumer; instances using the same one share the messages
+    /// (`MEDIATHEK_NATS_CONSUMER`, default "mediathek-recommendations").
+    pub consumer: String,
+    /// Subject of session lists, JSON like the body of POST /lists (`MEDIATHEK_NATS_LISTS_SUBJECT`,
+    /// default "mediathek.lists").
+    pub lists_subject: String,
+    /// Subject of play events, JSON like the body of POST /counters (`MEDIATHEK_NATS_PLAYS_SUBJECT`,
+    /// default "mediathek.plays").
+    pub plays_subject: String,
+}
+
+/// Settings for keeping copies of the snapshots in S3-compatible object storage, for
+/// containers whose disks don't outlive them.
+#[derive(Clone)]
+pub struct ObjectStorageSettings {
+    /// Bucket every snapshot is uploaded to after it is written, and downloaded from on
+    /// startup if there is no local one (`MEDIATHEK_S3_BUCKET`, default: none, which
+    /// disables both).
+    pub bucket: Option<String>,
+    /// Region of the bucket (`MEDIATHEK_S3_REGION`, default "us-east-1").
+    pub region: String,
+    /// Base URL of the service, e.g. "http://minio:9000" (`MEDIATHEK_S3_ENDPOINT`, default:
+    /// AWS S3 in the configured region). Buckets are addressed by path.
+    pub endpoint: String,
+    /// Prepended to the snapshot file names to form the object keys (`MEDIATHEK_S3_PREFIX`,
+    /// default "snapshots/").
+    pub prefix: String,
+    /// `MEDIATHEK_S3_ACCESS_KEY_ID`, default: none.
+    pub access_key_id: String,
+    /// `MEDIATHEK_S3_SECRET_ACCESS_KEY`, default: none.
+    pub secret_access_key: String,
+}
+
+impl std::fmt::Debug for ObjectStorageSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ObjectStorageSettings")
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("endpoint", &self.endpoint)
+            .field("prefix", &self.prefix)
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Settings for hosting several apps with separate counters from one server.
+#[derive(Debug, Clone)]
+pub struct TenantSettings {
+    /// Tenants requests may address with the `/t/{tenant}` prefix or the `X-Tenant`
+    /// header (`MEDIATHEK_TENANTS`, comma-separated, default: none, which disables
+    /// tenants). Names may contain letters, digits, "-" and "_". A tenant's state is
+    /// created on its first request and persisted in `tenants/{tenant}` in the data
+    /// directory, apart from the state of requests without a tenant. Only the
+    /// co-occurrences and counters are kept per tenant; the gRPC API and the message buses
+    /// serve the default state.
+    pub names: Vec<String>,
+}
+
+/// Settings for forgetting identifiers that stopped appearing, e.g. depublished items.
+#[derive(Debug, Clone)]
+pub struct EvictionSettings {
+    /// Identifiers not seen in a list or play for this many days are removed with their
+    /// pairs and counts (`MEDIATHEK_EVICTION_TTL_DAYS`, default 0, which keeps them
+    /// forever). Not supported with a co-occurrence database.
+    pub ttl_days: u64,
+    /// How often to look for such identifiers (`MEDIATHEK_EVICTION_INTERVAL_SECS`, default
+    /// 3600). Every pass that removes any writes a full co-occurrence snapshot.
+    pub interval_secs: u64,
+}
+
+/// Settings for giving back the memory freed by removals and rotations.
+#[derive(Debug, Clone)]
+pub struct CompactionSettings {
+    /// How often to shrink the maps to their contents and renumber the co-occurrence
+    /// IDs densely (`MEDIATHEK_COMPACTION_INTERVAL_SECS`, default 86400; 0 disables it).
+    /// Renumbering writes a full co-occurrence snapshot.
+    pub interval_secs: u64,
+}
+
+/// Settings for running as a replica of another instance.
+#[derive(Clone)]
+pub struct ReplicationSettings {
+    /// Base URL of the primary to follow, e.g. "http://primary:3030"
+    /// (`MEDIATHEK_REPLICATION_PRIMARY_URL`, default: none, which makes this instance a
+    /// primary). A replica loads the primary's state, applies its lists, plays and
+    /// rotations as they happen, and rejects writes until it is promoted with
+    /// POST /admin/replication/promote. Only the state of requests without a tenant is
+    /// replicated; don't configure message buses on a replica.
+    pub primary_url: Option<String>,
+    /// Admin token of the primary (`MEDIATHEK_REPLICATION_PRIMARY_TOKEN`, default: none).
+    pub primary_token: Option<String>,
+    /// Number of changes a primary buffers per replica (`MEDIATHEK_REPLICATION_BUFFER`,
+    /// default 65536). Replicas falling further behind load the whole state again.
+    pub buffer: usize,
+    /// How often a primary sends a heartbeat on an idle stream, and how long a replica
+    /// waits for one twice before reconnecting (`MEDIATHEK_REPLICATION_HEARTBEAT_SECS`,
+    /// default 10).
+    pub heartbeat_secs: u64,
+}
+
+impl std::fmt::Debug for ReplicationSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplicationSettings")
+            .field("primary_url", &self.primary_url)
+            .field("primary_token", &self.primary_token.as_ref().map(|_| "<redacted>"))
+            .field("buffer", &self.buffer)
+            .field("heartbeat_secs", &self.heartbeat_secs)
+            .finish()
+    }
+}
+
+/// Settings for running as a router in front of shards.
+#[derive(Debug, Clone)]
+pub struct ShardSettings {
+    /// Base URLs of the shards, e.g. "http://shard-1:3030,http://shard-2:3030"
+    /// (`MEDIATHEK_SHARD_PEERS`, default: none). With any, this instance keeps no state and
+    /// only routes: every identifier is assigned to one shard by consistent hashing, lists
+    /// are sent to the shards of their identifiers, lookups to the shard of the identifier,
+    /// and basket recommendations are gathered from all shards of the basket and merged.
+    /// Changing the list moves about one in n identifiers to another shard.
+    pub peers: Vec<String>,
+    /// Points per shard on the hash ring; more spread the identifiers more evenly
+    /// (`MEDIATHEK_SHARD_VIRTUAL_NODES`, default 128, at least 1).
+    pub virtual_nodes: usize,
+    /// Milliseconds a shard gets to answer a routed request (`MEDIATHEK_SHARD_TIMEOUT_MS`,
+    /// default 2000).
+    pub timeout_ms: u64,
+}
+
+/// Settings for reproducible runs, e.g. of integration tests.
+#[derive(Debug, Clone)]
+pub struct DeterminismSettings {
+    /// Whether identical inputs produce identical state (`MEDIATHEK_DETERMINISTIC`,
+    /// default false): hash maps are seeded with `seed` instead of randomly, so snapshots
+    /// list the co-occurrences in the same order, and the clock stands still at `start`
+    /// until moved with POST /admin/clock, which also drives the counter rotation. Never
+    /// enable it in production, as fixed seeds make hash flooding possible.
+    pub enabled: bool,
+    /// Seed of the hash maps (`MEDIATHEK_DETERMINISTIC_SEED`, default 0).
+    pub seed: u64,
+    /// Time the clock starts at (`MEDIATHEK_DETERMINISTIC_START`, default
+    /// 2026-01-01T00:00:00Z).
+    pub start: DateTime<Utc>,
+}
+
+/// Settings for the `Idempotency-Key` header of POST /lists and POST /counters.
+#[derive(Debug, Clone)]
+pub struct IdempotencySettings {
+    /// Number of keys remembered (`MEDIATHEK_IDEMPOTENCY_CAPACITY`, default 100000; 0
+    /// ignores the header).
+    pub capacity: usize,
+    /// Seconds within which a retry with the same key isn't processed again
+    /// (`MEDIATHEK_IDEMPOTENCY_WINDOW_SECS`, default 600).
+    pub window_secs: u64,
+}
+
+/// Settings for skipping lists submitted again shortly after, see `SessionDedup`.
+#[derive(Debug, Clone)]
+pub struct SessionDedupSettings {
+    /// Number of recent lists remembered (`MEDIATHEK_SESSION_DEDUP_CAPACITY`, default 0,
+    /// which turns deduplication off)
+    pub capacity: usize,
+    /// Seconds within which an identical list is skipped
+    /// (`MEDIATHEK_SESSION_DEDUP_WINDOW_SECS`, default 300)
+    pub window_secs: u64,
+}
+
+/// Settings for keeping deleted identifiers out of the ingestion, see `Tombstones`.
+#[derive(Debug, Clone)]
+pub struct TombstoneSettings {
+    /// Seconds an identifier deleted through DELETE /counters/{id} or POST /admin/purge
+    /// stays out of the ingestion (`MEDIATHEK_TOMBSTONES_TTL_SECS`, default 0, which lets
+    /// the next list or play re-create it right away)
+    pub ttl_secs: u64,
+    /// What happens to lists and plays containing a deleted identifier
+    /// (`MEDIATHEK_TOMBSTONES_MODE`, "drop" or "reject", default "drop")
+    pub mode: TombstoneMode,
+}
+
+/// How ingestion treats deleted identifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TombstoneMode {
+    /// Left out of lists, and plays of them skipped, without telling the client
+    #[default]
+    Drop,
+    /// The whole list or play is rejected with 422
+    Reject,
+}
+
+impl FromStr for TombstoneMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "drop" => Ok(TombstoneMode::Drop),
+            "reject" => Ok(TombstoneMode::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Settings for capping the distinct identifiers of the co-occurrences, so a client sending
+/// a new identifier with every list can't grow them without bounds.
+#[derive(Debug, Clone, Default)]
+pub struct CardinalitySettings {
+    /// Distinct identifiers kept at most (`MEDIATHEK_CARDINALITY_MAX_IDENTIFIERS`, default
+    /// 0, which doesn't limit them)
+    pub max_identifiers: usize,
+    /// What happens once they are reached (`MEDIATHEK_CARDINALITY_POLICY`, "reject" or
+    /// "evict", default "reject")
+    pub policy: CardinalityPolicy,
+}
+
+impl CardinalitySettings {
+    /// Whether lists with new identifiers have to be checked before they are counted.
+    pub fn rejects_new_identifiers(&self) -> bool {
+        self.max_identifiers > 0 && self.policy == CardinalityPolicy::Reject
+    }
+}
+
+/// How the co-occurrences stay within the limit of distinct identifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CardinalityPolicy {
+    /// Lists with identifiers beyond the limit are rejected with 422
+    #[default]
+    Reject,
+    /// Lists are counted, and the identifiers seen least recently removed with their pairs
+    /// once the limit is exceeded. Not supported with a co-occurrence database.
+    Evict,
+}
+
+impl FromStr for CardinalityPolicy {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reject" => Ok(CardinalityPolicy::Reject),
+            "evict" => Ok(CardinalityPolicy::Evict),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Settings for the second co-occurrence hop of POST /recommendations (`two_hop=true`),
+/// which fills the slots the direct neighbors leave with the neighbors of neighbors.
+#[derive(Debug, Clone, Copy)]
+pub struct TwoHopSettings {
+    /// Factor the scores of the second hop are multiplied with, so they rank below direct
+    /// neighbors of the same strength (`MEDIATHEK_TWO_HOP_DECAY`, default 0.5)
+    pub decay: f64,
+    /// Strongest direct neighbors expanded (`MEDIATHEK_TWO_HOP_FAN_OUT`, default 10)
+    pub fan_out: usize,
+    /// Candidates the second hop proposes at most (`MEDIATHEK_TWO_HOP_MAX_CANDIDATES`,
+    /// default 100)
+    pub max_candidates: usize,
+}
+
+/// Settings for GET /metrics/business.
+#[derive(Debug, Clone, Copy)]
+pub struct BusinessMetricsSettings {
+    /// Most played items exported per window (`MEDIATHEK_BUSINESS_METRICS_TOP_N`, default
+    /// 10). Every item is a series of its own, so this bounds the cardinality.
+    pub top_n: usize,
+}
+
+/// Settings for warming a new instance up from a published backup, see `algorithms::warmup`.
+#[derive(Debug, Clone)]
+pub struct WarmupSettings {
+    /// HTTP(S) URL of a backup (as written by GET /admin/backup) that an instance starting
+    /// without any state loads before it serves requests (`MEDIATHEK_WARMUP_URL`, default:
+    /// none, which starts empty).
+    pub url: Option<String>,
+    /// Expected SHA-256 of the backup, hex-encoded (`MEDIATHEK_WARMUP_SHA256`, default:
+    /// none, which reads it from "<url>.sha256" instead).
+    pub sha256: Option<String>,
+}
+
+/// Settings for ranking the recommendations.
+#[derive(Debug, Clone)]
+pub struct ScoringSettings {
+    /// Scoring stages by endpoint ("recommendations" for POST /recommendations, "page" for
+    /// GET /page/{identifier}) or experiment variant, which requests choose with
+    /// `?variant=<name>`, e.g. "recommendations=similarity>popularity:0.2;fresh=similarity>diversity:0.5"
+    /// (`MEDIATHEK_SCORING_PIPELINES`, default: none). Endpoints without a pipeline rank by
+    /// similarity alone.
+    pub pipelines: ScoringPipelines,
+    /// Alternative scoring stages by endpoint, which rank a copy of the candidates of every
+    /// request without a variant for comparison only, e.g. "recommendations=similarity>idf:0.5"
+    /// (`MEDIATHEK_SCORING_SHADOW`, default: none). See GET /admin/scoring/shadow.
+    pub shadow: ScoringPipelines,
+    /// Damping of shares based on few lists, e.g. the conditional probabilities
+    pub shrinkage: Shrinkage,
+}
+
+/// A stage of a scoring pipeline, see `algorithms::scoring`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoringStage {
+    /// "similarity": the models' order, as a score from 1 down
+    Similarity,
+    /// "popularity:<weight>": blends in today's plays with the given weight (0–1)
+    Popularity { weight: f64 },
+    /// "penalty:<min pair count>:<factor>": multiplies the scores of items sharing fewer
+    /// lists with the input by the factor
+    Penalty { min_pair_count: u64, factor: f64 },
+    /// "diversity:<factor>": multiplies the score of every further item of a prefix
+    /// ("news:" of "news:123") by the factor once more
+    Diversity { factor: f64 },
+    /// "idf:<weight>": scales down items in many lists, which co-occur with everything, by
+    /// their inverse list count to the power of the weight (0–1)
+    Idf { weight: f64 },
+}
+
+impl FromStr for ScoringStage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arguments) = s.trim().split_once(':').unwrap_or((s.trim(), ""));
+        let arguments: Vec<&str> = arguments.split(':').filter(|argument| !argument.is_empty()).collect();
+        let fraction = |argument: &str| match argument.parse::<f64>() {
+            Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+            _ => Err(format!("expected a number between 0 and 1 instead of '{}'", argument)),
+        };
+        match (name, arguments.as_slice()) {
+            ("similarity", []) => Ok(ScoringStage::Similarity),
+            ("popularity", [weight]) => Ok(ScoringStage::Popularity { weight: fraction(weight)? }),
+            ("penalty", [count, factor]) => Ok(ScoringStage::Penalty {
+                min_pair_count: count.parse().map_err(|_| format!("invalid pair count '{}'", count))?,
+                factor: fraction(factor)?,
+            }),
+            ("diversity", [factor]) => Ok(ScoringStage::Diversity { factor: fraction(factor)? }),
+            ("idf", [weight]) => Ok(ScoringStage::Idf { weight: fraction(weight)? }),
+            ("similarity" | "popularity" | "penalty" | "diversity" | "idf", _) => Err(format!("wrong number of arguments for {}", name)),
+            _ => Err(format!("unknown scoring stage '{}'", name)),
+        }
+    }
+}
+
+impl fmt::Display for ScoringStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScoringStage::Similarity => write!(f, "similarity"),
+            ScoringStage::Popularity { weight } => write!(f, "popularity:{}", weight),
+            ScoringStage::Penalty { min_pair_count, factor } => write!(f, "penalty:{}:{}", min_pair_count, factor),
+            ScoringStage::Diversity { factor } => write!(f, "diversity:{}", factor),
+            ScoringStage::Idf { weight } => write!(f, "idf:{}", weight),
+        }
+    }
+}
+
+/// Scoring pipelines by name, parsed from "<name>=<stage>><stage>...;<name>=...".
+#[derive(Debug, Clone, Default)]
+pub struct ScoringPipelines(pub HashMap<String, Vec<ScoringStage>>);
+
+impl FromStr for ScoringPipelines {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (name, stages) = entry.split_once('=').ok_or("expected <name>=<stages>")?;
+                let stages = stages.split('>').map(str::parse).collect::<Result<Vec<_>, _>>()?;
+                Ok((name.trim().to_string(), stages))
+            })
+            .collect::<Result<_, String>>()
+            .map(ScoringPipelines)
+    }
+}
+
+/// Bayesian shrinkage of shares like P(B | A): `count` of `total` lists is taken as if
+/// `pseudo_counts` more lists had the share `prior`. A pair seen in both of two lists then
+/// no longer gets a perfect share, while well-supported shares barely move.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Shrinkage {
+    /// Share assumed without evidence, 0–1 (`MEDIATHEK_SCORING_SHRINKAGE_PRIOR`, default 0).
+    pub prior: f64,
+    /// Weight of the prior in lists (`MEDIATHEK_SCORING_SHRINKAGE_PSEUDO_COUNTS`, default 0,
+    /// which leaves the shares as they are).
+    pub pseudo_counts: f64,
+}
+
+impl Shrinkage {
+    /// The share of `count` in `total`, shrunk towards the prior; 0 without any lists.
+    pub fn share(&self, count: u64, total: u64) -> f64 {
+        let total = total as f64 + self.pseudo_counts;
+        if total <= 0.0 {
+            return 0.0;
+        }
+        (count as f64 + self.prior * self.pseudo_counts) / total
+    }
+}
+
+/// A named API key.
+#[derive(Clone)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+}
+
+impl std::fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiKey").field("name", &self.name).field("key", &"<redacted>").finish()
+    }
+}
+
+/// API keys, parsed from "<name>=<key>,<name>=<key>,...".
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys(pub Vec<ApiKey>);
+
+impl FromStr for ApiKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (name, key) = entry.split_once('=').ok_or("expected <name>=<key>")?;
+                let (name, key) = (name.trim(), key.trim());
+                if name.is_empty() || key.is_empty() {
+                    return Err("names and keys must not be empty".to_string());
+                }
+                Ok(ApiKey { name: name.to_string(), key: key.to_string() })
+            })
+            .collect::<Result<_, String>>()
+            .map(ApiKeys)
+    }
+}
+
+impl Settings {
+    /// Reads the settings from the command line `args` (starting with the program name),
+    /// the environment and the config file, see `Settings`. Settings in the file or
+    /// `--set` that don't exist are reported, as they are likely typos. Exits with a usage
+    /// message on invalid flags and `--help`.
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let cli = Cli::parse_from(args);
+        let mut flags = HashMap::new();
+        for setting in &cli.settings {
+            let (key, value) = setting.split_once('=').ok_or_else(|| format!("Invalid --set '{}', expected KEY=VALUE", setting))?;
+            flags.insert(env_key(key), value.to_string());
+        }
+        let named_flags = [
+            ("MEDIATHEK_BIND_ADDRESS", cli.bind),
+            ("MEDIATHEK_PORT", cli.port.or(cli.legacy_port)),
+            ("MEDIATHEK_DATA_DIR", cli.data_dir),
+            ("MEDIATHEK_IMPORT_PATH", cli.import),
+        ];
+        for (key, value) in named_flags {
+            if let Some(value) = value {
+                flags.insert(key.to_string(), value);
+            }
+        }
+        let file = match cli.config.or_else(|| env_path("MEDIATHEK_CONFIG")) {
+            Some(path) => read_config_file(&path)?,
+            None => HashMap::new(),
+        };
+
+        LAYERS.with(|layers| *layers.borrow_mut() = Layers { flags, file, read: HashSet::new() });
+        let settings = Settings::from_env();
+        let layers = LAYERS.with(RefCell::take);
+        let mut unknown: Vec<&String> = layers.flags.keys().chain(layers.file.keys()).filter(|key| !layers.read.contains(*key)).collect();
+        unknown.sort();
+        unknown.dedup();
+        for key in unknown {
+            eprintln!("Ignoring unknown setting {}.", key);
+        }
+        Ok(settings)
+    }
+
+    /// Reads the settings from the environment only, or from all sources while `load`
+    /// runs.
+    pub fn from_env() -> Self {
+        Settings {
+            server: ServerSettings {
+                bind_address: env_or("MEDIATHEK_BIND_ADDRESS", IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                port: env_or("MEDIATHEK_PORT", 3030),
+                workers: env_or("MEDIATHEK_WORKERS", 0),
+                keep_alive_secs: env_or("MEDIATHEK_KEEP_ALIVE_SECS", 5),
+                client_timeout_ms: env_or("MEDIATHEK_CLIENT_TIMEOUT_MS", 5000),
+                shutdown_timeout_secs: env_or("MEDIATHEK_SHUTDOWN_TIMEOUT_SECS", 30),
+                socket_path: env_path("MEDIATHEK_SOCKET_PATH"),
+                socket_mode: env_or("MEDIATHEK_SOCKET_MODE", FileMode(0o660)),
+                socket_only: env_or("MEDIATHEK_SOCKET_ONLY", false),
+            },
+            recent_lists_capacity: env_or("MEDIATHEK_RECENT_LISTS_CAPACITY", 10_000),
+            source_weights: env_or("MEDIATHEK_SOURCE_WEIGHTS", SourceWeights::default()),
+            ingest_rules: env_or("MEDIATHEK_INGEST_RULES", IngestRules::default()),
+            pair_strategy: env_or("MEDIATHEK_LISTS_PAIR_STRATEGY", PairStrategy::All),
+            co_visitation_window_secs: env_or("MEDIATHEK_LISTS_CO_VISITATION_WINDOW_SECS", 1800),
+            metrics_cache: MetricsCacheSettings {
+                capacity: env_or("MEDIATHEK_METRICS_CACHE_CAPACITY", 10_000),
+                ttl_secs: env_or("MEDIATHEK_METRICS_CACHE_TTL_SECS", 60),
+            },
+            identifier_filter: IdentifierFilterSettings {
+                capacity: env_or("MEDIATHEK_IDENTIFIER_FILTER_CAPACITY", 1_000_000),
+                false_positive_rate: env_or("MEDIATHEK_IDENTIFIER_FILTER_FALSE_POSITIVE_RATE", 0.01),
+            },
+            counters: CounterSettings {
+                hourly_buckets: env_or(
+                    "MEDIATHEK_COUNTERS_HOURLY_BUCKETS",
+                    if env_or("MEDIATHEK_COUNTERS_EXTENDED_HOURLY", false) { 48 } else { 3 },
+                ),
+                daily_buckets: env_or("MEDIATHEK_COUNTERS_DAILY_BUCKETS", 13),
+                weekly_buckets: env_or("MEDIATHEK_COUNTERS_WEEKLY_BUCKETS", 4),
+                monthly_buckets: env_or("MEDIATHEK_COUNTERS_MONTHLY_BUCKETS", 3),
+                rotation_timezone: env_or("MEDIATHEK_ROTATION_TIMEZONE", host_timezone()),
+                event_log: env_or("MEDIATHEK_COUNTERS_EVENT_LOG", true),
+                event_log_sync_batch: env_or("MEDIATHEK_COUNTERS_EVENT_LOG_SYNC_BATCH", 32),
+                backend: env_or("MEDIATHEK_COUNTERS_BACKEND", CounterBackend::Memory),
+                redis_url: env_or("MEDIATHEK_REDIS_URL", "redis://127.0.0.1/".to_string()),
+                redis_key_prefix: env_or("MEDIATHEK_REDIS_KEY_PREFIX", "mediathek:counters".to_string()),
+                gossip_peers: env_list("MEDIATHEK_COUNTERS_GOSSIP_PEERS", ""),
+                gossip_node_id: env_or("MEDIATHEK_COUNTERS_GOSSIP_NODE_ID", {
+                    let host = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+                    format!("{}:{}", host, env_or("MEDIATHEK_PORT", 3030u16))
+                }),
+                sync_interval_secs: env_or("MEDIATHEK_COUNTERS_SYNC_INTERVAL_SECS", 5).max(1),
+                persist_interval_secs: env_or("MEDIATHEK_COUNTERS_PERSIST_INTERVAL_SECS", 0),
+                minute_buckets: env_or("MEDIATHEK_COUNTERS_MINUTE_BUCKETS", 0),
+                popularity_half_life_secs: env_or("MEDIATHEK_COUNTERS_POPULARITY_HALF_LIFE_SECS", 86_400),
+                labels: env_or("MEDIATHEK_COUNTERS_LABELS", BucketLabels::default()),
+            },
+            storage: StorageSettings {
+                data_dir: env_path("MEDIATHEK_DATA_DIR").unwrap_or_else(|| PathBuf::from(".")),
+                sqlite_path: env_path("MEDIATHEK_SQLITE_PATH"),
+                postgres_url: lookup("MEDIATHEK_POSTGRES_URL").filter(|url| !url.is_empty()),
+                postgres_max_connections: env_or("MEDIATHEK_POSTGRES_MAX_CONNECTIONS", 4),
+                postgres_flush_interval_ms: env_or("MEDIATHEK_POSTGRES_FLUSH_INTERVAL_MS", 1000).max(10),
+                pairs_path: env_path("MEDIATHEK_PAIRS_PATH"),
+                pairs_hot_cache: env_or("MEDIATHEK_PAIRS_HOT_CACHE", 10000),
+                pairs_page_cache_mb: env_or("MEDIATHEK_PAIRS_PAGE_CACHE_MB", 256),
+                lists_wal: env_or("MEDIATHEK_LISTS_WAL", true),
+                lists_wal_sync_batch: env_or("MEDIATHEK_LISTS_WAL_SYNC_BATCH", 32),
+                lists_snapshot_interval_secs: env_or("MEDIATHEK_LISTS_SNAPSHOT_INTERVAL_SECS", 3600).max(1),
+                lists_compaction_deltas: env_or("MEDIATHEK_LISTS_COMPACTION_DELTAS", 24),
+                snapshots: SnapshotSettings {
+                    format: env_or("MEDIATHEK_SNAPSHOT_FORMAT", SnapshotFormat::Json),
+                    compression_level: env_or("MEDIATHEK_SNAPSHOT_COMPRESSION_LEVEL", 0).clamp(0, 22),
+                    keep_last: env_or("MEDIATHEK_SNAPSHOT_KEEP_LAST", 0),
+                    keep_daily: env_or("MEDIATHEK_SNAPSHOT_KEEP_DAILY", 0),
+                    keep_weekly: env_or("MEDIATHEK_SNAPSHOT_KEEP_WEEKLY", 0),
+                },
+                state_backend: env_or("MEDIATHEK_STATE_BACKEND", StateBackend::File),
+                import_path: env_path("MEDIATHEK_IMPORT_PATH"),
+            },
+            association_rules: AssociationRuleSettings {
+                min_support: env_or("MEDIATHEK_RULES_MIN_SUPPORT", 0.01),
+                min_confidence: env_or("MEDIATHEK_RULES_MIN_CONFIDENCE", 0.2),
+                max_itemset_size: env_or("MEDIATHEK_RULES_MAX_ITEMSET_SIZE", 3),
+                mining_interval_secs: env_or("MEDIATHEK_RULES_MINING_INTERVAL_SECS", 600),
+            },
+            quality: QualitySettings {
+                interval_secs: env_or("MEDIATHEK_QUALITY_INTERVAL_SECS", 3600),
+                min_neighbors: env_or("MEDIATHEK_QUALITY_MIN_NEIGHBORS", 5),
+                neighbors: env_or("MEDIATHEK_QUALITY_NEIGHBORS", 10).max(1),
+                sample_size: env_or("MEDIATHEK_QUALITY_SAMPLE_SIZE", 1000),
+            },
+            evaluation: EvaluationSettings {
+                holdout_fraction: env_or("MEDIATHEK_EVALUATION_HOLDOUT_FRACTION", 0.0_f64).clamp(0.0, 1.0),
+                holdout_capacity: env_or("MEDIATHEK_EVALUATION_HOLDOUT_CAPACITY", 10_000),
+                k: env_or("MEDIATHEK_EVALUATION_K", 10).max(1),
+                interval_secs: env_or("MEDIATHEK_EVALUATION_INTERVAL_SECS", 3600).max(1),
+            },
+            embeddings: EmbeddingSettings {
+                dimensions: env_or("MEDIATHEK_EMBEDDINGS_DIMENSIONS", 32),
+                window: env_or("MEDIATHEK_EMBEDDINGS_WINDOW", 5),
+                negative_samples: env_or("MEDIATHEK_EMBEDDINGS_NEGATIVE_SAMPLES", 5),
+                epochs: env_or("MEDIATHEK_EMBEDDINGS_EPOCHS", 5),
+                learning_rate: env_or("MEDIATHEK_EMBEDDINGS_LEARNING_RATE", 0.025),
+                min_count: env_or("MEDIATHEK_EMBEDDINGS_MIN_COUNT", 2),
+                training_interval_secs: env_or("MEDIATHEK_EMBEDDINGS_TRAINING_INTERVAL_SECS", 3600),
+            },
+            factorization: FactorizationSettings {
+                enabled: env_or("MEDIATHEK_FACTORIZATION_ENABLED", false),
+                factors: env_or("MEDIATHEK_FACTORIZATION_FACTORS", 16),
+                iterations: env_or("MEDIATHEK_FACTORIZATION_ITERATIONS", 10),
+                regularization: env_or("MEDIATHEK_FACTORIZATION_REGULARIZATION", 0.1),
+                alpha: env_or("MEDIATHEK_FACTORIZATION_ALPHA", 40.0),
+                training_interval_secs: env_or("MEDIATHEK_FACTORIZATION_TRAINING_INTERVAL_SECS", 3600),
+            },
+            alerts: AlertSettings {
+                spike_ratio: env_or("MEDIATHEK_ALERTS_SPIKE_RATIO", 5.0),
+                min_count: env_or("MEDIATHEK_ALERTS_MIN_COUNT", 50),
+                check_interval_secs: env_or("MEDIATHEK_ALERTS_CHECK_INTERVAL_SECS", 60),
+                cooldown_secs: env_or("MEDIATHEK_ALERTS_COOLDOWN_SECS", 3600),
+                history: env_or("MEDIATHEK_ALERTS_HISTORY", 1000),
+                webhook_url: lookup("MEDIATHEK_ALERTS_WEBHOOK_URL").filter(|url| !url.is_empty()),
+            },
+            digest: DigestSettings {
+                webhook_urls: env_list("MEDIATHEK_DIGEST_WEBHOOK_URLS", ""),
+                interval_secs: env_or("MEDIATHEK_DIGEST_INTERVAL_SECS", 0),
+                after_rotation: env_or("MEDIATHEK_DIGEST_AFTER_ROTATION", true),
+                windows: env_list("MEDIATHEK_DIGEST_WINDOWS", "today,yesterday,this_week"),
+                limit: env_or("MEDIATHEK_DIGEST_LIMIT", 10),
+                max_retries: env_or("MEDIATHEK_DIGEST_MAX_RETRIES", 5),
+                retry_backoff_secs: env_or("MEDIATHEK_DIGEST_RETRY_BACKOFF_SECS", 2),
+            },
+            logging: LogSettings {
+                level: env_or("MEDIATHEK_LOG_LEVEL", "info".to_string()),
+                json: env_or("MEDIATHEK_LOG_JSON", true),
+                otlp_endpoint: lookup("MEDIATHEK_OTLP_ENDPOINT").filter(|url| !url.is_empty()),
+                sentry_dsn: lookup("MEDIATHEK_SENTRY_DSN").filter(|dsn| !dsn.is_empty()),
+                sentry_environment: env_or("MEDIATHEK_SENTRY_ENVIRONMENT", "production".to_string()),
+                sentry_release: env_or("MEDIATHEK_SENTRY_RELEASE", env!("CARGO_PKG_VERSION").to_string()),
+            },
+            validation: ValidationSettings {
+                max_list_identifiers: env_or("MEDIATHEK_VALIDATION_MAX_LIST_IDENTIFIERS", 200),
+                max_identifier_length: env_or("MEDIATHEK_VALIDATION_MAX_IDENTIFIER_LENGTH", 256),
+                identifier_pattern: env_or(
+                    "MEDIATHEK_VALIDATION_IDENTIFIER_PATTERN",
+                    Regex::new("^[!-~]+$").expect("the default identifier pattern is valid"),
+                ),
+                normalization: NormalizationSettings {
+                    percent_decode: env_or("MEDIATHEK_NORMALIZE_PERCENT_DECODE", false),
+                    trim: env_or("MEDIATHEK_NORMALIZE_TRIM", false),
+                    case_fold: env_or("MEDIATHEK_NORMALIZE_CASE_FOLD", CaseFold::None),
+                    rewrites: env_or("MEDIATHEK_NORMALIZE_REWRITES", IdentifierRewrites::default()),
+                },
+            },
+            rate_limit: RateLimitSettings {
+                enabled: env_or("MEDIATHEK_RATE_LIMIT_ENABLED", false),
+                default_limit: env_or("MEDIATHEK_RATE_LIMIT_DEFAULT", RateLimit { per_second: 50.0, burst: 100 }),
+                route_limits: env_or("MEDIATHEK_RATE_LIMIT_ROUTES", RouteRateLimits::default()),
+                trust_proxy: env_or("MEDIATHEK_RATE_LIMIT_TRUST_PROXY", false),
+            },
+            overload: OverloadSettings {
+                max_in_flight: env_or("MEDIATHEK_OVERLOAD_MAX_IN_FLIGHT", 0),
+                route_limits: env_or("MEDIATHEK_OVERLOAD_ROUTES", RouteConcurrencyLimits::default()),
+                lock_timeout_ms: env_or("MEDIATHEK_OVERLOAD_LOCK_TIMEOUT_MS", 0),
+                retry_after_secs: env_or("MEDIATHEK_OVERLOAD_RETRY_AFTER_SECS", 1u64).max(1),
+                request_timeout_ms: env_or("MEDIATHEK_REQUEST_TIMEOUT_MS", 0),
+            },
+            auth: AuthSettings {
+                // Not read with `env_or`, which would echo the keys and silently fall back to
+                // no authentication at all; a malformed value aborts the startup instead
+                api_keys: match lookup("MEDIATHEK_API_KEYS") {
+                    Some(value) => value.parse().unwrap_or_else(|e| panic!("Invalid MEDIATHEK_API_KEYS: {}", e)),
+                    None => ApiKeys::default(),
+                },
+                protect_reads: env_or("MEDIATHEK_AUTH_PROTECT_READS", false),
+                daily_quotas: env_or("MEDIATHEK_AUTH_DAILY_QUOTAS", DailyQuotas::default()),
+            },
+            admin: AdminSettings {
+                enabled: env_or("MEDIATHEK_ADMIN_ENABLED", true),
+                // Not read with `env_or`, which would echo the token
+                token: lookup("MEDIATHEK_ADMIN_TOKEN").filter(|token| !token.is_empty()),
+            },
+            allowlist: AllowlistSettings {
+                // Not read with `env_or`, which would silently fall back to allowing everyone
+                networks: match lookup("MEDIATHEK_ALLOWLIST_NETWORKS") {
+                    Some(value) => value.parse().unwrap_or_else(|e| panic!("Invalid MEDIATHEK_ALLOWLIST_NETWORKS: {}", e)),
+                    None => IpNetworks::default(),
+                },
+                include_writes: env_or("MEDIATHEK_ALLOWLIST_WRITES", false),
+                trust_proxy: env_or("MEDIATHEK_ALLOWLIST_TRUST_PROXY", false),
+            },
+            signing: SigningSettings {
+                // Read directly for the same reasons as the API keys
+                client_secrets: match lookup("MEDIATHEK_SIGNING_SECRETS") {
+                    Some(value) => value.parse().unwrap_or_else(|e| panic!("Invalid MEDIATHEK_SIGNING_SECRETS: {}", e)),
+                    None => ApiKeys::default(),
+                },
+                required: env_or("MEDIATHEK_SIGNING_REQUIRED", false),
+                max_skew_secs: env_or("MEDIATHEK_SIGNING_MAX_SKEW_SECS", 300),
+                max_body_bytes: env_or("MEDIATHEK_SIGNING_MAX_BODY_BYTES", 16 * 1024 * 1024),
+            },
+            tls: TlsSettings {
+                cert_path: env_path("MEDIATHEK_TLS_CERT"),
+                key_path: env_path("MEDIATHEK_TLS_KEY"),
+                client_ca_path: env_path("MEDIATHEK_TLS_CLIENT_CA"),
+            },
+            compression: CompressionSettings {
+                enabled: env_or("MEDIATHEK_COMPRESSION_ENABLED", true),
+                level: env_or("MEDIATHEK_COMPRESSION_LEVEL", 5).min(11),
+                min_bytes: env_or("MEDIATHEK_COMPRESSION_MIN_BYTES", 1024),
+            },
+            grpc: GrpcSettings {
+                enabled: env_or("MEDIATHEK_GRPC_ENABLED", false),
+                port: env_or("MEDIATHEK_GRPC_PORT", 50051),
+            },
+            websocket: WebSocketSettings {
+                check_interval_secs: env_or("MEDIATHEK_WS_CHECK_INTERVAL_SECS", 2).max(1),
+                min_change: env_or("MEDIATHEK_WS_MIN_CHANGE", 0.1),
+                push_interval_secs: env_or("MEDIATHEK_WS_PUSH_INTERVAL_SECS", 30),
+            },
+            kafka: KafkaSettings {
+                brokers: lookup("MEDIATHEK_KAFKA_BROKERS").filter(|brokers| !brokers.is_empty()),
+                group: env_or("MEDIATHEK_KAFKA_GROUP", "mediathek-recommendations".to_string()),
+                lists_topic: lookup("MEDIATHEK_KAFKA_LISTS_TOPIC").filter(|topic| !topic.is_empty()),
+                plays_topic: lookup("MEDIATHEK_KAFKA_PLAYS_TOPIC").filter(|topic| !topic.is_empty()),
+            },
+            nats: NatsSettings {
+                url: lookup("MEDIATHEK_NATS_URL").filter(|url| !url.is_empty()),
+                stream: env_or("MEDIATHEK_NATS_STREAM", "MEDIATHEK_EVENTS".to_string()),
+                consumer: env_or("MEDIATHEK_NATS_CONSUMER", "mediathek-recommendations".to_string()),
+                lists_subject: env_or("MEDIATHEK_NATS_LISTS_SUBJECT", "mediathek.lists".to_string()),
+                plays_subject: env_or("MEDIATHEK_NATS_PLAYS_SUBJECT", "mediathek.plays".to_string()),
+            },
+            object_storage: {
+                let region = env_or("MEDIATHEK_S3_REGION", "us-east-1".to_string());
+                ObjectStorageSettings {
+                    bucket: lookup("MEDIATHEK_S3_BUCKET").filter(|bucket| !bucket.is_empty()),
+                    endpoint: env_or("MEDIATHEK_S3_ENDPOINT", format!("https://s3.{}.amazonaws.com", region)),
+                    region,
+                    prefix: env_or("MEDIATHEK_S3_PREFIX", "snapshots/".to_string()),
+                    access_key_id: env_or("MEDIATHEK_S3_ACCESS_KEY_ID", String::new()),
+                    secret_access_key: env_or("MEDIATHEK_S3_SECRET_ACCESS_KEY", String::new()),
+                }
+            },
+            tenants: TenantSettings {
+                names: env_list("MEDIATHEK_TENANTS", ""),
+            },
+            eviction: EvictionSettings {
+                ttl_days: env_or("MEDIATHEK_EVICTION_TTL_DAYS", 0),
+                interval_secs: env_or("MEDIATHEK_EVICTION_INTERVAL_SECS", 3600).max(1),
+            },
+            compaction: CompactionSettings {
+                interval_secs: env_or("MEDIATHEK_COMPACTION_INTERVAL_SECS", 86400),
+            },
+            replication: ReplicationSettings {
+                primary_url: lookup("MEDIATHEK_REPLICATION_PRIMARY_URL").filter(|url| !url.is_empty()),
+                primary_token: lookup("MEDIATHEK_REPLICATION_PRIMARY_TOKEN").filter(|token| !token.is_empty()),
+                buffer: env_or("MEDIATHEK_REPLICATION_BUFFER", 65536).max(1),
+                heartbeat_secs: env_or("MEDIATHEK_REPLICATION_HEARTBEAT_SECS", 10).max(1),
+            },
+            shards: ShardSettings {
+                peers: env_list("MEDIATHEK_SHARD_PEERS", ""),
+                virtual_nodes: env_or("MEDIATHEK_SHARD_VIRTUAL_NODES", 128).max(1),
+                timeout_ms: env_or("MEDIATHEK_SHARD_TIMEOUT_MS", 2000),
+            },
+            determinism: DeterminismSettings {
+                enabled: env_or("MEDIATHEK_DETERMINISTIC", false),
+                seed: env_or("MEDIATHEK_DETERMINISTIC_SEED", 0),
+                start: env_or("MEDIATHEK_DETERMINISTIC_START", Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()),
+            },
+            scoring: ScoringSettings {
+                pipelines: env_or("MEDIATHEK_SCORING_PIPELINES", ScoringPipelines::default()),
+                shadow: env_or("MEDIATHEK_SCORING_SHADOW", ScoringPipelines::default()),
+                shrinkage: Shrinkage {
+                    prior: env_or("MEDIATHEK_SCORING_SHRINKAGE_PRIOR", 0.0f64).clamp(0.0, 1.0),
+                    pseudo_counts: env_or("MEDIATHEK_SCORING_SHRINKAGE_PSEUDO_COUNTS", 0.0f64).max(0.0),
+                },
+            },
+            idempotency: IdempotencySettings {
+                capacity: env_or("MEDIATHEK_IDEMPOTENCY_CAPACITY", 100_000),
+                window_secs: env_or("MEDIATHEK_IDEMPOTENCY_WINDOW_SECS", 600),
+            },
+            session_dedup: SessionDedupSettings {
+                capacity: env_or("MEDIATHEK_SESSION_DEDUP_CAPACITY", 0),
+                window_secs: env_or("MEDIATHEK_SESSION_DEDUP_WINDOW_SECS", 300),
+            },
+            warmup: WarmupSettings {
+                url: lookup("MEDIATHEK_WARMUP_URL").filter(|url| !url.is_empty()),
+                sha256: lookup("MEDIATHEK_WARMUP_SHA256").filter(|checksum| !checksum.is_empty()),
+            },
+            tombstones: TombstoneSettings {
+                ttl_secs: env_or("MEDIATHEK_TOMBSTONES_TTL_SECS", 0),
+                mode: env_or("MEDIATHEK_TOMBSTONES_MODE", TombstoneMode::Drop),
+            },
+            recorder: RecorderSettings {
+                enabled: env_or("MEDIATHEK_RECORDER_ENABLED", false),
+                max_file_bytes: env_or("MEDIATHEK_RECORDER_MAX_FILE_BYTES", 100 * 1024 * 1024),
+                max_body_bytes: env_or("MEDIATHEK_RECORDER_MAX_BODY_BYTES", 256 * 1024),
+            },
+            ingest_queue: IngestQueueSettings {
+                capacity: env_or("MEDIATHEK_INGEST_QUEUE_CAPACITY", 0),
+            },
+            cardinality: CardinalitySettings {
+                max_identifiers: env_or("MEDIATHEK_CARDINALITY_MAX_IDENTIFIERS", 0),
+                policy: env_or("MEDIATHEK_CARDINALITY_POLICY", CardinalityPolicy::Reject),
+            },
+            two_hop: TwoHopSettings {
+                decay: env_or("MEDIATHEK_TWO_HOP_DECAY", 0.5),
+                fan_out: env_or("MEDIATHEK_TWO_HOP_FAN_OUT", 10),
+                max_candidates: env_or("MEDIATHEK_TWO_HOP_MAX_CANDIDATES", 100),
+            },
+            business_metrics: BusinessMetricsSettings {
+                top_n: env_or("MEDIATHEK_BUSINESS_METRICS_TOP_N", 10),
+            },
+        }
+    }
+}
+
+/// The settings the server runs with. Reloading replaces the reloadable sections (see
+/// `reload_from`) at once, so a request sees either the old or the new settings, never a
+/// mix.
+#[derive(Debug)]
+pub struct SharedSettings(RwLock<Arc<Settings>>);
+
+impl SharedSettings {
+    pub fn new(settings: Settings) -> Self {
+        SharedSettings(RwLock::new(Arc::new(settings)))
+    }
+
+    /// Returns the current settings.
+    pub fn current(&self) -> Arc<Settings> {
+        Arc::clone(&locks::read(&self.0, "settings"))
+    }
+
+    /// Reads the settings again from the same sources as at startup, see `reload_from`.
+    /// The current settings stay in place if the config file can't be read.
+    pub fn reload(&self) -> Result<(), String> {
+        self.reload_from(Settings::load(env::args())?);
+        info!("Settings reloaded.");
+        Ok(())
+    }
+
+    /// Takes over the sections of `loaded` that apply to every request: the rate limits,
+    /// the validation limits, the API keys and admin token, the allowlist, the signing
+    /// secrets, the compression, the WebSocket pushes, the scoring pipelines and shrinkage,
+    /// the source weights, the ingest rules and the counter labels. Everything else is only read at startup,
+    /// so changing it needs a restart.
+    pub fn reload_from(&self, loaded: Settings) {
+        let mut current = locks::write(&self.0, "settings");
+        let mut settings = Settings::clone(&current);
+        settings.rate_limit = loaded.rate_limit;
+        settings.overload = loaded.overload;
+        settings.validation = loaded.validation;
+        settings.auth = loaded.auth;
+        settings.admin.token = loaded.admin.token;
+        settings.allowlist = loaded.allowlist;
+        settings.signing = loaded.signing;
+        settings.compression = loaded.compression;
+        settings.websocket = loaded.websocket;
+        settings.scoring = loaded.scoring;
+        settings.source_weights = loaded.source_weights;
+        settings.ingest_rules = loaded.ingest_rules;
+        settings.counters.labels = loaded.counters.labels;
+        *current = Arc::new(settings);
+    }
+}
+
+// Function to reload the settings whenever the process receives SIGHUP
+#[cfg(unix)]
+pub async fn run_reload_on_hangup(settings: Arc<SharedSettings>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP, settings can only be reloaded via POST /admin/reload: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        let settings = Arc::clone(&settings);
+        match web::block(move || settings.reload()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Failed to reload the settings, keeping the current ones: {}", e),
+            Err(e) => error!("Error in settings reload block: {:?}", e),
+        }
+    }
+}
+
+/// Returns the host's time zone, or UTC if it cannot be determined.
+fn host_timezone() -> Tz {
+    iana_time_zone::get_timezone()
+        .ok()
+        .and_then(|name| name.parse().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+/// The config file and command-line values, keyed by environment variable, while
+/// `Settings::load` reads them.
+#[derive(Default)]
+struct Layers {
+    /// Command-line values, which take precedence over the environment
+    flags: HashMap<String, String>,
+    /// Config file values, which the environment takes precedence over
+    file: HashMap<String, String>,
+    /// Every variable looked up, for reporting unknown settings
+    read: HashSet<String>,
+}
+
+thread_local! {
+    static LAYERS: RefCell<Layers> = RefCell::new(Layers::default());
+}
+
+/// Looks up a setting by its environment variable: on the command line, in the
+/// environment, then in the config file.
+fn lookup(key: &str) -> Option<String> {
+    LAYERS.with(|layers| {
+        let mut layers = layers.borrow_mut();
+        layers.read.insert(key.to_string());
+        layers.flags.get(key).cloned().or_else(|| env::var(key).ok()).or_else(|| layers.file.get(key).cloned())
+    })
+}
+
+/// Environment variable of a setting named in the config file or `--set`, e.g.
+/// "MEDIATHEK_COUNTERS_HOURLY_BUCKETS" for "counters.hourly_buckets".
+fn env_key(name: &str) -> String {
+    format!("MEDIATHEK_{}", name.replace(['.', '-'], "_").to_uppercase())
+}
+
+/// Reads a TOML config file into values keyed by environment variable.
+fn read_config_file(path: &Path) -> Result<HashMap<String, String>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+    let table: toml::Table = content.parse().map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+    let mut values = HashMap::new();
+    flatten_table("", &table, &mut values);
+    Ok(values)
+}
+
+/// Adds the values of a TOML table, nested tables included, named by their path.
+fn flatten_table(prefix: &str, table: &toml::Table, values: &mut HashMap<String, String>) {
+    let as_string = |value: &toml::Value| match value {
+        toml::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    };
+    for (key, value) in table {
+        let name = if prefix.is_empty() { key.clone() } else { format!("{}_{}", prefix, key) };
+        let value = match value {
+            toml::Value::Table(table) => {
+                flatten_table(&name, table, values);
+                continue;
+            }
+            toml::Value::Array(items) => items.iter().map(as_string).collect::<Vec<_>>().join(","),
+            value => as_string(value),
+        };
+        values.insert(env_key(&name), value);
+    }
+}
+
+/// Reads an optional file path from an environment variable; empty values count as unset.
+fn env_path(key: &str) -> Option<PathBuf> {
+    lookup(key).filter(|path| !path.is_empty()).map(PathBuf::from)
+}
+
+/// Reads a comma-separated list from an environment variable, `default` if it is missing.
+/// Empty entries are skipped.
+fn env_list(key: &str, default: &str) -> Vec<String> {
+    let value = lookup(key).unwrap_or_else(|| default.to_string());
+    value.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(String::from).collect()
+}
+
+/// Reads and parses an environment variable, falling back to `default` if it is missing.
+/// Unparseable values are reported and ignored. Settings are read before logging is set
+/// up, so this writes to stderr directly.
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match lookup(key) {
+        Some(value) => match value.parse() {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                eprintln!("Ignoring invalid value '{}' for {}.", value, key);
+                default
+            }
+        },
+        None => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_take_precedence_over_the_config_file() {
+        let path = env::temp_dir().join(format!("mediathek_config_{}.toml", std::process::id()));
+        fs::write(&path, "port = 4000\n\n[counters]\nhourly_buckets = 48\ndaily_buckets = 20\n\n[digest]\nwebhook_urls = [\"http://a\", \"http://b\"]\n").unwrap();
+        let args = ["mediathek_rs", "--config", path.to_str().unwrap(), "--set", "counters.daily_buckets=30", "--bind", "0.0.0.0"];
+        let settings = Settings::load(args.map(String::from)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(settings.server.port, 4000);
+        assert_eq!(settings.server.bind_address, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(settings.counters.hourly_buckets, 48);
+        assert_eq!(settings.counters.daily_buckets, 30);
+        assert_eq!(settings.digest.webhook_urls, ["http://a", "http://b"]);
+        assert_eq!(env_key("counters.hourly-buckets"), "MEDIATHEK_COUNTERS_HOURLY_BUCKETS");
+    }
+
+    #[test]
+    fn test_the_server_runs_without_a_subcommand() {
+        assert_eq!(Command::from_args(["mediathek_rs", "4000"].map(String::from)), Command::Serve);
+        assert_eq!(
+            Command::from_args(["mediathek_rs", "top", "counters.json", "--window", "last_24h"].map(String::from)),
+            Command::Top { path: PathBuf::from("counters.json"), window: "last_24h".to_string(), limit: 10 }
+        );
+    }
+
+    #[test]
+    fn test_reload_only_replaces_the_reloadable_settings() {
+        let shared = SharedSettings::new(Settings::from_env());
+        let before = shared.current();
+        let mut loaded = Settings::from_env();
+        loaded.rate_limit.default_limit = RateLimit { per_second: 1.0, burst: 1 };
+        loaded.validation.max_list_identifiers = 7;
+        loaded.server.port = 1;
+        shared.reload_from(loaded);
+
+        let after = shared.current();
+        assert_eq!(after.rate_limit.default_limit.burst, 1);
+        assert_eq!(after.validation.max_list_identifiers, 7);
+        assert_eq!(after.server.port, before.server.port);
+        // Requests already running keep the settings they started with
+        assert_ne!(before.validation.max_list_identifiers, 7);
+    }
+
+    #[test]
+    fn test_configured_bucket_labels_override_the_built_in_ones() {
+        let labels: BucketLabels = "de.today=Heute;fr.today=aujourd'hui;fr.day_minus_{n}=il y a {n} jours".parse().unwrap();
+        assert_eq!(labels.label("de", "today"), "Heute");
+        assert_eq!(labels.label("de", "yesterday"), "gestern");
+        assert_eq!(labels.label("de", "hour_minus_5"), "vor 5 Stunden");
+        assert_eq!(labels.label("fr", "day_minus_12"), "il y a 12 jours");
+        assert_eq!(labels.label("fr", "this_week"), "this_week");
+        assert!(labels.knows("en") && labels.knows("fr") && !labels.knows("it"));
+        assert!("today=heute".parse::<BucketLabels>().is_err());
+    }
+}
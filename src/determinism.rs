@@ -0,0 +1,118 @@
+// src/determinism.rs
+use std::sync::OnceLock;
+use ahash::RandomState;
+use chrono::{DateTime, Utc};
+use tokio::sync::watch;
+
+use crate::config::DeterminismSettings;
+
+// The clock and hash seeds of the whole process, so the code reading them doesn't need
+// the settings. Normally the system clock and random seeds; in deterministic mode (see
+// `DeterminismSettings`) a clock that only moves when told to, and fixed seeds. State
+// kept in `DashMap`s (the counters) still iterates in random order, but holds the same
+// counts.
+
+static DETERMINISTIC: OnceLock<Deterministic> = OnceLock::new();
+
+struct Deterministic {
+    seed: u64,
+    clock: ManualClock,
+}
+
+/// A clock standing still until set, which wakes everyone waiting for a later time.
+pub struct ManualClock {
+    now: watch::Sender<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        ManualClock { now: watch::Sender::new(start) }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    /// Moves the clock to `at`. It never goes back, as the counters can't rotate back.
+    pub fn set(&self, at: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+        let mut result = Ok(at);
+        self.now.send_if_modified(|now| {
+            if at < *now {
+                result = Err(format!("The clock is at {} already and can't go back", now.to_rfc3339()));
+                return false;
+            }
+            let modified = at != *now;
+            *now = at;
+            modified
+        });
+        result
+    }
+
+    /// Waits until the clock reaches `deadline`.
+    pub async fn sleep_until(&self, deadline: DateTime<Utc>) {
+        let mut receiver = self.now.subscribe();
+        // The sender lives as long as the clock, so this only returns once it is reached
+        let _ = receiver.wait_for(|now| *now >= deadline).await;
+    }
+}
+
+/// Turns on deterministic mode for the rest of the process, if enabled in `settings`.
+/// Has to happen before any state is created, as existing maps keep their seeds.
+pub fn configure(settings: &DeterminismSettings) {
+    if settings.enabled && DETERMINISTIC.set(Deterministic { seed: settings.seed, clock: ManualClock::new(settings.start) }).is_ok() {
+        tracing::warn!(seed = settings.seed, start = %settings.start.to_rfc3339(), "Deterministic mode is on; don't use it in production.");
+    }
+}
+
+/// The clock of deterministic mode, if on.
+pub fn manual_clock() -> Option<&'static ManualClock> {
+    DETERMINISTIC.get().map(|deterministic| &deterministic.clock)
+}
+
+/// The current time: the system's, or that of the deterministic clock.
+pub fn now() -> DateTime<Utc> {
+    manual_clock().map_or_else(Utc::now, ManualClock::now)
+}
+
+/// Waits until `now()` reaches `deadline`.
+pub async fn sleep_until(deadline: DateTime<Utc>) {
+    match manual_clock() {
+        Some(clock) => clock.sleep_until(deadline).await,
+        None => tokio::time::sleep((deadline - Utc::now()).to_std().unwrap_or_default()).await,
+    }
+}
+
+/// The hasher of new hash maps: randomly seeded, or by the seed of deterministic mode.
+pub fn random_state() -> RandomState {
+    match DETERMINISTIC.get() {
+        Some(deterministic) => seeded_state(deterministic.seed),
+        None => RandomState::new(),
+    }
+}
+
+fn seeded_state(seed: u64) -> RandomState {
+    RandomState::with_seeds(seed, seed.rotate_left(16), seed.rotate_left(32), seed.rotate_left(48))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_manual_clock_only_moves_forward_and_wakes_sleepers() {
+        let start = DateTime::UNIX_EPOCH;
+        let clock = std::sync::Arc::new(ManualClock::new(start));
+        let sleeper = {
+            let clock = std::sync::Arc::clone(&clock);
+            actix_web::rt::spawn(async move { clock.sleep_until(start + chrono::Duration::hours(1)).await })
+        };
+        assert!(clock.set(start + chrono::Duration::minutes(30)).is_ok());
+        assert!(clock.set(start).is_err());
+        assert!(!sleeper.is_finished());
+        clock.set(start + chrono::Duration::hours(2)).unwrap();
+        sleeper.await.unwrap();
+        assert_eq!(clock.now(), start + chrono::Duration::hours(2));
+
+        assert_eq!(seeded_state(7).hash_one("a"), seeded_state(7).hash_one("a"));
+    }
+}
@@ -0,0 +1,133 @@
+// src/engine.rs
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+use crate::algorithms::rotating_counters::{count_of, top_entries, CountEntry};
+use crate::algorithms::state_store::open_state_store;
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::config::{CounterSettings, Settings};
+use crate::locks;
+
+/// Co-occurrences and popularity counters behind one API, as the server uses them.
+///
+/// The engine is cheap to clone and can be shared between threads; clones see the same
+/// state. Nothing happens in the background: callers rotate the counters with
+/// `advance_to` and write the state with `persist`.
+#[derive(Clone)]
+pub struct RecommendationEngine {
+    co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    counters: Arc<RwLock<Counters>>,
+    timezone: Tz,
+}
+
+impl RecommendationEngine {
+    /// Creates an empty engine kept in memory only, with the counter depths and time zone
+    /// of `settings`.
+    pub fn in_memory(settings: &CounterSettings) -> Self {
+        let counters =
+            Counters::with_depths(settings.hourly_buckets, settings.daily_buckets, settings.weekly_buckets, settings.monthly_buckets);
+        RecommendationEngine {
+            co_occurrence: Arc::new(RwLock::new(CoOccurrenceCounter::new())),
+            counters: Arc::new(RwLock::new(counters)),
+            timezone: settings.rotation_timezone,
+        }
+    }
+
+    /// Loads the state the server persisted in the data directory of `settings`, and keeps
+    /// persisting it there. Databases aren't attached; use the server for those.
+    pub fn open(settings: &Settings) -> Self {
+        let mut co_occurrence = CoOccurrenceCounter::with_metrics_cache(&settings.metrics_cache);
+        co_occurrence.set_pair_strategy(settings.pair_strategy);
+        co_occurrence.set_co_visitation_window(settings.co_visitation_window_secs);
+        co_occurrence.set_cardinality_limit(&settings.cardinality);
+        co_occurrence.set_identifier_filter(&settings.identifier_filter);
+        co_occurrence.recover(&settings.storage);
+        RecommendationEngine {
+            co_occurrence: Arc::new(RwLock::new(co_occurrence)),
+            counters: Arc::new(RwLock::new(Counters::new(&settings.counters, &settings.storage, open_state_store(&settings.storage), None))),
+            timezone: settings.counters.rotation_timezone,
+        }
+    }
+
+    /// Counts every pair of identifiers in a list, e.g. a watch session.
+    pub fn add_list(&self, identifiers: &[String]) {
+        locks::write(&self.co_occurrence, "co_occurrence").process_list(identifiers);
+    }
+
+    /// Adds `count` plays of `id` to the current buckets.
+    pub fn record_play(&self, id: &str, count: u64) {
+        locks::read(&self.counters, "rotating_counters").increment(id, count);
+    }
+
+    /// Returns how often each identifier appeared in a list with `identifier`.
+    pub fn co_occurrences(&self, identifier: &str) -> HashMap<String, u64> {
+        locks::read(&self.co_occurrence, "co_occurrence").cached_metrics_for_identifier(identifier)
+    }
+
+    /// Returns the `limit` identifiers appearing most often with `identifier`, most
+    /// frequent first.
+    pub fn related(&self, identifier: &str, limit: usize) -> Vec<CountEntry> {
+        let mut related: Vec<CountEntry> =
+            self.co_occurrences(identifier).into_iter().map(|(id, count)| CountEntry { id, count }).collect();
+        related.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.id.cmp(&b.id)));
+        related.truncate(limit);
+        related
+    }
+
+    /// Returns the plays of `id` in a bucket or window like "today" or "last_7_days", or
+    /// `None` if there is no such bucket.
+    pub fn count(&self, id: &str, window: &str) -> Option<u64> {
+        locks::read(&self.counters, "rotating_counters").window(window).map(|bucket| count_of(&bucket, id))
+    }
+
+    /// Returns the `limit` most played identifiers in a bucket or window, or `None` if
+    /// there is no such bucket.
+    pub fn top(&self, window: &str, limit: usize) -> Option<Vec<CountEntry>> {
+        locks::read(&self.counters, "rotating_counters").window(window).map(|bucket| top_entries(&bucket, 0, limit))
+    }
+
+    /// Rotates the counter buckets up to `now`, in the time zone of the counter settings.
+    /// Returns whether any bucket was rotated.
+    pub fn advance_to(&self, now: DateTime<Utc>) -> bool {
+        locks::write(&self.counters, "rotating_counters").advance_to(&now.with_timezone(&self.timezone))
+    }
+
+    /// Writes snapshots of the state, if the engine was opened from a data directory.
+    pub fn persist(&self) {
+        locks::write(&self.co_occurrence, "co_occurrence").compact();
+        locks::write(&self.counters, "rotating_counters").persist();
+    }
+
+    /// The co-occurrences, for queries the engine doesn't cover.
+    pub fn co_occurrence_counter(&self) -> &Arc<RwLock<CoOccurrenceCounter>> {
+        &self.co_occurrence
+    }
+
+    /// The popularity counters, for queries the engine doesn't cover.
+    pub fn counters(&self) -> &Arc<RwLock<Counters>> {
+        &self.counters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_records_lists_and_plays() {
+        let engine = RecommendationEngine::in_memory(&Settings::from_env().counters);
+        engine.add_list(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        engine.add_list(&["a".to_string(), "c".to_string()]);
+        engine.record_play("a", 2);
+        engine.record_play("b", 5);
+
+        let related = engine.related("a", 1);
+        assert_eq!((related[0].id.as_str(), related[0].count), ("c", 2));
+        assert_eq!(engine.co_occurrences("a").len(), 2);
+        assert_eq!(engine.count("a", "today"), Some(2));
+        assert_eq!(engine.count("a", "no_such_window"), None);
+        assert_eq!(engine.top("today", 1).unwrap()[0].id, "b");
+    }
+}
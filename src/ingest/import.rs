@@ -0,0 +1,254 @@
+// src/ingest/import.rs
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api::v1::LineError;
+use crate::ingest::pipeline::{Admission, PlayVerdict};
+use crate::ingest::{rules, Ingestor, ListMessage, PlayMessage};
+use crate::locks;
+
+// Bulk import of historical lists and plays, e.g. to warm up a fresh instance. Files are
+// either NDJSON, with the bodies of POST /lists (`{"identifiers": [...]}`) and
+// POST /counters (`{"id": ..., "count": ...}`) as lines, or CSV, with lines like
+// "list,<identifier>,<identifier>,..." and "play,<identifier>[,<count>]".
+
+/// Longest line accepted, so a file without newlines can't exhaust the memory.
+pub const MAX_LINE_BYTES: usize = 1024 * 1024;
+/// Number of rejected lines reported with their reason.
+const MAX_REPORTED_LINE_ERRORS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    Ndjson,
+    Csv,
+}
+
+impl ImportFormat {
+    /// CSV for files ending in ".csv", NDJSON otherwise.
+    pub fn of_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => ImportFormat::Csv,
+            _ => ImportFormat::Ndjson,
+        }
+    }
+}
+
+/// What an import applied and rejected.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ImportSummary {
+    /// Number of lines processed as lists
+    pub lists: usize,
+    /// Number of lines processed as plays
+    pub plays: usize,
+    /// Number of lines skipped because they are malformed or violate the identifier limits
+    pub rejected: usize,
+    /// The first rejected lines with the reason
+    pub errors: Vec<LineError>,
+}
+
+/// A line of an NDJSON import.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Record {
+    List(ListMessage),
+    Play(PlayMessage),
+}
+
+fn parse_csv(line: &str) -> Result<Record, String> {
+    let mut fields = line.split(',').map(str::trim);
+    match fields.next() {
+        Some("list") => Ok(Record::List(ListMessage { identifiers: fields.map(str::to_string).collect(), source: None })),
+        Some("play") => {
+            let id = fields.next().ok_or("A play needs an identifier")?.to_string();
+            let count = match fields.next() {
+                Some(count) => Some(count.parse().map_err(|_| format!("Invalid count '{}'", count))?),
+                None => None,
+            };
+            Ok(Record::Play(PlayMessage { id, count }))
+        }
+        _ => Err("Lines must start with 'list' or 'play'".to_string()),
+    }
+}
+
+/// An import in progress, fed the data in chunks of any size.
+pub struct Import<'a> {
+    ingestor: &'a Ingestor,
+    format: ImportFormat,
+    /// The incomplete last line of the data so far
+    buffer: Vec<u8>,
+    line_number: usize,
+    summary: ImportSummary,
+}
+
+impl<'a> Import<'a> {
+    pub fn new(ingestor: &'a Ingestor, format: ImportFormat) -> Self {
+        Import { ingestor, format, buffer: Vec::new(), line_number: 0, summary: ImportSummary::default() }
+    }
+
+    /// Imports the complete lines received so far. Fails if a line exceeds
+    /// `MAX_LINE_BYTES`, leaving the lines before it imported.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), String> {
+        self.buffer.extend_from_slice(chunk);
+        if let Some(end) = self.buffer.iter().rposition(|&byte| byte == b'\n') {
+            let lines: Vec<u8> = self.buffer.drain(..=end).collect();
+            self.import_lines(&lines[..end]);
+        }
+        if self.buffer.len() > MAX_LINE_BYTES {
+            return Err(format!("Line {} exceeds {} bytes", self.line_number + 1, MAX_LINE_BYTES));
+        }
+        Ok(())
+    }
+
+    /// Imports the last line, which may lack its newline.
+    pub fn finish(mut self) -> ImportSummary {
+        let rest = std::mem::take(&mut self.buffer);
+        self.import_lines(&rest);
+        self.summary
+    }
+
+    /// Applies the lines in `data`, taking each lock once for all of them.
+    fn import_lines(&mut self, data: &[u8]) {
+        let ingestor = self.ingestor;
+        let settings = ingestor.settings.current();
+        let admission = Admission::new(&settings, &ingestor.tombstones, Some(&ingestor.co_occurrence));
+        let (mut lists, mut plays) = (Vec::new(), Vec::new());
+        let (mut routed_lists, mut routed_plays) = (Vec::new(), Vec::new());
+        for line in data.split(|&byte| byte == b'\n') {
+            self.line_number += 1;
+            let line = line.trim_ascii();
+            if line.is_empty() {
+                continue;
+            }
+            let record = match self.format {
+                ImportFormat::Ndjson => serde_json::from_slice(line).map_err(|e| e.to_string()),
+                ImportFormat::Csv => std::str::from_utf8(line).map_err(|e| e.to_string()).and_then(parse_csv),
+            };
+            let result = record.and_then(|record| match record {
+                Record::List(mut list) => {
+                    let routed = admission.list(&mut list.identifiers).map_err(|e| e.to_string())?;
+                    let weight = settings.source_weights.of(list.source.as_deref());
+                    routed_lists.push((routed, weight));
+                    lists.push((weight, list.identifiers));
+                    Ok(())
+                }
+                Record::Play(mut play) => {
+                    let count = play.count.unwrap_or(1);
+                    match admission.play(&mut play.id).map_err(|e| e.to_string())? {
+                        PlayVerdict::Count => plays.push((play.id, count)),
+                        PlayVerdict::Route(tenant) => routed_plays.push((tenant, play.id, count)),
+                        PlayVerdict::Dropped | PlayVerdict::Deleted => {}
+                    }
+                    Ok(())
+                }
+            });
+            if let Err(message) = result {
+                self.summary.rejected += 1;
+                if self.summary.errors.len() < MAX_REPORTED_LINE_ERRORS {
+                    self.summary.errors.push(LineError { line: self.line_number, message });
+                }
+            }
+        }
+        drop(admission);
+
+        for (routed, weight) in &routed_lists {
+            routed.count(&ingestor.tenants, *weight);
+        }
+        for (tenant, id, count) in &routed_plays {
+            rules::route_play(&ingestor.tenants, tenant, id, *count);
+        }
+        self.summary.plays += routed_plays.len();
+
+        if !lists.is_empty() {
+            let mut co_occurrence = locks::write(&ingestor.co_occurrence, "co_occurrence");
+            for (weight, identifiers) in &lists {
+                co_occurrence.process_weighted_list(identifiers, *weight);
+            }
+            drop(co_occurrence);
+            let mut recent_lists = locks::lock(&ingestor.recent_lists, "recent_lists");
+            for (_, identifiers) in &lists {
+                recent_lists.push(identifiers);
+            }
+            self.summary.lists += lists.len();
+        }
+        if !plays.is_empty() {
+            let counters = locks::read(&ingestor.counters, "rotating_counters");
+            for (id, count) in &plays {
+                counters.increment(id, *count);
+            }
+            self.summary.plays += plays.len();
+        }
+    }
+}
+
+/// Writes snapshots of what an import changed, so the imported lists and plays aren't
+/// replayed from the logs on the next start.
+pub fn persist(ingestor: &Ingestor, summary: &ImportSummary) {
+    if summary.lists > 0 {
+        locks::write(&ingestor.co_occurrence, "co_occurrence").compact();
+    }
+    if summary.plays > 0 {
+        locks::write(&ingestor.counters, "rotating_counters").persist();
+    }
+}
+
+/// Imports the file at `path`, in the format its extension suggests, and persists the
+/// result.
+pub fn import_file(ingestor: &Ingestor, path: &Path) -> Result<ImportSummary, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut import = Import::new(ingestor, ImportFormat::of_path(path));
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut chunk).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if read == 0 {
+            let summary = import.finish();
+            persist(ingestor, &summary);
+            return Ok(summary);
+        }
+        import.push(&chunk[..read]).map_err(|e| format!("{} in {}", e, path.display()))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex, RwLock};
+    use crate::algorithms::rotating_counters::count_of;
+    use crate::algorithms::holdout::Holdout;
+    use crate::algorithms::tenants::Tenants;
+    use crate::algorithms::tombstones::Tombstones;
+    use crate::algorithms::{CoOccurrenceCounter, Counters, RecentLists};
+    use crate::config::{Settings, SharedSettings};
+
+    #[test]
+    fn test_lists_and_plays_are_imported_in_both_formats() {
+        let ingestor = Ingestor::new(
+            Arc::new(RwLock::new(CoOccurrenceCounter::new())),
+            Arc::new(Mutex::new(RecentLists::new(10))),
+            Arc::new(RwLock::new(Counters::with_depths(3, 3, 1, 1))),
+            Arc::new(RwLock::new(Tombstones::default())),
+            Arc::new(Holdout::new(&Settings::from_env().evaluation)),
+            Arc::new(Tenants::new(&Settings::from_env()).0),
+            Arc::new(SharedSettings::new(Settings::from_env())),
+        );
+        let mut import = Import::new(&ingestor, ImportFormat::Ndjson);
+        // Lines may be split across chunks
+        import.push(b"{\"identifiers\": [\"a\", \"b\"]}\n{\"id\": \"a\", \"co").unwrap();
+        import.push(b"unt\": 2}\n\n{\"identifiers\": [\"a\", \"\"]}\n{\"id\": \"b\"}").unwrap();
+        let summary = import.finish();
+        assert_eq!((summary.lists, summary.plays, summary.rejected), (1, 2, 1));
+        assert_eq!(summary.errors[0].line, 4);
+
+        let mut import = Import::new(&ingestor, ImportFormat::Csv);
+        import.push(b"list,a,c\nplay,a,3\nplay,b\nplay,c,x\nview,a\n").unwrap();
+        let summary = import.finish();
+        assert_eq!((summary.lists, summary.plays, summary.rejected), (1, 2, 2));
+
+        assert_eq!(locks::read(&ingestor.co_occurrence, "co_occurrence").cached_metrics_for_identifier("a").len(), 2);
+        let counters = locks::read(&ingestor.counters, "rotating_counters");
+        assert_eq!(count_of(&counters.window("today").unwrap(), "a"), 5);
+        assert_eq!(count_of(&counters.window("today").unwrap(), "b"), 2);
+    }
+}
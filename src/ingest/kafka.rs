@@ -0,0 +1,71 @@
+// src/ingest/kafka.rs
+use std::time::Duration;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::Message;
+use tracing::{error, info, warn};
+
+use crate::config::KafkaSettings;
+use crate::ingest::Ingestor;
+
+/// Pause after a failed receive before trying again, e.g. while the brokers are down.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Creates a consumer in the configured group. Offsets are only stored once a message
+/// has been applied, so messages in flight during a crash are delivered again
+/// (at least once) instead of being lost.
+fn consumer(settings: &KafkaSettings) -> Result<StreamConsumer, rdkafka::error::KafkaError> {
+    ClientConfig::new()
+        .set("bootstrap.servers", settings.brokers.as_deref().unwrap_or_default())
+        .set("group.id", &settings.group)
+        .set("enable.auto.commit", "true")
+        .set("enable.auto.offset.store", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()
+}
+
+// Function to ingest the session lists and play events from Kafka.
+pub async fn run_consumer(ingestor: Ingestor, settings: KafkaSettings) {
+    let topics: Vec<&str> = [&settings.lists_topic, &settings.plays_topic].into_iter().flatten().map(String::as_str).collect();
+    if topics.is_empty() {
+        warn!("MEDIATHEK_KAFKA_BROKERS is set, but neither a lists nor a plays topic is configured.");
+        return;
+    }
+    let consumer = match consumer(&settings) {
+        Ok(consumer) => consumer,
+        Err(e) => {
+            error!("Failed to create Kafka consumer: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = consumer.subscribe(&topics) {
+        error!("Failed to subscribe to Kafka topics {:?}: {}", topics, e);
+        return;
+    }
+    info!(group = settings.group, "Kafka consumer subscribed to {:?}.", topics);
+
+    loop {
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Failed to receive Kafka message: {}", e);
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let payload = message.payload().unwrap_or_default();
+        let result = if settings.lists_topic.as_deref() == Some(message.topic()) {
+            ingestor.add_list(payload)
+        } else {
+            ingestor.add_play(payload)
+        };
+        // Malformed messages won't get any better, so they are skipped
+        if let Err(reason) = result {
+            let (topic, partition, offset) = (message.topic(), message.partition(), message.offset());
+            warn!(topic, partition, offset, "Skipping malformed Kafka message: {}", reason);
+        }
+        if let Err(e) = consumer.store_offset_from_message(&message) {
+            warn!("Failed to store Kafka offset: {}", e);
+        }
+    }
+}
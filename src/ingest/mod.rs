@@ -0,0 +1,160 @@
+// src/ingest/mod.rs
+use std::sync::{Arc, Mutex, RwLock};
+use serde::Deserialize;
+use tokio::task::JoinHandle;
+
+use crate::algorithms::holdout::Holdout;
+use crate::algorithms::tenants::Tenants;
+use crate::algorithms::tombstones::Tombstones;
+use crate::algorithms::{CoOccurrenceCounter, Counters, RecentLists};
+use crate::config::{KafkaSettings, NatsSettings, SharedSettings, StorageSettings};
+use crate::ingest::pipeline::{Admission, PlayVerdict};
+use crate::locks;
+
+pub mod import;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod pipeline;
+pub mod rules;
+
+// Ingestion from message buses and bulk imports, as an alternative to the HTTP endpoints.
+// Every bus maps its messages to lists or plays and hands them to `Ingestor`, which
+// takes them through the same steps as the HTTP API (see `pipeline`).
+
+/// A session list, like the body of POST /lists.
+#[derive(Debug, Deserialize)]
+struct ListMessage {
+    identifiers: Vec<String>,
+    source: Option<String>,
+}
+
+/// A play event, like the body of POST /counters.
+#[derive(Debug, Deserialize)]
+struct PlayMessage {
+    id: String,
+    #[serde(alias = "weight")]
+    count: Option<u64>,
+}
+
+/// Applies ingested messages to the shared state.
+#[derive(Clone)]
+pub struct Ingestor {
+    co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    recent_lists: Arc<Mutex<RecentLists>>,
+    counters: Arc<RwLock<Counters>>,
+    tombstones: Arc<RwLock<Tombstones>>,
+    holdout: Arc<Holdout>,
+    /// Where the ingest rules route identifiers to
+    tenants: Arc<Tenants>,
+    settings: Arc<SharedSettings>,
+}
+
+impl Ingestor {
+    pub fn new(
+        co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+        recent_lists: Arc<Mutex<RecentLists>>,
+        counters: Arc<RwLock<Counters>>,
+        tombstones: Arc<RwLock<Tombstones>>,
+        holdout: Arc<Holdout>,
+        tenants: Arc<Tenants>,
+        settings: Arc<SharedSettings>,
+    ) -> Self {
+        Ingestor { co_occurrence, recent_lists, counters, tombstones, holdout, tenants, settings }
+    }
+
+    /// Ingests a JSON list message (`{"identifiers": [...], "source": ...}`, the source
+    /// being optional). Returns why it was rejected if it is malformed or violates the
+    /// identifier limits.
+    pub fn add_list(&self, payload: &[u8]) -> Result<(), String> {
+        let mut message: ListMessage = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+        let settings = self.settings.current();
+        let admission = Admission::new(&settings, &self.tombstones, Some(&self.co_occurrence));
+        let routed = admission.list(&mut message.identifiers).map_err(|e| e.to_string())?;
+        drop(admission);
+        let weight = settings.source_weights.of(message.source.as_deref());
+        routed.count(&self.tenants, weight);
+        if self.holdout.hold_out(&message.identifiers) {
+            return Ok(());
+        }
+        let mut counter_lock = locks::write(&self.co_occurrence, "co_occurrence");
+        counter_lock.process_weighted_list(&message.identifiers, weight);
+        drop(counter_lock);
+        locks::lock(&self.recent_lists, "recent_lists").push(&message.identifiers);
+        Ok(())
+    }
+
+    /// Ingests a JSON play message (`{"id": ..., "count": ...}`, the count defaulting
+    /// to 1). Returns why it was rejected if it is malformed or the identifier invalid.
+    pub fn add_play(&self, payload: &[u8]) -> Result<(), String> {
+        let mut message: PlayMessage = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+        let settings = self.settings.current();
+        let verdict = Admission::new(&settings, &self.tombstones, None).play(&mut message.id).map_err(|e| e.to_string())?;
+        match verdict {
+            PlayVerdict::Count => locks::read(&self.counters, "rotating_counters").increment(&message.id, message.count.unwrap_or(1)),
+            PlayVerdict::Route(tenant) => rules::route_play(&self.tenants, tenant, &message.id, message.count.unwrap_or(1)),
+            PlayVerdict::Dropped | PlayVerdict::Deleted => {}
+        }
+        Ok(())
+    }
+}
+
+/// Starts consuming the configured Kafka topics in the background. Returns the task,
+/// unless the server was built without Kafka support.
+pub fn start_kafka_consumer(ingestor: Ingestor, settings: KafkaSettings) -> Option<JoinHandle<()>> {
+    #[cfg(feature = "kafka")]
+    return Some(tokio::task::spawn(kafka::run_consumer(ingestor, settings)));
+
+    #[cfg(not(feature = "kafka"))]
+    {
+        let _ = (ingestor, settings);
+        tracing::warn!("MEDIATHEK_KAFKA_BROKERS is set, but the server was built without the kafka feature.");
+        None
+    }
+}
+
+/// Starts subscribing to the configured NATS JetStream subjects in the background.
+/// Malformed messages are kept in the data directory. Returns the task, unless the server
+/// was built without NATS support.
+pub fn start_nats_subscriber(ingestor: Ingestor, settings: NatsSettings, storage: &StorageSettings) -> Option<JoinHandle<()>> {
+    #[cfg(feature = "nats")]
+    return Some(tokio::task::spawn(nats::run_subscriber(ingestor, settings, storage.data_path(nats::DEAD_LETTER_PATH))));
+
+    #[cfg(not(feature = "nats"))]
+    {
+        let _ = (ingestor, settings, storage);
+        tracing::warn!("MEDIATHEK_NATS_URL is set, but the server was built without the nats feature.");
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::rotating_counters::count_of;
+    use crate::config::Settings;
+
+    #[test]
+    fn test_messages_are_validated_and_applied() {
+        let ingestor = Ingestor::new(
+            Arc::new(RwLock::new(CoOccurrenceCounter::new())),
+            Arc::new(Mutex::new(RecentLists::new(10))),
+            Arc::new(RwLock::new(Counters::with_depths(3, 3, 1, 1))),
+            Arc::new(RwLock::new(Tombstones::default())),
+            Arc::new(Holdout::new(&Settings::from_env().evaluation)),
+            Arc::new(Tenants::new(&Settings::from_env()).0),
+            Arc::new(SharedSettings::new(Settings::from_env())),
+        );
+        assert!(ingestor.add_list(br#"{"identifiers": ["a", "b"]}"#).is_ok());
+        assert!(ingestor.add_list(br#"{"identifiers": ["a", ""]}"#).is_err());
+        assert!(ingestor.add_list(b"[").is_err());
+        assert_eq!(locks::read(&ingestor.co_occurrence, "co_occurrence").cached_metrics_for_identifier("a").len(), 1);
+
+        assert!(ingestor.add_play(br#"{"id": "a", "count": 3}"#).is_ok());
+        assert!(ingestor.add_play(br#"{"id": "a"}"#).is_ok());
+        assert!(ingestor.add_play(br#"{"count": 3}"#).is_err());
+        let counters = locks::read(&ingestor.counters, "rotating_counters");
+        assert_eq!(count_of(&counters.window("today").unwrap(), "a"), 4);
+    }
+}
@@ -0,0 +1,163 @@
+// src/ingest/nats.rs
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use async_nats::jetstream::{self, consumer, stream, AckKind};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::config::NatsSettings;
+use crate::ingest::Ingestor;
+
+/// Where malformed messages are kept for inspection, one JSON object per line.
+pub const DEAD_LETTER_PATH: &str = "nats_dead_letters.log";
+/// Pause after a failed receive before trying again, e.g. while the server is down.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// One line of the dead-letter log.
+#[derive(Debug, Serialize)]
+struct DeadLetter<'a> {
+    received_at: DateTime<Utc>,
+    subject: &'a str,
+    reason: &'a str,
+    /// The payload as text; invalid UTF-8 is replaced
+    payload: String,
+}
+
+/// Append-only log of messages that could not be ingested.
+#[derive(Debug)]
+pub struct DeadLetterLog {
+    file: File,
+}
+
+impl DeadLetterLog {
+    /// Opens (or creates) the log at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(DeadLetterLog { file })
+    }
+
+    /// Appends a rejected message and why it was rejected.
+    pub fn append(&mut self, subject: &str, reason: &str, payload: &[u8]) -> io::Result<()> {
+        let letter = DeadLetter { received_at: Utc::now(), subject, reason, payload: String::from_utf8_lossy(payload).into_owned() };
+        let mut line = serde_json::to_vec(&letter).map_err(io::Error::other)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+}
+
+/// Gets the durable pull consumer, creating it and its stream on first use. Messages are
+/// acknowledged one by one once applied, so messages in flight during a crash are
+/// delivered again (at least once) instead of being lost.
+async fn consumer(settings: &NatsSettings, url: &str) -> Result<consumer::PullConsumer, async_nats::Error> {
+    let client = async_nats::connect(url).await?;
+    let context = jetstream::new(client);
+    let stream = context
+        .get_or_create_stream(stream::Config {
+            name: settings.stream.clone(),
+            subjects: vec![settings.lists_subject.clone(), settings.plays_subject.clone()],
+            ..Default::default()
+        })
+        .await?;
+    let consumer = stream
+        .get_or_create_consumer(
+            &settings.consumer,
+            consumer::pull::Config {
+                durable_name: Some(settings.consumer.clone()),
+                ack_policy: consumer::AckPolicy::Explicit,
+                ..Default::default()
+            },
+        )
+        .await?;
+    Ok(consumer)
+}
+
+// Function to ingest the session lists and play events from NATS JetStream.
+pub async fn run_subscriber(ingestor: Ingestor, settings: NatsSettings, dead_letter_path: PathBuf) {
+    let Some(url) = settings.url.clone() else {
+        return;
+    };
+    let mut dead_letters = match DeadLetterLog::open(&dead_letter_path) {
+        Ok(dead_letters) => dead_letters,
+        Err(e) => {
+            error!("Failed to open NATS dead-letter log {}: {}", dead_letter_path.display(), e);
+            return;
+        }
+    };
+    let consumer = match consumer(&settings, &url).await {
+        Ok(consumer) => consumer,
+        Err(e) => {
+            error!("Failed to create NATS consumer: {}", e);
+            return;
+        }
+    };
+    let mut messages = match consumer.messages().await {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("Failed to subscribe to NATS stream {}: {}", settings.stream, e);
+            return;
+        }
+    };
+    info!(stream = settings.stream, consumer = settings.consumer, "NATS subscriber started.");
+
+    while let Some(message) = messages.next().await {
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Failed to receive NATS message: {}", e);
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let subject = message.subject.as_str();
+        let result = if subject == settings.lists_subject {
+            ingestor.add_list(&message.payload)
+        } else {
+            ingestor.add_play(&message.payload)
+        };
+        // Malformed messages won't get any better, so they are set aside and acknowledged.
+        // Only if that fails too, the message is handed back to be delivered again.
+        let ack = match result {
+            Ok(()) => AckKind::Ack,
+            Err(reason) => match dead_letters.append(subject, &reason, &message.payload) {
+                Ok(()) => {
+                    warn!(subject, "Moved malformed NATS message to the dead-letter log: {}", reason);
+                    AckKind::Ack
+                }
+                Err(e) => {
+                    error!("Failed to write NATS dead-letter log: {}", e);
+                    AckKind::Nak(Some(RETRY_DELAY))
+                }
+            },
+        };
+        if let Err(e) = message.ack_with(ack).await {
+            warn!("Failed to acknowledge NATS message: {}", e);
+        }
+    }
+    warn!("NATS subscription ended.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_letters_are_appended_as_json_lines() {
+        let path = std::env::temp_dir().join(format!("nats_dead_letters_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut log = DeadLetterLog::open(&path).unwrap();
+        log.append("mediathek.plays", "missing field `id`", br#"{"count": 3}"#).unwrap();
+        log.append("mediathek.lists", "expected value", b"\xff[").unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["subject"], "mediathek.plays");
+        assert_eq!(lines[0]["payload"], r#"{"count": 3}"#);
+        assert_eq!(lines[1]["payload"], "\u{fffd}[");
+        let _ = std::fs::remove_file(&path);
+    }
+}
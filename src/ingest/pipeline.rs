@@ -0,0 +1,208 @@
+// src/ingest/pipeline.rs
+use std::borrow::Cow;
+use std::sync::{RwLock, RwLockReadGuard};
+use chrono::{DateTime, Utc};
+
+use crate::algorithms::tenants::Tenants;
+use crate::algorithms::tombstones::Tombstones;
+use crate::algorithms::CoOccurrenceCounter;
+use crate::api::error::ApiError;
+use crate::api::validation::{list_violations, normalize_identifier, validate_identifier};
+use crate::config::{NormalizationSettings, RuleVerdict, Settings};
+use crate::ingest::rules::{self, RuleTarget};
+use crate::{determinism, locks};
+
+// The steps every ingestion path (the HTTP and gRPC endpoints, the message buses and the
+// imports) takes lists and plays through before counting them, always in this order:
+// normalization, validation, the ingest rules (rewritten identifiers are validated
+// again), the tombstones and, for lists, the limit of distinct identifiers. The parts the
+// rules route to other tenants are only counted there once the path accepted the list.
+
+/// The state lists and plays are checked against, locked once for any number of them.
+/// Has to be dropped before the co-occurrences are locked for writing.
+pub struct Admission<'a> {
+    settings: &'a Settings,
+    tombstones: RwLockReadGuard<'a, Tombstones>,
+    /// Only locked if lists with new identifiers are rejected
+    co_occurrence: Option<RwLockReadGuard<'a, CoOccurrenceCounter>>,
+    now: DateTime<Utc>,
+}
+
+/// What the ingestion steps made of a list, besides the identifiers left to count.
+pub struct ListReview<T> {
+    /// Identifiers left out by the ingest rules or as deleted
+    pub dropped: Vec<String>,
+    pub routed: Routed<T>,
+    /// Every reason the list is rejected for; empty if it is accepted
+    pub violations: Vec<String>,
+}
+
+/// What becomes of a play after the ingestion steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayVerdict<'a> {
+    Count,
+    /// Left out by an ingest rule
+    Dropped,
+    /// Left out as deleted
+    Deleted,
+    /// Counted for the tenant instead, see `rules::route_play`
+    Route(&'a str),
+}
+
+/// The parts of a list the ingest rules route to other tenants, by tenant in the order
+/// the tenants first appear.
+pub struct Routed<T>(pub Vec<(String, Vec<T>)>);
+
+impl<T: RuleTarget> Routed<T> {
+    /// Counts every part in its tenant's co-occurrences with `weight`, along with the
+    /// times of its identifiers if they all have one.
+    pub fn count(&self, tenants: &Tenants, weight: u64) {
+        for (tenant, part) in &self.0 {
+            let times: Option<Vec<i64>> = part.iter().map(RuleTarget::time).collect();
+            rules::route_list(tenants, tenant, part, times.as_deref(), weight);
+        }
+    }
+}
+
+fn normalize<T: RuleTarget>(identifier: &mut T, settings: &NormalizationSettings) {
+    let normalized = match normalize_identifier(identifier.as_ref(), settings) {
+        Cow::Owned(normalized) => Some(normalized),
+        Cow::Borrowed(_) => None,
+    };
+    if let Some(normalized) = normalized {
+        identifier.rewrite(normalized);
+    }
+}
+
+impl<'a> Admission<'a> {
+    /// Locks the tombstones, and the co-occurrences if they are given and lists with new
+    /// identifiers are rejected (paths ingesting only plays have none).
+    pub fn new(settings: &'a Settings, tombstones: &'a RwLock<Tombstones>, co_occurrence: Option<&'a RwLock<CoOccurrenceCounter>>) -> Self {
+        let tombstones = locks::read(tombstones, "tombstones");
+        let co_occurrence = co_occurrence
+            .filter(|_| settings.cardinality.rejects_new_identifiers())
+            .map(|co_occurrence| locks::read(co_occurrence, "co_occurrence"));
+        Admission { settings, tombstones, co_occurrence, now: determinism::now() }
+    }
+
+    /// Takes a list through the steps, leaving the identifiers to count in `identifiers`.
+    /// Fails with the first reason the list is rejected for. The routed parts are to be
+    /// counted once the list is accepted.
+    pub fn list<T: RuleTarget>(&self, identifiers: &mut Vec<T>) -> Result<Routed<T>, ApiError> {
+        let review = self.steps(identifiers);
+        if let Some(violation) = review.violations.into_iter().next() {
+            return Err(ApiError::Unprocessable(violation));
+        }
+        if let Some(co_occurrence) = &self.co_occurrence {
+            co_occurrence.admits_list(identifiers).map_err(ApiError::Unprocessable)?;
+        }
+        Ok(review.routed)
+    }
+
+    /// Like `list`, but reports every reason the list would be rejected for, and what
+    /// was left out, without counting the rejection.
+    pub fn review_list<T: RuleTarget>(&self, identifiers: &mut Vec<T>) -> ListReview<T> {
+        let mut review = self.steps(identifiers);
+        if let Some(co_occurrence) = &self.co_occurrence {
+            review.violations.extend(co_occurrence.cardinality_violation(identifiers));
+        }
+        review
+    }
+
+    fn steps<T: RuleTarget>(&self, identifiers: &mut Vec<T>) -> ListReview<T> {
+        let settings = self.settings;
+        if settings.validation.normalization.is_enabled() {
+            identifiers.iter_mut().for_each(|identifier| normalize(identifier, &settings.validation.normalization));
+        }
+        let mut violations = list_violations(identifiers, &settings.validation);
+        let (mut kept, mut dropped, mut routed) = (Vec::with_capacity(identifiers.len()), Vec::new(), Vec::<(String, Vec<T>)>::new());
+        for mut identifier in identifiers.drain(..) {
+            match settings.ingest_rules.verdict(identifier.as_ref()) {
+                RuleVerdict::Keep => {}
+                RuleVerdict::Drop => {
+                    dropped.push(identifier.as_ref().to_string());
+                    continue;
+                }
+                RuleVerdict::Rewrite(rewritten) => {
+                    violations.extend(validate_identifier(&rewritten, &settings.validation).err().map(|e| e.to_string()));
+                    identifier.rewrite(rewritten);
+                }
+                RuleVerdict::Route(tenant) => {
+                    match routed.iter_mut().find(|(name, _)| name == tenant) {
+                        Some((_, part)) => part.push(identifier),
+                        None => routed.push((tenant.to_string(), vec![identifier])),
+                    }
+                    continue;
+                }
+            }
+            match self.tombstones.admits(identifier.as_ref(), self.now) {
+                Ok(false) => dropped.push(identifier.as_ref().to_string()),
+                admitted => {
+                    violations.extend(admitted.err());
+                    kept.push(identifier);
+                }
+            }
+        }
+        *identifiers = kept;
+        ListReview { dropped, routed: Routed(routed), violations }
+    }
+
+    /// Takes a play of `id` through the steps, leaving it normalized and rewritten.
+    /// Fails if the identifier is invalid or, with `TombstoneMode::Reject`, deleted.
+    pub fn play(&self, id: &mut String) -> Result<PlayVerdict<'a>, ApiError> {
+        let settings = self.settings;
+        normalize(id, &settings.validation.normalization);
+        validate_identifier(id, &settings.validation)?;
+        match settings.ingest_rules.verdict(id) {
+            RuleVerdict::Keep => {}
+            RuleVerdict::Drop => return Ok(PlayVerdict::Dropped),
+            RuleVerdict::Rewrite(rewritten) => {
+                validate_identifier(&rewritten, &settings.validation)?;
+                *id = rewritten;
+            }
+            RuleVerdict::Route(tenant) => return Ok(PlayVerdict::Route(tenant)),
+        }
+        match self.tombstones.admits(id, self.now).map_err(ApiError::Unprocessable)? {
+            true => Ok(PlayVerdict::Count),
+            false => Ok(PlayVerdict::Deleted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IngestRules;
+
+    #[test]
+    fn test_rules_apply_after_validation_and_before_the_tombstones() {
+        let mut settings = Settings::from_env();
+        settings.ingest_rules = "prefix:test:=>drop; regex:^br:(.+)$=>rewrite:ard:$1;prefix:old-=>rewrite:zdf:;prefix:kika:=>tenant:kika;prefix:bad-=>rewrite:a b"
+            .parse()
+            .unwrap();
+        let tombstones = RwLock::new(Tombstones::default());
+        let admission = Admission::new(&settings, &tombstones, None);
+
+        let mut identifiers: Vec<String> = ["test:a", "br:1", "old-2", "kika:x", "ard:3", "kika:y"].into_iter().map(String::from).collect();
+        let routed = admission.list(&mut identifiers).unwrap();
+        assert_eq!(identifiers, ["ard:1", "zdf:2", "ard:3"]);
+        assert_eq!(routed.0, [("kika".to_string(), vec!["kika:x".to_string(), "kika:y".to_string()])]);
+        assert_eq!(settings.ingest_rules.tenants().collect::<Vec<&str>>(), ["kika"]);
+
+        // Rewritten identifiers are validated again
+        let review = admission.review_list(&mut vec!["bad-1".to_string(), "test:b".to_string()]);
+        assert_eq!((review.dropped, review.violations.len()), (vec!["test:b".to_string()], 1));
+        assert!(admission.list(&mut vec!["bad-1".to_string()]).is_err());
+        assert!(admission.list(&mut vec!["".to_string()]).is_err());
+
+        assert_eq!(admission.play(&mut "test:a".to_string()).unwrap(), PlayVerdict::Dropped);
+        assert_eq!(admission.play(&mut "kika:x".to_string()).unwrap(), PlayVerdict::Route("kika"));
+        let mut id = "br:2".to_string();
+        assert_eq!((admission.play(&mut id).unwrap(), id.as_str()), (PlayVerdict::Count, "ard:2"));
+        assert!(admission.play(&mut "bad-1".to_string()).is_err());
+
+        assert!("prefix:a=>keep".parse::<IngestRules>().is_err());
+        assert!("regex:(=>drop".parse::<IngestRules>().is_err());
+        assert!("a=>drop".parse::<IngestRules>().is_err());
+    }
+}
@@ -0,0 +1,58 @@
+// src/ingest/rules.rs
+use std::borrow::Cow;
+use tracing::warn;
+
+use crate::algorithms::tenants::Tenants;
+use crate::locks;
+
+// Where the ingest rules (`MEDIATHEK_INGEST_RULES`) route identifiers to: the parts of
+// lists and the plays `pipeline::Admission` routes to a tenant are counted there, without
+// the deduplication, the holdout and the tombstones of the request's own state.
+
+/// An identifier the normalization and the ingest rules can rewrite, alone or along with
+/// data of its own.
+pub trait RuleTarget: AsRef<str> {
+    fn rewrite(&mut self, identifier: String);
+
+    /// When the identifier was watched (seconds since the Unix epoch), if known.
+    fn time(&self) -> Option<i64> {
+        None
+    }
+}
+
+impl RuleTarget for String {
+    fn rewrite(&mut self, identifier: String) {
+        *self = identifier;
+    }
+}
+
+impl RuleTarget for Cow<'_, str> {
+    fn rewrite(&mut self, identifier: String) {
+        *self = Cow::Owned(identifier);
+    }
+}
+
+/// Counts the part of a list routed to `tenant` in the tenant's co-occurrences. Parts
+/// that can't be counted there, e.g. as the tenant isn't configured, are left out with a
+/// warning. Blocks while the tenant is loaded on first use.
+pub fn route_list<S: AsRef<str>>(tenants: &Tenants, tenant: &str, identifiers: &[S], times: Option<&[i64]>, weight: u64) {
+    let result = tenants.get(tenant).and_then(|found| {
+        let found = found.ok_or_else(|| "unknown tenant".to_string())?;
+        let mut counter = locks::write(&found.co_occurrence, "co_occurrence");
+        counter.admits_list(identifiers)?;
+        counter.process_timed_list(identifiers, times, weight);
+        Ok(())
+    });
+    if let Err(e) = result {
+        warn!("Left out {} identifiers routed to tenant '{}': {}", identifiers.len(), tenant, e);
+    }
+}
+
+/// Counts a play routed to `tenant` in the tenant's counters, like `route_list`.
+pub fn route_play(tenants: &Tenants, tenant: &str, id: &str, count: u64) {
+    match tenants.get(tenant) {
+        Ok(Some(found)) => locks::read(&found.counters, "rotating_counters").increment(id, count),
+        Ok(None) => warn!("Left out a play of '{}' routed to tenant '{}': unknown tenant", id, tenant),
+        Err(e) => warn!("Left out a play of '{}' routed to tenant '{}': {}", id, tenant, e),
+    }
+}
@@ -0,0 +1,143 @@
+// src/inspect.rs
+use std::fs;
+use std::path::Path;
+
+use crate::algorithms::co_occurrence::Snapshot;
+use crate::algorithms::rotating_counters::top_entries;
+use crate::algorithms::{snapshot, Counters};
+use crate::config::{Command, SnapshotFormat, SnapshotSettings};
+
+// The subcommands working on snapshot files offline, so the persisted state can be
+// examined without a running server. They print to stdout and only read the files given.
+// Co-occurrence snapshots are read without their deltas.
+
+/// The contents of a snapshot file.
+enum StateFile {
+    CoOccurrences(Snapshot),
+    Counters(Box<Counters>),
+}
+
+impl StateFile {
+    /// Reads a co-occurrence or counter snapshot, whichever `data` decodes as.
+    fn decode(data: &[u8]) -> Result<Self, String> {
+        if let Ok(snapshot) = snapshot::decode(data) {
+            return Ok(StateFile::CoOccurrences(snapshot));
+        }
+        snapshot::decode(data).map(|counters| StateFile::Counters(Box::new(counters)))
+    }
+}
+
+fn read(path: &Path) -> Result<(Vec<u8>, StateFile), String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let state = StateFile::decode(&data).map_err(|e| format!("{} is no snapshot: {}", path.display(), e))?;
+    Ok((data, state))
+}
+
+/// Runs one of the subcommands on snapshots; `Serve` is left to `server::run` and
+/// `Simulate` to `simulate::run`.
+pub fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Serve | Command::Simulate { .. } | Command::Bench { .. } | Command::Replay { .. } => Err("Not a snapshot command".to_string()),
+        Command::Inspect { path, limit } => inspect(&path, limit),
+        Command::Top { path, window, limit } => top(&path, &window, limit),
+        Command::Convert { input, output, format, compression_level } => {
+            let settings = SnapshotSettings { format, compression_level, ..SnapshotSettings::default() };
+            let bytes = convert(&input, &output, settings)?;
+            println!("Wrote {} ({} bytes).", output.display(), bytes);
+            Ok(())
+        }
+    }
+}
+
+fn inspect(path: &Path, limit: usize) -> Result<(), String> {
+    let (data, state) = read(path)?;
+    let (format, compressed) = snapshot::format_of(&data)?;
+    let encoding = match format {
+        SnapshotFormat::Json => "JSON",
+        SnapshotFormat::MessagePack => "MessagePack",
+    };
+    let compression = if compressed { ", zstd-compressed" } else { "" };
+    match state {
+        StateFile::CoOccurrences(snapshot) => {
+            println!("{}: co-occurrence snapshot, {} bytes ({}{})", path.display(), data.len(), encoding, compression);
+            println!("Identifiers: {}", snapshot.identifier_count());
+            println!("Pairs: {}", snapshot.pair_count());
+            println!("Top pairs:");
+            for (a, b, count) in snapshot.top_pairs(limit) {
+                println!("  {:>10}  {}  {}", count, a, b);
+            }
+        }
+        StateFile::Counters(counters) => {
+            println!("{}: counter snapshot, {} bytes ({}{})", path.display(), data.len(), encoding, compression);
+            println!("Identifiers: {}", counters.first_seen.len());
+            if let Some(rotated_at) = counters.last_rotation_at {
+                println!("Last rotation: {}", rotated_at.to_rfc3339());
+            }
+            println!("Buckets (identifiers, total count):");
+            for (name, bucket) in counters.named_buckets() {
+                let total: u64 = bucket.iter().map(|entry| *entry.value()).sum();
+                println!("  {:<16}{:>10}{:>12}", name, bucket.len(), total);
+            }
+            if let Some(today) = counters.bucket("today") {
+                println!("Top today:");
+                for entry in top_entries(today, 0, limit) {
+                    println!("  {:>10}  {}", entry.count, entry.id);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn top(path: &Path, window: &str, limit: usize) -> Result<(), String> {
+    let StateFile::Counters(counters) = read(path)?.1 else {
+        return Err(format!("{} is no counter snapshot", path.display()));
+    };
+    let bucket = counters.window(window).ok_or_else(|| format!("Unknown window '{}'", window))?;
+    for entry in top_entries(&bucket, 0, limit) {
+        println!("{:>10}  {}", entry.count, entry.id);
+    }
+    Ok(())
+}
+
+/// Writes the snapshot at `input` to `output` as `settings` say. Returns the size of the
+/// output.
+fn convert(input: &Path, output: &Path, settings: SnapshotSettings) -> Result<u64, String> {
+    let (data, _) = match read(input)?.1 {
+        StateFile::CoOccurrences(snapshot) => snapshot::encode_compressed(&snapshot, settings),
+        StateFile::Counters(counters) => snapshot::encode_compressed(&counters, settings),
+    }
+    .map_err(|e| e.to_string())?;
+    snapshot::write(output, &data).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    Ok(data.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::CoOccurrenceCounter;
+
+    #[test]
+    fn test_snapshots_convert_between_encodings() {
+        let mut co_occurrence = CoOccurrenceCounter::new();
+        co_occurrence.process_list(&["a".to_string(), "b".to_string()]);
+        let snapshot = co_occurrence.to_snapshot().unwrap();
+        let directory = std::env::temp_dir().join(format!("mediathek_inspect_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let (json, binary) = (directory.join("co_occurrences.json"), directory.join("co_occurrences.bin"));
+        fs::write(&json, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+
+        let settings = SnapshotSettings { format: SnapshotFormat::MessagePack, compression_level: 3, ..SnapshotSettings::default() };
+        convert(&json, &binary, settings).unwrap();
+        let data = fs::read(&binary).unwrap();
+        assert_eq!(snapshot::format_of(&data), Ok((SnapshotFormat::MessagePack, true)));
+        let StateFile::CoOccurrences(converted) = StateFile::decode(&data).unwrap() else { panic!("Not a co-occurrence snapshot") };
+        assert_eq!(converted.top_pairs(10), [("a".to_string(), "b".to_string(), 1)]);
+
+        let counters = Counters::with_depths(3, 3, 1, 1);
+        counters.increment("a", 2);
+        fs::write(&json, serde_json::to_vec(&counters).unwrap()).unwrap();
+        assert!(matches!(read(&json).unwrap().1, StateFile::Counters(_)));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
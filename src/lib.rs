@@ -0,0 +1,45 @@
+// src/lib.rs
+//! The recommendation server as a library, so the algorithms can be reused outside of it,
+//! e.g. in batch pipelines.
+//!
+//! `RecommendationEngine` is the stable entry point: it records lists and plays and
+//! answers co-occurrence and popularity queries, with or without persistence.
+//! `CoOccurrenceCounter` and `Counters` are the structures behind it, for callers that
+//! need more control. `server::run` starts the HTTP server the binary is made of (or a
+//! router in front of several of them, if shards are configured), `inspect`, `simulate`
+//! and `bench` implement its offline subcommands, `replay` sends recorded requests to a
+//! running server, and `client` (with `--features client`) talks to one. `testing` (with
+//! `--features test-support`) serves the API on temporary state for end-to-end tests.
+
+// Declare the modules
+pub mod algorithms;
+mod api;
+pub mod bench;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod config;
+mod determinism;
+pub mod engine;
+// Partly only used by the message bus consumers, which are optional features
+#[cfg_attr(not(any(feature = "kafka", feature = "nats")), allow(dead_code))]
+mod ingest;
+pub mod inspect;
+mod locks;
+mod logging;
+mod memory;
+mod router;
+mod scheduler;
+pub mod replay;
+pub mod server;
+pub mod simulate;
+mod shutdown;
+pub mod stats;
+mod systemd;
+#[cfg(feature = "test-support")]
+pub mod testing;
+mod tls;
+#[cfg(unix)]
+mod unix_socket;
+
+pub use crate::algorithms::{CoOccurrenceCounter, Counters};
+pub use crate::engine::RecommendationEngine;
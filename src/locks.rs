@@ -0,0 +1,110 @@
+// src/locks.rs
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, trace_span};
+
+use crate::stats;
+
+// Lock acquisition wrapped in `lock_wait` spans, so contention shows up in traces.
+// The wait times are also rolled up for GET /admin/stats.
+// A poisoned lock means a thread panicked while holding it. The counts behind our locks
+// stay usable after a partial update, so we log it and carry on rather than letting every
+// later request panic as well.
+
+/// Locks `mutex`, recording the wait under `name`.
+pub fn lock<'a, T>(mutex: &'a Mutex<T>, name: &'static str) -> MutexGuard<'a, T> {
+    let _span = trace_span!("lock_wait", lock = name).entered();
+    let started = Instant::now();
+    let guard = mutex.lock().unwrap_or_else(|e| recover(name, e));
+    stats::record_lock_wait(name, started.elapsed());
+    guard
+}
+
+/// Acquires shared access to `lock`, recording the wait under `name`.
+pub fn read<'a, T>(lock: &'a RwLock<T>, name: &'static str) -> RwLockReadGuard<'a, T> {
+    let _span = trace_span!("lock_wait", lock = name, mode = "read").entered();
+    let started = Instant::now();
+    let guard = lock.read().unwrap_or_else(|e| recover(name, e));
+    stats::record_lock_wait(name, started.elapsed());
+    guard
+}
+
+/// Acquires exclusive access to `lock`, recording the wait under `name`.
+pub fn write<'a, T>(lock: &'a RwLock<T>, name: &'static str) -> RwLockWriteGuard<'a, T> {
+    let _span = trace_span!("lock_wait", lock = name, mode = "write").entered();
+    let started = Instant::now();
+    let guard = lock.write().unwrap_or_else(|e| recover(name, e));
+    stats::record_lock_wait(name, started.elapsed());
+    guard
+}
+
+/// Interval between two attempts of the timed acquisitions below.
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Retries `try_acquire` until it succeeds or `timeout` passed, recording the wait under
+/// `name`. Without a timeout, this is `acquire`.
+fn acquire_within<G>(
+    name: &'static str,
+    timeout: Option<Duration>,
+    acquire: impl FnOnce() -> G,
+    try_acquire: impl Fn() -> Result<G, TryLockError<G>>,
+) -> Option<G> {
+    let Some(timeout) = timeout else {
+        return Some(acquire());
+    };
+    let _span = trace_span!("lock_wait", lock = name, timeout_ms = timeout.as_millis() as u64).entered();
+    let started = Instant::now();
+    loop {
+        match try_acquire() {
+            Ok(guard) => {
+                stats::record_lock_wait(name, started.elapsed());
+                return Some(guard);
+            }
+            Err(TryLockError::Poisoned(e)) => {
+                stats::record_lock_wait(name, started.elapsed());
+                return Some(recover(name, e));
+            }
+            Err(TryLockError::WouldBlock) if started.elapsed() >= timeout => {
+                stats::record_lock_wait(name, started.elapsed());
+                return None;
+            }
+            Err(TryLockError::WouldBlock) => thread::sleep(RETRY_INTERVAL),
+        }
+    }
+}
+
+/// Acquires shared access to `lock` like [`read`], but gives up after `timeout` (if any).
+pub fn read_within<'a, T>(lock: &'a RwLock<T>, name: &'static str, timeout: Option<Duration>) -> Option<RwLockReadGuard<'a, T>> {
+    acquire_within(name, timeout, || read(lock, name), || lock.try_read())
+}
+
+fn recover<G>(name: &'static str, poisoned: PoisonError<G>) -> G {
+    error!("Lock '{}' was poisoned by a panic, continuing with its current state.", name);
+    poisoned.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_poisoned_locks_keep_serving() {
+        let mutex = Arc::new(Mutex::new(1));
+        let rw_lock = Arc::new(RwLock::new(1));
+        let (poisoned_mutex, poisoned_rw_lock) = (Arc::clone(&mutex), Arc::clone(&rw_lock));
+        let _ = thread::spawn(move || {
+            let _mutex = poisoned_mutex.lock().unwrap();
+            let _rw_lock = poisoned_rw_lock.write().unwrap();
+            panic!("broken");
+        })
+        .join();
+        assert!(mutex.is_poisoned() && rw_lock.is_poisoned());
+
+        *lock(&mutex, "test") += 1;
+        *write(&rw_lock, "test") += 1;
+        assert_eq!((*lock(&mutex, "test"), *read(&rw_lock, "test")), (2, 2));
+        assert_eq!(read_within(&rw_lock, "test", Some(Duration::from_millis(5))).as_deref(), Some(&2));
+    }
+}
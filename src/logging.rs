@@ -0,0 +1,198 @@
+// src/logging.rs
+use std::time::Instant;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage};
+use tracing::field::Empty;
+use tracing::{info, info_span, Instrument};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::api::auth::ApiClient;
+use crate::config::LogSettings;
+use crate::stats;
+
+/// Header carrying the ID of a request, from the client or generated, in both directions.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Longest request ID taken from a client; longer ones are replaced by a generated one.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Name under which traces are reported to the tracing backend.
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "mediathek-recommendation-server";
+
+/// Keeps the trace exporter and the Sentry client (if any) alive until the server shuts down.
+pub struct LogGuard {
+    #[cfg(feature = "otel")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+    #[cfg(feature = "sentry")]
+    sentry: Option<sentry::ClientInitGuard>,
+}
+
+impl LogGuard {
+    /// Flushes spans and error reports that haven't been sent yet.
+    pub fn shutdown(self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.tracer_provider {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush traces: {}", e);
+            }
+        }
+        #[cfg(feature = "sentry")]
+        if let Some(sentry) = self.sentry {
+            if !sentry.close(Some(std::time::Duration::from_secs(2))) {
+                eprintln!("Failed to send the remaining error reports to Sentry.");
+            }
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber. Invalid level directives fall back to "info".
+/// If an OTLP endpoint is configured (and the `otel` feature is enabled), spans are
+/// exported there as well. If a Sentry DSN is configured (and the `sentry` feature is
+/// enabled), panics and error events, e.g. failed persistence or rotation, are reported
+/// there, with the warnings and infos before them as breadcrumbs.
+pub fn init(settings: &LogSettings) -> LogGuard {
+    let filter = EnvFilter::try_new(&settings.level).unwrap_or_else(|e| {
+        eprintln!("Ignoring invalid log level '{}': {}", settings.level, e);
+        EnvFilter::new("info")
+    });
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(settings.json.then(|| fmt::layer().json()))
+        .with((!settings.json).then(fmt::layer));
+
+    #[cfg(feature = "sentry")]
+    let (sentry, registry) = {
+        let sentry = settings.sentry_dsn.as_deref().map(|dsn| sentry_client(dsn, settings));
+        let layer = sentry.is_some().then(sentry_tracing::layer);
+        (sentry, registry.with(layer))
+    };
+    #[cfg(not(feature = "sentry"))]
+    if settings.sentry_dsn.is_some() {
+        eprintln!("MEDIATHEK_SENTRY_DSN is set, but the server was built without the sentry feature.");
+    }
+
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::TracerProvider;
+
+        let tracer_provider = settings.otlp_endpoint.as_deref().and_then(otlp_tracer_provider);
+        let layer = tracer_provider
+            .as_ref()
+            .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("mediathek_rs")));
+        registry.with(layer).init();
+        LogGuard {
+            tracer_provider,
+            #[cfg(feature = "sentry")]
+            sentry,
+        }
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        registry.init();
+        if settings.otlp_endpoint.is_some() {
+            tracing::warn!("MEDIATHEK_OTLP_ENDPOINT is set, but the server was built without the otel feature.");
+        }
+        LogGuard {
+            #[cfg(feature = "sentry")]
+            sentry,
+        }
+    }
+}
+
+/// Starts the Sentry client, which also installs its panic hook. Events are tagged with
+/// the configured release and environment.
+#[cfg(feature = "sentry")]
+fn sentry_client(dsn: &str, settings: &LogSettings) -> sentry::ClientInitGuard {
+    let guard = sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: Some(settings.sentry_release.clone().into()),
+            environment: Some(settings.sentry_environment.clone().into()),
+            attach_stacktrace: true,
+            ..Default::default()
+        },
+    ));
+    if !guard.is_enabled() {
+        eprintln!("Invalid Sentry DSN '{}', errors aren't reported.", dsn);
+    }
+    guard
+}
+
+/// Creates a tracer provider exporting batches of spans via OTLP/HTTP to `endpoint`.
+#[cfg(feature = "otel")]
+fn otlp_tracer_provider(endpoint: &str) -> Option<opentelemetry_sdk::trace::SdkTracerProvider> {
+    use opentelemetry_otlp::WithExportConfig;
+
+    let exporter = match opentelemetry_otlp::SpanExporter::builder().with_http().with_endpoint(endpoint).build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            eprintln!("Failed to set up the OTLP exporter for {}: {}", endpoint, e);
+            return None;
+        }
+    };
+    let resource = opentelemetry_sdk::Resource::builder().with_service_name(SERVICE_NAME).build();
+    Some(
+        opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build(),
+    )
+}
+
+/// Returns the request ID sent by the client if it is printable ASCII and not too long,
+/// otherwise a new random one.
+fn request_id(req: &ServiceRequest) -> String {
+    let sent = req.headers().get(REQUEST_ID_HEADER).and_then(|value| value.to_str().ok()).map(str::trim);
+    match sent {
+        Some(id) if !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH && id.bytes().all(|byte| byte.is_ascii_graphic()) => id.to_string(),
+        _ => format!("{:032x}", rand::random::<u128>()),
+    }
+}
+
+/// Middleware logging method, path, status and latency of every request. The request
+/// is handled within a `request` span, so everything it does shows up beneath it in traces.
+/// The span and the log line carry the request's ID (see `request_id`), which is also set
+/// on the request, for handlers forwarding it, and echoed in the response.
+pub async fn access_log(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let request_id = request_id(&req);
+    let header_value = HeaderValue::from_str(&request_id).expect("request IDs are printable ASCII");
+    req.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), header_value.clone());
+    let span = info_span!("request", method, path, request_id, status = Empty, client = Empty);
+
+    let mut response = next.call(req).instrument(span.clone()).await?;
+    response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), header_value);
+    span.record("status", response.status().as_u16());
+    let latency = started.elapsed();
+    // Rolled up by route pattern, so identifiers in paths don't create a series each
+    let route = match response.request().match_pattern() {
+        Some(pattern) => format!("{} {}", method, pattern),
+        None => "unmatched".to_string(),
+    };
+    stats::record_request(route, latency);
+    let client = response.request().extensions().get::<ApiClient>().map(|client| client.name.clone());
+
+    info!(
+        target: "access_log",
+        parent: &span,
+        method,
+        path,
+        request_id,
+        status = response.status().as_u16(),
+        client,
+        latency_ms = latency.as_secs_f64() * 1000.0,
+        "request handled"
+    );
+    Ok(response)
+}
@@ -1,49 +1,26 @@
 // src/main.rs
-use std::sync::{Arc, Mutex};
-use actix_web::{web, App, HttpServer};
-
-// Declare the modules
-mod algorithms;
-mod api;
-
-// Import our custom modules
-use crate::algorithms::{CoOccurrenceCounter, Counters, run_daily_counter_rotation, perform_final_persistence};
+use mediathek_rs::config::{Command, Settings};
+use mediathek_rs::bench::{self, BenchOptions};
+use mediathek_rs::{inspect, replay, server, simulate};
 
+fn load_settings() -> std::io::Result<Settings> {
+    Settings::load(std::env::args()).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Initialize both counter types
-    let co_occurrence_counter_arc = Arc::new(Mutex::new(CoOccurrenceCounter::new()));
-    let rotating_counters_arc = Arc::new(Mutex::new(Counters::new()));
-    let rotating_counters_for_http_server_setup = Arc::clone(&rotating_counters_arc);
-
-    // Start the background task for rotating counter rotation and persistence
-    // This task will run concurrently with the HTTP server.
-    let rotating_counters_for_task = Arc::clone(&rotating_counters_arc); // Clone for the spawned task
-    tokio::task::spawn(async move {
-        run_daily_counter_rotation(rotating_counters_for_task).await;
-    });
-
-    println!("Server running on http://127.0.0.1:3030");
-
-    let server_result = HttpServer::new(move || {
-        App::new()
-            // Register co_occurrence_counter as app data
-            .app_data(web::Data::new(co_occurrence_counter_arc.clone()))
-            // Register rotating_counters as app data (distinct type from co_occurrence_counter_arc)
-            .app_data(web::Data::new(rotating_counters_for_http_server_setup.clone()))
-            // Configure all routes from the api module
-            .configure(api::config_routes)
-    })
-    .bind(("127.0.0.1", 3030))?
-    .run()
-    .await;
-
-    // --- GRACEFUL SHUTDOWN PERSISTENCE ---
-    // The original `rotating_counters_arc` is still available here,
-    // and can be directly passed to the final persistence function.
-    perform_final_persistence(rotating_counters_arc).await;
-
-    server_result // Return the result of the server run
-
-}
\ No newline at end of file
+    let result = match Command::from_args(std::env::args()) {
+        Command::Serve => return server::run(load_settings()?).await,
+        Command::Simulate { logs, limit, speed } => simulate::run(&load_settings()?, &logs, limit, speed),
+        Command::Bench { sessions, identifiers, exponent, session_length, query_rate, seed } => {
+            bench::run(&load_settings()?, &BenchOptions { sessions, identifiers, exponent, session_length, query_rate, seed })
+        }
+        Command::Replay { files, url, api_key, speed } => replay::run(&files, &url, api_key.as_deref(), speed).await,
+        command => inspect::run(command),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,19 @@
+// src/memory.rs
+use std::mem::size_of;
+use dashmap::DashMap;
+
+// Rough estimates of the heap memory held by the in-memory models. They count the hash
+// table slots (hashbrown keeps one control byte per slot) plus the string contents, but
+// ignore allocator overhead, so real usage is somewhat higher.
+
+/// Estimated bytes of the table of a hash map with `capacity` slots, without the heap
+/// memory owned by the keys and values themselves.
+pub fn table_bytes<K, V>(capacity: usize) -> usize {
+    capacity * (size_of::<(K, V)>() + 1)
+}
+
+/// Estimated bytes of a `DashMap` keyed by strings, including the key contents.
+pub fn string_dash_map_bytes<V>(map: &DashMap<String, V>) -> usize {
+    let keys: usize = map.iter().map(|entry| entry.key().capacity()).sum();
+    table_bytes::<String, V>(map.capacity()) + keys
+}
@@ -0,0 +1,124 @@
+// src/replay.rs
+use std::path::PathBuf;
+use std::time::Duration;
+use actix_web::http::Method;
+use chrono::{DateTime, Utc};
+
+use crate::api::recorder::{read_recording, RecordedRequest};
+
+// Sends the ingest requests recorded by a server (see `MEDIATHEK_RECORDER_ENABLED`) to a
+// running instance, usually a local one started from the same snapshots, to reproduce
+// what production saw. Requests go out one at a time in the recorded order, so the
+// instance applies them in that order as well.
+
+/// Name of the header the API key is sent in.
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Outcome of a replay.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplayReport {
+    pub requests: usize,
+    /// Requests answered with a 2xx status
+    pub succeeded: usize,
+    /// Requests answered with another status than when recorded
+    pub differing: usize,
+    /// Requests that couldn't be sent, or whose body was damaged
+    pub failed: usize,
+}
+
+/// The URL of a recorded request at the instance at `base_url`.
+fn request_url(base_url: &str, request: &RecordedRequest) -> String {
+    format!("{}{}", base_url.trim_end_matches('/'), request.path)
+}
+
+/// Replays the recordings at `paths` against the instance at `base_url`. With a `speed`
+/// above 0, the replay is paced to that many times the recorded speed; otherwise it runs
+/// as fast as the instance answers.
+pub async fn replay(paths: &[PathBuf], base_url: &str, api_key: Option<&str>, speed: f64) -> Result<ReplayReport, String> {
+    let mut requests = Vec::new();
+    for path in paths {
+        requests.extend(read_recording(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?);
+    }
+
+    let client = awc::Client::default();
+    let mut report = ReplayReport { requests: requests.len(), ..ReplayReport::default() };
+    let mut previous_at: Option<DateTime<Utc>> = None;
+    for request in &requests {
+        if let Some(previous_at) = previous_at.filter(|_| speed > 0.0) {
+            let recorded = (request.at - previous_at).to_std().unwrap_or_default();
+            tokio::time::sleep(Duration::from_secs_f64(recorded.as_secs_f64() / speed)).await;
+        }
+        previous_at = Some(request.at);
+
+        let (Ok(method), Ok(body)) = (request.method.parse::<Method>(), request.body_bytes()) else {
+            report.failed += 1;
+            continue;
+        };
+        let mut sent = client.request(method, request_url(base_url, request));
+        for (name, value) in [("content-type", &request.content_type), ("content-encoding", &request.content_encoding)] {
+            if let Some(value) = value {
+                sent = sent.insert_header((name, value.as_str()));
+            }
+        }
+        if let Some(key) = api_key {
+            sent = sent.insert_header((API_KEY_HEADER, key));
+        }
+        match sent.send_body(body).await {
+            Ok(response) => {
+                let status = response.status();
+                report.succeeded += usize::from(status.is_success());
+                report.differing += usize::from(status.as_u16() != request.status);
+            }
+            Err(e) => {
+                eprintln!("{} {} failed: {}", request.method, request.path, e);
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Runs the `replay` subcommand and prints the report.
+pub async fn run(paths: &[PathBuf], base_url: &str, api_key: Option<&str>, speed: f64) -> Result<(), String> {
+    let report = replay(paths, base_url, api_key, speed).await?;
+    println!("Requests: {}", report.requests);
+    println!("  Succeeded: {}", report.succeeded);
+    println!("  Answered differently than recorded: {}", report.differing);
+    println!("  Failed: {}", report.failed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    #[actix_web::test]
+    async fn test_recorded_requests_are_sent_in_order() {
+        let path = std::env::temp_dir().join(format!("mediathek_replay_{}.log", std::process::id()));
+        let lines = [
+            r#"{"at":"2026-01-05T10:00:00Z","method":"POST","path":"/t/ard/v1/lists","content_type":"application/json","body":"{\"identifiers\":[\"a\",\"b\"]}","status":200}"#,
+            r#"{"at":"2026-01-05T10:00:01Z","method":"POST","path":"/v1/counters","content_type":"application/json","body":"{\"id\":\"a\"}","status":200}"#,
+            r#"{"at":"2026-01-05T10:00:02Z","method":"POST","path":"/v1/counters","content_type":"application/json","body":"zz","hex":true,"status":200}"#,
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let server = HttpServer::new(|| {
+            App::new()
+                .route("/t/ard/v1/lists", web::post().to(|body: String| async move { HttpResponse::Ok().body(body) }))
+                .route("/v1/counters", web::post().to(|| async { HttpResponse::UnprocessableEntity().finish() }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        let handle = server.run();
+        let running = actix_web::rt::spawn(handle);
+
+        let report = replay(std::slice::from_ref(&path), &format!("http://{}/", address), None, 0.0).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        running.abort();
+        // The counter was rejected, and the damaged body never sent
+        assert_eq!(report, ReplayReport { requests: 3, succeeded: 1, differing: 1, failed: 1 });
+    }
+}
@@ -0,0 +1,441 @@
+// src/router.rs
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::http::{KeepAlive, Method};
+use actix_web::web::Bytes;
+use actix_web::{get, middleware, post, web, App, HttpRequest, HttpResponse, HttpServer};
+use awc::error::{PayloadError, SendRequestError};
+use futures_util::Stream;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::api::error::{self, ApiError};
+use crate::api::v1::{AddListRequest, BatchMetricsRequest, BatchMetricsResponse, IncrementCounterRequest, DEFAULT_BATCH_METRICS_LIMIT};
+use crate::api::validation::{normalize_identifier, normalize_list, validate_identifier, validate_list, IdentifierPath};
+use crate::config::{Settings, ShardSettings, SharedSettings};
+use crate::{api, logging, shutdown, systemd};
+
+// Router mode: an instance with shards configured (see `ShardSettings`) keeps no state of
+// its own. Every identifier belongs to the shard its hash falls to on a ring of virtual
+// nodes, so adding or removing a shard only moves the identifiers of its ring segments.
+// A list is sent whole to every shard owning one of its identifiers, so each shard knows
+// all pairs of its own identifiers. Basket queries ask each shard for its part of the
+// basket and merge the ranked results.
+
+/// Largest shard response read, e.g. of a hot identifier's co-occurrences.
+const MAX_SHARD_RESPONSE_BYTES: usize = 64 * 1024 * 1024;
+
+const DEFAULT_RECOMMENDATIONS_LIMIT: usize = 10;
+
+/// Headers passed on to the shards, so they authenticate the client themselves and log
+/// the request under the same ID.
+const FORWARDED_HEADERS: [&str; 3] = ["x-api-key", "authorization", logging::REQUEST_ID_HEADER];
+
+/// Assigns identifiers to shards by consistent hashing.
+#[derive(Debug)]
+pub struct HashRing {
+    /// Points on the ring with the index of their shard, sorted by point
+    points: Vec<(u64, usize)>,
+    shards: Vec<String>,
+}
+
+/// Position of a key on the ring, the same on every instance and platform.
+fn ring_position(key: &[u8]) -> u64 {
+    let digest = Sha256::digest(key);
+    u64::from_be_bytes(digest[..8].try_into().unwrap_or_default())
+}
+
+impl HashRing {
+    /// Places `virtual_nodes` points per shard on the ring.
+    pub fn new(shards: &[String], virtual_nodes: usize) -> Self {
+        let mut points: Vec<(u64, usize)> = shards
+            .iter()
+            .enumerate()
+            .flat_map(|(index, shard)| {
+                (0..virtual_nodes).map(move |node| (ring_position(format!("{}#{}", shard, node).as_bytes()), index))
+            })
+            .collect();
+        points.sort_unstable();
+        HashRing { points, shards: shards.to_vec() }
+    }
+
+    /// Returns the index of the shard owning `identifier`: the one of the first point at or
+    /// after its position, wrapping around.
+    pub fn shard_of(&self, identifier: &str) -> usize {
+        let position = ring_position(identifier.as_bytes());
+        let index = self.points.partition_point(|&(point, _)| point < position);
+        self.points.get(index).or_else(|| self.points.first()).map_or(0, |&(_, shard)| shard)
+    }
+
+    /// Base URL of a shard.
+    pub fn shard_url(&self, shard: usize) -> &str {
+        &self.shards[shard]
+    }
+
+    /// Groups identifiers by the shard owning them, keeping their order.
+    pub fn group<'a>(&self, identifiers: &'a [String]) -> BTreeMap<usize, Vec<&'a String>> {
+        let mut groups: BTreeMap<usize, Vec<&String>> = BTreeMap::new();
+        for identifier in identifiers {
+            groups.entry(self.shard_of(identifier)).or_default().push(identifier);
+        }
+        groups
+    }
+}
+
+/// What the routing handlers share: the ring and a client, one per worker.
+struct Shards {
+    ring: Arc<HashRing>,
+    client: awc::Client,
+    timeout: Duration,
+}
+
+impl Shards {
+    fn url(&self, shard: usize, path: &str) -> String {
+        format!("{}/v1{}", self.ring.shard_url(shard).trim_end_matches('/'), path)
+    }
+
+    fn request(&self, method: Method, url: &str, headers: &HeaderMap) -> awc::ClientRequest {
+        let mut request = self.client.request(method, url).timeout(self.timeout);
+        for name in FORWARDED_HEADERS {
+            if let Some(value) = headers.get(name) {
+                request = request.insert_header((name, value.clone()));
+            }
+        }
+        request
+    }
+}
+
+/// Path and query of a request without the version prefix, to forward it to a shard.
+fn forwarded_path(req: &HttpRequest) -> &str {
+    let path = req.uri().path_and_query().map_or(req.path(), |path| path.as_str());
+    path.strip_prefix("/v1").unwrap_or(path)
+}
+
+/// Relays a shard's response as it is, status and ETag included.
+async fn relay<S>(shard: &str, response: Result<awc::ClientResponse<S>, SendRequestError>) -> Result<HttpResponse, ApiError>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    let mut response = response.map_err(|e| ApiError::BadGateway(format!("Shard {} failed: {}", shard, e)))?;
+    let body = response
+        .body()
+        .limit(MAX_SHARD_RESPONSE_BYTES)
+        .await
+        .map_err(|e| ApiError::BadGateway(format!("Shard {} failed: {}", shard, e)))?;
+    let mut relayed = HttpResponse::build(response.status());
+    for name in [header::CONTENT_TYPE, header::ETAG] {
+        if let Some(value) = response.headers().get(&name) {
+            relayed.insert_header((name, value.clone()));
+        }
+    }
+    Ok(relayed.body(body))
+}
+
+/// Sends a JSON body to a shard and parses its JSON response, which has to be successful.
+async fn call_shard<T: DeserializeOwned>(request: awc::ClientRequest, shard: String, body: impl Serialize) -> Result<T, ApiError> {
+    let failed = |e: String| ApiError::BadGateway(format!("Shard {} failed: {}", shard, e));
+    let mut response = request.send_json(&body).await.map_err(|e| failed(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(failed(format!("status {}", response.status())));
+    }
+    response.json::<T>().limit(MAX_SHARD_RESPONSE_BYTES).await.map_err(|e| failed(e.to_string()))
+}
+
+/// Sends a list to every shard owning one of its identifiers.
+#[post("/lists")]
+async fn route_list(
+    req: HttpRequest,
+    mut body: web::Json<AddListRequest>,
+    shards: web::Data<Shards>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    // Normalized here already, so every variant of an identifier goes to the same shard
+    normalize_list(&mut body.identifiers, &settings.validation.normalization);
+    validate_list(&body.identifiers, &settings.validation)?;
+    let body = Arc::new(body.into_inner());
+    let tasks: Vec<_> = shards
+        .ring
+        .group(&body.identifiers)
+        .into_keys()
+        .map(|shard| {
+            let request = shards.request(Method::POST, &shards.url(shard, "/lists"), req.headers());
+            let (shard_url, body) = (shards.ring.shard_url(shard).to_string(), Arc::clone(&body));
+            actix_web::rt::spawn(async move { call_shard::<serde_json::Value>(request, shard_url, &*body).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await.map_err(|e| ApiError::Internal(e.to_string()))??;
+    }
+    Ok(HttpResponse::Ok().json(HashMap::from([("status", "success")])))
+}
+
+/// Forwards a co-occurrence lookup to the shard owning the identifier.
+#[get("/lists/{identifier}")]
+async fn route_lookup(req: HttpRequest, path: IdentifierPath, shards: web::Data<Shards>) -> Result<HttpResponse, ApiError> {
+    let shard = shards.ring.shard_of(&path.into_inner());
+    let mut request = shards.request(Method::GET, &shards.url(shard, forwarded_path(&req)), req.headers());
+    if let Some(if_none_match) = req.headers().get(header::IF_NONE_MATCH) {
+        request = request.insert_header((header::IF_NONE_MATCH, if_none_match.clone()));
+    }
+    relay(shards.ring.shard_url(shard), request.send().await).await
+}
+
+/// Asks every shard owning some of the identifiers for their metrics, and merges them.
+#[post("/lists/metrics")]
+async fn route_batch_metrics(
+    req: HttpRequest,
+    mut body: web::Json<BatchMetricsRequest>,
+    shards: web::Data<Shards>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    normalize_list(&mut body.identifiers, &settings.validation.normalization);
+    validate_list(&body.identifiers, &settings.validation)?;
+    let limit = body.limit.unwrap_or(DEFAULT_BATCH_METRICS_LIMIT);
+    let tasks: Vec<_> = shards
+        .ring
+        .group(&body.identifiers)
+        .into_iter()
+        .map(|(shard, identifiers)| {
+            let request = shards.request(Method::POST, &shards.url(shard, "/lists/metrics"), req.headers());
+            let shard_url = shards.ring.shard_url(shard).to_string();
+            let shard_body = BatchMetricsRequest { identifiers: identifiers.into_iter().cloned().collect(), limit: Some(limit) };
+            actix_web::rt::spawn(async move { call_shard::<BatchMetricsResponse>(request, shard_url, shard_body).await })
+        })
+        .collect();
+    // Every identifier is owned by one shard, so the responses don't overlap
+    let mut merged = BatchMetricsResponse::default();
+    for task in tasks {
+        let response = task.await.map_err(|e| ApiError::Internal(e.to_string()))??;
+        merged.metrics.extend(response.metrics);
+        merged.unknown.extend(response.unknown);
+    }
+    Ok(HttpResponse::Ok().json(merged))
+}
+
+/// Forwards a play to the shard owning the identifier, which also counts it.
+#[post("/counters")]
+async fn route_play(
+    req: HttpRequest,
+    mut body: web::Json<IncrementCounterRequest>,
+    shards: web::Data<Shards>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    body.id = normalize_identifier(&body.id, &settings.validation.normalization).into_owned();
+    validate_identifier(&body.id, &settings.validation)?;
+    let shard = shards.ring.shard_of(&body.id);
+    let request = shards.request(Method::POST, &shards.url(shard, "/counters"), req.headers());
+    relay(shards.ring.shard_url(shard), request.send_json(&body.into_inner()).await).await
+}
+
+/// Forwards a counter lookup to the shard owning the identifier.
+#[get("/counters/{id}")]
+async fn route_counter(req: HttpRequest, path: IdentifierPath, shards: web::Data<Shards>) -> Result<HttpResponse, ApiError> {
+    let shard = shards.ring.shard_of(&path.into_inner());
+    let request = shards.request(Method::GET, &shards.url(shard, forwarded_path(&req)), req.headers());
+    relay(shards.ring.shard_url(shard), request.send().await).await
+}
+
+/// A POST /recommendations request as sent to one shard.
+#[derive(Debug, Serialize)]
+struct ShardBasketRequest<'a> {
+    identifiers: Vec<&'a String>,
+    limit: usize,
+    exclude: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct RouterBasketRequest {
+    identifiers: Vec<String>,
+    limit: Option<usize>,
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShardRecommendation {
+    identifier: String,
+    score: f64,
+    source: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShardRecommendationsResponse {
+    recommendations: Vec<ShardRecommendation>,
+}
+
+/// Merges the recommendations of several shards: scores of the same identifier are
+/// summed, as each shard only saw its part of the basket, and the basket itself is left
+/// out. The source is the one of the highest score.
+fn merge_recommendations(responses: Vec<ShardRecommendationsResponse>, basket: &[String], limit: usize) -> Vec<ShardRecommendation> {
+    let mut merged: HashMap<String, ShardRecommendation> = HashMap::new();
+    for recommendation in responses.into_iter().flat_map(|response| response.recommendations) {
+        if basket.contains(&recommendation.identifier) {
+            continue;
+        }
+        match merged.get_mut(&recommendation.identifier) {
+            Some(existing) => {
+                if recommendation.score > existing.score {
+                    existing.source = recommendation.source;
+                }
+                existing.score += recommendation.score;
+            }
+            None => {
+                merged.insert(recommendation.identifier.clone(), recommendation);
+            }
+        }
+    }
+    let mut merged: Vec<ShardRecommendation> = merged.into_values().collect();
+    merged.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.identifier.cmp(&b.identifier)));
+    merged.truncate(limit);
+    merged
+}
+
+/// Asks every shard owning part of the basket for recommendations for that part, and
+/// merges them.
+#[post("/recommendations")]
+async fn route_recommendations(
+    req: HttpRequest,
+    mut body: web::Json<RouterBasketRequest>,
+    shards: web::Data<Shards>,
+    settings: web::Data<Arc<SharedSettings>>,
+) -> Result<HttpResponse, ApiError> {
+    let settings = settings.current();
+    normalize_list(&mut body.identifiers, &settings.validation.normalization);
+    normalize_list(&mut body.exclude, &settings.validation.normalization);
+    let limit = body.limit.unwrap_or(DEFAULT_RECOMMENDATIONS_LIMIT);
+    // Each shard may recommend items of the basket the router filters out
+    let shard_limit = limit + body.identifiers.len();
+    let tasks: Vec<_> = shards
+        .ring
+        .group(&body.identifiers)
+        .into_iter()
+        .map(|(shard, identifiers)| {
+            let request = shards.request(Method::POST, &shards.url(shard, "/recommendations"), req.headers());
+            let shard_url = shards.ring.shard_url(shard).to_string();
+            let shard_body = serde_json::to_value(ShardBasketRequest { identifiers, limit: shard_limit, exclude: &body.exclude }).unwrap_or_default();
+            actix_web::rt::spawn(async move { call_shard::<ShardRecommendationsResponse>(request, shard_url, shard_body).await })
+        })
+        .collect();
+    let mut responses = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        match task.await.map_err(|e| ApiError::Internal(e.to_string()))? {
+            Ok(response) => responses.push(response),
+            // Recommendations of the other shards are better than none
+            Err(e) => warn!("{}", e),
+        }
+    }
+    let recommendations = merge_recommendations(responses, &body.identifiers, limit);
+    Ok(HttpResponse::Ok().json(ShardRecommendationsResponse { recommendations }))
+}
+
+fn config_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(route_list)
+        .service(route_batch_metrics)
+        .service(route_lookup)
+        .service(route_play)
+        .service(route_counter)
+        .service(route_recommendations);
+}
+
+/// Runs a router in front of the shards of `settings` until it is stopped by a signal.
+/// Only the routes of `config_routes` are served; the shards authenticate and rate limit
+/// the requests themselves.
+pub async fn run(settings: Settings) -> std::io::Result<()> {
+    let ShardSettings { peers, virtual_nodes, timeout_ms } = settings.shards.clone();
+    let ring = Arc::new(HashRing::new(&peers, virtual_nodes));
+    info!(shards = ?peers, "Routing to shards.");
+
+    let shared_settings_arc = Arc::new(SharedSettings::new(settings.clone()));
+    let address = (settings.server.bind_address, settings.server.port);
+    let server_settings = settings.server.clone();
+    let server = HttpServer::new(move || {
+        let shards = Shards {
+            ring: Arc::clone(&ring),
+            client: awc::Client::default(),
+            timeout: Duration::from_millis(timeout_ms),
+        };
+        App::new()
+            .wrap(middleware::from_fn(logging::access_log))
+            .wrap(error::json_error_bodies())
+            .wrap(middleware::from_fn(api::compression::compress))
+            .app_data(web::Data::new(shards))
+            .app_data(web::Data::new(shared_settings_arc.clone()))
+            .configure(error::config_extractors)
+            .service(web::scope("/v1").configure(config_routes).default_service(web::to(error::route_not_found)))
+            // The unprefixed routes from before versioning, like on the shards
+            .service(web::scope("").configure(config_routes).default_service(web::to(error::route_not_found)))
+    });
+    let keep_alive = match server_settings.keep_alive_secs {
+        0 => KeepAlive::Disabled,
+        secs => KeepAlive::Timeout(Duration::from_secs(secs)),
+    };
+    let server = server
+        .keep_alive(keep_alive)
+        .client_request_timeout(Duration::from_millis(server_settings.client_timeout_ms))
+        .shutdown_timeout(server_settings.shutdown_timeout_secs)
+        .disable_signals();
+    let server = if server_settings.workers > 0 { server.workers(server_settings.workers) } else { server };
+    info!("Router running on http://{}", SocketAddr::from(address));
+    let server = server.bind(address)?.run();
+    actix_web::rt::spawn(shutdown::stop_on_signal(server.handle()));
+    systemd::notify("READY=1");
+    let result = server.await;
+    systemd::notify("STOPPING=1");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shards(count: usize) -> Vec<String> {
+        (0..count).map(|index| format!("http://shard-{}:3030", index)).collect()
+    }
+
+    #[test]
+    fn test_adding_a_shard_only_moves_identifiers_to_it() {
+        let identifiers: Vec<String> = (0..2000).map(|index| format!("ard:{}", index)).collect();
+        let before = HashRing::new(&shards(4), 128);
+        let after = HashRing::new(&shards(5), 128);
+        let mut moved = 0;
+        for identifier in &identifiers {
+            let (old, new) = (before.shard_of(identifier), after.shard_of(identifier));
+            if old != new {
+                assert_eq!(new, 4);
+                moved += 1;
+            }
+        }
+        // About a fifth of the identifiers move to the new shard
+        assert!((250..550).contains(&moved), "{} moved", moved);
+        assert_eq!(before.group(&identifiers).values().map(Vec::len).sum::<usize>(), identifiers.len());
+    }
+
+    #[test]
+    fn test_merged_recommendations_sum_scores_and_skip_the_basket() {
+        let response = |entries: &[(&str, f64)]| ShardRecommendationsResponse {
+            recommendations: entries
+                .iter()
+                .map(|&(identifier, score)| ShardRecommendation {
+                    identifier: identifier.to_string(),
+                    score,
+                    source: "co_occurrence".to_string(),
+                })
+                .collect(),
+        };
+        let merged = merge_recommendations(
+            vec![response(&[("b", 3.0), ("c", 2.0)]), response(&[("c", 2.0), ("a", 9.0), ("d", 1.0)])],
+            &["a".to_string()],
+            2,
+        );
+        let merged: Vec<(&str, f64)> = merged.iter().map(|r| (r.identifier.as_str(), r.score)).collect();
+        assert_eq!(merged, [("c", 4.0), ("b", 3.0)]);
+    }
+}
@@ -0,0 +1,152 @@
+// src/scheduler.rs
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::web;
+use chrono::{DateTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+use crate::determinism;
+
+/// When a job runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// Once per interval, the first time one interval after the start
+    Every(Duration),
+    /// At the start of every hour in the time zone, which includes every day, week and
+    /// month boundary. Follows the deterministic clock, if on.
+    Hourly(Tz),
+}
+
+/// Returns the start of the hour following `now`. Every day, week and month boundary
+/// is also an hour boundary, so this is the next point in time a rotation can be due.
+pub fn next_hour_boundary<Tz: TimeZone>(now: &DateTime<Tz>) -> DateTime<Tz> {
+    let start_of_hour = now.clone() - chrono::Duration::seconds((now.minute() * 60 + now.second()) as i64)
+        - chrono::Duration::nanoseconds(now.nanosecond() as i64);
+    start_of_hour + chrono::Duration::hours(1)
+}
+
+impl Schedule {
+    /// Waits until the job is due next.
+    async fn wait(&self) {
+        match self {
+            Schedule::Every(interval) => tokio::time::sleep(*interval).await,
+            // Sleeps until exactly the boundary instead of polling, so jobs neither lag nor
+            // drift. On the deterministic clock, until it is moved past it.
+            Schedule::Hourly(timezone) => {
+                let now = determinism::now().with_timezone(timezone);
+                determinism::sleep_until(next_hour_boundary(&now).with_timezone(&Utc)).await;
+            }
+        }
+    }
+}
+
+/// Why a run of a job failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobError {
+    /// Logged, and the job runs again when it is due next
+    Failed(String),
+    /// Logged, and the job isn't run again, e.g. as it isn't supported by the storage
+    Stop(String),
+}
+
+type JobFn = dyn Fn() -> Result<(), JobError> + Send + Sync;
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    run: Arc<JobFn>,
+}
+
+impl Job {
+    /// Runs the job whenever it is due, on the blocking thread pool, as jobs take locks
+    /// and write files. A failing or panicking run is logged and doesn't affect the
+    /// other jobs or the next run.
+    async fn run_forever(self) {
+        loop {
+            self.schedule.wait().await;
+            let run = Arc::clone(&self.run);
+            match web::block(move || run()).await {
+                Ok(Ok(())) => debug!(job = self.name, "Job ran."),
+                Ok(Err(JobError::Failed(e))) => warn!(job = self.name, "Job failed: {}", e),
+                Ok(Err(JobError::Stop(e))) => {
+                    warn!(job = self.name, "Stopping job: {}", e);
+                    return;
+                }
+                Err(e) => error!(job = self.name, "Job panicked: {:?}", e),
+            }
+        }
+    }
+}
+
+/// The background jobs of an instance or a tenant, each registered with its schedule
+/// and run independently of the others.
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler::default()
+    }
+
+    /// Registers `run` to be called under `name` (for the logs) whenever `schedule` says.
+    pub fn register<F>(&mut self, name: impl Into<String>, schedule: Schedule, run: F) -> &mut Self
+    where
+        F: Fn() -> Result<(), JobError> + Send + Sync + 'static,
+    {
+        self.jobs.push(Job { name: name.into(), schedule, run: Arc::new(run) });
+        self
+    }
+
+    /// Names of the registered jobs, in the order of registration.
+    pub fn job_names(&self) -> Vec<&str> {
+        self.jobs.iter().map(|job| job.name.as_str()).collect()
+    }
+
+    /// Runs every job until the returned future is dropped, or all jobs stopped.
+    pub async fn run(self) {
+        info!(jobs = ?self.job_names(), "Scheduler started.");
+        let mut jobs = JoinSet::new();
+        for job in self.jobs {
+            jobs.spawn(job.run_forever());
+        }
+        while jobs.join_next().await.is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_next_hour_boundary() {
+        let now = Utc.with_ymd_and_hms(2025, 12, 31, 23, 59, 59).unwrap() + chrono::Duration::milliseconds(500);
+        assert_eq!(next_hour_boundary(&now), Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap());
+
+        let on_boundary = Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap();
+        assert_eq!(next_hour_boundary(&on_boundary), Utc.with_ymd_and_hms(2025, 6, 1, 13, 0, 0).unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_failing_jobs_dont_stop_the_others() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&runs);
+        let mut scheduler = Scheduler::new();
+        scheduler
+            .register("counting", Schedule::Every(Duration::from_millis(10)), move || {
+                counted.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .register("failing", Schedule::Every(Duration::from_millis(10)), || Err(JobError::Failed("broken".to_string())))
+            .register("panicking", Schedule::Every(Duration::from_millis(10)), || panic!("broken"))
+            .register("stopping", Schedule::Every(Duration::from_millis(10)), || Err(JobError::Stop("unsupported".to_string())));
+        assert_eq!(scheduler.job_names(), ["counting", "failing", "panicking", "stopping"]);
+
+        let _ = tokio::time::timeout(Duration::from_millis(100), scheduler.run()).await;
+        assert!(runs.load(Ordering::Relaxed) >= 3);
+    }
+}
@@ -0,0 +1,613 @@
+// src/server.rs
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::KeepAlive;
+use actix_web::{middleware, web, App, HttpServer};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+// Import our custom modules
+use crate::algorithms::{CoOccurrenceCounter, Counters, TransitionCounter, run_counter_sync, run_counter_gossip, perform_final_persistence};
+use crate::algorithms::maintenance::maintenance_jobs;
+use crate::algorithms::{RecentLists, RuleSet, run_rule_mining};
+use crate::algorithms::{QualityMonitor, run_quality_evaluation};
+use crate::algorithms::shadow::ShadowScoring;
+use crate::algorithms::{ItemEmbeddings, run_embedding_training};
+use crate::algorithms::{FactorizationState, run_factorization_training};
+use crate::algorithms::{AlertLog, run_spike_detection};
+use crate::algorithms::run_digest_webhooks;
+use crate::algorithms::boosts::Boosts;
+use crate::algorithms::co_occurrence::PairStore;
+use crate::algorithms::identifier_filter::IdentifierFilter;
+use crate::algorithms::counter_store::{open_counter_store, CounterStore};
+use crate::algorithms::object_storage::{self, run_snapshot_uploads, ObjectStorage};
+use crate::algorithms::snapshot;
+use crate::algorithms::tenants::{perform_final_tenant_persistence, run_tenant_tasks, Tenant, Tenants};
+use crate::algorithms::replication::{run_replication, ChangeFeed, ReplicationState};
+use crate::algorithms::postgres_store::{run_postgres_flush, PostgresStore};
+use crate::algorithms::session_dedup::SessionDedup;
+use crate::algorithms::holdout::{run_holdout_evaluation, Holdout};
+use crate::algorithms::tombstones::Tombstones;
+use crate::algorithms::sled_store::SledStore;
+use crate::algorithms::sqlite_store::SqliteStore;
+use crate::algorithms::state_store::open_state_store;
+use crate::algorithms::warmup;
+use crate::api::audit::AuditLog;
+use crate::api::recorder::Recorder;
+use crate::api::idempotency::IdempotencyKeys;
+use crate::api::overload::ConcurrencyLimiter;
+use crate::api::quota::UsageMeter;
+use crate::api::ingest_queue::{run_ingest_queue, IngestQueue};
+use crate::api::ingest_stats::IngestStats;
+use crate::api::rate_limit::RateLimiter;
+use crate::config::{self, CardinalityPolicy, CounterBackend, Settings, SharedSettings};
+use crate::{algorithms, api, determinism, ingest, locks, logging, router, shutdown, systemd, tls};
+#[cfg(unix)]
+use crate::unix_socket;
+
+/// The state shared by all workers of the HTTP API, registered as their app data.
+#[derive(Clone)]
+pub(crate) struct AppState {
+    pub co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    /// The filter of the co-occurrences' identifiers, checked before taking their lock
+    pub identifier_filter: Arc<IdentifierFilter>,
+    pub counters: Arc<RwLock<Counters>>,
+    pub transitions: Arc<Mutex<TransitionCounter>>,
+    pub recent_lists: Arc<Mutex<RecentLists>>,
+    pub rule_set: Arc<Mutex<RuleSet>>,
+    pub quality_monitor: Arc<Mutex<QualityMonitor>>,
+    pub shadow_scoring: Arc<ShadowScoring>,
+    pub embeddings: Arc<Mutex<ItemEmbeddings>>,
+    pub factorization: Arc<Mutex<FactorizationState>>,
+    pub settings: Arc<SharedSettings>,
+    pub alert_log: Arc<Mutex<AlertLog>>,
+    pub boosts: Arc<RwLock<Boosts>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub usage_meter: Arc<UsageMeter>,
+    pub ingest_stats: Arc<IngestStats>,
+    pub ingest_queue: Arc<IngestQueue>,
+    pub concurrency_limiter: Arc<ConcurrencyLimiter>,
+    pub idempotency_keys: Arc<IdempotencyKeys>,
+    pub session_dedup: Arc<SessionDedup>,
+    pub holdout: Arc<Holdout>,
+    pub tombstones: Arc<RwLock<Tombstones>>,
+    pub audit_log: Arc<AuditLog>,
+    pub recorder: Arc<Recorder>,
+    pub tenants: Arc<Tenants>,
+    pub replication_state: Arc<ReplicationState>,
+}
+
+impl AppState {
+    /// Creates the state around the co-occurrences and counters, loading the rest from the
+    /// data directory of `settings`. Also returns the tenants created from now on, for
+    /// starting their background tasks.
+    pub(crate) fn new(
+        settings: &Settings,
+        co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+        counters: Arc<RwLock<Counters>>,
+    ) -> (Self, mpsc::UnboundedReceiver<Arc<Tenant>>) {
+        let shared_settings = Arc::new(SharedSettings::new(settings.clone()));
+        let (tenants, created_tenants) = Tenants::new(settings);
+        let identifier_filter = locks::read(&co_occurrence, "co_occurrence").identifier_filter();
+        let state = AppState {
+            identifier_filter,
+            co_occurrence,
+            counters,
+            transitions: Arc::new(Mutex::new(TransitionCounter::new())),
+            recent_lists: Arc::new(Mutex::new(RecentLists::new(settings.recent_lists_capacity))),
+            rule_set: Arc::new(Mutex::new(RuleSet::default())),
+            quality_monitor: Arc::new(Mutex::new(QualityMonitor::new())),
+            shadow_scoring: Arc::new(ShadowScoring::new()),
+            embeddings: Arc::new(Mutex::new(ItemEmbeddings::default())),
+            factorization: Arc::new(Mutex::new(FactorizationState::default())),
+            alert_log: Arc::new(Mutex::new(AlertLog::new(settings.alerts.history))),
+            boosts: Arc::new(RwLock::new(Boosts::load(settings.storage.data_path(algorithms::boosts::SNAPSHOT_PATH)))),
+            rate_limiter: Arc::new(RateLimiter::new(Arc::clone(&shared_settings))),
+            usage_meter: Arc::new(UsageMeter::new(Arc::clone(&shared_settings))),
+            ingest_stats: Arc::new(IngestStats::new(Arc::clone(&shared_settings))),
+            ingest_queue: Arc::new(IngestQueue::new(&settings.ingest_queue)),
+            concurrency_limiter: Arc::new(ConcurrencyLimiter::new(Arc::clone(&shared_settings))),
+            idempotency_keys: Arc::new(IdempotencyKeys::new(&settings.idempotency)),
+            session_dedup: Arc::new(SessionDedup::new(&settings.session_dedup)),
+            holdout: Arc::new(Holdout::new(&settings.evaluation)),
+            tombstones: Arc::new(RwLock::new(Tombstones::load(&settings.tombstones, settings.storage.data_path(algorithms::tombstones::SNAPSHOT_PATH)))),
+            audit_log: Arc::new(AuditLog::open(settings.storage.data_path(api::audit::AUDIT_LOG_PATH))),
+            recorder: Arc::new(Recorder::open(settings.storage.data_path(api::recorder::RECORDING_PATH), &settings.recorder)),
+            tenants: Arc::new(tenants),
+            replication_state: Arc::new(ReplicationState::new(settings.replication.primary_url.is_some())),
+            settings: shared_settings,
+        };
+        (state, created_tenants)
+    }
+
+    /// The state serving the requests of `tenant`: its co-occurrences and counters in
+    /// place of the default ones.
+    pub(crate) fn for_tenant(&self, tenant: &Tenant) -> Self {
+        AppState {
+            co_occurrence: Arc::clone(&tenant.co_occurrence),
+            identifier_filter: Arc::clone(&tenant.identifier_filter),
+            counters: Arc::clone(&tenant.counters),
+            ..self.clone()
+        }
+    }
+}
+
+/// The HTTP API on `state`, with all middleware: what every worker of the server runs.
+pub(crate) fn app(
+    state: &AppState,
+    settings: &Settings,
+) -> App<impl ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error, InitError = ()>> {
+    App::new()
+        // Tell how stale the served state is (innermost, as it only decorates successful responses)
+        .wrap(middleware::from_fn(api::freshness::freshness_headers))
+        // Reject writes while this instance is a replica (so they are authenticated first)
+        .wrap(middleware::from_fn(api::replica::reject_replica_writes))
+        // Count requests per API key and enforce the daily quotas (inside the rate
+        // limiting, so rate-limited requests don't use up the quota)
+        .wrap(middleware::from_fn(api::quota::enforce_quota))
+        // Shed requests to routes already serving their limit (inside the rate limiting,
+        // so rate-limited requests don't take a slot)
+        .wrap(middleware::from_fn(api::overload::limit_concurrency))
+        // Reject clients exceeding their rate limit (so rejections are logged)
+        .wrap(middleware::from_fn(api::rate_limit::rate_limit))
+        // Count the ingest requests rejected as invalid, per client
+        .wrap(middleware::from_fn(api::ingest_stats::count_rejections))
+        // Record the ingest requests for replaying them, if enabled
+        .wrap(middleware::from_fn(api::recorder::record_ingest))
+        // Record administrative operations, after the authentication tells who made them
+        .wrap(middleware::from_fn(api::audit::audit_trail))
+        // Check API keys; runs before the rate limiting, which counts clients by key
+        .wrap(middleware::from_fn(api::auth::authenticate))
+        // Verify request signatures; signed clients don't need an API key
+        .wrap(middleware::from_fn(api::signing::verify_signature))
+        // Restrict admin (and optionally write) endpoints to the allowed networks
+        .wrap(middleware::from_fn(api::allowlist::ip_allowlist))
+        // Swap in the state of the request's tenant, and remove its path prefix
+        .wrap(middleware::from_fn(api::tenants::resolve_tenant))
+        // Log method, path, status and latency of every request
+        .wrap(middleware::from_fn(logging::access_log))
+        // Give every error response a JSON body
+        .wrap(api::error::json_error_bodies())
+        // Compress large response bodies (outermost, so it sees the final body)
+        .wrap(middleware::from_fn(api::compression::compress))
+        // Register the whole state, for the handlers using many parts of it
+        .app_data(web::Data::new(state.clone()))
+        // Register the co-occurrences
+        .app_data(web::Data::new(state.co_occurrence.clone()))
+        // Register the filter of its identifiers, checked before taking its lock
+        .app_data(web::Data::new(state.identifier_filter.clone()))
+        // Register the rotating counters (distinct type from the co-occurrences)
+        .app_data(web::Data::new(state.counters.clone()))
+        // Register the transition counter for sequence-aware predictions
+        .app_data(web::Data::new(state.transitions.clone()))
+        // Register the recent lists buffer and the mined association rules
+        .app_data(web::Data::new(state.recent_lists.clone()))
+        .app_data(web::Data::new(state.rule_set.clone()))
+        // Register the latest quality report of the recommendations
+        .app_data(web::Data::new(state.quality_monitor.clone()))
+        // Register the agreement of the shadow scoring pipelines
+        .app_data(web::Data::new(state.shadow_scoring.clone()))
+        // Register the trained item embeddings
+        .app_data(web::Data::new(state.embeddings.clone()))
+        // Register the factorization model state and the settings (for feature flags)
+        .app_data(web::Data::new(state.factorization.clone()))
+        .app_data(web::Data::new(state.settings.clone()))
+        // Register the spike alerts
+        .app_data(web::Data::new(state.alert_log.clone()))
+        // Register the editorial boosts applied when ranking recommendations
+        .app_data(web::Data::new(state.boosts.clone()))
+        // Register the rate limiter buckets, shared by all workers
+        .app_data(web::Data::new(state.rate_limiter.clone()))
+        // Register the requests per API key and day, checked against the quotas
+        .app_data(web::Data::new(state.usage_meter.clone()))
+        // Register the ingestion per client
+        .app_data(web::Data::new(state.ingest_stats.clone()))
+        // Register the queue of lists accepted but not yet counted
+        .app_data(web::Data::new(state.ingest_queue.clone()))
+        // Register the requests in flight per route, checked against the concurrency limits
+        .app_data(web::Data::new(state.concurrency_limiter.clone()))
+        // Register the idempotency keys of recent writes, shared by all workers
+        .app_data(web::Data::new(state.idempotency_keys.clone()))
+        // Register the hashes of recent lists, for skipping repeated submissions
+        .app_data(web::Data::new(state.session_dedup.clone()))
+        // Register the lists held out of the co-occurrences for evaluating them
+        .app_data(web::Data::new(state.holdout.clone()))
+        // Register the deleted identifiers kept out of the ingestion
+        .app_data(web::Data::new(state.tombstones.clone()))
+        // Register the audit log of administrative operations
+        .app_data(web::Data::new(state.audit_log.clone()))
+        // Register the recording of the ingest requests
+        .app_data(web::Data::new(state.recorder.clone()))
+        // Register the tenants, whose state is swapped in per request
+        .app_data(web::Data::new(state.tenants.clone()))
+        // Register the replication role, which decides whether writes are accepted
+        .app_data(web::Data::new(state.replication_state.clone()))
+        // Configure all routes from the api module
+        .configure(|cfg| api::config_routes(cfg, settings))
+}
+
+/// Runs the server with `settings` until it is stopped by a signal, then persists the
+/// state.
+pub async fn run(settings: Settings) -> std::io::Result<()> {
+    let log_guard = logging::init(&settings.logging);
+
+    // A router keeps no state, it only forwards requests to the shards
+    if !settings.shards.peers.is_empty() {
+        let result = router::run(settings).await;
+        log_guard.shutdown();
+        return result;
+    }
+
+    // Before any state is created, which keeps the hash seeds it was created with
+    determinism::configure(&settings.determinism);
+
+    // Fail early if the persisted files can't be written
+    if let Err(e) = snapshot::prepare_data_dir(&settings.storage.data_dir) {
+        error!("{}", e);
+        return Err(std::io::Error::other(e));
+    }
+    info!(data_dir = %settings.storage.data_dir.display(), "Using the data directory.");
+
+    // Background tasks, stopped on shutdown before the final persistence
+    let mut background_tasks = Vec::new();
+
+    // Download the snapshots missing locally, and upload every new one, if a bucket is configured
+    let snapshot_paths = [
+        settings.storage.data_path(algorithms::rotating_counters::SNAPSHOT_PATH),
+        settings.storage.data_path(algorithms::co_occurrence::SNAPSHOT_PATH),
+    ];
+    let object_storage = ObjectStorage::new(&settings.object_storage, &settings.storage.data_dir);
+    if let Some(storage) = &object_storage {
+        object_storage::restore_snapshots(storage, &snapshot_paths).await;
+        let uploads = object_storage::start_uploads();
+        let storage = storage.clone();
+        background_tasks.push(actix_web::rt::spawn(async move {
+            run_snapshot_uploads(storage, uploads).await;
+        }));
+    }
+
+    // Connect to PostgreSQL or open the SQLite database, if configured
+    let postgres_store = match &settings.storage.postgres_url {
+        Some(url) => match PostgresStore::connect(url, &settings.storage, &settings.counters).await {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => {
+                error!("Failed to connect to PostgreSQL, keeping the state in memory: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let sqlite_path = settings.storage.sqlite_path.as_ref().map(|path| settings.storage.data_path(path));
+    let sqlite_store = match (&postgres_store, &sqlite_path) {
+        (None, Some(path)) => match SqliteStore::open(path, &settings.counters) {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => {
+                error!("Failed to open SQLite database {}, keeping the state in memory: {}", path.display(), e);
+                None
+            }
+        },
+        _ => None,
+    };
+    let database: Option<(Arc<dyn PairStore>, Arc<dyn CounterStore>)> = match (&postgres_store, sqlite_store) {
+        (Some(store), _) => Some((store.clone(), store.clone())),
+        (None, Some(store)) => Some((store.clone(), store)),
+        (None, None) => None,
+    };
+
+    // Initialize all counter types
+    let mut co_occurrence_counter = CoOccurrenceCounter::with_metrics_cache(&settings.metrics_cache);
+    co_occurrence_counter.set_pair_strategy(settings.pair_strategy);
+    co_occurrence_counter.set_co_visitation_window(settings.co_visitation_window_secs);
+    co_occurrence_counter.set_cardinality_limit(&settings.cardinality);
+    co_occurrence_counter.set_identifier_filter(&settings.identifier_filter);
+    let pairs_path = settings.storage.pairs_path.as_ref().map(|path| settings.storage.data_path(path));
+    let sled_store = pairs_path.and_then(|path| match SledStore::open(&path, &settings.storage) {
+        Ok(store) => Some(Arc::new(store) as Arc<dyn PairStore>),
+        Err(e) => {
+            error!("Failed to open co-occurrence database {}: {}", path.display(), e);
+            None
+        }
+    });
+    let pair_store = sled_store.or_else(|| database.as_ref().map(|(pair_store, _)| pair_store.clone()));
+    let co_occurrences_in_memory = pair_store.is_none();
+    match pair_store {
+        Some(pair_store) => co_occurrence_counter.attach_store(pair_store),
+        None => co_occurrence_counter.recover(&settings.storage),
+    }
+    if !co_occurrences_in_memory && settings.cardinality.max_identifiers > 0 && settings.cardinality.policy == CardinalityPolicy::Evict {
+        warn!("MEDIATHEK_CARDINALITY_POLICY is \"evict\", which a co-occurrence database doesn't support, so identifiers beyond the limit are kept.");
+    }
+    // Stream the changes of the default state to replicas, if any subscribe
+    let change_feed = Arc::new(ChangeFeed::new(settings.replication.buffer));
+    co_occurrence_counter.attach_change_feed(Arc::clone(&change_feed));
+    let co_occurrence_counter_arc = Arc::new(RwLock::new(co_occurrence_counter));
+    let counter_store = open_counter_store(&settings.counters, database.map(|(_, counter_store)| counter_store));
+    let mut rotating_counters = Counters::new(&settings.counters, &settings.storage, open_state_store(&settings.storage), counter_store);
+    rotating_counters.attach_change_feed(change_feed);
+    rotating_counters.set_following(settings.replication.primary_url.is_some());
+    let rotating_counters_arc = Arc::new(RwLock::new(rotating_counters));
+    // Load a published backup into a new instance before it serves requests, if configured
+    warmup::warm_up(&settings.warmup, &co_occurrence_counter_arc, &rotating_counters_arc, settings.counters.rotation_timezone).await;
+    let (state, created_tenants) = AppState::new(&settings, Arc::clone(&co_occurrence_counter_arc), Arc::clone(&rotating_counters_arc));
+    let co_occurrence_for_shutdown = Arc::clone(&co_occurrence_counter_arc);
+    let tenants_for_shutdown = Arc::clone(&state.tenants);
+    let rotation_timezone = settings.counters.rotation_timezone;
+
+    // Rotate and snapshot the counters, snapshot the co-occurrences unless a store records
+    // every list, and evict and compact as configured
+    let scheduler = maintenance_jobs("default", &co_occurrence_counter_arc, &rotating_counters_arc, &settings, co_occurrences_in_memory);
+    background_tasks.push(tokio::task::spawn(scheduler.run()));
+
+    // Reload the settings that can change at runtime on SIGHUP
+    #[cfg(unix)]
+    background_tasks.push(tokio::task::spawn(config::run_reload_on_hangup(Arc::clone(&state.settings))));
+
+    // Let systemd restart the server if its state can't be locked anymore, if configured
+    if let Some(interval) = systemd::watchdog_interval() {
+        background_tasks.push(tokio::task::spawn(systemd::run_watchdog(
+            Arc::clone(&rotating_counters_arc),
+            Arc::clone(&co_occurrence_counter_arc),
+            interval,
+        )));
+    }
+
+    // Start the rotation and persistence of each tenant once it is used, if there are any
+    if state.tenants.is_enabled() {
+        info!(tenants = ?settings.tenants.names, "Serving tenants.");
+        background_tasks.push(tokio::task::spawn(run_tenant_tasks(created_tenants, settings.clone())));
+    }
+    for tenant in settings.ingest_rules.tenants().filter(|tenant| !settings.tenants.names.iter().any(|name| name == tenant)) {
+        warn!("MEDIATHEK_INGEST_RULES routes identifiers to tenant '{}', which isn't configured, so they will be left out.", tenant);
+    }
+
+    // Start reloading the counters from the shared store, if one is configured
+    if settings.counters.backend == CounterBackend::Redis {
+        background_tasks.push(tokio::task::spawn(run_counter_sync(Arc::clone(&rotating_counters_arc), settings.counters.sync_interval_secs)));
+    }
+
+    // Start writing the changes to PostgreSQL in batches, if connected
+    if let Some(store) = &postgres_store {
+        background_tasks.push(tokio::task::spawn(run_postgres_flush(
+            Arc::clone(store),
+            Arc::clone(&rotating_counters_arc),
+            Duration::from_millis(settings.storage.postgres_flush_interval_ms),
+            Duration::from_secs(settings.counters.sync_interval_secs),
+        )));
+    }
+
+    // Start the background task mining association rules from the recent lists
+    let recent_lists_for_task = Arc::clone(&state.recent_lists);
+    let rule_set_for_task = Arc::clone(&state.rule_set);
+    let rule_settings = settings.association_rules.clone();
+    background_tasks.push(tokio::task::spawn(async move {
+        run_rule_mining(recent_lists_for_task, rule_set_for_task, rule_settings).await;
+    }));
+
+    // Start the background task evaluating the quality of the recommendations, if enabled
+    if settings.quality.interval_secs > 0 {
+        let co_occurrence_for_quality = Arc::clone(&co_occurrence_counter_arc);
+        let quality_monitor_for_task = Arc::clone(&state.quality_monitor);
+        let quality_settings = settings.quality.clone();
+        background_tasks.push(tokio::task::spawn(async move {
+            run_quality_evaluation(co_occurrence_for_quality, quality_monitor_for_task, quality_settings).await;
+        }));
+    }
+
+    // Start the background task evaluating the recommendations on held-out lists, if any
+    if state.holdout.is_enabled() {
+        let co_occurrence_for_evaluation = Arc::clone(&co_occurrence_counter_arc);
+        let holdout_for_task = Arc::clone(&state.holdout);
+        let evaluation_settings = settings.evaluation.clone();
+        background_tasks.push(tokio::task::spawn(async move {
+            run_holdout_evaluation(co_occurrence_for_evaluation, holdout_for_task, evaluation_settings).await;
+        }));
+    }
+
+    // Start the worker counting the lists queued by POST /lists, if the queue is enabled
+    if state.ingest_queue.is_enabled() {
+        background_tasks.push(tokio::task::spawn(run_ingest_queue(Arc::clone(&state.ingest_queue))));
+    }
+
+    // Start the background task training item embeddings from the recent lists
+    let recent_lists_for_training = Arc::clone(&state.recent_lists);
+    let embeddings_for_task = Arc::clone(&state.embeddings);
+    let embedding_settings = settings.embeddings.clone();
+    background_tasks.push(tokio::task::spawn(async move {
+        run_embedding_training(recent_lists_for_training, embeddings_for_task, embedding_settings).await;
+    }));
+
+    // Start the background task training the factorization model
+    let recent_lists_for_factorization = Arc::clone(&state.recent_lists);
+    let factorization_for_task = Arc::clone(&state.factorization);
+    let factorization_settings = settings.factorization.clone();
+    background_tasks.push(tokio::task::spawn(async move {
+        run_factorization_training(recent_lists_for_factorization, factorization_for_task, factorization_settings).await;
+    }));
+
+    // Start the background task detecting spikes in the counters.
+    // It runs on the actix runtime (not `tokio::task::spawn`) because the webhook client is not `Send`.
+    let rotating_counters_for_alerts = Arc::clone(&rotating_counters_arc);
+    let alert_log_for_task = Arc::clone(&state.alert_log);
+    let alert_settings = settings.alerts.clone();
+    background_tasks.push(actix_web::rt::spawn(async move {
+        run_spike_detection(rotating_counters_for_alerts, alert_log_for_task, alert_settings).await;
+    }));
+
+    // Follow the primary, if this instance is a replica.
+    // Like the spike detection, it runs on the actix runtime for the HTTP client.
+    if state.replication_state.is_following() {
+        background_tasks.push(actix_web::rt::spawn(run_replication(
+            Arc::clone(&co_occurrence_counter_arc),
+            Arc::clone(&rotating_counters_arc),
+            rotation_timezone,
+            settings.replication.clone(),
+            Arc::clone(&state.replication_state),
+        )));
+    }
+
+    // Exchange the counters with the peers, if they are gossiped.
+    // Like the spike detection, it runs on the actix runtime for the HTTP client.
+    if settings.counters.backend == CounterBackend::Gossip && !settings.counters.gossip_peers.is_empty() {
+        background_tasks.push(actix_web::rt::spawn(run_counter_gossip(
+            Arc::clone(&rotating_counters_arc),
+            settings.counters.clone(),
+            settings.admin.token.clone(),
+        )));
+    }
+
+    // Start the background task sending trending digests to the webhooks, if any.
+    // Like the spike detection, it runs on the actix runtime for the webhook client.
+    if !settings.digest.webhook_urls.is_empty() {
+        let rotating_counters_for_digests = Arc::clone(&rotating_counters_arc);
+        let digest_settings = settings.digest.clone();
+        background_tasks.push(actix_web::rt::spawn(async move {
+            run_digest_webhooks(rotating_counters_for_digests, digest_settings, rotation_timezone).await;
+        }));
+    }
+
+    let ingestor = ingest::Ingestor::new(
+        Arc::clone(&co_occurrence_counter_arc),
+        Arc::clone(&state.recent_lists),
+        Arc::clone(&rotating_counters_arc),
+        Arc::clone(&state.tombstones),
+        Arc::clone(&state.holdout),
+        Arc::clone(&state.tenants),
+        Arc::clone(&state.settings),
+    );
+
+    // Import the historical lists and plays before serving, if given
+    if let Some(path) = settings.storage.import_path.clone() {
+        let importer = ingestor.clone();
+        let summary = web::block(move || ingest::import::import_file(&importer, &path))
+            .await
+            .map_err(std::io::Error::other)?
+            .map_err(|e| {
+                error!("{}", e);
+                std::io::Error::other(e)
+            })?;
+        info!(lists = summary.lists, plays = summary.plays, rejected = summary.rejected, "Import finished.");
+        for line_error in &summary.errors {
+            warn!("Skipped line {} of the import: {}", line_error.line, line_error.message);
+        }
+    }
+
+    // Start consuming the Kafka topics and NATS subjects, if configured
+    if settings.kafka.brokers.is_some() {
+        background_tasks.extend(ingest::start_kafka_consumer(ingestor.clone(), settings.kafka.clone()));
+    }
+    if settings.nats.url.is_some() {
+        background_tasks.extend(ingest::start_nats_subscriber(ingestor, settings.nats.clone(), &settings.storage));
+    }
+
+    if settings.auth.api_keys.0.is_empty() {
+        warn!("No API keys configured (MEDIATHEK_API_KEYS), write endpoints are open to everyone.");
+    }
+
+    // Serve the gRPC API on its own port, sharing the state of the HTTP API
+    if settings.grpc.enabled {
+        let service = api::grpc::RecommendationService::new(
+            Arc::clone(&co_occurrence_counter_arc),
+            Arc::clone(&state.recent_lists),
+            Arc::clone(&rotating_counters_arc),
+            Arc::clone(&state.tombstones),
+            Arc::clone(&state.tenants),
+            Arc::clone(&state.settings),
+        );
+        let address = (settings.server.bind_address, settings.grpc.port).into();
+        info!("gRPC server running on http://{}", address);
+        background_tasks.push(actix_web::rt::spawn(async move {
+            if let Err(e) = api::grpc::serve(service, address).await {
+                error!("gRPC server failed: {}", e);
+            }
+        }));
+    }
+
+    let tls_config = tls::server_config(&settings.tls)?;
+    let mutual_tls = settings.tls.client_ca_path.is_some();
+
+    let address = (settings.server.bind_address, settings.server.port);
+    let server_settings = settings.server.clone();
+    let app_state = state.clone();
+    let server = HttpServer::new(move || app(&app_state, &settings));
+    let keep_alive = match server_settings.keep_alive_secs {
+        0 => KeepAlive::Disabled,
+        secs => KeepAlive::Timeout(Duration::from_secs(secs)),
+    };
+    let server = server
+        .keep_alive(keep_alive)
+        .client_request_timeout(Duration::from_millis(server_settings.client_timeout_ms))
+        .shutdown_timeout(server_settings.shutdown_timeout_secs)
+        // Signals are handled by `shutdown::stop_on_signal`
+        .disable_signals();
+    let mut server = if server_settings.workers > 0 { server.workers(server_settings.workers) } else { server };
+    if let Some(path) = &server_settings.socket_path {
+        #[cfg(unix)]
+        {
+            unix_socket::remove(path)?;
+            server = server.bind_uds(path)?;
+            unix_socket::set_mode(path, server_settings.socket_mode)?;
+            info!("Server running on unix:{}", path.display());
+        }
+        #[cfg(not(unix))]
+        warn!("MEDIATHEK_SOCKET_PATH is set to {}, but Unix domain sockets aren't supported here.", path.display());
+    }
+    if !server_settings.socket_only {
+        server = match tls_config {
+            Some(tls_config) => {
+                info!(mutual_tls, "Server running on https://{}", SocketAddr::from(address));
+                server.bind_rustls_0_23(address, tls_config)?
+            }
+            None => {
+                info!("Server running on http://{}", SocketAddr::from(address));
+                server.bind(address)?
+            }
+        };
+    } else if server_settings.socket_path.is_none() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "MEDIATHEK_SOCKET_ONLY needs MEDIATHEK_SOCKET_PATH"));
+    }
+    let server = server.run();
+    actix_web::rt::spawn(shutdown::stop_on_signal(server.handle()));
+    systemd::notify("READY=1");
+    let server_result = server.await;
+    systemd::notify("STOPPING=1");
+
+    // Stop the background tasks, so none of them changes the state after it was persisted.
+    // Blocking work they already started still finishes, holding the locks the final
+    // persistence waits for.
+    for task in background_tasks {
+        task.abort();
+        let _ = task.await;
+    }
+    // Count the lists accepted before the server stopped
+    state.ingest_queue.drain().await;
+    #[cfg(unix)]
+    if let Some(path) = &server_settings.socket_path {
+        if let Err(e) = unix_socket::remove(path) {
+            warn!("Failed to remove the socket {}: {}", path.display(), e);
+        }
+    }
+
+    // --- GRACEFUL SHUTDOWN PERSISTENCE ---
+    // The original `rotating_counters_arc` is still available here,
+    // and can be directly passed to the final persistence function.
+    perform_final_persistence(rotating_counters_arc).await;
+    if let Err(e) = web::block(move || locks::write(&co_occurrence_for_shutdown, "co_occurrence").compact()).await {
+        error!("Error during final co-occurrence persistence block: {:?}", e);
+    }
+    perform_final_tenant_persistence(&tenants_for_shutdown).await;
+    if let Some(storage) = &object_storage {
+        object_storage::upload_snapshots(storage, &snapshot_paths).await;
+    }
+    if let Some(store) = postgres_store {
+        match store.flush().await {
+            Ok(()) => info!("Wrote the last changes to PostgreSQL."),
+            Err(e) => error!("Failed to write the last changes to PostgreSQL: {}", e),
+        }
+    }
+    log_guard.shutdown();
+
+    server_result // Return the result of the server run
+}
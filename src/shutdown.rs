@@ -0,0 +1,44 @@
+// src/shutdown.rs
+use actix_web::dev::ServerHandle;
+use tracing::{error, info, warn};
+
+// Function to stop the HTTP server on SIGTERM or SIGINT, replacing the signal handling of
+// actix so the shutdown is logged. The server stops accepting connections and lets running
+// requests finish (up to `MEDIATHEK_SHUTDOWN_TIMEOUT_SECS`); a second signal stops it
+// right away. Either way `HttpServer::run` returns, and the final persistence runs.
+pub async fn stop_on_signal(server: ServerHandle) {
+    let signal = wait_for_signal().await;
+    info!(signal, "Shutting down, waiting for running requests to finish.");
+    tokio::select! {
+        _ = server.stop(true) => {}
+        signal = wait_for_signal() => {
+            warn!(signal, "Shutting down without waiting for running requests.");
+            server.stop(false).await;
+        }
+    }
+}
+
+/// Waits for SIGTERM (e.g. from systemd or Kubernetes) or SIGINT (Ctrl-C), returning its
+/// name. Never returns if the signals can't be listened for.
+async fn wait_for_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = terminate.recv() => return "SIGTERM",
+                _ = ctrl_c() => return "SIGINT",
+            },
+            Err(e) => error!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    ctrl_c().await;
+    "SIGINT"
+}
+
+async fn ctrl_c() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("Failed to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
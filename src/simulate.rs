@@ -0,0 +1,209 @@
+// src/simulate.rs
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::config::Settings;
+use crate::engine::RecommendationEngine;
+use crate::locks;
+
+// Replays recorded event logs against a fresh in-memory engine, to evaluate algorithm or
+// settings changes before deploying them. The logs have the format of the event logs the
+// server writes (co_occurrences.log, rotating_counters.log): one JSON entry per line with
+// its time `at`. Entries of all logs are replayed in the order of their times, and the
+// counters rotate along with them.
+//
+// Quality is measured prequentially: every list and play is first predicted from the
+// state before it, then applied. For a list, the first identifier is the seed and the
+// others are the targets the seed's co-occurrences should contain; for a play, the played
+// identifier should be among the most played today.
+
+/// Name of the counter window plays are predicted from.
+const POPULARITY_WINDOW: &str = "today";
+
+/// A recorded change, from either kind of event log.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum RecordedEvent {
+    List { identifiers: Vec<String> },
+    Increment { id: String, count: u64 },
+    Remove { id: String },
+    Reset,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordedEntry {
+    at: DateTime<Utc>,
+    #[serde(flatten)]
+    event: RecordedEvent,
+}
+
+/// Quality metrics of a replay.
+#[derive(Debug, Default, PartialEq)]
+pub struct SimulationReport {
+    pub events: usize,
+    pub first_event_at: Option<DateTime<Utc>>,
+    pub last_event_at: Option<DateTime<Utc>>,
+    /// Lists with at least one identifier besides the seed
+    pub evaluated_lists: usize,
+    /// Evaluated lists whose seed had no co-occurrences yet
+    pub cold_starts: usize,
+    /// Share of evaluated lists with at least one target among the seed's top recommendations
+    pub hit_rate: f64,
+    /// Average share of the targets among the seed's top recommendations
+    pub recall: f64,
+    /// Average reciprocal rank of the first target among the seed's top recommendations
+    pub mean_reciprocal_rank: f64,
+    /// Share of the identifiers seen that were recommended at least once
+    pub coverage: f64,
+    pub evaluated_plays: usize,
+    /// Share of plays of an identifier that was among the most played today
+    pub popularity_hit_rate: f64,
+}
+
+fn read_log(path: &Path) -> Result<Vec<RecordedEntry>, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    data.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| serde_json::from_str(line).map_err(|e| format!("Line {} of {}: {}", index + 1, path.display(), e)))
+        .collect()
+}
+
+/// Whether `id` is among the `limit` most played identifiers of the popularity window,
+/// ranked as `top_entries` does.
+fn in_top(engine: &RecommendationEngine, id: &str, limit: usize) -> bool {
+    let counters = locks::read(engine.counters(), "rotating_counters");
+    let Some(bucket) = counters.window(POPULARITY_WINDOW) else {
+        return false;
+    };
+    let Some(count) = bucket.get(id).map(|count| *count) else {
+        return false;
+    };
+    let ahead = bucket
+        .iter()
+        .filter(|entry| *entry.value() > count || (*entry.value() == count && entry.key().as_str() < id))
+        .count();
+    ahead < limit
+}
+
+/// Replays the event logs at `paths` against an engine with the counter settings of
+/// `settings`, evaluating the top `limit` recommendations. With a `speed` above 0, the
+/// replay is paced to that many times the recorded speed; otherwise it runs as fast as
+/// possible.
+pub fn simulate(settings: &Settings, paths: &[PathBuf], limit: usize, speed: f64) -> Result<SimulationReport, String> {
+    let mut entries = Vec::new();
+    for path in paths {
+        entries.extend(read_log(path)?);
+    }
+    // Stable, so entries recorded at the same time keep the order of their log
+    entries.sort_by_key(|entry| entry.at);
+
+    let engine = RecommendationEngine::in_memory(&settings.counters);
+    let mut report = SimulationReport {
+        events: entries.len(),
+        first_event_at: entries.first().map(|entry| entry.at),
+        last_event_at: entries.last().map(|entry| entry.at),
+        ..SimulationReport::default()
+    };
+    let (mut hits, mut recall, mut reciprocal_ranks, mut popularity_hits) = (0, 0.0, 0.0, 0);
+    let (mut seen, mut recommended) = (HashSet::new(), HashSet::new());
+    let mut previous_at: Option<DateTime<Utc>> = None;
+
+    for RecordedEntry { at, event } in entries {
+        if let Some(previous_at) = previous_at.filter(|_| speed > 0.0) {
+            let recorded = (at - previous_at).to_std().unwrap_or_default();
+            std::thread::sleep(Duration::from_secs_f64(recorded.as_secs_f64() / speed));
+        }
+        previous_at = Some(at);
+        engine.advance_to(at);
+
+        match event {
+            RecordedEvent::List { identifiers } => {
+                let Some((seed, rest)) = identifiers.split_first() else { continue };
+                let targets: HashSet<&String> = rest.iter().filter(|target| *target != seed).collect();
+                if !targets.is_empty() {
+                    let related = engine.related(seed, limit);
+                    report.evaluated_lists += 1;
+                    if related.is_empty() {
+                        report.cold_starts += 1;
+                    }
+                    let found = related.iter().filter(|entry| targets.contains(&entry.id)).count();
+                    if let Some(rank) = related.iter().position(|entry| targets.contains(&entry.id)) {
+                        hits += 1;
+                        reciprocal_ranks += 1.0 / (rank + 1) as f64;
+                    }
+                    recall += found as f64 / targets.len() as f64;
+                    recommended.extend(related.into_iter().map(|entry| entry.id));
+                }
+                seen.extend(identifiers.iter().cloned());
+                engine.add_list(&identifiers);
+            }
+            RecordedEvent::Increment { id, count } => {
+                report.evaluated_plays += 1;
+                if in_top(&engine, &id, limit) {
+                    popularity_hits += 1;
+                }
+                seen.insert(id.clone());
+                engine.record_play(&id, count);
+            }
+            RecordedEvent::Remove { id } => {
+                locks::write(engine.counters(), "rotating_counters").remove(&id);
+            }
+            RecordedEvent::Reset => locks::write(engine.counters(), "rotating_counters").reset(),
+        }
+    }
+
+    let share = |part: f64, total: usize| if total > 0 { part / total as f64 } else { 0.0 };
+    report.hit_rate = share(hits as f64, report.evaluated_lists);
+    report.recall = share(recall, report.evaluated_lists);
+    report.mean_reciprocal_rank = share(reciprocal_ranks, report.evaluated_lists);
+    report.coverage = share(recommended.len() as f64, seen.len());
+    report.popularity_hit_rate = share(popularity_hits as f64, report.evaluated_plays);
+    Ok(report)
+}
+
+/// Runs the `simulate` subcommand and prints the report.
+pub fn run(settings: &Settings, paths: &[PathBuf], limit: usize, speed: f64) -> Result<(), String> {
+    let report = simulate(settings, paths, limit, speed)?;
+    println!("Events: {}", report.events);
+    if let (Some(first), Some(last)) = (report.first_event_at, report.last_event_at) {
+        println!("Recorded: {} to {}", first.to_rfc3339(), last.to_rfc3339());
+    }
+    println!("Lists evaluated: {} ({} cold starts)", report.evaluated_lists, report.cold_starts);
+    println!("  Hit rate@{}: {:.4}", limit, report.hit_rate);
+    println!("  Recall@{}: {:.4}", limit, report.recall);
+    println!("  MRR@{}: {:.4}", limit, report.mean_reciprocal_rank);
+    println!("  Coverage: {:.4}", report.coverage);
+    println!("Plays evaluated: {}", report.evaluated_plays);
+    println!("  Hit rate@{} of {}: {:.4}", limit, POPULARITY_WINDOW, report.popularity_hit_rate);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_measures_predictions_before_applying_events() {
+        let path = std::env::temp_dir().join(format!("mediathek_simulate_{}.log", std::process::id()));
+        let lines = [
+            r#"{"seq":1,"at":"2026-01-05T10:00:00Z","op":"list","identifiers":["a","b"]}"#,
+            r#"{"seq":2,"at":"2026-01-05T10:01:00Z","op":"increment","id":"a","count":1}"#,
+            r#"{"seq":3,"at":"2026-01-05T10:02:00Z","op":"list","identifiers":["a","b","c"]}"#,
+            r#"{"seq":4,"at":"2026-01-05T10:03:00Z","op":"increment","id":"a","count":1}"#,
+        ];
+        fs::write(&path, lines.join("\n")).unwrap();
+        let report = simulate(&Settings::from_env(), std::slice::from_ref(&path), 10, 0.0).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((report.events, report.evaluated_lists, report.cold_starts), (4, 2, 1));
+        // The second list finds b, but not c
+        assert_eq!((report.hit_rate, report.recall, report.mean_reciprocal_rank), (0.5, 0.25, 0.5));
+        assert_eq!(report.coverage, 1.0 / 3.0);
+        assert_eq!((report.evaluated_plays, report.popularity_hit_rate), (2, 0.5));
+    }
+}
@@ -0,0 +1,308 @@
+// src/stats.rs
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::LazyLock;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Histogram buckets per doubling of the latency, i.e. bucket bounds grow by a factor
+/// of 2^(1/4) ≈ 1.19, which bounds the error of the reported percentiles to ~19%.
+const BUCKETS_PER_DOUBLING: f64 = 4.0;
+/// Number of buckets; the last one catches everything above ~2^36 µs (19 hours).
+const BUCKET_COUNT: usize = 36 * BUCKETS_PER_DOUBLING as usize;
+
+/// Process-wide runtime statistics for GET /admin/stats. They live in a static rather than
+/// in app data, because lock waits are recorded deep inside code that has no access to it.
+static STATS: LazyLock<RuntimeStats> = LazyLock::new(RuntimeStats::default);
+
+#[derive(Debug, Default)]
+struct RuntimeStats {
+    /// Request latencies, keyed by "METHOD /route/{pattern}"
+    routes: DashMap<String, Histogram>,
+    /// Lock wait times, keyed by lock name
+    locks: DashMap<&'static str, Histogram>,
+    /// The last snapshot written, keyed by file
+    snapshots: DashMap<String, SnapshotSummary>,
+    /// The last snapshot found corrupt on load, keyed by file
+    recoveries: DashMap<String, SnapshotRecovery>,
+    /// The last memory compaction, keyed by tenant
+    compactions: DashMap<String, CompactionSummary>,
+    /// When a list or play was last ingested, in milliseconds since the Unix epoch; 0 if never
+    last_ingest_millis: AtomicI64,
+    /// When the counters last rotated, as the ingest time
+    last_rotation_millis: AtomicI64,
+    /// When a snapshot or delta was last written, as the ingest time
+    last_persist_millis: AtomicI64,
+    /// Lists rejected for exceeding the limit of distinct identifiers
+    cardinality_rejections: AtomicU64,
+    /// Identifiers evicted for exceeding the limit of distinct identifiers
+    cardinality_evictions: AtomicU64,
+}
+
+/// A log-scale latency histogram with constant memory, no matter how many samples it saw.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum_micros: u64,
+    max_micros: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: vec![0; BUCKET_COUNT],
+            count: 0,
+            sum_micros: 0,
+            max_micros: 0,
+        }
+    }
+}
+
+/// Rolled-up view of a histogram. Percentiles are upper bounds of the bucket they fall into.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Histogram {
+    /// Adds one sample.
+    pub fn record(&mut self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+        let index = ((micros.max(1) as f64).log2() * BUCKETS_PER_DOUBLING) as usize;
+        self.buckets[index.min(BUCKET_COUNT - 1)] += 1;
+        self.count += 1;
+        self.sum_micros = self.sum_micros.saturating_add(micros);
+        self.max_micros = self.max_micros.max(micros);
+    }
+
+    /// Returns the upper bound (in milliseconds) of the bucket containing the `quantile`
+    /// (0..=1) of all samples, capped at the largest sample seen.
+    fn percentile(&self, quantile: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = ((self.count as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper_micros = 2f64.powf((index + 1) as f64 / BUCKETS_PER_DOUBLING);
+                return upper_micros.min(self.max_micros as f64) / 1000.0;
+            }
+        }
+        self.max_micros as f64 / 1000.0
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count,
+            mean_ms: if self.count == 0 { 0.0 } else { self.sum_micros as f64 / self.count as f64 / 1000.0 },
+            p50_ms: self.percentile(0.5),
+            p95_ms: self.percentile(0.95),
+            p99_ms: self.percentile(0.99),
+            max_ms: self.max_micros as f64 / 1000.0,
+        }
+    }
+}
+
+/// Size and duration of a written snapshot.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct SnapshotSummary {
+    pub written_at: DateTime<Utc>,
+    /// Size on disk
+    pub bytes: u64,
+    /// Size before compression; equal to `bytes` if uncompressed
+    pub uncompressed_bytes: u64,
+    /// Time taken to encode, compress and write the snapshot
+    pub duration_ms: f64,
+}
+
+/// A snapshot found corrupt on load, and what was loaded instead.
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct SnapshotRecovery {
+    pub recovered_at: DateTime<Utc>,
+    /// The files that couldn't be loaded, newest first
+    pub corrupt_files: Vec<String>,
+    /// The older snapshot or version loaded instead; `None` if there was no valid one, so
+    /// the state started out empty
+    pub recovered_from: Option<String>,
+    /// Times the file was found corrupt since the server started
+    pub recoveries: u64,
+}
+
+/// Outcome of a memory compaction (see `memory_compaction`).
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct CompactionSummary {
+    pub compacted_at: DateTime<Utc>,
+    pub duration_ms: f64,
+    /// Estimated bytes freed by the last compaction
+    pub reclaimed_bytes: usize,
+    /// Estimated bytes freed by all compactions since the server started
+    pub total_reclaimed_bytes: usize,
+    /// Co-occurrence IDs left unused by removals that were given back
+    pub released_ids: usize,
+}
+
+/// How current the served state is, see `freshness`.
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, ToSchema)]
+pub struct Freshness {
+    pub last_ingest_at: Option<DateTime<Utc>>,
+    pub last_rotation_at: Option<DateTime<Utc>>,
+    pub last_persist_at: Option<DateTime<Utc>>,
+}
+
+/// Lists and identifiers turned away by the limit of distinct identifiers since the server
+/// started, over all tenants.
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, ToSchema)]
+pub struct CardinalitySummary {
+    pub rejected_lists: u64,
+    pub evicted_identifiers: u64,
+}
+
+fn time_of(millis: &AtomicI64) -> Option<DateTime<Utc>> {
+    Some(millis.load(Ordering::Relaxed)).filter(|&millis| millis != 0).and_then(DateTime::from_timestamp_millis)
+}
+
+/// Records the latency of a handled request to `route`.
+pub fn record_request(route: String, latency: Duration) {
+    STATS.routes.entry(route).or_default().record(latency);
+}
+
+/// Records how long acquiring the lock `name` took.
+pub fn record_lock_wait(name: &'static str, wait: Duration) {
+    STATS.locks.entry(name).or_default().record(wait);
+}
+
+/// Records a snapshot written to `file`.
+pub fn record_snapshot(file: String, summary: SnapshotSummary) {
+    STATS.snapshots.insert(file, summary);
+}
+
+/// Records that the snapshot `file` was found corrupt on load.
+pub fn record_snapshot_recovery(file: String, mut recovery: SnapshotRecovery) {
+    recovery.recoveries += STATS.recoveries.get(&file).map_or(0, |last| last.recoveries);
+    STATS.recoveries.insert(file, recovery);
+}
+
+/// Records a memory compaction of the state of `tenant`, adding up the reclaimed bytes.
+pub fn record_compaction(tenant: String, mut summary: CompactionSummary) {
+    let total_so_far = STATS.compactions.get(&tenant).map_or(0, |last| last.total_reclaimed_bytes);
+    summary.total_reclaimed_bytes = total_so_far.saturating_add(summary.reclaimed_bytes);
+    STATS.compactions.insert(tenant, summary);
+}
+
+/// Records that a list or play was ingested at `at`.
+pub fn record_ingest(at: DateTime<Utc>) {
+    STATS.last_ingest_millis.fetch_max(at.timestamp_millis(), Ordering::Relaxed);
+}
+
+/// Records that the counters are rotated up to `at`.
+pub fn record_rotation(at: DateTime<Utc>) {
+    STATS.last_rotation_millis.fetch_max(at.timestamp_millis(), Ordering::Relaxed);
+}
+
+/// Records that a snapshot or delta was written at `at`.
+pub fn record_persist(at: DateTime<Utc>) {
+    STATS.last_persist_millis.fetch_max(at.timestamp_millis(), Ordering::Relaxed);
+}
+
+/// Records a list rejected for exceeding the limit of distinct identifiers. Returns the
+/// number of lists rejected so far.
+pub fn record_cardinality_rejection() -> u64 {
+    STATS.cardinality_rejections.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Records `count` identifiers evicted for exceeding the limit of distinct identifiers.
+pub fn record_cardinality_eviction(count: usize) {
+    STATS.cardinality_evictions.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+pub fn cardinality() -> CardinalitySummary {
+    CardinalitySummary {
+        rejected_lists: STATS.cardinality_rejections.load(Ordering::Relaxed),
+        evicted_identifiers: STATS.cardinality_evictions.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns when anything was last ingested, the counters last rotated and a snapshot or
+/// delta last written, over all tenants. Times before the server started are only known
+/// for the rotation, which is restored with the counters.
+pub fn freshness() -> Freshness {
+    Freshness {
+        last_ingest_at: time_of(&STATS.last_ingest_millis),
+        last_rotation_at: time_of(&STATS.last_rotation_millis),
+        last_persist_at: time_of(&STATS.last_persist_millis),
+    }
+}
+
+/// Returns the summaries of all routes, keyed by route.
+pub fn route_summaries() -> BTreeMap<String, LatencySummary> {
+    STATS.routes.iter().map(|entry| (entry.key().clone(), entry.summary())).collect()
+}
+
+/// Returns the lock wait summaries, keyed by lock name.
+pub fn lock_summaries() -> BTreeMap<String, LatencySummary> {
+    STATS.locks.iter().map(|entry| (entry.key().to_string(), entry.summary())).collect()
+}
+
+/// Returns the last snapshot written per file.
+pub fn snapshot_summaries() -> BTreeMap<String, SnapshotSummary> {
+    STATS.snapshots.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect()
+}
+
+/// Returns the last recovery from a corrupt snapshot per file.
+pub fn snapshot_recoveries() -> BTreeMap<String, SnapshotRecovery> {
+    STATS.recoveries.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect()
+}
+
+/// Returns the last memory compaction per tenant.
+pub fn compaction_summaries() -> BTreeMap<String, CompactionSummary> {
+    STATS.compactions.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_are_within_bucket_resolution() {
+        let mut histogram = Histogram::default();
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        let summary = histogram.summary();
+        assert_eq!(summary.count, 100);
+        assert!((summary.mean_ms - 50.5).abs() < 1e-9);
+        assert!(summary.p50_ms >= 50.0 && summary.p50_ms <= 50.0 * 1.2);
+        assert!(summary.p95_ms >= 95.0 && summary.p95_ms <= 100.0);
+        assert!(summary.p99_ms >= 99.0 && summary.p99_ms <= 100.0);
+        assert_eq!(summary.max_ms, 100.0);
+    }
+
+    #[test]
+    fn test_freshness_keeps_the_latest_times() {
+        // Later than any other test records
+        let later = DateTime::from_timestamp(4_102_444_800, 0).unwrap();
+        record_ingest(later);
+        record_ingest(later - chrono::Duration::hours(1));
+        record_rotation(later);
+        assert_eq!(freshness().last_ingest_at, Some(later));
+        assert_eq!(freshness().last_rotation_at, Some(later));
+    }
+
+    #[test]
+    fn test_empty_histogram() {
+        let summary = Histogram::default().summary();
+        assert_eq!(summary.count, 0);
+        assert_eq!(summary.p99_ms, 0.0);
+    }
+}
@@ -0,0 +1,122 @@
+// src/systemd.rs
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use actix_web::web;
+use tracing::{error, info, warn};
+
+use crate::algorithms::{CoOccurrenceCounter, Counters};
+use crate::locks;
+
+// Integration with the systemd service manager for `Type=notify` units: it learns when
+// the state is loaded and the server ready, when the final persistence starts, and, with
+// `WatchdogSec=`, restarts the server if its state locks stop being acquirable. Outside
+// of such units (no NOTIFY_SOCKET) nothing is sent.
+
+/// Sends `state`, e.g. "READY=1", to the service manager, if it asked for notifications.
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send(&path, state) {
+        warn!("Failed to notify the service manager of {}: {}", state, e);
+    }
+}
+
+#[cfg(unix)]
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        // An abstract socket, which only exists on Linux
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_path: &OsStr, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Notifications need Unix domain sockets"))
+}
+
+/// Returns how often to ping the watchdog, if the service manager enabled it for this
+/// process: half its timeout, so a late ping doesn't get us killed.
+pub fn watchdog_interval() -> Option<Duration> {
+    let timeout_usec = env::var("WATCHDOG_USEC").ok();
+    let pid = env::var("WATCHDOG_PID").ok();
+    ping_interval(timeout_usec.as_deref(), pid.as_deref(), std::process::id())
+}
+
+fn ping_interval(timeout_usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    // Without WATCHDOG_PID, the watchdog is meant for whichever process reads it
+    if pid.is_some_and(|pid| pid.parse() != Ok(own_pid)) {
+        return None;
+    }
+    let timeout_usec: u64 = timeout_usec?.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(timeout_usec / 2))
+}
+
+// Function to ping the watchdog as long as the counters and the co-occurrences can be
+// locked, as the background tasks and handlers need to. A deadlock stops the pings, and
+// systemd restarts the server.
+pub async fn run_watchdog(
+    counters: Arc<RwLock<Counters>>,
+    co_occurrence: Arc<RwLock<CoOccurrenceCounter>>,
+    interval: Duration,
+) {
+    info!(interval_ms = interval.as_millis() as u64, "Watchdog started.");
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        let counters = Arc::clone(&counters);
+        let co_occurrence = Arc::clone(&co_occurrence);
+        let result = web::block(move || {
+            drop(locks::write(&counters, "rotating_counters"));
+            drop(locks::write(&co_occurrence, "co_occurrence"));
+        })
+        .await;
+        match result {
+            Ok(()) => notify("WATCHDOG=1"),
+            Err(e) => error!("Error in watchdog block: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_only_applies_to_its_process() {
+        assert_eq!(ping_interval(Some("30000000"), Some("42"), 42), Some(Duration::from_secs(15)));
+        assert_eq!(ping_interval(Some("30000000"), None, 42), Some(Duration::from_secs(15)));
+        assert_eq!(ping_interval(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(ping_interval(Some("0"), Some("42"), 42), None);
+        assert_eq!(ping_interval(None, None, 42), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_states_are_sent_to_the_socket() {
+        let path = env::temp_dir().join(format!("mediathek_notify_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        send(path.as_os_str(), "READY=1").unwrap();
+        let mut buffer = [0; 16];
+        let length = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
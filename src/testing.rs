@@ -0,0 +1,140 @@
+// src/testing.rs
+//! Support for end-to-end tests of the HTTP API, built with `--features test-support`.
+//!
+//! A `TestApp` serves the full API (all middleware and routes, as the server does) on
+//! state kept in a data directory of its own, with the clock and hash seeds of
+//! deterministic mode. Tests move the clock instead of waiting for an hour boundary, and
+//! restart the app on the same directory to check what survives.
+//!
+//! The clock belongs to the whole process and only moves forward, so tests moving it
+//! should have a test binary of their own.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use actix_web::web::{self, Bytes};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::algorithms::state_store::open_state_store;
+use crate::algorithms::{perform_final_persistence, CoOccurrenceCounter, Counters};
+use crate::config::{DeterminismSettings, Settings};
+use crate::server::{self, AppState};
+use crate::{determinism, locks};
+
+/// Seed of the hash maps of all test apps.
+const SEED: u64 = 42;
+
+/// A data directory of its own, removed when dropped.
+struct TempDataDir(PathBuf);
+
+impl TempDataDir {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!("mediathek_test_{}_{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("Failed to create the data directory");
+        TempDataDir(path)
+    }
+}
+
+impl Drop for TempDataDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The API on the state of a temporary data directory. Background jobs don't run; the
+/// counters rotate when the clock is moved with `advance`.
+pub struct TestApp {
+    settings: Settings,
+    state: AppState,
+    data_dir: TempDataDir,
+}
+
+impl TestApp {
+    /// An app with the settings from the environment, in UTC, starting at `start`. Only
+    /// the first app of the process sets the clock; later ones find it where it is.
+    pub fn new(start: DateTime<Utc>) -> Self {
+        let mut settings = Settings::from_env();
+        settings.counters.rotation_timezone = chrono_tz::UTC;
+        TestApp::with_settings(settings, start)
+    }
+
+    /// An app with `settings`, except for the data directory and deterministic mode.
+    pub fn with_settings(mut settings: Settings, start: DateTime<Utc>) -> Self {
+        settings.determinism = DeterminismSettings { enabled: true, seed: SEED, start };
+        determinism::configure(&settings.determinism);
+        let data_dir = TempDataDir::new();
+        settings.storage.data_dir = data_dir.0.clone();
+        TestApp::open(settings, data_dir)
+    }
+
+    /// Loads the state from the data directory, as the server does on startup.
+    fn open(settings: Settings, data_dir: TempDataDir) -> Self {
+        let mut co_occurrence = CoOccurrenceCounter::with_metrics_cache(&settings.metrics_cache);
+        co_occurrence.set_pair_strategy(settings.pair_strategy);
+        co_occurrence.set_co_visitation_window(settings.co_visitation_window_secs);
+        co_occurrence.set_cardinality_limit(&settings.cardinality);
+        co_occurrence.set_identifier_filter(&settings.identifier_filter);
+        co_occurrence.recover(&settings.storage);
+        let counters = Counters::new(&settings.counters, &settings.storage, open_state_store(&settings.storage), None);
+        let (state, _) = AppState::new(&settings, Arc::new(RwLock::new(co_occurrence)), Arc::new(RwLock::new(counters)));
+        TestApp { settings, state, data_dir }
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir.0
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        determinism::now()
+    }
+
+    /// Moves the clock forward by `by` and rotates the counters up to it, as the hourly
+    /// rotation would.
+    pub fn advance(&self, by: chrono::Duration) {
+        let clock = determinism::manual_clock().expect("Deterministic mode is on");
+        let now = clock.set(clock.now() + by).expect("The clock only moves forward");
+        let timezone = self.settings.counters.rotation_timezone;
+        locks::write(&self.state.counters, "rotating_counters").advance_to(&now.with_timezone(&timezone));
+    }
+
+    /// Sends `request` to the API and returns the status and body of the response.
+    pub async fn send(&self, request: TestRequest) -> (StatusCode, Bytes) {
+        let service = test::init_service(server::app(&self.state, &self.settings)).await;
+        let response = test::call_service(&service, request.to_request()).await;
+        let status = response.status();
+        (status, test::read_body(response).await)
+    }
+
+    /// Sends `request` and parses the body of the response as JSON.
+    pub async fn send_json(&self, request: TestRequest) -> (StatusCode, Value) {
+        let (status, body) = self.send(request).await;
+        let value = serde_json::from_slice(&body).unwrap_or_else(|e| panic!("Not JSON ({}): {}", e, String::from_utf8_lossy(&body)));
+        (status, value)
+    }
+
+    /// Shuts down like the server, persisting the state, and starts again on the same
+    /// data directory.
+    pub async fn restart(self) -> Self {
+        let TestApp { settings, state, data_dir } = self;
+        state.ingest_queue.drain().await;
+        perform_final_persistence(Arc::clone(&state.counters)).await;
+        let co_occurrence = Arc::clone(&state.co_occurrence);
+        web::block(move || locks::write(&co_occurrence, "co_occurrence").compact()).await.expect("Final persistence");
+        drop(state);
+        TestApp::open(settings, data_dir)
+    }
+
+    /// Starts again on the same data directory without persisting anything first, as after
+    /// a crash, so the state is recovered from the logs.
+    pub fn restart_after_crash(self) -> Self {
+        let TestApp { settings, state, data_dir } = self;
+        drop(state);
+        TestApp::open(settings, data_dir)
+    }
+}
@@ -0,0 +1,72 @@
+// src/tls.rs
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+
+use crate::config::TlsSettings;
+
+/// Builds the rustls configuration of the listener, or returns `None` if TLS is not
+/// configured. Unreadable or invalid files are returned as errors, so the server refuses
+/// to start rather than falling back to plain HTTP.
+pub fn server_config(settings: &TlsSettings) -> io::Result<Option<ServerConfig>> {
+    let (cert_path, key_path) = match (&settings.cert_path, &settings.key_path) {
+        (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+        (None, None) => return Ok(None),
+        _ => return Err(invalid("MEDIATHEK_TLS_CERT and MEDIATHEK_TLS_KEY must be set together")),
+    };
+    let cert_chain = load_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| invalid(format!("Cannot read private key {}: {}", key_path.display(), e)))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid)?;
+    let builder = match &settings.client_ca_path {
+        // Mutual TLS: only clients presenting a certificate signed by one of these CAs get through
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca_path)? {
+                roots.add(cert).map_err(invalid)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(invalid)?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    builder.with_single_cert(cert_chain, key).map(Some).map_err(invalid)
+}
+
+/// Reads all certificates of a PEM file.
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("Cannot read certificates {}: {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(invalid(format!("No certificates in {}", path.display())));
+    }
+    Ok(certs)
+}
+
+fn invalid(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_requires_cert_and_key() {
+        let mut settings = TlsSettings { cert_path: None, key_path: None, client_ca_path: None };
+        assert!(server_config(&settings).unwrap().is_none());
+        settings.cert_path = Some("cert.pem".into());
+        assert_eq!(server_config(&settings).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,49 @@
+// src/unix_socket.rs
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+use crate::config::FileMode;
+
+/// Removes the socket file at `path`, e.g. one left behind by a run that crashed, which
+/// would make binding fail. Anything but a socket is left alone, so a misconfigured path
+/// fails to bind rather than deleting a file.
+pub fn remove(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Sets the permissions of the bound socket, which decide who may connect.
+pub fn set_mode(path: &Path, mode: FileMode) -> io::Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_only_sockets_are_removed() {
+        let path = std::env::temp_dir().join(format!("mediathek_socket_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        drop(UnixListener::bind(&path).unwrap());
+        set_mode(&path, FileMode(0o600)).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o600);
+        remove(&path).unwrap();
+        assert!(!path.exists());
+        remove(&path).unwrap();
+
+        fs::write(&path, "not a socket").unwrap();
+        assert!(remove(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
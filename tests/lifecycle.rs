@@ -0,0 +1,68 @@
+// tests/lifecycle.rs
+// Ingest, rotation, persistence, restart and query of the HTTP API, with a clock that
+// moves when told to. Run with `cargo test --features test-support`.
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use chrono::{Duration, TimeZone, Utc};
+use mediathek_rs::testing::TestApp;
+use serde_json::{json, Value};
+
+/// The count of the bucket named `bucket` in a response of GET /counters/{id}.
+fn count_in(response: &Value, granularity: &str, bucket: &str) -> u64 {
+    let points = response[granularity].as_array().expect("A series of buckets");
+    points.iter().find(|point| point["bucket"] == bucket).map_or(0, |point| point["count"].as_u64().unwrap())
+}
+
+async fn play(app: &TestApp, id: &str, count: u64) {
+    let (status, _) = app.send(TestRequest::post().uri("/v1/counters").set_json(json!({ "id": id, "count": count }))).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+async fn add_list(app: &TestApp, identifiers: &[&str]) {
+    let (status, _) = app.send(TestRequest::post().uri("/v1/lists").set_json(json!({ "identifiers": identifiers }))).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+async fn counts(app: &TestApp, id: &str) -> Value {
+    let (status, response) = app.send_json(TestRequest::get().uri(&format!("/v1/counters/{}", id))).await;
+    assert_eq!(status, StatusCode::OK);
+    response
+}
+
+async fn co_occurrences(app: &TestApp, identifier: &str) -> Value {
+    let (status, response) = app.send_json(TestRequest::get().uri(&format!("/v1/lists/{}", identifier))).await;
+    assert_eq!(status, StatusCode::OK);
+    response["co_occurrences"].clone()
+}
+
+#[actix_web::test]
+async fn test_state_survives_rotation_and_restarts() {
+    let app = TestApp::new(Utc.with_ymd_and_hms(2026, 3, 2, 10, 30, 0).unwrap());
+    add_list(&app, &["ard:a", "ard:b", "ard:c"]).await;
+    play(&app, "ard:a", 3).await;
+
+    // Into the next hour, which moves the plays to the previous bucket
+    app.advance(Duration::hours(1));
+    let response = counts(&app, "ard:a").await;
+    assert_eq!((count_in(&response, "hourly", "this_hour"), count_in(&response, "hourly", "last_hour")), (0, 3));
+    assert_eq!(count_in(&response, "daily", "today"), 3);
+
+    // A clean restart loads the snapshots
+    let app = app.restart().await;
+    assert!(app.data_dir().join("rotating_counters.json").exists());
+    let response = counts(&app, "ard:a").await;
+    assert_eq!((count_in(&response, "hourly", "last_hour"), count_in(&response, "daily", "today")), (3, 3));
+    assert_eq!(co_occurrences(&app, "ard:a").await["ard:b"], 1);
+
+    // After a crash, the logs recover what happened since the snapshots
+    play(&app, "ard:b", 2).await;
+    add_list(&app, &["ard:a", "ard:b"]).await;
+    let app = app.restart_after_crash();
+    assert_eq!(count_in(&counts(&app, "ard:b").await, "hourly", "this_hour"), 2);
+    assert_eq!(co_occurrences(&app, "ard:a").await["ard:b"], 2);
+
+    // Past midnight, today's plays are yesterday's
+    app.advance(Duration::hours(14));
+    let response = counts(&app, "ard:a").await;
+    assert_eq!((count_in(&response, "daily", "today"), count_in(&response, "daily", "yesterday")), (0, 3));
+}